@@ -0,0 +1,57 @@
+//! Measures the time saved by drawing circles with cairo's own arcs, filling
+//! and stroking the points of a scatter plot as circles and as paths.
+//!
+//! Run with `cargo bench --bench circles`.
+
+use std::time::{Duration, Instant};
+
+use cairo::{Context, Format, ImageSurface};
+
+use piet::kurbo::{BezPath, Circle, Shape};
+use piet::{Color, RenderContext};
+use piet_cairo::CairoRenderContext;
+
+const FRAMES: usize = 10;
+const POINTS: usize = 10_000;
+
+fn main() {
+    // warm up, so that the first measurement doesn't include loading cairo
+    time_frames(true);
+
+    println!("{} frames of {} circles, per frame:", FRAMES, POINTS);
+    for &(name, as_paths) in &[("as paths", true), ("as circles", false)] {
+        let time = time_frames(as_paths);
+        println!("  {:<11} {:?}", name, time / FRAMES as u32);
+    }
+}
+
+fn time_frames(as_paths: bool) -> Duration {
+    let surface = ImageSurface::create(Format::ARgb32, 400, 400).unwrap();
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        let cr = Context::new(&surface);
+        let mut rc = CairoRenderContext::new(&cr);
+        draw_frame(&mut rc, as_paths);
+        rc.finish().unwrap();
+    }
+    start.elapsed()
+}
+
+/// Draw the points, spread along a spiral.
+fn draw_frame(rc: &mut impl RenderContext, as_paths: bool) {
+    rc.clear(Color::WHITE);
+    for i in 0..POINTS {
+        let t = i as f64 / POINTS as f64;
+        let angle = t * 40.0;
+        let center = (200. + 180. * t * angle.cos(), 200. + 180. * t * angle.sin());
+        let circle = Circle::new(center, 3.0);
+        if as_paths {
+            let path = BezPath::from_vec(circle.to_bez_path(1e-3).collect());
+            rc.fill(&path, &Color::rgb8(0x40, 0x80, 0xc0));
+            rc.stroke(&path, &Color::BLACK, 0.5);
+        } else {
+            rc.fill(circle, &Color::rgb8(0x40, 0x80, 0xc0));
+            rc.stroke(circle, &Color::BLACK, 0.5);
+        }
+    }
+}
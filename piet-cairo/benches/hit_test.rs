@@ -0,0 +1,57 @@
+//! Measures hit testing in a long line: building a layout of a single line
+//! of 10,000 characters, and then hit testing points and text positions
+//! across it.
+//!
+//! Run with `cargo bench --bench hit_test`.
+
+use std::time::{Duration, Instant};
+
+use piet::kurbo::Point;
+use piet::{FontFamily, Text, TextLayout, TextLayoutBuilder};
+use piet_cairo::CairoText;
+
+const LENGTH: usize = 10_000;
+const HITS: usize = 1_000;
+
+fn main() {
+    let text: String = "piet text is the best text. "
+        .chars()
+        .cycle()
+        .take(LENGTH)
+        .collect();
+
+    let start = Instant::now();
+    let layout = CairoText::new()
+        .new_text_layout(text)
+        .font(FontFamily::SANS_SERIF, 12.0)
+        .build()
+        .unwrap();
+    let build = start.elapsed();
+    let width = layout.size().width;
+
+    let points = time(|i| {
+        let x = width * i as f64 / HITS as f64;
+        layout.hit_test_point(Point::new(x, 0.0)).idx
+    });
+    let positions = time(|i| {
+        let position = LENGTH * i / HITS;
+        layout.hit_test_text_position(position).unwrap().point.x as usize
+    });
+
+    println!("a line of {} characters:", LENGTH);
+    println!("  build:                        {:?}", build);
+    println!("  {} hit_test_point:          {:?}", HITS, points);
+    println!("  {} hit_test_text_position:  {:?}", HITS, positions);
+}
+
+/// The time taken to hit test `HITS` times, where `hit` does the `i`th test.
+fn time(mut hit: impl FnMut(usize) -> usize) -> Duration {
+    let start = Instant::now();
+    let mut total = 0;
+    for i in 0..HITS {
+        total += hit(i);
+    }
+    // use the results, so they aren't optimized away
+    assert!(total > 0);
+    start.elapsed()
+}
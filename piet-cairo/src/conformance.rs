@@ -0,0 +1,346 @@
+//! Tests of text layouts that only use piet's text API, run against both
+//! the toy text implementation and the Pango one.
+
+use cairo::{Context, Format, ImageSurface};
+
+use piet::kurbo::Point;
+use piet::{
+    Affinity, Color, FontFamily, FontWeight, RenderContext, Text, TextAttribute, TextLayout,
+    TextLayoutBuilder,
+};
+
+use crate::{CairoRenderContext, CairoText, CairoTextLayout};
+
+fn layout(text: &str) -> CairoTextLayout {
+    CairoText::new()
+        .new_text_layout(text.to_string())
+        .font(FontFamily::SANS_SERIF, 16.0)
+        .build()
+        .unwrap()
+}
+
+/// Draws `layout` at the origin in black on white, and returns a function
+/// giving the (r, g, b) of each pixel.
+pub(crate) fn render(
+    layout: &CairoTextLayout,
+    width: i32,
+    height: i32,
+) -> impl Fn(i32, i32) -> (u8, u8, u8) {
+    let mut surface = ImageSurface::create(Format::ARgb32, width, height).unwrap();
+    {
+        let cr = Context::new(&surface);
+        let mut piet = CairoRenderContext::new(&cr);
+        piet.clear(Color::WHITE);
+        piet.draw_text(layout, Point::ZERO);
+        piet.finish().unwrap();
+    }
+    surface.flush();
+    let stride = surface.get_stride();
+    let data = surface.get_data().unwrap().to_vec();
+    move |x, y| {
+        let off = (y * stride + x * 4) as usize;
+        (data[off + 2], data[off + 1], data[off])
+    }
+}
+
+/// The x positions of the carets at each char boundary of the layout.
+fn carets(layout: &CairoTextLayout) -> Vec<(usize, f64)> {
+    let text = layout.text();
+    text.char_indices()
+        .map(|(idx, _)| idx)
+        .chain(Some(text.len()))
+        .map(|idx| (idx, layout.hit_test_text_position(idx).unwrap().point.x))
+        .collect()
+}
+
+#[test]
+fn test_empty_text() {
+    let layout = layout("");
+    assert_eq!(layout.size().width, 0.0);
+    assert_eq!(layout.hit_test_point(Point::new(10.0, 5.0)).idx, 0);
+    assert!(layout.rects_for_range(..).is_empty());
+    assert!(layout.glyph_runs().is_empty());
+}
+
+#[test]
+fn test_single_line_metrics() {
+    let layout = layout("hello");
+    assert_eq!(layout.line_count(), 1);
+    let lm = layout.line_metric(0).unwrap();
+    assert_eq!(lm.range(), 0..5);
+    assert_eq!(lm.trailing_whitespace, 0);
+    assert!(lm.baseline > 0.0 && lm.baseline < lm.height);
+    assert_eq!(layout.size().height, lm.height);
+    assert!(layout.size().width > 20.0);
+    let position = layout.hit_test_text_position(0).unwrap();
+    assert_eq!(position.point.y, lm.baseline);
+    assert_eq!(position.line, 0);
+}
+
+#[test]
+fn test_hard_breaks() {
+    let layout = layout("one\ntwo\nthree");
+    assert_eq!(layout.line_count(), 3);
+    assert_eq!(layout.line_text(0), Some("one\n"));
+    assert_eq!(layout.line_text(1), Some("two\n"));
+    assert_eq!(layout.line_text(2), Some("three"));
+    let lines: Vec<_> = (0..3).map(|n| layout.line_metric(n).unwrap()).collect();
+    assert_eq!(lines[0].trailing_whitespace, 1);
+    for pair in lines.windows(2) {
+        assert_eq!(pair[0].end_offset, pair[1].start_offset);
+        assert!(pair[1].y_offset >= pair[0].y_offset + pair[0].height - 0.01);
+    }
+    // a caret before the newline is at the end of its line, and one after
+    // it is at the start of the next
+    let before = layout.hit_test_text_position(3).unwrap();
+    assert_eq!(before.line, 0);
+    assert!(before.point.x > 0.0);
+    let after = layout.hit_test_text_position(4).unwrap();
+    assert_eq!(after.line, 1);
+    assert_eq!(after.point.x, 0.0);
+    assert_eq!(after.point.y, lines[1].y_offset + lines[1].baseline);
+}
+
+#[test]
+fn test_wrapping() {
+    let text = "the quick brown fox jumps over the lazy dog";
+    let mut layout = CairoText::new()
+        .new_text_layout(text)
+        .font(FontFamily::SANS_SERIF, 16.0)
+        .max_width(100.0)
+        .build()
+        .unwrap();
+    assert!(layout.line_count() > 2);
+    assert!(layout.size().width <= 100.0);
+    let joined: String = (0..layout.line_count())
+        .map(|n| layout.line_text(n).unwrap())
+        .collect();
+    assert_eq!(joined, text);
+    // wrapped lines keep their trailing space
+    assert_eq!(layout.line_metric(0).unwrap().trailing_whitespace, 1);
+
+    layout.update_width(None).unwrap();
+    assert_eq!(layout.line_count(), 1);
+    assert!(layout.size().width > 100.0);
+}
+
+#[test]
+fn test_carets_increase() {
+    let layout = layout("hello world");
+    let carets = carets(&layout);
+    assert_eq!(carets[0].1, 0.0);
+    for pair in carets.windows(2) {
+        assert!(pair[1].1 > pair[0].1, "{:?}", pair);
+    }
+    let end = carets.last().unwrap().1;
+    assert!(
+        (end - layout.size().width).abs() < 1.0,
+        "{} {}",
+        end,
+        layout.size().width
+    );
+}
+
+#[test]
+fn test_hit_test_round_trip() {
+    let layout = layout("piet text");
+    let y = layout.line_metric(0).unwrap().baseline;
+    let carets = carets(&layout);
+    for pair in carets.windows(2) {
+        let ((start, x0), (end, x1)) = (pair[0], pair[1]);
+        let quarter = (x1 - x0) / 4.0;
+        let hit = layout.hit_test_point(Point::new(x0 + quarter, y));
+        assert_eq!(hit.idx, start);
+        assert!(hit.is_inside);
+        let hit = layout.hit_test_point(Point::new(x1 - quarter, y));
+        assert_eq!(hit.idx, end);
+        assert!(hit.is_inside);
+    }
+}
+
+#[test]
+fn test_hit_test_beyond_ends() {
+    let layout = layout("piet text");
+    let hit = layout.hit_test_point(Point::new(-10.0, 5.0));
+    assert_eq!(hit.idx, 0);
+    assert!(!hit.is_inside);
+    let hit = layout.hit_test_point(Point::new(500.0, 5.0));
+    assert_eq!(hit.idx, 9);
+    assert!(!hit.is_inside);
+    // points above and below the layout hit its first and last lines
+    let hit = layout.hit_test_point(Point::new(500.0, 500.0));
+    assert_eq!(hit.idx, 9);
+    assert!(!hit.is_inside);
+    let hit = layout.hit_test_point(Point::new(1.0, -10.0));
+    assert_eq!(hit.idx, 0);
+    assert!(!hit.is_inside);
+}
+
+#[test]
+fn test_rects_for_range() {
+    let layout = layout("piet\ntext");
+    let rects = layout.rects_for_range(1..3);
+    assert_eq!(rects.len(), 1);
+    let x1 = layout.hit_test_text_position(1).unwrap().point.x;
+    let x3 = layout.hit_test_text_position(3).unwrap().point.x;
+    assert!((rects[0].x0 - x1).abs() < 0.01);
+    assert!((rects[0].x1 - x3).abs() < 0.01);
+    let lm = layout.line_metric(0).unwrap();
+    assert_eq!(rects[0].y0, lm.y_offset);
+    assert_eq!(rects[0].y1, lm.y_offset + lm.height);
+
+    // a range including the newline covers it too, and the next line
+    let rects = layout.rects_for_range(2..7);
+    assert_eq!(rects.len(), 3);
+    assert!(rects[2].y0 >= rects[0].y1 - 0.01);
+    assert!(layout.rects_for_range(2..2).is_empty());
+}
+
+#[test]
+fn test_size_and_weight() {
+    let width = |size: f64, weight: FontWeight| {
+        CairoText::new()
+            .new_text_layout("piet text")
+            .font(FontFamily::SANS_SERIF, size)
+            .default_attribute(weight)
+            .build()
+            .unwrap()
+            .size()
+            .width
+    };
+    let regular = width(16.0, FontWeight::REGULAR);
+    assert!(width(32.0, FontWeight::REGULAR) > 1.8 * regular);
+    assert!(width(16.0, FontWeight::BOLD) > regular);
+}
+
+#[test]
+fn test_foreground_color() {
+    let layout = CairoText::new()
+        .new_text_layout("\u{2588}\u{2588}")
+        .font(FontFamily::MONOSPACE, 20.0)
+        .default_attribute(TextAttribute::ForegroundColor(Color::rgb8(0, 0, 255)))
+        .build()
+        .unwrap();
+    let y = layout.line_metric(0).unwrap().baseline as i32 - 4;
+    let pixel = render(&layout, 100, 40);
+    let (r, g, b) = pixel(layout_x(&layout, 0, 3) as i32, y);
+    assert!(r < 50 && g < 50 && b > 200, "{:?}", (r, g, b));
+}
+
+// toy text draws all of a layout in its default color
+#[test]
+#[cfg(all(feature = "pango", not(feature = "send-layouts")))]
+fn test_foreground_color_range() {
+    let layout = CairoText::new()
+        .new_text_layout("\u{2588}\u{2588}\u{2588}\u{2588}")
+        .font(FontFamily::MONOSPACE, 20.0)
+        .range_attribute(0..6, TextAttribute::ForegroundColor(Color::rgb8(255, 0, 0)))
+        .build()
+        .unwrap();
+    let y = layout.line_metric(0).unwrap().baseline as i32 - 4;
+    let pixel = render(&layout, 100, 40);
+    let (r, g, b) = pixel(layout_x(&layout, 0, 3) as i32, y);
+    assert!(r > 200 && g < 50 && b < 50, "{:?}", (r, g, b));
+    let (r, g, b) = pixel(layout_x(&layout, 9, 12) as i32, y);
+    assert!(r < 50 && g < 50 && b < 50, "{:?}", (r, g, b));
+}
+
+#[test]
+fn test_underline() {
+    let plain = layout("____ piet ____");
+    let underlined = CairoText::new()
+        .new_text_layout("____ piet ____")
+        .font(FontFamily::SANS_SERIF, 16.0)
+        .range_attribute(5..9, TextAttribute::Underline(true))
+        .build()
+        .unwrap();
+    // below "piet", where only the underline is drawn
+    let x = layout_x(&underlined, 5, 9);
+    let baseline = underlined.line_metric(0).unwrap().baseline;
+    let ink = |layout: &CairoTextLayout| {
+        let pixel = render(layout, 200, 40);
+        (baseline as i32..baseline as i32 + 4).any(|y| pixel(x as i32, y).0 < 128)
+    };
+    assert!(ink(&underlined));
+    assert!(!ink(&plain));
+}
+
+/// The x position midway between the carets at `start` and `end`.
+fn layout_x(layout: &CairoTextLayout, start: usize, end: usize) -> f64 {
+    let x0 = layout.hit_test_text_position(start).unwrap().point.x;
+    let x1 = layout.hit_test_text_position(end).unwrap().point.x;
+    (x0 + x1) / 2.0
+}
+
+#[test]
+fn test_rtl_caret_order() {
+    // the Hebrew letters alef, bet, gimel
+    let layout = layout("\u{5D0}\u{5D1}\u{5D2}");
+    let carets = carets(&layout);
+    for pair in carets.windows(2) {
+        assert!(pair[1].1 < pair[0].1, "{:?}", carets);
+    }
+    let text_end = carets.last().unwrap().1;
+    assert!(text_end.abs() < 0.01);
+}
+
+#[test]
+fn test_combining_marks() {
+    // an e with a combining acute accent is a single grapheme
+    let layout = layout("e\u{301}x");
+    let start = layout.hit_test_text_position(0).unwrap().point;
+    assert_eq!(layout.hit_test_text_position(1).unwrap().point, start);
+    let x = layout.hit_test_text_position(3).unwrap().point.x;
+    assert!(x > start.x);
+    let hit = layout.hit_test_point(Point::new(x - 0.5, 5.0));
+    assert_eq!(hit.idx, 3);
+}
+
+#[test]
+fn test_soft_wrap_affinity() {
+    let layout = CairoText::new()
+        .new_text_layout("piet text is the best text\nmore")
+        .font(FontFamily::SANS_SERIF, 12.0)
+        .max_width(60.0)
+        .build()
+        .unwrap();
+    assert!(layout.line_count() > 2);
+    let line_0 = layout.line_metric(0).unwrap();
+    let line_1 = layout.line_metric(1).unwrap();
+
+    let hit = layout.hit_test_point(Point::new(200.0, line_0.y_offset + 1.0));
+    assert_eq!(hit.idx, line_1.start_offset);
+    assert_eq!(hit.affinity, Affinity::Upstream);
+    let hit = layout.hit_test_point(Point::new(-10.0, line_1.y_offset + 1.0));
+    assert_eq!(hit.idx, line_1.start_offset);
+    assert_eq!(hit.affinity, Affinity::Downstream);
+
+    let upstream = layout
+        .hit_test_text_position_with_affinity(line_1.start_offset, Affinity::Upstream)
+        .unwrap();
+    assert_eq!(upstream.line, 0);
+    assert!(upstream.point.x > 0.0);
+    let downstream = layout
+        .hit_test_text_position_with_affinity(line_1.start_offset, Affinity::Downstream)
+        .unwrap();
+    assert_eq!(downstream.line, 1);
+    assert_eq!(downstream.point.x, 0.0);
+}
+
+#[test]
+fn test_bidi_affinity() {
+    // the Hebrew letters alef, bet and gimel, at 4..10
+    let layout = layout("abc \u{5D0}\u{5D1}\u{5D2} def");
+    let caret = |idx, affinity| {
+        layout
+            .hit_test_text_position_with_affinity(idx, affinity)
+            .unwrap()
+            .point
+            .x
+    };
+    // the logical start of the Hebrew run is on its right, while an
+    // upstream caret there follows the space on its left
+    assert!(caret(4, Affinity::Upstream) < caret(4, Affinity::Downstream));
+    // inside a run, the affinity makes no difference
+    assert_eq!(caret(2, Affinity::Upstream), caret(2, Affinity::Downstream));
+}
@@ -2,22 +2,43 @@
 
 #![deny(clippy::trivially_copy_pass_by_ref)]
 
+// Pango layouts can't be sent to other threads, so with `send-layouts` the
+// toy text is used, even with the `pango` feature.
+#[cfg(all(feature = "pango", not(feature = "send-layouts")))]
+mod pango;
+#[cfg(any(not(feature = "pango"), feature = "send-layouts"))]
 mod text;
 
 use std::borrow::Cow;
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::f64::consts::{FRAC_PI_2, PI};
 use std::fmt;
 
-use cairo::{BorrowError, Context, Filter, Format, ImageSurface, Matrix, Status, SurfacePattern};
+use cairo::{
+    BorrowError, Context, Extend, Filter, Format, ImageSurface, Matrix, Operator, Status,
+    SurfacePattern,
+};
 
-use piet::kurbo::{Affine, PathEl, Point, QuadBez, Rect, Shape, Size};
+use piet::kurbo::{Affine, BezPath, PathEl, Point, QuadBez, Rect, Shape, Size};
 
 use piet::{
-    Color, Error, FixedGradient, ImageFormat, InterpolationMode, IntoBrush, LineCap, LineJoin,
-    RenderContext, StrokeStyle, TextLayout,
+    util, BlendMode, Color, Error, FixedGradient, FixedSweepGradient, GradientExtend, Image,
+    ImageBuf, ImageFormat, InterpolationMode, IntoBrush, LayerMask, LineCap, LineJoin,
+    RenderContext, StrokeStyle, TextLayout, TileMode,
 };
 
+#[cfg(all(feature = "pango", not(feature = "send-layouts")))]
+pub use crate::pango::{CairoText, CairoTextLayout, CairoTextLayoutBuilder};
+#[cfg(any(not(feature = "pango"), feature = "send-layouts"))]
 pub use crate::text::{CairoText, CairoTextLayout, CairoTextLayoutBuilder};
 
+thread_local! {
+    /// The brushes for `make_brush_cached`. Cairo patterns don't belong to
+    /// a surface, so they can be shared by every context on the thread.
+    static BRUSH_CACHE: RefCell<util::BrushCache<Brush>> = RefCell::new(Default::default());
+}
+
 pub struct CairoRenderContext<'a> {
     // Cairo has this as Clone and with &self methods, but we do this to avoid
     // concurrency problems.
@@ -26,8 +47,91 @@ pub struct CairoRenderContext<'a> {
     // because of the relationship between GTK and cairo (where GTK applies a transform
     // to adjust for menus and window borders) we cannot trust the transform returned
     // by cairo. Instead we maintain our own stack, which will contain
-    // only those transforms applied by us.
-    transform_stack: Vec<Affine>,
+    // only those transforms applied by us, and the bounds of our clips.
+    state_stack: Vec<CtxState>,
+    // For each `save` or `save_layer`, the layer it started, if any.
+    layer_stack: Vec<Option<Layer>>,
+    opacity: util::OpacityStack,
+}
+
+#[derive(Clone, Copy, Default)]
+struct CtxState {
+    transform: Affine,
+    /// The bounds of the clip, before any of our transforms.
+    clip: Option<Rect>,
+}
+
+/// A cairo image surface, as a piet image.
+#[derive(Clone)]
+pub struct CairoImage(ImageSurface);
+
+impl CairoImage {
+    /// The underlying image surface.
+    pub fn surface(&self) -> &ImageSurface {
+        &self.0
+    }
+}
+
+impl Image for CairoImage {
+    fn size(&self) -> Size {
+        Size::new(self.0.get_width() as f64, self.0.get_height() as f64)
+    }
+}
+
+/// The pixels of a surface, mapped to an image with
+/// `cairo_surface_map_to_image`, and unmapped on drop.
+///
+/// For image surfaces this is the surface's own data; other surfaces, such
+/// as xlib ones, copy their contents to a new image.
+// cairo-rs has `MappedImageSurface` for this, but it takes a reference
+// to the image after unmapping it, which has already destroyed it.
+struct MappedImage<'a> {
+    target: &'a cairo::Surface,
+    image: *mut cairo_sys::cairo_surface_t,
+    surface: ImageSurface,
+}
+
+impl<'a> MappedImage<'a> {
+    fn new(target: &'a cairo::Surface) -> Result<MappedImage<'a>, Error> {
+        unsafe {
+            let image =
+                cairo_sys::cairo_surface_map_to_image(target.to_raw_none(), std::ptr::null());
+            let status = Status::from(cairo_sys::cairo_surface_status(image));
+            if status != Status::Success {
+                // unmapping an error image would put the target in an
+                // error state too, so this is only released
+                cairo_sys::cairo_surface_destroy(image);
+                return Err(CairoError::new("cairo_surface_map_to_image", status).into());
+            }
+            // the mapping is always an image surface
+            let surface = ImageSurface::try_from(cairo::Surface::from_raw_none(image))
+                .expect("cairo_surface_map_to_image returned a non-image surface");
+            Ok(MappedImage {
+                target,
+                image,
+                surface,
+            })
+        }
+    }
+
+    fn surface(&self) -> &ImageSurface {
+        &self.surface
+    }
+}
+
+impl<'a> Drop for MappedImage<'a> {
+    fn drop(&mut self) {
+        // this releases the mapping's reference; `surface` releases its own
+        unsafe { cairo_sys::cairo_surface_unmap_image(self.target.to_raw_none(), self.image) }
+    }
+}
+
+// How a layer is composited when it is restored.
+enum Layer {
+    Opacity(f64),
+    // masks keep the matrix in effect when the layer was started
+    Shape(BezPath, Matrix),
+    Image(ImageSurface, Rect, Matrix),
 }
 
 impl<'a> CairoRenderContext<'a> {
@@ -40,7 +144,9 @@ impl<'a> CairoRenderContext<'a> {
         CairoRenderContext {
             ctx,
             text: CairoText::new(),
-            transform_stack: Vec::new(),
+            state_stack: Vec::new(),
+            layer_stack: Vec::new(),
+            opacity: util::OpacityStack::new(),
         }
     }
 }
@@ -50,40 +156,106 @@ pub enum Brush {
     Solid(u32),
     Linear(cairo::LinearGradient),
     Radial(cairo::RadialGradient),
+    Sweep(FixedSweepGradient),
+    Image(SurfacePattern),
 }
 
+/// An error from cairo, with the operation that failed.
+///
+/// This is the error wrapped by [`piet::Error::BackendError`] for cairo
+/// failures; get it with [`piet::Error::backend_error_downcast`].
+///
+/// [`piet::Error::BackendError`]: ../piet/enum.Error.html#variant.BackendError
+/// [`piet::Error::backend_error_downcast`]: ../piet/enum.Error.html#method.backend_error_downcast
 #[derive(Debug)]
-struct WrappedStatus(Status);
+pub struct CairoError {
+    operation: &'static str,
+    kind: CairoErrorKind,
+}
 
-impl fmt::Display for WrappedStatus {
+#[derive(Debug)]
+enum CairoErrorKind {
+    Status(Status),
+    Borrow(BorrowError),
+}
+
+impl CairoError {
+    /// An error with the given cairo status.
+    pub fn new(operation: &'static str, status: Status) -> CairoError {
+        CairoError {
+            operation,
+            kind: CairoErrorKind::Status(status),
+        }
+    }
+
+    /// The operation that failed, usually the name of the cairo function.
+    pub fn operation(&self) -> &'static str {
+        self.operation
+    }
+
+    /// The cairo status, if the error came from cairo, rather than from
+    /// borrowing an image surface that is still in use.
+    pub fn status(&self) -> Option<Status> {
+        match self.kind {
+            CairoErrorKind::Status(status) => Some(status),
+            CairoErrorKind::Borrow(BorrowError::Cairo(status)) => Some(status),
+            CairoErrorKind::Borrow(BorrowError::NonExclusive) => None,
+        }
+    }
+}
+
+impl fmt::Display for CairoError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Cairo error: {:?}", self.0)
+        match &self.kind {
+            CairoErrorKind::Status(status) => write!(f, "{} failed: {}", self.operation, status),
+            CairoErrorKind::Borrow(e) => write!(f, "{} failed: {}", self.operation, e),
+        }
+    }
+}
+
+impl std::error::Error for CairoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            CairoErrorKind::Status(_) => None,
+            CairoErrorKind::Borrow(e) => Some(e),
+        }
     }
 }
 
-impl std::error::Error for WrappedStatus {}
+impl From<CairoError> for Error {
+    fn from(e: CairoError) -> Error {
+        Error::BackendError(Box::new(e))
+    }
+}
 
 trait WrapError<T> {
-    fn wrap(self) -> Result<T, Error>;
+    /// Convert the error, naming the operation that failed.
+    fn wrap(self, operation: &'static str) -> Result<T, Error>;
 }
 
-// Discussion question: a blanket impl here should be pretty doable.
-
 impl<T> WrapError<T> for Result<T, BorrowError> {
-    fn wrap(self) -> Result<T, Error> {
+    fn wrap(self, operation: &'static str) -> Result<T, Error> {
         self.map_err(|e| {
-            let e: Box<dyn std::error::Error> = Box::new(e);
-            e.into()
+            CairoError {
+                operation,
+                kind: CairoErrorKind::Borrow(e),
+            }
+            .into()
         })
     }
 }
 
 impl<T> WrapError<T> for Result<T, Status> {
-    fn wrap(self) -> Result<T, Error> {
-        self.map_err(|e| {
-            let e: Box<dyn std::error::Error> = Box::new(WrappedStatus(e));
-            e.into()
-        })
+    fn wrap(self, operation: &'static str) -> Result<T, Error> {
+        self.map_err(|status| CairoError::new(operation, status).into())
+    }
+}
+
+/// Check a cairo status, naming the operation that set it.
+fn check_status(status: Status, operation: &'static str) -> Result<(), Error> {
+    match status {
+        Status::Success => Ok(()),
+        status => Err(CairoError::new(operation, status).into()),
     }
 }
 
@@ -110,26 +282,25 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
     type Text = CairoText;
     type TextLayout = CairoTextLayout;
 
-    type Image = ImageSurface;
+    type Image = CairoImage;
 
     fn status(&mut self) -> Result<(), Error> {
-        let status = self.ctx.status();
-        if status == Status::Success {
-            Ok(())
-        } else {
-            let e: Box<dyn std::error::Error> = Box::new(WrappedStatus(status));
-            Err(e.into())
-        }
+        // the context doesn't record which call failed
+        check_status(self.ctx.status(), "drawing")
     }
 
     fn clear(&mut self, color: Color) {
         let rgba = color.as_rgba_u32();
+        // clearing ignores the blend mode
+        self.ctx.save();
+        self.ctx.set_operator(Operator::Source);
         self.ctx.set_source_rgb(
             byte_to_frac(rgba >> 24),
             byte_to_frac(rgba >> 16),
             byte_to_frac(rgba >> 8),
         );
         self.ctx.paint();
+        self.ctx.restore();
     }
 
     fn solid_brush(&mut self, color: Color) -> Brush {
@@ -137,31 +308,62 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
     }
 
     fn gradient(&mut self, gradient: impl Into<FixedGradient>) -> Result<Brush, Error> {
-        match gradient.into() {
+        match util::resolve_color_interpolation(gradient.into()) {
             FixedGradient::Linear(linear) => {
                 let (x0, y0) = (linear.start.x, linear.start.y);
                 let (x1, y1) = (linear.end.x, linear.end.y);
                 let lg = cairo::LinearGradient::new(x0, y0, x1, y1);
                 set_gradient_stops!(&lg, &linear.stops);
+                lg.set_extend(convert_extend(linear.extend));
                 Ok(Brush::Linear(lg))
             }
             FixedGradient::Radial(radial) => {
+                let (radial, transform) = radial.to_circular();
                 let (xc, yc) = (radial.center.x, radial.center.y);
                 let (xo, yo) = (radial.origin_offset.x, radial.origin_offset.y);
-                let r = radial.radius;
+                let r = radial.radii.x;
                 let rg = cairo::RadialGradient::new(xc + xo, yc + yo, 0.0, xc, yc, r);
                 set_gradient_stops!(&rg, &radial.stops);
+                rg.set_extend(convert_extend(radial.extend));
+                // the pattern matrix maps user space to gradient space
+                rg.set_matrix(affine_to_matrix(transform.inverse()));
                 Ok(Brush::Radial(rg))
             }
+            FixedGradient::Sweep(sweep) => Ok(Brush::Sweep(sweep)),
         }
     }
 
+    fn make_brush_cached(
+        &mut self,
+        key: u64,
+        gradient: impl FnOnce() -> FixedGradient,
+    ) -> Result<Brush, Error> {
+        BRUSH_CACHE.with(|cache| {
+            cache
+                .borrow_mut()
+                .get_or_try_insert_with(key, || self.gradient(gradient()))
+        })
+    }
+
+    fn make_image_brush(
+        &mut self,
+        image: &CairoImage,
+        tile: TileMode,
+        transform: Affine,
+    ) -> Result<Brush, Error> {
+        let pattern = SurfacePattern::create(&image.0);
+        pattern.set_extend(convert_tile_mode(tile));
+        // the pattern matrix maps user space to image space
+        pattern.set_matrix(affine_to_matrix(transform.inverse()));
+        Ok(Brush::Image(pattern))
+    }
+
     fn fill(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
         let brush = brush.make_brush(self, || shape.bounding_box());
         self.set_path(shape);
         self.set_brush(&*brush);
         self.ctx.set_fill_rule(cairo::FillRule::Winding);
-        self.ctx.fill();
+        self.draw_with_opacity(&brush, |ctx| ctx.fill());
     }
 
     fn fill_even_odd(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
@@ -169,13 +371,15 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
         self.set_path(shape);
         self.set_brush(&*brush);
         self.ctx.set_fill_rule(cairo::FillRule::EvenOdd);
-        self.ctx.fill();
+        self.draw_with_opacity(&brush, |ctx| ctx.fill());
     }
 
     fn clip(&mut self, shape: impl Shape) {
-        self.set_path(shape);
-        self.ctx.set_fill_rule(cairo::FillRule::Winding);
-        self.ctx.clip();
+        self.clip_with_rule(shape, cairo::FillRule::Winding);
+    }
+
+    fn clip_even_odd(&mut self, shape: impl Shape) {
+        self.clip_with_rule(shape, cairo::FillRule::EvenOdd);
     }
 
     fn stroke(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, width: f64) {
@@ -183,7 +387,7 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
         self.set_path(shape);
         self.set_stroke(width, None);
         self.set_brush(&*brush);
-        self.ctx.stroke();
+        self.draw_with_opacity(&brush, |ctx| ctx.stroke());
     }
 
     fn stroke_styled(
@@ -193,11 +397,27 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
         width: f64,
         style: &StrokeStyle,
     ) {
+        if let Some(mode) = style.snap_mode {
+            // cairo's matrix includes transforms that aren't ours
+            let transform = matrix_to_affine(self.ctx.get_matrix());
+            let (path, width) = util::snap_stroke(shape, width, mode, transform);
+            let style = StrokeStyle {
+                snap_mode: None,
+                ..style.clone()
+            };
+            return self.stroke_styled(path, brush, width, &style);
+        }
         let brush = brush.make_brush(self, || shape.bounding_box());
-        self.set_path(shape);
+        if style.dash.is_some() {
+            // cairo's arcs are a little longer or shorter than the curves of
+            // the shape's path, which would move the dashes along them
+            self.set_path(shape.into_bez_path(1e-3));
+        } else {
+            self.set_path(shape);
+        }
         self.set_stroke(width, Some(style));
         self.set_brush(&*brush);
-        self.ctx.stroke();
+        self.draw_with_opacity(&brush, |ctx| ctx.stroke());
     }
 
     fn text(&mut self) -> &mut Self::Text {
@@ -206,26 +426,52 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
 
     fn draw_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>) {
         let pos = pos.into();
-        let rect = layout.image_bounds() + pos.to_vec2();
-        let brush = layout.fg_color.make_brush(self, || rect);
-        self.ctx.set_scaled_font(&layout.font);
-        self.set_brush(&*brush);
+        for (rect, color) in layout.background_rects() {
+            self.fill(rect + pos.to_vec2(), &color);
+        }
 
-        for lm in &layout.line_metrics {
-            self.ctx.move_to(pos.x, pos.y + lm.y_offset + lm.baseline);
-            self.ctx.show_text(&layout.text[lm.range()]);
+        let shadows = layout.shadow_rects();
+        if !shadows.is_empty() {
+            // the toy text API can't draw shadows, so we blur the coverage
+            // of the glyph outlines inside each shadowed range.
+            let outline = layout.outline();
+            for (rects, shadow) in shadows {
+                let (image, origin) = compute_blurred_shape_in_rects(&outline, &rects, shadow.blur);
+                let origin = origin + pos.to_vec2() + shadow.offset;
+                let brush = self.solid_brush(shadow.color.clone());
+                self.set_brush(&brush);
+                self.draw_with_opacity(&brush, |ctx| ctx.mask_surface(&image, origin.x, origin.y));
+            }
         }
+
+        self.draw_glyphs(layout, pos);
     }
 
     fn save(&mut self) -> Result<(), Error> {
         self.ctx.save();
-        let state = self.transform_stack.last().copied().unwrap_or_default();
-        self.transform_stack.push(state);
+        self.state_stack.push(self.current_state());
+        self.layer_stack.push(None);
         self.status()
     }
 
+    fn save_layer(&mut self, opacity: f64) -> Result<(), Error> {
+        self.push_layer(Layer::Opacity(opacity))
+    }
+
+    fn save_layer_with_mask(&mut self, mask: LayerMask<CairoImage>) -> Result<(), Error> {
+        let matrix = self.ctx.get_matrix();
+        let layer = match mask {
+            LayerMask::Shape(path) => Layer::Shape(path, matrix),
+            LayerMask::Image(image, rect) => Layer::Image(image.0.clone(), rect, matrix),
+        };
+        self.push_layer(layer)
+    }
+
     fn restore(&mut self) -> Result<(), Error> {
-        if self.transform_stack.pop().is_some() {
+        if self.state_stack.pop().is_some() {
+            if let Some(Some(layer)) = self.layer_stack.pop() {
+                self.pop_layer(layer);
+            }
             // we're defensive about calling restore on the inner context,
             // because an unbalanced call will trigger a panic in cairo-rs
             self.ctx.restore();
@@ -235,47 +481,86 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
         }
     }
 
+    fn set_blend_mode(&mut self, mode: BlendMode) -> Result<(), Error> {
+        self.ctx.set_operator(convert_blend_mode(mode));
+        Ok(())
+    }
+
+    fn push_opacity(&mut self, alpha: f64) {
+        self.opacity.push(alpha);
+    }
+
+    fn pop_opacity(&mut self) -> Result<(), Error> {
+        self.opacity.pop()
+    }
+
     fn finish(&mut self) -> Result<(), Error> {
-        self.ctx.get_target().flush();
+        if self.layer_stack.iter().any(Option::is_some) {
+            return Err(Error::StackUnbalance);
+        }
+        let target = self.ctx.get_target();
+        target.flush();
+        check_status(target.status(), "cairo_surface_flush")?;
         self.status()
     }
 
     fn transform(&mut self, transform: Affine) {
-        if let Some(last) = self.transform_stack.last_mut() {
-            *last *= transform;
-        } else {
-            self.transform_stack.push(transform);
-        }
+        let state = self.current_state();
+        self.set_current_state(CtxState {
+            transform: state.transform * transform,
+            ..state
+        });
         self.ctx.transform(affine_to_matrix(transform));
     }
 
     fn current_transform(&self) -> Affine {
-        self.transform_stack.last().copied().unwrap_or_default()
+        self.current_state().transform
+    }
+
+    fn current_clip_bounds(&self) -> Option<Rect> {
+        let state = self.current_state();
+        util::user_clip_bounds(state.clip, state.transform)
+    }
+
+    fn make_image(
+        &mut self,
+        width: usize,
+        height: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<Self::Image, Error> {
+        let stride = width * format.bytes_per_pixel();
+        self.make_image_with_stride(width, height, stride, buf, format)
     }
 
     // allows e.g. raw_data[dst_off + x * 4 + 2] = buf[src_off + x * 4 + 0];
     #[allow(clippy::identity_op)]
-    fn make_image(
+    fn make_image_with_stride(
         &mut self,
         width: usize,
         height: usize,
+        src_stride: usize,
         buf: &[u8],
         format: ImageFormat,
     ) -> Result<Self::Image, Error> {
+        let bytes_per_row = util::check_image_stride(width, height, src_stride, buf.len(), format)?;
         let cairo_fmt = match format {
-            ImageFormat::Rgb => Format::Rgb24,
-            ImageFormat::RgbaSeparate | ImageFormat::RgbaPremul => Format::ARgb32,
+            // cairo's only 8 bit format is alpha, so we expand grayscale
+            ImageFormat::Rgb | ImageFormat::Grayscale => Format::Rgb24,
+            ImageFormat::RgbaSeparate
+            | ImageFormat::RgbaPremul
+            | ImageFormat::Bgra
+            | ImageFormat::BgraPremul => Format::ARgb32,
             _ => return Err(Error::NotSupported),
         };
-        let mut image = ImageSurface::create(cairo_fmt, width as i32, height as i32).wrap()?;
+        let mut image = ImageSurface::create(cairo_fmt, width as i32, height as i32)
+            .wrap("cairo_image_surface_create")?;
         // Confident no borrow errors because we just created it.
-        let bytes_per_pixel = format.bytes_per_pixel();
-        let bytes_per_row = width * bytes_per_pixel;
         let stride = image.get_stride() as usize;
         {
-            let mut data = image.get_data().wrap()?;
+            let mut data = image.get_data().wrap("cairo_image_surface_get_data")?;
             for y in 0..height {
-                let src_off = y * bytes_per_row;
+                let src_off = y * src_stride;
                 let dst_off = y * stride;
                 match format {
                     ImageFormat::Rgb => {
@@ -285,10 +570,17 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
                             data[dst_off + x * 4 + 2] = buf[src_off + x * 3 + 0];
                         }
                     }
+                    ImageFormat::Grayscale => {
+                        for x in 0..width {
+                            let v = buf[src_off + x];
+                            data[dst_off + x * 4 + 0] = v;
+                            data[dst_off + x * 4 + 1] = v;
+                            data[dst_off + x * 4 + 2] = v;
+                        }
+                    }
                     ImageFormat::RgbaPremul => {
                         // It's annoying that Cairo exposes only ARGB. Ah well. Let's
                         // hope that LLVM generates pretty good code for this.
-                        // TODO: consider adding BgraPremul format.
                         for x in 0..width {
                             data[dst_off + x * 4 + 0] = buf[src_off + x * 4 + 2];
                             data[dst_off + x * 4 + 1] = buf[src_off + x * 4 + 1];
@@ -309,11 +601,92 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
                             data[dst_off + x * 4 + 3] = a;
                         }
                     }
+                    // BGRA is cairo's own (little-endian) byte order
+                    ImageFormat::BgraPremul => {
+                        data[dst_off..dst_off + bytes_per_row]
+                            .copy_from_slice(&buf[src_off..src_off + bytes_per_row]);
+                    }
+                    ImageFormat::Bgra => {
+                        fn premul(x: u8, a: u8) -> u8 {
+                            let y = (x as u16) * (a as u16);
+                            ((y + (y >> 8) + 0x80) >> 8) as u8
+                        }
+                        for x in 0..width {
+                            let a = buf[src_off + x * 4 + 3];
+                            for c in 0..3 {
+                                data[dst_off + x * 4 + c] = premul(buf[src_off + x * 4 + c], a);
+                            }
+                            data[dst_off + x * 4 + 3] = a;
+                        }
+                    }
                     _ => return Err(Error::NotSupported),
                 }
             }
         }
-        Ok(image)
+        Ok(CairoImage(image))
+    }
+
+    fn capture_image_area(&mut self, src_rect: impl Into<Rect>) -> Result<Self::Image, Error> {
+        // This reads the target, under any layers that are still open.
+        let target = self.ctx.get_target();
+        target.flush();
+        check_status(target.status(), "cairo_surface_flush")?;
+        let mapped = MappedImage::new(&target)?;
+        let pixels = mapped.surface();
+
+        // Unlike our `state_stack`, the cairo matrix includes any transforms
+        // applied before we were made, and maps user space to the target's
+        // device space; its device scale and offset take that to the
+        // target's pixels, and the mapped image's device offset to its own.
+        let (scale_x, scale_y) = target.get_device_scale();
+        let (offset_x, offset_y) = target.get_device_offset();
+        let (mapped_x, mapped_y) = pixels.get_device_offset();
+        let to_pixels = Affine::new([
+            scale_x,
+            0.0,
+            0.0,
+            scale_y,
+            offset_x + mapped_x,
+            offset_y + mapped_y,
+        ]);
+        let transform = to_pixels * matrix_to_affine(self.ctx.get_matrix());
+        let size = Size::new(pixels.get_width() as f64, pixels.get_height() as f64);
+        let rect = util::capture_rect(src_rect.into(), transform, size)?;
+
+        let image = ImageSurface::create(Format::ARgb32, rect.width() as i32, rect.height() as i32)
+            .wrap("cairo_image_surface_create")?;
+        let cr = Context::new(&image);
+        cr.set_operator(Operator::Source);
+        // the mapped image's device offset applies when it is a source, too
+        cr.set_source_surface(pixels, mapped_x - rect.x0, mapped_y - rect.y0);
+        cr.paint();
+        check_status(cr.status(), "cairo_paint")?;
+        drop(cr);
+        image.flush();
+        Ok(CairoImage(image))
+    }
+
+    fn to_image_buf(&mut self, image: &CairoImage, format: ImageFormat) -> Result<ImageBuf, Error> {
+        let width = image.0.get_width();
+        let height = image.0.get_height();
+        // The image may be shared, by clones or brushes, which keeps us from
+        // borrowing its data, so we read from a copy.
+        let mut copy = ImageSurface::create(Format::ARgb32, width, height)
+            .wrap("cairo_image_surface_create")?;
+        {
+            let cr = Context::new(&copy);
+            cr.set_operator(Operator::Source);
+            cr.set_source_surface(&image.0, 0.0, 0.0);
+            cr.paint();
+        }
+        copy.flush();
+        let (width, height) = (width as usize, height as usize);
+        let stride = copy.get_stride() as usize;
+        let data = copy.get_data().wrap("cairo_image_surface_get_data")?;
+        // ARGB32 is BGRA in (little-endian) memory
+        let format_in = ImageFormat::BgraPremul;
+        let pixels = util::pack_image_rows(width, height, stride, &data, format_in)?;
+        Ok(ImageBuf::from_raw(pixels.into_owned(), format_in, width, height).to_format(format))
     }
 
     #[inline]
@@ -337,11 +710,11 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
         draw_image(self, image, Some(src_rect.into()), dst_rect.into(), interp);
     }
 
-    fn blurred_rect(&mut self, rect: Rect, blur_radius: f64, brush: &impl IntoBrush<Self>) {
-        let brush = brush.make_brush(self, || rect);
-        let (image, origin) = compute_blurred_rect(rect, blur_radius);
+    fn blurred_shape(&mut self, shape: impl Shape, blur_radius: f64, brush: &impl IntoBrush<Self>) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        let (image, origin) = compute_blurred_shape(&shape, blur_radius);
         self.set_brush(&*brush);
-        self.ctx.mask_surface(&image, origin.x, origin.y);
+        self.draw_with_opacity(&brush, |ctx| ctx.mask_surface(&image, origin.x, origin.y));
     }
 }
 
@@ -353,15 +726,16 @@ fn draw_image<'a>(
     interp: InterpolationMode,
 ) {
     let _ = ctx.with_save(|rc| {
-        let surface_pattern = SurfacePattern::create(image);
+        let surface_pattern = SurfacePattern::create(&image.0);
         let filter = match interp {
             InterpolationMode::NearestNeighbor => Filter::Nearest,
             InterpolationMode::Bilinear => Filter::Bilinear,
+            InterpolationMode::Bicubic => Filter::Best,
         };
         surface_pattern.set_filter(filter);
         let src_rect = match src_rect {
             Some(src_rect) => src_rect,
-            None => Size::new(image.get_width() as f64, image.get_height() as f64).to_rect(),
+            None => image.size().to_rect(),
         };
         let scale_x = dst_rect.width() / src_rect.width();
         let scale_y = dst_rect.height() / src_rect.height();
@@ -372,7 +746,7 @@ fn draw_image<'a>(
         );
         rc.ctx.scale(scale_x, scale_y);
         rc.ctx.set_source(&surface_pattern);
-        rc.ctx.paint();
+        rc.ctx.paint_with_alpha(rc.opacity.current());
         Ok(())
     });
 }
@@ -387,6 +761,35 @@ impl<'a> IntoBrush<CairoRenderContext<'a>> for Brush {
     }
 }
 
+fn convert_blend_mode(mode: BlendMode) -> Operator {
+    match mode {
+        BlendMode::Normal => Operator::Over,
+        BlendMode::Multiply => Operator::Multiply,
+        BlendMode::Screen => Operator::Screen,
+        BlendMode::Overlay => Operator::Overlay,
+        BlendMode::Darken => Operator::Darken,
+        BlendMode::Lighten => Operator::Lighten,
+        BlendMode::Clear => Operator::Clear,
+        BlendMode::SourceIn => Operator::In,
+        BlendMode::DestinationOut => Operator::DestOut,
+    }
+}
+
+fn convert_extend(extend: GradientExtend) -> Extend {
+    match extend {
+        GradientExtend::Pad => Extend::Pad,
+        GradientExtend::Repeat => Extend::Repeat,
+        GradientExtend::Reflect => Extend::Reflect,
+    }
+}
+
+fn convert_tile_mode(tile: TileMode) -> Extend {
+    match tile {
+        TileMode::Repeat => Extend::Repeat,
+        TileMode::Reflect => Extend::Reflect,
+    }
+}
+
 fn convert_line_cap(line_cap: LineCap) -> cairo::LineCap {
     match line_cap {
         LineCap::Butt => cairo::LineCap::Butt,
@@ -408,17 +811,197 @@ impl<'a> CairoRenderContext<'a> {
     ///
     /// Cairo is super stateful, and we're trying to have more retained stuff.
     /// This is part of the impedance matching.
+    /// Draw the glyphs of a layout, and its decorations.
+    #[cfg(any(not(feature = "pango"), feature = "send-layouts"))]
+    fn draw_glyphs(&mut self, layout: &CairoTextLayout, pos: Point) {
+        let rect = layout.image_bounds() + pos.to_vec2();
+        let brush = layout.fg_color.make_brush(self, || rect);
+        self.ctx.set_scaled_font(&layout.font);
+        self.set_brush(&*brush);
+
+        self.draw_with_opacity(&brush, |ctx| {
+            for line_number in 0..layout.line_count() {
+                for (origin, _, run, fallback) in layout.line_runs(line_number) {
+                    ctx.set_scaled_font(layout.run_font(fallback).0);
+                    ctx.move_to(pos.x + origin.x, pos.y + origin.y);
+                    ctx.show_text(run);
+                }
+            }
+
+            if let Some(ellipsis) = layout.ellipsis {
+                ctx.set_scaled_font(&layout.font);
+                ctx.move_to(pos.x + ellipsis.x, pos.y + ellipsis.y);
+                ctx.show_text(util::ELLIPSIS);
+            }
+
+            ctx.new_path();
+            for rect in layout.strikethrough_rects() {
+                let rect = rect + pos.to_vec2();
+                ctx.rectangle(rect.x0, rect.y0, rect.width(), rect.height());
+            }
+            ctx.fill();
+        });
+
+        let thickness = layout.underline_thickness();
+        for (path, style, color) in layout.underline_paths() {
+            let path = Affine::translate(pos.to_vec2()) * path;
+            let color = color.as_ref().unwrap_or(&layout.fg_color);
+            self.stroke_styled(path, color, thickness, &style);
+        }
+    }
+
+    /// Draw the glyphs of a layout, and its decorations.
+    #[cfg(all(feature = "pango", not(feature = "send-layouts")))]
+    fn draw_glyphs(&mut self, layout: &CairoTextLayout, pos: Point) {
+        // Pango draws the ranges with a foreground color itself, at their own
+        // alpha, so the text is drawn into a group to apply the opacity
+        let translucent = self.opacity.is_translucent();
+        if translucent {
+            self.ctx.push_group();
+        }
+        let (r, g, b, a) = layout.fg_color.as_rgba();
+        self.ctx.set_source_rgba(r, g, b, a);
+        layout.draw(self.ctx, pos);
+        if translucent {
+            self.ctx.pop_group_to_source();
+            self.ctx.paint_with_alpha(self.opacity.current());
+        }
+    }
+
     fn set_brush(&mut self, brush: &Brush) {
         match *brush {
             Brush::Solid(rgba) => self.ctx.set_source_rgba(
                 byte_to_frac(rgba >> 24),
                 byte_to_frac(rgba >> 16),
                 byte_to_frac(rgba >> 8),
-                byte_to_frac(rgba),
+                byte_to_frac(rgba) * self.opacity.current(),
             ),
             Brush::Linear(ref linear) => self.ctx.set_source(linear),
             Brush::Radial(ref radial) => self.ctx.set_source(radial),
+            Brush::Sweep(ref sweep) => self.set_sweep_source(sweep),
+            Brush::Image(ref pattern) => self.ctx.set_source(pattern),
+        }
+    }
+
+    /// Draw with the source set by `set_brush`, at the current opacity.
+    ///
+    /// Solid colors already have the opacity in their alpha; other sources
+    /// are drawn into a group, which is painted with the opacity.
+    fn draw_with_opacity(&mut self, brush: &Brush, draw: impl FnOnce(&Context)) {
+        if !self.opacity.is_translucent() || matches!(brush, Brush::Solid(_)) {
+            return draw(self.ctx);
+        }
+        self.ctx.push_group();
+        draw(self.ctx);
+        self.ctx.pop_group_to_source();
+        self.ctx.paint_with_alpha(self.opacity.current());
+    }
+
+    fn clip_with_rule(&mut self, shape: impl Shape, fill_rule: cairo::FillRule) {
+        let state = self.current_state();
+        let clip = util::intersect_clip_bounds(state.clip, shape.bounding_box(), state.transform);
+        self.set_current_state(CtxState {
+            clip: Some(clip),
+            ..state
+        });
+        self.set_path(shape);
+        self.ctx.set_fill_rule(fill_rule);
+        self.ctx.clip();
+    }
+
+    fn current_state(&self) -> CtxState {
+        self.state_stack.last().copied().unwrap_or_default()
+    }
+
+    fn set_current_state(&mut self, state: CtxState) {
+        if let Some(last) = self.state_stack.last_mut() {
+            *last = state;
+        } else {
+            self.state_stack.push(state);
+        }
+    }
+
+    /// Start drawing into a group, to be composited on restore.
+    fn push_layer(&mut self, layer: Layer) -> Result<(), Error> {
+        // the outer save keeps the source set by `pop_group_to_source`
+        // from leaking out of `restore`.
+        self.ctx.save();
+        self.ctx.push_group();
+        self.state_stack.push(self.current_state());
+        self.layer_stack.push(Some(layer));
+        self.status()
+    }
+
+    /// Composite the current group; the caller restores the outer save.
+    fn pop_layer(&mut self, layer: Layer) {
+        self.ctx.pop_group_to_source();
+        match layer {
+            Layer::Opacity(opacity) => self.ctx.paint_with_alpha(opacity),
+            Layer::Shape(path, matrix) => {
+                self.ctx.set_matrix(matrix);
+                self.set_path(path);
+                self.ctx.set_fill_rule(cairo::FillRule::Winding);
+                self.ctx.fill();
+            }
+            Layer::Image(image, rect, matrix) => {
+                self.ctx.set_matrix(matrix);
+                self.ctx.translate(rect.x0, rect.y0);
+                self.ctx.scale(
+                    rect.width() / image.get_width() as f64,
+                    rect.height() / image.get_height() as f64,
+                );
+                self.ctx.mask(&SurfacePattern::create(&image));
+            }
+        }
+    }
+
+    /// Set a sweep gradient as the source.
+    ///
+    /// Cairo has no sweep gradients, so we rasterize the gradient for the
+    /// device pixels covered by the current path (or by the clip, if there
+    /// is no path), and use the resulting image as the source.
+    fn set_sweep_source(&mut self, sweep: &FixedSweepGradient) {
+        let (x0, y0, x1, y1) = self.ctx.clip_extents();
+        let mut user_rect = Rect::new(x0, y0, x1, y1);
+        if self.ctx.has_current_point() {
+            let (x0, y0, x1, y1) = self.ctx.path_extents();
+            let path_rect = Rect::new(x0, y0, x1, y1);
+            let (x0, y0, x1, y1) = self.ctx.stroke_extents();
+            let stroke_rect = Rect::new(x0, y0, x1, y1);
+            user_rect = user_rect.intersect(path_rect.union(stroke_rect));
+        }
+        let device_affine = matrix_to_affine(self.ctx.get_matrix());
+        let device_rect = device_affine.transform_rect_bbox(user_rect).expand();
+        let width = device_rect.width() as i32;
+        let height = device_rect.height() as i32;
+        let mut image = match ImageSurface::create(Format::ARgb32, width.max(1), height.max(1)) {
+            Ok(image) => image,
+            Err(_) => return,
+        };
+        let inverse = device_affine.inverse();
+        let stride = image.get_stride() as usize;
+        if let Ok(mut data) = image.get_data() {
+            for y in 0..height as usize {
+                for x in 0..width as usize {
+                    let device_pt = Point::new(
+                        device_rect.x0 + x as f64 + 0.5,
+                        device_rect.y0 + y as f64 + 0.5,
+                    );
+                    let (r, g, b, a) = sweep.color_at(inverse * device_pt).as_rgba8();
+                    let premul = |c: u8| ((c as u16 * a as u16 + 127) / 255) as u8;
+                    let off = y * stride + x * 4;
+                    data[off] = premul(b);
+                    data[off + 1] = premul(g);
+                    data[off + 2] = premul(r);
+                    data[off + 3] = a;
+                }
+            }
         }
+        let pattern = SurfacePattern::create(&image);
+        let pattern_affine = Affine::translate(-device_rect.origin().to_vec2()) * device_affine;
+        pattern.set_matrix(affine_to_matrix(pattern_affine));
+        pattern.set_extend(Extend::Pad);
+        self.ctx.set_source(&pattern);
     }
 
     /// Set the stroke parameters.
@@ -435,7 +1018,9 @@ impl<'a> CairoRenderContext<'a> {
             .unwrap_or(LineCap::Butt);
         self.ctx.set_line_cap(convert_line_cap(line_cap));
 
-        let miter_limit = style.and_then(|style| style.miter_limit).unwrap_or(10.0);
+        let miter_limit = style
+            .and_then(|style| style.miter_limit)
+            .unwrap_or(StrokeStyle::DEFAULT_MITER_LIMIT);
         self.ctx.set_miter_limit(miter_limit);
 
         match style.and_then(|style| style.dash.as_ref()) {
@@ -448,6 +1033,40 @@ impl<'a> CairoRenderContext<'a> {
         // This shouldn't be necessary, we always leave the context in no-path
         // state. But just in case, and it should be harmless.
         self.ctx.new_path();
+        // use cairo's own primitives where we can, which are faster than
+        // converting to a path, particularly for circles
+        if let Some(rect) = shape.as_rect() {
+            self.ctx
+                .rectangle(rect.x0, rect.y0, rect.width(), rect.height());
+            return;
+        }
+        if let Some(circle) = shape.as_circle().filter(|c| c.radius >= 0.0) {
+            let (x, y) = (circle.center.x, circle.center.y);
+            self.ctx.arc(x, y, circle.radius, 0.0, 2.0 * PI);
+            self.ctx.close_path();
+            return;
+        }
+        if let Some(rounded) = shape.as_rounded_rect() {
+            let (rect, r) = (rounded.rect(), rounded.radius());
+            // the corners in the same order as kurbo's path
+            let corners = [
+                (rect.x0 + r, rect.y0 + r),
+                (rect.x1 - r, rect.y0 + r),
+                (rect.x1 - r, rect.y1 - r),
+                (rect.x0 + r, rect.y1 - r),
+            ];
+            for (i, &(x, y)) in corners.iter().enumerate() {
+                let start = FRAC_PI_2 * (i + 2) as f64;
+                self.ctx.arc(x, y, r, start, start + FRAC_PI_2);
+            }
+            self.ctx.close_path();
+            return;
+        }
+        if let Some(line) = shape.as_line() {
+            self.ctx.move_to(line.p0.x, line.p0.y);
+            self.ctx.line_to(line.p1.x, line.p1.y);
+            return;
+        }
         let mut last = Point::ZERO;
         for el in shape.to_bez_path(1e-3) {
             match el {
@@ -493,15 +1112,762 @@ fn affine_to_matrix(affine: Affine) -> Matrix {
     }
 }
 
-fn compute_blurred_rect(rect: Rect, radius: f64) -> (ImageSurface, Point) {
-    let size = piet::util::size_for_blurred_rect(rect, radius);
+fn matrix_to_affine(matrix: Matrix) -> Affine {
+    Affine::new([
+        matrix.xx, matrix.yx, matrix.xy, matrix.yy, matrix.x0, matrix.y0,
+    ])
+}
+
+fn compute_blurred_shape_in_rects(
+    shape: &impl Shape,
+    rects: &[Rect],
+    radius: f64,
+) -> (ImageSurface, Point) {
+    let size = piet::util::size_for_blurred_shape_in_rects(shape, rects, radius);
+    let mut image =
+        ImageSurface::create(Format::A8, size.width as i32, size.height as i32).unwrap();
+    let stride = image.get_stride() as usize;
+    let mut data = image.get_data().unwrap();
+    let rect_exp =
+        piet::util::compute_blurred_shape_in_rects(shape, rects, radius, stride, &mut data);
+    std::mem::drop(data);
+    (image, rect_exp.origin())
+}
+
+fn compute_blurred_shape(shape: &impl Shape, radius: f64) -> (ImageSurface, Point) {
+    let size = piet::util::size_for_blurred_shape(shape, radius);
     // TODO: maybe not panic on error (but likely to happen only in extreme cases such as OOM)
     let mut image =
         ImageSurface::create(Format::A8, size.width as i32, size.height as i32).unwrap();
     let stride = image.get_stride() as usize;
     let mut data = image.get_data().unwrap();
-    let rect_exp = piet::util::compute_blurred_rect(rect, radius, stride, &mut *data);
+    let rect_exp = piet::util::compute_blurred_shape(shape, radius, stride, &mut *data);
     std::mem::drop(data);
     let origin = rect_exp.origin();
     (image, origin)
 }
+
+#[cfg(test)]
+mod conformance;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use piet::kurbo::Insets;
+    use piet::{ColorInterpolation, GradientStop, LinearGradient, ShadowStyle, UnitPoint};
+
+    #[test]
+    fn sweep_gradient_colors() {
+        let mut surface = ImageSurface::create(Format::ARgb32, 40, 40).unwrap();
+        {
+            let cr = Context::new(&surface);
+            let mut piet = CairoRenderContext::new(&cr);
+            let stop = |pos, color| GradientStop { pos, color };
+            let sweep = FixedSweepGradient {
+                center: Point::new(20.0, 20.0),
+                start_angle: 0.0,
+                stops: vec![
+                    stop(0.0, Color::rgb8(255, 0, 0)),
+                    stop(0.25, Color::rgb8(0, 255, 0)),
+                    stop(0.5, Color::rgb8(0, 0, 255)),
+                    stop(1.0, Color::rgb8(255, 0, 0)),
+                ],
+                interpolation: ColorInterpolation::Srgb,
+            };
+            let brush = piet.gradient(sweep).unwrap();
+            piet.fill(Rect::new(0.0, 0.0, 40.0, 40.0), &brush);
+            piet.finish().unwrap();
+        }
+        surface.flush();
+        let stride = surface.get_stride() as usize;
+        let data = surface.get_data().unwrap();
+        // returns (r, g, b) for the pixel at (x, y)
+        let pixel = |x: usize, y: usize| {
+            let off = y * stride + x * 4;
+            (data[off + 2], data[off + 1], data[off])
+        };
+
+        let (r, g, b) = pixel(35, 20);
+        assert!(r > 240 && g < 15 && b < 15, "{:?}", (r, g, b));
+        let (r, g, b) = pixel(20, 35);
+        assert!(g > 240 && r < 15 && b < 15, "{:?}", (r, g, b));
+        let (r, g, b) = pixel(5, 20);
+        assert!(b > 240 && r < 15 && g < 15, "{:?}", (r, g, b));
+    }
+
+    #[test]
+    fn linear_rgb_interpolation() {
+        // returns (r, g) at the middle of a red-green ramp
+        fn midpoint(interpolation: ColorInterpolation) -> (u8, u8) {
+            let mut surface = ImageSurface::create(Format::ARgb32, 101, 1).unwrap();
+            {
+                let cr = Context::new(&surface);
+                let mut piet = CairoRenderContext::new(&cr);
+                let gradient = LinearGradient::new(
+                    UnitPoint::LEFT,
+                    UnitPoint::RIGHT,
+                    (Color::rgb8(255, 0, 0), Color::rgb8(0, 255, 0)),
+                )
+                .with_interpolation(interpolation);
+                piet.fill(Rect::new(0.0, 0.0, 101.0, 1.0), &gradient);
+                piet.finish().unwrap();
+            }
+            surface.flush();
+            let data = surface.get_data().unwrap();
+            (data[50 * 4 + 2], data[50 * 4 + 1])
+        }
+
+        // sRGB goes through a dark, muddy yellow
+        let (r, g) = midpoint(ColorInterpolation::Srgb);
+        assert!((r as i32 - 128).abs() <= 3, "{}", r);
+        assert!((g as i32 - 128).abs() <= 3, "{}", g);
+        // linear RGB is half of each primary's light, which is much brighter
+        let (r, g) = midpoint(ColorInterpolation::LINEAR_RGB);
+        assert!((r as i32 - 188).abs() <= 4, "{}", r);
+        assert!((g as i32 - 188).abs() <= 4, "{}", g);
+    }
+
+    #[test]
+    fn image_brush_tiles() {
+        // returns the red channel along a row filled with a red and black tile
+        fn row(tile: TileMode) -> Vec<u8> {
+            let mut surface = ImageSurface::create(Format::ARgb32, 8, 1).unwrap();
+            {
+                let cr = Context::new(&surface);
+                let mut piet = CairoRenderContext::new(&cr);
+                let buf = [255, 0, 0, 255, 0, 0, 0, 255];
+                let image = piet
+                    .make_image(2, 1, &buf, ImageFormat::RgbaSeparate)
+                    .unwrap();
+                let brush = piet
+                    .make_image_brush(&image, tile, Affine::default())
+                    .unwrap();
+                // the same brush follows the current transform
+                piet.fill(Rect::new(0.0, 0.0, 4.0, 1.0), &brush);
+                piet.transform(Affine::translate((5.0, 0.0)));
+                piet.fill(Rect::new(-1.0, 0.0, 3.0, 1.0), &brush);
+                piet.finish().unwrap();
+            }
+            surface.flush();
+            let data = surface.get_data().unwrap();
+            data.chunks(4).map(|px| px[2]).collect()
+        }
+
+        assert_eq!(row(TileMode::Repeat), [255, 0, 255, 0, 0, 255, 0, 255]);
+        assert_eq!(row(TileMode::Reflect), [255, 0, 0, 255, 255, 255, 0, 0]);
+    }
+
+    #[test]
+    fn grayscale_image() {
+        let mut surface = ImageSurface::create(Format::ARgb32, 3, 1).unwrap();
+        {
+            let cr = Context::new(&surface);
+            let mut piet = CairoRenderContext::new(&cr);
+            let buf = [0, 0x80, 0xff];
+            let image = piet.make_image(3, 1, &buf, ImageFormat::Grayscale).unwrap();
+            piet.draw_image(
+                &image,
+                Rect::new(0.0, 0.0, 3.0, 1.0),
+                InterpolationMode::NearestNeighbor,
+            );
+            piet.finish().unwrap();
+        }
+        surface.flush();
+        let data = surface.get_data().unwrap();
+        assert_eq!(
+            &data[..],
+            &[0, 0, 0, 0xff, 0x80, 0x80, 0x80, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+    }
+
+    #[test]
+    fn image_round_trip() {
+        let surface = ImageSurface::create(Format::ARgb32, 1, 1).unwrap();
+        let cr = Context::new(&surface);
+        let mut piet = CairoRenderContext::new(&cr);
+        let premul: Vec<u8> = (0..=255).flat_map(|a| vec![a / 2, a / 3, a, a]).collect();
+        let image = piet
+            .make_image(16, 16, &premul, ImageFormat::RgbaPremul)
+            .unwrap();
+        // a brush keeps a reference to the image surface
+        let _brush = piet
+            .make_image_brush(&image, TileMode::Repeat, Affine::default())
+            .unwrap();
+        let buf = piet.to_image_buf(&image, ImageFormat::RgbaPremul).unwrap();
+        assert_eq!((buf.width(), buf.height()), (16, 16));
+        assert_eq!(buf.raw_pixels(), &premul[..]);
+
+        let rgb = [1, 2, 3, 4, 5, 6];
+        let image = piet.make_image(2, 1, &rgb, ImageFormat::Rgb).unwrap();
+        let buf = piet.to_image_buf(&image, ImageFormat::Rgb).unwrap();
+        assert_eq!(buf.raw_pixels(), &rgb);
+        piet.finish().unwrap();
+    }
+
+    #[test]
+    fn image_with_stride() {
+        let mut surface = ImageSurface::create(Format::ARgb32, 2, 2).unwrap();
+        {
+            let cr = Context::new(&surface);
+            let mut piet = CairoRenderContext::new(&cr);
+            // two rows of two pixels, each padded with a junk byte
+            let buf = [0x10, 0x20, 0xff, 0x30, 0x40, 0xff];
+            let fmt = ImageFormat::Grayscale;
+            assert!(piet.make_image_with_stride(2, 2, 1, &buf, fmt).is_err());
+            assert!(piet.make_image_with_stride(2, 3, 3, &buf, fmt).is_err());
+            let image = piet.make_image_with_stride(2, 2, 3, &buf, fmt).unwrap();
+            piet.draw_image(
+                &image,
+                Rect::new(0.0, 0.0, 2.0, 2.0),
+                InterpolationMode::NearestNeighbor,
+            );
+            piet.finish().unwrap();
+        }
+        surface.flush();
+        let data = surface.get_data().unwrap();
+        let gray: Vec<u8> = data.chunks(4).map(|px| px[0]).collect();
+        assert_eq!(gray, [0x10, 0x20, 0x30, 0x40]);
+    }
+
+    #[test]
+    fn bgra_images() {
+        fn draw(buf: &[u8], format: ImageFormat) -> Vec<u8> {
+            let mut surface = ImageSurface::create(Format::ARgb32, 2, 1).unwrap();
+            {
+                let cr = Context::new(&surface);
+                let mut piet = CairoRenderContext::new(&cr);
+                let image = piet.make_image(2, 1, buf, format).unwrap();
+                piet.draw_image(
+                    &image,
+                    Rect::new(0.0, 0.0, 2.0, 1.0),
+                    InterpolationMode::NearestNeighbor,
+                );
+                piet.finish().unwrap();
+            }
+            surface.flush();
+            let data = surface.get_data().unwrap();
+            data.to_vec()
+        }
+
+        let premul = [10, 20, 30, 255, 20, 40, 60, 128];
+        assert_eq!(draw(&premul, ImageFormat::BgraPremul), premul);
+        let separate = [10, 20, 30, 255, 40, 80, 120, 128];
+        assert_eq!(draw(&separate, ImageFormat::Bgra), premul);
+    }
+
+    #[test]
+    fn draw_image_nine_slices() {
+        let mut surface = ImageSurface::create(Format::ARgb32, 8, 6).unwrap();
+        {
+            let cr = Context::new(&surface);
+            let mut piet = CairoRenderContext::new(&cr);
+            // a 3x3 image, with a different red value for each slice
+            let buf: Vec<u8> = (1..=9).flat_map(|i| vec![i * 16, 0, 0]).collect();
+            let image = piet.make_image(3, 3, &buf, ImageFormat::Rgb).unwrap();
+            let insets = Insets::uniform(1.0);
+            let nearest = InterpolationMode::NearestNeighbor;
+            piet.draw_image_nine(&image, insets, Rect::new(0.0, 0.0, 6.0, 6.0), nearest);
+            // just wide enough for the corners, so the middle column is empty
+            piet.draw_image_nine(&image, insets, Rect::new(6.0, 0.0, 8.0, 6.0), nearest);
+            piet.finish().unwrap();
+        }
+        surface.flush();
+        let data = surface.get_data().unwrap();
+        let rows: Vec<Vec<u8>> = data
+            .chunks(8 * 4)
+            .map(|row| row.chunks(4).map(|px| px[2] / 16).collect())
+            .collect();
+        assert_eq!(rows[0], [1, 2, 2, 2, 2, 3, 1, 3]);
+        for row in &rows[1..5] {
+            assert_eq!(row, &[4, 5, 5, 5, 5, 6, 4, 6]);
+        }
+        assert_eq!(rows[5], [7, 8, 8, 8, 8, 9, 7, 9]);
+    }
+
+    #[test]
+    fn native_shapes_match_paths() {
+        use piet::kurbo::{BezPath, Circle, Line, RoundedRect};
+
+        fn draw(shape: impl Shape + Clone) -> Vec<u8> {
+            let mut surface = ImageSurface::create(Format::ARgb32, 40, 40).unwrap();
+            {
+                let cr = Context::new(&surface);
+                let mut piet = CairoRenderContext::new(&cr);
+                piet.clear(Color::WHITE);
+                piet.fill(shape.clone(), &Color::rgb8(0, 0, 0xff));
+                let style = StrokeStyle::new().dash(vec![3.0, 2.0], 1.0);
+                piet.stroke_styled(shape, &Color::BLACK, 2.0, &style);
+                piet.finish().unwrap();
+            }
+            let data = surface.get_data().unwrap().to_vec();
+            data
+        }
+
+        fn check(shape: impl Shape + Clone) {
+            let path = BezPath::from_vec(shape.to_bez_path(1e-3).collect());
+            let (native, path) = (draw(shape), draw(path));
+            let diff = native
+                .iter()
+                .zip(&path)
+                .map(|(a, b)| (*a as i32 - *b as i32).abs());
+            // cairo approximates its arcs to its own tolerance, which moves
+            // the edges by a fraction of a pixel
+            assert!(diff.max().unwrap() <= 24);
+        }
+
+        check(Rect::new(5.5, 5.0, 30.0, 34.5));
+        check(RoundedRect::new(5.0, 5.0, 35.0, 30.0, 8.0));
+        check(Circle::new((20.0, 20.0), 14.5));
+        check(Line::new((3.0, 5.0), (36.0, 30.0)));
+    }
+
+    #[test]
+    fn clip_bounds() {
+        let surface = ImageSurface::create(Format::ARgb32, 100, 100).unwrap();
+        let cr = Context::new(&surface);
+        let mut piet = CairoRenderContext::new(&cr);
+        let clip_extents = |cr: &Context| {
+            let (x0, y0, x1, y1) = cr.clip_extents();
+            Rect::new(x0, y0, x1, y1)
+        };
+        assert_eq!(piet.current_clip_bounds(), None);
+
+        piet.save().unwrap();
+        piet.transform(Affine::translate((10.0, 10.0)));
+        piet.clip(Rect::new(0.0, 0.0, 60.0, 60.0));
+        let bounds = Rect::new(0.0, 0.0, 60.0, 60.0);
+        assert_eq!(piet.current_clip_bounds(), Some(bounds));
+        assert_eq!(clip_extents(&cr), bounds);
+
+        piet.save().unwrap();
+        piet.transform(Affine::scale(2.0));
+        // partly outside the first clip
+        piet.clip(Rect::new(10.0, 20.0, 40.0, 40.0));
+        let bounds = Rect::new(10.0, 20.0, 30.0, 30.0);
+        assert_eq!(piet.current_clip_bounds(), Some(bounds));
+        assert_eq!(clip_extents(&cr), bounds);
+        // transforms after the clip change its bounds, but not what's clipped
+        piet.transform(Affine::translate((-10.0, 0.0)));
+        assert_eq!(
+            piet.current_clip_bounds(),
+            Some(Rect::new(20.0, 20.0, 40.0, 30.0))
+        );
+        piet.restore().unwrap();
+
+        assert_eq!(
+            piet.current_clip_bounds(),
+            Some(Rect::new(0.0, 0.0, 60.0, 60.0))
+        );
+        piet.restore().unwrap();
+        assert_eq!(piet.current_clip_bounds(), None);
+    }
+
+    #[test]
+    fn save_guard_restores() {
+        let surface = ImageSurface::create(Format::ARgb32, 10, 10).unwrap();
+        let cr = Context::new(&surface);
+        let mut piet = CairoRenderContext::new(&cr);
+        fn draw(rc: &mut CairoRenderContext, fail: bool) -> Result<(), Error> {
+            let mut rc = rc.save_guard();
+            rc.transform(Affine::scale(2.0));
+            rc.clip(Rect::new(0.0, 0.0, 2.0, 2.0));
+            if fail {
+                return Err(Error::InvalidInput);
+            }
+            rc.save_guard().restore()
+        }
+        assert!(draw(&mut piet, true).is_err());
+        assert!(draw(&mut piet, false).is_ok());
+        assert_eq!(piet.current_transform(), Affine::default());
+        assert_eq!(piet.current_clip_bounds(), None);
+        // every save was restored
+        assert!(matches!(piet.restore(), Err(Error::StackUnbalance)));
+    }
+
+    #[test]
+    fn layer_opacity() {
+        let mut surface = ImageSurface::create(Format::ARgb32, 30, 10).unwrap();
+        {
+            let cr = Context::new(&surface);
+            let mut piet = CairoRenderContext::new(&cr);
+            piet.clear(Color::WHITE);
+            let red = Color::rgb8(255, 0, 0);
+            piet.with_layer(0.5, |rc| {
+                rc.fill(Rect::new(0.0, 0.0, 20.0, 10.0), &red);
+                rc.fill(Rect::new(10.0, 0.0, 30.0, 10.0), &red);
+                Ok(())
+            })
+            .unwrap();
+            piet.finish().unwrap();
+        }
+        surface.flush();
+        let data = surface.get_data().unwrap();
+        // returns the green channel at (x, 5)
+        let stride = 30 * 4;
+        let green = |x: usize| data[5 * stride + x * 4 + 1];
+
+        // the overlap is no darker than the rest of the group
+        assert!((green(5) as i32 - 128).abs() <= 2, "{}", green(5));
+        assert_eq!(green(5), green(15));
+        assert_eq!(green(15), green(25));
+    }
+
+    #[test]
+    fn opacity_stack() {
+        let mut surface = ImageSurface::create(Format::ARgb32, 50, 10).unwrap();
+        {
+            let cr = Context::new(&surface);
+            let mut piet = CairoRenderContext::new(&cr);
+            piet.clear(Color::WHITE);
+            let red = Color::rgb8(255, 0, 0);
+            let gradient = LinearGradient::new(
+                UnitPoint::LEFT,
+                UnitPoint::RIGHT,
+                (red.clone(), red.clone()),
+            );
+            let image = piet
+                .make_image(1, 1, &[255, 0, 0, 255], ImageFormat::RgbaSeparate)
+                .unwrap();
+            piet.push_opacity(0.5);
+            // each shape is faded on its own, so overlaps are darker
+            piet.fill(Rect::new(0.0, 0.0, 20.0, 10.0), &red);
+            piet.fill(Rect::new(10.0, 0.0, 20.0, 10.0), &red);
+            piet.with_opacity(0.5, |rc| {
+                rc.fill(Rect::new(20.0, 0.0, 30.0, 10.0), &gradient);
+                Ok(())
+            })
+            .unwrap();
+            piet.draw_image(
+                &image,
+                Rect::new(30.0, 0.0, 40.0, 10.0),
+                InterpolationMode::NearestNeighbor,
+            );
+            piet.pop_opacity().unwrap();
+            piet.fill(Rect::new(40.0, 0.0, 50.0, 10.0), &red);
+            assert!(matches!(piet.pop_opacity(), Err(Error::StackUnbalance)));
+            piet.finish().unwrap();
+        }
+        surface.flush();
+        let data = surface.get_data().unwrap();
+        // returns the green channel at (x, 5)
+        let stride = 50 * 4;
+        let green = |x: usize| data[5 * stride + x * 4 + 1] as i32;
+
+        assert!((green(5) - 128).abs() <= 2, "{}", green(5));
+        assert!((green(15) - 64).abs() <= 2, "{}", green(15));
+        assert!((green(25) - 191).abs() <= 2, "{}", green(25));
+        assert!((green(35) - 128).abs() <= 2, "{}", green(35));
+        assert_eq!(green(45), 0);
+    }
+
+    #[test]
+    fn fill_with_shadow() {
+        let mut surface = ImageSurface::create(Format::ARgb32, 40, 20).unwrap();
+        {
+            let cr = Context::new(&surface);
+            let mut piet = CairoRenderContext::new(&cr);
+            piet.clear(Color::WHITE);
+            // the offset scales with the transform
+            piet.transform(Affine::scale(2.0));
+            let shadow = ShadowStyle::new((5.0, 0.0), 0.5, Color::BLACK);
+            let red = Color::rgb8(255, 0, 0);
+            piet.fill_with_shadow(Rect::new(2.0, 2.0, 8.0, 8.0), &red, &shadow);
+            piet.finish().unwrap();
+        }
+        surface.flush();
+        let data = surface.get_data().unwrap();
+        // returns the red and green channels at (x, 10)
+        let stride = 40 * 4;
+        let pixel = |x: usize| (data[10 * stride + x * 4 + 2], data[10 * stride + x * 4 + 1]);
+
+        assert_eq!(pixel(2), (255, 255));
+        assert_eq!(pixel(10), (255, 0));
+        assert!(pixel(22).0 <= 8, "{:?}", pixel(22));
+        assert_eq!(pixel(32), (255, 255));
+    }
+
+    #[test]
+    fn layer_masks() {
+        let mut surface = ImageSurface::create(Format::ARgb32, 40, 10).unwrap();
+        {
+            let cr = Context::new(&surface);
+            let mut piet = CairoRenderContext::new(&cr);
+            piet.clear(Color::WHITE);
+            // transparent on the left, opaque on the right
+            let buf: Vec<u8> = (0..200)
+                .flat_map(|i| vec![0, 0, 0, if i % 20 < 10 { 0 } else { 255 }])
+                .collect();
+            let image = piet
+                .make_image(20, 10, &buf, ImageFormat::RgbaSeparate)
+                .unwrap();
+            let black = Color::BLACK;
+
+            // the mask is positioned by the transform at the time of the call
+            piet.transform(Affine::translate((20.0, 0.0)));
+            let mask = LayerMask::Image(&image, Rect::new(0.0, 0.0, 20.0, 10.0));
+            piet.save_layer_with_mask(mask).unwrap();
+            piet.transform(Affine::translate((-20.0, 0.0)));
+            piet.fill(Rect::new(0.0, 0.0, 40.0, 10.0), &black);
+            piet.restore().unwrap();
+
+            piet.transform(Affine::translate((-20.0, 0.0)));
+            let mask = LayerMask::shape(Rect::new(0.0, 0.0, 5.5, 10.0));
+            piet.with_save(|rc| {
+                rc.save_layer_with_mask(mask)?;
+                rc.fill(Rect::new(0.0, 0.0, 20.0, 10.0), &black);
+                rc.restore()
+            })
+            .unwrap();
+            piet.finish().unwrap();
+        }
+        surface.flush();
+        let data = surface.get_data().unwrap();
+        let value = |x: usize| data[5 * 40 * 4 + x * 4];
+
+        assert_eq!(value(2), 0);
+        // half covered by the shape
+        assert!((value(5) as i32 - 128).abs() <= 2, "{}", value(5));
+        assert_eq!(value(10), 255);
+        assert_eq!(value(25), 255);
+        assert_eq!(value(35), 0);
+    }
+
+    #[test]
+    fn backend_error_context() {
+        let surface = ImageSurface::create(Format::ARgb32, 1, 1).unwrap();
+        let cr = Context::new(&surface);
+        let mut piet = CairoRenderContext::new(&cr);
+        // larger than cairo's maximum image size
+        let width = 40_000;
+        let buf = vec![0; width * 4];
+        let err = piet
+            .make_image(width, 1, &buf, ImageFormat::RgbaPremul)
+            .err()
+            .unwrap();
+        let cairo_err = err.backend_error_downcast::<CairoError>().unwrap();
+        assert_eq!(cairo_err.operation(), "cairo_image_surface_create");
+        assert_eq!(cairo_err.status(), Some(Status::InvalidSize));
+        assert!(err
+            .to_string()
+            .contains("cairo_image_surface_create failed: Status::InvalidSize"));
+        assert!(std::error::Error::source(&err).is_some());
+        assert!(err.backend_error_downcast::<std::io::Error>().is_none());
+        piet.finish().unwrap();
+    }
+
+    #[test]
+    #[cfg(any(not(feature = "pango"), feature = "send-layouts"))]
+    fn color_glyphs() {
+        use piet::{Text, TextAttribute, TextLayout, TextLayoutBuilder};
+
+        let mut surface = ImageSurface::create(Format::ARgb32, 60, 40).unwrap();
+        let layout = {
+            let cr = Context::new(&surface);
+            let mut piet = CairoRenderContext::new(&cr);
+            let font = text::test::test_color_font("Piet Cairo Color");
+            let family = piet.text().load_font(&font).unwrap();
+            let layout = piet
+                .text()
+                .new_text_layout("AA")
+                .font(family, 40.0)
+                .default_attribute(TextAttribute::ForegroundColor(Color::rgb8(0, 0, 255)))
+                .build()
+                .unwrap();
+            piet.clear(Color::WHITE);
+            piet.draw_text(&layout, (0.0, 0.0));
+            piet.finish().unwrap();
+            layout
+        };
+        surface.flush();
+        let stride = surface.get_stride() as usize;
+        let data = surface.get_data().unwrap();
+        let pixel = |x: usize, y: usize| {
+            let off = y * stride + x * 4;
+            (data[off + 2], data[off + 1], data[off])
+        };
+
+        // each 'A' is drawn in its palette's red rather than the text color,
+        // advancing by its own width
+        let x = layout.hit_test_text_position(1).unwrap().point.x;
+        assert!((x - 24.0).abs() < 0.01, "{}", x);
+        assert_eq!(pixel(10, 20), (255, 0, 0));
+        assert_eq!(pixel(22, 20), (255, 255, 255));
+        assert_eq!(pixel(34, 20), (255, 0, 0));
+    }
+
+    #[test]
+    fn unbalanced_layer() {
+        let surface = ImageSurface::create(Format::ARgb32, 10, 10).unwrap();
+        let cr = Context::new(&surface);
+        let mut piet = CairoRenderContext::new(&cr);
+        piet.save_layer(0.5).unwrap();
+        assert!(matches!(piet.finish(), Err(Error::StackUnbalance)));
+        piet.restore().unwrap();
+        piet.finish().unwrap();
+    }
+
+    #[test]
+    fn dynamic_matches_static() {
+        use piet::dynamic::{to_path, Dyn, DynRenderContext};
+        use piet::kurbo::Circle;
+        use piet::PaintBrush;
+
+        let gradient: PaintBrush = LinearGradient::new(
+            UnitPoint::LEFT,
+            UnitPoint::RIGHT,
+            (Color::rgb8(255, 0, 0), Color::rgb8(0, 0, 255)),
+        )
+        .into();
+        let circle = Circle::new((30.0, 30.0), 20.0);
+        let pixels = [0x80u8; 8 * 8 * 4];
+
+        let mut static_surface = ImageSurface::create(Format::ARgb32, 60, 60).unwrap();
+        {
+            let cr = Context::new(&static_surface);
+            let mut piet = CairoRenderContext::new(&cr);
+            piet.clear(Color::WHITE);
+            // the dynamic context only sees paths, which don't take the fast
+            // path for circles
+            piet.fill(Rect::new(5.0, 5.0, 55.0, 25.0), &gradient);
+            piet.stroke(to_path(circle), &Color::BLACK, 3.0);
+            piet.save().unwrap();
+            piet.transform(Affine::rotate(0.3));
+            piet.clip(to_path(circle));
+            let image = piet
+                .make_image(8, 8, &pixels, ImageFormat::RgbaPremul)
+                .unwrap();
+            piet.draw_image(
+                &image,
+                Rect::new(20.0, 20.0, 50.0, 50.0),
+                InterpolationMode::Bilinear,
+            );
+            piet.restore().unwrap();
+            piet.finish().unwrap();
+        }
+
+        let mut dyn_surface = ImageSurface::create(Format::ARgb32, 60, 60).unwrap();
+        {
+            let cr = Context::new(&dyn_surface);
+            let mut piet = CairoRenderContext::new(&cr);
+            let mut adapter = Dyn::new(&mut piet);
+            let rc: &mut dyn DynRenderContext = &mut adapter;
+            rc.clear(Color::WHITE);
+            rc.fill_shape(Rect::new(5.0, 5.0, 55.0, 25.0), gradient);
+            rc.stroke_shape(circle, Color::BLACK, 3.0);
+            rc.save().unwrap();
+            rc.transform(Affine::rotate(0.3));
+            rc.clip(&to_path(circle));
+            let image = rc
+                .make_image(8, 8, &pixels, ImageFormat::RgbaPremul)
+                .unwrap();
+            rc.draw_image(
+                &image,
+                Rect::new(20.0, 20.0, 50.0, 50.0),
+                InterpolationMode::Bilinear,
+            );
+            rc.restore().unwrap();
+            rc.finish().unwrap();
+        }
+
+        static_surface.flush();
+        dyn_surface.flush();
+        assert!(*static_surface.get_data().unwrap() == *dyn_surface.get_data().unwrap());
+    }
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    /// Capture `rect`, returning its size and premultiplied RGBA pixels.
+    fn capture(
+        piet: &mut CairoRenderContext,
+        rect: Rect,
+    ) -> Result<(usize, usize, Vec<u8>), Error> {
+        let image = piet.capture_image_area(rect)?;
+        let buf = piet.to_image_buf(&image, ImageFormat::RgbaPremul)?;
+        Ok((buf.width(), buf.height(), buf.raw_pixels().to_vec()))
+    }
+
+    #[test]
+    fn capture_image_area() {
+        let surface = ImageSurface::create(Format::ARgb32, 4, 4).unwrap();
+        let cr = Context::new(&surface);
+        let mut piet = CairoRenderContext::new(&cr);
+        piet.fill(
+            Rect::new(0.0, 0.0, 2.0, 4.0),
+            &Color::from_rgba32_u32(0xff00_00ff),
+        );
+        piet.fill(
+            Rect::new(2.0, 0.0, 4.0, 4.0),
+            &Color::from_rgba32_u32(0x0000_ffff),
+        );
+        piet.transform(Affine::translate((1.0, 1.0)));
+
+        let pixels = capture(&mut piet, Rect::new(0.0, 0.0, 2.0, 1.0)).unwrap();
+        assert_eq!(pixels, (2, 1, [RED, BLUE].concat()));
+        // the area is clipped to the surface
+        let pixels = capture(&mut piet, Rect::new(-3.0, -3.0, 0.0, 0.0)).unwrap();
+        assert_eq!(pixels, (1, 1, RED.to_vec()));
+        assert!(matches!(
+            capture(&mut piet, Rect::new(3.0, 3.0, 5.0, 5.0)),
+            Err(Error::InvalidInput)
+        ));
+
+        // an open layer isn't part of the target yet
+        piet.save_layer(1.0).unwrap();
+        piet.clear(Color::WHITE);
+        let pixels = capture(&mut piet, Rect::new(0.0, 0.0, 1.0, 1.0)).unwrap();
+        assert_eq!(pixels, (1, 1, RED.to_vec()));
+        piet.restore().unwrap();
+        piet.finish().unwrap();
+    }
+
+    #[test]
+    fn capture_image_area_device_scale() {
+        let surface = ImageSurface::create(Format::ARgb32, 4, 4).unwrap();
+        surface.set_device_scale(2.0, 2.0);
+        let cr = Context::new(&surface);
+        let mut piet = CairoRenderContext::new(&cr);
+        piet.fill(
+            Rect::new(0.0, 0.0, 1.0, 2.0),
+            &Color::from_rgba32_u32(0xff00_00ff),
+        );
+        piet.fill(
+            Rect::new(1.0, 0.0, 2.0, 2.0),
+            &Color::from_rgba32_u32(0x0000_ffff),
+        );
+
+        // the image has the surface's pixels, not its device units
+        let pixels = capture(&mut piet, Rect::new(0.5, 0.0, 1.5, 1.0)).unwrap();
+        let row = [RED, BLUE].concat();
+        assert_eq!(pixels, (2, 2, [&row[..], &row[..]].concat()));
+        piet.finish().unwrap();
+    }
+
+    #[test]
+    fn capture_image_area_of_recording_surface() {
+        // a surface without pixels of its own
+        let extents = cairo::Rectangle {
+            x: 0.0,
+            y: 0.0,
+            width: 4.0,
+            height: 2.0,
+        };
+        let surface = cairo::RecordingSurface::create(cairo::Content::ColorAlpha, extents).unwrap();
+        let cr = Context::new(&surface);
+        let mut piet = CairoRenderContext::new(&cr);
+        piet.fill(
+            Rect::new(0.0, 0.0, 2.0, 2.0),
+            &Color::from_rgba32_u32(0xff00_00ff),
+        );
+        piet.fill(
+            Rect::new(2.0, 0.0, 4.0, 2.0),
+            &Color::from_rgba32_u32(0x0000_ffff),
+        );
+
+        let pixels = capture(&mut piet, Rect::new(1.0, 1.0, 3.0, 2.0)).unwrap();
+        assert_eq!(pixels, (2, 1, [RED, BLUE].concat()));
+        let pixels = capture(&mut piet, Rect::new(3.0, 0.0, 7.0, 1.0)).unwrap();
+        assert_eq!(pixels, (1, 1, BLUE.to_vec()));
+        piet.finish().unwrap();
+    }
+}
@@ -0,0 +1,1139 @@
+//! Text functionality for Piet cairo backend, with Pango.
+//!
+//! This replaces the toy text API when the `pango` feature is enabled.
+//! Layouts are `PangoLayout`s, which do their own shaping, bidi and font
+//! fallback, and are drawn with pangocairo.
+
+mod ffi;
+
+use std::cell::OnceCell;
+use std::ffi::{CStr, CString};
+use std::ops::{Range, RangeBounds};
+use std::os::raw::{c_int, c_uint, c_void};
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+use cairo::{Context, Format, ImageSurface, PathSegment};
+
+use piet::kurbo::{BezPath, Point, Rect, Size, Vec2};
+use piet::{
+    util, Affinity, Color, Error, FontFamily, FontFamilyInner, FontMetrics, Glyph, GlyphRun,
+    HitTestPoint, HitTestPosition, IntoLayoutStorage, LayoutStorage, LineMetric, LineSpacing,
+    ShadowStyle, TabStops, Text, TextAlignment, TextAttribute, TextDirection, TextLayout,
+    TextLayoutBuilder, TextOrientation, TruncationMode, UnderlineStyle,
+};
+
+use self::ffi::*;
+
+/// Pango keeps its font map for each thread, so there is no state here.
+#[derive(Clone)]
+pub struct CairoText;
+
+#[derive(Clone)]
+pub struct CairoTextLayout {
+    /// the color of text without a `ForegroundColor` attribute, which is
+    /// drawn with the brush set on the context.
+    pub(crate) fg_color: Color,
+    layout: Layout,
+    text: Arc<dyn LayoutStorage>,
+    line_spacing: LineSpacing,
+    max_lines: Option<usize>,
+    truncation: TruncationMode,
+    size: Size,
+    image_bounds: Rect,
+    line_metrics: Vec<LineMetric>,
+    /// where each line is placed, with the same index as its `PangoLayoutLine`.
+    lines: Vec<LinePlacement>,
+    /// ranges of the text with a background color.
+    backgrounds: Vec<(Range<usize>, Color)>,
+    /// ranges of the text with a shadow.
+    shadows: Vec<(Range<usize>, ShadowStyle)>,
+    /// the glyph runs, made the first time they are asked for.
+    glyph_runs: OnceCell<Vec<GlyphRun>>,
+}
+
+pub struct CairoTextLayoutBuilder {
+    text: Arc<dyn LayoutStorage>,
+    defaults: util::LayoutDefaults,
+    width_constraint: f64,
+    line_spacing: LineSpacing,
+    max_lines: Option<usize>,
+    truncation: TruncationMode,
+    alignment: TextAlignment,
+    direction: TextDirection,
+    orientation: TextOrientation,
+    tab_stops: Option<TabStops>,
+    /// the offsets at which lines may break, if they were set with
+    /// `break_opportunities`.
+    break_opportunities: Option<Vec<usize>>,
+    /// the range attributes, in the order they were added.
+    attributes: Vec<(Range<usize>, TextAttribute)>,
+}
+
+/// A `PangoLayout`, which we own a reference to.
+///
+/// Layouts are changed by `update_width`, so cloning one makes a copy.
+struct Layout(NonNull<PangoLayout>);
+
+/// The position of a line of a layout.
+#[derive(Clone, Copy, Debug)]
+struct LinePlacement {
+    /// the origin of the line's baseline, relative to the layout's origin.
+    origin: Point,
+    /// the left and right edges of the line.
+    x0: f64,
+    x1: f64,
+    /// the vertical center of the line in Pango's layout, at which it is hit
+    /// tested, in Pango units.
+    pango_y: c_int,
+    rtl: bool,
+}
+
+/// A line of a `PangoLayout`, as Pango laid it out.
+struct PangoLine {
+    start: usize,
+    len: usize,
+    /// the logical and ink extents of the line in the layout, and the x
+    /// position and baseline of its origin, in display points.
+    logical: Rect,
+    ink: Rect,
+    x: f64,
+    baseline: f64,
+    rtl: bool,
+}
+
+impl CairoText {
+    /// Create a new factory that satisfies the piet `Text` trait.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> CairoText {
+        CairoText
+    }
+}
+
+impl Text for CairoText {
+    type TextLayout = CairoTextLayout;
+    type TextLayoutBuilder = CairoTextLayoutBuilder;
+
+    fn font_family(&mut self, family_name: &str) -> Option<FontFamily> {
+        let generic = [
+            FontFamily::SERIF,
+            FontFamily::SANS_SERIF,
+            FontFamily::MONOSPACE,
+            FontFamily::SYSTEM_UI,
+        ];
+        if let Some(family) = generic.iter().find(|f| f.name() == family_name) {
+            return Some(family.clone());
+        }
+        self.font_families()
+            .into_iter()
+            .find(|family| family.name().eq_ignore_ascii_case(family_name))
+    }
+
+    fn font_families(&mut self) -> Vec<FontFamily> {
+        let mut families = std::ptr::null_mut();
+        let mut count = 0;
+        unsafe {
+            pango_font_map_list_families(
+                pango_cairo_font_map_get_default(),
+                &mut families,
+                &mut count,
+            );
+            if families.is_null() {
+                return Vec::new();
+            }
+            let list = std::slice::from_raw_parts(families, count.max(0) as usize)
+                .iter()
+                .map(|&family| {
+                    let name = CStr::from_ptr(pango_font_family_get_name(family));
+                    FontFamily::new_unchecked(name.to_string_lossy().as_ref())
+                })
+                .collect();
+            g_free(families as *mut c_void);
+            list
+        }
+    }
+
+    fn load_font(&mut self, _data: &[u8]) -> Result<FontFamily, Error> {
+        // fonts would have to be added to fontconfig, for every font map
+        Err(Error::NotSupported)
+    }
+
+    fn font_metrics(&mut self, family: &FontFamily, size: f64) -> Option<FontMetrics> {
+        let desc = FontDescription::new(family_name(family), size, 400, false)?;
+        let layout = Layout::new(TextDirection::LeftToRight)?;
+        unsafe {
+            let context = pango_layout_get_context(layout.as_ptr());
+            let metrics = pango_context_get_metrics(context, desc.0, std::ptr::null_mut());
+            if metrics.is_null() {
+                return None;
+            }
+            let units = |value: c_int| value as f64 / PANGO_SCALE;
+            let underline_thickness = units(pango_font_metrics_get_underline_thickness(metrics));
+            let strikeout_thickness =
+                units(pango_font_metrics_get_strikethrough_thickness(metrics));
+            // Pango's positions are of the top edges of the lines, upwards
+            let underline_position = -units(pango_font_metrics_get_underline_position(metrics))
+                + underline_thickness / 2.0;
+            let strikeout_position = -units(pango_font_metrics_get_strikethrough_position(metrics))
+                + strikeout_thickness / 2.0;
+            let result = FontMetrics {
+                ascent: units(pango_font_metrics_get_ascent(metrics)),
+                descent: units(pango_font_metrics_get_descent(metrics)),
+                cap_height: layout.glyph_height("H", &desc),
+                x_height: layout.glyph_height("x", &desc),
+                underline_position,
+                underline_thickness,
+                strikeout_position,
+                strikeout_thickness,
+            };
+            pango_font_metrics_unref(metrics);
+            Some(result)
+        }
+    }
+
+    fn new_text_layout(&mut self, text: impl IntoLayoutStorage) -> Self::TextLayoutBuilder {
+        CairoTextLayoutBuilder {
+            defaults: util::LayoutDefaults::default(),
+            text: Arc::new(text.into_layout_storage()),
+            width_constraint: f64::INFINITY,
+            line_spacing: LineSpacing::Default,
+            max_lines: None,
+            truncation: TruncationMode::Clip,
+            alignment: TextAlignment::default(),
+            direction: TextDirection::default(),
+            orientation: TextOrientation::Horizontal,
+            tab_stops: None,
+            break_opportunities: None,
+            attributes: Vec::new(),
+        }
+    }
+}
+
+impl TextLayoutBuilder for CairoTextLayoutBuilder {
+    type Out = CairoTextLayout;
+
+    fn max_width(mut self, width: f64) -> Self {
+        self.width_constraint = width;
+        self
+    }
+
+    fn alignment(mut self, alignment: piet::TextAlignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
+
+    fn text_direction(mut self, direction: TextDirection) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    fn orientation(mut self, orientation: TextOrientation) -> Self {
+        self.orientation = orientation;
+        self
+    }
+
+    fn line_spacing(mut self, spacing: LineSpacing) -> Self {
+        self.line_spacing = spacing;
+        self
+    }
+
+    fn max_lines(mut self, max_lines: usize) -> Self {
+        self.max_lines = Some(max_lines.max(1));
+        self
+    }
+
+    fn truncate(mut self, mode: TruncationMode) -> Self {
+        self.truncation = mode;
+        self
+    }
+
+    fn tab_stops(mut self, stops: TabStops) -> Self {
+        self.tab_stops = Some(stops);
+        self
+    }
+
+    fn break_opportunities(mut self, breaks: impl FnOnce(&str) -> Vec<usize>) -> Self {
+        let text = self.text.as_str();
+        self.break_opportunities = Some(util::resolve_break_opportunities(text, breaks(text)));
+        self
+    }
+
+    fn default_attribute(mut self, attribute: impl Into<TextAttribute>) -> Self {
+        self.defaults.set(attribute);
+        self
+    }
+
+    fn range_attribute(
+        mut self,
+        range: impl RangeBounds<usize>,
+        attribute: impl Into<TextAttribute>,
+    ) -> Self {
+        let range = util::resolve_range(range, self.text.as_str().len());
+        self.attributes.push((range, attribute.into()));
+        self
+    }
+
+    fn build(self) -> Result<Self::Out, Error> {
+        // Pango has no word spacing, and we don't lay out vertical text
+        let word_spacing = self.attributes.iter().any(|(range, attr)| {
+            matches!(attr, TextAttribute::WordSpacing(spacing) if *spacing != 0.0 && !range.is_empty())
+        });
+        if word_spacing
+            || self.defaults.word_spacing != 0.0
+            || self.orientation != TextOrientation::Horizontal
+        {
+            return Err(Error::NotSupported);
+        }
+
+        let layout = Layout::new(self.direction).ok_or_else(pango_error)?;
+        let text = self.text.as_str();
+        let len = text.len();
+        let defaults = &self.defaults;
+        unsafe {
+            let ptr = layout.as_ptr();
+            pango_layout_set_text(ptr, text.as_ptr() as *const _, len as c_int);
+            let families = std::iter::once(&defaults.font).chain(&defaults.font_fallbacks);
+            let desc = FontDescription::new(
+                &family_list(families),
+                defaults.font_size,
+                defaults.weight.to_raw(),
+                defaults.italic,
+            )
+            .ok_or_else(pango_error)?;
+            pango_layout_set_font_description(ptr, desc.0);
+
+            let list = AttrList::new();
+            let whole = 0..len;
+            if defaults.strikethrough {
+                list.change(whole.clone(), pango_attr_strikethrough_new(1));
+            }
+            if defaults.letter_spacing != 0.0 {
+                list.change(whole.clone(), letter_spacing(defaults.letter_spacing));
+            }
+            if defaults.baseline_shift != 0.0 {
+                list.change(whole.clone(), rise(defaults.baseline_shift));
+            }
+            if !defaults.font_features.is_empty() {
+                list.change(whole.clone(), font_features(&defaults.font_features));
+            }
+            if let Some(locale) = &defaults.locale {
+                list.change(whole.clone(), language(locale));
+            }
+            let mut underlines = Vec::new();
+            let mut underline_colors = Vec::new();
+            let mut underline_styles = Vec::new();
+            let mut backgrounds = Vec::new();
+            let mut shadows = Vec::new();
+            for (range, attribute) in &self.attributes {
+                let range = range.clone();
+                match attribute {
+                    TextAttribute::Underline(flag) => underlines.push((range, *flag)),
+                    TextAttribute::UnderlineColor(color) => {
+                        underline_colors.push((range, color.clone()))
+                    }
+                    TextAttribute::UnderlineStyle(style) => underline_styles.push((range, *style)),
+                    TextAttribute::BackgroundColor(color) => {
+                        backgrounds.push((range, color.clone()))
+                    }
+                    TextAttribute::Shadow(shadow) => shadows.push((range, shadow.clone())),
+                    TextAttribute::WordSpacing(_) => (),
+                    TextAttribute::ForegroundColor(color) => {
+                        let (fg, alpha) = foreground(color);
+                        list.change(range.clone(), fg);
+                        list.change(range, alpha);
+                    }
+                    attribute => {
+                        if let Some(attr) = pango_attribute(attribute) {
+                            list.change(range, attr);
+                        }
+                    }
+                }
+            }
+            // Pango draws underlines itself, but has no dotted or dashed lines
+            let underlines = util::resolve_underlines(
+                defaults,
+                len,
+                underlines,
+                underline_colors,
+                underline_styles,
+            );
+            for (range, style, color) in underlines {
+                let underline = match style {
+                    UnderlineStyle::Wavy => PANGO_UNDERLINE_ERROR,
+                    _ => PANGO_UNDERLINE_SINGLE,
+                };
+                list.change(range.clone(), pango_attr_underline_new(underline));
+                if let Some(color) = color {
+                    let (r, g, b) = color_u16(&color);
+                    list.change(range, pango_attr_underline_color_new(r, g, b));
+                }
+            }
+            if let Some(breaks) = &self.break_opportunities {
+                // lines can still break at the opportunities, but not between them
+                let bounds = std::iter::once(0).chain(breaks.iter().copied());
+                for (start, end) in bounds.zip(breaks.iter().copied().chain(Some(len))) {
+                    list.change(start..end, pango_attr_allow_breaks_new(0));
+                }
+            }
+            pango_layout_set_attributes(ptr, list.0);
+
+            pango_layout_set_wrap(ptr, PANGO_WRAP_WORD_CHAR);
+            let rtl = match self.direction {
+                TextDirection::Auto => util::first_strong_rtl(text),
+                direction => direction.is_rtl(text),
+            };
+            let (start, end) = if rtl {
+                (PANGO_ALIGN_RIGHT, PANGO_ALIGN_LEFT)
+            } else {
+                (PANGO_ALIGN_LEFT, PANGO_ALIGN_RIGHT)
+            };
+            let alignment = match self.alignment {
+                TextAlignment::Start | TextAlignment::Justified => start,
+                TextAlignment::End => end,
+                TextAlignment::Center => PANGO_ALIGN_CENTER,
+            };
+            pango_layout_set_alignment(ptr, alignment);
+            pango_layout_set_justify(ptr, (self.alignment == TextAlignment::Justified) as _);
+            if let Some(stops) = &self.tab_stops {
+                let tabs = tab_array(stops);
+                pango_layout_set_tabs(ptr, tabs);
+                pango_tab_array_free(tabs);
+            }
+
+            let mut layout = CairoTextLayout {
+                fg_color: defaults.fg_color.clone(),
+                layout,
+                text: self.text.clone(),
+                line_spacing: self.line_spacing,
+                max_lines: self.max_lines,
+                truncation: self.truncation,
+                size: Size::ZERO,
+                image_bounds: Rect::ZERO,
+                line_metrics: Vec::new(),
+                lines: Vec::new(),
+                backgrounds: util::resolve_spans(
+                    defaults.background_color.clone(),
+                    len,
+                    backgrounds,
+                ),
+                shadows: util::resolve_spans(defaults.shadow.clone(), len, shadows),
+                glyph_runs: OnceCell::new(),
+            };
+            layout.update_width(self.width_constraint)?;
+            Ok(layout)
+        }
+    }
+}
+
+impl TextLayout for CairoTextLayout {
+    fn width(&self) -> f64 {
+        self.size.width
+    }
+
+    fn size(&self) -> Size {
+        self.size
+    }
+
+    fn image_bounds(&self) -> Rect {
+        self.image_bounds
+    }
+
+    fn text(&self) -> &str {
+        self.text.as_str()
+    }
+
+    fn update_width(&mut self, new_width: impl Into<Option<f64>>) -> Result<(), Error> {
+        let new_width = new_width.into().unwrap_or(f64::INFINITY);
+        self.glyph_runs = OnceCell::new();
+        let ptr = self.layout.as_ptr();
+        let mut lines = unsafe {
+            pango_layout_set_width(ptr, pango_units(new_width).unwrap_or(-1));
+            pango_layout_set_ellipsize(ptr, PANGO_ELLIPSIZE_NONE);
+            pango_layout_set_height(ptr, -1);
+            self.layout.lines()
+        };
+        let max_lines = self.max_lines.filter(|max_lines| lines.len() > *max_lines);
+        if let (Some(max_lines), TruncationMode::EllipsisEnd) = (max_lines, self.truncation) {
+            // Pango ellipsizes the last line that fits in the height, which
+            // needs the layout to have a width
+            unsafe {
+                if !new_width.is_finite() {
+                    let widest = lines.iter().map(|line| line.logical.width());
+                    let widest = widest.fold(0.0, f64::max).ceil();
+                    pango_layout_set_width(ptr, pango_units(widest).unwrap_or(-1));
+                }
+                pango_layout_set_ellipsize(ptr, PANGO_ELLIPSIZE_END);
+                let bottom = lines[max_lines - 1].logical.y1;
+                pango_layout_set_height(ptr, pango_units(bottom).unwrap_or(-1));
+                lines = self.layout.lines();
+            }
+        }
+
+        let text = self.text.as_str();
+        let ends: Vec<usize> = lines
+            .iter()
+            .skip(1)
+            .map(|line| line.start)
+            .chain(lines.last().map(|line| line.start + line.len))
+            .collect();
+        if let Some(max_lines) = max_lines {
+            lines.truncate(max_lines);
+        }
+
+        self.line_metrics.clear();
+        self.lines.clear();
+        let mut y_offset = 0.0;
+        let mut width: f64 = 0.0;
+        let mut bounds = Rect::ZERO;
+        for (index, (line, end)) in lines.iter().zip(ends).enumerate() {
+            let line_text = &text[line.start..end];
+            let content_end = line.start + line_text.trim_end().len();
+            let whitespace_end = line.start + line_text.trim_end_matches(&['\n', '\r'][..]).len();
+            let trailing_whitespace_width = self
+                .line_spans(index, content_end..whitespace_end)
+                .iter()
+                .map(|(x0, x1)| x1 - x0)
+                .sum();
+            let (height, baseline) = util::resolve_line_spacing(
+                self.line_spacing,
+                line.logical.height(),
+                line.baseline - line.logical.y0,
+            );
+            let dy = y_offset + baseline - line.baseline;
+            self.line_metrics.push(LineMetric {
+                start_offset: line.start,
+                end_offset: end,
+                trailing_whitespace: end - content_end,
+                trailing_whitespace_width,
+                baseline,
+                height,
+                y_offset,
+            });
+            self.lines.push(LinePlacement {
+                origin: Point::new(line.x, y_offset + baseline),
+                x0: line.logical.x0,
+                x1: line.logical.x1,
+                pango_y: ((line.logical.y0 + line.logical.y1) / 2.0 * PANGO_SCALE) as c_int,
+                rtl: line.rtl,
+            });
+            width = width.max(line.logical.width() - trailing_whitespace_width);
+            if line.ink.area() > 0.0 {
+                bounds = bounds.union(line.ink + Vec2::new(0.0, dy));
+            }
+            y_offset += height;
+        }
+        self.size = Size::new(width, y_offset);
+        let bounds = bounds.union(self.size.to_rect());
+        let shadows = self.shadows.iter().map(|(_, shadow)| shadow);
+        self.image_bounds = util::bounds_with_shadows(bounds, shadows);
+        Ok(())
+    }
+
+    fn line_text(&self, line_number: usize) -> Option<&str> {
+        self.line_metrics
+            .get(line_number)
+            .map(|lm| &self.text.as_str()[lm.range()])
+    }
+
+    fn line_text_range(&self, line_number: usize) -> Option<Range<usize>> {
+        self.line_metrics.get(line_number).map(LineMetric::range)
+    }
+
+    fn line_metric(&self, line_number: usize) -> Option<LineMetric> {
+        self.line_metrics.get(line_number).cloned()
+    }
+
+    fn line_count(&self) -> usize {
+        self.line_metrics.len()
+    }
+
+    fn hit_test_point(&self, point: Point) -> HitTestPoint {
+        let last = match self.line_metrics.last() {
+            Some(last) if !self.text.as_str().is_empty() => last,
+            _ => return HitTestPoint::default(),
+        };
+        // points above and below the layout hit test the first and last lines
+        let (y_inside, line_number) = if point.y < 0.0 {
+            (false, 0)
+        } else if point.y >= last.y_offset + last.height {
+            (false, self.line_metrics.len() - 1)
+        } else {
+            let line_number = self
+                .line_metrics
+                .iter()
+                .rposition(|lm| point.y >= lm.y_offset)
+                .unwrap_or_default();
+            (true, line_number)
+        };
+
+        // Pango keeps a click past the end of a wrapped line before the
+        // whitespace it wraps at, rather than at the end of the line
+        let lm = &self.line_metrics[line_number];
+        let placement = &self.lines[line_number];
+        let text = self.text.as_str();
+        let wrapped =
+            line_number + 1 < self.lines.len() && !text[lm.range()].ends_with(&['\n', '\r'][..]);
+        let past_end = if placement.rtl {
+            point.x < placement.x0
+        } else {
+            point.x >= placement.x1
+        };
+        if wrapped && past_end {
+            return HitTestPoint::new(lm.end_offset, false, Affinity::Upstream);
+        }
+
+        let x = (point.x * PANGO_SCALE) as c_int;
+        let (mut index, mut trailing) = (0, 0);
+        let x_inside = unsafe {
+            let y = placement.pango_y;
+            pango_layout_xy_to_index(self.layout.as_ptr(), x, y, &mut index, &mut trailing) != 0
+        };
+        // `trailing` is the number of chars of the grapheme at `index`, if
+        // the point is on its trailing half
+        let index = (index.max(0) as usize).min(text.len());
+        let grapheme: usize = text[index..]
+            .chars()
+            .take(trailing.max(0) as usize)
+            .map(char::len_utf8)
+            .sum();
+        let affinity = if grapheme > 0 {
+            Affinity::Upstream
+        } else {
+            Affinity::Downstream
+        };
+        HitTestPoint::new(index + grapheme, x_inside && y_inside, affinity)
+    }
+
+    fn hit_test_text_position(&self, text_position: usize) -> Option<HitTestPosition> {
+        let text = self.text.as_str();
+        let idx = util::snap_to_graphemes(text, text_position..text_position).start;
+        let line_number = util::line_number_for_position(&self.line_metrics, idx);
+        let lm = self.line_metrics.get(line_number)?;
+        let x = self.caret_x(line_number, idx);
+        let point = Point::new(x, lm.y_offset + lm.baseline);
+        Some(HitTestPosition::new(point, line_number))
+    }
+
+    fn hit_test_text_position_with_affinity(
+        &self,
+        text_position: usize,
+        affinity: Affinity,
+    ) -> Option<HitTestPosition> {
+        let text = self.text.as_str();
+        let idx = util::snap_to_graphemes(text, text_position..text_position).start;
+        // upstream is the trailing edge of the previous grapheme, which is
+        // where pango puts it at soft breaks and direction changes.
+        let prev = match affinity {
+            Affinity::Upstream => util::prev_grapheme_offset(text, idx)
+                .filter(|&prev| !text[prev..idx].ends_with(&['\n', '\r'][..])),
+            Affinity::Downstream => None,
+        };
+        let prev = match prev {
+            Some(prev) => prev,
+            None => return self.hit_test_text_position(idx),
+        };
+        let line_number = util::line_number_for_position(&self.line_metrics, prev);
+        let lm = self.line_metrics.get(line_number)?;
+        let x = self.edge_x(line_number, prev, true);
+        let point = Point::new(x, lm.y_offset + lm.baseline);
+        Some(HitTestPosition::new(point, line_number))
+    }
+
+    fn rects_for_range(&self, range: impl RangeBounds<usize>) -> Vec<Rect> {
+        self.range_rects(range, true)
+    }
+
+    fn glyph_runs(&self) -> &[GlyphRun] {
+        self.glyph_runs.get_or_init(|| self.make_glyph_runs())
+    }
+
+    fn outline(&self) -> BezPath {
+        // the glyph outlines are only available through a context, which
+        // needs a surface, although we never draw to it.
+        let surface = match ImageSurface::create(Format::ARgb32, 1, 1) {
+            Ok(surface) => surface,
+            Err(_) => return BezPath::new(),
+        };
+        let ctx = Context::new(&surface);
+        for (index, placement) in self.lines.iter().enumerate() {
+            ctx.move_to(placement.origin.x, placement.origin.y);
+            unsafe { pango_cairo_layout_line_path(ctx.to_raw_none(), self.layout.line(index)) };
+        }
+
+        let mut path = BezPath::new();
+        for segment in ctx.copy_path().iter() {
+            match segment {
+                PathSegment::MoveTo(p) => path.move_to(p),
+                PathSegment::LineTo(p) => path.line_to(p),
+                PathSegment::CurveTo(p1, p2, p3) => path.curve_to(p1, p2, p3),
+                PathSegment::ClosePath => path.close_path(),
+            }
+        }
+        path
+    }
+}
+
+impl CairoTextLayout {
+    /// The glyphs of the layout, from Pango's runs; adjacent runs in the
+    /// same font are merged.
+    fn make_glyph_runs(&self) -> Vec<GlyphRun> {
+        let mut runs: Vec<GlyphRun> = Vec::new();
+        for (index, placement) in self.lines.iter().enumerate() {
+            let mut x = placement.origin.x;
+            let mut run = unsafe { (*self.layout.line(index)).runs };
+            while !run.is_null() {
+                let (glyph_item, next) =
+                    unsafe { (&*((*run).data as *const PangoGlyphItem), (*run).next) };
+                run = next;
+                let (item, glyphs) = unsafe { (&*glyph_item.item, &*glyph_item.glyphs) };
+                let y = placement.origin.y - glyph_item.y_offset as f64 / PANGO_SCALE;
+                let count = glyphs.num_glyphs.max(0) as usize;
+                let (infos, clusters) = unsafe {
+                    (
+                        std::slice::from_raw_parts(glyphs.glyphs, count),
+                        std::slice::from_raw_parts(glyphs.log_clusters, count),
+                    )
+                };
+                let mut run_glyphs = Vec::with_capacity(count);
+                for (info, cluster) in infos.iter().zip(clusters) {
+                    let drawn = info.glyph != PANGO_GLYPH_EMPTY
+                        && info.glyph & PANGO_GLYPH_UNKNOWN_FLAG == 0;
+                    if drawn {
+                        run_glyphs.push(Glyph {
+                            id: info.glyph,
+                            position: Point::new(
+                                x + info.x_offset as f64 / PANGO_SCALE,
+                                y + info.y_offset as f64 / PANGO_SCALE,
+                            ),
+                            cluster: (item.offset + cluster) as usize,
+                        });
+                    }
+                    x += info.width as f64 / PANGO_SCALE;
+                }
+                if run_glyphs.is_empty() {
+                    continue;
+                }
+                let (font, font_size) = unsafe { font_of(item.analysis.font) };
+                match runs.last_mut() {
+                    Some(last) if last.font == font && last.font_size == font_size => {
+                        last.glyphs.extend(run_glyphs)
+                    }
+                    _ => runs.push(GlyphRun {
+                        font,
+                        font_size,
+                        glyphs: run_glyphs,
+                    }),
+                }
+            }
+        }
+        runs
+    }
+
+    /// Draw the lines of the layout, with the baseline of the first at
+    /// `pos.y`, in the source set on `ctx`, except for ranges with a
+    /// foreground color.
+    pub(crate) fn draw(&self, ctx: &Context, pos: Point) {
+        for (index, placement) in self.lines.iter().enumerate() {
+            ctx.move_to(pos.x + placement.origin.x, pos.y + placement.origin.y);
+            unsafe { pango_cairo_show_layout_line(ctx.to_raw_none(), self.layout.line(index)) };
+        }
+    }
+
+    /// Returns the rects (relative to the layout's origin) and colors of the
+    /// backgrounds of this layout.
+    pub(crate) fn background_rects(&self) -> Vec<(Rect, Color)> {
+        let mut result = Vec::new();
+        for (range, color) in &self.backgrounds {
+            for rect in self.range_rects(range.clone(), false) {
+                if rect.width() > 0.0 {
+                    result.push((rect, color.clone()));
+                }
+            }
+        }
+        result
+    }
+
+    /// Returns the shadows of this layout, with the rects (relative to the
+    /// layout's origin) of the text that casts each.
+    pub(crate) fn shadow_rects(&self) -> Vec<(Vec<Rect>, &ShadowStyle)> {
+        self.shadows
+            .iter()
+            .map(|(range, shadow)| (self.range_rects(range.clone(), false), shadow))
+            .filter(|(rects, _)| !rects.is_empty())
+            .collect()
+    }
+
+    /// The rects covering a range of the text, from the top to the bottom of
+    /// each line, including the rects of any newlines if `newlines` is `true`.
+    fn range_rects(&self, range: impl RangeBounds<usize>, newlines: bool) -> Vec<Rect> {
+        let mut rects = Vec::new();
+        for (line_number, range, newline) in
+            util::line_ranges(self.text.as_str(), &self.line_metrics, range)
+        {
+            let lm = &self.line_metrics[line_number];
+            for (x0, x1) in self.line_spans(line_number, range.clone()) {
+                rects.push(Rect::new(x0, lm.y_offset, x1, lm.y_offset + lm.height));
+            }
+            if newline && newlines {
+                rects.push(util::newline_rect(lm, self.caret_x(line_number, range.end)));
+            }
+        }
+        rects
+    }
+
+    /// The extents, from left to right, of the parts of a line covering
+    /// `range`, which is in the layout's text.
+    fn line_spans(&self, line_number: usize, range: Range<usize>) -> Vec<(f64, f64)> {
+        if range.is_empty() {
+            return Vec::new();
+        }
+        let mut ranges = std::ptr::null_mut();
+        let mut count = 0;
+        unsafe {
+            pango_layout_line_get_x_ranges(
+                self.layout.line(line_number),
+                range.start as c_int,
+                range.end as c_int,
+                &mut ranges,
+                &mut count,
+            );
+            if ranges.is_null() {
+                return Vec::new();
+            }
+            let mut spans: Vec<(f64, f64)> = Vec::new();
+            let pairs = std::slice::from_raw_parts(ranges, 2 * count.max(0) as usize);
+            for pair in pairs.chunks(2) {
+                let (x0, x1) = (pair[0] as f64 / PANGO_SCALE, pair[1] as f64 / PANGO_SCALE);
+                match spans.last_mut() {
+                    Some(span) if span.1 == x0 => span.1 = x1,
+                    _ => spans.push((x0, x1)),
+                }
+            }
+            g_free(ranges as *mut c_void);
+            spans.sort_by(|a, b| a.0.total_cmp(&b.0));
+            spans
+        }
+    }
+
+    /// The x position of a caret at `idx` on a line, at the leading edge of
+    /// the grapheme there.
+    ///
+    /// A caret at the end of the line is at the trailing edge of its last
+    /// grapheme, which Pango would put at the end of the paragraph instead.
+    fn caret_x(&self, line_number: usize, idx: usize) -> f64 {
+        let line = self.layout.line(line_number);
+        let (start, end) = unsafe { ((*line).start_index, (*line).start_index + (*line).length) };
+        match util::prev_grapheme_offset(self.text.as_str(), idx) {
+            Some(prev) if idx >= end as usize && prev >= start as usize => {
+                self.edge_x(line_number, prev, true)
+            }
+            _ => self.edge_x(line_number, idx, false),
+        }
+    }
+
+    /// The x position of the leading or trailing edge of the grapheme at
+    /// `idx` in the given line.
+    fn edge_x(&self, line_number: usize, idx: usize, trailing: bool) -> f64 {
+        let line = self.layout.line(line_number);
+        let mut x = 0;
+        unsafe { pango_layout_line_index_to_x(line, idx as c_int, trailing as c_int, &mut x) };
+        self.lines[line_number].origin.x + x as f64 / PANGO_SCALE
+    }
+}
+
+impl Layout {
+    /// A new, empty layout, with paragraphs in the given direction.
+    fn new(direction: TextDirection) -> Option<Layout> {
+        unsafe {
+            // each layout has its own context, for its base direction
+            let context = pango_font_map_create_context(pango_cairo_font_map_get_default());
+            if context.is_null() {
+                return None;
+            }
+            match direction {
+                TextDirection::LeftToRight => {
+                    pango_context_set_base_dir(context, PANGO_DIRECTION_LTR)
+                }
+                TextDirection::RightToLeft => {
+                    pango_context_set_base_dir(context, PANGO_DIRECTION_RTL)
+                }
+                TextDirection::Auto => (),
+            }
+            let layout = NonNull::new(pango_layout_new(context)).map(Layout);
+            g_object_unref(context as *mut c_void);
+            if let Some(layout) = &layout {
+                let auto_dir = direction == TextDirection::Auto;
+                pango_layout_set_auto_dir(layout.as_ptr(), auto_dir as _);
+            }
+            layout
+        }
+    }
+
+    fn as_ptr(&self) -> *mut PangoLayout {
+        self.0.as_ptr()
+    }
+
+    /// The line at `index`, which must exist.
+    ///
+    /// The line belongs to the layout, and is valid until it is changed.
+    fn line(&self, index: usize) -> *mut PangoLayoutLine {
+        let line = unsafe { pango_layout_get_line_readonly(self.as_ptr(), index as c_int) };
+        assert!(!line.is_null(), "no line {} in layout", index);
+        line
+    }
+
+    /// The lines of the layout, as it is currently laid out.
+    unsafe fn lines(&self) -> Vec<PangoLine> {
+        let iter = pango_layout_get_iter(self.as_ptr());
+        let mut lines = Vec::new();
+        loop {
+            let line = pango_layout_iter_get_line_readonly(iter);
+            let (mut ink, mut logical) = (PangoRectangle::default(), PangoRectangle::default());
+            pango_layout_iter_get_line_extents(iter, &mut ink, &mut logical);
+            // the line's own extents are relative to its origin
+            let mut line_logical = PangoRectangle::default();
+            pango_layout_line_get_extents(line, std::ptr::null_mut(), &mut line_logical);
+            lines.push(PangoLine {
+                start: (*line).start_index.max(0) as usize,
+                len: (*line).length.max(0) as usize,
+                logical: rect(logical),
+                ink: rect(ink),
+                x: (logical.x - line_logical.x) as f64 / PANGO_SCALE,
+                baseline: pango_layout_iter_get_baseline(iter) as f64 / PANGO_SCALE,
+                rtl: ((*line).flags >> 1) & 7 == PANGO_DIRECTION_RTL as c_uint,
+            });
+            if pango_layout_iter_next_line(iter) == 0 {
+                break;
+            }
+        }
+        pango_layout_iter_free(iter);
+        lines
+    }
+
+    /// The distance from the baseline to the top of the ink of `text`, in
+    /// the given font.
+    fn glyph_height(&self, text: &str, desc: &FontDescription) -> f64 {
+        unsafe {
+            pango_layout_set_font_description(self.as_ptr(), desc.0);
+            pango_layout_set_text(
+                self.as_ptr(),
+                text.as_ptr() as *const _,
+                text.len() as c_int,
+            );
+            match self.lines().first() {
+                Some(line) if line.ink.area() > 0.0 => line.baseline - line.ink.y0,
+                _ => 0.0,
+            }
+        }
+    }
+}
+
+impl Clone for Layout {
+    fn clone(&self) -> Self {
+        let copy = unsafe { pango_layout_copy(self.as_ptr()) };
+        Layout(NonNull::new(copy).expect("out of memory copying a layout"))
+    }
+}
+
+impl Drop for Layout {
+    fn drop(&mut self) {
+        unsafe { g_object_unref(self.as_ptr() as *mut c_void) }
+    }
+}
+
+/// A font description, for the duration of a call that copies it.
+struct FontDescription(*mut PangoFontDescription);
+
+impl FontDescription {
+    fn new(family: &str, size: f64, weight: u16, italic: bool) -> Option<FontDescription> {
+        let family = CString::new(family).ok()?;
+        unsafe {
+            let desc = pango_font_description_new();
+            if desc.is_null() {
+                return None;
+            }
+            pango_font_description_set_family(desc, family.as_ptr());
+            pango_font_description_set_absolute_size(desc, size * PANGO_SCALE);
+            pango_font_description_set_weight(desc, weight as c_int);
+            let style = if italic {
+                PANGO_STYLE_ITALIC
+            } else {
+                PANGO_STYLE_NORMAL
+            };
+            pango_font_description_set_style(desc, style);
+            Some(FontDescription(desc))
+        }
+    }
+}
+
+impl Drop for FontDescription {
+    fn drop(&mut self) {
+        unsafe { pango_font_description_free(self.0) }
+    }
+}
+
+/// An attribute list, which we own a reference to.
+struct AttrList(*mut PangoAttrList);
+
+impl AttrList {
+    fn new() -> AttrList {
+        AttrList(unsafe { pango_attr_list_new() })
+    }
+
+    /// Set `attr` over `range`, replacing any attributes of the same kind there.
+    ///
+    /// The list takes ownership of the attribute.
+    unsafe fn change(&self, range: Range<usize>, attr: *mut PangoAttribute) {
+        if attr.is_null() {
+            return;
+        }
+        (*attr).start_index = range.start as _;
+        (*attr).end_index = range.end as _;
+        pango_attr_list_change(self.0, attr);
+    }
+}
+
+impl Drop for AttrList {
+    fn drop(&mut self) {
+        unsafe { pango_attr_list_unref(self.0) }
+    }
+}
+
+/// The Pango attribute for a piet attribute that Pango applies itself.
+unsafe fn pango_attribute(attribute: &TextAttribute) -> Option<*mut PangoAttribute> {
+    let attr = match attribute {
+        TextAttribute::Font(family) => family_attr(family_name(family)),
+        TextAttribute::FontFamilies(families) => family_attr(&family_list(families.iter())),
+        TextAttribute::Size(size) => pango_attr_size_new_absolute((size * PANGO_SCALE) as c_int),
+        TextAttribute::Weight(weight) => pango_attr_weight_new(weight.to_raw() as c_int),
+        TextAttribute::Italic(italic) => pango_attr_style_new(if *italic {
+            PANGO_STYLE_ITALIC
+        } else {
+            PANGO_STYLE_NORMAL
+        }),
+        TextAttribute::Strikethrough(flag) => pango_attr_strikethrough_new(*flag as _),
+        TextAttribute::LetterSpacing(spacing) => letter_spacing(*spacing),
+        TextAttribute::BaselineShift(shift) => rise(*shift),
+        TextAttribute::FontFeatures(features) => font_features(features),
+        TextAttribute::Locale(locale) => language(locale),
+        _ => return None,
+    };
+    Some(attr)
+}
+
+unsafe fn family_attr(family: &str) -> *mut PangoAttribute {
+    match CString::new(family) {
+        Ok(family) => pango_attr_family_new(family.as_ptr()),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+unsafe fn letter_spacing(spacing: f64) -> *mut PangoAttribute {
+    pango_attr_letter_spacing_new((spacing * PANGO_SCALE) as c_int)
+}
+
+unsafe fn rise(shift: f64) -> *mut PangoAttribute {
+    pango_attr_rise_new((shift * PANGO_SCALE) as c_int)
+}
+
+unsafe fn font_features(features: &piet::FontFeatures) -> *mut PangoAttribute {
+    let settings: Vec<String> = features
+        .iter()
+        .map(|(tag, value)| format!("{}={}", String::from_utf8_lossy(&tag), value))
+        .collect();
+    match CString::new(settings.join(",")) {
+        Ok(settings) => pango_attr_font_features_new(settings.as_ptr()),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+unsafe fn language(locale: &str) -> *mut PangoAttribute {
+    match CString::new(locale) {
+        Ok(locale) => pango_attr_language_new(pango_language_from_string(locale.as_ptr())),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// The foreground color and alpha attributes for `color`.
+unsafe fn foreground(color: &Color) -> (*mut PangoAttribute, *mut PangoAttribute) {
+    let (r, g, b) = color_u16(color);
+    let alpha = (color.as_rgba_u32() & 0xff) as u16 * 257;
+    (
+        pango_attr_foreground_new(r, g, b),
+        pango_attr_foreground_alpha_new(alpha),
+    )
+}
+
+fn color_u16(color: &Color) -> (u16, u16, u16) {
+    let rgba = color.as_rgba_u32();
+    let channel = |shift: u32| ((rgba >> shift) & 0xff) as u16 * 257;
+    (channel(24), channel(16), channel(8))
+}
+
+/// The name fontconfig knows a family by.
+fn family_name(family: &FontFamily) -> &str {
+    match family.inner() {
+        // fontconfig has no system-ui alias
+        FontFamilyInner::SystemUi => FontFamily::SANS_SERIF.name(),
+        _ => family.name(),
+    }
+}
+
+/// A list of families, as Pango's comma-separated family names.
+fn family_list<'a>(families: impl Iterator<Item = &'a FontFamily>) -> String {
+    families.map(family_name).collect::<Vec<_>>().join(",")
+}
+
+/// The family and size of a font that Pango chose for a run.
+unsafe fn font_of(font: *mut PangoFont) -> (FontFamily, f64) {
+    let desc = pango_font_describe_with_absolute_size(font);
+    if desc.is_null() {
+        return (FontFamily::SANS_SERIF, 0.0);
+    }
+    let family = pango_font_description_get_family(desc);
+    let family = if family.is_null() {
+        FontFamily::SANS_SERIF
+    } else {
+        FontFamily::new_unchecked(CStr::from_ptr(family).to_string_lossy().as_ref())
+    };
+    let size = pango_font_description_get_size(desc) as f64 / PANGO_SCALE;
+    pango_font_description_free(desc);
+    (family, size)
+}
+
+/// A Pango tab array for the stops; Pango repeats the interval between
+/// the last two stops, as piet does.
+unsafe fn tab_array(stops: &TabStops) -> *mut PangoTabArray {
+    let mut positions = match stops {
+        TabStops::Uniform(_) => Vec::new(),
+        TabStops::Explicit(positions) => positions.clone(),
+    };
+    let last = positions.last().copied().unwrap_or_default();
+    positions.push(last + stops.interval());
+    if positions.len() == 1 {
+        positions.push(last + 2.0 * stops.interval());
+    }
+    let tabs = pango_tab_array_new(positions.len() as c_int, 0);
+    for (index, position) in positions.iter().enumerate() {
+        let location = (position * PANGO_SCALE) as c_int;
+        pango_tab_array_set_tab(tabs, index as c_int, PANGO_TAB_LEFT, location);
+    }
+    tabs
+}
+
+/// The error for Pango failing to create something, which it only does
+/// when it runs out of memory.
+fn pango_error() -> Error {
+    Error::BackendError("Pango could not create a layout".into())
+}
+
+/// `value` in Pango units, if it is finite.
+fn pango_units(value: f64) -> Option<c_int> {
+    Some((value * PANGO_SCALE).ceil() as c_int).filter(|_| value.is_finite())
+}
+
+fn rect(rect: PangoRectangle) -> Rect {
+    let (x, y) = (rect.x as f64 / PANGO_SCALE, rect.y as f64 / PANGO_SCALE);
+    let (width, height) = (rect.width as f64, rect.height as f64);
+    Rect::new(x, y, x + width / PANGO_SCALE, y + height / PANGO_SCALE)
+}
@@ -0,0 +1,288 @@
+//! The parts of the Pango C API that the pango text pipeline uses.
+//!
+//! Structs that are only handled through pointers are opaque; the others
+//! are declared as far as the fields we read, which are public in Pango's
+//! headers.
+
+#![allow(non_camel_case_types)]
+
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+
+use cairo_sys::cairo_t;
+
+pub type gboolean = c_int;
+
+pub const PANGO_SCALE: f64 = 1024.0;
+
+pub const PANGO_STYLE_NORMAL: c_int = 0;
+pub const PANGO_STYLE_ITALIC: c_int = 2;
+
+pub const PANGO_UNDERLINE_SINGLE: c_int = 1;
+pub const PANGO_UNDERLINE_ERROR: c_int = 4;
+
+pub const PANGO_WRAP_WORD_CHAR: c_int = 2;
+
+pub const PANGO_ELLIPSIZE_NONE: c_int = 0;
+pub const PANGO_ELLIPSIZE_END: c_int = 3;
+
+pub const PANGO_ALIGN_LEFT: c_int = 0;
+pub const PANGO_ALIGN_CENTER: c_int = 1;
+pub const PANGO_ALIGN_RIGHT: c_int = 2;
+
+pub const PANGO_DIRECTION_LTR: c_int = 0;
+pub const PANGO_DIRECTION_RTL: c_int = 1;
+
+pub const PANGO_TAB_LEFT: c_int = 0;
+
+/// Glyphs with this bit set are hex boxes for characters no font covers.
+pub const PANGO_GLYPH_UNKNOWN_FLAG: u32 = 0x1000_0000;
+pub const PANGO_GLYPH_EMPTY: u32 = 0x0FFF_FFFF;
+
+macro_rules! opaque {
+    ($($name:ident),*) => {
+        $(
+            #[repr(C)]
+            pub struct $name {
+                _private: [u8; 0],
+            }
+        )*
+    };
+}
+
+opaque!(
+    PangoFontMap,
+    PangoContext,
+    PangoLayout,
+    PangoLayoutIter,
+    PangoFontDescription,
+    PangoFontFamily,
+    PangoFontMetrics,
+    PangoFont,
+    PangoAttrList,
+    PangoLanguage,
+    PangoTabArray
+);
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PangoRectangle {
+    pub x: c_int,
+    pub y: c_int,
+    pub width: c_int,
+    pub height: c_int,
+}
+
+#[repr(C)]
+pub struct PangoAttribute {
+    pub klass: *const c_void,
+    pub start_index: c_uint,
+    pub end_index: c_uint,
+}
+
+#[repr(C)]
+pub struct GSList {
+    pub data: *mut c_void,
+    pub next: *mut GSList,
+}
+
+#[repr(C)]
+pub struct PangoLayoutLine {
+    pub layout: *mut PangoLayout,
+    pub start_index: c_int,
+    pub length: c_int,
+    pub runs: *mut GSList,
+    /// `is_paragraph_start` and `resolved_dir`, as bitfields.
+    pub flags: c_uint,
+}
+
+#[repr(C)]
+pub struct PangoAnalysis {
+    pub shape_engine: *mut c_void,
+    pub lang_engine: *mut c_void,
+    pub font: *mut PangoFont,
+    pub level: u8,
+    pub gravity: u8,
+    pub flags: u8,
+    pub script: u8,
+    pub language: *mut PangoLanguage,
+    pub extra_attrs: *mut c_void,
+}
+
+#[repr(C)]
+pub struct PangoItem {
+    pub offset: c_int,
+    pub length: c_int,
+    pub num_chars: c_int,
+    pub analysis: PangoAnalysis,
+}
+
+#[repr(C)]
+pub struct PangoGlyphInfo {
+    pub glyph: u32,
+    pub width: i32,
+    pub x_offset: i32,
+    pub y_offset: i32,
+    pub attr: u32,
+}
+
+#[repr(C)]
+pub struct PangoGlyphString {
+    pub num_glyphs: c_int,
+    pub glyphs: *mut PangoGlyphInfo,
+    pub log_clusters: *mut c_int,
+}
+
+#[repr(C)]
+pub struct PangoGlyphItem {
+    pub item: *mut PangoItem,
+    pub glyphs: *mut PangoGlyphString,
+    /// The shift of the run's baseline, upwards.
+    pub y_offset: c_int,
+}
+
+#[link(name = "pango-1.0")]
+extern "C" {
+    pub fn pango_font_map_create_context(map: *mut PangoFontMap) -> *mut PangoContext;
+    pub fn pango_font_map_list_families(
+        map: *mut PangoFontMap,
+        families: *mut *mut *mut PangoFontFamily,
+        n_families: *mut c_int,
+    );
+    pub fn pango_font_family_get_name(family: *mut PangoFontFamily) -> *const c_char;
+
+    pub fn pango_context_set_base_dir(context: *mut PangoContext, direction: c_int);
+    pub fn pango_context_get_metrics(
+        context: *mut PangoContext,
+        desc: *const PangoFontDescription,
+        language: *mut PangoLanguage,
+    ) -> *mut PangoFontMetrics;
+    pub fn pango_font_metrics_get_ascent(metrics: *mut PangoFontMetrics) -> c_int;
+    pub fn pango_font_metrics_get_descent(metrics: *mut PangoFontMetrics) -> c_int;
+    pub fn pango_font_metrics_get_underline_position(metrics: *mut PangoFontMetrics) -> c_int;
+    pub fn pango_font_metrics_get_underline_thickness(metrics: *mut PangoFontMetrics) -> c_int;
+    pub fn pango_font_metrics_get_strikethrough_position(metrics: *mut PangoFontMetrics) -> c_int;
+    pub fn pango_font_metrics_get_strikethrough_thickness(metrics: *mut PangoFontMetrics) -> c_int;
+    pub fn pango_font_metrics_unref(metrics: *mut PangoFontMetrics);
+
+    pub fn pango_font_description_new() -> *mut PangoFontDescription;
+    pub fn pango_font_description_free(desc: *mut PangoFontDescription);
+    pub fn pango_font_description_set_family(
+        desc: *mut PangoFontDescription,
+        family: *const c_char,
+    );
+    pub fn pango_font_description_get_family(desc: *const PangoFontDescription) -> *const c_char;
+    pub fn pango_font_description_set_absolute_size(desc: *mut PangoFontDescription, size: f64);
+    pub fn pango_font_description_get_size(desc: *const PangoFontDescription) -> c_int;
+    pub fn pango_font_description_set_weight(desc: *mut PangoFontDescription, weight: c_int);
+    pub fn pango_font_description_set_style(desc: *mut PangoFontDescription, style: c_int);
+    pub fn pango_font_describe_with_absolute_size(
+        font: *mut PangoFont,
+    ) -> *mut PangoFontDescription;
+
+    pub fn pango_layout_new(context: *mut PangoContext) -> *mut PangoLayout;
+    pub fn pango_layout_get_context(layout: *mut PangoLayout) -> *mut PangoContext;
+    pub fn pango_layout_copy(layout: *mut PangoLayout) -> *mut PangoLayout;
+    pub fn pango_layout_set_text(layout: *mut PangoLayout, text: *const c_char, length: c_int);
+    pub fn pango_layout_set_font_description(
+        layout: *mut PangoLayout,
+        desc: *const PangoFontDescription,
+    );
+    pub fn pango_layout_set_attributes(layout: *mut PangoLayout, attrs: *mut PangoAttrList);
+    pub fn pango_layout_set_width(layout: *mut PangoLayout, width: c_int);
+    pub fn pango_layout_set_height(layout: *mut PangoLayout, height: c_int);
+    pub fn pango_layout_set_wrap(layout: *mut PangoLayout, wrap: c_int);
+    pub fn pango_layout_set_ellipsize(layout: *mut PangoLayout, ellipsize: c_int);
+    pub fn pango_layout_set_alignment(layout: *mut PangoLayout, alignment: c_int);
+    pub fn pango_layout_set_justify(layout: *mut PangoLayout, justify: gboolean);
+    pub fn pango_layout_set_auto_dir(layout: *mut PangoLayout, auto_dir: gboolean);
+    pub fn pango_layout_set_tabs(layout: *mut PangoLayout, tabs: *mut PangoTabArray);
+    pub fn pango_layout_get_line_readonly(
+        layout: *mut PangoLayout,
+        line: c_int,
+    ) -> *mut PangoLayoutLine;
+    pub fn pango_layout_get_iter(layout: *mut PangoLayout) -> *mut PangoLayoutIter;
+
+    pub fn pango_layout_iter_free(iter: *mut PangoLayoutIter);
+    pub fn pango_layout_iter_next_line(iter: *mut PangoLayoutIter) -> gboolean;
+    pub fn pango_layout_iter_get_line_readonly(iter: *mut PangoLayoutIter) -> *mut PangoLayoutLine;
+    pub fn pango_layout_iter_get_line_extents(
+        iter: *mut PangoLayoutIter,
+        ink: *mut PangoRectangle,
+        logical: *mut PangoRectangle,
+    );
+    pub fn pango_layout_iter_get_baseline(iter: *mut PangoLayoutIter) -> c_int;
+
+    pub fn pango_layout_line_get_extents(
+        line: *mut PangoLayoutLine,
+        ink: *mut PangoRectangle,
+        logical: *mut PangoRectangle,
+    );
+    pub fn pango_layout_line_index_to_x(
+        line: *mut PangoLayoutLine,
+        index: c_int,
+        trailing: gboolean,
+        x_pos: *mut c_int,
+    );
+    pub fn pango_layout_xy_to_index(
+        layout: *mut PangoLayout,
+        x: c_int,
+        y: c_int,
+        index: *mut c_int,
+        trailing: *mut c_int,
+    ) -> gboolean;
+    pub fn pango_layout_line_get_x_ranges(
+        line: *mut PangoLayoutLine,
+        start_index: c_int,
+        end_index: c_int,
+        ranges: *mut *mut c_int,
+        n_ranges: *mut c_int,
+    );
+
+    pub fn pango_attr_list_new() -> *mut PangoAttrList;
+    pub fn pango_attr_list_unref(list: *mut PangoAttrList);
+    pub fn pango_attr_list_change(list: *mut PangoAttrList, attr: *mut PangoAttribute);
+    pub fn pango_attr_family_new(family: *const c_char) -> *mut PangoAttribute;
+    pub fn pango_attr_size_new_absolute(size: c_int) -> *mut PangoAttribute;
+    pub fn pango_attr_weight_new(weight: c_int) -> *mut PangoAttribute;
+    pub fn pango_attr_style_new(style: c_int) -> *mut PangoAttribute;
+    pub fn pango_attr_foreground_new(red: u16, green: u16, blue: u16) -> *mut PangoAttribute;
+    pub fn pango_attr_foreground_alpha_new(alpha: u16) -> *mut PangoAttribute;
+    pub fn pango_attr_underline_new(underline: c_int) -> *mut PangoAttribute;
+    pub fn pango_attr_underline_color_new(red: u16, green: u16, blue: u16) -> *mut PangoAttribute;
+    pub fn pango_attr_strikethrough_new(strikethrough: gboolean) -> *mut PangoAttribute;
+    pub fn pango_attr_letter_spacing_new(letter_spacing: c_int) -> *mut PangoAttribute;
+    pub fn pango_attr_rise_new(rise: c_int) -> *mut PangoAttribute;
+    pub fn pango_attr_font_features_new(features: *const c_char) -> *mut PangoAttribute;
+    pub fn pango_attr_language_new(language: *mut PangoLanguage) -> *mut PangoAttribute;
+    pub fn pango_attr_allow_breaks_new(allow_breaks: gboolean) -> *mut PangoAttribute;
+    pub fn pango_language_from_string(language: *const c_char) -> *mut PangoLanguage;
+
+    pub fn pango_tab_array_new(
+        initial_size: c_int,
+        positions_in_pixels: gboolean,
+    ) -> *mut PangoTabArray;
+    pub fn pango_tab_array_set_tab(
+        tabs: *mut PangoTabArray,
+        tab_index: c_int,
+        alignment: c_int,
+        location: c_int,
+    );
+    pub fn pango_tab_array_free(tabs: *mut PangoTabArray);
+}
+
+#[link(name = "pangocairo-1.0")]
+extern "C" {
+    pub fn pango_cairo_font_map_get_default() -> *mut PangoFontMap;
+    pub fn pango_cairo_show_layout_line(cr: *mut cairo_t, line: *mut PangoLayoutLine);
+    pub fn pango_cairo_layout_line_path(cr: *mut cairo_t, line: *mut PangoLayoutLine);
+}
+
+#[link(name = "gobject-2.0")]
+extern "C" {
+    pub fn g_object_unref(object: *mut c_void);
+}
+
+#[link(name = "glib-2.0")]
+extern "C" {
+    pub fn g_free(mem: *mut c_void);
+}
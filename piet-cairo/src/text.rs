@@ -1,20 +1,33 @@
 //! Text functionality for Piet cairo backend
 
+mod bidi;
+mod fallback;
+mod fonts;
 mod grapheme;
 mod lines;
 
-use std::ops::RangeBounds;
+use std::cell::OnceCell;
+use std::cmp::Ordering;
+use std::ops::{Deref, Range, RangeBounds};
+use std::sync::Arc;
 
-use cairo::{FontFace, FontOptions, FontSlant, FontWeight, Matrix, ScaledFont};
+use cairo::{
+    Context, FontFace, FontOptions, FontSlant, FontWeight, Format, ImageSurface, Matrix,
+    PathSegment, ScaledFont,
+};
 
-use piet::kurbo::{Point, Rect, Size};
+use piet::kurbo::{BezPath, Point, Rect, Size};
 use piet::{
-    util, Color, Error, FontFamily, HitTestPoint, HitTestPosition, LineMetric, Text, TextAttribute,
-    TextLayout, TextLayoutBuilder,
+    util, Affinity, Color, Error, FontFamily, FontMetrics, Glyph, GlyphRun, HitTestPoint,
+    HitTestPosition, IntoLayoutStorage, LayoutStorage, LineMetric, LineSpacing, ShadowStyle,
+    StrokeStyle, TabStops, Text, TextAlignment, TextAttribute, TextDirection, TextLayout,
+    TextLayoutBuilder, TextOrientation, TruncationMode, UnderlineStyle,
 };
 
 use unicode_segmentation::UnicodeSegmentation;
 
+use self::bidi::VisualLine;
+use self::fallback::FallbackRun;
 use self::grapheme::{get_grapheme_boundaries, point_x_in_grapheme};
 
 /// Right now, we don't need any state, as the "toy text API" treats the
@@ -29,23 +42,112 @@ struct CairoFont {
     family: FontFamily,
 }
 
+/// A `ScaledFont` that can be sent to another thread, so that layouts can be.
+///
+/// Cairo scaled fonts are reference counted atomically and guard their
+/// caches with a mutex, so this is safe, although cairo-rs doesn't say so.
+#[derive(Clone)]
+pub(crate) struct SendScaledFont(ScaledFont);
+
+unsafe impl Send for SendScaledFont {}
+
+impl Deref for SendScaledFont {
+    type Target = ScaledFont;
+
+    fn deref(&self) -> &ScaledFont {
+        &self.0
+    }
+}
+
 #[derive(Clone)]
 pub struct CairoTextLayout {
     // we currently don't handle range attributes, so we stash the default
     // color here and then just grab it when we draw ourselves.
     pub(crate) fg_color: Color,
     size: Size,
-    pub(crate) font: ScaledFont,
-    pub(crate) text: String,
+    image_bounds: Rect,
+    pub(crate) font: SendScaledFont,
+    /// the family and size `font` was created from, for `glyph_runs`.
+    font_family: FontFamily,
+    font_size: f64,
+    /// the clusters that `font` has no glyphs for, and the fonts they are
+    /// drawn with instead, in order.
+    fallbacks: Vec<FallbackRun>,
+    /// the decoration metrics of `font`.
+    decorations: Decorations,
+    pub(crate) text: Arc<dyn LayoutStorage>,
+    /// ranges of the text with nonzero letter spacing, the extra advance
+    /// after each grapheme; the toy text API can't do this for us.
+    letter_spacings: Vec<(Range<usize>, f64)>,
+    line_spacing: LineSpacing,
+    max_lines: Option<usize>,
+    truncation: TruncationMode,
+    alignment: TextAlignment,
+    tab_stops: Option<TabStops>,
+    /// the offsets at which lines may break, if they were set with
+    /// `break_opportunities`.
+    break_opportunities: Option<Vec<usize>>,
 
     // currently calculated on build
     pub(crate) line_metrics: Vec<LineMetric>,
+    /// the extra advance after each space, for each line; nonzero only
+    /// for the justified lines of `TextAlignment::Justified` text.
+    pub(crate) word_spacing: Vec<f64>,
+    /// the offset and x position of the leading edge of each grapheme of each
+    /// line, and of its end, for hit testing.
+    grapheme_edges: Vec<Vec<(usize, f64)>>,
+    /// the direction of the text's paragraphs.
+    direction: TextDirection,
+    /// the graphemes of each line with right-to-left text, in visual order.
+    visual_lines: Vec<Option<VisualLine>>,
+    /// the position of the ellipsis baseline, if the text was truncated
+    /// with `TruncationMode::EllipsisEnd`.
+    pub(crate) ellipsis: Option<Point>,
+    /// ranges of the text that are underlined, with their style and color.
+    underlines: Vec<(Range<usize>, UnderlineStyle, Option<Color>)>,
+    /// ranges of the text that are struck through.
+    strikethroughs: Vec<Range<usize>>,
+    /// ranges of the text with a background color.
+    backgrounds: Vec<(Range<usize>, Color)>,
+    /// ranges of the text with a nonzero baseline shift.
+    baseline_shifts: Vec<(Range<usize>, f64)>,
+    /// ranges of the text with a nonzero `WordSpacing` attribute.
+    word_spacing_spans: Vec<(Range<usize>, f64)>,
+    /// ranges of the text with a shadow.
+    shadows: Vec<(Range<usize>, ShadowStyle)>,
+    /// the glyph runs, made the first time they are asked for.
+    glyph_runs: OnceCell<Vec<GlyphRun>>,
 }
 
 pub struct CairoTextLayoutBuilder {
-    text: String,
+    text: Arc<dyn LayoutStorage>,
     defaults: util::LayoutDefaults,
     width_constraint: f64,
+    line_spacing: LineSpacing,
+    max_lines: Option<usize>,
+    truncation: TruncationMode,
+    alignment: TextAlignment,
+    direction: TextDirection,
+    orientation: TextOrientation,
+    tab_stops: Option<TabStops>,
+    /// the offsets at which lines may break, if they were set with
+    /// `break_opportunities`.
+    break_opportunities: Option<Vec<usize>>,
+    underlines: Vec<(Range<usize>, bool)>,
+    underline_colors: Vec<(Range<usize>, Color)>,
+    underline_styles: Vec<(Range<usize>, UnderlineStyle)>,
+    strikethroughs: Vec<(Range<usize>, bool)>,
+    backgrounds: Vec<(Range<usize>, Color)>,
+    baseline_shifts: Vec<(Range<usize>, f64)>,
+    letter_spacings: Vec<(Range<usize>, f64)>,
+    word_spacing_spans: Vec<(Range<usize>, f64)>,
+    shadows: Vec<(Range<usize>, ShadowStyle)>,
+    /// toy text can't apply font features, so any range with them causes
+    /// `build` to fail.
+    has_font_features: bool,
+    /// the last family set with a range attribute over all of the text,
+    /// which toy text can draw, unlike a family for part of it.
+    whole_text_font: Option<TextAttribute>,
 }
 
 impl CairoText {
@@ -67,11 +169,50 @@ impl Text for CairoText {
         Some(FontFamily::new_unchecked(family_name))
     }
 
-    fn new_text_layout(&mut self, text: &str) -> Self::TextLayoutBuilder {
+    fn font_families(&mut self) -> Vec<FontFamily> {
+        // the toy text API has no way to list the installed fonts
+        vec![
+            FontFamily::MONOSPACE,
+            FontFamily::SANS_SERIF,
+            FontFamily::SERIF,
+            FontFamily::SYSTEM_UI,
+        ]
+    }
+
+    fn load_font(&mut self, data: &[u8]) -> Result<FontFamily, Error> {
+        fonts::load(data)
+    }
+
+    fn font_metrics(&mut self, family: &FontFamily, size: f64) -> Option<FontMetrics> {
+        let font =
+            CairoFont::new(family.clone()).resolve(size, FontSlant::Normal, FontWeight::Normal);
+        Some(font_metrics(&font))
+    }
+
+    fn new_text_layout(&mut self, text: impl IntoLayoutStorage) -> Self::TextLayoutBuilder {
         CairoTextLayoutBuilder {
             defaults: util::LayoutDefaults::default(),
-            text: text.to_owned(),
+            text: Arc::new(text.into_layout_storage()),
             width_constraint: f64::INFINITY,
+            line_spacing: LineSpacing::Default,
+            max_lines: None,
+            truncation: TruncationMode::Clip,
+            alignment: TextAlignment::default(),
+            direction: TextDirection::default(),
+            orientation: TextOrientation::Horizontal,
+            tab_stops: None,
+            break_opportunities: None,
+            underlines: Vec::new(),
+            underline_colors: Vec::new(),
+            underline_styles: Vec::new(),
+            strikethroughs: Vec::new(),
+            backgrounds: Vec::new(),
+            baseline_shifts: Vec::new(),
+            letter_spacings: Vec::new(),
+            word_spacing_spans: Vec::new(),
+            shadows: Vec::new(),
+            has_font_features: false,
+            whole_text_font: None,
         }
     }
 }
@@ -88,7 +229,8 @@ impl CairoFont {
 
     /// Create a ScaledFont for this family.
     pub(crate) fn resolve(&self, size: f64, slant: FontSlant, weight: FontWeight) -> ScaledFont {
-        let font_face = FontFace::toy_create(self.family.name(), slant, weight);
+        let font_face = fonts::face(self.family.name(), slant, weight)
+            .unwrap_or_else(|| FontFace::toy_create(self.family.name(), slant, weight));
         let font_matrix = scale_matrix(size);
         let ctm = scale_matrix(1.0);
         let options = FontOptions::default();
@@ -104,8 +246,44 @@ impl TextLayoutBuilder for CairoTextLayoutBuilder {
         self
     }
 
-    fn alignment(self, _alignment: piet::TextAlignment) -> Self {
-        eprintln!("TextAlignment not supported by cairo toy text");
+    fn alignment(mut self, alignment: piet::TextAlignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
+
+    fn text_direction(mut self, direction: TextDirection) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    fn orientation(mut self, orientation: TextOrientation) -> Self {
+        self.orientation = orientation;
+        self
+    }
+
+    fn line_spacing(mut self, spacing: LineSpacing) -> Self {
+        self.line_spacing = spacing;
+        self
+    }
+
+    fn max_lines(mut self, max_lines: usize) -> Self {
+        self.max_lines = Some(max_lines.max(1));
+        self
+    }
+
+    fn truncate(mut self, mode: TruncationMode) -> Self {
+        self.truncation = mode;
+        self
+    }
+
+    fn tab_stops(mut self, stops: TabStops) -> Self {
+        self.tab_stops = Some(stops);
+        self
+    }
+
+    fn break_opportunities(mut self, breaks: impl FnOnce(&str) -> Vec<usize>) -> Self {
+        let text = self.text.as_str();
+        self.break_opportunities = Some(util::resolve_break_opportunities(text, breaks(text)));
         self
     }
 
@@ -115,16 +293,57 @@ impl TextLayoutBuilder for CairoTextLayoutBuilder {
     }
 
     fn range_attribute(
-        self,
-        _range: impl RangeBounds<usize>,
-        _attribute: impl Into<TextAttribute>,
+        mut self,
+        range: impl RangeBounds<usize>,
+        attribute: impl Into<TextAttribute>,
     ) -> Self {
+        let range = util::resolve_range(range, self.text.as_str().len());
+        match attribute.into() {
+            TextAttribute::Underline(flag) => self.underlines.push((range, flag)),
+            TextAttribute::UnderlineColor(color) => self.underline_colors.push((range, color)),
+            TextAttribute::UnderlineStyle(style) => self.underline_styles.push((range, style)),
+            TextAttribute::Strikethrough(flag) => self.strikethroughs.push((range, flag)),
+            TextAttribute::BackgroundColor(color) => self.backgrounds.push((range, color)),
+            TextAttribute::BaselineShift(shift) => self.baseline_shifts.push((range, shift)),
+            TextAttribute::LetterSpacing(spacing) => self.letter_spacings.push((range, spacing)),
+            TextAttribute::WordSpacing(spacing) => self.word_spacing_spans.push((range, spacing)),
+            TextAttribute::Shadow(shadow) => self.shadows.push((range, shadow)),
+            TextAttribute::FontFeatures(features) => self.has_font_features |= !features.is_empty(),
+            font @ TextAttribute::Font(_) | font @ TextAttribute::FontFamilies(_)
+                if range == (0..self.text.as_str().len()) =>
+            {
+                self.whole_text_font = Some(font)
+            }
+            // other font attributes are only supported as default attributes.
+            _ => (),
+        }
         self
     }
 
-    fn build(self) -> Result<Self::Out, Error> {
-        // set our default font
-        let font = CairoFont::new(self.defaults.font.clone());
+    fn build(mut self) -> Result<Self::Out, Error> {
+        // toy text can't apply font features, or lay out text vertically
+        if self.has_font_features
+            || !self.defaults.font_features.is_empty()
+            || self.orientation != TextOrientation::Horizontal
+        {
+            return Err(Error::NotSupported);
+        }
+
+        // lines are always placed from the left edge. An `End` alignment in
+        // right-to-left text is the left edge, which we can do.
+        let rtl = self.direction.is_rtl(self.text.as_str());
+        let alignment = match self.alignment {
+            TextAlignment::End if rtl => TextAlignment::Start,
+            TextAlignment::Start if rtl => TextAlignment::End,
+            other => other,
+        };
+        if matches!(alignment, TextAlignment::End | TextAlignment::Center) {
+            eprintln!("{:?} alignment not supported by cairo toy text", alignment);
+        }
+
+        if let Some(font) = self.whole_text_font.take() {
+            self.defaults.set(font);
+        }
         let size = self.defaults.font_size;
         let weight = if self.defaults.weight.to_raw() <= piet::FontWeight::MEDIUM.to_raw() {
             FontWeight::Normal
@@ -137,15 +356,105 @@ impl TextLayoutBuilder for CairoTextLayoutBuilder {
             FontSlant::Normal
         };
 
-        let scaled_font = font.resolve(size, slant, weight);
+        // set our default font: the first family that covers all of the text,
+        // or the primary family if none do.
+        let resolve =
+            |family: &FontFamily| CairoFont::new(family.clone()).resolve(size, slant, weight);
+        let primary = resolve(&self.defaults.font);
+        let text = self.text.as_str();
+        let (font_family, scaled_font) =
+            if self.defaults.font_fallbacks.is_empty() || covers_text(&primary, text) {
+                (self.defaults.font.clone(), primary)
+            } else {
+                self.defaults
+                    .font_fallbacks
+                    .iter()
+                    .map(|family| (family.clone(), resolve(family)))
+                    .find(|(_, font)| covers_text(font, text))
+                    .unwrap_or((self.defaults.font.clone(), primary))
+            };
+        // clusters that font doesn't cover fall back to the other families,
+        // and then to whatever fontconfig finds
+        let fallbacks = if covers_text(&scaled_font, text) {
+            Vec::new()
+        } else {
+            let families: Vec<_> = std::iter::once(&self.defaults.font)
+                .chain(&self.defaults.font_fallbacks)
+                .filter(|family| **family != font_family)
+                .map(|family| (family.clone(), resolve(family)))
+                .collect();
+            fallback::fallback_runs(text, &scaled_font, &families, size, slant, weight)
+        };
+
+        let len = self.text.as_str().len();
+        let underlines = util::resolve_underlines(
+            &self.defaults,
+            len,
+            self.underlines,
+            self.underline_colors,
+            self.underline_styles,
+        );
+        let strikethroughs =
+            util::resolve_flag_ranges(self.defaults.strikethrough, len, self.strikethroughs);
+        let backgrounds =
+            util::resolve_spans(self.defaults.background_color, len, self.backgrounds);
+        let baseline_shifts = util::resolve_spans(
+            Some(self.defaults.baseline_shift),
+            len,
+            self.baseline_shifts,
+        )
+        .into_iter()
+        .filter(|(_, shift)| *shift != 0.0)
+        .collect();
+        let letter_spacings = util::resolve_spans(
+            Some(self.defaults.letter_spacing),
+            len,
+            self.letter_spacings,
+        )
+        .into_iter()
+        .filter(|(_, spacing)| *spacing != 0.0)
+        .collect();
+        let word_spacing_spans = util::resolve_spans(
+            Some(self.defaults.word_spacing),
+            len,
+            self.word_spacing_spans,
+        )
+        .into_iter()
+        .filter(|(_, spacing)| *spacing != 0.0)
+        .collect();
+        let shadows = util::resolve_spans(self.defaults.shadow.clone(), len, self.shadows);
 
         // invalid until update_width() is called
         let mut layout = CairoTextLayout {
             fg_color: self.defaults.fg_color,
-            font: scaled_font,
+            decorations: Decorations::new(&scaled_font),
+            font: SendScaledFont(scaled_font),
+            font_family,
+            font_size: size,
+            fallbacks,
             size: Size::ZERO,
+            image_bounds: Rect::ZERO,
             line_metrics: Vec::new(),
             text: self.text,
+            letter_spacings,
+            line_spacing: self.line_spacing,
+            max_lines: self.max_lines,
+            truncation: self.truncation,
+            alignment,
+            tab_stops: self.tab_stops,
+            break_opportunities: self.break_opportunities,
+            word_spacing: Vec::new(),
+            grapheme_edges: Vec::new(),
+            direction: self.direction,
+            visual_lines: Vec::new(),
+            ellipsis: None,
+            underlines,
+            strikethroughs,
+            backgrounds,
+            baseline_shifts,
+            word_spacing_spans,
+            shadows,
+            glyph_runs: OnceCell::new(),
         };
 
         layout.update_width(self.width_constraint)?;
@@ -164,23 +473,61 @@ impl TextLayout for CairoTextLayout {
     }
 
     fn image_bounds(&self) -> Rect {
-        self.size.to_rect()
+        self.image_bounds
     }
 
     fn text(&self) -> &str {
-        &self.text
+        self.text.as_str()
     }
 
     fn update_width(&mut self, new_width: impl Into<Option<f64>>) -> Result<(), Error> {
         let new_width = new_width.into().unwrap_or(std::f64::INFINITY);
 
-        self.line_metrics = lines::calculate_line_metrics(&self.text, &self.font, new_width);
-
-        let width = self
-            .line_metrics
-            .iter()
-            .map(|lm| self.font.text_extents(&self.text[lm.range()]).x_advance)
-            .fold(0.0, |a: f64, b| a.max(b));
+        self.glyph_runs = OnceCell::new();
+        self.line_metrics = lines::calculate_line_metrics(
+            self.text.as_str(),
+            self.measure(0.0),
+            self.line_spacing,
+            self.break_opportunities.as_deref(),
+            new_width,
+        );
+        self.truncate_lines(new_width);
+        self.word_spacing = (0..self.line_metrics.len())
+            .map(|line_number| self.justified_word_spacing(line_number, new_width))
+            .collect();
+        self.grapheme_edges = (0..self.line_metrics.len())
+            .map(|line_number| {
+                let line = self.line_text(line_number).unwrap_or_default();
+                grapheme::grapheme_edges(self.line_measure(line_number), line)
+            })
+            .collect();
+        let bidi = bidi::bidi_info(self.text.as_str(), self.direction);
+        let visual_lines = (0..self.line_metrics.len())
+            .map(|line_number| {
+                let lm = &self.line_metrics[line_number];
+                let runs = bidi::visual_runs(&bidi, lm.range())?;
+                let line = self.line_text(line_number).unwrap_or_default();
+                Some(VisualLine::new(self.line_measure(line_number), line, &runs))
+            })
+            .collect();
+        self.visual_lines = visual_lines;
+
+        let ellipsis_width = self
+            .ellipsis
+            .map(|pos| pos.x + self.font.text_extents(util::ELLIPSIS).x_advance)
+            .unwrap_or_default();
+        let width = (0..self.line_metrics.len())
+            .map(|line_number| {
+                let line = self.line_text(line_number).unwrap_or_default();
+                let measure = self.line_measure(line_number);
+                if measure.word_spacing > 0.0 {
+                    // justified lines fill the width, excluding trailing whitespace
+                    measure.line_advance(lines::trim_trailing_whitespace(line))
+                } else {
+                    measure.wrap_advance(line)
+                }
+            })
+            .fold(ellipsis_width, |a: f64, b| a.max(b));
 
         let height = self
             .line_metrics
@@ -188,210 +535,2319 @@ impl TextLayout for CairoTextLayout {
             .map(|l| l.y_offset + l.height)
             .unwrap_or_default();
         self.size = Size::new(width, height);
+        let bounds = self.shifted_bounds(self.size.to_rect());
+        let shadows = self.shadows.iter().map(|(_, shadow)| shadow);
+        self.image_bounds = util::bounds_with_shadows(bounds, shadows);
 
         Ok(())
     }
 
-    fn line_text(&self, line_number: usize) -> Option<&str> {
-        self.line_metrics
-            .get(line_number)
-            .map(|lm| &self.text[lm.range()])
+    fn line_text(&self, line_number: usize) -> Option<&str> {
+        self.line_metrics
+            .get(line_number)
+            .map(|lm| &self.text.as_str()[lm.range()])
+    }
+
+    fn line_text_range(&self, line_number: usize) -> Option<Range<usize>> {
+        self.line_metrics.get(line_number).map(LineMetric::range)
+    }
+
+    fn line_metric(&self, line_number: usize) -> Option<LineMetric> {
+        self.line_metrics.get(line_number).cloned()
+    }
+
+    fn line_count(&self) -> usize {
+        self.line_metrics.len()
+    }
+
+    fn hit_test_point(&self, point: Point) -> HitTestPoint {
+        // internal logic is using grapheme clusters, but return the text position associated
+        // with the border of the grapheme cluster.
+
+        // null case
+        if self.text.as_str().is_empty() {
+            return HitTestPoint::default();
+        }
+
+        let height = self
+            .line_metrics
+            .last()
+            .map(|lm| lm.y_offset + lm.height)
+            .unwrap_or(0.0);
+
+        // determine whether this click is within the y bounds of the layout,
+        // and what line it coorresponds to. (For points above and below the layout,
+        // we hittest the first and last lines respectively.)
+        let (y_inside, line_num) = if point.y < 0. {
+            (false, 0)
+        } else if point.y >= height {
+            (false, self.line_metrics.len() - 1)
+        } else {
+            let line_num = self
+                .line_metrics
+                .iter()
+                .position(|l| point.y >= l.y_offset && point.y < l.y_offset + l.height)
+                .unwrap();
+            (true, line_num)
+        };
+        let lm = &self.line_metrics[line_num];
+
+        let mut htp = match &self.visual_lines[line_num] {
+            Some(visual) => visual.hit_test_point(point.x),
+            None => hit_test_line_point(&self.grapheme_edges[line_num], point),
+        };
+        htp.idx += lm.start_offset;
+        htp.is_inside &= y_inside;
+        htp
+    }
+
+    fn hit_test_text_position(&self, text_position: usize) -> Option<HitTestPosition> {
+        // first need to find line it's on, and get line start offset
+        let line_num = util::line_number_for_position(&self.line_metrics, text_position);
+        let lm = self.line_metrics.get(line_num).cloned().unwrap();
+
+        let y_pos = lm.y_offset + lm.baseline;
+
+        // Then for the line, do text position
+        let line_position = text_position - lm.start_offset;
+
+        let x_pos = match &self.visual_lines[line_num] {
+            Some(visual) => Some(visual.caret_x(line_position, Affinity::Downstream)),
+            None => hit_test_line_position(&self.grapheme_edges[line_num], line_position),
+        };
+        x_pos.map(|x_pos| HitTestPosition::new(Point::new(x_pos, y_pos), line_num))
+    }
+
+    fn hit_test_text_position_with_affinity(
+        &self,
+        idx: usize,
+        affinity: Affinity,
+    ) -> Option<HitTestPosition> {
+        // where the direction changes within a line, an upstream caret is
+        // at the trailing edge of the grapheme before it
+        let line_num = util::line_number_for_position(&self.line_metrics, idx);
+        let lm = self.line_metrics.get(line_num)?;
+        match &self.visual_lines[line_num] {
+            Some(visual) if affinity == Affinity::Upstream && idx > lm.start_offset => {
+                let x = visual.caret_x(idx - lm.start_offset, affinity);
+                let point = Point::new(x, lm.y_offset + lm.baseline);
+                Some(HitTestPosition::new(point, line_num))
+            }
+            _ => util::hit_test_text_position_with_affinity(self, idx, affinity),
+        }
+    }
+
+    fn rects_for_range(&self, range: impl RangeBounds<usize>) -> Vec<Rect> {
+        self.range_rects(range, true)
+    }
+
+    fn glyph_runs(&self) -> &[GlyphRun] {
+        self.glyph_runs.get_or_init(|| self.make_glyph_runs())
+    }
+
+    fn outline(&self) -> BezPath {
+        // the glyph outlines are only available through a context, which
+        // needs a surface, although we never draw to it.
+        let surface = match ImageSurface::create(Format::ARgb32, 1, 1) {
+            Ok(surface) => surface,
+            Err(_) => return BezPath::new(),
+        };
+        let ctx = Context::new(&surface);
+        for line_number in 0..self.line_count() {
+            for (origin, _, run, fallback) in self.line_runs(line_number) {
+                ctx.set_scaled_font(self.run_font(fallback).0);
+                ctx.move_to(origin.x, origin.y);
+                ctx.text_path(run);
+            }
+        }
+        if let Some(ellipsis) = self.ellipsis {
+            ctx.set_scaled_font(&self.font);
+            ctx.move_to(ellipsis.x, ellipsis.y);
+            ctx.text_path(util::ELLIPSIS);
+        }
+
+        let mut path = BezPath::new();
+        for segment in ctx.copy_path().iter() {
+            match segment {
+                PathSegment::MoveTo(p) => path.move_to(p),
+                PathSegment::LineTo(p) => path.line_to(p),
+                PathSegment::CurveTo(p1, p2, p3) => path.curve_to(p1, p2, p3),
+                PathSegment::ClosePath => path.close_path(),
+            }
+        }
+        path
+    }
+}
+
+impl CairoTextLayout {
+    /// The glyphs of the layout, in runs of the same font.
+    fn make_glyph_runs(&self) -> Vec<GlyphRun> {
+        let mut runs = Vec::new();
+        let mut push_run = |font: &FontFamily, glyphs: Vec<Glyph>| {
+            if !glyphs.is_empty() {
+                runs.push(GlyphRun {
+                    font: font.clone(),
+                    font_size: self.font_size,
+                    glyphs,
+                });
+            }
+        };
+
+        // each line is one run, unless part of it is drawn with a fallback font
+        for line_number in 0..self.line_count() {
+            let mut family = &self.font_family;
+            let mut glyphs = Vec::new();
+            for (origin, offset, run, fallback) in self.line_runs(line_number) {
+                let (font, run_family) = self.run_font(fallback);
+                if run_family != family {
+                    push_run(family, std::mem::take(&mut glyphs));
+                    family = run_family;
+                }
+                glyphs.extend(self.shape_run(font, origin, offset, run));
+            }
+            push_run(family, glyphs);
+        }
+        if let Some(ellipsis) = self.ellipsis {
+            let end = self.line_metrics.last().map(|lm| lm.end_offset);
+            let glyphs = self.shape_run(
+                &self.font,
+                ellipsis,
+                end.unwrap_or_default(),
+                util::ELLIPSIS,
+            );
+            push_run(&self.font_family, glyphs);
+        }
+        runs
+    }
+
+    /// The glyphs that the toy text API draws for `run` in `font`, with its
+    /// baseline at `origin`; `offset` is the position of the run in the text,
+    /// from which the glyphs' clusters are found.
+    fn shape_run(&self, font: &ScaledFont, origin: Point, offset: usize, run: &str) -> Vec<Glyph> {
+        let (glyphs, clusters) = font.text_to_glyphs(origin.x, origin.y, run);
+        let glyph = |glyph: &cairo::Glyph, cluster| Glyph {
+            id: glyph.index as u32,
+            position: Point::new(glyph.x, glyph.y),
+            cluster,
+        };
+        if clusters.is_empty() {
+            return glyphs.iter().map(|g| glyph(g, offset)).collect();
+        }
+
+        let mut result = Vec::with_capacity(glyphs.len());
+        let mut glyphs = glyphs.iter();
+        let mut cluster_start = offset;
+        for cluster in clusters {
+            let cluster_glyphs = glyphs.by_ref().take(cluster.num_glyphs as usize);
+            result.extend(cluster_glyphs.map(|g| glyph(g, cluster_start)));
+            cluster_start += cluster.num_bytes as usize;
+        }
+        result
+    }
+
+    /// The thickness of underlines.
+    pub(crate) fn underline_thickness(&self) -> f64 {
+        self.decorations.underline_thickness
+    }
+
+    /// Returns the start and end x positions of the visible part of `range`
+    /// in each visual run it touches, with the baseline of that run.
+    ///
+    /// Runs are split where the baseline shift changes, so decorations
+    /// follow the text they decorate.
+    fn decoration_spans(&self, range: &Range<usize>) -> Vec<(f64, f64, f64)> {
+        let mut spans = Vec::new();
+        for (line_number, lm) in self.line_metrics.iter().enumerate() {
+            let start = range.start.max(lm.start_offset);
+            let end = range.end.min(lm.end_offset - lm.trailing_whitespace);
+            if start >= end {
+                continue;
+            }
+            let line_range = start - lm.start_offset..end - lm.start_offset;
+            for (segment, shift) in self.line_segments(line_number) {
+                let segment_start = segment.start.max(line_range.start);
+                let segment_end = segment.end.min(line_range.end);
+                if segment_start >= segment_end {
+                    continue;
+                }
+                let baseline = lm.y_offset + lm.baseline - shift;
+                for (x0, x1) in self.line_spans(line_number, segment_start..segment_end) {
+                    spans.push((x0, x1, baseline));
+                }
+            }
+        }
+        spans
+    }
+
+    /// Returns the rects covering the strikethrough decorations of this
+    /// layout, relative to the layout's origin.
+    pub(crate) fn strikethrough_rects(&self) -> Vec<Rect> {
+        let Decorations {
+            strikeout_position: offset,
+            strikeout_thickness: thickness,
+            ..
+        } = self.decorations;
+        let mut rects = Vec::new();
+        for range in &self.strikethroughs {
+            for (x0, x1, baseline) in self.decoration_spans(range) {
+                let y0 = baseline + offset - thickness / 2.0;
+                rects.push(Rect::new(x0, y0, x1, y0 + thickness));
+            }
+        }
+        rects
+    }
+
+    /// Returns the underlines of this layout, relative to the layout's origin.
+    ///
+    /// Each is a path to be stroked with its `StrokeStyle` at the width given by
+    /// [`underline_thickness`], in its color if it has one, or else in the
+    /// foreground color.
+    ///
+    /// [`underline_thickness`]: #method.underline_thickness
+    pub(crate) fn underline_paths(&self) -> Vec<(BezPath, StrokeStyle, Option<Color>)> {
+        let Decorations {
+            underline_position: offset,
+            underline_thickness: thickness,
+            ..
+        } = self.decorations;
+        let mut paths = Vec::new();
+        for (range, style, color) in &self.underlines {
+            for (x0, x1, baseline) in self.decoration_spans(range) {
+                let (path, stroke) =
+                    util::underline_geometry(*style, x0, x1, baseline + offset, thickness);
+                paths.push((path, stroke, color.clone()));
+            }
+        }
+        paths
+    }
+
+    /// Returns the rects (relative to the layout's origin) and colors of the
+    /// backgrounds of this layout.
+    pub(crate) fn background_rects(&self) -> Vec<(Rect, Color)> {
+        let mut result = Vec::new();
+        for (range, color) in &self.backgrounds {
+            for rect in self.range_rects(range.clone(), false) {
+                if rect.width() > 0.0 {
+                    result.push((rect, color.clone()));
+                }
+            }
+        }
+        result
+    }
+
+    /// Returns the shadows of this layout, with the rects (relative to the
+    /// layout's origin) of the text that casts each.
+    pub(crate) fn shadow_rects(&self) -> Vec<(Vec<Rect>, &ShadowStyle)> {
+        self.shadows
+            .iter()
+            .map(|(range, shadow)| (self.range_rects(range.clone(), false), shadow))
+            .filter(|(rects, _)| !rects.is_empty())
+            .collect()
+    }
+
+    /// The rects covering a range of the text, from the top to the bottom of
+    /// each line, including the rects of any newlines if `newlines` is `true`.
+    fn range_rects(&self, range: impl RangeBounds<usize>, newlines: bool) -> Vec<Rect> {
+        let mut rects = Vec::new();
+        for (line_number, range, newline) in
+            util::line_ranges(self.text.as_str(), &self.line_metrics, range)
+        {
+            let lm = &self.line_metrics[line_number];
+            let line_range = range.start - lm.start_offset..range.end - lm.start_offset;
+            for (x0, x1) in self.line_spans(line_number, line_range.clone()) {
+                rects.push(Rect::new(x0, lm.y_offset, x1, lm.y_offset + lm.height));
+            }
+            if newline && newlines {
+                let x1 = self.line_position(line_number, line_range.end);
+                rects.push(util::newline_rect(lm, x1));
+            }
+        }
+        rects
+    }
+
+    /// Splits a line into the ranges (relative to the start of the line) that
+    /// share a baseline shift, along with that shift.
+    pub(crate) fn line_segments(&self, line_number: usize) -> Vec<(Range<usize>, f64)> {
+        let lm = &self.line_metrics[line_number];
+        let mut segments = Vec::new();
+        let mut pos = lm.start_offset;
+        for (range, shift) in &self.baseline_shifts {
+            let start = range.start.max(lm.start_offset);
+            let end = range.end.min(lm.end_offset);
+            if start >= end {
+                continue;
+            }
+            if start > pos {
+                segments.push((pos - lm.start_offset..start - lm.start_offset, 0.0));
+            }
+            segments.push((start - lm.start_offset..end - lm.start_offset, *shift));
+            pos = end;
+        }
+        if pos < lm.end_offset {
+            segments.push((pos - lm.start_offset..lm.end_offset - lm.start_offset, 0.0));
+        }
+        segments
+    }
+
+    /// The font to draw a run of [`line_runs`] with, and its family.
+    ///
+    /// [`line_runs`]: #method.line_runs
+    pub(crate) fn run_font<'a>(
+        &'a self,
+        fallback: Option<&'a FallbackRun>,
+    ) -> (&'a ScaledFont, &'a FontFamily) {
+        match fallback {
+            Some(fallback) => (&fallback.font, &fallback.family),
+            None => (&self.font, &self.font_family),
+        }
+    }
+
+    /// Splits a line into runs of text that can each be drawn with a single
+    /// call to the toy text API, along with the origin of each run's baseline
+    /// relative to the layout's origin, the run's offset in the text, and the
+    /// fallback run it is part of, if it isn't drawn with the layout's font.
+    ///
+    /// Tabs and line breaks are never drawn; the runs following tabs start
+    /// at the tab stop. Each grapheme of a line with right-to-left text is
+    /// its own run, placed in visual order.
+    pub(crate) fn line_runs<'a>(
+        &'a self,
+        line_number: usize,
+    ) -> Vec<(Point, usize, &'a str, Option<&'a FallbackRun>)> {
+        if let Some(visual) = &self.visual_lines[line_number] {
+            return self.visual_line_runs(line_number, visual);
+        }
+        let lm = &self.line_metrics[line_number];
+        let line = &self.text.as_str()[lm.range()];
+        let measure = self.line_measure(line_number);
+        // a justified line places each word, in addition to each tab-separated run
+        let separators: &[char] = if measure.word_spacing != 0.0 {
+            &[' ', '\t']
+        } else {
+            &['\t']
+        };
+
+        let mut runs = Vec::new();
+        for (segment, shift) in self.line_segments(line_number) {
+            let y = lm.y_offset + lm.baseline - shift;
+            let origin = |offset: usize| Point::new(measure.advance(&line[..offset]), y);
+            let mut push = |offset: usize, run: &'a str| {
+                // the toy text API draws a line break as a missing glyph
+                let run = run.trim_end_matches(is_hard_break);
+                let pos = lm.start_offset + offset;
+                for (idx, piece, fallback) in fallback::split(run, pos, &self.fallbacks) {
+                    runs.push((origin(offset + idx), pos + idx, piece, fallback));
+                }
+            };
+            if !self.letter_spacings.is_empty() {
+                // the toy text API has no letter spacing, so we place each grapheme ourselves
+                for (idx, grapheme) in line[segment.clone()].grapheme_indices(true) {
+                    if grapheme != "\t" {
+                        push(segment.start + idx, grapheme);
+                    }
+                }
+            } else {
+                let mut idx = segment.start;
+                for run in line[segment].split(separators) {
+                    if !run.is_empty() {
+                        push(idx, run);
+                    }
+                    // separators are all a single byte
+                    idx += run.len() + 1;
+                }
+            }
+        }
+        runs
+    }
+
+    /// The runs of [`line_runs`] for a line with right-to-left text.
+    ///
+    /// [`line_runs`]: #method.line_runs
+    fn visual_line_runs<'a>(
+        &'a self,
+        line_number: usize,
+        visual: &'a VisualLine,
+    ) -> Vec<(Point, usize, &'a str, Option<&'a FallbackRun>)> {
+        let lm = &self.line_metrics[line_number];
+        let line = &self.text.as_str()[lm.range()];
+        let segments = self.line_segments(line_number);
+        let mut runs = Vec::new();
+        for grapheme in &visual.graphemes {
+            let text = &line[grapheme.range.clone()];
+            if text == "\t" || text.chars().all(is_hard_break) {
+                continue;
+            }
+            let shift = segments
+                .iter()
+                .find(|(segment, _)| segment.contains(&grapheme.range.start))
+                .map(|(_, shift)| *shift)
+                .unwrap_or_default();
+            let origin = Point::new(grapheme.x0, lm.y_offset + lm.baseline - shift);
+            let pos = lm.start_offset + grapheme.range.start;
+            // fallback runs are made of whole graphemes
+            let idx = self.fallbacks.partition_point(|run| run.range.end <= pos);
+            let fallback = self.fallbacks.get(idx).filter(|run| run.range.start <= pos);
+            runs.push((origin, pos, text, fallback));
+        }
+        runs
+    }
+
+    /// Expands `bounds` to include the line boxes of any shifted text.
+    fn shifted_bounds(&self, mut bounds: Rect) -> Rect {
+        for (line_number, lm) in self.line_metrics.iter().enumerate() {
+            for (segment, shift) in self.line_segments(line_number) {
+                if shift == 0.0 {
+                    continue;
+                }
+                let y0 = lm.y_offset - shift;
+                for (x0, x1) in self.line_spans(line_number, segment) {
+                    bounds = bounds.union(Rect::new(x0, y0, x1, y0 + lm.height));
+                }
+            }
+        }
+        bounds
+    }
+
+    /// Drop any lines past `max_lines`, making room for the ellipsis if needed.
+    fn truncate_lines(&mut self, width: f64) {
+        self.ellipsis = None;
+        let max_lines = match self.max_lines {
+            Some(max_lines) if self.line_metrics.len() > max_lines => max_lines,
+            _ => return,
+        };
+        self.line_metrics.truncate(max_lines);
+        if self.truncation != TruncationMode::EllipsisEnd {
+            return;
+        }
+
+        let ellipsis_width = self.font.text_extents(util::ELLIPSIS).x_advance;
+        // not `self.measure()`, since we're also borrowing the line metrics
+        let measure = LineMeasure {
+            font: &self.font,
+            letter_spacings: &self.letter_spacings,
+            word_spacing: 0.0,
+            tab_stops: self.tab_stops.as_ref(),
+            word_spacing_spans: &self.word_spacing_spans,
+            fallbacks: &self.fallbacks,
+            offset: 0,
+        };
+        let lm = self.line_metrics.last_mut().unwrap();
+        let measure = measure.at(lm.start_offset);
+        let line = &self.text.as_str()[lm.start_offset..lm.end_offset - lm.trailing_whitespace];
+        // remove graphemes from the end of the line until the ellipsis fits
+        let mut end = line.len();
+        while end > 0 && measure.advance(&line[..end]) + ellipsis_width > width {
+            end = line[..end]
+                .grapheme_indices(true)
+                .next_back()
+                .map(|(idx, _)| idx)
+                .unwrap_or(0);
+        }
+        let visible = lines::trim_trailing_whitespace(&line[..end]);
+        lm.end_offset = lm.start_offset + visible.len();
+        lm.trailing_whitespace = 0;
+        lm.trailing_whitespace_width = 0.0;
+
+        let x = measure.advance(visible);
+        self.ellipsis = Some(Point::new(x, lm.y_offset + lm.baseline));
+    }
+
+    /// The extra advance after each space needed to justify this line to `width`.
+    ///
+    /// The last line, and lines that end a paragraph, are not justified.
+    fn justified_word_spacing(&self, line_number: usize, width: f64) -> f64 {
+        let lm = &self.line_metrics[line_number];
+        let line = &self.text.as_str()[lm.range()];
+        let is_last_line = line_number + 1 == self.line_metrics.len();
+        if self.alignment != TextAlignment::Justified
+            || !width.is_finite()
+            || is_last_line
+            || line.ends_with(is_hard_break)
+        {
+            return 0.0;
+        }
+
+        let visible = lines::trim_trailing_whitespace(line);
+        let gaps = visible.matches(' ').count();
+        let extra = width - self.measure(0.0).at(lm.start_offset).line_advance(visible);
+        if gaps == 0 || extra <= 0.0 {
+            return 0.0;
+        }
+        extra / gaps as f64
+    }
+
+    /// The x position of a position relative to the start of a line.
+    fn line_position(&self, line_number: usize, text_position: usize) -> f64 {
+        if let Some(Some(visual)) = self.visual_lines.get(line_number) {
+            return visual.caret_x(text_position, Affinity::Downstream);
+        }
+        let edges = self.grapheme_edges.get(line_number);
+        edges
+            .and_then(|edges| hit_test_line_position(edges, text_position))
+            .unwrap_or_default()
+    }
+
+    /// The extents, from left to right, of the parts of a line covering a
+    /// range relative to its start; more than one if the line has
+    /// right-to-left text.
+    fn line_spans(&self, line_number: usize, range: Range<usize>) -> Vec<(f64, f64)> {
+        if let Some(Some(visual)) = self.visual_lines.get(line_number) {
+            return visual.spans(range);
+        }
+        if range.is_empty() {
+            return Vec::new();
+        }
+        let x0 = self.line_position(line_number, range.start);
+        let x1 = self.line_position(line_number, range.end);
+        vec![(x0, x1)]
+    }
+
+    /// The parameters for measuring text starting at the beginning of the layout,
+    /// with the given extra advance after each space for justification.
+    fn measure(&self, word_spacing: f64) -> LineMeasure<'_> {
+        LineMeasure {
+            font: &self.font,
+            letter_spacings: &self.letter_spacings,
+            word_spacing,
+            tab_stops: self.tab_stops.as_ref(),
+            word_spacing_spans: &self.word_spacing_spans,
+            fallbacks: &self.fallbacks,
+            offset: 0,
+        }
+    }
+
+    /// The parameters for measuring a line of this layout.
+    fn line_measure(&self, line_number: usize) -> LineMeasure<'_> {
+        let lm = &self.line_metrics[line_number];
+        self.measure(self.word_spacing[line_number])
+            .at(lm.start_offset)
+    }
+}
+
+fn is_hard_break(c: char) -> bool {
+    matches!(c, '\n' | '\r' | '\u{2028}' | '\u{2029}')
+}
+
+/// Everything needed to measure text within a line; the toy text API
+/// handles none of the spacing or tab stops for us.
+#[derive(Clone, Copy)]
+pub(crate) struct LineMeasure<'a> {
+    pub font: &'a ScaledFont,
+    /// the ranges of the text with nonzero letter spacing, the extra
+    /// advance after each grapheme but the last of a line.
+    pub letter_spacings: &'a [(Range<usize>, f64)],
+    /// extra advance after each space, for justification.
+    pub word_spacing: f64,
+    pub tab_stops: Option<&'a TabStops>,
+    /// the ranges of the text with a nonzero `WordSpacing` attribute.
+    pub word_spacing_spans: &'a [(Range<usize>, f64)],
+    /// the ranges of the text drawn with fallback fonts.
+    pub fallbacks: &'a [FallbackRun],
+    /// the position in the text of the start of the measured text.
+    pub offset: usize,
+}
+
+impl<'a> LineMeasure<'a> {
+    #[cfg(test)]
+    pub(crate) fn new(font: &'a ScaledFont) -> Self {
+        LineMeasure {
+            font,
+            letter_spacings: &[],
+            word_spacing: 0.0,
+            tab_stops: None,
+            word_spacing_spans: &[],
+            fallbacks: &[],
+            offset: 0,
+        }
+    }
+
+    /// The same parameters, for text starting at `offset`.
+    pub(crate) fn at(self, offset: usize) -> Self {
+        LineMeasure { offset, ..self }
+    }
+
+    /// The advance of `text`, which is assumed to start at the beginning of a line.
+    pub(crate) fn advance(&self, text: &str) -> f64 {
+        let stops = match self.tab_stops {
+            Some(stops) => stops,
+            None => return self.run_advance(text, 0),
+        };
+        // each tab advances to the next stop, whatever its own glyph's advance
+        let mut x = 0.0;
+        let mut run_start = 0;
+        for (i, run) in text.split('\t').enumerate() {
+            if i > 0 {
+                x = stops.next_stop(x);
+            }
+            x += self.run_advance(run, run_start);
+            run_start += run.len() + 1;
+        }
+        x
+    }
+
+    /// The advance of `text` for the purpose of line breaking, which excludes
+    /// the `WordSpacing` of any trailing whitespace; it is absorbed by the break.
+    pub(crate) fn wrap_advance(&self, text: &str) -> f64 {
+        let trimmed = lines::trim_trailing_whitespace(text).len();
+        self.line_advance(text) - self.attribute_spacing(&text[trimmed..], trimmed)
+    }
+
+    /// The advance of `text` as a whole line, which has no letter spacing
+    /// after its last grapheme.
+    pub(crate) fn line_advance(&self, text: &str) -> f64 {
+        self.advance(text) - self.trailing_letter_spacing(text)
+    }
+
+    /// The letter spacing after the last grapheme of `text`, not counting
+    /// line breaks, which `line_advance` leaves out.
+    pub(crate) fn trailing_letter_spacing(&self, text: &str) -> f64 {
+        if self.letter_spacings.is_empty() {
+            return 0.0;
+        }
+        let content = text.trim_end_matches(is_hard_break);
+        match content.grapheme_indices(true).next_back() {
+            // a tab advances to the next stop, without letter spacing
+            Some((_, "\t")) if self.tab_stops.is_some() => 0.0,
+            Some((idx, grapheme)) => self.letter_spacing_after(grapheme, idx),
+            None => 0.0,
+        }
+    }
+
+    /// The advance of `text`, which starts `start` bytes into the measured
+    /// text, ignoring tab stops.
+    fn run_advance(&self, text: &str, start: usize) -> f64 {
+        let spaces = text.matches(' ').count();
+        let letter_spacing: f64 = if self.letter_spacings.is_empty() {
+            0.0
+        } else {
+            text.grapheme_indices(true)
+                .map(|(idx, grapheme)| self.letter_spacing_after(grapheme, start + idx))
+                .sum()
+        };
+        self.glyph_advance(text, start)
+            + letter_spacing
+            + self.word_spacing * spaces as f64
+            + self.attribute_spacing(text, start)
+    }
+
+    /// The letter spacing after `grapheme`, which starts `start` bytes into
+    /// the measured text, from the range it starts in. Negative spacing can
+    /// tighten a grapheme's advance to nothing, but no further.
+    fn letter_spacing_after(&self, grapheme: &str, start: usize) -> f64 {
+        let spacing = span_value_at(self.letter_spacings, self.offset + start);
+        if spacing >= 0.0 {
+            return spacing;
+        }
+        let advance = self.glyph_advance(grapheme, start);
+        (advance + spacing).max(0.0) - advance
+    }
+
+    /// The advance of the glyphs of `text`, which starts `start` bytes into
+    /// the measured text, each in the font it is drawn with.
+    fn glyph_advance(&self, text: &str, start: usize) -> f64 {
+        if self.fallbacks.is_empty() {
+            return self.font.text_extents(text).x_advance;
+        }
+        fallback::split(text, self.offset + start, self.fallbacks)
+            .into_iter()
+            .map(|(_, piece, fallback)| {
+                let font = fallback.map_or(self.font, |fallback| &fallback.font);
+                font.text_extents(piece).x_advance
+            })
+            .sum()
+    }
+
+    /// The total `WordSpacing` of the space separators in `text`, which starts
+    /// `start` bytes into the measured text.
+    fn attribute_spacing(&self, text: &str, start: usize) -> f64 {
+        if self.word_spacing_spans.is_empty() {
+            return 0.0;
+        }
+        text.char_indices()
+            .filter(|(_, c)| util::is_space_separator(*c))
+            .map(|(idx, _)| span_value_at(self.word_spacing_spans, self.offset + start + idx))
+            .sum()
+    }
+}
+
+/// The value of the span containing `pos`, in sorted, non-overlapping spans
+/// such as those of `util::resolve_spans`, or zero if there is none.
+fn span_value_at(spans: &[(Range<usize>, f64)], pos: usize) -> f64 {
+    spans
+        .binary_search_by(|(range, _)| {
+            if range.end <= pos {
+                Ordering::Less
+            } else if range.start > pos {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+        .map(|idx| spans[idx].1)
+        .unwrap_or_default()
+}
+
+/// Returns `true` if `font` has a glyph for each character of `text` that
+/// is drawn.
+fn covers_text(font: &ScaledFont, text: &str) -> bool {
+    let drawn: String = text.chars().filter(|c| is_drawn(*c)).collect();
+    let (glyphs, _) = font.text_to_glyphs(0.0, 0.0, &drawn);
+    glyphs.iter().all(|glyph| glyph.index != 0)
+}
+
+/// Returns `true` if `c` is drawn with a glyph, rather than only moving the pen.
+fn is_drawn(c: char) -> bool {
+    !c.is_control() && !c.is_whitespace()
+}
+
+/// The offsets from the baseline to the centers of the underline and the
+/// strikeout of a font, and their thicknesses.
+#[derive(Clone, Copy, Debug)]
+struct Decorations {
+    underline_position: f64,
+    underline_thickness: f64,
+    strikeout_position: f64,
+    strikeout_thickness: f64,
+}
+
+impl Decorations {
+    /// The decoration metrics of `font`, from its FreeType face; those the
+    /// font doesn't have are approximated from its extents.
+    fn new(font: &ScaledFont) -> Self {
+        let extents = font.extents();
+        let thickness = (extents.ascent / 14.0).max(1.0);
+        let (underline, strikeout) = fonts::decoration_metrics(font);
+        let (underline_position, underline_thickness) =
+            underline.unwrap_or((extents.descent * 0.5, thickness));
+        let (strikeout_position, strikeout_thickness) =
+            strikeout.unwrap_or((extents.ascent * -0.3, thickness));
+        Decorations {
+            underline_position,
+            underline_thickness,
+            strikeout_position,
+            strikeout_thickness,
+        }
+    }
+}
+
+/// The metrics of a font; the cap height and x-height are measured from the
+/// glyphs for 'H' and 'x'.
+fn font_metrics(font: &ScaledFont) -> FontMetrics {
+    let extents = font.extents();
+    let decorations = Decorations::new(font);
+    FontMetrics {
+        ascent: extents.ascent,
+        descent: extents.descent,
+        cap_height: -font.text_extents("H").y_bearing,
+        x_height: -font.text_extents("x").y_bearing,
+        underline_position: decorations.underline_position,
+        underline_thickness: decorations.underline_thickness,
+        strikeout_position: decorations.strikeout_position,
+        strikeout_thickness: decorations.strikeout_thickness,
+    }
+}
+
+// NOTE this is the same as the old, non-line-aware version of hit_test_point,
+// with the grapheme edges of the line measured in advance
+fn hit_test_line_point(edges: &[(usize, f64)], point: Point) -> HitTestPoint {
+    // null case: the only edge is the end of the line
+    if edges.len() < 2 {
+        return HitTestPoint::default();
+    }
+    let text_len = edges[edges.len() - 1].0;
+
+    // get bounds
+    let end = edges.len() - 2;
+    let end_bounds = match get_grapheme_boundaries(edges, end) {
+        Some(bounds) => bounds,
+        None => return HitTestPoint::default(),
+    };
+
+    let start = 0;
+    let start_bounds = match get_grapheme_boundaries(edges, start) {
+        Some(bounds) => bounds,
+        None => return HitTestPoint::default(),
+    };
+
+    // first test beyond ends
+    if point.x > end_bounds.trailing {
+        return HitTestPoint::new(text_len, false, Affinity::Upstream);
+    }
+    if point.x <= start_bounds.leading {
+        return HitTestPoint::default();
+    }
+
+    // then test the beginning and end (common cases)
+    if let Some(hit) = point_x_in_grapheme(point.x, &start_bounds) {
+        return hit;
+    }
+    if let Some(hit) = point_x_in_grapheme(point.x, &end_bounds) {
+        return hit;
+    }
+
+    // Now that we know it's not beginning or end, begin binary search.
+    // Iterative style
+    let mut left = start;
+    let mut right = end;
+    loop {
+        // pick halfway point
+        let middle = left + ((right - left) / 2);
+
+        let grapheme_bounds = match get_grapheme_boundaries(edges, middle) {
+            Some(bounds) => bounds,
+            None => return HitTestPoint::default(),
+        };
+
+        if let Some(hit) = point_x_in_grapheme(point.x, &grapheme_bounds) {
+            return hit;
+        }
+
+        // since it's not a hit, check if closer to start or finish
+        // and move the appropriate search boundary
+        if point.x < grapheme_bounds.leading {
+            right = middle;
+        } else if point.x > grapheme_bounds.trailing {
+            left = middle + 1;
+        } else {
+            unreachable!("hit_test_point conditional is exhaustive");
+        }
+    }
+}
+
+// NOTE this is the same as the old, non-line-aware version of hit_test_text_position,
+// with the grapheme edges of the line measured in advance
+fn hit_test_line_position(edges: &[(usize, f64)], text_position: usize) -> Option<f64> {
+    // If text position is not at a grapheme boundary, use the text position of current
+    // grapheme cluster; positions past the end are at the end of the line.
+    let idx = edges.partition_point(|(byte_idx, _)| *byte_idx <= text_position);
+    edges.get(idx.checked_sub(1)?).map(|(_, x)| *x)
+}
+
+fn scale_matrix(scale: f64) -> Matrix {
+    Matrix {
+        xx: scale,
+        yx: 0.0,
+        xy: 0.0,
+        yy: scale,
+        x0: 0.0,
+        y0: 0.0,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::grapheme::GraphemeBoundaries;
+    use super::*;
+    use piet::{FontFamilyList, TextLayout};
+
+    macro_rules! assert_close {
+        ($val:expr, $target:expr, $tolerance:expr) => {{
+            let min = $target - $tolerance;
+            let max = $target + $tolerance;
+            if $val < min || $val > max {
+                panic!(
+                    "value {} outside target {} with tolerance {}",
+                    $val, $target, $tolerance
+                );
+            }
+        }};
+
+        ($val:expr, $target:expr, $tolerance:expr,) => {{
+            assert_close!($val, $target, $tolerance)
+        }};
+    }
+
+    #[test]
+    fn test_letter_spacing() {
+        let mut text_layout = CairoText::new();
+
+        let input = "piet text!";
+        let layout = text_layout.new_text_layout(input).build().unwrap();
+        let spaced = text_layout
+            .new_text_layout(input)
+            .default_attribute(TextAttribute::LetterSpacing(2.0))
+            .build()
+            .unwrap();
+
+        // the spacing goes between graphemes, not after the last one
+        assert_close!(spaced.size().width, layout.size().width + 18.0, 0.01);
+        assert_close!(
+            spaced.hit_test_text_position(4).unwrap().point.x,
+            layout.hit_test_text_position(4).unwrap().point.x + 8.0,
+            0.01,
+        );
+        assert_close!(
+            spaced.hit_test_text_position(input.len()).unwrap().point.x,
+            spaced.size().width,
+            0.01,
+        );
+
+        let pos = spaced.hit_test_text_position(4).unwrap().point;
+        assert_eq!(spaced.hit_test_point(Point::new(pos.x + 0.5, 0.0)).idx, 4);
+    }
+
+    #[test]
+    fn test_range_letter_spacing() {
+        let mut text_layout = CairoText::new();
+
+        let input = "piet text!";
+        let layout = text_layout.new_text_layout(input).build().unwrap();
+        let caret = |layout: &CairoTextLayout, idx: usize| {
+            layout.hit_test_text_position(idx).unwrap().point.x
+        };
+        let spaced = text_layout
+            .new_text_layout(input)
+            .range_attribute(..4, TextAttribute::LetterSpacing(2.0))
+            .build()
+            .unwrap();
+
+        // only the graphemes of "piet" are followed by spacing
+        assert_close!(spaced.size().width, layout.size().width + 8.0, 0.01);
+        assert_close!(caret(&spaced, 2), caret(&layout, 2) + 4.0, 0.01);
+        assert_close!(caret(&spaced, 4), caret(&layout, 4) + 8.0, 0.01);
+        assert_close!(caret(&spaced, 7), caret(&layout, 7) + 8.0, 0.01);
+        // and the graphemes are drawn where hit testing puts them
+        let runs = spaced.line_runs(0);
+        for (origin, pos, _, _) in runs {
+            assert_close!(origin.x, caret(&spaced, pos), 0.01);
+        }
+
+        // a range can override the default, and there's still no spacing
+        // after the last grapheme
+        let spaced = text_layout
+            .new_text_layout(input)
+            .default_attribute(TextAttribute::LetterSpacing(2.0))
+            .range_attribute(4.., TextAttribute::LetterSpacing(0.0))
+            .build()
+            .unwrap();
+        assert_close!(spaced.size().width, layout.size().width + 8.0, 0.01);
+        let spaced = text_layout
+            .new_text_layout(input)
+            .range_attribute(9.., TextAttribute::LetterSpacing(2.0))
+            .build()
+            .unwrap();
+        assert_close!(spaced.size().width, layout.size().width, 0.01);
+    }
+
+    #[test]
+    fn test_letter_spacing_width() {
+        let mut text_layout = CairoText::new();
+        let mut width = |spacing: f64| {
+            let layout = text_layout
+                .new_text_layout("AVATAR")
+                .default_attribute(TextAttribute::LetterSpacing(spacing))
+                .build()
+                .unwrap();
+            layout.size().width
+        };
+
+        let tight = width(0.0);
+        assert!(tight > 0.0);
+        assert_close!(width(2.0), tight + 10.0, 0.01);
+        assert_close!(width(-2.0), tight - 10.0, 0.01);
+    }
+
+    #[test]
+    fn test_letter_spacing_wrapping() {
+        let mut text_layout = CairoText::new();
+        let plain = text_layout.new_text_layout("AVATAR").build().unwrap();
+        let max_width = plain.size().width * 2.0 + 10.0;
+        let mut lines = |spacing: f64| {
+            text_layout
+                .new_text_layout("AVATAR AVATAR")
+                .default_attribute(TextAttribute::LetterSpacing(spacing))
+                .max_width(max_width)
+                .build()
+                .unwrap()
+                .line_count()
+        };
+
+        assert_eq!(lines(0.0), 1);
+        assert_eq!(lines(2.0), 2);
+    }
+
+    #[test]
+    fn test_negative_letter_spacing() {
+        let mut text_layout = CairoText::new();
+        let layout = text_layout
+            .new_text_layout("AVATAR")
+            .default_attribute(TextAttribute::LetterSpacing(-1000.0))
+            .build()
+            .unwrap();
+
+        // every grapheme is tightened to nothing, except the last, which
+        // keeps its advance
+        let last = text_layout.new_text_layout("R").build().unwrap();
+        assert_close!(layout.size().width, last.size().width, 0.01);
+        let carets: Vec<f64> = (0..=6)
+            .map(|idx| layout.hit_test_text_position(idx).unwrap().point.x)
+            .collect();
+        assert!(carets.iter().all(|x| *x >= 0.0));
+        assert!(carets.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn test_line_spacing() {
+        let mut text_layout = CairoText::new();
+
+        let input = "piet\ntext";
+        let layout = text_layout.new_text_layout(input).build().unwrap();
+        let spaced = text_layout
+            .new_text_layout(input)
+            .line_spacing(LineSpacing::Multiple(2.0))
+            .build()
+            .unwrap();
+
+        let line = layout.line_metric(1).unwrap();
+        let spaced_line = spaced.line_metric(1).unwrap();
+        assert_close!(spaced_line.height, line.height * 2.0, 0.01);
+        assert_close!(spaced_line.y_offset, line.y_offset * 2.0, 0.01);
+        assert_close!(
+            spaced_line.baseline,
+            line.baseline + line.height / 2.0,
+            0.01
+        );
+        assert_close!(spaced.size().height, layout.size().height * 2.0, 0.01);
+
+        let exact = text_layout
+            .new_text_layout(input)
+            .line_spacing(LineSpacing::Exact(30.0))
+            .build()
+            .unwrap();
+        assert_close!(exact.line_metric(1).unwrap().y_offset, 30.0, 0.01);
+        assert_close!(exact.size().height, 60.0, 0.01);
+    }
+
+    #[test]
+    fn test_max_lines() {
+        let mut text_layout = CairoText::new();
+
+        let input = "piet text is the best text, and this is a lot of it";
+        let layout = text_layout
+            .new_text_layout(input)
+            .max_width(80.0)
+            .max_lines(2)
+            .build()
+            .unwrap();
+        assert_eq!(layout.line_count(), 2);
+        let last = layout.line_metric(1).unwrap();
+        let end_x = layout
+            .hit_test_text_position(last.end_offset)
+            .unwrap()
+            .point
+            .x;
+        assert_close!(
+            layout.hit_test_text_position(input.len()).unwrap().point.x,
+            end_x,
+            0.01,
+        );
+        let point = Point::new(200.0, last.y_offset + 1.0);
+        assert_eq!(layout.hit_test_point(point).idx, last.end_offset);
+
+        let ellipsized = text_layout
+            .new_text_layout(input)
+            .max_width(80.0)
+            .max_lines(2)
+            .truncate(TruncationMode::EllipsisEnd)
+            .build()
+            .unwrap();
+        assert_eq!(ellipsized.line_count(), 2);
+        let last = ellipsized.line_metric(1).unwrap();
+        assert!(last.end_offset < layout.line_metric(1).unwrap().end_offset);
+        assert!(ellipsized.size().width <= 80.0);
+        let point = Point::new(200.0, last.y_offset + 1.0);
+        assert_eq!(ellipsized.hit_test_point(point).idx, last.end_offset);
+    }
+
+    #[test]
+    fn test_background_color() {
+        let mut text_layout = CairoText::new();
+
+        let input = "piet text is the best text, and this is a lot of it";
+        let layout = text_layout
+            .new_text_layout(input)
+            .max_width(120.0)
+            .range_attribute(5..20, TextAttribute::BackgroundColor(Color::WHITE))
+            .build()
+            .unwrap();
+        let line_0 = layout.line_metric(0).unwrap();
+        let line_1 = layout.line_metric(1).unwrap();
+        assert!(line_0.end_offset > 5 && line_0.end_offset < 20);
+
+        let rects = layout.background_rects();
+        assert_eq!(rects.len(), 2);
+        let (first, second) = (rects[0].0, rects[1].0);
+        assert_close!(
+            first.x0,
+            layout.hit_test_text_position(5).unwrap().point.x,
+            0.01
+        );
+        assert_close!(first.y0, line_0.y_offset, 0.01);
+        assert_close!(first.y1, line_0.y_offset + line_0.height, 0.01);
+        assert_close!(second.x0, 0.0, 0.01);
+        assert_close!(second.y0, line_1.y_offset, 0.01);
+        assert_close!(
+            second.x1,
+            layout.hit_test_text_position(20).unwrap().point.x,
+            0.01
+        );
+    }
+
+    #[test]
+    fn test_shadow() {
+        use crate::CairoRenderContext;
+        use piet::RenderContext;
+
+        let mut text_layout = CairoText::new();
+        let shadow = ShadowStyle::new((0.0, 30.0), 1.0, Color::BLACK);
+        let layout = text_layout
+            .new_text_layout("piet text")
+            .font(FontFamily::SANS_SERIF, 20.0)
+            .range_attribute(5.., shadow)
+            .build()
+            .unwrap();
+        let plain = text_layout
+            .new_text_layout("piet text")
+            .font(FontFamily::SANS_SERIF, 20.0)
+            .build()
+            .unwrap();
+        assert_close!(
+            layout.image_bounds().y1,
+            plain.image_bounds().y1 + 30.0 + 2.5,
+            0.01
+        );
+
+        let mut surface = ImageSurface::create(Format::ARgb32, 120, 80).unwrap();
+        {
+            let cr = Context::new(&surface);
+            let mut piet = CairoRenderContext::new(&cr);
+            piet.clear(Color::WHITE);
+            piet.draw_text(&layout, (0.0, 0.0));
+            piet.finish().unwrap();
+        }
+        surface.flush();
+        let data = surface.get_data().unwrap();
+        let stride = 120 * 4;
+        let split = layout.hit_test_text_position(5).unwrap().point.x as usize;
+        // counts the dark pixels below the text, in columns `x0..x1`
+        let shadow_pixels = |x0: usize, x1: usize| {
+            (30..80)
+                .flat_map(|y| (x0..x1).map(move |x| y * stride + x * 4))
+                .filter(|&i| data[i] < 128)
+                .count()
+        };
+        assert_eq!(shadow_pixels(0, split - 1), 0);
+        assert!(shadow_pixels(split, 120) > 20);
+    }
+
+    #[test]
+    fn test_baseline_shift() {
+        let mut text_layout = CairoText::new();
+
+        let input = "E = mc2";
+        let layout = text_layout.new_text_layout(input).build().unwrap();
+        let shifted = text_layout
+            .new_text_layout(input)
+            .range_attribute(6..7, TextAttribute::BaselineShift(4.0))
+            .build()
+            .unwrap();
+
+        assert_eq!(shifted.line_metric(0), layout.line_metric(0));
+        assert_eq!(shifted.line_segments(0), vec![(0..6, 0.0), (6..7, 4.0)]);
+        assert_close!(shifted.image_bounds().y0, -4.0, 0.01);
+        assert_close!(shifted.image_bounds().y1, layout.image_bounds().y1, 0.01);
+
+        // decorations are split into runs, following the shifted text
+        let underlined = text_layout
+            .new_text_layout(input)
+            .default_attribute(TextAttribute::Underline(true))
+            .range_attribute(6..7, TextAttribute::BaselineShift(4.0))
+            .build()
+            .unwrap();
+        let paths = underlined.underline_paths();
+        assert_eq!(paths.len(), 2);
+        let bounds = |idx: usize| piet::kurbo::Shape::bounding_box(&paths[idx].0);
+        assert_close!(bounds(1).y0, bounds(0).y0 - 4.0, 0.01);
+        assert_close!(bounds(0).x1, bounds(1).x0, 0.01);
+    }
+
+    #[test]
+    fn test_tab_stops() {
+        let mut text_layout = CairoText::new();
+
+        let layout = text_layout
+            .new_text_layout("a\tb")
+            .tab_stops(TabStops::Uniform(50.0))
+            .build()
+            .unwrap();
+        let a_width = layout.hit_test_text_position(1).unwrap().point.x;
+        assert_close!(
+            layout.hit_test_text_position(2).unwrap().point.x,
+            50.0,
+            0.01
+        );
+        let runs = layout.line_runs(0);
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[1].1, 2);
+        assert_eq!(runs[1].2, "b");
+        assert_close!(runs[1].0.x, 50.0, 0.01);
+
+        // the tab is a single grapheme spanning the whole gap
+        assert_eq!(layout.hit_test_point(Point::new(a_width + 1.0, 0.0)).idx, 1);
+        assert_eq!(layout.hit_test_point(Point::new(49.0, 0.0)).idx, 2);
+
+        let layout = text_layout
+            .new_text_layout("a\tb\tc\td")
+            .tab_stops(TabStops::Explicit(vec![30.0, 100.0]))
+            .build()
+            .unwrap();
+        assert_close!(
+            layout.hit_test_text_position(2).unwrap().point.x,
+            30.0,
+            0.01
+        );
+        assert_close!(
+            layout.hit_test_text_position(4).unwrap().point.x,
+            100.0,
+            0.01
+        );
+        // past the last stop, stops continue at the last interval
+        assert_close!(
+            layout.hit_test_text_position(6).unwrap().point.x,
+            170.0,
+            0.01
+        );
+    }
+
+    #[test]
+    fn test_word_spacing() {
+        let mut text_layout = CairoText::new();
+
+        let input = "piet text is";
+        let layout = text_layout.new_text_layout(input).build().unwrap();
+        let spaced = text_layout
+            .new_text_layout(input)
+            .default_attribute(TextAttribute::WordSpacing(5.0))
+            .build()
+            .unwrap();
+        assert_close!(spaced.size().width, layout.size().width + 10.0, 0.01);
+        let x = |layout: &CairoTextLayout, pos| layout.hit_test_text_position(pos).unwrap().point.x;
+        assert_close!(x(&spaced, 5), x(&layout, 5) + 5.0, 0.01);
+
+        let ranged = text_layout
+            .new_text_layout(input)
+            .range_attribute(0..6, TextAttribute::WordSpacing(5.0))
+            .build()
+            .unwrap();
+        assert_close!(ranged.size().width, layout.size().width + 5.0, 0.01);
+        assert_close!(x(&ranged, 10), x(&layout, 10) + 5.0, 0.01);
+
+        // the spacing of a space at a line break is absorbed by the break
+        let wrapped = text_layout
+            .new_text_layout("piet text")
+            .max_width(60.0)
+            .default_attribute(TextAttribute::WordSpacing(100.0))
+            .build()
+            .unwrap();
+        assert_eq!(wrapped.line_count(), 2);
+        assert!(wrapped.size().width < 60.0);
+
+        let justified = text_layout
+            .new_text_layout("piet text is the best text, and this is a lot of it")
+            .max_width(120.0)
+            .alignment(TextAlignment::Justified)
+            .default_attribute(TextAttribute::WordSpacing(2.0))
+            .build()
+            .unwrap();
+        assert_close!(justified.size().width, 120.0, 0.01);
+    }
+
+    #[test]
+    fn test_trailing_whitespace_width() {
+        let mut text_layout = CairoText::new();
+
+        let layout = text_layout.new_text_layout("hello   ").build().unwrap();
+        let x = |pos| layout.hit_test_text_position(pos).unwrap().point.x;
+        let line = layout.line_metric(0).unwrap();
+        assert_eq!(line.trailing_whitespace, 3);
+        assert!(line.trailing_whitespace_width > 0.0);
+        assert_close!(line.trailing_whitespace_width, x(8) - x(5), 0.01);
+        let spaces = line.trailing_whitespace_width;
+
+        let wrapped = text_layout
+            .new_text_layout("hello   world")
+            .max_width(40.0)
+            .build()
+            .unwrap();
+        assert_eq!(wrapped.line_count(), 2);
+        let line_0 = wrapped.line_metric(0).unwrap();
+        assert_eq!(line_0.trailing_whitespace, 3);
+        assert_close!(line_0.trailing_whitespace_width, spaces, 0.01);
+        assert_eq!(
+            wrapped.line_metric(1).unwrap().trailing_whitespace_width,
+            0.0
+        );
+
+        // a newline doesn't add to the width
+        let layout = text_layout
+            .new_text_layout("hello   \nworld")
+            .build()
+            .unwrap();
+        let line_0 = layout.line_metric(0).unwrap();
+        assert_close!(line_0.trailing_whitespace_width, spaces, 0.01);
+    }
+
+    #[test]
+    fn test_glyph_runs() {
+        let mut text_layout = CairoText::new();
+
+        let layout = text_layout
+            .new_text_layout("piet text\nis the best")
+            .font(FontFamily::SANS_SERIF, 16.0)
+            .build()
+            .unwrap();
+        let runs = layout.glyph_runs();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].font, FontFamily::SANS_SERIF);
+        assert_eq!(runs[0].font_size, 16.0);
+
+        for (line, run) in runs.iter().enumerate() {
+            let lm = layout.line_metric(line).unwrap();
+            let baseline = lm.y_offset + lm.baseline;
+            assert_eq!(run.glyphs[0].cluster, lm.start_offset);
+            assert_close!(run.glyphs[0].position.x, 0.0, 0.01);
+            for pair in run.glyphs.windows(2) {
+                assert!(pair[0].cluster < pair[1].cluster);
+                assert!(pair[0].position.x < pair[1].position.x);
+            }
+            for glyph in &run.glyphs {
+                assert_close!(glyph.position.y, baseline, 0.01);
+                assert!(glyph.cluster < lm.end_offset);
+            }
+        }
+        // 'e' is drawn with the same glyph each time
+        let e = |idx| runs[0].glyphs.iter().find(|g| g.cluster == idx).unwrap().id;
+        assert_eq!(e(2), e(6));
+        assert_ne!(e(0), e(2));
+
+        // the runs are made again when the layout is rewrapped
+        let mut layout = layout;
+        layout.update_width(40.0).unwrap();
+        assert!(layout.line_count() > 2);
+        assert_eq!(layout.glyph_runs().len(), layout.line_count());
+    }
+
+    #[test]
+    fn test_outline() {
+        use piet::kurbo::Shape;
+        let mut text_layout = CairoText::new();
+
+        let layout = text_layout
+            .new_text_layout("piet text\nis the best")
+            .font(FontFamily::SANS_SERIF, 16.0)
+            .build()
+            .unwrap();
+        let outline = layout.outline();
+        assert!(!outline.elements().is_empty());
+        // the outlines are inside the layout's inked area
+        let bounds = outline.bounding_box();
+        let image_bounds = layout.image_bounds().inflate(1.0, 1.0);
+        assert_eq!(image_bounds.union(bounds), image_bounds);
+        // and reach the second line
+        assert!(bounds.y1 > layout.line_metric(1).unwrap().y_offset);
+
+        let empty = text_layout.new_text_layout("").build().unwrap();
+        assert!(empty.outline().elements().is_empty());
+    }
+
+    #[test]
+    fn test_shared_text_storage() {
+        let mut text_layout = CairoText::new();
+
+        let text: std::sync::Arc<str> = "piet text is the best text".into();
+        let layout = text_layout
+            .new_text_layout(text.clone())
+            .max_width(60.0)
+            .build()
+            .unwrap();
+        // the layout holds on to the text, rather than a copy of it
+        assert_eq!(layout.text().as_ptr(), text.as_ptr());
+        assert_eq!(layout.text(), &*text);
+
+        let owned = String::from("piet text");
+        let layout = text_layout.new_text_layout(owned).build().unwrap();
+        assert_eq!(layout.text(), "piet text");
+        assert_eq!(layout.line_text(0), Some("piet text"));
+    }
+
+    #[test]
+    fn test_font_metrics() {
+        let mut text = CairoText::new();
+        let metrics = text.font_metrics(&FontFamily::SANS_SERIF, 20.0).unwrap();
+        assert!(metrics.ascent > metrics.cap_height);
+        assert!(metrics.cap_height > metrics.x_height);
+        assert!(metrics.x_height > 0.0);
+        assert!(metrics.descent > 0.0);
+        assert!(metrics.underline_position > 0.0);
+        assert!(metrics.strikeout_position < 0.0);
+        assert!(metrics.underline_thickness > 0.0);
+
+        // metrics grow with the font size; glyph extents are hinted, so
+        // they don't scale exactly.
+        let small = text.font_metrics(&FontFamily::SANS_SERIF, 10.0).unwrap();
+        assert!(small.cap_height < metrics.cap_height);
+        assert_close!(small.ascent * 2.0, metrics.ascent, 1.0);
+
+        // and match the layout's baseline
+        let layout = text
+            .new_text_layout("Hx")
+            .font(FontFamily::SANS_SERIF, 20.0)
+            .build()
+            .unwrap();
+        let lm = layout.line_metric(0).unwrap();
+        assert_close!(lm.baseline, metrics.ascent, 0.01);
+    }
+
+    #[test]
+    fn test_break_opportunities() {
+        let mut text = CairoText::new();
+        let path = "/usr/local/share/fonts/truetype/dejavu-sans-mono";
+        let after_slashes = |text: &str| text.match_indices('/').map(|(i, _)| i + 1).collect();
+        let layout = text
+            .new_text_layout(path)
+            .max_width(80.0)
+            .break_opportunities(after_slashes)
+            .build()
+            .unwrap();
+        assert!(layout.line_count() > 2);
+        for line in 0..layout.line_count() - 1 {
+            assert!(layout.line_text(line).unwrap().ends_with('/'));
+        }
+        assert_eq!(
+            layout.line_text(layout.line_count() - 1),
+            Some("dejavu-sans-mono")
+        );
+
+        // with no opportunities, only mandatory breaks are taken
+        let layout = text
+            .new_text_layout("a long line of text\nand another")
+            .max_width(20.0)
+            .break_opportunities(|_| Vec::new())
+            .build()
+            .unwrap();
+        assert_eq!(layout.line_count(), 2);
+        assert_eq!(layout.line_text(1), Some("and another"));
+
+        // and the layout keeps them when it is rewrapped
+        let mut layout = text
+            .new_text_layout(path)
+            .break_opportunities(after_slashes)
+            .build()
+            .unwrap();
+        assert_eq!(layout.line_count(), 1);
+        layout.update_width(80.0).unwrap();
+        assert!(layout.line_count() > 2);
+        assert!(layout.line_text(0).unwrap().ends_with('/'));
+    }
+
+    #[test]
+    #[cfg(feature = "send-layouts")]
+    fn test_layout_from_another_thread() {
+        let layout = std::thread::spawn(|| {
+            let mut text = CairoText::new();
+            text.new_text_layout("sent between threads")
+                .font(FontFamily::SANS_SERIF, 16.0)
+                .build()
+                .unwrap()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(layout.line_count(), 1);
+
+        use piet::RenderContext;
+        let mut surface = ImageSurface::create(Format::ARgb32, 200, 40).unwrap();
+        {
+            let cairo_ctx = Context::new(&surface);
+            let mut piet = crate::CairoRenderContext::new(&cairo_ctx);
+            piet.draw_text(&layout, (0.0, 0.0));
+            piet.finish().unwrap();
+        }
+        surface.flush();
+        let data = surface.get_data().unwrap();
+        assert!(data.chunks(4).any(|px| px[3] != 0));
+    }
+
+    #[test]
+    fn test_vertical_not_supported() {
+        let mut text = CairoText::new();
+        let layout = text
+            .new_text_layout("縦書き")
+            .orientation(TextOrientation::VerticalRightToLeft)
+            .build();
+        assert!(matches!(layout, Err(Error::NotSupported)));
+    }
+
+    #[test]
+    fn test_load_font_from_path() {
+        let mut text = CairoText::new();
+        let missing = text.load_font_from_path(std::path::Path::new("/no/such/font.ttf"));
+        assert!(matches!(missing, Err(Error::BackendError(_))));
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+        let not_a_font = text.load_font_from_path(&path);
+        assert!(matches!(not_a_font, Err(Error::MissingFont)));
     }
 
-    fn line_metric(&self, line_number: usize) -> Option<LineMetric> {
-        self.line_metrics.get(line_number).cloned()
+    fn be16(out: &mut Vec<u8>, values: &[u16]) {
+        for x in values {
+            out.extend_from_slice(&x.to_be_bytes());
+        }
     }
 
-    fn line_count(&self) -> usize {
-        self.line_metrics.len()
+    /// A TrueType font with one glyph, a square for 'A', 600 units wide.
+    pub(crate) fn test_font(family: &str) -> Vec<u8> {
+        test_font_with_tables(family, Vec::new())
     }
 
-    fn hit_test_point(&self, point: Point) -> HitTestPoint {
-        // internal logic is using grapheme clusters, but return the text position associated
-        // with the border of the grapheme cluster.
+    /// The same font as `test_font`, with 'A' drawn in red from `COLR` and
+    /// `CPAL` tables.
+    pub(crate) fn test_color_font(family: &str) -> Vec<u8> {
+        // one base glyph, 'A', with one layer, itself in palette entry 0
+        let mut colr = Vec::new();
+        be16(&mut colr, &[0, 1, 0, 14, 0, 20, 1, 1, 0, 1, 1, 0]);
+        let mut cpal = Vec::new();
+        be16(&mut cpal, &[0, 1, 1, 1, 0, 14, 0]);
+        // blue, green, red, alpha
+        cpal.extend_from_slice(&[0, 0, 255, 255]);
+        test_font_with_tables(family, vec![(b"COLR", colr), (b"CPAL", cpal)])
+    }
 
-        // null case
-        if self.text.is_empty() {
-            return HitTestPoint::default();
+    /// The test font, with `extra` tables, which sort before the others.
+    fn test_font_with_tables(family: &str, extra: Vec<(&[u8; 4], Vec<u8>)>) -> Vec<u8> {
+        let mut head = vec![0; 54];
+        head[12..16].copy_from_slice(&0x5F0F_3CF5u32.to_be_bytes());
+        head[18..20].copy_from_slice(&1000u16.to_be_bytes());
+        let mut hhea = vec![0; 36];
+        hhea[4..6].copy_from_slice(&800i16.to_be_bytes());
+        hhea[6..8].copy_from_slice(&(-200i16).to_be_bytes());
+        hhea[34..36].copy_from_slice(&2u16.to_be_bytes());
+        let hmtx = vec![2, 0x58, 0, 0, 2, 0x58, 0, 0];
+        let maxp = vec![0, 0, 0x50, 0, 0, 2];
+        let name_bytes: Vec<u8> = family.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        let mut name = Vec::new();
+        be16(
+            &mut name,
+            &[0, 1, 18, 3, 1, 0x409, 1, name_bytes.len() as u16, 0],
+        );
+        name.extend_from_slice(&name_bytes);
+        // a format 12 subtable with one group, for 'A'
+        let mut cmap = Vec::new();
+        be16(
+            &mut cmap,
+            &[
+                0, 1, 3, 10, 0, 12, 12, 0, 0, 28, 0, 0, 0, 1, 0, 0x41, 0, 0x41, 0, 1,
+            ],
+        );
+        // one contour of four points on the curve
+        let mut glyf = Vec::new();
+        be16(
+            &mut glyf,
+            &[1, 0, 0, 0, 0, 3, 0, 0x101, 0x101, 0, 500, 0, -500i16 as u16],
+        );
+        be16(&mut glyf, &[0, 0, 700, 0]);
+        let mut loca = Vec::new();
+        be16(&mut loca, &[0, 0, 17]);
+
+        let mut tables = extra;
+        tables.extend(vec![
+            (b"cmap", cmap),
+            (b"glyf", glyf),
+            (b"head", head),
+            (b"hhea", hhea),
+            (b"hmtx", hmtx),
+            (b"loca", loca),
+            (b"maxp", maxp),
+            (b"name", name),
+        ]);
+        let mut font = Vec::new();
+        be16(&mut font, &[1, 0, tables.len() as u16, 0, 0, 0]);
+        let mut offset = 12 + 16 * tables.len();
+        for (tag, table) in &tables {
+            font.extend_from_slice(&tag[..]);
+            font.extend_from_slice(&[0; 4]);
+            font.extend_from_slice(&(offset as u32).to_be_bytes());
+            font.extend_from_slice(&(table.len() as u32).to_be_bytes());
+            offset += table.len();
+        }
+        for (_, table) in &tables {
+            font.extend_from_slice(table);
         }
+        font
+    }
 
-        let height = self
-            .line_metrics
-            .last()
-            .map(|lm| lm.y_offset + lm.height)
-            .unwrap_or(0.0);
+    #[test]
+    fn test_load_font() {
+        let mut text = CairoText::new();
+        let family = text.load_font(&test_font("Piet Cairo Test")).unwrap();
+        assert_eq!(family.name(), "Piet Cairo Test");
 
-        // determine whether this click is within the y bounds of the layout,
-        // and what line it coorresponds to. (For points above and below the layout,
-        // we hittest the first and last lines respectively.)
-        let (y_inside, lm) = if point.y < 0. {
-            (false, self.line_metrics.first().unwrap())
-        } else if point.y >= height {
-            (false, self.line_metrics.last().unwrap())
-        } else {
-            let line = self
-                .line_metrics
-                .iter()
-                .find(|l| point.y >= l.y_offset && point.y < l.y_offset + l.height)
-                .unwrap();
-            (true, line)
-        };
+        // the loaded face is used for the family, as a default attribute or
+        // as a range attribute over all of the text
+        let layout = text
+            .new_text_layout("AA")
+            .font(family.clone(), 10.0)
+            .build()
+            .unwrap();
+        assert_close!(layout.size().width, 12.0, 0.01);
+        let layout = text
+            .new_text_layout("AA")
+            .default_attribute(TextAttribute::Size(10.0))
+            .range_attribute(.., family.clone())
+            .build()
+            .unwrap();
+        assert_close!(layout.size().width, 12.0, 0.01);
+        let found = text.font_family("piet cairo test").unwrap();
+        let metrics = text.font_metrics(&found, 10.0).unwrap();
+        assert_close!(metrics.ascent, 8.0, 0.01);
+        assert_close!(metrics.descent, 2.0, 0.01);
+
+        assert!(matches!(
+            text.load_font(b"not a font"),
+            Err(Error::MissingFont)
+        ));
+        assert!(matches!(text.load_font(&[]), Err(Error::MissingFont)));
+    }
 
-        // Trailing whitespace is remove for the line
-        let line = &self.text[lm.range()];
+    #[test]
+    fn test_cluster_fallback() {
+        let mut text = CairoText::new();
+        // the test font only has a glyph for 'A'
+        let family = text.load_font(&test_font("Piet Cairo Fallback")).unwrap();
+        let layout = text
+            .new_text_layout("AxA")
+            .font(family.clone(), 10.0)
+            .build()
+            .unwrap();
+        assert_eq!(layout.fallbacks.len(), 1);
+        assert_eq!(layout.fallbacks[0].range, 1..2);
+        let x_width = layout.fallbacks[0].font.text_extents("x").x_advance;
+        assert!(x_width > 0.0);
+        assert_close!(layout.size().width, 12.0 + x_width, 0.01);
+
+        // hit testing measures each cluster in the font it is drawn with
+        let after_x = 6.0 + x_width;
+        assert_close!(
+            layout.hit_test_text_position(2).unwrap().point.x,
+            after_x,
+            0.01
+        );
+        assert_eq!(layout.hit_test_point(Point::new(after_x + 1.0, 0.0)).idx, 2);
+        assert_eq!(layout.hit_test_point(Point::new(after_x - 1.0, 0.0)).idx, 2);
+
+        // and the glyph runs are split where the font changes
+        let runs = layout.glyph_runs();
+        assert_eq!(runs.len(), 3);
+        assert_eq!(runs[0].font, family);
+        assert_eq!(runs[1].font, layout.fallbacks[0].family);
+        assert_eq!(runs[1].glyphs[0].cluster, 1);
+        assert_close!(runs[2].glyphs[0].position.x, after_x, 0.01);
+
+        // the layout's other families are tried first, then fontconfig
+        let serif = text.font_family("DejaVu Serif").unwrap();
+        let arabic = "\u{627}";
+        let layout = text
+            .new_text_layout(format!("Ax{}", arabic))
+            .default_attribute(FontFamilyList::new(family).with(serif.clone()))
+            .build()
+            .unwrap();
+        assert_eq!(layout.fallbacks[0].range, 1..2);
+        assert_eq!(layout.fallbacks[0].family, serif);
+        if !covers_text(&CairoFont::new(serif).resolve_simple(12.0), arabic) {
+            let run = &layout.fallbacks[1];
+            assert_eq!(run.range, 2..4);
+            assert!(covers_text(&run.font, arabic));
+        }
+    }
 
-        let mut htp = hit_test_line_point(&self.font, line, point);
-        htp.idx += lm.start_offset;
-        htp.is_inside &= y_inside;
-        htp
+    #[test]
+    fn test_font_families() {
+        let mut text = CairoText::new();
+        let families = text.font_families();
+        assert!(!families.is_empty());
+        for family in families {
+            let found = text.font_family(family.name()).unwrap();
+            assert_eq!(found.name(), family.name());
+        }
     }
 
-    fn hit_test_text_position(&self, text_position: usize) -> Option<HitTestPosition> {
-        // first need to find line it's on, and get line start offset
-        let line_num = util::line_number_for_position(&self.line_metrics, text_position);
-        let lm = self.line_metrics.get(line_num).cloned().unwrap();
+    #[test]
+    fn test_font_fallbacks() {
+        let mut text = CairoText::new();
+        let serif = text.font_family("DejaVu Serif").unwrap();
+        let sans = text.font_family("DejaVu Sans").unwrap();
+        let families = FontFamilyList::new(serif.clone()).with(sans.clone());
 
-        let y_pos = lm.y_offset + lm.baseline;
+        // text the primary family covers is drawn with it
+        let layout = text
+            .new_text_layout("hello")
+            .default_attribute(families.clone())
+            .build()
+            .unwrap();
+        assert_eq!(layout.font_family, serif);
 
-        // Then for the line, do text position
-        // Trailing whitespace is removed for the line
-        let line = &self.text[lm.range()];
-        let line_position = text_position - lm.start_offset;
+        // as is text that no family covers
+        let layout = text
+            .new_text_layout("hello \u{10FFFD}")
+            .default_attribute(families.clone())
+            .build()
+            .unwrap();
+        assert_eq!(layout.font_family, serif);
+
+        // otherwise the first fallback that covers it is used
+        let arabic = "hello \u{627}";
+        let resolve = |family: &FontFamily| CairoFont::new(family.clone()).resolve_simple(12.0);
+        let expected = if covers_text(&resolve(&serif), arabic) {
+            &serif
+        } else if covers_text(&resolve(&sans), arabic) {
+            &sans
+        } else {
+            &serif
+        };
+        let layout = text
+            .new_text_layout(arabic)
+            .default_attribute(families)
+            .build()
+            .unwrap();
+        assert_eq!(&layout.font_family, expected);
 
-        hit_test_line_position(&self.font, line, line_position)
-            .map(|x_pos| HitTestPosition::new(Point::new(x_pos, y_pos), line_num))
+        // and a single family replaces the list
+        let layout = text
+            .new_text_layout(arabic)
+            .default_attribute(FontFamilyList::new(serif.clone()).with(sans))
+            .font(serif.clone(), 12.0)
+            .build()
+            .unwrap();
+        assert_eq!(layout.font_family, serif);
     }
-}
 
-// NOTE this is the same as the old, non-line-aware version of hit_test_point
-// Future: instead of passing Font, should there be some other line-level text layout?
-fn hit_test_line_point(font: &ScaledFont, text: &str, point: Point) -> HitTestPoint {
-    // null case
-    if text.is_empty() {
-        return HitTestPoint::default();
+    #[test]
+    fn test_grapheme_edges() {
+        // the edges cached for hit testing are where measuring each line up
+        // to each grapheme puts it
+        let mut text = CairoText::new();
+        let layouts = vec![
+            text.new_text_layout("piet text is the best text\nmore e\u{301}")
+                .max_width(60.0)
+                .build()
+                .unwrap(),
+            text.new_text_layout("a\tb\tc")
+                .tab_stops(TabStops::Uniform(50.0))
+                .build()
+                .unwrap(),
+            text.new_text_layout("spaced out")
+                .default_attribute(TextAttribute::LetterSpacing(2.0))
+                .build()
+                .unwrap(),
+            text.new_text_layout("the quick brown fox jumps over")
+                .alignment(TextAlignment::Justified)
+                .max_width(80.0)
+                .build()
+                .unwrap(),
+        ];
+        for layout in &layouts {
+            for (line_number, edges) in layout.grapheme_edges.iter().enumerate() {
+                let line = layout.line_text(line_number).unwrap();
+                let measure = layout.line_measure(line_number);
+                let content_end = line.trim_end_matches(is_hard_break).len();
+                assert_eq!(edges.last().unwrap().0, line.len());
+                for &(idx, x) in edges {
+                    let advance = if idx < content_end {
+                        measure.advance(&line[..idx])
+                    } else {
+                        measure.line_advance(&line[..idx])
+                    };
+                    assert_close!(x, advance, 1e-9);
+                }
+            }
+        }
     }
 
-    // get bounds
-    // TODO handle if string is not null yet count is 0?
-    let end = UnicodeSegmentation::graphemes(text, true).count() - 1;
-    let end_bounds = match get_grapheme_boundaries(font, text, end) {
-        Some(bounds) => bounds,
-        None => return HitTestPoint::default(),
-    };
-
-    let start = 0;
-    let start_bounds = match get_grapheme_boundaries(font, text, start) {
-        Some(bounds) => bounds,
-        None => return HitTestPoint::default(),
-    };
-
-    // first test beyond ends
-    if point.x > end_bounds.trailing {
-        return HitTestPoint::new(text.len(), false);
+    /// The x position of `idx` in a line, from measuring the line up to it,
+    /// as hit testing did before the grapheme edges were cached.
+    fn measured_x(layout: &CairoTextLayout, line_number: usize, idx: usize, rtl: bool) -> f64 {
+        let line = layout.line_text(line_number).unwrap();
+        let measure = layout.line_measure(line_number);
+        let content_end = line.trim_end_matches(is_hard_break).len();
+        let ltr_x = |idx: usize| {
+            if idx < content_end {
+                measure.advance(&line[..idx])
+            } else {
+                measure.line_advance(&line[..idx])
+            }
+        };
+        if rtl {
+            ltr_x(line.len()) - ltr_x(idx)
+        } else {
+            ltr_x(idx)
+        }
     }
-    if point.x <= start_bounds.leading {
-        return HitTestPoint::default();
+
+    /// Hit test a point in a line by checking each of its graphemes in turn.
+    fn linear_hit_test_point(
+        layout: &CairoTextLayout,
+        line_number: usize,
+        x: f64,
+        rtl: bool,
+    ) -> HitTestPoint {
+        let line = layout.line_text(line_number).unwrap();
+        let start = layout.line_metric(line_number).unwrap().start_offset;
+        let mut hit = line
+            .grapheme_indices(true)
+            .find_map(|(idx, grapheme)| {
+                let next_idx = idx + grapheme.len();
+                let bounds = GraphemeBoundaries {
+                    curr_idx: idx,
+                    next_idx,
+                    leading: measured_x(layout, line_number, idx, rtl),
+                    trailing: measured_x(layout, line_number, next_idx, rtl),
+                };
+                point_x_in_grapheme(x, &bounds)
+            })
+            .unwrap();
+        hit.idx += start;
+        hit
     }
 
-    // then test the beginning and end (common cases)
-    if let Some(hit) = point_x_in_grapheme(point.x, &start_bounds) {
-        return hit;
+    #[test]
+    fn test_hit_test_matches_linear_scan() {
+        let mut text = CairoText::new();
+        let mixed = text
+            .new_text_layout("iiWW mm \u{3042}\u{3044} e\u{301}x \u{1F47E}. lll MMM wide")
+            .font(FontFamily::SANS_SERIF, 12.0)
+            .max_width(90.0)
+            .build()
+            .unwrap();
+        assert!(mixed.line_count() > 1);
+        // Hebrew and Arabic letters, of different widths
+        let rtl = text
+            .new_text_layout("\u{5D0}\u{5D1}\u{5D2} \u{5E9}\u{5DC}\u{5D5}\u{5DD} \u{644}\u{627}")
+            .font(FontFamily::SANS_SERIF, 12.0)
+            .build()
+            .unwrap();
+        assert!(mixed.visual_lines.iter().all(Option::is_none));
+        assert!(rtl.visual_lines[0].is_some());
+
+        for (layout, is_rtl) in &[(mixed, false), (rtl, true)] {
+            for line_number in 0..layout.line_count() {
+                let lm = layout.line_metric(line_number).unwrap();
+                let line = layout.line_text(line_number).unwrap();
+                // the end of a wrapped line is the start of the next one
+                let last = line_number + 1 == layout.line_count();
+                let end = Some(line.len()).filter(|_| last);
+                let boundaries = line.grapheme_indices(true).map(|(idx, _)| idx);
+                for idx in boundaries.chain(end) {
+                    let pos = layout.hit_test_text_position(lm.start_offset + idx);
+                    let pos = pos.unwrap();
+                    let x = measured_x(layout, line_number, idx, *is_rtl);
+                    assert_eq!(pos.line, line_number);
+                    assert_close!(pos.point.x, x, 1e-6);
+                }
+
+                let width = measured_x(layout, line_number, line.len(), false);
+                let y = lm.y_offset + lm.height / 2.0;
+                // points that fall on neither a grapheme edge nor its middle
+                let xs = (0..).map(|i| 0.37 + i as f64 * 0.83);
+                for x in xs.take_while(|x| *x < width) {
+                    let hit = layout.hit_test_point(Point::new(x, y));
+                    let linear = linear_hit_test_point(layout, line_number, x, *is_rtl);
+                    assert_eq!(
+                        (hit.idx, hit.affinity, hit.is_inside),
+                        (linear.idx, linear.affinity, linear.is_inside),
+                        "line {} x {}",
+                        line_number,
+                        x
+                    );
+                }
+            }
+        }
     }
-    if let Some(hit) = point_x_in_grapheme(point.x, &end_bounds) {
-        return hit;
+
+    #[test]
+    fn test_hit_test_affinity() {
+        let mut text_layout = CairoText::new();
+        let layout = text_layout
+            .new_text_layout("piet text is the best text\nmore")
+            .font(FontFamily::SANS_SERIF, 12.0)
+            .max_width(60.0)
+            .build()
+            .unwrap();
+        assert!(layout.line_count() > 2);
+        let line_0 = layout.line_metric(0).unwrap();
+        let line_1 = layout.line_metric(1).unwrap();
+
+        // a click past the end of a wrapped line is upstream of the break
+        let hit = layout.hit_test_point(Point::new(200.0, line_0.y_offset + 1.0));
+        assert_eq!(hit.idx, line_1.start_offset);
+        assert_eq!(hit.affinity, Affinity::Upstream);
+        let hit = layout.hit_test_point(Point::new(-10.0, line_1.y_offset + 1.0));
+        assert_eq!(hit.idx, line_1.start_offset);
+        assert_eq!(hit.affinity, Affinity::Downstream);
+
+        let upstream = layout
+            .hit_test_text_position_with_affinity(line_1.start_offset, Affinity::Upstream)
+            .unwrap();
+        let downstream = layout
+            .hit_test_text_position_with_affinity(line_1.start_offset, Affinity::Downstream)
+            .unwrap();
+        assert_eq!(upstream.line, 0);
+        assert_eq!(upstream.point.y, line_0.y_offset + line_0.baseline);
+        assert!(upstream.point.x > 0.0);
+        assert_eq!(downstream.line, 1);
+        assert_eq!(downstream.point.x, 0.0);
+
+        // after a hard break, the affinity makes no difference
+        let last = layout.line_metric(layout.line_count() - 1).unwrap();
+        let upstream = layout
+            .hit_test_text_position_with_affinity(last.start_offset, Affinity::Upstream)
+            .unwrap();
+        assert_eq!(upstream.line, layout.line_count() - 1);
     }
 
-    // Now that we know it's not beginning or end, begin binary search.
-    // Iterative style
-    let mut left = start;
-    let mut right = end;
-    loop {
-        // pick halfway point
-        let middle = left + ((right - left) / 2);
+    #[test]
+    fn test_bidi() {
+        let mut text = CairoText::new();
+        // the Hebrew letters alef, bet and gimel, at 4..10
+        let layout = text
+            .new_text_layout("abc \u{5D0}\u{5D1}\u{5D2} def")
+            .font(FontFamily::SANS_SERIF, 12.0)
+            .build()
+            .unwrap();
+        let hebrew = text
+            .new_text_layout("\u{5D0}\u{5D1}\u{5D2}")
+            .font(FontFamily::SANS_SERIF, 12.0)
+            .build()
+            .unwrap();
+        let caret = |idx, affinity| {
+            layout
+                .hit_test_text_position_with_affinity(idx, affinity)
+                .unwrap()
+                .point
+                .x
+        };
 
-        let grapheme_bounds = match get_grapheme_boundaries(font, text, middle) {
-            Some(bounds) => bounds,
-            None => return HitTestPoint::default(),
+        // the Hebrew run is right to left, so its logical start is on its
+        // right, and an upstream caret there follows the space to its left
+        let left = caret(4, Affinity::Upstream);
+        let right = caret(4, Affinity::Downstream);
+        assert_close!(right - left, hebrew.size().width, 1e-9);
+        assert_eq!(layout.hit_test_text_position(4).unwrap().point.x, right);
+        assert_eq!(caret(10, Affinity::Upstream), left);
+        assert_eq!(caret(10, Affinity::Downstream), right);
+        let bet = caret(6, Affinity::Downstream);
+        assert!(left < bet && bet < right);
+        assert_eq!(caret(6, Affinity::Upstream), bet);
+        assert_eq!(caret(0, Affinity::Downstream), 0.0);
+        assert_close!(caret(14, Affinity::Downstream), layout.size().width, 1e-9);
+
+        // the leading half of a right-to-left grapheme is its right half
+        let hit = layout.hit_test_point(Point::new(right - 0.5, 1.0));
+        assert_eq!(hit.idx, 4);
+        assert_eq!(hit.affinity, Affinity::Downstream);
+        assert!(hit.is_inside);
+        let hit = layout.hit_test_point(Point::new(left + 0.5, 1.0));
+        assert_eq!(hit.idx, 10);
+        assert_eq!(hit.affinity, Affinity::Upstream);
+
+        // so is the area of a range, and the glyphs are drawn in visual order
+        let alef_bet = layout.rects_for_range(4..8);
+        assert_eq!(alef_bet.len(), 1);
+        assert_eq!(alef_bet[0].x0, caret(8, Affinity::Downstream));
+        assert_eq!(alef_bet[0].x1, right);
+        let glyphs: Vec<_> = layout.glyph_runs()[0].glyphs.clone();
+        let x = |cluster| {
+            glyphs
+                .iter()
+                .find(|glyph| glyph.cluster == cluster)
+                .unwrap()
+                .position
+                .x
         };
+        assert!(x(0) < x(8) && x(8) < x(6) && x(6) < x(4) && x(4) < x(11));
+    }
 
-        if let Some(hit) = point_x_in_grapheme(point.x, &grapheme_bounds) {
-            return hit;
-        }
+    #[test]
+    fn test_bidi_rtl_paragraph() {
+        let mut text = CairoText::new();
+        let layout = text
+            .new_text_layout("\u{5D0}\u{5D1} abc")
+            .font(FontFamily::SANS_SERIF, 12.0)
+            .text_direction(TextDirection::RightToLeft)
+            .build()
+            .unwrap();
+        let width = layout.size().width;
+        // the paragraph starts at the right edge, with the Latin run on the left
+        assert_close!(
+            layout.hit_test_text_position(0).unwrap().point.x,
+            width,
+            1e-9
+        );
+        assert_eq!(layout.hit_test_text_position(5).unwrap().point.x, 0.0);
+        let hit = layout.hit_test_point(Point::new(width + 10.0, 1.0));
+        assert_eq!(hit.idx, 0);
+        assert!(!hit.is_inside);
+        let hit = layout.hit_test_point(Point::new(-10.0, 1.0));
+        assert_eq!(hit.idx, 5);
+        assert!(!hit.is_inside);
+    }
 
-        // since it's not a hit, check if closer to start or finish
-        // and move the appropriate search boundary
-        if point.x < grapheme_bounds.leading {
-            right = middle;
-        } else if point.x > grapheme_bounds.trailing {
-            left = middle + 1;
-        } else {
-            unreachable!("hit_test_point conditional is exhaustive");
+    #[test]
+    fn test_line_text_range() {
+        let mut text_layout = CairoText::new();
+
+        let text = "piet text\r\nis the best text\n";
+        let layout = text_layout
+            .new_text_layout(text)
+            .max_width(60.0)
+            .build()
+            .unwrap();
+        assert!(layout.line_count() > 3);
+        assert_eq!(layout.line_text_range(layout.line_count()), None);
+        // the hard break, with its \r\n, ends a line
+        assert!((0..layout.line_count()).any(|i| layout.line_text_range(i).unwrap().end == 11));
+
+        let mut end = 0;
+        for line in 0..layout.line_count() {
+            let range = layout.line_text_range(line).unwrap();
+            assert_eq!(range.start, end);
+            assert_eq!(&text[range.clone()], layout.line_text(line).unwrap());
+            assert_eq!(range, layout.line_metric(line).unwrap().range());
+            end = range.end;
         }
+        assert_eq!(end, text.len());
     }
-}
 
-// NOTE this is the same as the old, non-line-aware version of hit_test_text_position.
-// Future: instead of passing Font, should there be some other line-level text layout?
-fn hit_test_line_position(font: &ScaledFont, text: &str, text_position: usize) -> Option<f64> {
-    // Using substrings with unicode grapheme awareness
+    #[test]
+    fn test_rects_for_range() {
+        let mut text_layout = CairoText::new();
+
+        let layout = text_layout
+            .new_text_layout("piet text\nis the best")
+            .build()
+            .unwrap();
+        let x = |pos| layout.hit_test_text_position(pos).unwrap().point.x;
+        let line_0 = layout.line_metric(0).unwrap();
+        let line_1 = layout.line_metric(1).unwrap();
+
+        let rects = layout.rects_for_range(2..13);
+        assert_eq!(rects.len(), 3);
+        assert_close!(rects[0].x0, x(2), 0.01);
+        assert_close!(rects[0].x1, x(9), 0.01);
+        assert_close!(rects[0].y0, line_0.y_offset, 0.01);
+        assert_close!(rects[0].height(), line_0.height, 0.01);
+        // the newline
+        assert_close!(rects[1].x0, x(9), 0.01);
+        assert!(rects[1].width() > 0.0 && rects[1].width() < rects[0].width() / 4.0);
+        assert_close!(rects[2].x0, 0.0, 0.01);
+        assert_close!(rects[2].x1, x(13), 0.01);
+        assert_close!(rects[2].y0, line_1.y_offset, 0.01);
+
+        // a range ending at a soft line break doesn't reach the next line
+        let wrapped = text_layout
+            .new_text_layout("piet text is the best text")
+            .max_width(60.0)
+            .build()
+            .unwrap();
+        let end = wrapped.line_metric(0).unwrap().end_offset;
+        assert_eq!(wrapped.rects_for_range(0..end).len(), 1);
+
+        // ranges are expanded to grapheme boundaries
+        let layout = text_layout.new_text_layout("ae\u{301}b").build().unwrap();
+        let x = |pos| layout.hit_test_text_position(pos).unwrap().point.x;
+        let rects = layout.rects_for_range(2..3);
+        assert_eq!(rects.len(), 1);
+        assert_close!(rects[0].x0, x(1), 0.01);
+        assert_close!(rects[0].x1, x(4), 0.01);
+    }
+
+    #[test]
+    fn test_font_features_not_supported() {
+        let mut text_layout = CairoText::new();
+
+        let result = text_layout
+            .new_text_layout("1234")
+            .range_attribute(1..3, piet::FontFeatures::TABULAR_NUMS)
+            .build();
+        assert!(matches!(result, Err(Error::NotSupported)));
+
+        // an empty set of features is the same as none
+        let result = text_layout
+            .new_text_layout("1234")
+            .default_attribute(piet::FontFeatures::new())
+            .build();
+        assert!(result.is_ok());
+    }
 
-    let text_len = text.len();
+    #[test]
+    fn test_underline_style() {
+        let mut text_layout = CairoText::new();
 
-    if text_position == 0 {
-        return Some(0.0);
+        let layout = text_layout
+            .new_text_layout("piet text")
+            .default_attribute(TextAttribute::Underline(true))
+            .range_attribute(0..4, TextAttribute::UnderlineColor(Color::WHITE))
+            .range_attribute(5..9, UnderlineStyle::Wavy)
+            .build()
+            .unwrap();
+        let paths = layout.underline_paths();
+        assert_eq!(paths.len(), 3);
+        assert!(paths[0].2.is_some());
+        assert!(paths[1].2.is_none());
+        assert!(paths[1].1.dash.is_none());
+
+        let thickness = layout.underline_thickness();
+        let wave = piet::kurbo::Shape::bounding_box(&paths[2].0);
+        assert_close!(wave.height(), thickness * 2.0, 0.01);
+        assert_close!(
+            wave.x1,
+            layout.hit_test_text_position(9).unwrap().point.x,
+            0.01
+        );
     }
 
-    if text_position as usize >= text_len {
-        return Some(font.text_extents(&text).x_advance);
+    /// The test font, with a `post` table for an underline 100 units thick
+    /// whose center is 150 units below the baseline, and an `OS/2` table for
+    /// a strikeout as thick, 350 units above it.
+    fn test_font_with_decorations(family: &str) -> Vec<u8> {
+        let mut post = Vec::new();
+        be16(&mut post, &[3, 0, 0, 0, -100i16 as u16, 100]);
+        post.extend_from_slice(&[0; 20]);
+        let mut os2 = vec![0; 78];
+        os2[26..28].copy_from_slice(&100i16.to_be_bytes());
+        os2[28..30].copy_from_slice(&400i16.to_be_bytes());
+        test_font_with_tables(family, vec![(b"OS/2", os2), (b"post", post)])
     }
 
-    // Already checked that text_position > 0 and text_position < count.
-    // If text position is not at a grapheme boundary, use the text position of current
-    // grapheme cluster. But return the original text position
-    // Use the indices (byte offset, which for our purposes = utf8 code units).
-    let grapheme_indices = UnicodeSegmentation::grapheme_indices(text, true)
-        .take_while(|(byte_idx, _s)| text_position >= *byte_idx);
-
-    if let Some((byte_idx, _s)) = grapheme_indices.last() {
-        let point_x = font.text_extents(&text[0..byte_idx]).x_advance;
-        Some(point_x)
-    } else {
-        // iterated to end boundary
-        Some(font.text_extents(&text).x_advance)
+    #[test]
+    fn test_decoration_metrics() {
+        let mut text = CairoText::new();
+        let font = test_font_with_decorations("Piet Cairo Decoration Metrics");
+        let family = text.load_font(&font).unwrap();
+        for &size in &[10.0, 20.0, 40.0] {
+            let metrics = text.font_metrics(&family, size).unwrap();
+            assert_close!(metrics.underline_position, size * 0.15, 1e-9);
+            assert_close!(metrics.underline_thickness, size * 0.1, 1e-9);
+            assert_close!(metrics.strikeout_position, size * -0.35, 1e-9);
+            assert_close!(metrics.strikeout_thickness, size * 0.1, 1e-9);
+        }
+
+        // without the tables, they're approximated
+        let family = text
+            .load_font(&test_font("Piet Cairo No Decorations"))
+            .unwrap();
+        let metrics = text.font_metrics(&family, 10.0).unwrap();
+        assert!(metrics.underline_position > 0.0);
+        assert!(metrics.strikeout_position < 0.0);
+        assert!(metrics.strikeout_thickness > 0.0);
     }
-}
 
-fn scale_matrix(scale: f64) -> Matrix {
-    Matrix {
-        xx: scale,
-        yx: 0.0,
-        xy: 0.0,
-        yy: scale,
-        x0: 0.0,
-        y0: 0.0,
+    #[test]
+    fn test_decorations_drawn() {
+        use crate::conformance::render;
+
+        const BLACK: (u8, u8, u8) = (0, 0, 0);
+        const WHITE: (u8, u8, u8) = (255, 255, 255);
+        const RED: (u8, u8, u8) = (255, 0, 0);
+
+        let mut text = CairoText::new();
+        let font = test_font_with_decorations("Piet Cairo Decorations");
+        let family = text.load_font(&font).unwrap();
+        // each 'A' is a square 0.5em wide and 0.7em tall, advancing 0.6em;
+        // the baseline is 0.8em down
+        for &size in &[10.0, 20.0, 40.0] {
+            let mut builder = || {
+                text.new_text_layout("AAAA")
+                    .font(family.clone(), size)
+                    .default_attribute(TextAttribute::Underline(true))
+                    .range_attribute(2..4, TextAttribute::UnderlineColor(Color::rgb8(255, 0, 0)))
+                    .range_attribute(1..3, TextAttribute::Strikethrough(true))
+            };
+            let width = (size * 2.4) as i32;
+            let layout = builder().build().unwrap();
+            let pixel = render(&layout, width, size as i32);
+            let at = |x: f64, y: f64| pixel((x * size) as i32, (y * size) as i32);
+
+            // the underline is 0.9em to 1em down, and changes color with
+            // its attribute
+            assert_eq!(at(0.25, 0.95), BLACK, "size {}", size);
+            assert_eq!(at(0.25, 0.85), WHITE, "size {}", size);
+            assert_eq!(at(1.45, 0.95), RED, "size {}", size);
+            // the strikeout is 0.4em to 0.5em down, seen between the glyphs
+            // it strikes
+            assert_eq!(at(0.55, 0.45), WHITE, "size {}", size);
+            assert_eq!(at(1.15, 0.45), BLACK, "size {}", size);
+            assert_eq!(at(1.15, 0.35), WHITE, "size {}", size);
+            assert_eq!(at(1.75, 0.45), BLACK, "size {}", size);
+            assert_eq!(at(2.35, 0.45), WHITE, "size {}", size);
+
+            // decorations end with each line they're on
+            let wrapped = builder()
+                .break_opportunities(|_| vec![2])
+                .max_width(size * 1.3)
+                .build()
+                .unwrap();
+            assert_eq!(wrapped.line_count(), 2);
+            let pixel = render(&wrapped, width, size as i32 * 2);
+            let at = |x: f64, y: f64| pixel((x * size) as i32, (y * size) as i32);
+            assert_eq!(at(0.25, 0.95), BLACK, "size {}", size);
+            assert_eq!(at(1.45, 0.95), WHITE, "size {}", size);
+            assert_eq!(at(0.25, 1.95), RED, "size {}", size);
+            assert_eq!(at(0.55, 0.45), WHITE, "size {}", size);
+            assert_eq!(at(0.55, 1.45), BLACK, "size {}", size);
+        }
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use piet::TextLayout;
+    #[test]
+    fn test_justified() {
+        let mut text_layout = CairoText::new();
 
-    macro_rules! assert_close {
-        ($val:expr, $target:expr, $tolerance:expr) => {{
-            let min = $target - $tolerance;
-            let max = $target + $tolerance;
-            if $val < min || $val > max {
-                panic!(
-                    "value {} outside target {} with tolerance {}",
-                    $val, $target, $tolerance
-                );
-            }
-        }};
+        let input = "piet text is the best text, and this is a lot of it";
+        let layout = text_layout
+            .new_text_layout(input)
+            .max_width(120.0)
+            .build()
+            .unwrap();
+        let justified = text_layout
+            .new_text_layout(input)
+            .max_width(120.0)
+            .alignment(TextAlignment::Justified)
+            .build()
+            .unwrap();
+        assert!(justified.line_count() > 1);
+        assert_close!(justified.size().width, 120.0, 0.01);
+
+        // the first line is stretched, the last line is not
+        let first = justified.line_metric(0).unwrap();
+        let visible_end = first.end_offset - first.trailing_whitespace;
+        let end = justified.hit_test_text_position(visible_end).unwrap().point;
+        assert_close!(end.x, 120.0, 0.01);
+        let last = justified.line_metric(justified.line_count() - 1).unwrap();
+        assert_close!(
+            justified
+                .hit_test_text_position(input.len())
+                .unwrap()
+                .point
+                .x,
+            layout.hit_test_text_position(input.len()).unwrap().point.x,
+            0.01,
+        );
 
-        ($val:expr, $target:expr, $tolerance:expr,) => {{
-            assert_close!($val, $target, $tolerance)
-        }};
+        // hit testing uses the justified positions
+        let word_start = input.find("text").unwrap();
+        let pos = justified.hit_test_text_position(word_start).unwrap().point;
+        assert!(pos.x > layout.hit_test_text_position(word_start).unwrap().point.x);
+        let point = Point::new(pos.x + 0.5, first.y_offset + 1.0);
+        assert_eq!(justified.hit_test_point(point).idx, word_start);
+        let point = Point::new(200.0, last.y_offset + 1.0);
+        assert_eq!(justified.hit_test_point(point).idx, input.len());
     }
 
     #[test]
@@ -708,7 +3164,7 @@ mod test {
         //println!("text pos 2: {:?}", layout.hit_test_text_position(2)); // 6.99999999
         //println!("text pos 9: {:?}", layout.hit_test_text_position(9)); // 24.0
         //println!("text pos 10: {:?}", layout.hit_test_text_position(10)); // 32.0
-        //println!("text pos 14: {:?}", layout.hit_test_text_position(14)); // 39.0, line width
+        //println!("text pos 14: {:?}", layout.hit_test_text_position(14)); // 44.0, line width, with a fallback font for 𝐇
 
         let pt = layout.hit_test_point(Point::new(2.0, 0.0));
         assert_eq!(pt.idx, 0);
@@ -731,11 +3187,11 @@ mod test {
         let pt = layout.hit_test_point(Point::new(32.0, 0.0));
         assert_eq!(pt.idx, 10);
         let pt = layout.hit_test_point(Point::new(35.5, 0.0));
-        assert_eq!(pt.idx, 14);
-        let pt = layout.hit_test_point(Point::new(38.0, 0.0));
-        assert_eq!(pt.idx, 14);
+        assert_eq!(pt.idx, 10);
         let pt = layout.hit_test_point(Point::new(40.0, 0.0));
         assert_eq!(pt.idx, 14);
+        let pt = layout.hit_test_point(Point::new(43.0, 0.0));
+        assert_eq!(pt.idx, 14);
     }
 
     #[test]
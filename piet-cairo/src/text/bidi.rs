@@ -0,0 +1,299 @@
+//! Bidirectional text, for the lines of a layout with right-to-left text.
+//!
+//! The toy text API only lays glyphs out left to right, so the graphemes of
+//! these lines are placed one at a time, in the visual order given by the
+//! [Unicode Bidirectional Algorithm](https://unicode.org/reports/tr9/).
+
+use std::ops::Range;
+
+use piet::{Affinity, HitTestPoint, TextDirection};
+use unic_bidi::{BidiClass, BidiInfo, Level};
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::grapheme::{point_x_in_grapheme, GraphemeBoundaries};
+use super::{is_hard_break, LineMeasure};
+
+/// The embedding levels of `text`, with paragraphs in the given direction.
+pub(crate) fn bidi_info(text: &str, direction: TextDirection) -> BidiInfo<'_> {
+    let level = match direction {
+        TextDirection::LeftToRight => Some(Level::ltr()),
+        TextDirection::RightToLeft => Some(Level::rtl()),
+        TextDirection::Auto => None,
+    };
+    BidiInfo::new(text, level)
+}
+
+/// The directional runs of the line at `range` in the text, in visual order,
+/// each relative to the start of the line and with whether it is
+/// right-to-left; `None` if the line is all left-to-right.
+pub(crate) fn visual_runs(
+    bidi: &BidiInfo,
+    range: Range<usize>,
+) -> Option<Vec<(Range<usize>, bool)>> {
+    if !bidi.levels[range.clone()].iter().any(Level::is_rtl) {
+        return None;
+    }
+    let para = bidi
+        .paragraphs
+        .iter()
+        .find(|para| para.range.contains(&range.start))?;
+    let text = &bidi.text[range.clone()];
+    let classes = &bidi.original_classes[range.clone()];
+    let mut levels = bidi.levels[range].to_vec();
+
+    // rule L1: separators, and the whitespace before them or at the end of
+    // the line, are at the paragraph level
+    let mut reset = true;
+    for (idx, c) in text.char_indices().rev() {
+        let reset_char = match classes[idx] {
+            BidiClass::ParagraphSeparator | BidiClass::SegmentSeparator => {
+                reset = true;
+                true
+            }
+            BidiClass::WhiteSpace
+            | BidiClass::FirstStrongIsolate
+            | BidiClass::LeftToRightIsolate
+            | BidiClass::RightToLeftIsolate
+            | BidiClass::PopDirectionalIsolate
+            | BidiClass::BoundaryNeutral
+            | BidiClass::LeftToRightEmbedding
+            | BidiClass::LeftToRightOverride
+            | BidiClass::RightToLeftEmbedding
+            | BidiClass::RightToLeftOverride
+            | BidiClass::PopDirectionalFormat => reset,
+            _ => {
+                reset = false;
+                false
+            }
+        };
+        if reset_char {
+            levels[idx..idx + c.len_utf8()].fill(para.level);
+        }
+    }
+
+    let mut runs: Vec<(Range<usize>, Level)> = Vec::new();
+    for (idx, level) in levels.iter().enumerate() {
+        match runs.last_mut() {
+            Some((run, run_level)) if run_level == level => run.end = idx + 1,
+            _ => runs.push((idx..idx + 1, *level)),
+        }
+    }
+
+    // rule L2: from the highest level down to the lowest odd level, reverse
+    // each sequence of runs at that level or higher
+    let highest = runs.iter().map(|(_, level)| level.number()).max();
+    let lowest = runs.iter().map(|(_, level)| level.number()).min();
+    let (highest, lowest) = (highest.unwrap_or(0), lowest.unwrap_or(0));
+    for level in ((lowest | 1)..=highest).rev() {
+        let mut start = 0;
+        while start < runs.len() {
+            if runs[start].1.number() < level {
+                start += 1;
+                continue;
+            }
+            let len = runs[start..]
+                .iter()
+                .take_while(|(_, run_level)| run_level.number() >= level)
+                .count();
+            runs[start..start + len].reverse();
+            start += len;
+        }
+    }
+    Some(
+        runs.into_iter()
+            .map(|(run, level)| (run, level.is_rtl()))
+            .collect(),
+    )
+}
+
+/// A grapheme of a [`VisualLine`].
+///
+/// [`VisualLine`]: struct.VisualLine.html
+#[derive(Clone, Debug)]
+pub(crate) struct VisualGrapheme {
+    /// the range of the grapheme, relative to the start of the line.
+    pub range: Range<usize>,
+    /// the x positions of the left and right edges of its advance.
+    pub x0: f64,
+    pub x1: f64,
+    pub rtl: bool,
+}
+
+impl VisualGrapheme {
+    /// The boundaries of the grapheme; the leading edge of a right-to-left
+    /// grapheme is its right edge.
+    fn boundaries(&self) -> GraphemeBoundaries {
+        let (leading, trailing) = if self.rtl {
+            (self.x1, self.x0)
+        } else {
+            (self.x0, self.x1)
+        };
+        GraphemeBoundaries {
+            curr_idx: self.range.start,
+            next_idx: self.range.end,
+            leading,
+            trailing,
+        }
+    }
+}
+
+/// The graphemes of a line with right-to-left text, placed in visual order.
+///
+/// These take the place of the line's grapheme edges for hit testing, and
+/// its graphemes are drawn one at a time.
+#[derive(Clone, Debug)]
+pub(crate) struct VisualLine {
+    /// the graphemes, from left to right.
+    pub graphemes: Vec<VisualGrapheme>,
+    /// the indices of the graphemes in `graphemes`, in logical order.
+    logical: Vec<usize>,
+}
+
+impl VisualLine {
+    /// Place the graphemes of the line `text`, given its directional runs in
+    /// visual order.
+    pub(crate) fn new(measure: LineMeasure, text: &str, runs: &[(Range<usize>, bool)]) -> Self {
+        let mut graphemes = Vec::new();
+        let mut x = 0.0;
+        // there's no letter spacing after the last grapheme of the line
+        let last = text
+            .trim_end_matches(is_hard_break)
+            .grapheme_indices(true)
+            .next_back()
+            .map(|(idx, _)| idx);
+        for (run, rtl) in runs {
+            let mut run_graphemes: Vec<_> = text[run.clone()]
+                .grapheme_indices(true)
+                .map(|(idx, grapheme)| (run.start + idx, grapheme))
+                .collect();
+            if *rtl {
+                run_graphemes.reverse();
+            }
+            for (idx, grapheme) in run_graphemes {
+                let x1 = match measure.tab_stops {
+                    Some(stops) if grapheme == "\t" => stops.next_stop(x),
+                    // a line break is at the paragraph level, so it may not be
+                    // at the end of the line visually, and takes no space
+                    _ if grapheme.chars().all(is_hard_break) => x,
+                    _ if Some(idx) == last => {
+                        x + measure.run_advance(grapheme, idx)
+                            - measure.trailing_letter_spacing(text)
+                    }
+                    _ => x + measure.run_advance(grapheme, idx),
+                };
+                graphemes.push(VisualGrapheme {
+                    range: idx..idx + grapheme.len(),
+                    x0: x,
+                    x1,
+                    rtl: *rtl,
+                });
+                x = x1;
+            }
+        }
+        let mut logical: Vec<usize> = (0..graphemes.len()).collect();
+        logical.sort_by_key(|&idx| graphemes[idx].range.start);
+        VisualLine { graphemes, logical }
+    }
+
+    /// The text position (relative to the start of the line) nearest to
+    /// `x`, as in `hit_test_point`.
+    pub(crate) fn hit_test_point(&self, x: f64) -> HitTestPoint {
+        let (first, last) = match (self.graphemes.first(), self.graphemes.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return HitTestPoint::default(),
+        };
+        let clamped = x.max(first.x0).min(last.x1);
+        let idx = self.graphemes.partition_point(|g| g.x1 < clamped);
+        let grapheme = &self.graphemes[idx.min(self.graphemes.len() - 1)];
+        let mut hit = point_x_in_grapheme(clamped, &grapheme.boundaries()).unwrap_or_default();
+        hit.is_inside &= x > first.x0 && x <= last.x1;
+        hit
+    }
+
+    /// The x position of a caret at `text_position`, relative to the start of
+    /// the line.
+    ///
+    /// This is the leading edge of the grapheme at the position, or for an
+    /// upstream caret at the start of a grapheme, the trailing edge of the
+    /// grapheme before it; they differ where the direction changes. A caret
+    /// at the end of the line is at the trailing edge of its last grapheme.
+    pub(crate) fn caret_x(&self, text_position: usize, affinity: Affinity) -> f64 {
+        let count = self
+            .logical
+            .partition_point(|&idx| self.graphemes[idx].range.start <= text_position);
+        let grapheme = match count.checked_sub(1) {
+            Some(idx) => &self.graphemes[self.logical[idx]],
+            None => return 0.0,
+        };
+        if text_position >= grapheme.range.end {
+            return grapheme.boundaries().trailing;
+        }
+        if text_position == grapheme.range.start && affinity == Affinity::Upstream && count > 1 {
+            return self.graphemes[self.logical[count - 2]]
+                .boundaries()
+                .trailing;
+        }
+        grapheme.boundaries().leading
+    }
+
+    /// The extents, from left to right, of the parts of the line covering
+    /// `range`, which is relative to the start of the line.
+    pub(crate) fn spans(&self, range: Range<usize>) -> Vec<(f64, f64)> {
+        let mut spans: Vec<(f64, f64)> = Vec::new();
+        let covered = self
+            .graphemes
+            .iter()
+            .filter(|g| range.contains(&g.range.start));
+        for grapheme in covered {
+            match spans.last_mut() {
+                Some(span) if span.1 == grapheme.x0 => span.1 = grapheme.x1,
+                _ => spans.push((grapheme.x0, grapheme.x1)),
+            }
+        }
+        spans
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn runs(text: &str, direction: TextDirection) -> Option<Vec<(Range<usize>, bool)>> {
+        visual_runs(&bidi_info(text, direction), 0..text.len())
+    }
+
+    #[test]
+    fn test_visual_runs() {
+        assert_eq!(runs("abc def", TextDirection::Auto), None);
+        // "abc ", the Hebrew letters alef bet, then " def"
+        let text = "abc \u{5D0}\u{5D1} def";
+        assert_eq!(
+            runs(text, TextDirection::LeftToRight),
+            Some(vec![(0..4, false), (4..8, true), (8..12, false)])
+        );
+        // an RTL paragraph puts the runs right to left, and the trailing
+        // space at the paragraph level
+        let text = "\u{5D0}\u{5D1} abc ";
+        assert_eq!(
+            runs(text, TextDirection::Auto),
+            Some(vec![(8..9, true), (5..8, false), (0..5, true)])
+        );
+        // numbers in RTL text are left-to-right, at a higher level
+        let text = "\u{5D0} 12 \u{5D1}";
+        assert_eq!(
+            runs(text, TextDirection::RightToLeft),
+            Some(vec![(5..8, true), (3..5, false), (0..3, true)])
+        );
+    }
+
+    #[test]
+    fn test_visual_runs_of_later_lines() {
+        let text = "abc\n\u{5D0}\u{5D1} d";
+        let bidi = bidi_info(text, TextDirection::Auto);
+        assert_eq!(visual_runs(&bidi, 0..4), None);
+        assert_eq!(
+            visual_runs(&bidi, 4..text.len()),
+            Some(vec![(5..6, false), (0..5, true)])
+        );
+    }
+}
@@ -0,0 +1,289 @@
+//! Fallback fonts for the clusters of a layout that its font has no glyphs for.
+//!
+//! The toy text API draws whatever glyphs the font has, and an empty box for
+//! the rest, so we find a font covering each missing cluster ourselves: first
+//! among the layout's fallback families, then by asking fontconfig.
+//!
+//! Cairo draws the glyphs of color fonts in color itself, through FreeType,
+//! so for emoji we only have to ask fontconfig for a color font.
+
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::ops::Range;
+use std::os::raw::{c_char, c_int, c_void};
+use std::sync::{Mutex, PoisonError};
+
+use cairo::{FontSlant, FontWeight, ScaledFont};
+use unicode_segmentation::UnicodeSegmentation;
+
+use piet::FontFamily;
+
+use super::{covers_text, is_drawn, CairoFont, SendScaledFont};
+
+type FcPattern = c_void;
+type FcCharSet = c_void;
+
+#[repr(C)]
+struct FcFontSet {
+    nfont: c_int,
+    sfont: c_int,
+    fonts: *mut *mut FcPattern,
+}
+
+const FC_RESULT_MATCH: c_int = 0;
+const FC_MATCH_PATTERN: c_int = 0;
+const FC_TRUE: c_int = 1;
+const FC_FAMILY: &[u8] = b"family\0";
+const FC_CHARSET: &[u8] = b"charset\0";
+const FC_COLOR: &[u8] = b"color\0";
+
+#[link(name = "fontconfig")]
+extern "C" {
+    fn FcPatternCreate() -> *mut FcPattern;
+    fn FcPatternDestroy(pattern: *mut FcPattern);
+    fn FcPatternAddCharSet(
+        pattern: *mut FcPattern,
+        object: *const c_char,
+        charset: *const FcCharSet,
+    ) -> c_int;
+    fn FcPatternAddBool(pattern: *mut FcPattern, object: *const c_char, b: c_int) -> c_int;
+    fn FcPatternGetCharSet(
+        pattern: *const FcPattern,
+        object: *const c_char,
+        n: c_int,
+        charset: *mut *mut FcCharSet,
+    ) -> c_int;
+    fn FcPatternGetString(
+        pattern: *const FcPattern,
+        object: *const c_char,
+        n: c_int,
+        string: *mut *mut u8,
+    ) -> c_int;
+    fn FcCharSetCreate() -> *mut FcCharSet;
+    fn FcCharSetDestroy(charset: *mut FcCharSet);
+    fn FcCharSetAddChar(charset: *mut FcCharSet, c: u32) -> c_int;
+    fn FcCharSetHasChar(charset: *const FcCharSet, c: u32) -> c_int;
+    fn FcConfigSubstitute(config: *mut c_void, pattern: *mut FcPattern, kind: c_int) -> c_int;
+    fn FcDefaultSubstitute(pattern: *mut FcPattern);
+    fn FcFontSort(
+        config: *mut c_void,
+        pattern: *mut FcPattern,
+        trim: c_int,
+        charsets: *mut *mut FcCharSet,
+        result: *mut c_int,
+    ) -> *mut FcFontSet;
+    fn FcFontSetDestroy(set: *mut FcFontSet);
+}
+
+/// A range of a layout's text that is drawn with a fallback font.
+#[derive(Clone)]
+pub(crate) struct FallbackRun {
+    pub range: Range<usize>,
+    pub family: FontFamily,
+    pub font: SendScaledFont,
+}
+
+/// The key of a fallback lookup: the block of 256 code points the first
+/// drawn character of a cluster is in, whether it prefers a color font, and
+/// the size and style of the font.
+type CacheKey = (u32, bool, u64, bool, bool);
+
+/// A fallback font, and the family it was created from.
+type Fallback = (FontFamily, SendScaledFont);
+
+/// The fonts fontconfig found, for each block and size; `None` if it found
+/// no font.
+///
+/// A font found for one character usually covers the rest of its block, but
+/// is checked before it is used; clusters it doesn't cover are looked up
+/// again, without replacing it.
+static CACHE: Mutex<Option<HashMap<CacheKey, Option<Fallback>>>> = Mutex::new(None);
+
+/// Finds the clusters of `text` that `font` has no glyphs for, and the
+/// fallback font to draw each with, merging adjacent clusters drawn with
+/// the same family.
+///
+/// `families` are tried in order before fontconfig is asked; clusters that
+/// no font covers are left to `font`.
+pub(crate) fn fallback_runs(
+    text: &str,
+    font: &ScaledFont,
+    families: &[(FontFamily, ScaledFont)],
+    size: f64,
+    slant: FontSlant,
+    weight: FontWeight,
+) -> Vec<FallbackRun> {
+    let mut runs: Vec<FallbackRun> = Vec::new();
+    for (idx, cluster) in text.grapheme_indices(true) {
+        if covers_text(font, cluster) {
+            continue;
+        }
+        let fallback = families
+            .iter()
+            .find(|(_, font)| covers_text(font, cluster))
+            .map(|(family, font)| (family.clone(), SendScaledFont(font.clone())))
+            .or_else(|| system_fallback(cluster, size, slant, weight));
+        let (family, font) = match fallback {
+            Some(fallback) => fallback,
+            None => continue,
+        };
+        let range = idx..idx + cluster.len();
+        match runs.last_mut() {
+            Some(last) if last.range.end == idx && last.family == family => {
+                last.range.end = range.end
+            }
+            _ => runs.push(FallbackRun {
+                range,
+                family,
+                font,
+            }),
+        }
+    }
+    runs
+}
+
+/// Splits `text`, which starts at `pos` in a layout's text, into the pieces
+/// that are drawn with a single font, each with its offset in `text` and the
+/// fallback run it is part of, if any.
+pub(crate) fn split<'t, 'f>(
+    text: &'t str,
+    pos: usize,
+    fallbacks: &'f [FallbackRun],
+) -> Vec<(usize, &'t str, Option<&'f FallbackRun>)> {
+    let end = pos + text.len();
+    let mut pieces = Vec::new();
+    let mut at = pos;
+    let first = fallbacks.partition_point(|run| run.range.end <= pos);
+    for run in &fallbacks[first..] {
+        if run.range.start >= end {
+            break;
+        }
+        let start = run.range.start.max(pos);
+        if start > at {
+            pieces.push((at - pos, &text[at - pos..start - pos], None));
+        }
+        let stop = run.range.end.min(end);
+        pieces.push((start - pos, &text[start - pos..stop - pos], Some(run)));
+        at = stop;
+    }
+    if at < end {
+        pieces.push((at - pos, &text[at - pos..], None));
+    }
+    pieces
+}
+
+/// A font covering `cluster`, found by fontconfig.
+fn system_fallback(
+    cluster: &str,
+    size: f64,
+    slant: FontSlant,
+    weight: FontWeight,
+) -> Option<Fallback> {
+    let first = cluster.chars().find(|c| is_drawn(*c))?;
+    let color = prefers_color(cluster);
+    let key = (
+        first as u32 >> 8,
+        color,
+        size.to_bits(),
+        slant != FontSlant::Normal,
+        weight == FontWeight::Bold,
+    );
+    let mut cache = CACHE.lock().unwrap_or_else(PoisonError::into_inner);
+    let cache = cache.get_or_insert_with(HashMap::new);
+    let lookup = || {
+        let family = unsafe { family_covering(cluster, color) }?;
+        let font = CairoFont::new(family.clone()).resolve(size, slant, weight);
+        // the toy text API may resolve the family to another font
+        Some((family, SendScaledFont(font))).filter(|(_, font)| covers_text(font, cluster))
+    };
+    match cache.get(&key) {
+        Some(Some((family, font))) if covers_text(font, cluster) => {
+            Some((family.clone(), font.clone()))
+        }
+        Some(_) => lookup(),
+        None => {
+            let found = lookup();
+            cache.insert(key, found.clone());
+            found
+        }
+    }
+}
+
+/// Returns `true` if `cluster` is an emoji that should be drawn in color:
+/// one from the emoji blocks, or one followed by the emoji variation
+/// selector, unless it is followed by the text variation selector.
+fn prefers_color(cluster: &str) -> bool {
+    !cluster.contains('\u{FE0E}')
+        && cluster
+            .chars()
+            .any(|c| c == '\u{FE0F}' || ('\u{1F000}'..='\u{1FAFF}').contains(&c))
+}
+
+/// The family of the best font fontconfig has with glyphs for all of the
+/// drawn characters of `cluster`, preferring color fonts if `color` is set.
+unsafe fn family_covering(cluster: &str, color: bool) -> Option<FontFamily> {
+    let chars: Vec<u32> = cluster
+        .chars()
+        .filter(|c| is_drawn(*c))
+        .map(u32::from)
+        .collect();
+    let charset = FcCharSetCreate();
+    for &c in &chars {
+        FcCharSetAddChar(charset, c);
+    }
+    let pattern = FcPatternCreate();
+    FcPatternAddCharSet(pattern, FC_CHARSET.as_ptr() as *const c_char, charset);
+    FcCharSetDestroy(charset);
+    if color {
+        FcPatternAddBool(pattern, FC_COLOR.as_ptr() as *const c_char, FC_TRUE);
+    }
+    FcConfigSubstitute(std::ptr::null_mut(), pattern, FC_MATCH_PATTERN);
+    FcDefaultSubstitute(pattern);
+    let mut result = 0;
+    let set = FcFontSort(
+        std::ptr::null_mut(),
+        pattern,
+        FC_TRUE,
+        std::ptr::null_mut(),
+        &mut result,
+    );
+    FcPatternDestroy(pattern);
+    if set.is_null() {
+        return None;
+    }
+
+    let fonts = std::slice::from_raw_parts((*set).fonts, (*set).nfont.max(0) as usize);
+    let family = fonts.iter().find_map(|&font| {
+        let mut charset = std::ptr::null_mut();
+        let object = FC_CHARSET.as_ptr() as *const c_char;
+        if FcPatternGetCharSet(font, object, 0, &mut charset) != FC_RESULT_MATCH
+            || !chars.iter().all(|&c| FcCharSetHasChar(charset, c) != 0)
+        {
+            return None;
+        }
+        let mut family = std::ptr::null_mut();
+        let object = FC_FAMILY.as_ptr() as *const c_char;
+        if FcPatternGetString(font, object, 0, &mut family) != FC_RESULT_MATCH {
+            return None;
+        }
+        let family = CStr::from_ptr(family as *const c_char).to_str().ok()?;
+        Some(FontFamily::new_unchecked(family))
+    });
+    FcFontSetDestroy(set);
+    family
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_prefers_color() {
+        assert!(prefers_color("\u{1F600}"));
+        assert!(prefers_color("\u{2764}\u{FE0F}"));
+        assert!(prefers_color("#\u{FE0F}\u{20E3}"));
+        assert!(!prefers_color("\u{2764}"));
+        assert!(!prefers_color("\u{1F600}\u{FE0E}"));
+        assert!(!prefers_color("\u{627}"));
+    }
+}
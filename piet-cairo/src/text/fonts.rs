@@ -0,0 +1,271 @@
+//! Fonts loaded from memory with `load_font`, through FreeType.
+//!
+//! The toy text API only knows the fonts that fontconfig finds, so loaded
+//! fonts are kept here, and resolving a family looks here first. The
+//! FreeType faces of fonts are also where we read the metrics that the toy
+//! text API doesn't expose.
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_long, c_void};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use cairo::{FontFace, FontSlant, FontType, FontWeight, ScaledFont};
+use cairo_sys as ffi;
+
+use piet::{Error, FontFamily};
+
+type FtLibrary = *mut c_void;
+type FtFace = *mut FtFaceRec;
+
+/// The start of FreeType's `FT_FaceRec`, as far as the fields we read.
+#[repr(C)]
+struct FtFaceRec {
+    num_faces: c_long,
+    face_index: c_long,
+    face_flags: c_long,
+    style_flags: c_long,
+    num_glyphs: c_long,
+    family_name: *const c_char,
+    style_name: *const c_char,
+    num_fixed_sizes: c_int,
+    available_sizes: *mut c_void,
+    num_charmaps: c_int,
+    charmaps: *mut c_void,
+    generic: [*mut c_void; 2],
+    bbox: [c_long; 4],
+    units_per_em: u16,
+    ascender: i16,
+    descender: i16,
+    height: i16,
+    max_advance_width: i16,
+    max_advance_height: i16,
+    /// the center of the underline, from the `post` table.
+    underline_position: i16,
+    underline_thickness: i16,
+}
+
+/// The start of FreeType's `TT_OS2`, as far as the fields we read.
+#[repr(C)]
+struct TtOs2 {
+    version: u16,
+    x_avg_char_width: i16,
+    us_weight_class: u16,
+    us_width_class: u16,
+    fs_type: u16,
+    y_subscript: [i16; 4],
+    y_superscript: [i16; 4],
+    y_strikeout_size: i16,
+    /// the top of the strikeout.
+    y_strikeout_position: i16,
+}
+
+const FT_STYLE_FLAG_ITALIC: c_long = 1;
+const FT_STYLE_FLAG_BOLD: c_long = 2;
+const FT_SFNT_OS2: c_int = 2;
+
+#[link(name = "freetype")]
+extern "C" {
+    fn FT_Init_FreeType(library: *mut FtLibrary) -> c_int;
+    fn FT_New_Memory_Face(
+        library: FtLibrary,
+        data: *const u8,
+        size: c_long,
+        index: c_long,
+        face: *mut FtFace,
+    ) -> c_int;
+    fn FT_Done_Face(face: FtFace) -> c_int;
+    fn FT_Get_Sfnt_Table(face: FtFace, tag: c_int) -> *mut c_void;
+}
+
+extern "C" {
+    fn cairo_ft_font_face_create_for_ft_face(
+        face: FtFace,
+        load_flags: c_int,
+    ) -> *mut ffi::cairo_font_face_t;
+    fn cairo_ft_scaled_font_lock_face(scaled_font: *mut ffi::cairo_scaled_font_t) -> FtFace;
+    fn cairo_ft_scaled_font_unlock_face(scaled_font: *mut ffi::cairo_scaled_font_t);
+}
+
+/// The key of the data that keeps a FreeType face alive with its cairo face.
+static FACE_KEY: ffi::cairo_user_data_key_t = ffi::cairo_user_data_key_t { unused: 0 };
+
+/// The loaded fonts, and the FreeType library they were loaded with.
+///
+/// FreeType libraries aren't thread safe, so faces are only created with
+/// the lock held.
+static LOADED: Mutex<Option<Loaded>> = Mutex::new(None);
+
+struct Loaded {
+    library: FtLibrary,
+    faces: Vec<LoadedFace>,
+}
+
+struct LoadedFace {
+    family: String,
+    bold: bool,
+    italic: bool,
+    face: FontFace,
+}
+
+// cairo font faces are reference counted atomically, and the library is
+// only used with the lock held.
+unsafe impl Send for Loaded {}
+
+/// What a cairo face owns: the FreeType face, and the data it was loaded from.
+struct FaceData {
+    face: FtFace,
+    _data: Arc<[u8]>,
+}
+
+unsafe extern "C" fn destroy_face_data(data: *mut c_void) {
+    // loaded faces are kept for the rest of the program, so this only runs
+    // for faces that fail to load, with the lock still held
+    let data = Box::from_raw(data as *mut FaceData);
+    FT_Done_Face(data.face);
+}
+
+/// Load the faces in `data`, which may be a collection, and return the
+/// family of the first.
+pub(crate) fn load(data: &[u8]) -> Result<FontFamily, Error> {
+    let mut loaded = LOADED.lock().unwrap_or_else(PoisonError::into_inner);
+    if loaded.is_none() {
+        let mut library = std::ptr::null_mut();
+        if unsafe { FT_Init_FreeType(&mut library) } != 0 {
+            return Err(Error::BackendError(
+                "FreeType could not be initialized".into(),
+            ));
+        }
+        *loaded = Some(Loaded {
+            library,
+            faces: Vec::new(),
+        });
+    }
+    let loaded = loaded.as_mut().unwrap();
+    let data: Arc<[u8]> = data.into();
+    let mut faces = Vec::new();
+    let mut count = 1;
+    while faces.len() < count {
+        let face = unsafe { load_face(loaded.library, &data, faces.len()) }?;
+        count = face.0;
+        faces.push(face.1);
+    }
+    let family = FontFamily::new_unchecked(faces[0].family.as_str());
+    loaded.faces.extend(faces);
+    Ok(family)
+}
+
+/// Load a face of the font in `data`, returning the number of faces in it.
+unsafe fn load_face(
+    library: FtLibrary,
+    data: &Arc<[u8]>,
+    index: usize,
+) -> Result<(usize, LoadedFace), Error> {
+    let mut face = std::ptr::null_mut();
+    let size = data.len() as c_long;
+    // FreeType checks the data, and fails rather than reading past it
+    if FT_New_Memory_Face(library, data.as_ptr(), size, index as c_long, &mut face) != 0 {
+        return Err(Error::MissingFont);
+    }
+    let rec = &*face;
+    let family = match rec.family_name {
+        name if name.is_null() => None,
+        name => Some(CStr::from_ptr(name).to_string_lossy().into_owned()),
+    };
+    let family = match family {
+        Some(family) if !family.is_empty() => family,
+        _ => {
+            FT_Done_Face(face);
+            return Err(Error::MissingFont);
+        }
+    };
+    let count = rec.num_faces.max(1) as usize;
+    let (bold, italic) = (
+        rec.style_flags & FT_STYLE_FLAG_BOLD != 0,
+        rec.style_flags & FT_STYLE_FLAG_ITALIC != 0,
+    );
+
+    let cairo_face = cairo_ft_font_face_create_for_ft_face(face, 0);
+    let owned = Box::into_raw(Box::new(FaceData {
+        face,
+        _data: data.clone(),
+    }));
+    let status = ffi::cairo_font_face_set_user_data(
+        cairo_face,
+        &FACE_KEY,
+        owned as *mut c_void,
+        Some(destroy_face_data),
+    );
+    if status != ffi::STATUS_SUCCESS {
+        ffi::cairo_font_face_destroy(cairo_face);
+        destroy_face_data(owned as *mut c_void);
+        return Err(Error::MissingFont);
+    }
+    let face = LoadedFace {
+        family,
+        bold,
+        italic,
+        face: FontFace::from_raw_full(cairo_face),
+    };
+    Ok((count, face))
+}
+
+/// The loaded face of `family` closest to the style, if it was loaded.
+pub(crate) fn face(family: &str, slant: FontSlant, weight: FontWeight) -> Option<FontFace> {
+    let loaded = LOADED.lock().unwrap_or_else(PoisonError::into_inner);
+    let family = family.to_lowercase();
+    let italic = slant != FontSlant::Normal;
+    let bold = weight == FontWeight::Bold;
+    // a matching slant matters more than a matching weight, and the last
+    // face loaded in a style replaces any earlier ones
+    loaded
+        .as_ref()?
+        .faces
+        .iter()
+        .rev()
+        .filter(|face| face.family.to_lowercase() == family)
+        .min_by_key(|face| 2 * (face.italic != italic) as u8 + (face.bold != bold) as u8)
+        .map(|face| face.face.clone())
+}
+
+/// The offset from the baseline to the center of a decoration line, and its
+/// thickness.
+pub(crate) type DecorationLine = (f64, f64);
+
+/// The underline and the strikeout of `font`, scaled to the font's size.
+///
+/// These are read from the `post` and `OS/2` tables of the font's FreeType
+/// face; either is `None` if the font doesn't have it.
+pub(crate) fn decoration_metrics(
+    font: &ScaledFont,
+) -> (Option<DecorationLine>, Option<DecorationLine>) {
+    if font.get_type() != FontType::FontTypeFt {
+        return (None, None);
+    }
+    let raw = font.to_raw_none();
+    unsafe {
+        let face = cairo_ft_scaled_font_lock_face(raw);
+        if face.is_null() {
+            return (None, None);
+        }
+        let rec = &*face;
+        let scale = font.get_font_matrix().yy / f64::from(rec.units_per_em.max(1));
+        let underline = match rec.underline_thickness {
+            thickness if thickness > 0 => Some((
+                -f64::from(rec.underline_position) * scale,
+                f64::from(thickness) * scale,
+            )),
+            _ => None,
+        };
+        let os2 = FT_Get_Sfnt_Table(face, FT_SFNT_OS2) as *const TtOs2;
+        let strikeout = match os2.as_ref() {
+            Some(os2) if os2.y_strikeout_size > 0 => {
+                let size = f64::from(os2.y_strikeout_size);
+                let center = f64::from(os2.y_strikeout_position) - size / 2.0;
+                Some((-center * scale, size * scale))
+            }
+            _ => None,
+        };
+        cairo_ft_scaled_font_unlock_face(raw);
+        (underline, strikeout)
+    }
+}
@@ -1,33 +1,56 @@
-use cairo::ScaledFont;
-use piet::HitTestPoint;
+use piet::{Affinity, HitTestPoint};
 use unicode_segmentation::UnicodeSegmentation;
 
-use super::hit_test_line_position;
+use super::{is_hard_break, LineMeasure};
+
+/// The offset and x position of the leading edge of each grapheme of a line,
+/// followed by the offset of the end of the line and its advance.
+///
+/// These are computed once for each line when the layout is built, so that
+/// hit testing doesn't have to measure the text again.
+pub(crate) fn grapheme_edges(measure: LineMeasure, text: &str) -> Vec<(usize, f64)> {
+    let mut edges = Vec::new();
+    let mut x = 0.0;
+    // there's no letter spacing after the last grapheme of the line
+    let content_end = text.trim_end_matches(is_hard_break).len();
+    let trailing_spacing = measure.trailing_letter_spacing(text);
+    for (idx, grapheme) in text.grapheme_indices(true) {
+        if idx == content_end && idx > 0 {
+            x -= trailing_spacing;
+        }
+        edges.push((idx, x));
+        x = match measure.tab_stops {
+            Some(stops) if grapheme == "\t" => stops.next_stop(x),
+            _ => x + measure.run_advance(grapheme, idx),
+        };
+    }
+    // measured as a whole, to match the width of the line exactly
+    edges.push((text.len(), measure.line_advance(text)));
+    edges
+}
 
 /// get grapheme boundaries, intended to act on a line of text, not a full text layout that has
 /// both horizontal and vertial components
 pub(crate) fn get_grapheme_boundaries(
-    font: &ScaledFont,
-    text: &str,
+    edges: &[(usize, f64)],
     grapheme_position: usize,
 ) -> Option<GraphemeBoundaries> {
-    let mut graphemes = UnicodeSegmentation::grapheme_indices(text, true);
-    let (text_position, _) = graphemes.nth(grapheme_position)?;
-    let (next_text_position, _) = graphemes.next().unwrap_or_else(|| (text.len(), ""));
-
-    let curr_edge = hit_test_line_position(font, text, text_position)?;
-    let next_edge = hit_test_line_position(font, text, next_text_position)?;
-
-    let res = GraphemeBoundaries {
-        curr_idx: text_position,
-        next_idx: next_text_position,
-        leading: curr_edge,
-        trailing: next_edge,
-    };
-
-    Some(res)
+    let (curr_idx, leading) = *edges.get(grapheme_position)?;
+    let (next_idx, trailing) = *edges.get(grapheme_position + 1)?;
+    Some(GraphemeBoundaries {
+        curr_idx,
+        next_idx,
+        leading,
+        trailing,
+    })
 }
 
+/// The hit of `point_x` in a grapheme, if it is between the grapheme's
+/// boundaries.
+///
+/// The trailing edge of a right-to-left grapheme is to the left of its
+/// leading edge, so that the hit of a point on its left half is the
+/// position after it.
 pub(crate) fn point_x_in_grapheme(
     point_x: f64,
     grapheme_boundaries: &GraphemeBoundaries,
@@ -36,18 +59,30 @@ pub(crate) fn point_x_in_grapheme(
     let trailing = grapheme_boundaries.trailing;
     let curr_idx = grapheme_boundaries.curr_idx;
     let next_idx = grapheme_boundaries.next_idx;
+    let mirrored = trailing < leading;
+    let (left, right) = if mirrored {
+        (trailing, leading)
+    } else {
+        (leading, trailing)
+    };
 
-    if point_x >= leading && point_x <= trailing {
+    if point_x >= left && point_x <= right {
         // Check which boundary it's closer to.
         // Round up to next grapheme boundary if
         let midpoint = leading + ((trailing - leading) / 2.0);
         let is_inside = true;
-        let idx = if point_x >= midpoint {
-            next_idx
+        // a hit on the trailing half belongs with this grapheme
+        let past_midpoint = if mirrored {
+            point_x <= midpoint
+        } else {
+            point_x >= midpoint
+        };
+        let (idx, affinity) = if past_midpoint {
+            (next_idx, Affinity::Upstream)
         } else {
-            curr_idx
+            (curr_idx, Affinity::Downstream)
         };
-        Some(HitTestPoint::new(idx, is_inside))
+        Some(HitTestPoint::new(idx, is_inside, affinity))
     } else {
         None
     }
@@ -80,15 +115,16 @@ mod test {
         };
 
         // test grapheme boundaries
+        let edges = grapheme_edges(LineMeasure::new(&font), text);
         assert_eq!(
-            get_grapheme_boundaries(&font, text, 3).unwrap().curr_idx,
+            get_grapheme_boundaries(&edges, 3).unwrap().curr_idx,
             expected_3.curr_idx
         );
         assert_eq!(
-            get_grapheme_boundaries(&font, text, 3).unwrap().next_idx,
+            get_grapheme_boundaries(&edges, 3).unwrap().next_idx,
             expected_3.next_idx
         );
-        assert_eq!(get_grapheme_boundaries(&font, text, 4), None);
+        assert_eq!(get_grapheme_boundaries(&edges, 4), None);
     }
 
     #[test]
@@ -100,12 +136,33 @@ mod test {
             trailing: 14.0,
         };
 
-        let expected_curr = Some(HitTestPoint::new(2, true));
-        let expected_next = Some(HitTestPoint::new(4, true));
+        let expected_curr = Some(HitTestPoint::new(2, true, Affinity::Downstream));
+        let expected_next = Some(HitTestPoint::new(4, true, Affinity::Upstream));
 
         assert_eq!(point_x_in_grapheme(10.0, &bounds), expected_curr);
         assert_eq!(point_x_in_grapheme(11.0, &bounds), expected_curr);
         assert_eq!(point_x_in_grapheme(12.0, &bounds), expected_next);
         assert_eq!(point_x_in_grapheme(13.0, &bounds), expected_next);
+        assert_eq!(point_x_in_grapheme(15.0, &bounds), None);
+    }
+
+    #[test]
+    fn test_x_in_rtl_grapheme_boundaries() {
+        let bounds = GraphemeBoundaries {
+            curr_idx: 2,
+            next_idx: 4,
+            leading: 14.0,
+            trailing: 10.0,
+        };
+
+        let expected_curr = Some(HitTestPoint::new(2, true, Affinity::Downstream));
+        let expected_next = Some(HitTestPoint::new(4, true, Affinity::Upstream));
+
+        assert_eq!(point_x_in_grapheme(10.0, &bounds), expected_next);
+        assert_eq!(point_x_in_grapheme(11.0, &bounds), expected_next);
+        assert_eq!(point_x_in_grapheme(12.0, &bounds), expected_next);
+        assert_eq!(point_x_in_grapheme(13.0, &bounds), expected_curr);
+        assert_eq!(point_x_in_grapheme(14.0, &bounds), expected_curr);
+        assert_eq!(point_x_in_grapheme(9.0, &bounds), None);
     }
 }
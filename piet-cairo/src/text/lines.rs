@@ -1,9 +1,16 @@
-use cairo::ScaledFont;
 use xi_unicode::LineBreakIterator;
 
-use super::LineMetric;
+use piet::{util, LineSpacing};
 
-pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64) -> Vec<LineMetric> {
+use super::{LineMeasure, LineMetric};
+
+pub(crate) fn calculate_line_metrics(
+    text: &str,
+    measure: LineMeasure,
+    line_spacing: LineSpacing,
+    break_opportunities: Option<&[usize]>,
+    width: f64,
+) -> Vec<LineMetric> {
     // first pass, completely naive and inefficient. Check at every break to see if line longer
     // than width.
     //
@@ -36,14 +43,17 @@ pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64)
     let mut y_offset = 0.0;
 
     // vertical measures constant across all lines for now (cairo toy text)
-    let height = font.extents().height;
-    let baseline = font.extents().ascent;
+    let (height, baseline) = util::resolve_line_spacing(
+        line_spacing,
+        measure.font.extents().height,
+        measure.font.extents().ascent,
+    );
 
-    for (line_break, is_hard_break) in LineBreakIterator::new(text) {
+    for (line_break, is_hard_break) in line_breaks(text, break_opportunities) {
         if !is_hard_break {
             // this section is for soft breaks
             let curr_str = &text[line_start..line_break];
-            let curr_width = font.text_extents(curr_str).x_advance;
+            let curr_width = measure.at(line_start).wrap_advance(curr_str);
 
             if curr_width > width {
                 // since curr_width is longer than desired line width, it's time to break ending
@@ -62,6 +72,7 @@ pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64)
                 // first do the line to prev break
                 add_line_metric(
                     text,
+                    measure,
                     line_start,
                     prev_break,
                     baseline,
@@ -77,11 +88,12 @@ pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64)
                 // If it's shorter than desired width, just continue.
 
                 let curr_str = &text[prev_break..line_break];
-                let curr_width = font.text_extents(curr_str).x_advance;
+                let curr_width = measure.at(prev_break).wrap_advance(curr_str);
 
                 if curr_width > width {
                     add_line_metric(
                         text,
+                        measure,
                         prev_break,
                         line_break,
                         baseline,
@@ -107,7 +119,7 @@ pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64)
             // even when there's a hard break, need to check first to see if width is too wide. If
             // it is, need to break at the previous soft break first.
             let curr_str = &text[line_start..line_break];
-            let curr_width = font.text_extents(curr_str).x_advance;
+            let curr_width = measure.at(line_start).wrap_advance(curr_str);
 
             if curr_width > width {
                 // if line is too wide but can't break down anymore, just skip to the next
@@ -119,6 +131,7 @@ pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64)
                 if prev_break != line_start {
                     add_line_metric(
                         text,
+                        measure,
                         line_start,
                         prev_break,
                         baseline,
@@ -134,6 +147,7 @@ pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64)
             // now do the hard break
             add_line_metric(
                 text,
+                measure,
                 line_start,
                 line_break,
                 baseline,
@@ -149,8 +163,30 @@ pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64)
     line_metrics
 }
 
+/// The offsets at which `text` may be broken, and whether each break is
+/// mandatory; `break_opportunities`, if set, replaces the soft breaks found
+/// by the unicode line breaking algorithm.
+fn line_breaks(text: &str, break_opportunities: Option<&[usize]>) -> Vec<(usize, bool)> {
+    let breaks = LineBreakIterator::new(text);
+    match break_opportunities {
+        None => breaks.collect(),
+        Some(soft_breaks) => {
+            let mut breaks: Vec<_> = breaks
+                .filter(|(_, is_hard_break)| *is_hard_break)
+                .chain(soft_breaks.iter().map(|offset| (*offset, false)))
+                .collect();
+            // a hard break takes precedence over a soft break at the same offset
+            breaks.sort_by_key(|(offset, is_hard_break)| (*offset, !is_hard_break));
+            breaks.dedup_by_key(|(offset, _)| *offset);
+            breaks
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn add_line_metric(
     text: &str,
+    measure: LineMeasure,
     start_offset: usize,
     end_offset: usize,
     baseline: f64,
@@ -160,12 +196,14 @@ fn add_line_metric(
 ) {
     let line = &text[start_offset..end_offset];
     let trailing_whitespace = count_trailing_whitespace(line);
+    let trailing_whitespace_width = measure_trailing_whitespace(line, measure.at(start_offset));
 
     #[allow(deprecated)]
     let line_metric = LineMetric {
         start_offset,
         end_offset,
         trailing_whitespace,
+        trailing_whitespace_width,
         baseline,
         height,
         y_offset: *y_offset,
@@ -174,10 +212,34 @@ fn add_line_metric(
     *y_offset += height;
 }
 
-// TODO: is non-breaking space trailing whitespace? Check with dwrite and
-// coretext
+/// The advance of the whitespace at the end of a line, not including any newline.
+///
+/// This is measured from the start of the line, so that tabs advance to
+/// the correct stops.
+fn measure_trailing_whitespace(line: &str, measure: LineMeasure) -> f64 {
+    let content = trim_trailing_whitespace(line);
+    let whitespace_end = line.trim_end_matches(&['\n', '\r'][..]).len();
+    if whitespace_end <= content.len() {
+        return 0.0;
+    }
+    measure.advance(&line[..whitespace_end]) - measure.advance(content)
+}
+
+/// The length in bytes of the whitespace at the end of a line, including
+/// any newline.
 fn count_trailing_whitespace(line: &str) -> usize {
-    line.chars().rev().take_while(|c| c.is_whitespace()).count()
+    line.len() - trim_trailing_whitespace(line).len()
+}
+
+/// `line` without its trailing whitespace, which hangs past the end of the
+/// line. No-break spaces hold the text around them together, so they're not
+/// trailing whitespace.
+pub(crate) fn trim_trailing_whitespace(line: &str) -> &str {
+    line.trim_end_matches(|c: char| c.is_whitespace() && !is_no_break_space(c))
+}
+
+fn is_no_break_space(c: char) -> bool {
+    matches!(c, '\u{A0}' | '\u{2007}' | '\u{202F}')
 }
 
 #[cfg(test)]
@@ -191,7 +253,13 @@ mod test {
         input: &str,
         font: &ScaledFont,
     ) {
-        let line_metrics = calculate_line_metrics(input, &font, width);
+        let line_metrics = calculate_line_metrics(
+            input,
+            LineMeasure::new(&font),
+            LineSpacing::Default,
+            None,
+            width,
+        );
 
         for (i, (metric, exp)) in line_metrics.iter().zip(expected).enumerate() {
             println!("calculated: {:?}\nexpected: {:?}", metric, exp);
@@ -217,7 +285,13 @@ mod test {
         let width = 50.0;
 
         let font = CairoFont::new(FontFamily::SANS_SERIF).resolve_simple(12.0);
-        let line_metrics = calculate_line_metrics(input, &font, width);
+        let line_metrics = calculate_line_metrics(
+            input,
+            LineMeasure::new(&font),
+            LineSpacing::Default,
+            None,
+            width,
+        );
 
         // Some print debugging, in case font size/width needs to be changed in future because of
         // brittle tests
@@ -247,7 +321,13 @@ mod test {
         let width = 50.0;
 
         let font = CairoFont::new(FontFamily::SANS_SERIF).resolve_simple(14.0);
-        let line_metrics = calculate_line_metrics(input, &font, width);
+        let line_metrics = calculate_line_metrics(
+            input,
+            LineMeasure::new(&font),
+            LineSpacing::Default,
+            None,
+            width,
+        );
 
         // Some print debugging, in case font size/width needs to be changed in future because of
         // brittle tests
@@ -275,7 +355,13 @@ mod test {
         let width = 10.0;
 
         let font = CairoFont::new(FontFamily::SANS_SERIF).resolve_simple(12.0);
-        let line_metrics = calculate_line_metrics(input, &font, width);
+        let line_metrics = calculate_line_metrics(
+            input,
+            LineMeasure::new(&font),
+            LineSpacing::Default,
+            None,
+            width,
+        );
 
         // Some print debugging, in case font size/width needs to be changed in future because of
         // brittle tests
@@ -327,6 +413,7 @@ mod test {
                 y_offset: 0.,
                 baseline: 12.0,
                 height: 14.0,
+                ..Default::default()
             },
             LineMetric {
                 start_offset: 5,
@@ -335,6 +422,7 @@ mod test {
                 y_offset: 14.0,
                 baseline: 12.0,
                 height: 14.0,
+                ..Default::default()
             },
             LineMetric {
                 start_offset: 10,
@@ -343,6 +431,7 @@ mod test {
                 y_offset: 28.0,
                 baseline: 12.0,
                 height: 14.0,
+                ..Default::default()
             },
             LineMetric {
                 start_offset: 15,
@@ -351,6 +440,7 @@ mod test {
                 y_offset: 42.0,
                 baseline: 12.0,
                 height: 14.0,
+                ..Default::default()
             },
         ];
 
@@ -363,6 +453,7 @@ mod test {
                 y_offset: 0.0,
                 baseline: 12.0,
                 height: 14.0,
+                ..Default::default()
             },
             LineMetric {
                 start_offset: 10,
@@ -371,6 +462,7 @@ mod test {
                 y_offset: 14.0,
                 baseline: 12.0,
                 height: 14.0,
+                ..Default::default()
             },
         ];
 
@@ -382,6 +474,7 @@ mod test {
             y_offset: 0.0,
             baseline: 12.0,
             height: 14.0,
+            ..Default::default()
         }];
 
         let empty_input = "";
@@ -392,6 +485,7 @@ mod test {
             y_offset: 0.0,
             baseline: 12.0,
             height: 14.0,
+            ..Default::default()
         }];
 
         // setup cairo layout
@@ -439,6 +533,7 @@ mod test {
                 y_offset: 0.0,
                 baseline: 12.0,
                 height: 14.0,
+                ..Default::default()
             },
             LineMetric {
                 start_offset: 5,
@@ -447,6 +542,7 @@ mod test {
                 y_offset: 14.0,
                 baseline: 12.0,
                 height: 14.0,
+                ..Default::default()
             },
             LineMetric {
                 start_offset: 10,
@@ -455,6 +551,7 @@ mod test {
                 y_offset: 28.0,
                 baseline: 12.0,
                 height: 14.0,
+                ..Default::default()
             },
             LineMetric {
                 start_offset: 15,
@@ -463,6 +560,7 @@ mod test {
                 y_offset: 42.0,
                 baseline: 12.0,
                 height: 14.0,
+                ..Default::default()
             },
         ];
 
@@ -472,10 +570,76 @@ mod test {
         test_metrics_with_width(width_small, expected_small, input, &font);
     }
 
+    /// The text and trailing whitespace of each line of `input`, wrapped to `width`.
+    fn wrap(input: &str, width: f64) -> Vec<(&str, usize)> {
+        let font = CairoFont::new(FontFamily::SANS_SERIF).resolve_simple(12.0);
+        let measure = LineMeasure::new(&font);
+        calculate_line_metrics(input, measure, LineSpacing::Default, None, width)
+            .iter()
+            .map(|lm| (&input[lm.range()], lm.trailing_whitespace))
+            .collect()
+    }
+
+    fn advance(text: &str) -> f64 {
+        let font = CairoFont::new(FontFamily::SANS_SERIF).resolve_simple(12.0);
+        font.text_extents(text).x_advance
+    }
+
+    #[test]
+    fn test_wrap_japanese() {
+        // there are no spaces, but lines can break between ideographs
+        let input = "日本語の文章は、単語の間に空白がありません。";
+        let width = advance("日本語") + 0.5;
+        let lines = wrap(input, width);
+        assert!(lines.len() > 1);
+        let rejoined: String = lines.iter().map(|(line, _)| *line).collect();
+        assert_eq!(rejoined, input);
+        for (line, trailing_whitespace) in lines {
+            assert!(advance(line) <= width, "{:?}", line);
+            assert_eq!(trailing_whitespace, 0);
+            // but not before closing punctuation
+            assert!(!line.starts_with(&['、', '。'][..]), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn test_wrap_no_break_spaces() {
+        let width = advance("piet text") - 1.0;
+        assert_eq!(
+            wrap("piet\u{A0}text is\u{A0}best", width),
+            vec![("piet\u{A0}text ", 1), ("is\u{A0}best", 0)]
+        );
+        // a no-break space before a break isn't trailing whitespace
+        assert_eq!(
+            wrap("piet\u{A0} text", width),
+            vec![("piet\u{A0} ", 1), ("text", 0)]
+        );
+    }
+
+    #[test]
+    fn test_wrap_hyphens() {
+        // at a tiny width, every break opportunity is taken: after soft
+        // hyphens, hyphens and spaces, but not at word joiners
+        let input = "extra\u{AD}ordinary hyphen-ated ab\u{2060}cd";
+        assert_eq!(
+            wrap(input, 1.0),
+            vec![
+                ("extra\u{AD}", 0),
+                ("ordinary ", 1),
+                ("hyphen-", 0),
+                ("ated ", 1),
+                ("ab\u{2060}cd", 0),
+            ]
+        );
+    }
+
     #[test]
     fn test_count_trailing_whitespace() {
         assert_eq!(count_trailing_whitespace(" 1 "), 1);
         assert_eq!(count_trailing_whitespace(" 2  "), 2);
         assert_eq!(count_trailing_whitespace(" 3  \n"), 3);
+        assert_eq!(count_trailing_whitespace("4\u{A0} "), 1);
+        // ideographic spaces are three bytes each
+        assert_eq!(count_trailing_whitespace("五\u{3000}\u{3000}"), 6);
     }
 }
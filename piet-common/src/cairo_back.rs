@@ -3,7 +3,7 @@
 
 //! Support for piet Cairo back-end.
 
-use cairo::{Context, Format, ImageSurface};
+use cairo::{Context, Format, ImageSurface, Status};
 #[cfg(feature = "png")]
 use png::{ColorType, Encoder};
 #[cfg(feature = "png")]
@@ -43,7 +43,7 @@ pub type PietTextLayoutBuilder = CairoTextLayoutBuilder;
 /// The associated image type for this backend.
 ///
 /// This type matches `RenderContext::Image`
-pub type Image = ImageSurface;
+pub type PietImage = CairoImage;
 
 /// A struct that can be used to create bitmap render contexts.
 ///
@@ -78,7 +78,8 @@ impl Device {
         height: usize,
         pix_scale: f64,
     ) -> Result<BitmapTarget, piet::Error> {
-        let surface = ImageSurface::create(Format::ARgb32, width as i32, height as i32).unwrap();
+        let surface = ImageSurface::create(Format::ARgb32, width as i32, height as i32)
+            .map_err(|status| CairoError::new("cairo_image_surface_create", status))?;
         let cr = Context::new(&surface);
         cr.scale(pix_scale, pix_scale);
         let phantom = Default::default();
@@ -112,6 +113,10 @@ impl<'a> BitmapTarget<'a> {
             return Err(piet::Error::NotSupported);
         }
         self.surface.flush();
+        match self.surface.status() {
+            Status::Success => (),
+            status => return Err(CairoError::new("cairo_surface_flush", status).into()),
+        }
         let stride = self.surface.get_stride() as usize;
         let width = self.surface.get_width() as usize;
         let height = self.surface.get_height() as usize;
@@ -132,8 +137,9 @@ impl<'a> BitmapTarget<'a> {
             let data = {
                 let data_ptr = cairo_sys::cairo_image_surface_get_data(self.surface.to_raw_none());
                 if data_ptr.is_null() {
-                    let err = cairo::BorrowError::from(cairo::Status::SurfaceFinished);
-                    return Err((Box::new(err) as Box<dyn std::error::Error>).into());
+                    let err =
+                        CairoError::new("cairo_image_surface_get_data", Status::SurfaceFinished);
+                    return Err(err.into());
                 }
                 std::slice::from_raw_parts(data_ptr, data_len)
             };
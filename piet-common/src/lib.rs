@@ -57,6 +57,8 @@ mod test {
     use super::*;
     use std::marker::PhantomData;
 
+    use kurbo::Rect;
+
     use static_assertions as sa;
 
     // Make sure all the common types exist and don't get accidentally removed
@@ -67,10 +69,179 @@ mod test {
         piet_text: PietText,
         piet_text_layout: PietTextLayout,
         piet_text_layout_builder: PietTextLayoutBuilder,
-        image: Image,
+        image: PietImage,
         _phantom: PhantomData<&'a ()>,
     }
 
     sa::assert_impl_all!(Device: Send);
     sa::assert_not_impl_any!(Device: Sync);
+
+    fn draw_sample(number: usize, replay: bool) -> Result<Vec<u8>, Error> {
+        let size = samples::get::<Piet>(number).size();
+        let mut device = Device::new().unwrap();
+        let mut target = device
+            .bitmap_target(size.width as usize, size.height as usize, 1.0)
+            .unwrap();
+        let mut rc = target.render_context();
+        if replay {
+            let mut recorder = record::RecordingRenderContext::new(rc.text().clone());
+            samples::get(number).draw(&mut recorder)?;
+            recorder.finish()?;
+            recorder.into_display_list().replay(&mut rc)?;
+        } else {
+            samples::get(number).draw(&mut rc)?;
+        }
+        rc.finish()?;
+        std::mem::drop(rc);
+        target.raw_pixels(ImageFormat::RgbaPremul)
+    }
+
+    #[test]
+    fn draw_image_orientations() {
+        // a 3x2 image, with every pixel a different color
+        let (width, height) = (3, 2);
+        let colors: Vec<[u8; 4]> = (0..6)
+            .map(|i| [i * 40, 255 - i * 40, i * 20, 255])
+            .collect();
+        let buf: Vec<u8> = colors.iter().flatten().copied().collect();
+        let rotations = [
+            ImageRotation::None,
+            ImageRotation::Clockwise90,
+            ImageRotation::Rotate180,
+            ImageRotation::Clockwise270,
+        ];
+        for &rotation in &rotations {
+            for &(flip_x, flip_y) in &[(false, false), (true, false), (false, true), (true, true)] {
+                let options = ImageDrawOptions::new()
+                    .flip_x(flip_x)
+                    .flip_y(flip_y)
+                    .rotation(rotation);
+                let quarter = matches!(
+                    rotation,
+                    ImageRotation::Clockwise90 | ImageRotation::Clockwise270
+                );
+                let (dst_width, dst_height) = if quarter {
+                    (height, width)
+                } else {
+                    (width, height)
+                };
+
+                let mut device = Device::new().unwrap();
+                let mut target = device.bitmap_target(5, 5, 1.0).unwrap();
+                let mut rc = target.render_context();
+                let image = rc
+                    .make_image(width, height, &buf, ImageFormat::RgbaPremul)
+                    .unwrap();
+                let dst_rect = Rect::new(1.0, 1.0, 1.0 + dst_width as f64, 1.0 + dst_height as f64);
+                rc.draw_image_with(
+                    &image,
+                    dst_rect,
+                    InterpolationMode::NearestNeighbor,
+                    options,
+                );
+                rc.finish().unwrap();
+                std::mem::drop(rc);
+                let pixels = target.raw_pixels(ImageFormat::RgbaPremul).unwrap();
+
+                let mut expected = vec![0; 5 * 5 * 4];
+                for y in 0..height {
+                    for x in 0..width {
+                        let fx = if flip_x { width - 1 - x } else { x };
+                        let fy = if flip_y { height - 1 - y } else { y };
+                        let (dx, dy) = match rotation {
+                            ImageRotation::None => (fx, fy),
+                            ImageRotation::Clockwise90 => (height - 1 - fy, fx),
+                            ImageRotation::Rotate180 => (width - 1 - fx, height - 1 - fy),
+                            ImageRotation::Clockwise270 => (fy, width - 1 - fx),
+                        };
+                        let i = ((dy + 1) * 5 + dx + 1) * 4;
+                        expected[i..i + 4].copy_from_slice(&colors[y * width + x]);
+                    }
+                }
+                assert!(pixels == expected, "{:?} differs", options);
+            }
+        }
+    }
+
+    #[test]
+    fn capture_image_area_round_trip() {
+        // a 3x2 image, with every pixel a different color
+        let (width, height) = (3, 2);
+        let colors: Vec<[u8; 4]> = (0..6)
+            .map(|i| [i * 40, 255 - i * 40, i * 20, 255])
+            .collect();
+        let buf: Vec<u8> = colors.iter().flatten().copied().collect();
+        for &scale in &[1.0, 2.0] {
+            let mut device = Device::new().unwrap();
+            let pix = scale as usize;
+            let mut target = device.bitmap_target(10 * pix, 5 * pix, scale).unwrap();
+            let mut rc = target.render_context();
+            rc.clear(Color::WHITE);
+            let image = rc
+                .make_image(width, height, &buf, ImageFormat::RgbaPremul)
+                .unwrap();
+            let dst_rect = Rect::new(1.0, 1.0, 4.0, 3.0);
+            rc.draw_image(&image, dst_rect, InterpolationMode::NearestNeighbor);
+
+            // the capture has the device pixels, so each image pixel is
+            // `scale` pixels across
+            let captured = rc.capture_image_area(dst_rect).unwrap();
+            let captured = rc.to_image_buf(&captured, ImageFormat::RgbaPremul).unwrap();
+            let pix = scale as usize;
+            assert_eq!(
+                (captured.width(), captured.height()),
+                (width * pix, height * pix)
+            );
+            let mut expected = Vec::new();
+            for y in 0..height * pix {
+                for x in 0..width * pix {
+                    expected.extend_from_slice(&colors[y / pix * width + x / pix]);
+                }
+            }
+            assert!(
+                captured.raw_pixels() == &expected[..],
+                "differs at scale {}",
+                scale
+            );
+
+            // drawing the capture back puts the same pixels elsewhere
+            let copy = rc
+                .make_image(
+                    captured.width(),
+                    captured.height(),
+                    captured.raw_pixels(),
+                    ImageFormat::RgbaPremul,
+                )
+                .unwrap();
+            let copy_rect = Rect::new(5.0, 1.0, 8.0, 3.0);
+            rc.draw_image(&copy, copy_rect, InterpolationMode::NearestNeighbor);
+            let recaptured = rc.capture_image_area(copy_rect).unwrap();
+            let recaptured = rc
+                .to_image_buf(&recaptured, ImageFormat::RgbaPremul)
+                .unwrap();
+            assert!(
+                recaptured.raw_pixels() == &expected[..],
+                "copy differs at scale {}",
+                scale
+            );
+            rc.finish().unwrap();
+        }
+    }
+
+    #[test]
+    fn replay_matches_direct_rendering() {
+        for number in 0..samples::SAMPLE_COUNT {
+            // skip the samples this backend can't draw
+            let direct = match draw_sample(number, false) {
+                Ok(pixels) => pixels,
+                Err(_) => continue,
+            };
+            let replayed = draw_sample(number, true).unwrap();
+            assert!(
+                direct == replayed,
+                "sample {} differs when replayed",
+                number
+            );
+        }
+    }
 }
@@ -1,6 +1,5 @@
 //! Support for piet Web back-end.
 
-use std::fmt;
 use std::marker::PhantomData;
 use std::path::Path;
 
@@ -42,7 +41,7 @@ pub type PietTextLayoutBuilder = WebTextLayoutBuilder;
 /// The associated image type for this backend.
 ///
 /// This type matches `RenderContext::Image`
-pub type Image = WebImage;
+pub type PietImage = WebImage;
 
 /// A struct that can be used to create bitmap render contexts.
 pub struct Device {
@@ -121,7 +120,7 @@ impl<'a> BitmapTarget<'a> {
         let img_data = self
             .context
             .get_image_data(0.0, 0.0, width as f64, height as f64)
-            .map_err(|jsv| piet::Error::BackendError(Box::new(JsError::new(jsv))))?;
+            .map_err(|jsv| WebError::new("getImageData", jsv))?;
 
         // ImageDate is in RGBA order. This should be the same as expected on the output.
         Ok(img_data.data().0)
@@ -172,22 +171,3 @@ impl<'a> BitmapTarget<'a> {
         Err(piet::Error::MissingFeature)
     }
 }
-
-#[derive(Clone, Debug)]
-struct JsError {
-    jsv: wasm_bindgen::JsValue,
-}
-
-impl JsError {
-    fn new(jsv: wasm_bindgen::JsValue) -> Self {
-        JsError { jsv }
-    }
-}
-
-impl fmt::Display for JsError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self.jsv)
-    }
-}
-
-impl std::error::Error for JsError {}
@@ -4,21 +4,26 @@ use std::borrow::Cow;
 use std::convert::TryInto;
 use std::ffi::c_void;
 use std::ops::Deref;
+use std::path::Path;
 
 use core_foundation::{
     array::{CFArray, CFArrayRef, CFIndex},
     attributed_string::CFMutableAttributedString,
-    base::{CFTypeID, TCFType},
+    base::{CFType, CFTypeID, TCFType},
+    boolean::CFBoolean,
     declare_TCFType,
     dictionary::{CFDictionary, CFDictionaryRef},
+    error::{CFError, CFErrorRef},
     impl_TCFType,
     number::CFNumber,
     string::{CFString, CFStringRef},
+    url::{CFURLRef, CFURL},
 };
 use core_foundation_sys::base::CFRange;
 use core_graphics::{
     base::CGFloat,
     color::CGColor,
+    context::CGContextRef,
     geometry::{CGPoint, CGRect, CGSize},
     path::CGPathRef,
 };
@@ -29,28 +34,33 @@ use core_text::{
     },
     font_collection::{self, CTFontCollection, CTFontCollectionRef},
     font_descriptor::{self, CTFontDescriptor},
+    font_manager::CTFontManagerCreateFontDescriptorsFromURL,
     frame::{CTFrame, CTFrameRef},
     framesetter::CTFramesetter,
     line::{CTLine, CTLineRef, TypographicBounds},
+    run::CTRun,
     string_attributes,
 };
 
-use unic_bidi::bidi_class::{BidiClass, BidiClassCategory};
-
 use piet::kurbo::Rect;
-use piet::{Color, FontFamily, FontFamilyInner, TextAlignment};
+use piet::{Color, FontFamily, FontFamilyInner, FontFeatures, TabStops, TextAlignment};
 
 #[derive(Clone)]
 pub(crate) struct AttributedString {
     pub(crate) inner: CFMutableAttributedString,
-    /// a guess as to text direction
-    rtl: bool,
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct Framesetter(CTFramesetter);
 #[derive(Debug, Clone)]
 pub(crate) struct Frame(pub(crate) CTFrame);
+// CoreText's layout objects are immutable once they're created, and
+// CoreFoundation's reference counting is atomic, so these can be moved to
+// another thread, which lets layouts be built off the main thread.
+unsafe impl Send for AttributedString {}
+unsafe impl Send for Framesetter {}
+unsafe impl Send for Frame {}
+
 #[derive(Debug, Clone)]
 pub(crate) struct Line<'a>(pub(crate) Cow<'a, CTLine>);
 
@@ -73,12 +83,19 @@ enum CTParagraphStyleSpecifier {
     //FirstLineHeadIndent = 1,
     //HeadIndent = 2,
     //TailIndent = 3,
-    //TabStops = 4,
-    //TabInterval = 5,
+    TabStops = 4,
+    TabInterval = 5,
     //LineBreakMode = 6,
     // there are many more of these
+    BaseWritingDirection = 13,
 }
 
+pub enum __CTTextTab {}
+type CTTextTabRef = *const __CTTextTab;
+
+declare_TCFType!(CTTextTab, CTTextTabRef);
+impl_TCFType!(CTTextTab, CTTextTabRef, CTTextTabGetTypeID);
+
 #[repr(u8)]
 enum CTTextAlignment {
     Left = 0,
@@ -88,6 +105,12 @@ enum CTTextAlignment {
     Natural = 4,
 }
 
+#[repr(i8)]
+enum CTWritingDirection {
+    LeftToRight = 0,
+    RightToLeft = 1,
+}
+
 #[repr(C)]
 struct CTParagraphStyleSetting {
     spec: CTParagraphStyleSpecifier,
@@ -117,6 +140,37 @@ impl CTParagraphStyleSetting {
             value_size: std::mem::size_of::<CTTextAlignment>(),
         }
     }
+
+    fn base_writing_direction(is_rtl: bool) -> Self {
+        static LTR: CTWritingDirection = CTWritingDirection::LeftToRight;
+        static RTL: CTWritingDirection = CTWritingDirection::RightToLeft;
+
+        let direction: *const CTWritingDirection = if is_rtl { &RTL } else { &LTR };
+
+        CTParagraphStyleSetting {
+            spec: CTParagraphStyleSpecifier::BaseWritingDirection,
+            value: direction as *const c_void,
+            value_size: std::mem::size_of::<CTWritingDirection>(),
+        }
+    }
+
+    /// `stops` must outlive the use of this setting.
+    fn tab_stops(stops: &CFArrayRef) -> Self {
+        CTParagraphStyleSetting {
+            spec: CTParagraphStyleSpecifier::TabStops,
+            value: stops as *const CFArrayRef as *const c_void,
+            value_size: std::mem::size_of::<CFArrayRef>(),
+        }
+    }
+
+    /// `interval` must outlive the use of this setting.
+    fn tab_interval(interval: &CGFloat) -> Self {
+        CTParagraphStyleSetting {
+            spec: CTParagraphStyleSpecifier::TabInterval,
+            value: interval as *const CGFloat as *const c_void,
+            value_size: std::mem::size_of::<CGFloat>(),
+        }
+    }
 }
 
 impl AttributedString {
@@ -125,15 +179,41 @@ impl AttributedString {
         let range = CFRange::init(0, 0);
         let cf_string = CFString::new(text);
         inner.replace_str(&cf_string, range);
-        let rtl = first_strong_rtl(text);
-        AttributedString { inner, rtl }
+        AttributedString { inner }
     }
 
-    pub(crate) fn set_alignment(&mut self, alignment: TextAlignment) {
-        let alignment = CTParagraphStyleSetting::alignment(alignment, self.rtl);
-        let settings = [alignment];
+    /// Set the paragraph style for the whole string; this is where the
+    /// alignment, the base writing direction, and the tab stops live.
+    pub(crate) fn set_paragraph_style(
+        &mut self,
+        alignment: TextAlignment,
+        is_rtl: bool,
+        tab_stops: Option<&TabStops>,
+    ) {
+        let mut settings = vec![
+            CTParagraphStyleSetting::alignment(alignment, is_rtl),
+            CTParagraphStyleSetting::base_writing_direction(is_rtl),
+        ];
+
+        // these are referenced by the settings, and must outlive them.
+        let (stops, interval) = match tab_stops {
+            Some(TabStops::Uniform(interval)) => (Vec::new(), *interval),
+            Some(stops @ TabStops::Explicit(positions)) => (
+                positions.iter().map(|x| text_tab(*x)).collect(),
+                stops.interval(),
+            ),
+            None => (Vec::new(), 0.0),
+        };
+        let stops = CFArray::from_CFTypes(&stops);
+        let stops_ref = stops.as_concrete_TypeRef();
+        if tab_stops.is_some() {
+            // an empty list replaces the default stops, leaving only the interval
+            settings.push(CTParagraphStyleSetting::tab_stops(&stops_ref));
+            settings.push(CTParagraphStyleSetting::tab_interval(&interval));
+        }
+
         unsafe {
-            let style = CTParagraphStyleCreate(settings.as_ptr(), 1);
+            let style = CTParagraphStyleCreate(settings.as_ptr(), settings.len());
             let style = CTParagraphStyle::wrap_under_create_rule(style);
             self.inner.set_attribute(
                 self.range(),
@@ -150,21 +230,48 @@ impl AttributedString {
         }
     }
 
-    #[allow(non_upper_case_globals)]
-    pub(crate) fn set_underline(&mut self, range: CFRange, underline: bool) {
-        const kCTUnderlineStyleNone: i32 = 0x00;
-        const kCTUnderlineStyleSingle: i32 = 0x01;
+    /// Set the extra space added after each character.
+    ///
+    /// Note that a value of `0.0` disables the font's own kerning.
+    pub(crate) fn set_kern(&mut self, range: CFRange, kern: f64) {
+        unsafe {
+            self.inner.set_attribute(
+                range,
+                string_attributes::kCTKernAttributeName,
+                &CFNumber::from(kern).as_CFType(),
+            )
+        }
+    }
 
-        let value = if underline {
-            kCTUnderlineStyleSingle
-        } else {
-            kCTUnderlineStyleNone
-        };
+    /// Set the vertical offset of the baseline; positive values raise the text.
+    pub(crate) fn set_baseline_offset(&mut self, range: CFRange, offset: f64) {
         unsafe {
             self.inner.set_attribute(
                 range,
-                string_attributes::kCTUnderlineStyleAttributeName,
-                &CFNumber::from(value).as_CFType(),
+                kCTBaselineOffsetAttributeName,
+                &CFNumber::from(offset).as_CFType(),
+            )
+        }
+    }
+
+    /// Set the language of the text, as a BCP 47 tag.
+    pub(crate) fn set_language(&mut self, range: CFRange, locale: &str) {
+        unsafe {
+            self.inner.set_attribute(
+                range,
+                kCTLanguageAttributeName,
+                &CFString::new(locale).as_CFType(),
+            )
+        }
+    }
+
+    /// Use the vertical forms of glyphs, for text in vertical lines.
+    pub(crate) fn set_vertical_forms(&mut self, range: CFRange) {
+        unsafe {
+            self.inner.set_attribute(
+                range,
+                string_attributes::kCTVerticalFormsAttributeName,
+                &CFBoolean::true_value().as_CFType(),
             )
         }
     }
@@ -250,6 +357,11 @@ impl<'a> Line<'a> {
         }
     }
 
+    /// The width of the whitespace at the end of the line.
+    pub(crate) fn get_trailing_whitespace_width(&self) -> CGFloat {
+        unsafe { CTLineGetTrailingWhitespaceWidth(self.0.as_concrete_TypeRef()) }
+    }
+
     pub(crate) fn get_string_index_for_position(&self, position: CGPoint) -> CFIndex {
         self.0.get_string_index_for_position(position)
     }
@@ -264,6 +376,21 @@ impl<'a> Line<'a> {
     pub(crate) fn get_offset_for_string_index(&self, index: CFIndex) -> CGFloat {
         self.0.get_string_offset_for_string_index(index)
     }
+
+    /// The runs of glyphs in this line, each of which has a single font.
+    pub(crate) fn glyph_runs(&self) -> CFArray<CTRun> {
+        self.0.glyph_runs()
+    }
+
+    /// Draw the line at the context's current text position.
+    pub(crate) fn draw(&self, ctx: &mut CGContextRef) {
+        unsafe {
+            CTLineDraw(
+                self.0.as_concrete_TypeRef(),
+                ctx as *mut CGContextRef as *mut c_void,
+            )
+        }
+    }
 }
 
 impl<'a> From<CTLine> for Line<'a> {
@@ -272,6 +399,24 @@ impl<'a> From<CTLine> for Line<'a> {
     }
 }
 
+/// The font a run of glyphs is drawn with, which is the font it resolved to
+/// after any font fallback.
+pub(crate) fn run_font(run: &CTRun) -> Option<CTFont> {
+    let key = unsafe { CFString::wrap_under_get_rule(string_attributes::kCTFontAttributeName) };
+    let attributes = run.attributes()?;
+    attributes.find(&key)?.downcast::<CTFont>()
+}
+
+/// Creates the regular style of `family` at `size`.
+pub(crate) fn font_for_family(family: &FontFamily, size: f64) -> CTFont {
+    let family_key =
+        unsafe { CFString::wrap_under_get_rule(font_descriptor::kCTFontFamilyNameAttribute) };
+    let family_name = ct_family_name(family, size);
+    let attributes = CFDictionary::from_CFType_pairs(&[(family_key, family_name.as_CFType())]);
+    let descriptor = font_descriptor::new_from_attributes(&attributes);
+    font::new_from_descriptor(&descriptor, size)
+}
+
 /// The apple system fonts can resolve to different concrete families at
 /// different point sizes (SF Text vs. SF Displaykj,w)
 pub(crate) fn ct_family_name(family: &FontFamily, size: f64) -> CFString {
@@ -326,22 +471,6 @@ fn create_font_comma_never_fail_period() -> CTFont {
     font::new_from_descriptor(&descriptor, 0.0)
 }
 
-//TODO: this will probably be shared at some point?
-/// A heurstic for text direction; returns `true` if, while enumerating characters
-/// in this string, a character in the 'R' (strong right-to-left) category is
-/// encountered before any character in the 'L' (strong left-to-right) category is.
-///
-/// See [Unicode technical report 9](https://unicode.org/reports/tr9/#Table_Bidirectional_Character_Types).
-fn first_strong_rtl(text: &str) -> bool {
-    text.chars()
-        // an upper bound on how many chars we'll check
-        .take(200)
-        .map(BidiClass::of)
-        .find(|c| c.category() == BidiClassCategory::Strong)
-        .map(|c| c.is_rtl())
-        .unwrap_or(false)
-}
-
 impl FontCollection {
     pub(crate) fn new_with_all_fonts() -> FontCollection {
         FontCollection(font_collection::create_for_all_families())
@@ -368,9 +497,92 @@ impl FontCollection {
     }
 }
 
+/// The font descriptor attribute (`kCTFontFeatureSettingsAttribute`) and
+/// value for these OpenType features.
+pub(crate) fn font_feature_settings(features: &FontFeatures) -> (CFString, CFType) {
+    unsafe {
+        let tag_key = CFString::wrap_under_get_rule(kCTFontOpenTypeFeatureTag);
+        let value_key = CFString::wrap_under_get_rule(kCTFontOpenTypeFeatureValue);
+        let settings: Vec<CFDictionary<CFString, CFType>> = features
+            .iter()
+            .map(|(tag, value)| {
+                let tag = CFString::new(&String::from_utf8_lossy(&tag));
+                CFDictionary::from_CFType_pairs(&[
+                    (tag_key.clone(), tag.as_CFType()),
+                    (value_key.clone(), CFNumber::from(value as i64).as_CFType()),
+                ])
+            })
+            .collect();
+        let key = CFString::wrap_under_get_rule(font_descriptor::kCTFontFeatureSettingsAttribute);
+        (key, CFArray::from_CFTypes(&settings).as_CFType())
+    }
+}
+
+/// The font descriptor attribute (`kCTFontCascadeListAttribute`) and value
+/// that make CoreText try `families`, in order, for characters missing from
+/// a font.
+pub(crate) fn font_cascade_list(families: &[FontFamily], size: f64) -> (CFString, CFType) {
+    unsafe {
+        let family_key = CFString::wrap_under_get_rule(font_descriptor::kCTFontFamilyNameAttribute);
+        let descriptors: Vec<CTFontDescriptor> = families
+            .iter()
+            .map(|family| {
+                let family_name = ct_family_name(family, size);
+                let attributes = CFDictionary::from_CFType_pairs(&[(
+                    family_key.clone(),
+                    family_name.as_CFType(),
+                )]);
+                font_descriptor::new_from_attributes(&attributes)
+            })
+            .collect();
+        let key = CFString::wrap_under_get_rule(font_descriptor::kCTFontCascadeListAttribute);
+        (key, CFArray::from_CFTypes(&descriptors).as_CFType())
+    }
+}
+
+/// Registers the fonts in the file at `path` for this process, and returns
+/// the family of the first of them, or `None` if the file has no fonts.
+///
+/// CoreText reads the file itself, as the fonts are used.
+pub(crate) fn register_fonts_for_path(path: &Path) -> Option<FontFamily> {
+    let url = CFURL::from_path(path, false)?;
+    unsafe {
+        let mut error: CFErrorRef = std::ptr::null_mut();
+        // this fails if the fonts are already registered, but then they are
+        // still available.
+        let registered = CTFontManagerRegisterFontsForURL(
+            url.as_concrete_TypeRef(),
+            kCTFontManagerScopeProcess,
+            &mut error,
+        );
+        if !registered && !error.is_null() {
+            CFError::wrap_under_create_rule(error);
+        }
+        let descriptors = CTFontManagerCreateFontDescriptorsFromURL(url.as_concrete_TypeRef());
+        if descriptors.is_null() {
+            return None;
+        }
+        let descriptors = CFArray::<CTFontDescriptor>::wrap_under_create_rule(descriptors);
+        let descriptor = descriptors.get(0)?;
+        Some(FontFamily::new_unchecked(descriptor.family_name()))
+    }
+}
+
+/// A left-aligned tab stop at this position.
+fn text_tab(position: f64) -> CTTextTab {
+    unsafe {
+        let tab = CTTextTabCreate(CTTextAlignment::Left as u8, position, std::ptr::null());
+        CTTextTab::wrap_under_create_rule(tab)
+    }
+}
+
 #[link(name = "CoreText", kind = "framework")]
 extern "C" {
     static kCTFontFamilyNameKey: CFStringRef;
+    static kCTBaselineOffsetAttributeName: CFStringRef;
+    static kCTLanguageAttributeName: CFStringRef;
+    static kCTFontOpenTypeFeatureTag: CFStringRef;
+    static kCTFontOpenTypeFeatureValue: CFStringRef;
 
     fn CTFrameGetLines(frame: CTFrameRef) -> CFArrayRef;
     fn CTFontCreateUIFontForLanguage(
@@ -383,11 +595,25 @@ extern "C" {
         settings: *const CTParagraphStyleSetting,
         count: usize,
     ) -> CTParagraphStyleRef;
+    fn CTTextTabGetTypeID() -> CFTypeID;
+    fn CTTextTabCreate(alignment: u8, location: f64, options: CFDictionaryRef) -> CTTextTabRef;
     fn CTLineGetImageBounds(line: CTLineRef, ctx: *mut c_void) -> CGRect;
+    fn CTLineGetTrailingWhitespaceWidth(line: CTLineRef) -> CGFloat;
+    fn CTLineDraw(line: CTLineRef, ctx: *mut c_void);
     fn CTFontCollectionCreateMatchingFontDescriptorsForFamily(
         collection: CTFontCollectionRef,
         family: CFStringRef,
         option: CFDictionaryRef,
     ) -> CFArrayRef;
     fn CTFontCopyName(font: CTFontRef, nameKey: CFStringRef) -> CFStringRef;
+    fn CTFontManagerRegisterFontsForURL(
+        font_url: CFURLRef,
+        scope: u32,
+        error: *mut CFErrorRef,
+    ) -> bool;
 }
+
+/// `CTFontManagerScope`; fonts registered with this scope are available to
+/// the current process until it exits.
+#[allow(non_upper_case_globals)]
+const kCTFontManagerScopeProcess: u32 = 1;
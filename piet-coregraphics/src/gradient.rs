@@ -2,18 +2,25 @@
 
 //! core graphics gradient support
 
+use std::sync::Arc;
+
 use core_foundation::array::CFArray;
 use core_graphics::{
-    base::CGFloat,
+    base::{kCGImageAlphaPremultipliedLast, kCGRenderingIntentDefault, CGFloat},
     color::CGColor,
     color_space::{kCGColorSpaceSRGB, CGColorSpace},
     context::CGContextRef,
-    geometry::CGPoint,
+    data_provider::CGDataProvider,
+    geometry::{CGAffineTransform, CGPoint, CGRect},
     gradient::{CGGradient, CGGradientDrawingOptions},
+    image::CGImage,
 };
 
-use piet::kurbo::Point;
-use piet::{Color, FixedGradient, FixedLinearGradient, FixedRadialGradient, GradientStop};
+use piet::kurbo::{Affine, Point, Rect};
+use piet::{
+    util, Color, FixedGradient, FixedLinearGradient, FixedRadialGradient, FixedSweepGradient,
+    GradientExtend, GradientStop,
+};
 
 /// A wrapper around CGGradient
 #[derive(Clone)]
@@ -27,6 +34,8 @@ impl Gradient {
         let cg_grad = match &gradient {
             FixedGradient::Linear(grad) => new_cg_gradient(&grad.stops),
             FixedGradient::Radial(grad) => new_cg_gradient(&grad.stops),
+            // unused; sweep gradients are rasterized in `fill`.
+            FixedGradient::Sweep(grad) => new_cg_gradient(&grad.stops),
         };
         Gradient {
             cg_grad,
@@ -36,30 +45,125 @@ impl Gradient {
 
     pub(crate) fn fill(&self, ctx: &mut CGContextRef, options: CGGradientDrawingOptions) {
         match self.piet_grad {
-            FixedGradient::Radial(FixedRadialGradient {
-                center,
-                origin_offset,
-                radius,
-                ..
-            }) => {
-                let start_center = to_cgpoint(center + origin_offset);
-                let end_center = to_cgpoint(center);
-                ctx.draw_radial_gradient(
-                    &self.cg_grad,
-                    start_center,
-                    0.0,
-                    end_center,
-                    radius as CGFloat,
-                    options,
-                );
+            FixedGradient::Radial(ref radial) => {
+                // CGGradients are circles, so we stretch the context to
+                // draw ellipses.
+                let (radial, transform) = radial.to_circular();
+                ctx.save();
+                ctx.concat_ctm(to_cgaffine(transform));
+                if radial.extend != GradientExtend::Pad {
+                    self.fill_radial_periods(ctx, &radial);
+                } else {
+                    let start_center = to_cgpoint(radial.center + radial.origin_offset);
+                    let end_center = to_cgpoint(radial.center);
+                    ctx.draw_radial_gradient(
+                        &self.cg_grad,
+                        start_center,
+                        0.0,
+                        end_center,
+                        radial.radii.x as CGFloat,
+                        options,
+                    );
+                }
+                ctx.restore();
+            }
+            FixedGradient::Linear(ref linear) if linear.extend != GradientExtend::Pad => {
+                self.fill_linear_periods(ctx, linear)
             }
             FixedGradient::Linear(FixedLinearGradient { start, end, .. }) => {
                 let start = to_cgpoint(start);
                 let end = to_cgpoint(end);
                 ctx.draw_linear_gradient(&self.cg_grad, start, end, options);
             }
+            FixedGradient::Sweep(ref sweep) => fill_sweep(ctx, sweep),
         }
     }
+
+    /// CoreGraphics can only pad gradients, so we repeat or reflect them by
+    /// drawing each period covering the clip separately.
+    fn fill_linear_periods(&self, ctx: &mut CGContextRef, linear: &FixedLinearGradient) {
+        let bounds = from_cgrect(ctx.clip_bounding_box());
+        let axis = linear.end - linear.start;
+        for k in util::linear_gradient_periods(linear, bounds) {
+            let mut start = linear.start + axis * k as f64;
+            let mut end = start + axis;
+            if linear.extend == GradientExtend::Reflect && k.rem_euclid(2) == 1 {
+                std::mem::swap(&mut start, &mut end);
+            }
+            let options = CGGradientDrawingOptions::empty();
+            ctx.draw_linear_gradient(&self.cg_grad, to_cgpoint(start), to_cgpoint(end), options);
+        }
+    }
+
+    /// See `fill_linear_periods`. Period `k` is the band between the circles
+    /// at `k` and `k + 1`, which we draw as a single two-circle gradient.
+    fn fill_radial_periods(&self, ctx: &mut CGContextRef, radial: &FixedRadialGradient) {
+        let bounds = from_cgrect(ctx.clip_bounding_box());
+        let origin = radial.center + radial.origin_offset;
+        let circle = |t: f64| {
+            let center = origin - radial.origin_offset * t;
+            (to_cgpoint(center), (radial.radii.x * t) as CGFloat)
+        };
+        for k in util::radial_gradient_periods(radial, bounds) {
+            let (mut start, mut start_radius) = circle(k as f64);
+            let (mut end, mut end_radius) = circle(k as f64 + 1.0);
+            if radial.extend == GradientExtend::Reflect && k.rem_euclid(2) == 1 {
+                std::mem::swap(&mut start, &mut end);
+                std::mem::swap(&mut start_radius, &mut end_radius);
+            }
+            let options = CGGradientDrawingOptions::empty();
+            ctx.draw_radial_gradient(&self.cg_grad, start, start_radius, end, end_radius, options);
+        }
+    }
+}
+
+/// CoreGraphics has no sweep gradients, so we rasterize the gradient into
+/// an image covering the current clip, at the resolution of the device.
+fn fill_sweep(ctx: &mut CGContextRef, sweep: &FixedSweepGradient) {
+    let bounds = ctx.clip_bounding_box();
+    let ctm = ctx.get_ctm();
+    let scale = (ctm.a * ctm.d - ctm.b * ctm.c).abs().sqrt().max(1.0);
+    let width = (bounds.size.width * scale).ceil() as usize;
+    let height = (bounds.size.height * scale).ceil() as usize;
+    if width == 0 || height == 0 {
+        return;
+    }
+    let mut buf = vec![0u8; width * height * 4];
+    for y in 0..height {
+        for x in 0..width {
+            let point = Point::new(
+                bounds.origin.x + (x as f64 + 0.5) / scale,
+                bounds.origin.y + (y as f64 + 0.5) / scale,
+            );
+            let (r, g, b, a) = sweep.color_at(point).as_rgba8();
+            let premul = |c: u8| ((c as u16 * a as u16 + 127) / 255) as u8;
+            let off = (y * width + x) * 4;
+            buf[off] = premul(r);
+            buf[off + 1] = premul(g);
+            buf[off + 2] = premul(b);
+            buf[off + 3] = a;
+        }
+    }
+    let data_provider = CGDataProvider::from_buffer(Arc::new(buf));
+    let image = CGImage::new(
+        width,
+        height,
+        8,
+        32,
+        width * 4,
+        &CGColorSpace::create_device_rgb(),
+        kCGImageAlphaPremultipliedLast,
+        &data_provider,
+        false,
+        kCGRenderingIntentDefault,
+    );
+    // CGImage is drawn flipped; see `draw_image`.
+    ctx.save();
+    ctx.translate(bounds.origin.x, bounds.origin.y + bounds.size.height);
+    ctx.scale(1.0, -1.0);
+    let rect = CGRect::new(&CGPoint::new(0.0, 0.0), &bounds.size);
+    ctx.draw_image(rect, &image);
+    ctx.restore();
 }
 
 fn new_cg_gradient(stops: &[GradientStop]) -> CGGradient {
@@ -80,6 +184,18 @@ fn new_cg_gradient(stops: &[GradientStop]) -> CGGradient {
     }
 }
 
+fn from_cgrect(rect: CGRect) -> Rect {
+    Rect::from_origin_size(
+        (rect.origin.x, rect.origin.y),
+        (rect.size.width, rect.size.height),
+    )
+}
+
 fn to_cgpoint(point: Point) -> CGPoint {
     CGPoint::new(point.x as CGFloat, point.y as CGFloat)
 }
+
+fn to_cgaffine(affine: Affine) -> CGAffineTransform {
+    let [a, b, c, d, tx, ty] = affine.as_coeffs();
+    CGAffineTransform::new(a, b, c, d, tx, ty)
+}
@@ -4,26 +4,38 @@
 
 mod ct_helpers;
 mod gradient;
+mod pattern;
 mod text;
 
 use std::borrow::Cow;
 use std::sync::Arc;
 
 use core_graphics::base::{
-    kCGImageAlphaLast, kCGImageAlphaPremultipliedLast, kCGRenderingIntentDefault, CGFloat,
+    kCGBitmapByteOrder32Little, kCGImageAlphaFirst, kCGImageAlphaLast,
+    kCGImageAlphaPremultipliedFirst, kCGImageAlphaPremultipliedLast, kCGRenderingIntentDefault,
+    CGFloat,
 };
+use core_graphics::color::CGColor;
 use core_graphics::color_space::CGColorSpace;
-use core_graphics::context::{CGContextRef, CGInterpolationQuality, CGLineCap, CGLineJoin};
+use core_graphics::context::{
+    CGBlendMode, CGContext, CGContextRef, CGInterpolationQuality, CGLineCap, CGLineJoin,
+};
 use core_graphics::data_provider::CGDataProvider;
 use core_graphics::geometry::{CGAffineTransform, CGPoint, CGRect, CGSize};
 use core_graphics::gradient::CGGradientDrawingOptions;
 use core_graphics::image::CGImage;
+use core_graphics::path::CGPath;
+use core_graphics::sys::{CGContextRef as CGContextPtr, CGPathRef as CGPathPtr};
+
+use core_foundation_sys::dictionary::CFDictionaryRef;
+use foreign_types::{ForeignType, ForeignTypeRef};
 
-use piet::kurbo::{Affine, PathEl, Point, QuadBez, Rect, Shape, Size};
+use piet::kurbo::{Affine, BezPath, PathEl, Point, QuadBez, Rect, Shape, Size, Vec2};
 
 use piet::{
-    Color, Error, FixedGradient, ImageFormat, InterpolationMode, IntoBrush, LineCap, LineJoin,
-    RenderContext, RoundInto, StrokeStyle,
+    util, BlendMode, Color, Error, FixedGradient, Image, ImageBuf, ImageFormat, InterpolationMode,
+    IntoBrush, LayerMask, LineCap, LineJoin, RenderContext, RoundInto, ShadowStyle, StrokeStyle,
+    TileMode,
 };
 
 pub use crate::text::{
@@ -32,6 +44,7 @@ pub use crate::text::{
 };
 
 use gradient::Gradient;
+use pattern::ImagePattern;
 
 // getting this to be a const takes some gymnastics
 const GRADIENT_DRAW_BEFORE_AND_AFTER: CGGradientDrawingOptions =
@@ -48,8 +61,19 @@ pub struct CoreGraphicsContext<'a> {
     // because of the relationship between cocoa and coregraphics (where cocoa
     // may be asked to flip the y-axis) we cannot trust the transform returned
     // by CTContextGetCTM. Instead we maintain our own stack, which will contain
-    // only those transforms applied by us.
-    transform_stack: Vec<Affine>,
+    // only those transforms applied by us, and the bounds of our clips.
+    state_stack: Vec<CtxState>,
+    // For each `save` or `save_layer`, whether it began a transparency layer.
+    layer_stack: Vec<bool>,
+    // kept as the context's alpha, except while a layer begins
+    opacity: util::OpacityStack,
+}
+
+#[derive(Clone, Copy, Default)]
+struct CtxState {
+    transform: Affine,
+    /// The bounds of the clip, before any of our transforms.
+    clip: Option<Rect>,
 }
 
 impl<'a> CoreGraphicsContext<'a> {
@@ -79,7 +103,9 @@ impl<'a> CoreGraphicsContext<'a> {
         CoreGraphicsContext {
             ctx,
             text: CoreGraphicsText::new_with_unique_state(),
-            transform_stack: Vec::new(),
+            state_stack: Vec::new(),
+            layer_stack: Vec::new(),
+            opacity: util::OpacityStack::new(),
         }
     }
 }
@@ -94,19 +120,42 @@ impl<'a> Drop for CoreGraphicsContext<'a> {
 pub enum Brush {
     Solid(Color),
     Gradient(Gradient),
+    Image(ImagePattern),
+}
+
+/// A CoreGraphics image, as a piet image.
+#[derive(Clone)]
+pub struct CoreGraphicsImage(CGImage);
+
+impl CoreGraphicsImage {
+    /// The underlying `CGImage`.
+    pub fn as_cgimage(&self) -> &CGImage {
+        &self.0
+    }
+}
+
+impl Image for CoreGraphicsImage {
+    fn size(&self) -> Size {
+        Size::new(self.0.width() as f64, self.0.height() as f64)
+    }
 }
 
 impl<'a> RenderContext for CoreGraphicsContext<'a> {
     type Brush = Brush;
     type Text = CoreGraphicsText;
     type TextLayout = CoreGraphicsTextLayout;
-    type Image = CGImage;
+    type Image = CoreGraphicsImage;
     //type StrokeStyle = StrokeStyle;
 
     fn clear(&mut self, color: Color) {
         let (r, g, b, a) = color.as_rgba();
+        // clearing ignores the blend mode and opacity
+        self.ctx.save();
+        self.ctx.set_blend_mode(CGBlendMode::Normal);
+        unsafe { CGContextSetAlpha(self.ctx.as_ptr(), 1.0) };
         self.ctx.set_rgb_fill_color(r, g, b, a);
         self.ctx.fill_rect(self.ctx.clip_bounding_box());
+        self.ctx.restore();
     }
 
     fn solid_brush(&mut self, color: Color) -> Brush {
@@ -114,10 +163,20 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
     }
 
     fn gradient(&mut self, gradient: impl Into<FixedGradient>) -> Result<Brush, Error> {
-        let gradient = Gradient::from_piet_gradient(gradient.into());
+        let gradient = util::resolve_color_interpolation(gradient.into());
+        let gradient = Gradient::from_piet_gradient(gradient);
         Ok(Brush::Gradient(gradient))
     }
 
+    fn make_image_brush(
+        &mut self,
+        image: &CoreGraphicsImage,
+        tile: TileMode,
+        transform: Affine,
+    ) -> Result<Brush, Error> {
+        Ok(Brush::Image(ImagePattern::new(&image.0, tile, transform)))
+    }
+
     /// Fill a shape.
     fn fill(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
         let brush = brush.make_brush(self, || shape.bounding_box());
@@ -133,6 +192,12 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
                 grad.fill(self.ctx, GRADIENT_DRAW_BEFORE_AND_AFTER);
                 self.ctx.restore();
             }
+            Brush::Image(pattern) => {
+                self.ctx.save();
+                self.ctx.clip();
+                pattern.fill(self.ctx);
+                self.ctx.restore();
+            }
         }
     }
 
@@ -150,14 +215,25 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
                 grad.fill(self.ctx, GRADIENT_DRAW_BEFORE_AND_AFTER);
                 self.ctx.restore();
             }
+            Brush::Image(pattern) => {
+                self.ctx.save();
+                self.ctx.eo_clip();
+                pattern.fill(self.ctx);
+                self.ctx.restore();
+            }
         }
     }
 
     fn clip(&mut self, shape: impl Shape) {
-        self.set_path(shape);
+        self.set_clip_path(shape);
         self.ctx.clip();
     }
 
+    fn clip_even_odd(&mut self, shape: impl Shape) {
+        self.set_clip_path(shape);
+        self.ctx.eo_clip();
+    }
+
     fn stroke(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, width: f64) {
         let brush = brush.make_brush(self, || shape.bounding_box());
         self.set_path(shape);
@@ -174,6 +250,13 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
                 grad.fill(self.ctx, GRADIENT_DRAW_BEFORE_AND_AFTER);
                 self.ctx.restore();
             }
+            Brush::Image(pattern) => {
+                self.ctx.save();
+                self.ctx.replace_path_with_stroked_path();
+                self.ctx.clip();
+                pattern.fill(self.ctx);
+                self.ctx.restore();
+            }
         }
     }
 
@@ -184,8 +267,24 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
         width: f64,
         style: &StrokeStyle,
     ) {
+        if let Some(mode) = style.snap_mode {
+            // the CTM maps to device pixels; being y-up doesn't change the grid
+            let transform = from_cgaffine(self.ctx.get_ctm());
+            let (path, width) = util::snap_stroke(shape, width, mode, transform);
+            let style = StrokeStyle {
+                snap_mode: None,
+                ..style.clone()
+            };
+            return self.stroke_styled(path, brush, width, &style);
+        }
         let brush = brush.make_brush(self, || shape.bounding_box());
-        self.set_path(shape);
+        if style.dash.is_some() {
+            // the dashes must start where they do on the path of the shape,
+            // which isn't where they do on CoreGraphics' own rects and ellipses
+            self.set_path(shape.into_bez_path(1e-3));
+        } else {
+            self.set_path(shape);
+        }
         self.set_stroke(width.round_into(), Some(style));
         match brush.as_ref() {
             Brush::Solid(color) => {
@@ -199,6 +298,13 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
                 grad.fill(self.ctx, GRADIENT_DRAW_BEFORE_AND_AFTER);
                 self.ctx.restore();
             }
+            Brush::Image(pattern) => {
+                self.ctx.save();
+                self.ctx.replace_path_with_stroked_path();
+                self.ctx.clip();
+                pattern.fill(self.ctx);
+                self.ctx.restore();
+            }
         }
     }
 
@@ -208,43 +314,127 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
 
     fn draw_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>) {
         let pos = pos.into();
+        // the layout's geometry is in the space of its lines, which are
+        // rotated if they are vertical.
+        let transform = Affine::translate(pos.to_vec2()) * layout.line_transform();
+        for (rect, color) in layout.background_rects() {
+            self.set_fill_color(&color);
+            self.ctx
+                .fill_rect(to_cgrect(transform.transform_rect_bbox(rect)));
+        }
+        for (path, shadow) in layout.shadow_paths() {
+            self.draw_text_shadow(&(transform * path), shadow);
+        }
+
         self.ctx.save();
         // inverted coordinate system; text is drawn from bottom left corner,
         // and (0, 0) in context is also bottom left.
-        self.ctx.translate(pos.x, layout.frame_size.height + pos.y);
+        self.ctx.concat_ctm(to_cgaffine(transform));
+        self.ctx.translate(0.0, layout.frame_size.height);
         self.ctx.scale(1.0, -1.0);
         layout.draw(self.ctx);
         self.ctx.restore();
+
+        for (rect, color) in layout.strikethrough_rects() {
+            self.set_fill_color(&color);
+            self.ctx
+                .fill_rect(to_cgrect(transform.transform_rect_bbox(rect)));
+        }
+
+        let thickness = layout.decoration_thickness();
+        for (path, style, color) in layout.underline_paths() {
+            self.stroke_styled(transform * path, &color, thickness, &style);
+        }
     }
 
     fn save(&mut self) -> Result<(), Error> {
         self.ctx.save();
-        let state = self.transform_stack.last().copied().unwrap_or_default();
-        self.transform_stack.push(state);
+        self.state_stack.push(self.current_state());
+        self.layer_stack.push(false);
+        Ok(())
+    }
+
+    fn save_layer(&mut self, opacity: f64) -> Result<(), Error> {
+        self.ctx.save();
+        self.state_stack.push(self.current_state());
+        // the alpha in effect when the layer begins is used to composite it
+        unsafe { CGContextSetAlpha(self.ctx.as_ptr(), opacity) };
+        self.begin_layer();
+        self.apply_opacity();
+        Ok(())
+    }
+
+    fn save_layer_with_mask(&mut self, mask: LayerMask<CoreGraphicsImage>) -> Result<(), Error> {
+        self.ctx.save();
+        self.state_stack.push(self.current_state());
+        // the clip in effect when the layer begins masks it
+        match mask {
+            LayerMask::Shape(path) => self.clip(path),
+            LayerMask::Image(image, rect) => self.clip_to_image(rect, &alpha_mask(&image.0)),
+        }
+        unsafe { CGContextSetAlpha(self.ctx.as_ptr(), 1.0) };
+        self.begin_layer();
+        self.apply_opacity();
         Ok(())
     }
 
     fn restore(&mut self) -> Result<(), Error> {
-        if self.transform_stack.pop().is_some() {
+        if self.state_stack.pop().is_some() {
+            if self.layer_stack.pop() == Some(true) {
+                unsafe { CGContextEndTransparencyLayer(self.ctx.as_ptr()) };
+            }
             // we're defensive about calling restore on the inner context,
             // because an unbalanced call will trigger an assert in C
             self.ctx.restore();
+            // the opacity isn't part of the state
+            self.apply_opacity();
             Ok(())
         } else {
             Err(Error::StackUnbalance)
         }
     }
 
+    fn set_blend_mode(&mut self, mode: BlendMode) -> Result<(), Error> {
+        let mode = match mode {
+            BlendMode::Normal => CGBlendMode::Normal,
+            BlendMode::Multiply => CGBlendMode::Multiply,
+            BlendMode::Screen => CGBlendMode::Screen,
+            BlendMode::Overlay => CGBlendMode::Overlay,
+            BlendMode::Darken => CGBlendMode::Darken,
+            BlendMode::Lighten => CGBlendMode::Lighten,
+            BlendMode::Clear => CGBlendMode::Clear,
+            BlendMode::SourceIn => CGBlendMode::SourceIn,
+            BlendMode::DestinationOut => CGBlendMode::DestinationOut,
+        };
+        self.ctx.set_blend_mode(mode);
+        Ok(())
+    }
+
+    fn push_opacity(&mut self, alpha: f64) {
+        // CoreGraphics fades each drawing operation by its alpha
+        self.opacity.push(alpha);
+        self.apply_opacity();
+    }
+
+    fn pop_opacity(&mut self) -> Result<(), Error> {
+        self.opacity.pop()?;
+        self.apply_opacity();
+        Ok(())
+    }
+
     fn finish(&mut self) -> Result<(), Error> {
+        if self.layer_stack.contains(&true) {
+            return Err(Error::StackUnbalance);
+        }
         Ok(())
     }
 
     fn transform(&mut self, transform: Affine) {
-        if let Some(last) = self.transform_stack.last_mut() {
-            *last *= transform;
-        } else {
-            self.transform_stack.push(transform);
-        }
+        let state = self.current_state();
+        self.set_current_state(CtxState {
+            transform: state.transform * transform,
+            ..state
+        });
         self.ctx.concat_ctm(to_cgaffine(transform));
     }
 
@@ -255,16 +445,42 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
         buf: &[u8],
         format: ImageFormat,
     ) -> Result<Self::Image, Error> {
-        let data = Arc::new(buf.to_owned());
-        let data_provider = CGDataProvider::from_buffer(data);
+        let stride = width * format.bytes_per_pixel();
+        self.make_image_with_stride(width, height, stride, buf, format)
+    }
+
+    fn make_image_with_stride(
+        &mut self,
+        width: usize,
+        height: usize,
+        stride: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<Self::Image, Error> {
+        util::check_image_stride(width, height, stride, buf.len(), format)?;
+        // the data provider should cover every row, including its padding
+        let mut data = buf[..buf.len().min(stride * height)].to_owned();
+        data.resize(stride * height, 0);
+        let data_provider = CGDataProvider::from_buffer(Arc::new(data));
         let (colorspace, bitmap_info, bytes) = match format {
             ImageFormat::Rgb => (CGColorSpace::create_device_rgb(), 0, 3),
+            ImageFormat::Grayscale => (CGColorSpace::create_device_gray(), 0, 1),
             ImageFormat::RgbaPremul => (
                 CGColorSpace::create_device_rgb(),
                 kCGImageAlphaPremultipliedLast,
                 4,
             ),
             ImageFormat::RgbaSeparate => (CGColorSpace::create_device_rgb(), kCGImageAlphaLast, 4),
+            ImageFormat::Bgra => (
+                CGColorSpace::create_device_rgb(),
+                kCGImageAlphaFirst | kCGBitmapByteOrder32Little,
+                4,
+            ),
+            ImageFormat::BgraPremul => (
+                CGColorSpace::create_device_rgb(),
+                kCGImageAlphaPremultipliedFirst | kCGBitmapByteOrder32Little,
+                4,
+            ),
             _ => unimplemented!(),
         };
         let bits_per_component = 8;
@@ -276,14 +492,61 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
             height,
             bits_per_component,
             bytes * bits_per_component,
-            width * bytes,
+            stride,
             &colorspace,
             bitmap_info,
             &data_provider,
             should_interpolate,
             rendering_intent,
         );
-        Ok(image)
+        Ok(CoreGraphicsImage(image))
+    }
+
+    fn capture_image_area(&mut self, src_rect: impl Into<Rect>) -> Result<Self::Image, Error> {
+        // This is copy-on-write, so it's cheap until we draw again. Only
+        // bitmap contexts have pixels to copy.
+        let image = self.ctx.create_image().ok_or(Error::NotSupported)?;
+        let width = self.ctx.width() as f64;
+        let height = self.ctx.height() as f64;
+        // The CTM maps to y-up device space, but image rows are top down.
+        let device = from_cgaffine(self.ctx.get_ctm());
+        let to_image = Affine::new([1.0, 0.0, 0.0, -1.0, 0.0, height]) * device;
+        let rect = util::capture_rect(src_rect.into(), to_image, Size::new(width, height))?;
+        let rect = CGRect::new(
+            &CGPoint::new(rect.x0, rect.y0),
+            &CGSize::new(rect.width(), rect.height()),
+        );
+        let image = image.cropped(rect).ok_or(Error::InvalidInput)?;
+        Ok(CoreGraphicsImage(image))
+    }
+
+    fn to_image_buf(
+        &mut self,
+        image: &CoreGraphicsImage,
+        format: ImageFormat,
+    ) -> Result<ImageBuf, Error> {
+        let width = image.0.width();
+        let height = image.0.height();
+        let mut ctx = CGContext::create_bitmap_context(
+            None,
+            width,
+            height,
+            8,
+            0,
+            &CGColorSpace::create_device_rgb(),
+            kCGImageAlphaPremultipliedLast,
+        );
+        // copy the pixels, rather than compositing them
+        ctx.set_blend_mode(CGBlendMode::Copy);
+        let rect = CGRect::new(
+            &CGPoint::new(0.0, 0.0),
+            &CGSize::new(width as CGFloat, height as CGFloat),
+        );
+        ctx.draw_image(rect, &image.0);
+        let stride = ctx.bytes_per_row();
+        let format_in = ImageFormat::RgbaPremul;
+        let pixels = util::pack_image_rows(width, height, stride, ctx.data(), format_in)?;
+        Ok(ImageBuf::from_raw(pixels.into_owned(), format_in, width, height).to_format(format))
     }
 
     fn draw_image(
@@ -299,6 +562,7 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
                 CGInterpolationQuality::CGInterpolationQualityNone
             }
             InterpolationMode::Bilinear => CGInterpolationQuality::CGInterpolationQualityDefault,
+            InterpolationMode::Bicubic => CGInterpolationQuality::CGInterpolationQualityHigh,
         };
         self.ctx.set_interpolation_quality(quality);
         let rect = rect.into();
@@ -308,7 +572,7 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
         self.ctx.translate(rect.min_x(), rect.max_y());
         self.ctx.scale(1.0, -1.0);
         self.ctx
-            .draw_image(to_cgrect(rect.with_origin(Point::ZERO)), image);
+            .draw_image(to_cgrect(rect.with_origin(Point::ZERO)), &image.0);
         self.ctx.restore();
     }
 
@@ -317,25 +581,57 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
         image: &Self::Image,
         src_rect: impl Into<Rect>,
         dst_rect: impl Into<Rect>,
-        _interp: InterpolationMode,
+        interp: InterpolationMode,
     ) {
-        if let Some(cropped) = image.cropped(to_cgrect(src_rect)) {
-            // TODO: apply interpolation mode
-            self.ctx.draw_image(to_cgrect(dst_rect), &cropped);
+        if let Some(cropped) = image.0.cropped(to_cgrect(src_rect)) {
+            // draw_image handles the flip and the interpolation mode
+            self.draw_image(&CoreGraphicsImage(cropped), dst_rect, interp);
         }
     }
 
-    fn blurred_rect(&mut self, rect: Rect, blur_radius: f64, brush: &impl IntoBrush<Self>) {
-        let (image, rect) = compute_blurred_rect(rect, blur_radius);
-        let cg_rect = to_cgrect(rect);
+    fn blurred_shape(&mut self, shape: impl Shape, blur_radius: f64, brush: &impl IntoBrush<Self>) {
+        let (image, rect) = compute_blurred_shape(&shape, blur_radius);
         self.ctx.save();
-        self.ctx.clip_to_mask(cg_rect, &image);
+        self.clip_to_image(rect, &image);
         self.fill(rect, brush);
         self.ctx.restore()
     }
 
+    fn fill_with_shadow(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        shadow: &ShadowStyle,
+    ) {
+        // shadows ignore the transform, so they are given in device space,
+        // with a blur of twice the standard deviation.
+        let [a, b, c, d, _, _] = from_cgaffine(self.ctx.get_ctm()).as_coeffs();
+        let Vec2 { x, y } = shadow.offset;
+        let offset = CGSize::new(a * x + c * y, b * x + d * y);
+        let blur = 2.0 * shadow.blur * (a * d - b * c).abs().sqrt();
+        let (red, green, blue, alpha) = shadow.color.as_rgba();
+        self.ctx.save();
+        self.ctx
+            .set_shadow_with_color(offset, blur, &CGColor::rgb(red, green, blue, alpha));
+        // gradients and images are filled through a clip, which would also
+        // clip the shadow; in a transparency layer, the whole layer casts it.
+        unsafe {
+            CGContextSetAlpha(self.ctx.as_ptr(), 1.0);
+            CGContextBeginTransparencyLayer(self.ctx.as_ptr(), std::ptr::null());
+        }
+        self.apply_opacity();
+        self.fill(shape, brush);
+        unsafe { CGContextEndTransparencyLayer(self.ctx.as_ptr()) };
+        self.ctx.restore();
+    }
+
     fn current_transform(&self) -> Affine {
-        self.transform_stack.last().copied().unwrap_or_default()
+        self.current_state().transform
+    }
+
+    fn current_clip_bounds(&self) -> Option<Rect> {
+        let state = self.current_state();
+        util::user_clip_bounds(state.clip, state.transform)
     }
 
     fn status(&mut self) -> Result<(), Error> {
@@ -370,6 +666,85 @@ fn convert_line_cap(line_cap: LineCap) -> CGLineCap {
 }
 
 impl<'a> CoreGraphicsContext<'a> {
+    /// Clip to a grayscale mask image, drawn into `rect`.
+    fn clip_to_image(&mut self, rect: Rect, mask: &CGImage) {
+        // as in `draw_image`, the mask is drawn flipped
+        let flip = Affine::translate((rect.min_x(), rect.max_y())) * Affine::FLIP_Y;
+        self.ctx.concat_ctm(to_cgaffine(flip));
+        let mask_rect = to_cgrect(rect.with_origin(Point::ZERO));
+        self.ctx.clip_to_mask(mask_rect, mask);
+        self.ctx.concat_ctm(to_cgaffine(flip.inverse()));
+    }
+
+    /// Record the bounds of a new clip, and set its path.
+    fn set_clip_path(&mut self, shape: impl Shape) {
+        let state = self.current_state();
+        let clip = util::intersect_clip_bounds(state.clip, shape.bounding_box(), state.transform);
+        self.set_current_state(CtxState {
+            clip: Some(clip),
+            ..state
+        });
+        self.set_path(shape);
+    }
+
+    fn current_state(&self) -> CtxState {
+        self.state_stack.last().copied().unwrap_or_default()
+    }
+
+    fn set_current_state(&mut self, state: CtxState) {
+        if let Some(last) = self.state_stack.last_mut() {
+            *last = state;
+        } else {
+            self.state_stack.push(state);
+        }
+    }
+
+    /// Set the context's alpha to the current opacity.
+    fn apply_opacity(&mut self) {
+        unsafe { CGContextSetAlpha(self.ctx.as_ptr(), self.opacity.current()) };
+    }
+
+    /// Begin a transparency layer, to be ended on restore.
+    ///
+    /// The caller has already saved, and pushed a state for the layer.
+    fn begin_layer(&mut self) {
+        unsafe { CGContextBeginTransparencyLayer(self.ctx.as_ptr(), std::ptr::null()) };
+        self.layer_stack.push(true);
+    }
+
+    /// Draw only the shadow of some text, given as its glyph outline.
+    ///
+    /// The glyphs themselves are drawn by CoreText, after the shadows; so
+    /// here the outline is moved off the left edge of the context, and the
+    /// shadow offset moves its shadow back into place.
+    fn draw_text_shadow(&mut self, outline: &BezPath, shadow: &ShadowStyle) {
+        let ctm = from_cgaffine(self.ctx.get_ctm());
+        let [a, b, c, d, _, _] = ctm.as_coeffs();
+        let linear = Affine::new([a, b, c, d, 0.0, 0.0]);
+        if linear.determinant() == 0.0 {
+            return;
+        }
+        let shift = ctm
+            .transform_rect_bbox(outline.bounding_box())
+            .x1
+            .max(0.0)
+            .ceil()
+            + 1.0;
+        let Vec2 { x, y } = shadow.offset;
+        let offset = CGSize::new(a * x + c * y + shift, b * x + d * y);
+        let blur = 2.0 * shadow.blur * linear.determinant().abs().sqrt();
+        let (red, green, blue, alpha) = shadow.color.as_rgba();
+        self.ctx.save();
+        let user_shift = linear.inverse() * Point::new(-shift, 0.0);
+        self.ctx.translate(user_shift.x, user_shift.y);
+        self.ctx
+            .set_shadow_with_color(offset, blur, &CGColor::rgb(red, green, blue, alpha));
+        self.ctx.set_rgb_fill_color(0.0, 0.0, 0.0, 1.0);
+        self.set_path(outline);
+        self.ctx.fill_path();
+        self.ctx.restore();
+    }
+
     fn set_fill_color(&mut self, color: &Color) {
         let (r, g, b, a) = Color::as_rgba(&color);
         self.ctx.set_rgb_fill_color(r, g, b, a);
@@ -394,7 +769,9 @@ impl<'a> CoreGraphicsContext<'a> {
             .unwrap_or(LineCap::Butt);
         self.ctx.set_line_cap(convert_line_cap(line_cap));
 
-        let miter_limit = style.and_then(|style| style.miter_limit).unwrap_or(10.0);
+        let miter_limit = style
+            .and_then(|style| style.miter_limit)
+            .unwrap_or(StrokeStyle::DEFAULT_MITER_LIMIT);
         self.ctx.set_miter_limit(miter_limit);
 
         match style.and_then(|style| style.dash.as_ref()) {
@@ -407,6 +784,29 @@ impl<'a> CoreGraphicsContext<'a> {
         // This shouldn't be necessary, we always leave the context in no-path
         // state. But just in case, and it should be harmless.
         self.ctx.begin_path();
+        if let Some(rect) = shape.as_rect() {
+            unsafe { CGContextAddRect(self.ctx.as_ptr(), to_cgrect(rect)) };
+            return;
+        }
+        if let Some(circle) = shape.as_circle() {
+            let rect =
+                Rect::from_center_size(circle.center, (circle.radius * 2.0, circle.radius * 2.0));
+            unsafe { CGContextAddEllipseInRect(self.ctx.as_ptr(), to_cgrect(rect)) };
+            return;
+        }
+        if let Some(rounded) = shape.as_rounded_rect() {
+            let radius = rounded.radius();
+            let path = unsafe {
+                CGPath::from_ptr(CGPathCreateWithRoundedRect(
+                    to_cgrect(rounded.rect()),
+                    radius,
+                    radius,
+                    std::ptr::null(),
+                ))
+            };
+            self.ctx.add_path(&path);
+            return;
+        }
         let mut last = Point::default();
         for el in shape.to_bez_path(1e-3) {
             match el {
@@ -436,13 +836,13 @@ impl<'a> CoreGraphicsContext<'a> {
     }
 }
 
-fn compute_blurred_rect(rect: Rect, radius: f64) -> (CGImage, Rect) {
-    let size = piet::util::size_for_blurred_rect(rect, radius);
+fn compute_blurred_shape(shape: &impl Shape, radius: f64) -> (CGImage, Rect) {
+    let size = piet::util::size_for_blurred_shape(shape, radius);
     let width = size.width as usize;
     let height = size.height as usize;
 
     let mut data = vec![0u8; width * height];
-    let rect_exp = piet::util::compute_blurred_rect(rect, radius, width, &mut data);
+    let rect_exp = piet::util::compute_blurred_shape(shape, radius, width, &mut data);
 
     let data_provider = CGDataProvider::from_buffer(Arc::new(data));
     let color_space = CGColorSpace::create_device_gray();
@@ -461,6 +861,40 @@ fn compute_blurred_rect(rect: Rect, radius: f64) -> (CGImage, Rect) {
     (image, rect_exp)
 }
 
+/// A grayscale image of the alpha channel of `image`, for `clip_to_mask`.
+fn alpha_mask(image: &CGImage) -> CGImage {
+    let width = image.width();
+    let height = image.height();
+    let bytes_per_row = image.bytes_per_row();
+    // images from `make_image` without alpha are 24 bits per pixel
+    let has_alpha = image.bits_per_pixel() == 32;
+    let src = image.data();
+    let src = src.bytes();
+    let mut data = vec![255u8; width * height];
+    if has_alpha {
+        for y in 0..height {
+            for x in 0..width {
+                data[y * width + x] = src[y * bytes_per_row + x * 4 + 3];
+            }
+        }
+    }
+
+    let data_provider = CGDataProvider::from_buffer(Arc::new(data));
+    let color_space = CGColorSpace::create_device_gray();
+    CGImage::new(
+        width,
+        height,
+        8,
+        8,
+        width,
+        &color_space,
+        0,
+        &data_provider,
+        false,
+        0,
+    )
+}
+
 fn to_cgpoint(point: Point) -> CGPoint {
     CGPoint::new(point.x as CGFloat, point.y as CGFloat)
 }
@@ -479,6 +913,26 @@ fn to_cgaffine(affine: Affine) -> CGAffineTransform {
     CGAffineTransform::new(a, b, c, d, tx, ty)
 }
 
+fn from_cgaffine(affine: CGAffineTransform) -> Affine {
+    let CGAffineTransform { a, b, c, d, tx, ty } = affine;
+    Affine::new([a, b, c, d, tx, ty])
+}
+
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    fn CGContextSetAlpha(c: CGContextPtr, alpha: CGFloat);
+    fn CGContextBeginTransparencyLayer(c: CGContextPtr, auxiliary_info: CFDictionaryRef);
+    fn CGContextEndTransparencyLayer(c: CGContextPtr);
+    fn CGContextAddRect(c: CGContextPtr, rect: CGRect);
+    fn CGContextAddEllipseInRect(c: CGContextPtr, rect: CGRect);
+    fn CGPathCreateWithRoundedRect(
+        rect: CGRect,
+        corner_width: CGFloat,
+        corner_height: CGFloat,
+        transform: *const CGAffineTransform,
+    ) -> CGPathPtr;
+}
+
 #[allow(dead_code)]
 pub fn unpremultiply_rgba(data: &mut [u8]) {
     for i in (0..data.len()).step_by(4) {
@@ -565,4 +1019,29 @@ mod tests {
         piet.restore().unwrap();
         assert_affine_eq!(piet.current_transform(), Affine::default());
     }
+
+    #[test]
+    #[cfg(feature = "send-layouts")]
+    fn draw_layout_from_another_thread() {
+        use piet::{FontFamily, Text, TextLayout, TextLayoutBuilder};
+
+        let layout = std::thread::spawn(|| {
+            CoreGraphicsText::new_with_unique_state()
+                .new_text_layout("sent between threads")
+                .font(FontFamily::SANS_SERIF, 16.0)
+                .build()
+                .unwrap()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(layout.line_count(), 1);
+
+        let mut ctx = make_context((200.0, 40.0));
+        {
+            let mut piet = CoreGraphicsContext::new_y_down(&mut ctx);
+            piet.draw_text(&layout, (0.0, 0.0));
+            piet.finish().unwrap();
+        }
+        assert!(ctx.data().chunks(4).any(|px| px[3] != 0));
+    }
 }
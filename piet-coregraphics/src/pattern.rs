@@ -0,0 +1,81 @@
+//! core graphics image pattern support
+
+use core_graphics::{
+    base::{kCGImageAlphaPremultipliedLast, CGFloat},
+    color_space::CGColorSpace,
+    context::{CGContext, CGContextRef},
+    geometry::{CGAffineTransform, CGPoint, CGRect, CGSize},
+    image::CGImage,
+    sys::{CGContextRef as CGContextPtr, CGImageRef},
+};
+use foreign_types::{ForeignType, ForeignTypeRef};
+
+use piet::kurbo::Affine;
+use piet::TileMode;
+
+/// An image, tiled across the plane.
+#[derive(Clone)]
+pub struct ImagePattern {
+    /// One tile; for `TileMode::Reflect` this is four mirrored copies.
+    tile: CGImage,
+    transform: Affine,
+}
+
+impl ImagePattern {
+    pub(crate) fn new(image: &CGImage, tile: TileMode, transform: Affine) -> ImagePattern {
+        let tile = match tile {
+            TileMode::Repeat => image.clone(),
+            // tiled images only repeat, so we mirror the image ourselves
+            TileMode::Reflect => reflected_tile(image),
+        };
+        ImagePattern { tile, transform }
+    }
+
+    /// Fill the current clip with the pattern.
+    pub(crate) fn fill(&self, ctx: &mut CGContextRef) {
+        let width = self.tile.width() as f64;
+        let height = self.tile.height() as f64;
+        // images are drawn flipped relative to our y-down space
+        let flip = Affine::new([1.0, 0.0, 0.0, -1.0, 0.0, height]);
+        let [a, b, c, d, tx, ty] = (self.transform * flip).as_coeffs();
+        ctx.concat_ctm(CGAffineTransform::new(a, b, c, d, tx, ty));
+        let rect = CGRect::new(
+            &CGPoint::new(0.0, 0.0),
+            &CGSize::new(width as CGFloat, height as CGFloat),
+        );
+        unsafe { CGContextDrawTiledImage(ctx.as_ptr(), rect, self.tile.as_ptr()) };
+    }
+}
+
+/// A tile of four copies of `image`, mirrored horizontally and vertically.
+fn reflected_tile(image: &CGImage) -> CGImage {
+    let width = image.width();
+    let height = image.height();
+    let ctx = CGContext::create_bitmap_context(
+        None,
+        width * 2,
+        height * 2,
+        8,
+        0,
+        &CGColorSpace::create_device_rgb(),
+        kCGImageAlphaPremultipliedLast,
+    );
+    let (w, h) = (width as CGFloat, height as CGFloat);
+    // the bitmap context is y-up, so the unmirrored copy, drawn above the
+    // center line, ends up in the first rows of the image.
+    let rect = CGRect::new(&CGPoint::new(-w, 0.0), &CGSize::new(w, h));
+    for &(sx, sy) in &[(1.0, 1.0), (-1.0, 1.0), (1.0, -1.0), (-1.0, -1.0)] {
+        // mirror about the center of the tile
+        ctx.save();
+        ctx.translate(w, h);
+        ctx.scale(sx, sy);
+        ctx.draw_image(rect, image);
+        ctx.restore();
+    }
+    ctx.create_image().unwrap()
+}
+
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    fn CGContextDrawTiledImage(c: CGContextPtr, rect: CGRect, image: CGImageRef);
+}
@@ -1,7 +1,9 @@
 //! Text related stuff for the coregraphics backend
 
+use std::cell::OnceCell;
 use std::collections::HashMap;
 use std::ops::{Range, RangeBounds};
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 
 use core_foundation::base::TCFType;
@@ -11,14 +13,21 @@ use core_foundation::string::CFString;
 use core_foundation_sys::base::CFRange;
 use core_graphics::base::CGFloat;
 use core_graphics::context::CGContextRef;
-use core_graphics::geometry::{CGPoint, CGRect, CGSize};
-use core_graphics::path::CGPath;
-use core_text::{font, font::CTFont, font_descriptor, string_attributes};
+use core_graphics::font::CGGlyph;
+use core_graphics::geometry::{CGAffineTransform, CGPoint, CGRect, CGSize};
+use core_graphics::path::{CGPath, CGPathElementRef, CGPathElementType};
+use core_text::{
+    font, font::CTFont, font_collection, font_descriptor, line::CTLine, run::CTRun,
+    string_attributes,
+};
 
-use piet::kurbo::{Point, Rect, Size};
+use piet::kurbo::{Affine, BezPath, Point, Rect, Size};
 use piet::{
-    util, Error, FontFamily, FontWeight, HitTestPoint, HitTestPosition, LineMetric, Text,
-    TextAlignment, TextAttribute, TextLayout, TextLayoutBuilder,
+    util, Affinity, Color, Error, FontFamily, FontFamilyList, FontFeatures, FontMetrics,
+    FontWeight, Glyph, GlyphRun, HitTestPoint, HitTestPosition, IntoLayoutStorage, LayoutStorage,
+    LineMetric, LineSpacing, ShadowStyle, StrokeStyle, TabStops, Text, TextAlignment,
+    TextAttribute, TextDirection, TextLayout, TextLayoutBuilder, TextOrientation, TruncationMode,
+    UnderlineStyle,
 };
 
 use crate::ct_helpers::{self, AttributedString, FontCollection, Frame, Framesetter, Line};
@@ -50,7 +59,7 @@ pub struct CoreGraphicsFontBuilder(Option<CTFont>);
 
 #[derive(Clone)]
 pub struct CoreGraphicsTextLayout {
-    string: String,
+    string: Arc<dyn LayoutStorage>,
     attr_string: AttributedString,
     framesetter: Framesetter,
     pub(crate) frame: Option<Frame>,
@@ -61,13 +70,66 @@ pub struct CoreGraphicsTextLayout {
     pub(crate) frame_size: Size,
     image_bounds: Rect,
     width_constraint: f64,
+    alignment: TextAlignment,
+    decorations: Decorations,
+    /// CoreText has no background color attribute either.
+    backgrounds: Vec<(Range<usize>, Color)>,
+    shadows: Vec<(Range<usize>, ShadowStyle)>,
+    truncation: Option<Truncation>,
+    /// the utf8 offset of the end of the visible text
+    visible_end: usize,
+    /// the x position of the ellipsis on the last line, if one is drawn
+    ellipsis_x: Option<f64>,
+    /// whether the lines are vertical. We lay vertical lines out as if they
+    /// were horizontal, with vertical glyph forms, and then rotate them; all
+    /// of the layout's fields are in this unrotated space.
+    vertical: bool,
+    /// the glyph runs, made the first time they are asked for.
+    glyph_runs: OnceCell<Vec<GlyphRun>>,
+}
+
+/// Limits on the number of lines that are laid out.
+#[derive(Clone)]
+struct Truncation {
+    max_lines: usize,
+    /// drawn at the end of the last line, with `TruncationMode::EllipsisEnd`
+    ellipsis: Option<CTLine>,
+}
+
+// like the ct_helpers types, the ellipsis line is immutable.
+unsafe impl Send for Truncation {}
+
+/// CoreText has no strikethrough attribute, and its underlines don't support
+/// all of our styles or colors, so we draw both ourselves.
+#[derive(Clone)]
+struct Decorations {
+    /// the resolved ranges of the text that are struck through
+    strikethroughs: Vec<Range<usize>>,
+    /// the resolved ranges of the text that are underlined, with their style
+    /// and their color, if it isn't the foreground color
+    underlines: Vec<(Range<usize>, UnderlineStyle, Option<Color>)>,
+    /// foreground color ranges, in the order they were added
+    fg_colors: Vec<(Range<usize>, Color)>,
+    default_color: Color,
+    /// distance above the baseline of the center of the strikethrough, from the default font
+    strikethrough_offset: f64,
+    /// distance above the baseline of the center of the underline, from the default font;
+    /// this is negative, as the underline is below the baseline.
+    underline_offset: f64,
+    thickness: f64,
 }
 
 /// Building text layouts for `CoreGraphics`.
 pub struct CoreGraphicsTextLayoutBuilder {
     width: f64,
     alignment: TextAlignment,
-    text: String,
+    direction: TextDirection,
+    orientation: TextOrientation,
+    line_spacing: LineSpacing,
+    max_lines: Option<usize>,
+    truncation: TruncationMode,
+    tab_stops: Option<TabStops>,
+    text: Arc<dyn LayoutStorage>,
     /// the end bound up to which we have already added attrs to our AttributedString
     last_resolved_pos: usize,
     last_resolved_utf16: usize,
@@ -77,16 +139,30 @@ pub struct CoreGraphicsTextLayoutBuilder {
     /// we build the string.
     has_set_default_attrs: bool,
     attrs: Attributes,
+    strikethroughs: Vec<(Range<usize>, bool)>,
+    underlines: Vec<(Range<usize>, bool)>,
+    underline_colors: Vec<(Range<usize>, Color)>,
+    underline_styles: Vec<(Range<usize>, UnderlineStyle)>,
+    decorations: Decorations,
+    backgrounds: Vec<(Range<usize>, Color)>,
+    shadows: Vec<(Range<usize>, ShadowStyle)>,
+    /// letter spacing for ranges of the text, which is combined with word spacing.
+    letter_spacings: Vec<(Range<usize>, f64)>,
+    word_spacings: Vec<(Range<usize>, f64)>,
+    /// CoreText chooses its own line breaks, so custom break opportunities
+    /// cause `build` to fail.
+    has_break_opportunities: bool,
 }
 
 /// A helper type for storing and resolving attributes
 #[derive(Default)]
 struct Attributes {
     defaults: util::LayoutDefaults,
-    font: Option<Span<FontFamily>>,
+    font: Option<Span<FontFamilyList>>,
     size: Option<Span<f64>>,
     weight: Option<Span<FontWeight>>,
     italic: Option<Span<bool>>,
+    font_features: Option<Span<FontFeatures>>,
 }
 
 /// during construction, `Span`s represent font attributes that have been applied
@@ -129,9 +205,34 @@ impl CoreGraphicsTextLayoutBuilder {
         if !self.has_set_default_attrs {
             self.set_default_attrs();
         }
+        match attr {
+            TextAttribute::Strikethrough(flag) => return self.strikethroughs.push((range, flag)),
+            TextAttribute::Underline(flag) => return self.underlines.push((range, flag)),
+            TextAttribute::UnderlineColor(color) => {
+                return self.underline_colors.push((range, color))
+            }
+            TextAttribute::UnderlineStyle(style) => {
+                return self.underline_styles.push((range, style))
+            }
+            TextAttribute::BackgroundColor(color) => return self.backgrounds.push((range, color)),
+            TextAttribute::Shadow(shadow) => return self.shadows.push((range, shadow)),
+            TextAttribute::WordSpacing(spacing) => {
+                return self.word_spacings.push((range, spacing))
+            }
+            TextAttribute::LetterSpacing(spacing) => {
+                self.letter_spacings.push((range.clone(), spacing))
+            }
+            _ => (),
+        }
         // Some attributes are 'standalone' and can just be added to the attributed string
         // immediately.
-        if matches!(&attr, TextAttribute::ForegroundColor(_) | TextAttribute::Underline(_)) {
+        if matches!(
+            &attr,
+            TextAttribute::ForegroundColor(_)
+                | TextAttribute::LetterSpacing(_)
+                | TextAttribute::BaselineShift(_)
+                | TextAttribute::Locale(_)
+        ) {
             return self.add_immediately(attr, range);
         }
 
@@ -146,6 +247,7 @@ impl CoreGraphicsTextLayoutBuilder {
         self.attrs.add(range, attr);
     }
 
+    #[allow(clippy::float_cmp)]
     fn set_default_attrs(&mut self) {
         self.has_set_default_attrs = true;
         let whole_range = self.attr_string.range();
@@ -153,19 +255,38 @@ impl CoreGraphicsTextLayoutBuilder {
         self.attr_string.set_font(whole_range, &font);
         self.attr_string
             .set_fg_color(whole_range, &self.attrs.defaults.fg_color);
-        self.attr_string
-            .set_underline(whole_range, self.attrs.defaults.underline);
+        // setting an explicit kern of zero would disable the font's kerning
+        if self.attrs.defaults.letter_spacing != 0.0 {
+            self.attr_string
+                .set_kern(whole_range, self.attrs.defaults.letter_spacing);
+        }
+        if self.attrs.defaults.baseline_shift != 0.0 {
+            self.attr_string
+                .set_baseline_offset(whole_range, self.attrs.defaults.baseline_shift);
+        }
+        if let Some(locale) = &self.attrs.defaults.locale {
+            self.attr_string.set_language(whole_range, locale);
+        }
+        self.decorations.default_color = self.attrs.defaults.fg_color.clone();
+        self.decorations.strikethrough_offset = font.x_height() / 2.0;
+        self.decorations.underline_offset = font.underline_position();
+        self.decorations.thickness = font.underline_thickness();
     }
 
     fn add_immediately(&mut self, attr: TextAttribute, range: Range<usize>) {
-        let utf16_start = util::count_utf16(&self.text[..range.start]);
-        let utf16_len = util::count_utf16(&self.text[range]);
-        let range = CFRange::init(utf16_start as isize, utf16_len as isize);
+        let utf16_start = util::count_utf16(&self.text.as_str()[..range.start]);
+        let utf16_len = util::count_utf16(&self.text.as_str()[range.clone()]);
+        let cf_range = CFRange::init(utf16_start as isize, utf16_len as isize);
         match attr {
             TextAttribute::ForegroundColor(color) => {
-                self.attr_string.set_fg_color(range, &color);
+                self.attr_string.set_fg_color(cf_range, &color);
+                self.decorations.fg_colors.push((range, color));
+            }
+            TextAttribute::LetterSpacing(spacing) => self.attr_string.set_kern(cf_range, spacing),
+            TextAttribute::BaselineShift(shift) => {
+                self.attr_string.set_baseline_offset(cf_range, shift)
             }
-            TextAttribute::Underline(flag) => self.attr_string.set_underline(range, flag),
+            TextAttribute::Locale(locale) => self.attr_string.set_language(cf_range, &locale),
             _ => unreachable!(),
         }
     }
@@ -174,7 +295,46 @@ impl CoreGraphicsTextLayoutBuilder {
         if !self.has_set_default_attrs {
             self.set_default_attrs();
         }
-        self.resolve_up_to(self.text.len());
+        self.resolve_up_to(self.text.as_str().len());
+        self.apply_word_spacing();
+    }
+
+    /// CoreText has no word spacing attribute, so we add the word spacing to
+    /// the kern of each space separator, on top of any letter spacing.
+    fn apply_word_spacing(&mut self) {
+        let len = self.text.as_str().len();
+        let word_spacings = util::resolve_spans(
+            Some(self.attrs.defaults.word_spacing),
+            len,
+            std::mem::take(&mut self.word_spacings),
+        );
+        if word_spacings.iter().all(|(_, spacing)| *spacing == 0.0) {
+            return;
+        }
+        let letter_spacings = util::resolve_spans(
+            Some(self.attrs.defaults.letter_spacing),
+            len,
+            std::mem::take(&mut self.letter_spacings),
+        );
+        let spacing_at = |spans: &[(Range<usize>, f64)], pos: usize| {
+            spans
+                .iter()
+                .find(|(range, _)| range.contains(&pos))
+                .map(|(_, spacing)| *spacing)
+                .unwrap_or_default()
+        };
+
+        let mut utf16_pos = 0;
+        for (pos, c) in self.text.as_str().char_indices() {
+            let utf16_len = c.len_utf16();
+            let word_spacing = spacing_at(&word_spacings, pos);
+            if word_spacing != 0.0 && util::is_space_separator(c) {
+                let kern = spacing_at(&letter_spacings, pos) + word_spacing;
+                let range = CFRange::init(utf16_pos as isize, utf16_len as isize);
+                self.attr_string.set_kern(range, kern);
+            }
+            utf16_pos += utf16_len;
+        }
     }
 
     /// Add all font attributes up to a boundary.
@@ -184,7 +344,7 @@ impl CoreGraphicsTextLayoutBuilder {
             next_span_end = self.next_span_end(resolve_end);
             if next_span_end > self.last_resolved_pos {
                 let range_end_utf16 =
-                    util::count_utf16(&self.text[self.last_resolved_pos..next_span_end]);
+                    util::count_utf16(&self.text.as_str()[self.last_resolved_pos..next_span_end]);
                 let range =
                     CFRange::init(self.last_resolved_utf16 as isize, range_end_utf16 as isize);
                 let font = self.current_font();
@@ -238,15 +398,35 @@ impl CoreGraphicsTextLayoutBuilder {
                 traits.set(symbolic_traits_key, symbolic_traits.as_CFType());
             }
 
-            let attributes = CFDictionary::from_CFType_pairs(&[
+            let mut pairs = vec![
                 (family_key, family_name.as_CFType()),
                 (traits_key, traits.as_CFType()),
-            ]);
+            ];
+            let features = self.attrs.font_features();
+            if !features.is_empty() {
+                pairs.push(ct_helpers::font_feature_settings(features));
+            }
+            let fallbacks = self.attrs.font_fallbacks();
+            if !fallbacks.is_empty() {
+                pairs.push(ct_helpers::font_cascade_list(fallbacks, self.attrs.size()));
+            }
+            let attributes = CFDictionary::from_CFType_pairs(&pairs);
             let descriptor = font_descriptor::new_from_attributes(&attributes);
             font::new_from_descriptor(&descriptor, self.attrs.size())
         }
     }
 
+    /// The line drawn at the end of text truncated with `TruncationMode::EllipsisEnd`.
+    ///
+    /// This must be called after `finalize`, so that the current font is the default font.
+    fn ellipsis_line(&self) -> CTLine {
+        let mut string = AttributedString::new(util::ELLIPSIS);
+        let range = string.range();
+        string.set_font(range, &self.current_font());
+        string.set_fg_color(range, &self.attrs.defaults.fg_color);
+        CTLine::new_with_attributed_string(string.inner.as_concrete_TypeRef())
+    }
+
     /// After we have added a span, check to see if any of our attributes are no
     /// longer active.
     ///
@@ -260,10 +440,12 @@ impl CoreGraphicsTextLayoutBuilder {
 impl Attributes {
     fn add(&mut self, range: Range<usize>, attr: TextAttribute) {
         match attr {
-            TextAttribute::Font(font) => self.font = Some(Span::new(font, range)),
+            TextAttribute::Font(font) => self.font = Some(Span::new(font.into(), range)),
+            TextAttribute::FontFamilies(fonts) => self.font = Some(Span::new(fonts, range)),
             TextAttribute::Weight(w) => self.weight = Some(Span::new(w, range)),
             TextAttribute::Size(s) => self.size = Some(Span::new(s, range)),
             TextAttribute::Italic(b) => self.italic = Some(Span::new(b, range)),
+            TextAttribute::FontFeatures(f) => self.font_features = Some(Span::new(f, range)),
             _ => unreachable!(),
         }
     }
@@ -292,10 +474,24 @@ impl Attributes {
     fn font(&self) -> &FontFamily {
         self.font
             .as_ref()
-            .map(|t| &t.payload)
+            .map(|t| t.payload.primary())
             .unwrap_or_else(|| &self.defaults.font)
     }
 
+    fn font_fallbacks(&self) -> &[FontFamily] {
+        self.font
+            .as_ref()
+            .map(|t| t.payload.fallbacks())
+            .unwrap_or_else(|| &self.defaults.font_fallbacks)
+    }
+
+    fn font_features(&self) -> &FontFeatures {
+        self.font_features
+            .as_ref()
+            .map(|t| &t.payload)
+            .unwrap_or_else(|| &self.defaults.font_features)
+    }
+
     fn next_span_end(&self, max: usize) -> usize {
         self.font
             .as_ref()
@@ -304,6 +500,12 @@ impl Attributes {
             .min(self.size.as_ref().map(Span::range_end).unwrap_or(max))
             .min(self.weight.as_ref().map(Span::range_end).unwrap_or(max))
             .min(self.italic.as_ref().map(Span::range_end).unwrap_or(max))
+            .min(
+                self.font_features
+                    .as_ref()
+                    .map(Span::range_end)
+                    .unwrap_or(max),
+            )
             .min(max)
     }
 
@@ -321,9 +523,28 @@ impl Attributes {
         if self.size.as_ref().map(Span::range_end) == Some(last_pos) {
             self.size = None;
         }
+        if self.font_features.as_ref().map(Span::range_end) == Some(last_pos) {
+            self.font_features = None;
+        }
     }
 }
 
+/// A distance used for clip rects that should extend past the layout.
+const CLIP_EXTENT: CGFloat = 1e6;
+
+/// If `frame` has more than `max_lines` lines, returns the frame height
+/// that fits exactly the first `max_lines`.
+fn height_for_lines(frame: &Frame, frame_height: CGFloat, max_lines: usize) -> Option<CGFloat> {
+    let lines = frame.get_lines();
+    if lines.len() as usize <= max_lines {
+        return None;
+    }
+    let last = lines.get(max_lines as isize - 1)?;
+    let origins = frame.get_line_origins(CFRange::init(max_lines as isize - 1, 1));
+    let descent = Line::new(&last).get_typographic_bounds().descent;
+    Some((frame_height - origins.first()?.y + descent).ceil())
+}
+
 /// coretext uses a float in the range -1.0..=1.0, which has a non-linear mapping
 /// to css-style weights. This is a fudge, adapted from QT:
 ///
@@ -372,12 +593,55 @@ impl Text for CoreGraphicsText {
         self.shared.get_font(family_name)
     }
 
-    fn new_text_layout(&mut self, text: &str) -> Self::TextLayoutBuilder {
+    fn font_families(&mut self) -> Vec<FontFamily> {
+        let mut names: Vec<String> = font_collection::get_family_names()
+            .iter()
+            .map(|name| name.to_string())
+            // names beginning with '.' are private system fonts
+            .filter(|name| !name.starts_with('.'))
+            .collect();
+        names.sort();
+        names.into_iter().map(FontFamily::new_unchecked).collect()
+    }
+
+    fn load_font_from_path(&mut self, path: &Path) -> Result<FontFamily, Error> {
+        let family = ct_helpers::register_fonts_for_path(path).ok_or(Error::MissingFont)?;
+        self.shared.add_font(family.clone());
+        Ok(family)
+    }
+
+    fn font_metrics(&mut self, family: &FontFamily, size: f64) -> Option<FontMetrics> {
+        let font = ct_helpers::font_for_family(family, size);
+        Some(FontMetrics {
+            ascent: font.ascent(),
+            descent: font.descent(),
+            cap_height: font.cap_height(),
+            x_height: font.x_height(),
+            // CoreText's positions are above the baseline
+            underline_position: -font.underline_position(),
+            underline_thickness: font.underline_thickness(),
+            // CoreText has no strikethrough metrics; this matches the
+            // strikethroughs we draw ourselves.
+            strikeout_position: -font.x_height() / 2.0,
+            strikeout_thickness: font.underline_thickness(),
+        })
+    }
+
+    fn new_text_layout(&mut self, text: impl IntoLayoutStorage) -> Self::TextLayoutBuilder {
         CoreGraphicsTextLayoutBuilder::new(text)
     }
 }
 
 impl SharedTextState {
+    /// Add a family that was just loaded to the cache, so that it can be
+    /// found by name.
+    fn add_font(&mut self, family: FontFamily) {
+        let mut inner = self.inner.lock().unwrap();
+        inner
+            .family_cache
+            .insert(family.name().to_owned(), Some(family));
+    }
+
     /// return the family object for this family name, if it exists.
     ///
     /// This hits a cache before doing a lookup with the system.
@@ -397,17 +661,42 @@ impl SharedTextState {
 }
 
 impl CoreGraphicsTextLayoutBuilder {
-    fn new(text: &str) -> Self {
-        let attr_string = AttributedString::new(text);
+    fn new(text: impl IntoLayoutStorage) -> Self {
+        let text: Arc<dyn LayoutStorage> = Arc::new(text.into_layout_storage());
+        let attr_string = AttributedString::new(text.as_str());
         CoreGraphicsTextLayoutBuilder {
             width: f64::INFINITY,
             alignment: TextAlignment::default(),
+            direction: TextDirection::default(),
+            orientation: TextOrientation::Horizontal,
+            line_spacing: LineSpacing::Default,
+            max_lines: None,
+            truncation: TruncationMode::Clip,
+            tab_stops: None,
             attrs: Default::default(),
-            text: text.to_string(),
+            text,
             last_resolved_pos: 0,
             last_resolved_utf16: 0,
             attr_string,
             has_set_default_attrs: false,
+            strikethroughs: Vec::new(),
+            underlines: Vec::new(),
+            underline_colors: Vec::new(),
+            underline_styles: Vec::new(),
+            decorations: Decorations {
+                strikethroughs: Vec::new(),
+                underlines: Vec::new(),
+                fg_colors: Vec::new(),
+                default_color: util::DEFAULT_TEXT_COLOR,
+                strikethrough_offset: 0.0,
+                underline_offset: 0.0,
+                thickness: 0.0,
+            },
+            backgrounds: Vec::new(),
+            shadows: Vec::new(),
+            letter_spacings: Vec::new(),
+            word_spacings: Vec::new(),
+            has_break_opportunities: false,
         }
     }
 }
@@ -425,6 +714,41 @@ impl TextLayoutBuilder for CoreGraphicsTextLayoutBuilder {
         self
     }
 
+    fn text_direction(mut self, direction: TextDirection) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    fn orientation(mut self, orientation: TextOrientation) -> Self {
+        self.orientation = orientation;
+        self
+    }
+
+    fn line_spacing(mut self, spacing: LineSpacing) -> Self {
+        self.line_spacing = spacing;
+        self
+    }
+
+    fn max_lines(mut self, max_lines: usize) -> Self {
+        self.max_lines = Some(max_lines.max(1));
+        self
+    }
+
+    fn truncate(mut self, mode: TruncationMode) -> Self {
+        self.truncation = mode;
+        self
+    }
+
+    fn tab_stops(mut self, stops: TabStops) -> Self {
+        self.tab_stops = Some(stops);
+        self
+    }
+
+    fn break_opportunities(mut self, _breaks: impl FnOnce(&str) -> Vec<usize>) -> Self {
+        self.has_break_opportunities = true;
+        self
+    }
+
     fn default_attribute(mut self, attribute: impl Into<TextAttribute>) -> Self {
         let attribute = attribute.into();
         self.attrs.defaults.set(attribute);
@@ -436,38 +760,89 @@ impl TextLayoutBuilder for CoreGraphicsTextLayoutBuilder {
         range: impl RangeBounds<usize>,
         attribute: impl Into<TextAttribute>,
     ) -> Self {
-        let range = util::resolve_range(range, self.text.len());
+        let range = util::resolve_range(range, self.text.as_str().len());
         let attribute = attribute.into();
         self.add(attribute, range);
         self
     }
 
     fn build(mut self) -> Result<Self::Out, Error> {
+        if self.line_spacing != LineSpacing::Default || self.has_break_opportunities {
+            return Err(Error::NotSupported);
+        }
         self.finalize();
-        self.attr_string.set_alignment(self.alignment);
+        let is_rtl = self.direction.is_rtl(self.text.as_str());
+        self.attr_string
+            .set_paragraph_style(self.alignment, is_rtl, self.tab_stops.as_ref());
+        let vertical = self.orientation == TextOrientation::VerticalRightToLeft;
+        if vertical {
+            let range = self.attr_string.range();
+            self.attr_string.set_vertical_forms(range);
+        }
+        self.decorations.strikethroughs = util::resolve_flag_ranges(
+            self.attrs.defaults.strikethrough,
+            self.text.as_str().len(),
+            self.strikethroughs,
+        );
+        self.decorations.underlines = util::resolve_underlines(
+            &self.attrs.defaults,
+            self.text.as_str().len(),
+            self.underlines,
+            self.underline_colors,
+            self.underline_styles,
+        );
+        let ellipsis = match self.truncation {
+            TruncationMode::EllipsisEnd => Some(self.ellipsis_line()),
+            TruncationMode::Clip => None,
+        };
+        let truncation = self.max_lines.map(|max_lines| Truncation {
+            max_lines,
+            ellipsis,
+        });
+        let backgrounds = util::resolve_spans(
+            self.attrs.defaults.background_color.clone(),
+            self.text.as_str().len(),
+            std::mem::take(&mut self.backgrounds),
+        );
+        let shadows = util::resolve_spans(
+            self.attrs.defaults.shadow.clone(),
+            self.text.as_str().len(),
+            std::mem::take(&mut self.shadows),
+        );
         Ok(CoreGraphicsTextLayout::new(
             self.text,
             self.attr_string,
             self.width,
+            self.alignment,
+            self.decorations,
+            backgrounds,
+            shadows,
+            truncation,
+            vertical,
         ))
     }
 }
 
 impl TextLayout for CoreGraphicsTextLayout {
     fn width(&self) -> f64 {
-        self.frame_size.width
+        self.size().width
     }
 
     fn size(&self) -> Size {
-        self.frame_size
+        if self.vertical {
+            Size::new(self.frame_size.height, self.frame_size.width)
+        } else {
+            self.frame_size
+        }
     }
 
     fn image_bounds(&self) -> Rect {
-        self.image_bounds
+        let bounds = self.line_transform().transform_rect_bbox(self.image_bounds);
+        util::bounds_with_shadows(bounds, self.shadows.iter().map(|(_, shadow)| shadow))
     }
 
     fn text(&self) -> &str {
-        &self.string
+        self.string.as_str()
     }
 
     #[allow(clippy::float_cmp)]
@@ -476,11 +851,24 @@ impl TextLayout for CoreGraphicsTextLayout {
         if width.ceil() != self.width_constraint.ceil() {
             let constraints = CGSize::new(width as CGFloat, CGFloat::INFINITY);
             let char_range = self.attr_string.range();
-            let (frame_size, _) = self.framesetter.suggest_frame_size(char_range, constraints);
-            let rect = CGRect::new(&CGPoint::new(0.0, 0.0), &frame_size);
-            let path = CGPath::from_rect(rect, None);
+            let (mut frame_size, _) = self.framesetter.suggest_frame_size(char_range, constraints);
+            // justified lines are stretched to the width of the frame, which
+            // should be the width we were given, not the width of the longest line.
+            if self.alignment == TextAlignment::Justified && width.is_finite() {
+                frame_size.width = width;
+            }
             self.width_constraint = width;
-            let frame = self.framesetter.create_frame(char_range, &path);
+            self.glyph_runs = OnceCell::new();
+            let mut frame = self.create_frame(char_range, frame_size);
+            let truncated_height = self
+                .truncation
+                .as_ref()
+                .and_then(|t| height_for_lines(&frame, frame_size.height, t.max_lines));
+            if let Some(height) = truncated_height {
+                // CoreText only lays out the lines that fit in the frame
+                frame_size.height = height;
+                frame = self.create_frame(char_range, frame_size);
+            }
             let lines = frame.get_lines();
             let line_count = lines.len();
             let line_origins = frame.get_line_origins(CFRange::init(0, line_count));
@@ -501,13 +889,22 @@ impl TextLayout for CoreGraphicsTextLayout {
             self.image_bounds = line_bounds.fold(first_line_bounds, |acc, el| acc.union(el));
 
             self.rebuild_line_offsets();
+            self.visible_end = self.string.as_str().len();
+            self.ellipsis_x = None;
+            if truncated_height.is_some() {
+                self.truncate_last_line(width);
+            }
         }
         Ok(())
     }
 
     fn line_text(&self, line_number: usize) -> Option<&str> {
         self.line_range(line_number)
-            .map(|(start, end)| unsafe { self.string.get_unchecked(start..end) })
+            .map(|(start, end)| unsafe { self.string.as_str().get_unchecked(start..end) })
+    }
+
+    fn line_text_range(&self, line_number: usize) -> Option<Range<usize>> {
+        self.line_range(line_number).map(|(start, end)| start..end)
     }
 
     fn line_metric(&self, line_number: usize) -> Option<LineMetric> {
@@ -539,6 +936,7 @@ impl TextLayout for CoreGraphicsTextLayout {
             start_offset,
             end_offset,
             trailing_whitespace,
+            trailing_whitespace_width: line.get_trailing_whitespace_width(),
             baseline: typo_bounds.ascent,
             height,
             y_offset,
@@ -554,6 +952,7 @@ impl TextLayout for CoreGraphicsTextLayout {
         if self.line_y_positions.is_empty() {
             return HitTestPoint::default();
         }
+        let point = self.line_transform().inverse() * point;
 
         let mut line_num = self
             .line_y_positions
@@ -580,9 +979,20 @@ impl TextLayout for CoreGraphicsTextLayout {
         let fake_y = -(self.frame_size.height - fake_y);
         let point_in_string_space = CGPoint::new(point.x, fake_y);
         let offset_utf16 = line.get_string_index_for_position(point_in_string_space);
+        let line_start_16 = line.get_string_range().location;
+        // the hit is on the trailing half of the preceding grapheme if the
+        // caret at the resulting index is before the point.
+        let mut affinity = if offset_utf16 < 0
+            || (offset_utf16 > line_start_16
+                && line.get_offset_for_string_index(offset_utf16) < point.x)
+        {
+            Affinity::Upstream
+        } else {
+            Affinity::Downstream
+        };
         let offset = match offset_utf16 {
             // this is 'kCFNotFound'.
-            -1 => self.string.len(),
+            -1 => self.visible_end,
             n if n >= 0 => {
                 let utf16_range = line.get_string_range();
                 let utf8_range = self.line_range(line_num).unwrap();
@@ -595,16 +1005,75 @@ impl TextLayout for CoreGraphicsTextLayout {
             // some other value; should never happen
             _ => panic!("gross violation of api contract"),
         };
+        if offset > self.visible_end {
+            affinity = Affinity::Upstream;
+        }
+        let offset = offset.min(self.visible_end);
 
         let typo_bounds = line.get_typographic_bounds();
         let is_inside_y = point.y >= 0. && point.y <= self.frame_size.height;
         let is_inside_x = point.x >= 0. && point.x <= typo_bounds.width;
         let is_inside = is_inside_x && is_inside_y;
 
-        HitTestPoint::new(offset, is_inside)
+        HitTestPoint::new(offset, is_inside, affinity)
     }
 
     fn hit_test_text_position(&self, offset: usize) -> Option<HitTestPosition> {
+        let (point, line_num) = self.line_position(offset)?;
+        Some(HitTestPosition::new(
+            self.line_transform() * point,
+            line_num,
+        ))
+    }
+
+    fn hit_test_text_position_with_affinity(
+        &self,
+        offset: usize,
+        affinity: Affinity,
+    ) -> Option<HitTestPosition> {
+        util::hit_test_text_position_with_affinity(self, offset, affinity)
+    }
+
+    fn rects_for_range(&self, range: impl RangeBounds<usize>) -> Vec<Rect> {
+        let transform = self.line_transform();
+        self.line_rects_for_range(range)
+            .into_iter()
+            .map(|rect| transform.transform_rect_bbox(rect))
+            .collect()
+    }
+
+    fn glyph_runs(&self) -> &[GlyphRun] {
+        self.glyph_runs.get_or_init(|| {
+            let transform = self.line_transform();
+            let mut runs: Vec<_> = self.font_runs().into_iter().map(|(_, run)| run).collect();
+            for glyph in runs.iter_mut().flat_map(|run| run.glyphs.iter_mut()) {
+                glyph.position = transform * glyph.position;
+            }
+            runs
+        })
+    }
+
+    fn outline(&self) -> BezPath {
+        self.outline_for_range(0..usize::MAX)
+    }
+}
+
+impl CoreGraphicsTextLayout {
+    /// The transform from the space our lines are laid out in to the layout's
+    /// coordinate space; vertical lines are rotated a quarter turn clockwise,
+    /// so that successive lines are placed to the left.
+    pub(crate) fn line_transform(&self) -> Affine {
+        if self.vertical {
+            Affine::new([0.0, 1.0, -1.0, 0.0, self.frame_size.height, 0.0])
+        } else {
+            Affine::default()
+        }
+    }
+
+    /// The position of the caret at `offset` in the space of the lines, and
+    /// the line it is on.
+    fn line_position(&self, offset: usize) -> Option<(Point, usize)> {
+        let offset = offset.min(self.visible_end);
         let line_num = self.line_number_for_utf8_offset(offset);
         let line: Line = self.unwrap_frame().get_line(line_num)?.into();
         let text = self.line_text(line_num)?;
@@ -615,41 +1084,143 @@ impl TextLayout for CoreGraphicsTextLayout {
         let char_idx = line_range.location + off16 as isize;
         let x_pos = line.get_offset_for_string_index(char_idx);
         let y_pos = self.line_y_positions[line_num];
-        Some(HitTestPosition::new(Point::new(x_pos, y_pos), line_num))
+        Some((Point::new(x_pos, y_pos), line_num))
     }
 
-    fn rects_for_range(&self, range: impl RangeBounds<usize>) -> Vec<Rect> {
-        let range = util::resolve_range(range, self.string.len());
-        let first_line = self.line_number_for_utf8_offset(range.start);
-        let last_line = self.line_number_for_utf8_offset(range.end);
+    /// The rects covering `range`, in the space of the lines.
+    fn line_rects_for_range(&self, range: impl RangeBounds<usize>) -> Vec<Rect> {
+        let lines = (0..self.line_count())
+            .filter_map(|line| self.line_metric(line))
+            .collect::<Vec<_>>();
 
         let mut result = Vec::new();
+        for (line_num, range, newline) in util::line_ranges(self.string.as_str(), &lines, range) {
+            let end = range.end;
+            result.extend(self.rects_for_line_range(line_num, range));
+            if newline {
+                if let Some((point, _)) = self.line_position(end) {
+                    result.push(util::newline_rect(&lines[line_num], point.x));
+                }
+            }
+        }
+        result
+    }
 
-        for line in first_line..=last_line {
-            let metrics = self.line_metric(line).unwrap();
-            let y0 = metrics.y_offset;
-            let y1 = y0 + metrics.height;
-            let line_range_start = if line == first_line {
-                range.start
-            } else {
-                metrics.start_offset
-            };
+    /// The glyph runs of this layout, with the fonts they are drawn with.
+    fn font_runs(&self) -> Vec<(CTFont, GlyphRun)> {
+        let frame = match self.frame.as_ref() {
+            Some(frame) => frame,
+            None => return Vec::new(),
+        };
+        let lines = frame.get_lines();
+        let origins = frame.get_line_origins(CFRange::init(0, lines.len()));
+        // with an ellipsis, the last line still includes the text it replaces
+        let ellipsis = self.truncation.as_ref().and_then(|t| t.ellipsis.as_ref());
+        let ellipsis = ellipsis.zip(self.ellipsis_x);
+        let mut result = Vec::new();
 
-            let line_range_end = if line == last_line {
-                range.end
-            } else {
-                metrics.end_offset - metrics.trailing_whitespace
-            };
-            let start_point = self.hit_test_text_position(line_range_start).unwrap();
-            let end_point = self.hit_test_text_position(line_range_end).unwrap();
-            result.push(Rect::new(start_point.point.x, y0, end_point.point.x, y1));
+        for (line_num, (line, origin)) in lines.iter().zip(origins.iter()).enumerate() {
+            let line = Line::new(&line);
+            let line_start = self.line_offsets[line_num];
+            let line_start_16 = line.get_string_range().location;
+            let text = self.line_text(line_num).unwrap_or_default();
+            let y = self.line_y_positions[line_num];
+            for run in line.glyph_runs().iter() {
+                let run = glyph_run(
+                    &run,
+                    |pos| Point::new(origin.x + pos.x, y - pos.y),
+                    |idx16| {
+                        let idx16 = (idx16 - line_start_16).max(0) as usize;
+                        line_start + util::count_until_utf16(text, idx16).unwrap_or(text.len())
+                    },
+                );
+                result.extend(run.map(|(font, mut run)| {
+                    if ellipsis.is_some() {
+                        run.glyphs.retain(|g| g.cluster < self.visible_end);
+                    }
+                    (font, run)
+                }));
+            }
+        }
+
+        if let Some((ellipsis, ellipsis_x)) = ellipsis {
+            let y = self.line_y_positions.last().copied().unwrap_or_default();
+            for run in Line::new(&ellipsis).glyph_runs().iter() {
+                let run = glyph_run(
+                    &run,
+                    |pos| Point::new(ellipsis_x + pos.x, y - pos.y),
+                    |_| self.visible_end,
+                );
+                result.extend(run);
+            }
         }
+        result.retain(|(_, run)| !run.glyphs.is_empty());
         result
     }
 }
 
+/// Converts a CoreText run to a `GlyphRun`, given functions that map the
+/// run's glyph positions to layout coordinates, and its string indices to
+/// utf-8 offsets in the layout's text.
+fn glyph_run(
+    run: &CTRun,
+    position: impl Fn(CGPoint) -> Point,
+    cluster: impl Fn(isize) -> usize,
+) -> Option<(CTFont, GlyphRun)> {
+    let font = ct_helpers::run_font(run)?;
+    let glyphs = run
+        .glyphs()
+        .iter()
+        .zip(run.positions().iter())
+        .zip(run.string_indices().iter())
+        .map(|((id, pos), idx)| Glyph {
+            id: *id as u32,
+            position: position(*pos),
+            cluster: cluster(*idx),
+        })
+        .collect();
+    let run = GlyphRun {
+        font: FontFamily::new_unchecked(font.family_name()),
+        font_size: font.pt_size(),
+        glyphs,
+    };
+    Some((font, run))
+}
+
+/// Appends the elements of a `CGPath` to a `BezPath`.
+fn append_cg_path(path: &mut BezPath, cg_path: &CGPath) {
+    let to_point = |p: &CGPoint| Point::new(p.x, p.y);
+    let append = |element: CGPathElementRef| {
+        let points = element.points();
+        match element.element_type {
+            CGPathElementType::MoveToPoint => path.move_to(to_point(&points[0])),
+            CGPathElementType::AddLineToPoint => path.line_to(to_point(&points[0])),
+            CGPathElementType::AddQuadCurveToPoint => {
+                path.quad_to(to_point(&points[0]), to_point(&points[1]))
+            }
+            CGPathElementType::AddCurveToPoint => path.curve_to(
+                to_point(&points[0]),
+                to_point(&points[1]),
+                to_point(&points[2]),
+            ),
+            CGPathElementType::CloseSubpath => path.close_path(),
+        }
+    };
+    cg_path.apply(&append);
+}
+
 impl CoreGraphicsTextLayout {
-    fn new(text: String, attr_string: AttributedString, width_constraint: f64) -> Self {
+    fn new(
+        text: Arc<dyn LayoutStorage>,
+        attr_string: AttributedString,
+        width_constraint: f64,
+        alignment: TextAlignment,
+        decorations: Decorations,
+        backgrounds: Vec<(Range<usize>, Color)>,
+        shadows: Vec<(Range<usize>, ShadowStyle)>,
+        truncation: Option<Truncation>,
+        vertical: bool,
+    ) -> Self {
         let framesetter = Framesetter::new(&attr_string);
 
         let mut layout = CoreGraphicsTextLayout {
@@ -664,13 +1235,276 @@ impl CoreGraphicsTextLayout {
             // NaN to ensure we always execute code in update_width
             width_constraint: f64::NAN,
             line_offsets: Vec::new(),
+            alignment,
+            decorations,
+            backgrounds,
+            shadows,
+            truncation,
+            visible_end: 0,
+            ellipsis_x: None,
+            vertical,
+            glyph_runs: OnceCell::new(),
         };
         layout.update_width(width_constraint).unwrap();
         layout
     }
 
     pub(crate) fn draw(&self, ctx: &mut CGContextRef) {
-        self.unwrap_frame().0.draw(ctx)
+        let ellipsis = self.truncation.as_ref().and_then(|t| t.ellipsis.as_ref());
+        let (ellipsis, ellipsis_x) = match (ellipsis, self.ellipsis_x) {
+            (Some(ellipsis), Some(x)) => (ellipsis, x),
+            _ => return self.unwrap_frame().0.draw(ctx),
+        };
+
+        // the frame still contains the text that the ellipsis replaces, so we
+        // clip it out of the last line. We're drawing in the flipped space here.
+        let last_line = self.line_count() - 1;
+        let baseline = self.frame_size.height - self.line_y_positions[last_line];
+        let line_top = match self.unwrap_frame().get_line(last_line) {
+            Some(line) => baseline + Line::from(line).get_typographic_bounds().ascent,
+            None => baseline,
+        };
+        ctx.save();
+        ctx.clip_to_rects(&[
+            CGRect::new(
+                &CGPoint::new(-CLIP_EXTENT, line_top),
+                &CGSize::new(2.0 * CLIP_EXTENT, CLIP_EXTENT),
+            ),
+            CGRect::new(
+                &CGPoint::new(-CLIP_EXTENT, -CLIP_EXTENT),
+                &CGSize::new(CLIP_EXTENT + ellipsis_x, 2.0 * CLIP_EXTENT),
+            ),
+        ]);
+        self.unwrap_frame().0.draw(ctx);
+        ctx.restore();
+        ctx.set_text_position(ellipsis_x, baseline);
+        Line::new(&ellipsis).draw(ctx);
+    }
+
+    fn create_frame(&self, char_range: CFRange, size: CGSize) -> Frame {
+        let rect = CGRect::new(&CGPoint::new(0.0, 0.0), &size);
+        let path = CGPath::from_rect(rect, None);
+        self.framesetter.create_frame(char_range, &path)
+    }
+
+    /// After truncating to `max_lines`, find the end of the visible text,
+    /// making room for the ellipsis if there is one.
+    fn truncate_last_line(&mut self, width: f64) {
+        let last_line = self.line_count() - 1;
+        let line: Line = match self.unwrap_frame().get_line(last_line) {
+            Some(line) => line.into(),
+            None => return,
+        };
+        let range = line.get_string_range();
+        let line_start = self.line_offsets[last_line];
+        let line_end_16 = (range.location + range.length) as usize;
+        self.visible_end = util::count_until_utf16(self.string.as_str(), line_end_16)
+            .unwrap_or_else(|| self.string.as_str().len());
+
+        let ellipsis = match self.truncation.as_ref().and_then(|t| t.ellipsis.as_ref()) {
+            Some(ellipsis) => ellipsis,
+            None => return,
+        };
+        let ellipsis_width = Line::new(&ellipsis).get_typographic_bounds().width;
+        let text = &self.string.as_str()[line_start..self.visible_end];
+        let x_for_offset = |offset: usize| {
+            let off16 = util::count_utf16(&text[..offset]);
+            line.get_offset_for_string_index(range.location + off16 as isize)
+        };
+
+        let mut end = text.trim_end().len();
+        while end > 0 && x_for_offset(end) + ellipsis_width > width {
+            end = text[..end]
+                .char_indices()
+                .next_back()
+                .map(|(idx, _)| idx)
+                .unwrap_or(0);
+        }
+        let end = text[..end].trim_end().len();
+        self.ellipsis_x = Some(x_for_offset(end));
+        self.visible_end = line_start + end;
+    }
+
+    /// The rects (relative to the layout origin) and colors of strikethrough lines.
+    pub(crate) fn strikethrough_rects(&self) -> Vec<(Rect, Color)> {
+        let half_thickness = self.decorations.thickness / 2.0;
+        let mut result = Vec::new();
+        for range in &self.decorations.strikethroughs {
+            for (piece, color) in self.decorations.color_pieces(range.clone()) {
+                for (line_num, x0, x1) in self.decoration_extents(piece) {
+                    let y = self.line_y_positions[line_num] - self.decorations.strikethrough_offset;
+                    let rect = Rect::new(x0, y - half_thickness, x1, y + half_thickness);
+                    result.push((rect, color.clone()));
+                }
+            }
+        }
+        result
+    }
+
+    /// The thickness of underline and strikethrough lines.
+    pub(crate) fn decoration_thickness(&self) -> f64 {
+        self.decorations.thickness
+    }
+
+    /// The underlines of this layout, relative to the layout origin. Each is
+    /// a path to be stroked with its `StrokeStyle`, at the width given by
+    /// `decoration_thickness`.
+    pub(crate) fn underline_paths(&self) -> Vec<(BezPath, StrokeStyle, Color)> {
+        let thickness = self.decorations.thickness;
+        let mut result = Vec::new();
+        for (range, style, color) in &self.decorations.underlines {
+            let pieces = match color {
+                Some(color) => vec![(range.clone(), color.clone())],
+                None => self.decorations.color_pieces(range.clone()),
+            };
+            for (piece, color) in pieces {
+                for (line_num, x0, x1) in self.decoration_extents(piece) {
+                    let y = self.line_y_positions[line_num] - self.decorations.underline_offset;
+                    let (path, stroke) = util::underline_geometry(*style, x0, x1, y, thickness);
+                    result.push((path, stroke, color.clone()));
+                }
+            }
+        }
+        result
+    }
+
+    /// The start and end x positions of the visible part of `range` on each
+    /// line it touches.
+    fn decoration_extents(&self, range: Range<usize>) -> Vec<(usize, f64, f64)> {
+        let first_line = self.line_number_for_utf8_offset(range.start);
+        let last_line = self.line_number_for_utf8_offset(range.end);
+        (first_line..=last_line)
+            .filter_map(|line_num| {
+                self.decoration_extent_for_line(line_num, range.clone())
+                    .map(|(x0, x1)| (line_num, x0, x1))
+            })
+            .collect()
+    }
+
+    /// The rects (relative to the layout origin) and colors of text backgrounds.
+    pub(crate) fn background_rects(&self) -> Vec<(Rect, Color)> {
+        let mut result = Vec::new();
+        for (range, color) in &self.backgrounds {
+            let first_line = self.line_number_for_utf8_offset(range.start);
+            let last_line = self.line_number_for_utf8_offset(range.end);
+            for line_num in first_line..=last_line {
+                let metric = match self.line_metric(line_num) {
+                    Some(metric) => metric,
+                    None => continue,
+                };
+                let start = range.start.max(metric.start_offset);
+                let end = range
+                    .end
+                    .min(metric.end_offset - metric.trailing_whitespace);
+                for rect in self.rects_for_line_range(line_num, start..end) {
+                    result.push((rect, color.clone()));
+                }
+            }
+        }
+        result
+    }
+
+    /// The outlines (relative to the layout origin) of the glyphs that cast
+    /// each text shadow.
+    pub(crate) fn shadow_paths(&self) -> Vec<(BezPath, &ShadowStyle)> {
+        self.shadows
+            .iter()
+            .map(|(range, shadow)| (self.outline_for_range(range.clone()), shadow))
+            .collect()
+    }
+
+    /// The outline of the glyphs whose clusters start in `range`.
+    fn outline_for_range(&self, range: Range<usize>) -> BezPath {
+        let mut path = BezPath::new();
+        for (font, run) in self.font_runs() {
+            for glyph in run.glyphs.iter().filter(|g| range.contains(&g.cluster)) {
+                // glyph outlines are y-up, relative to the glyph's origin
+                let Point { x, y } = glyph.position;
+                let transform = CGAffineTransform::new(1.0, 0.0, 0.0, -1.0, x, y);
+                if let Ok(outline) = font.create_path_for_glyph(glyph.id as CGGlyph, &transform) {
+                    append_cg_path(&mut path, &outline);
+                }
+            }
+        }
+        self.line_transform() * path
+    }
+
+    /// The rects covering a range of the text on a single line, from left to
+    /// right.
+    ///
+    /// A range may cover several separate regions of a line if it includes runs
+    /// of different directions, so we measure each character and merge the
+    /// regions that touch.
+    fn rects_for_line_range(&self, line_num: usize, range: Range<usize>) -> Vec<Rect> {
+        let metric = match self.line_metric(line_num) {
+            Some(metric) => metric,
+            None => return Vec::new(),
+        };
+        let start = range.start.max(metric.start_offset);
+        let end = range.end.min(metric.end_offset);
+        if start >= end {
+            return Vec::new();
+        }
+
+        let line: Line = match self.unwrap_frame().get_line(line_num) {
+            Some(line) => line.into(),
+            None => return Vec::new(),
+        };
+        let line_start_16 = line.get_string_range().location;
+        let x_for_offset = |offset: usize| {
+            let off16 = util::count_utf16(&self.string.as_str()[metric.start_offset..offset]);
+            line.get_offset_for_string_index(line_start_16 + off16 as isize)
+        };
+
+        let mut spans = self.string.as_str()[start..end]
+            .char_indices()
+            .map(|(idx, c)| {
+                let x0 = x_for_offset(start + idx);
+                let x1 = x_for_offset(start + idx + c.len_utf8());
+                (x0.min(x1), x0.max(x1))
+            })
+            .collect::<Vec<_>>();
+        spans.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let mut merged: Vec<(f64, f64)> = Vec::new();
+        for (x0, x1) in spans {
+            match merged.last_mut() {
+                // allow for rounding between adjacent characters
+                Some(last) if x0 - last.1 < 0.5 => last.1 = last.1.max(x1),
+                _ => merged.push((x0, x1)),
+            }
+        }
+
+        let y0 = metric.y_offset;
+        let y1 = y0 + metric.height;
+        merged
+            .into_iter()
+            .map(|(x0, x1)| Rect::new(x0, y0, x1, y1))
+            .collect()
+    }
+
+    fn decoration_extent_for_line(
+        &self,
+        line_num: usize,
+        range: Range<usize>,
+    ) -> Option<(f64, f64)> {
+        let metric = self.line_metric(line_num)?;
+        let start = range.start.max(metric.start_offset);
+        let end = range
+            .end
+            .min(metric.end_offset - metric.trailing_whitespace);
+        if start >= end {
+            return None;
+        }
+
+        let line: Line = self.unwrap_frame().get_line(line_num)?.into();
+        let line_start_16 = line.get_string_range().location;
+        let x_for_offset = |offset: usize| {
+            let off16 = util::count_utf16(&self.string.as_str()[metric.start_offset..offset]);
+            line.get_offset_for_string_index(line_start_16 + off16 as isize)
+        };
+
+        Some((x_for_offset(start), x_for_offset(end)))
     }
 
     #[inline]
@@ -696,7 +1530,7 @@ impl CoreGraphicsTextLayout {
             range.location as usize
         });
 
-        let mut chars = self.string.chars();
+        let mut chars = self.string.as_str().chars();
         let mut cur_16 = 0;
         let mut cur_8 = 0;
 
@@ -721,7 +1555,7 @@ impl CoreGraphicsTextLayout {
         if line <= self.line_count() {
             let start = self.line_offsets[line];
             let end = if line == self.line_count() - 1 {
-                self.string.len()
+                self.visible_end
             } else {
                 self.line_offsets[line + 1]
             };
@@ -739,18 +1573,45 @@ impl CoreGraphicsTextLayout {
                 .line_offsets
                 .get(i + 1)
                 .copied()
-                .unwrap_or_else(|| self.string.len());
+                .unwrap_or_else(|| self.string.as_str().len());
             println!(
                 "L{} ({}..{}): '{}'",
                 i,
                 start,
                 end,
-                &self.string[start..end].escape_debug()
+                &self.string.as_str()[start..end].escape_debug()
             );
         }
     }
 }
 
+impl Decorations {
+    /// Split a range into pieces that each have a single foreground color.
+    fn color_pieces(&self, range: Range<usize>) -> Vec<(Range<usize>, Color)> {
+        let mut bounds = vec![range.start, range.end];
+        for (fg_range, _) in &self.fg_colors {
+            bounds.extend_from_slice(&[fg_range.start, fg_range.end]);
+        }
+        bounds.retain(|pos| range.contains(pos) || *pos == range.end);
+        bounds.sort_unstable();
+        bounds.dedup();
+
+        bounds
+            .windows(2)
+            .map(|pair| {
+                let color = self
+                    .fg_colors
+                    .iter()
+                    .rev()
+                    .find(|(fg_range, _)| fg_range.contains(&pair[0]))
+                    .map(|(_, color)| color.clone())
+                    .unwrap_or_else(|| self.default_color.clone());
+                (pair[0]..pair[1], color)
+            })
+            .collect()
+    }
+}
+
 #[cfg(test)]
 #[allow(clippy::float_cmp)]
 mod tests {
@@ -901,10 +1762,323 @@ mod tests {
         assert!(p2.point.y > p1.point.y);
     }
 
+    #[test]
+    fn font_metrics() {
+        let mut text = CoreGraphicsText::new_with_unique_state();
+        let family = FontFamily::new_unchecked("Helvetica");
+        let metrics = text.font_metrics(&family, 20.0).unwrap();
+        assert!(metrics.ascent > metrics.cap_height);
+        assert!(metrics.cap_height > metrics.x_height);
+        assert!(metrics.x_height > 0.0);
+        assert!(metrics.descent > 0.0);
+        assert!(metrics.underline_position > 0.0);
+        assert!(metrics.strikeout_position < 0.0);
+        assert!(metrics.underline_thickness > 0.0);
+
+        let small = text.font_metrics(&family, 10.0).unwrap();
+        assert!((small.cap_height * 2.0 - metrics.cap_height).abs() < 0.01);
+    }
+
+    #[test]
+    fn break_opportunities_unsupported() {
+        let layout = CoreGraphicsTextLayoutBuilder::new("/usr/local/share")
+            .break_opportunities(|_| vec![5])
+            .build();
+        assert!(matches!(layout, Err(Error::NotSupported)));
+    }
+
+    #[test]
+    fn vertical_lines() {
+        let text = "縦書きの文章です";
+        let layout = CoreGraphicsTextLayoutBuilder::new(text)
+            .font(FontFamily::new_unchecked("Hiragino Mincho ProN"), 20.0)
+            .max_width(45.0)
+            .orientation(TextOrientation::VerticalRightToLeft)
+            .build()
+            .unwrap();
+        // two characters fit in each line
+        assert_eq!(layout.line_count(), 4);
+        let size = layout.size();
+        assert!(size.height <= 45.0);
+        assert!(size.width > size.height);
+
+        // the text starts at the top right, and ends at the bottom left
+        let first = layout.hit_test_point(Point::new(size.width - 5.0, 5.0));
+        assert_eq!(first.idx, 0);
+        let last = text.len() - "す".len();
+        let last = layout.hit_test_text_position(last).unwrap();
+        assert!(last.point.x < size.width / 4.0);
+        assert!(last.point.y > 10.0);
+
+        // the first line is a tall and narrow rect on the right
+        let rects = layout.rects_for_range(0.."縦書".len());
+        assert_eq!(rects.len(), 1);
+        assert!(rects[0].height() > rects[0].width());
+        assert!(rects[0].x1 > size.width - 1.0);
+    }
+
+    #[test]
+    fn load_font_from_path() {
+        let mut text = CoreGraphicsText::new_with_unique_state();
+        let path = Path::new("/System/Library/Fonts/Helvetica.ttc");
+        let family = text.load_font_from_path(path).unwrap();
+        assert_eq!(family.name(), "Helvetica");
+        assert_eq!(text.font_family("Helvetica"), Some(family));
+
+        let missing = text.load_font_from_path(Path::new("/no/such/font.ttf"));
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn font_families() {
+        let mut text = CoreGraphicsText::new_with_unique_state();
+        let families = text.font_families();
+        assert!(families.iter().any(|family| family.name() == "Helvetica"));
+        for pair in families.windows(2) {
+            assert!(pair[0].name() <= pair[1].name());
+        }
+        for family in families {
+            let found = text.font_family(family.name()).unwrap();
+            assert_eq!(found.name(), family.name());
+        }
+    }
+
+    #[test]
+    fn locale_selects_fallback() {
+        // the same characters are drawn with different fonts in Chinese
+        // and in Japanese
+        let font_for = |locale| {
+            let layout = CoreGraphicsTextLayoutBuilder::new("直骨")
+                .locale(locale)
+                .build()
+                .unwrap();
+            layout.glyph_runs()[0].font.clone()
+        };
+        assert_ne!(font_for("zh-Hans"), font_for("ja"));
+
+        // a range attribute applies only to its range
+        let layout = CoreGraphicsTextLayoutBuilder::new("直骨 直骨")
+            .locale("zh-Hans")
+            .range_attribute(7.., TextAttribute::Locale("ja".into()))
+            .build()
+            .unwrap();
+        let runs = layout.glyph_runs();
+        assert_eq!(runs[0].font, font_for("zh-Hans"));
+        assert_eq!(runs.last().unwrap().font, font_for("ja"));
+    }
+
+    #[test]
+    fn font_fallbacks() {
+        let families = FontFamilyList::new(FontFamily::new_unchecked("Helvetica"))
+            .with(FontFamily::new_unchecked("Hiragino Mincho ProN"));
+        let layout = CoreGraphicsTextLayoutBuilder::new("piet 日本語")
+            .default_attribute(families.clone())
+            .build()
+            .unwrap();
+        let runs = layout.glyph_runs();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].font.name(), "Helvetica");
+        assert_eq!(runs[1].font.name(), "Hiragino Mincho ProN");
+        assert_eq!(runs[1].glyphs[0].cluster, 5);
+
+        // the list can also be set for a range
+        let layout = CoreGraphicsTextLayoutBuilder::new("日本語 日本語")
+            .range_attribute(0..9, families)
+            .build()
+            .unwrap();
+        let runs = layout.glyph_runs();
+        assert_eq!(runs[0].font.name(), "Hiragino Mincho ProN");
+        assert_ne!(runs.last().unwrap().font.name(), "Hiragino Mincho ProN");
+    }
+
     #[test]
     fn missing_font_is_missing() {
         assert!(CoreGraphicsText::new_with_unique_state()
             .font_family("Segoe UI")
             .is_none());
     }
+
+    #[test]
+    fn justified_fills_width() {
+        let text = "piet text is the best text, and this is a lot of it";
+        let a_font = FontFamily::new_unchecked("Helvetica");
+        let layout = CoreGraphicsTextLayoutBuilder::new(text)
+            .font(a_font, 16.0)
+            .max_width(120.0)
+            .alignment(TextAlignment::Justified)
+            .build()
+            .unwrap();
+        assert!(layout.line_count() > 1);
+        assert!((layout.size().width - 120.0).abs() < 0.01);
+
+        let first = layout.line_metric(0).unwrap();
+        let visible_end = first.end_offset - first.trailing_whitespace;
+        let end = layout.hit_test_text_position(visible_end).unwrap().point;
+        assert!(end.x > 115.0);
+        let hit = layout.hit_test_point(Point::new(end.x - 0.5, end.y));
+        assert_eq!(hit.idx, visible_end);
+    }
+
+    #[test]
+    fn word_spacing() {
+        let text = "piet text is";
+        let a_font = FontFamily::new_unchecked("Helvetica");
+        let layout = CoreGraphicsTextLayoutBuilder::new(text)
+            .font(a_font.clone(), 16.0)
+            .build()
+            .unwrap();
+        let spaced = CoreGraphicsTextLayoutBuilder::new(text)
+            .font(a_font, 16.0)
+            .default_attribute(TextAttribute::WordSpacing(5.0))
+            .build()
+            .unwrap();
+        assert!((spaced.size().width - layout.size().width - 10.0).abs() < 0.01);
+        let x = |layout: &CoreGraphicsTextLayout, pos| {
+            layout.hit_test_text_position(pos).unwrap().point.x
+        };
+        assert!((x(&spaced, 5) - x(&layout, 5) - 5.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn tab_stops() {
+        let a_font = FontFamily::new_unchecked("Helvetica");
+        let layout = CoreGraphicsTextLayoutBuilder::new("a\tb")
+            .font(a_font.clone(), 16.0)
+            .tab_stops(TabStops::Uniform(50.0))
+            .build()
+            .unwrap();
+        let b_pos = layout.hit_test_text_position(2).unwrap().point;
+        assert!((b_pos.x - 50.0).abs() < 0.01);
+
+        let layout = CoreGraphicsTextLayoutBuilder::new("a\tb\tc")
+            .font(a_font, 16.0)
+            .tab_stops(TabStops::Explicit(vec![30.0, 100.0]))
+            .build()
+            .unwrap();
+        let c_pos = layout.hit_test_text_position(4).unwrap().point;
+        assert!((c_pos.x - 100.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn trailing_whitespace_width() {
+        let a_font = FontFamily::new_unchecked("Helvetica");
+        let layout = CoreGraphicsTextLayoutBuilder::new("hello   ")
+            .font(a_font.clone(), 16.0)
+            .build()
+            .unwrap();
+        let x = |pos| layout.hit_test_text_position(pos).unwrap().point.x;
+        let line = layout.line_metric(0).unwrap();
+        assert_eq!(line.trailing_whitespace, 3);
+        assert!((line.trailing_whitespace_width - (x(8) - x(5))).abs() < 0.01);
+        let spaces = line.trailing_whitespace_width;
+
+        let wrapped = CoreGraphicsTextLayoutBuilder::new("hello   world")
+            .font(a_font, 16.0)
+            .max_width(60.0)
+            .build()
+            .unwrap();
+        assert_eq!(wrapped.line_count(), 2);
+        let line_0 = wrapped.line_metric(0).unwrap();
+        assert_eq!(line_0.trailing_whitespace, 3);
+        assert!((line_0.trailing_whitespace_width - spaces).abs() < 0.01);
+        assert_eq!(
+            wrapped.line_metric(1).unwrap().trailing_whitespace_width,
+            0.0
+        );
+    }
+
+    #[test]
+    fn glyph_runs() {
+        let text = "piet text\nis best";
+        let a_font = FontFamily::new_unchecked("Helvetica");
+        let layout = CoreGraphicsTextLayoutBuilder::new(text)
+            .font(a_font, 16.0)
+            .build()
+            .unwrap();
+        let runs = layout.glyph_runs();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].font.name(), "Helvetica");
+        assert_eq!(runs[0].font_size, 16.0);
+        assert_eq!(runs[0].glyphs[0].cluster, 0);
+        assert_eq!(runs[1].glyphs[0].cluster, 10);
+
+        let line_0 = layout.line_metric(0).unwrap();
+        let baseline = line_0.y_offset + line_0.baseline;
+        for glyph in &runs[0].glyphs {
+            assert!((glyph.position.y - baseline).abs() < 0.01);
+        }
+        for pair in runs[0].glyphs.windows(2) {
+            assert!(pair[0].cluster < pair[1].cluster);
+            assert!(pair[0].position.x < pair[1].position.x);
+        }
+    }
+
+    #[test]
+    fn outline() {
+        use piet::kurbo::Shape;
+        let text = "piet text\nis best";
+        let a_font = FontFamily::new_unchecked("Helvetica");
+        let layout = CoreGraphicsTextLayoutBuilder::new(text)
+            .font(a_font, 16.0)
+            .build()
+            .unwrap();
+        let outline = layout.outline();
+        assert!(!outline.elements().is_empty());
+        // the outlines are inside the layout's inked area, and not flipped
+        let bounds = outline.bounding_box();
+        let image_bounds = layout.image_bounds().inflate(1.0, 1.0);
+        assert_eq!(image_bounds.union(bounds), image_bounds);
+        assert!(bounds.y1 > layout.line_metric(1).unwrap().y_offset);
+    }
+
+    #[test]
+    fn rects_for_range() {
+        let text = "piet text\nis \u{5e9}\u{5dc}\u{5d5}\u{5dd} best";
+        let a_font = FontFamily::new_unchecked("Helvetica");
+        let layout = CoreGraphicsTextLayoutBuilder::new(text)
+            .font(a_font, 16.0)
+            .build()
+            .unwrap();
+        let x = |pos| layout.hit_test_text_position(pos).unwrap().point.x;
+        let line_1 = layout.line_metric(1).unwrap();
+
+        let rects = layout.rects_for_range(2..13);
+        assert_eq!(rects.len(), 3);
+        assert!((rects[0].x0 - x(2)).abs() < 0.01);
+        assert!((rects[0].x1 - x(9)).abs() < 0.01);
+        // the newline
+        assert!((rects[1].x0 - x(9)).abs() < 0.01);
+        assert!(rects[2].x0.abs() < 0.01);
+        assert!((rects[2].y0 - line_1.y_offset).abs() < 0.01);
+        assert!((rects[2].height() - line_1.height).abs() < 0.01);
+
+        // part of a right-to-left run and the text after it is two regions,
+        // from left to right.
+        let rects = layout.rects_for_range(17..24);
+        assert_eq!(rects.len(), 2);
+        assert!(rects[0].x1 < rects[1].x0);
+    }
+
+    #[test]
+    fn background_rects() {
+        let text = "piet text is the best text, and this is a lot of it";
+        let a_font = FontFamily::new_unchecked("Helvetica");
+        let layout = CoreGraphicsTextLayoutBuilder::new(text)
+            .font(a_font, 16.0)
+            .max_width(120.0)
+            .range_attribute(5..20, TextAttribute::BackgroundColor(Color::WHITE))
+            .build()
+            .unwrap();
+        let line_0 = layout.line_metric(0).unwrap();
+        assert!(line_0.end_offset > 5 && line_0.end_offset < 20);
+
+        let rects = layout.background_rects();
+        assert_eq!(rects.len(), 2);
+        let first = rects[0].0;
+        let start_x = layout.hit_test_text_position(5).unwrap().point.x;
+        assert!((first.x0 - start_x).abs() < 0.01);
+        assert!((first.y0 - line_0.y_offset).abs() < 0.01);
+        assert!((first.height() - line_0.height).abs() < 0.01);
+        assert!(rects[1].0.x0.abs() < 0.01);
+    }
 }
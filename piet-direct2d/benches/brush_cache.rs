@@ -0,0 +1,99 @@
+//! Measures the time saved by `make_brush_cached`, drawing many small
+//! gradient-filled widgets for a number of frames.
+//!
+//! Run with `cargo bench --bench brush_cache`.
+
+use std::time::{Duration, Instant};
+
+use piet::kurbo::Rect;
+use piet::{
+    util, Color, ColorInterpolation, FixedGradient, FixedLinearGradient, GradientExtend,
+    GradientStops, RenderContext,
+};
+use piet_direct2d::D2DRenderContext;
+
+const FRAMES: usize = 200;
+const WIDGETS: usize = 400;
+
+/// How each widget gets its brush.
+#[derive(Clone, Copy)]
+enum Brushes {
+    /// A new gradient every time.
+    Uncached,
+    /// A cached brush, with a key the widget already has.
+    Keyed,
+    /// A cached brush, keyed by a hash of the gradient.
+    Hashed,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // warm up, so that the first measurement doesn't include creating the
+    // device
+    time_frames(Brushes::Uncached)?;
+
+    println!("{} frames of {} gradients, per frame:", FRAMES, WIDGETS);
+    for &(name, brushes) in &[
+        ("gradient", Brushes::Uncached),
+        ("make_brush_cached", Brushes::Keyed),
+        ("... with gradient_key", Brushes::Hashed),
+    ] {
+        let time = time_frames(brushes)?;
+        println!("  {:<22} {:?}", name, time / FRAMES as u32);
+    }
+    Ok(())
+}
+
+fn time_frames(brushes: Brushes) -> Result<Duration, Box<dyn std::error::Error>> {
+    let d2d = piet_direct2d::D2DFactory::new()?;
+    let dwrite = piet_direct2d::DwriteFactory::new()?;
+    let (d3d, _d3d_ctx) = piet_direct2d::d3d::D3D11Device::create()?;
+    let mut device = unsafe { d2d.create_device(d3d.as_dxgi().unwrap().as_raw())? };
+    let mut context = device.create_device_context()?;
+    let tex = d3d.create_texture(400, 400, piet_direct2d::d3d::TextureMode::Target)?;
+    let target = unsafe { context.create_bitmap_from_dxgi(&tex.as_dxgi(), 1.0)? };
+    context.set_target(&target);
+
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        context.begin_draw();
+        let mut rc = D2DRenderContext::new(&d2d, dwrite.clone(), &mut context);
+        draw_frame(&mut rc, brushes);
+        rc.finish()?;
+        std::mem::drop(rc);
+        context.end_draw()?;
+    }
+    Ok(start.elapsed())
+}
+
+/// Draw the widgets, each a button with one of a few gradients.
+fn draw_frame(rc: &mut impl RenderContext, brushes: Brushes) {
+    for i in 0..WIDGETS {
+        let rect = Rect::from_origin_size(((i % 20 * 20) as f64, (i / 20 * 20) as f64), (4., 4.));
+        let style = i % 4;
+        let gradient = move || {
+            let shade = style as u8 * 40;
+            FixedGradient::Linear(FixedLinearGradient {
+                start: (0., 0.).into(),
+                end: (0., 20.).into(),
+                stops: GradientStops::to_vec((
+                    Color::rgb8(0xf0, 0xf0 - shade, 0xf0),
+                    Color::rgb8(0xc0, 0xc0, 0xc0 - shade),
+                    Color::rgb8(0xa0, 0xa0, 0xa0),
+                )),
+                extend: GradientExtend::Pad,
+                interpolation: ColorInterpolation::Oklab { subdivisions: 8 },
+            })
+        };
+        let brush = match brushes {
+            Brushes::Uncached => rc.gradient(gradient()).unwrap(),
+            // a widget knows which of its styles it is drawing
+            Brushes::Keyed => rc.make_brush_cached(style as u64, gradient).unwrap(),
+            Brushes::Hashed => {
+                let gradient = gradient();
+                let key = util::gradient_key(&gradient);
+                rc.make_brush_cached(key, || gradient).unwrap()
+            }
+        };
+        rc.fill(rect, &brush);
+    }
+}
@@ -0,0 +1,67 @@
+//! Measures the time saved by drawing circles with Direct2D's own ellipses,
+//! filling and stroking the points of a scatter plot as circles and as paths.
+//!
+//! Run with `cargo bench --bench circles`.
+
+use std::time::{Duration, Instant};
+
+use piet::kurbo::{BezPath, Circle, Shape};
+use piet::{Color, RenderContext};
+use piet_direct2d::D2DRenderContext;
+
+const FRAMES: usize = 10;
+const POINTS: usize = 10_000;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // warm up, so that the first measurement doesn't include creating the
+    // device
+    time_frames(true)?;
+
+    println!("{} frames of {} circles, per frame:", FRAMES, POINTS);
+    for &(name, as_paths) in &[("as paths", true), ("as circles", false)] {
+        let time = time_frames(as_paths)?;
+        println!("  {:<11} {:?}", name, time / FRAMES as u32);
+    }
+    Ok(())
+}
+
+fn time_frames(as_paths: bool) -> Result<Duration, Box<dyn std::error::Error>> {
+    let d2d = piet_direct2d::D2DFactory::new()?;
+    let dwrite = piet_direct2d::DwriteFactory::new()?;
+    let (d3d, _d3d_ctx) = piet_direct2d::d3d::D3D11Device::create()?;
+    let mut device = unsafe { d2d.create_device(d3d.as_dxgi().unwrap().as_raw())? };
+    let mut context = device.create_device_context()?;
+    let tex = d3d.create_texture(400, 400, piet_direct2d::d3d::TextureMode::Target)?;
+    let target = unsafe { context.create_bitmap_from_dxgi(&tex.as_dxgi(), 1.0)? };
+    context.set_target(&target);
+
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        context.begin_draw();
+        let mut rc = D2DRenderContext::new(&d2d, dwrite.clone(), &mut context);
+        draw_frame(&mut rc, as_paths);
+        rc.finish()?;
+        std::mem::drop(rc);
+        context.end_draw()?;
+    }
+    Ok(start.elapsed())
+}
+
+/// Draw the points, spread along a spiral.
+fn draw_frame(rc: &mut impl RenderContext, as_paths: bool) {
+    rc.clear(Color::WHITE);
+    for i in 0..POINTS {
+        let t = i as f64 / POINTS as f64;
+        let angle = t * 40.0;
+        let center = (200. + 180. * t * angle.cos(), 200. + 180. * t * angle.sin());
+        let circle = Circle::new(center, 3.0);
+        if as_paths {
+            let path = BezPath::from_vec(circle.to_bez_path(1e-3).collect());
+            rc.fill(&path, &Color::rgb8(0x40, 0x80, 0xc0));
+            rc.stroke(&path, &Color::BLACK, 0.5);
+        } else {
+            rc.fill(circle, &Color::rgb8(0x40, 0x80, 0xc0));
+            rc.stroke(circle, &Color::BLACK, 0.5);
+        }
+    }
+}
@@ -2,14 +2,18 @@
 
 use winapi::um::d2d1::{
     D2D1_CAP_STYLE, D2D1_CAP_STYLE_FLAT, D2D1_CAP_STYLE_ROUND, D2D1_CAP_STYLE_SQUARE, D2D1_COLOR_F,
-    D2D1_DASH_STYLE_CUSTOM, D2D1_DASH_STYLE_SOLID, D2D1_GRADIENT_STOP, D2D1_LINE_JOIN,
-    D2D1_LINE_JOIN_BEVEL, D2D1_LINE_JOIN_MITER, D2D1_LINE_JOIN_ROUND, D2D1_MATRIX_3X2_F,
+    D2D1_DASH_STYLE_CUSTOM, D2D1_DASH_STYLE_SOLID, D2D1_EXTEND_MODE, D2D1_EXTEND_MODE_CLAMP,
+    D2D1_EXTEND_MODE_MIRROR, D2D1_EXTEND_MODE_WRAP, D2D1_GRADIENT_STOP, D2D1_LINE_JOIN,
+    D2D1_LINE_JOIN_BEVEL, D2D1_LINE_JOIN_MITER_OR_BEVEL, D2D1_LINE_JOIN_ROUND, D2D1_MATRIX_3X2_F,
     D2D1_POINT_2F, D2D1_RECT_F, D2D1_STROKE_STYLE_PROPERTIES,
 };
 
 use piet::kurbo::{Affine, Point, Rect, Vec2};
 
-use piet::{Color, Error, GradientStop, LineCap, LineJoin, RoundFrom, RoundInto, StrokeStyle};
+use piet::{
+    Color, Error, GradientExtend, GradientStop, LineCap, LineJoin, RoundFrom, RoundInto,
+    StrokeStyle, TileMode,
+};
 
 use crate::d2d::D2DFactory;
 
@@ -115,6 +119,21 @@ pub(crate) fn gradient_stop_to_d2d(stop: &GradientStop) -> D2D1_GRADIENT_STOP {
     }
 }
 
+pub(crate) fn convert_extend(extend: GradientExtend) -> D2D1_EXTEND_MODE {
+    match extend {
+        GradientExtend::Pad => D2D1_EXTEND_MODE_CLAMP,
+        GradientExtend::Repeat => D2D1_EXTEND_MODE_WRAP,
+        GradientExtend::Reflect => D2D1_EXTEND_MODE_MIRROR,
+    }
+}
+
+pub(crate) fn convert_tile_mode(tile: TileMode) -> D2D1_EXTEND_MODE {
+    match tile {
+        TileMode::Repeat => D2D1_EXTEND_MODE_WRAP,
+        TileMode::Reflect => D2D1_EXTEND_MODE_MIRROR,
+    }
+}
+
 fn convert_line_cap(line_cap: LineCap) -> D2D1_CAP_STYLE {
     match line_cap {
         LineCap::Butt => D2D1_CAP_STYLE_FLAT,
@@ -128,12 +147,11 @@ fn convert_line_cap(line_cap: LineCap) -> D2D1_CAP_STYLE {
 
 fn convert_line_join(line_join: LineJoin) -> D2D1_LINE_JOIN {
     match line_join {
-        LineJoin::Miter => D2D1_LINE_JOIN_MITER,
+        // D2D1_LINE_JOIN_MITER clips miters at the limit; other backends
+        // bevel them, as this does.
+        LineJoin::Miter => D2D1_LINE_JOIN_MITER_OR_BEVEL,
         LineJoin::Round => D2D1_LINE_JOIN_ROUND,
         LineJoin::Bevel => D2D1_LINE_JOIN_BEVEL,
-        // Discussion topic: MiterOrBevel. Exposing that as optional
-        // functionality is actually a reasonable argument for this being
-        // an associated type rather than a concrete type.
     }
 }
 
@@ -145,6 +163,9 @@ pub(crate) fn convert_stroke_style(
     #[allow(unused)]
     let cap = convert_line_cap(stroke_style.line_cap.unwrap_or(LineCap::Butt));
     let join = convert_line_join(stroke_style.line_join.unwrap_or(LineJoin::Miter));
+    let miter_limit = stroke_style
+        .miter_limit
+        .unwrap_or(StrokeStyle::DEFAULT_MITER_LIMIT);
     let (dashes, dash_style, dash_off) = match &stroke_style.dash {
         Some((dashes, off)) => {
             let width_recip = if width == 0.0 { 1.0 } else { width.recip() };
@@ -157,7 +178,8 @@ pub(crate) fn convert_stroke_style(
                         .collect::<Vec<f32>>(),
                 ),
                 D2D1_DASH_STYLE_CUSTOM,
-                *off as f32,
+                // like the dashes, the offset is in multiples of the width
+                *off as f32 * width_recip,
             )
         }
         None => (None, D2D1_DASH_STYLE_SOLID, 0.0),
@@ -167,7 +189,8 @@ pub(crate) fn convert_stroke_style(
         endCap: cap,
         dashCap: D2D1_CAP_STYLE_FLAT,
         lineJoin: join,
-        miterLimit: stroke_style.miter_limit.unwrap_or(10.0) as f32,
+        // Direct2D compares the miter length to half the width, not the width
+        miterLimit: (miter_limit * 2.0) as f32,
         dashStyle: dash_style,
         dashOffset: dash_off,
     };
@@ -6,42 +6,47 @@
 // TODO: get rid of this when we actually do use everything
 #![allow(unused)]
 
+use std::cell::RefCell;
 use std::ffi::c_void;
-use std::fmt::{Debug, Display, Formatter};
 use std::marker::PhantomData;
 use std::ops::Deref;
 use std::ptr::{null, null_mut};
+use std::rc::Rc;
 
 use wio::com::ComPtr;
 
 use winapi::shared::dxgi::{IDXGIDevice, IDXGISurface};
-use winapi::shared::dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM;
+use winapi::shared::dxgiformat::{DXGI_FORMAT, DXGI_FORMAT_R8G8B8A8_UNORM};
 use winapi::shared::minwindef::TRUE;
 use winapi::shared::winerror::{HRESULT, SUCCEEDED};
 use winapi::um::d2d1::{
     D2D1CreateFactory, ID2D1Bitmap, ID2D1BitmapRenderTarget, ID2D1Brush, ID2D1Geometry,
     ID2D1GeometrySink, ID2D1GradientStopCollection, ID2D1Image, ID2D1Layer, ID2D1PathGeometry,
-    ID2D1SolidColorBrush, ID2D1StrokeStyle, D2D1_ANTIALIAS_MODE_PER_PRIMITIVE, D2D1_BEZIER_SEGMENT,
-    D2D1_BITMAP_INTERPOLATION_MODE, D2D1_BRUSH_PROPERTIES, D2D1_COLOR_F,
-    D2D1_COMPATIBLE_RENDER_TARGET_OPTIONS_NONE, D2D1_DEBUG_LEVEL_WARNING, D2D1_DRAW_TEXT_OPTIONS,
-    D2D1_EXTEND_MODE_CLAMP, D2D1_FACTORY_OPTIONS, D2D1_FACTORY_TYPE_MULTI_THREADED,
-    D2D1_FIGURE_BEGIN_FILLED, D2D1_FIGURE_BEGIN_HOLLOW, D2D1_FIGURE_END_CLOSED,
-    D2D1_FIGURE_END_OPEN, D2D1_FILL_MODE_ALTERNATE, D2D1_FILL_MODE_WINDING, D2D1_GAMMA_2_2,
-    D2D1_GRADIENT_STOP, D2D1_LAYER_OPTIONS_NONE, D2D1_LAYER_PARAMETERS,
-    D2D1_LINEAR_GRADIENT_BRUSH_PROPERTIES, D2D1_MATRIX_3X2_F, D2D1_POINT_2F,
-    D2D1_QUADRATIC_BEZIER_SEGMENT, D2D1_RADIAL_GRADIENT_BRUSH_PROPERTIES, D2D1_RECT_F, D2D1_SIZE_F,
-    D2D1_SIZE_U, D2D1_STROKE_STYLE_PROPERTIES,
+    ID2D1RenderTarget, ID2D1SolidColorBrush, ID2D1StrokeStyle, D2D1_ANTIALIAS_MODE_PER_PRIMITIVE,
+    D2D1_BEZIER_SEGMENT, D2D1_BITMAP_BRUSH_PROPERTIES, D2D1_BITMAP_INTERPOLATION_MODE_LINEAR,
+    D2D1_BRUSH_PROPERTIES, D2D1_COLOR_F, D2D1_COMPATIBLE_RENDER_TARGET_OPTIONS_NONE,
+    D2D1_DEBUG_LEVEL_WARNING, D2D1_DRAW_TEXT_OPTIONS, D2D1_ELLIPSE, D2D1_EXTEND_MODE,
+    D2D1_FACTORY_OPTIONS, D2D1_FACTORY_TYPE_MULTI_THREADED, D2D1_FIGURE_BEGIN_FILLED,
+    D2D1_FIGURE_BEGIN_HOLLOW, D2D1_FIGURE_END_CLOSED, D2D1_FIGURE_END_OPEN,
+    D2D1_FILL_MODE_ALTERNATE, D2D1_FILL_MODE_WINDING, D2D1_GAMMA_2_2, D2D1_GRADIENT_STOP,
+    D2D1_LAYER_OPTIONS_NONE, D2D1_LAYER_PARAMETERS, D2D1_LINEAR_GRADIENT_BRUSH_PROPERTIES,
+    D2D1_MATRIX_3X2_F, D2D1_POINT_2F, D2D1_POINT_2U, D2D1_QUADRATIC_BEZIER_SEGMENT,
+    D2D1_RADIAL_GRADIENT_BRUSH_PROPERTIES, D2D1_RECT_F, D2D1_RECT_U, D2D1_ROUNDED_RECT,
+    D2D1_SIZE_F, D2D1_SIZE_U, D2D1_STROKE_STYLE_PROPERTIES,
 };
 use winapi::um::d2d1_1::{
     ID2D1Bitmap1, ID2D1Device, ID2D1DeviceContext, ID2D1Effect, ID2D1Factory1,
-    D2D1_BITMAP_OPTIONS_NONE, D2D1_BITMAP_OPTIONS_TARGET, D2D1_BITMAP_PROPERTIES1,
-    D2D1_COMPOSITE_MODE, D2D1_DEVICE_CONTEXT_OPTIONS_NONE, D2D1_INTERPOLATION_MODE,
-    D2D1_PROPERTY_TYPE_FLOAT,
+    D2D1_BITMAP_OPTIONS_CANNOT_DRAW, D2D1_BITMAP_OPTIONS_CPU_READ, D2D1_BITMAP_OPTIONS_NONE,
+    D2D1_BITMAP_OPTIONS_TARGET, D2D1_BITMAP_PROPERTIES1, D2D1_COMPOSITE_MODE,
+    D2D1_DEVICE_CONTEXT_OPTIONS_NONE, D2D1_INTERPOLATION_MODE, D2D1_MAPPED_RECT,
+    D2D1_MAP_OPTIONS_READ, D2D1_PROPERTY_TYPE_FLOAT,
 };
 use winapi::um::d2d1effects::{CLSID_D2D1GaussianBlur, D2D1_GAUSSIANBLUR_PROP_STANDARD_DEVIATION};
 use winapi::um::dcommon::{D2D1_ALPHA_MODE, D2D1_ALPHA_MODE_PREMULTIPLIED, D2D1_PIXEL_FORMAT};
 use winapi::Interface;
 
+use piet::util::BrushCache;
+
 use crate::dwrite::TextLayout;
 
 pub enum FillRule {
@@ -49,10 +54,16 @@ pub enum FillRule {
     NonZero,
 }
 
-pub enum Error {
-    WinapiError(HRESULT),
+/// A shape that Direct2D can draw without a path geometry.
+pub enum Primitive {
+    Rect(D2D1_RECT_F),
+    RoundedRect(D2D1_ROUNDED_RECT),
+    Ellipse(D2D1_ELLIPSE),
+    Line(D2D1_POINT_2F, D2D1_POINT_2F),
 }
 
+pub use crate::error::D2DError as Error;
+
 /// A Direct2D factory object.
 ///
 /// This struct is public only to use for system integration in piet_common and druid-shell. It is not intended
@@ -75,8 +86,12 @@ unsafe impl Send for D2DDevice {}
 ///
 /// This struct is public only to use for system integration in piet_common and druid-shell. It is not intended
 /// that end-users directly use this struct.
+///
+/// It also holds the cache of brushes for `make_brush_cached`, since the
+/// brushes belong to the device context; recreating the context after the
+/// device is lost drops them.
 #[derive(Clone)]
-pub struct DeviceContext(ComPtr<ID2D1DeviceContext>);
+pub struct DeviceContext(ComPtr<ID2D1DeviceContext>, Rc<RefCell<BrushCache<Brush>>>);
 
 pub struct PathGeometry(ComPtr<ID2D1PathGeometry>);
 
@@ -109,41 +124,7 @@ pub struct Effect(ComPtr<ID2D1Effect>);
 // piet-common direct2d_back, but the use cases are somewhat different.
 pub struct BitmapRenderTarget(ComPtr<ID2D1BitmapRenderTarget>);
 
-impl From<HRESULT> for Error {
-    fn from(hr: HRESULT) -> Error {
-        Error::WinapiError(hr)
-    }
-}
-
-impl Debug for Error {
-    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        match self {
-            Error::WinapiError(hr) => write!(f, "hresult {:x}", hr),
-        }
-    }
-}
-
-impl Display for Error {
-    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        match self {
-            Error::WinapiError(hr) => write!(f, "hresult {:x}", hr),
-        }
-    }
-}
-
-impl std::error::Error for Error {
-    fn description(&self) -> &str {
-        "winapi error"
-    }
-}
-
-impl From<Error> for piet::Error {
-    fn from(e: Error) -> piet::Error {
-        piet::Error::BackendError(Box::new(e))
-    }
-}
-
-unsafe fn wrap<T, U, F>(hr: HRESULT, ptr: *mut T, f: F) -> Result<U, Error>
+unsafe fn wrap<T, U, F>(hr: HRESULT, ptr: *mut T, f: F, context: &'static str) -> Result<U, Error>
 where
     F: Fn(ComPtr<T>) -> U,
     T: Interface,
@@ -151,15 +132,15 @@ where
     if SUCCEEDED(hr) {
         Ok(f(ComPtr::from_raw(ptr)))
     } else {
-        Err(hr.into())
+        Err(Error::new(hr, context))
     }
 }
 
-pub(crate) fn wrap_unit(hr: HRESULT) -> Result<(), Error> {
+pub(crate) fn wrap_unit(hr: HRESULT, context: &'static str) -> Result<(), Error> {
     if SUCCEEDED(hr) {
         Ok(())
     } else {
-        Err(hr.into())
+        Err(Error::new(hr, context))
     }
 }
 
@@ -183,7 +164,7 @@ impl D2DFactory {
                 },
                 &mut ptr as *mut _ as *mut _,
             );
-            wrap(hr, ptr, D2DFactory)
+            wrap(hr, ptr, D2DFactory, "D2D1CreateFactory")
         }
     }
 
@@ -194,7 +175,7 @@ impl D2DFactory {
     pub unsafe fn create_device(&self, dxgi_device: *mut IDXGIDevice) -> Result<D2DDevice, Error> {
         let mut ptr = null_mut();
         let hr = self.0.CreateDevice(dxgi_device, &mut ptr);
-        wrap(hr, ptr, D2DDevice)
+        wrap(hr, ptr, D2DDevice, "CreateDevice")
     }
 
     /// Get the raw pointer
@@ -206,7 +187,7 @@ impl D2DFactory {
         unsafe {
             let mut ptr = null_mut();
             let hr = self.0.deref().deref().CreatePathGeometry(&mut ptr);
-            wrap(hr, ptr, PathGeometry)
+            wrap(hr, ptr, PathGeometry, "CreatePathGeometry")
         }
     }
 
@@ -225,7 +206,7 @@ impl D2DFactory {
                 dashes_len as u32,
                 &mut ptr,
             );
-            wrap(hr, ptr, StrokeStyle)
+            wrap(hr, ptr, StrokeStyle, "CreateStrokeStyle")
         }
     }
 }
@@ -240,7 +221,12 @@ impl D2DDevice {
             let mut ptr = null_mut();
             let options = D2D1_DEVICE_CONTEXT_OPTIONS_NONE;
             let hr = self.0.CreateDeviceContext(options, &mut ptr);
-            wrap(hr, ptr, DeviceContext)
+            wrap(
+                hr,
+                ptr,
+                |p| DeviceContext(p, Default::default()),
+                "CreateDeviceContext",
+            )
         }
     }
 }
@@ -263,7 +249,12 @@ impl DeviceContext {
     /// # Safety
     /// TODO
     pub unsafe fn new(ptr: ComPtr<ID2D1DeviceContext>) -> DeviceContext {
-        DeviceContext(ptr)
+        DeviceContext(ptr, Default::default())
+    }
+
+    /// The brushes made by `make_brush_cached` on this context.
+    pub(crate) fn brush_cache(&self) -> &RefCell<BrushCache<Brush>> {
+        &self.1
     }
 
     /// Get the raw pointer
@@ -308,7 +299,7 @@ impl DeviceContext {
         let hr = self
             .0
             .CreateBitmapFromDxgiSurface(dxgi.as_raw(), &props, &mut ptr);
-        wrap(hr, ptr, Bitmap)
+        wrap(hr, ptr, Bitmap, "CreateBitmapFromDxgiSurface")
     }
 
     /// Set the target for the device context.
@@ -345,7 +336,7 @@ impl DeviceContext {
             let mut tag1 = 0;
             let mut tag2 = 0;
             let hr = self.0.EndDraw(&mut tag1, &mut tag2);
-            wrap_unit(hr)
+            wrap_unit(hr, "EndDraw")
         }
     }
 
@@ -355,6 +346,24 @@ impl DeviceContext {
         }
     }
 
+    /// The size of the render target, in DIPs.
+    pub(crate) fn get_size(&self) -> D2D1_SIZE_F {
+        unsafe { self.0.GetSize() }
+    }
+
+    /// The size of the render target, in device pixels.
+    pub(crate) fn get_pixel_size(&self) -> D2D1_SIZE_U {
+        unsafe { self.0.GetPixelSize() }
+    }
+
+    /// The number of device pixels per DIP.
+    pub(crate) fn get_dpi_scale(&self) -> f32 {
+        let mut dpi_x = 0.0;
+        let mut dpi_y = 0.0;
+        unsafe { self.0.GetDpi(&mut dpi_x, &mut dpi_y) };
+        dpi_x / 96.0
+    }
+
     pub(crate) fn set_transform(&mut self, transform: &D2D1_MATRIX_3X2_F) {
         unsafe {
             self.0.SetTransform(transform);
@@ -393,16 +402,57 @@ impl DeviceContext {
         }
     }
 
+    /// Fill a primitive; lines have no area, so filling one draws nothing.
+    pub(crate) fn fill_primitive(&mut self, primitive: &Primitive, brush: &Brush) {
+        unsafe {
+            match primitive {
+                Primitive::Rect(rect) => self.0.FillRectangle(rect, brush.as_raw()),
+                Primitive::RoundedRect(rect) => self.0.FillRoundedRectangle(rect, brush.as_raw()),
+                Primitive::Ellipse(ellipse) => self.0.FillEllipse(ellipse, brush.as_raw()),
+                Primitive::Line(..) => (),
+            }
+        }
+    }
+
+    pub(crate) fn draw_primitive(
+        &mut self,
+        primitive: &Primitive,
+        brush: &Brush,
+        width: f32,
+        style: Option<&StrokeStyle>,
+    ) {
+        let style = style.map(|ss| ss.0.as_raw()).unwrap_or(null_mut());
+        unsafe {
+            match primitive {
+                Primitive::Rect(rect) => self.0.DrawRectangle(rect, brush.as_raw(), width, style),
+                Primitive::RoundedRect(rect) => {
+                    self.0
+                        .DrawRoundedRectangle(rect, brush.as_raw(), width, style)
+                }
+                Primitive::Ellipse(ellipse) => {
+                    self.0.DrawEllipse(ellipse, brush.as_raw(), width, style)
+                }
+                Primitive::Line(p0, p1) => self.0.DrawLine(*p0, *p1, brush.as_raw(), width, style),
+            }
+        }
+    }
+
     pub(crate) fn create_layer(&mut self, size: Option<D2D1_SIZE_F>) -> Result<Layer, Error> {
         unsafe {
             let mut ptr = null_mut();
             let hr = self.0.CreateLayer(optional(&size), &mut ptr);
-            wrap(hr, ptr, Layer)
+            wrap(hr, ptr, Layer, "CreateLayer")
         }
     }
 
     // Should be &mut layer?
-    pub(crate) fn push_layer_mask(&mut self, mask: &PathGeometry, layer: &Layer) {
+    pub(crate) fn push_layer(
+        &mut self,
+        layer: &Layer,
+        mask: Option<&PathGeometry>,
+        opacity: f32,
+        opacity_brush: Option<&Brush>,
+    ) {
         unsafe {
             let params = D2D1_LAYER_PARAMETERS {
                 contentBounds: D2D1_RECT_F {
@@ -411,11 +461,11 @@ impl DeviceContext {
                     right: std::f32::INFINITY,
                     bottom: std::f32::INFINITY,
                 },
-                geometricMask: mask.0.as_raw() as *mut ID2D1Geometry,
+                geometricMask: mask.map_or(null_mut(), |m| m.0.as_raw() as *mut ID2D1Geometry),
                 maskAntialiasMode: D2D1_ANTIALIAS_MODE_PER_PRIMITIVE,
                 maskTransform: IDENTITY_MATRIX_3X2_F,
-                opacity: 1.0,
-                opacityBrush: null_mut(),
+                opacity,
+                opacityBrush: opacity_brush.map_or(null_mut(), |b| b.as_raw()),
                 layerOptions: D2D1_LAYER_OPTIONS_NONE,
             };
             self.0.deref().deref().PushLayer(&params, layer.0.as_raw());
@@ -434,13 +484,14 @@ impl DeviceContext {
             let hr = self
                 .0
                 .CreateSolidColorBrush(&color, &DEFAULT_BRUSH_PROPERTIES, &mut ptr);
-            wrap(hr, ptr, |p| Brush(p.up()))
+            wrap(hr, ptr, |p| Brush(p.up()), "CreateSolidColorBrush")
         }
     }
 
     pub(crate) fn create_gradient_stops(
         &mut self,
         stops: &[D2D1_GRADIENT_STOP],
+        extend: D2D1_EXTEND_MODE,
     ) -> Result<GradientStopCollection, Error> {
         unsafe {
             // Should this assert or should we return an overflow error? Super
@@ -453,10 +504,15 @@ impl DeviceContext {
                 stops.as_ptr(),
                 stops.len() as u32,
                 D2D1_GAMMA_2_2,
-                D2D1_EXTEND_MODE_CLAMP,
+                extend,
                 &mut ptr,
             );
-            wrap(hr, ptr, GradientStopCollection)
+            wrap(
+                hr,
+                ptr,
+                GradientStopCollection,
+                "CreateGradientStopCollection",
+            )
         }
     }
 
@@ -474,7 +530,7 @@ impl DeviceContext {
                 stops.0.as_raw(),
                 &mut ptr,
             );
-            wrap(hr, ptr, |p| Brush(p.up()))
+            wrap(hr, ptr, |p| Brush(p.up()), "CreateLinearGradientBrush")
         }
     }
 
@@ -491,16 +547,47 @@ impl DeviceContext {
                 stops.0.as_raw(),
                 &mut ptr,
             );
-            wrap(hr, ptr, |p| Brush(p.up()))
+            wrap(hr, ptr, |p| Brush(p.up()), "CreateRadialGradientBrush")
+        }
+    }
+
+    pub(crate) fn create_bitmap_brush(
+        &mut self,
+        bitmap: &Bitmap,
+        extend: D2D1_EXTEND_MODE,
+        transform: D2D1_MATRIX_3X2_F,
+    ) -> Result<Brush, Error> {
+        let bitmap_props = D2D1_BITMAP_BRUSH_PROPERTIES {
+            extendModeX: extend,
+            extendModeY: extend,
+            interpolationMode: D2D1_BITMAP_INTERPOLATION_MODE_LINEAR,
+        };
+        let brush_props = D2D1_BRUSH_PROPERTIES {
+            opacity: 1.0,
+            transform,
+        };
+        unsafe {
+            let mut ptr = null_mut();
+            // As in `draw_bitmap`, we want the RenderTarget method.
+            let hr = self.0.deref().deref().CreateBitmapBrush(
+                bitmap.0.as_raw() as *mut ID2D1Bitmap,
+                &bitmap_props,
+                &brush_props,
+                &mut ptr,
+            );
+            wrap(hr, ptr, |p| Brush(p.up()), "CreateBitmapBrush")
         }
     }
 
-    // Buf is always interpreted as RGBA32 premultiplied.
+    // Buf is interpreted as 32 bits per pixel, in the given format, with rows
+    // `pitch` bytes apart.
     pub(crate) fn create_bitmap(
         &mut self,
         width: usize,
         height: usize,
         buf: &[u8],
+        pitch: usize,
+        pixel_format: DXGI_FORMAT,
         alpha_mode: D2D1_ALPHA_MODE,
     ) -> Result<Bitmap, Error> {
         // Maybe using TryInto would be more Rust-like.
@@ -512,7 +599,7 @@ impl DeviceContext {
             height: height as u32,
         };
         let format = D2D1_PIXEL_FORMAT {
-            format: DXGI_FORMAT_R8G8B8A8_UNORM,
+            format: pixel_format,
             alphaMode: alpha_mode,
         };
         let props = D2D1_BITMAP_PROPERTIES1 {
@@ -522,7 +609,9 @@ impl DeviceContext {
             bitmapOptions: D2D1_BITMAP_OPTIONS_NONE,
             colorContext: null_mut(),
         };
-        let pitch = (width * 4) as u32;
+        assert!(pitch >= width * 4 && pitch <= 0xffff_ffff);
+        assert!(height == 0 || buf.len() >= pitch * (height - 1) + width * 4);
+        let pitch = pitch as u32;
         unsafe {
             let mut ptr = null_mut();
             let hr = self.0.deref().CreateBitmap(
@@ -532,7 +621,79 @@ impl DeviceContext {
                 &props,
                 &mut ptr,
             );
-            wrap(hr, ptr, Bitmap)
+            wrap(hr, ptr, Bitmap, "CreateBitmap")
+        }
+    }
+
+    /// Copy the pixels of a bitmap into CPU memory.
+    ///
+    /// Returns the pixels, tightly packed with 4 bytes per pixel, along with
+    /// the format of the bitmap.
+    pub(crate) fn read_bitmap(
+        &mut self,
+        bitmap: &Bitmap,
+    ) -> Result<(Vec<u8>, D2D1_PIXEL_FORMAT), Error> {
+        unsafe {
+            let size = bitmap.get_pixel_size();
+            let format = bitmap.0.GetPixelFormat();
+            // Bitmaps that can be drawn can't be mapped, so we copy to a
+            // staging bitmap first.
+            let props = D2D1_BITMAP_PROPERTIES1 {
+                pixelFormat: format,
+                dpiX: 96.0,
+                dpiY: 96.0,
+                bitmapOptions: D2D1_BITMAP_OPTIONS_CPU_READ | D2D1_BITMAP_OPTIONS_CANNOT_DRAW,
+                colorContext: null_mut(),
+            };
+            let mut ptr = null_mut();
+            let hr = self.0.CreateBitmap(size, null(), 0, &props, &mut ptr);
+            let staging = wrap(hr, ptr, Bitmap, "CreateBitmap")?;
+            let hr =
+                staging
+                    .0
+                    .CopyFromBitmap(null(), bitmap.0.as_raw() as *mut ID2D1Bitmap, null());
+            wrap_unit(hr, "CopyFromBitmap")?;
+            let mut mapped = D2D1_MAPPED_RECT {
+                pitch: 0,
+                bits: null(),
+            };
+            wrap_unit(staging.0.Map(D2D1_MAP_OPTIONS_READ, &mut mapped), "Map")?;
+            let row_len = size.width as usize * 4;
+            let mut buf = Vec::with_capacity(row_len * size.height as usize);
+            for y in 0..size.height as usize {
+                let row = mapped.bits.add(y * mapped.pitch as usize);
+                buf.extend_from_slice(std::slice::from_raw_parts(row, row_len));
+            }
+            wrap_unit(staging.0.Unmap(), "Unmap")?;
+            Ok((buf, format))
+        }
+    }
+
+    /// Copy an area of the render target, in device pixels, to a new bitmap.
+    pub(crate) fn copy_from_target(&mut self, rect: D2D1_RECT_U) -> Result<Bitmap, Error> {
+        let size = D2D1_SIZE_U {
+            width: rect.right - rect.left,
+            height: rect.bottom - rect.top,
+        };
+        unsafe {
+            let props = D2D1_BITMAP_PROPERTIES1 {
+                pixelFormat: self.0.GetPixelFormat(),
+                dpiX: 96.0,
+                dpiY: 96.0,
+                bitmapOptions: D2D1_BITMAP_OPTIONS_NONE,
+                colorContext: null_mut(),
+            };
+            let mut ptr = null_mut();
+            let hr = self.0.CreateBitmap(size, null(), 0, &props, &mut ptr);
+            let bitmap = wrap(hr, ptr, Bitmap, "CreateBitmap")?;
+            let origin = D2D1_POINT_2U { x: 0, y: 0 };
+            let hr = bitmap.0.CopyFromRenderTarget(
+                &origin,
+                self.0.as_raw() as *mut ID2D1RenderTarget,
+                &rect,
+            );
+            wrap_unit(hr, "CopyFromRenderTarget")?;
+            Ok(bitmap)
         }
     }
 
@@ -555,18 +716,19 @@ impl DeviceContext {
         bitmap: &Bitmap,
         dst_rect: &D2D1_RECT_F,
         opacity: f32,
-        interp_mode: D2D1_BITMAP_INTERPOLATION_MODE,
+        interp_mode: D2D1_INTERPOLATION_MODE,
         src_rect: Option<&D2D1_RECT_F>,
     ) {
         unsafe {
-            // derefs are so we get RenderTarget method rather than DeviceContext method.
-            // pointer casts are partly to undo that :)
-            self.0.deref().deref().DrawBitmap(
+            // This is the DeviceContext method, which supports more
+            // interpolation modes than the RenderTarget one.
+            self.0.DrawBitmap(
                 bitmap.0.as_raw() as *mut ID2D1Bitmap,
                 dst_rect,
                 opacity,
                 interp_mode,
                 src_rect.map(|r| r as *const _).unwrap_or(null()),
+                null(),
             );
         }
     }
@@ -579,7 +741,7 @@ impl DeviceContext {
                 .0
                 .deref()
                 .CreateEffect(&CLSID_D2D1GaussianBlur, &mut ptr);
-            let effect = wrap(hr, ptr, Effect)?;
+            let effect = wrap(hr, ptr, Effect, "CreateEffect")?;
             let val = radius as f32;
             let hr = effect.0.SetValue(
                 D2D1_GAUSSIANBLUR_PROP_STANDARD_DEVIATION,
@@ -587,7 +749,7 @@ impl DeviceContext {
                 &val as *const _ as *const _,
                 std::mem::size_of_val(&val) as u32,
             );
-            wrap_unit(hr)?;
+            wrap_unit(hr, "SetValue")?;
             Ok(effect)
         }
     }
@@ -640,7 +802,7 @@ impl DeviceContext {
             let hr =
                 self.0
                     .CreateCompatibleRenderTarget(&size_f, null(), &format, options, &mut ptr);
-            wrap(hr, ptr, BitmapRenderTarget)
+            wrap(hr, ptr, BitmapRenderTarget, "CreateCompatibleRenderTarget")
         }
     }
 }
@@ -650,10 +812,11 @@ impl PathGeometry {
         unsafe {
             let mut ptr = null_mut();
             let hr = (self.0).Open(&mut ptr);
-            wrap(hr, ptr, |ptr| GeometrySink {
+            let sink = |ptr| GeometrySink {
                 ptr,
                 marker: Default::default(),
-            })
+            };
+            wrap(hr, ptr, sink, "Open")
         }
     }
 }
@@ -724,7 +887,7 @@ impl<'a> GeometrySink<'a> {
 
     // A case can be made for doing this in the drop instead.
     pub fn close(self) -> Result<(), Error> {
-        unsafe { wrap_unit(self.ptr.Close()) }
+        unsafe { wrap_unit(self.ptr.Close(), "Close") }
     }
 }
 
@@ -733,6 +896,10 @@ impl Bitmap {
     pub fn get_size(&self) -> D2D1_SIZE_F {
         unsafe { self.0.GetSize() }
     }
+
+    pub fn get_pixel_size(&self) -> D2D1_SIZE_U {
+        unsafe { self.0.GetPixelSize() }
+    }
 }
 
 impl Effect {
@@ -756,7 +923,18 @@ impl BitmapRenderTarget {
         unsafe {
             let mut ptr = null_mut();
             let hr = self.0.GetBitmap(&mut ptr);
-            wrap(hr, ptr, |com_ptr| com_ptr)
+            wrap(hr, ptr, |com_ptr| com_ptr, "GetBitmap")
+        }
+    }
+
+    /// Fill a geometry; this must be called between `BeginDraw` and `EndDraw`.
+    pub(crate) fn fill_geometry(&self, geom: &PathGeometry, brush: &Brush) {
+        unsafe {
+            self.0.FillGeometry(
+                geom.0.as_raw() as *mut ID2D1Geometry,
+                brush.as_raw(),
+                null_mut(),
+            );
         }
     }
 }
@@ -778,6 +956,12 @@ impl Brush {
     pub(crate) fn as_raw(&self) -> *mut ID2D1Brush {
         self.0.as_raw()
     }
+
+    /// Set the opacity the brush is drawn with; a brush is created with an
+    /// opacity of 1.
+    pub(crate) fn set_opacity(&self, opacity: f32) {
+        unsafe { self.0.SetOpacity(opacity) }
+    }
 }
 
 mod tests {
@@ -18,8 +18,7 @@ use winapi::Interface;
 
 use wio::com::ComPtr;
 
-#[derive(Debug)]
-pub struct Error(HRESULT);
+pub use crate::error::D2DError as Error;
 
 pub struct D3D11Device(ComPtr<ID3D11Device>);
 pub struct D3D11DeviceContext(ComPtr<ID3D11DeviceContext>);
@@ -63,7 +62,7 @@ impl TextureMode {
     }
 }
 
-unsafe fn wrap<T, U, F>(hr: HRESULT, ptr: *mut T, f: F) -> Result<U, Error>
+unsafe fn wrap<T, U, F>(hr: HRESULT, ptr: *mut T, f: F, context: &'static str) -> Result<U, Error>
 where
     F: Fn(ComPtr<T>) -> U,
     T: Interface,
@@ -71,7 +70,7 @@ where
     if SUCCEEDED(hr) {
         Ok(f(ComPtr::from_raw(ptr)))
     } else {
-        Err(Error(hr))
+        Err(Error::new(hr, context))
     }
 }
 
@@ -97,8 +96,8 @@ impl D3D11Device {
                 null_mut(), /* feature level */
                 &mut ctx_ptr,
             );
-            let device = wrap(hr, ptr, D3D11Device)?;
-            let device_ctx = wrap(hr, ctx_ptr, D3D11DeviceContext)?;
+            let device = wrap(hr, ptr, D3D11Device, "D3D11CreateDevice")?;
+            let device_ctx = wrap(hr, ctx_ptr, D3D11DeviceContext, "D3D11CreateDevice")?;
             Ok((device, device_ctx))
         }
     }
@@ -131,7 +130,7 @@ impl D3D11Device {
                 MiscFlags: 0,
             };
             let hr = self.0.CreateTexture2D(&desc, null_mut(), &mut ptr);
-            wrap(hr, ptr, D3D11Texture2D)
+            wrap(hr, ptr, D3D11Texture2D, "CreateTexture2D")
         }
     }
 }
@@ -157,11 +156,3 @@ impl DxgiDevice {
         self.0.as_raw()
     }
 }
-
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "HRESULT error {}", self.0)
-    }
-}
-
-impl std::error::Error for Error {}
@@ -5,32 +5,53 @@
 
 use std::convert::TryInto;
 use std::ffi::OsString;
-use std::fmt::{Debug, Display, Formatter};
 use std::mem::MaybeUninit;
-use std::ptr::null_mut;
+use std::ptr::{null, null_mut};
 use std::sync::Arc;
 
-use winapi::shared::minwindef::{FALSE, TRUE};
+use winapi::ctypes::c_void;
+use winapi::shared::guiddef::{IsEqualIID, REFIID};
+use winapi::shared::minwindef::{BOOL, FALSE, FLOAT, TRUE, ULONG};
 use winapi::shared::ntdef::LOCALE_NAME_MAX_LENGTH;
-use winapi::shared::winerror::{HRESULT, SUCCEEDED, S_OK};
+use winapi::shared::winerror::{E_NOINTERFACE, HRESULT, SUCCEEDED, S_OK};
+use winapi::um::d2d1::{
+    ID2D1SimplifiedGeometrySink, ID2D1SimplifiedGeometrySinkVtbl, D2D1_BEZIER_SEGMENT,
+    D2D1_FIGURE_BEGIN, D2D1_FIGURE_END, D2D1_FIGURE_END_CLOSED, D2D1_FILL_MODE, D2D1_PATH_SEGMENT,
+    D2D1_POINT_2F,
+};
 use winapi::um::dwrite::{
-    DWriteCreateFactory, IDWriteFactory, IDWriteFontCollection, IDWriteFontFamily,
-    IDWriteLocalizedStrings, IDWriteTextFormat, IDWriteTextLayout, DWRITE_FACTORY_TYPE_SHARED,
+    DWriteCreateFactory, IDWriteFactory, IDWriteFont, IDWriteFontCollection, IDWriteFontFace,
+    IDWriteFontFamily, IDWriteInlineObject, IDWriteLocalizedStrings, IDWritePixelSnapping,
+    IDWritePixelSnappingVtbl, IDWriteTextFormat, IDWriteTextLayout, IDWriteTextRenderer,
+    IDWriteTextRendererVtbl, IDWriteTypography, DWRITE_FACTORY_TYPE_SHARED,
+    DWRITE_FLOW_DIRECTION_RIGHT_TO_LEFT, DWRITE_FONT_FEATURE, DWRITE_FONT_METRICS,
     DWRITE_FONT_STRETCH_NORMAL, DWRITE_FONT_STYLE, DWRITE_FONT_STYLE_ITALIC,
-    DWRITE_FONT_STYLE_NORMAL, DWRITE_FONT_WEIGHT, DWRITE_FONT_WEIGHT_NORMAL,
-    DWRITE_HIT_TEST_METRICS, DWRITE_LINE_METRICS, DWRITE_OVERHANG_METRICS,
-    DWRITE_TEXT_ALIGNMENT_CENTER, DWRITE_TEXT_ALIGNMENT_JUSTIFIED, DWRITE_TEXT_ALIGNMENT_LEADING,
-    DWRITE_TEXT_ALIGNMENT_TRAILING, DWRITE_TEXT_METRICS, DWRITE_TEXT_RANGE,
+    DWRITE_FONT_STYLE_NORMAL, DWRITE_FONT_WEIGHT, DWRITE_FONT_WEIGHT_NORMAL, DWRITE_GLYPH_RUN,
+    DWRITE_GLYPH_RUN_DESCRIPTION, DWRITE_HIT_TEST_METRICS, DWRITE_LINE_METRICS,
+    DWRITE_LINE_SPACING_METHOD_UNIFORM, DWRITE_MATRIX, DWRITE_MEASURING_MODE,
+    DWRITE_OVERHANG_METRICS, DWRITE_READING_DIRECTION_LEFT_TO_RIGHT,
+    DWRITE_READING_DIRECTION_RIGHT_TO_LEFT, DWRITE_READING_DIRECTION_TOP_TO_BOTTOM,
+    DWRITE_STRIKETHROUGH, DWRITE_TEXT_ALIGNMENT_CENTER, DWRITE_TEXT_ALIGNMENT_JUSTIFIED,
+    DWRITE_TEXT_ALIGNMENT_LEADING, DWRITE_TEXT_ALIGNMENT_TRAILING, DWRITE_TEXT_METRICS,
+    DWRITE_TEXT_RANGE, DWRITE_TRIMMING, DWRITE_TRIMMING_GRANULARITY_CHARACTER, DWRITE_UNDERLINE,
+    DWRITE_WORD_WRAPPING_WRAP,
+};
+use winapi::um::dwrite_1::{IDWriteTextLayout1, DWRITE_UNICODE_RANGE};
+use winapi::um::dwrite_2::{
+    IDWriteFactory2, IDWriteFontFallback, IDWriteFontFallbackBuilder, IDWriteTextLayout2,
 };
-use winapi::um::unknwnbase::IUnknown;
+use winapi::um::unknwnbase::{IUnknown, IUnknownVtbl};
 use winapi::um::winnls::GetUserDefaultLocaleName;
 use winapi::Interface;
 
 use wio::com::ComPtr;
 use wio::wide::{FromWide, ToWide};
 
-use piet::kurbo::Insets;
-use piet::{FontFamily as PietFontFamily, FontWeight, TextAlignment};
+use piet::kurbo::{BezPath, Insets, Point, Vec2};
+use piet::{
+    FontFamily as PietFontFamily, FontFeatures, FontMetrics, FontWeight, Glyph, GlyphRun,
+    TextAlignment,
+};
 
 use crate::Brush;
 
@@ -44,11 +65,7 @@ const DEFAULT_LOCALE: &[u16] = utf16_lit::utf16_null!("en-US");
 // approximately the largest f32 without integer error
 const MAX_LAYOUT_CONSTRAINT: f32 = 1.6e7;
 
-// TODO: minimize cut'n'paste; probably the best way to do this is
-// unify with the crate error type
-pub enum Error {
-    WinapiError(HRESULT),
-}
+pub use crate::error::D2DError as Error;
 
 /// This struct is public only to use for system integration in piet_common and druid-shell. It is not intended
 /// that end-users directly use this struct.
@@ -70,41 +87,12 @@ pub struct FontCollection(ComPtr<IDWriteFontCollection>);
 #[derive(Clone)]
 pub struct TextLayout(ComPtr<IDWriteTextLayout>);
 
-impl From<HRESULT> for Error {
-    fn from(hr: HRESULT) -> Error {
-        Error::WinapiError(hr)
-    }
-}
+// DirectWrite objects can be used from any thread, and our D2D factory is
+// created multi-threaded, so a layout can be built on one thread and drawn
+// on another.
+unsafe impl Send for TextLayout {}
 
-impl Debug for Error {
-    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        match self {
-            Error::WinapiError(hr) => write!(f, "hresult {:x}", hr),
-        }
-    }
-}
-
-impl Display for Error {
-    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        match self {
-            Error::WinapiError(hr) => write!(f, "hresult {:x}", hr),
-        }
-    }
-}
-
-impl std::error::Error for Error {
-    fn description(&self) -> &str {
-        "winapi error"
-    }
-}
-
-impl From<Error> for piet::Error {
-    fn from(e: Error) -> piet::Error {
-        piet::Error::BackendError(Box::new(e))
-    }
-}
-
-unsafe fn wrap<T, U, F>(hr: HRESULT, ptr: *mut T, f: F) -> Result<U, Error>
+unsafe fn wrap<T, U, F>(hr: HRESULT, ptr: *mut T, f: F, context: &'static str) -> Result<U, Error>
 where
     F: Fn(ComPtr<T>) -> U,
     T: Interface,
@@ -112,7 +100,7 @@ where
     if SUCCEEDED(hr) {
         Ok(f(ComPtr::from_raw(ptr)))
     } else {
-        Err(hr.into())
+        Err(Error::new(hr, context))
     }
 }
 
@@ -125,7 +113,7 @@ impl DwriteFactory {
                 &IDWriteFactory::uuidof(),
                 &mut ptr as *mut _ as *mut _,
             );
-            wrap(hr, ptr, DwriteFactory)
+            wrap(hr, ptr, DwriteFactory, "DWriteCreateFactory")
         }
     }
 
@@ -137,7 +125,7 @@ impl DwriteFactory {
         unsafe {
             let mut ptr = null_mut();
             let hr = self.0.GetSystemFontCollection(&mut ptr, 0);
-            wrap(hr, ptr, FontCollection)
+            wrap(hr, ptr, FontCollection, "GetSystemFontCollection")
         }
     }
 
@@ -152,16 +140,74 @@ impl DwriteFactory {
 
 impl FontCollection {
     pub(crate) fn font_family(&self, name: &str) -> Option<PietFontFamily> {
+        self.find_family(name)?.family_name().ok()
+    }
+
+    /// The families in this collection, sorted by name.
+    pub(crate) fn families(&self) -> Vec<PietFontFamily> {
+        let mut families: Vec<PietFontFamily> = unsafe {
+            (0..self.0.GetFontFamilyCount())
+                .filter_map(|idx| {
+                    let mut family = null_mut();
+                    let hr = self.0.GetFontFamily(idx, &mut family);
+                    wrap(hr, family, FontFamily, "GetFontFamily")
+                        .ok()?
+                        .family_name()
+                        .ok()
+                })
+                .collect()
+        };
+        families.sort_by(|a, b| a.name().cmp(b.name()));
+        families
+    }
+
+    /// Returns the metrics of the regular style of the family `name`, at `size`.
+    pub(crate) fn font_metrics(&self, name: &str, size: f64) -> Option<FontMetrics> {
+        let family = self.find_family(name)?;
+        let metrics = unsafe {
+            let mut font = null_mut();
+            let hr = family.0.GetFirstMatchingFont(
+                DWRITE_FONT_WEIGHT_NORMAL,
+                DWRITE_FONT_STRETCH_NORMAL,
+                DWRITE_FONT_STYLE_NORMAL,
+                &mut font,
+            );
+            let font: ComPtr<IDWriteFont> =
+                wrap(hr, font, |ptr| ptr, "GetFirstMatchingFont").ok()?;
+            let mut metrics = MaybeUninit::<DWRITE_FONT_METRICS>::uninit();
+            font.GetMetrics(metrics.as_mut_ptr());
+            metrics.assume_init()
+        };
+
+        let scale = size / metrics.designUnitsPerEm as f64;
+        // dwrite's positions are of the tops of the lines, above the baseline
+        let underline_thickness = metrics.underlineThickness as f64 * scale;
+        let strikeout_thickness = metrics.strikethroughThickness as f64 * scale;
+        Some(FontMetrics {
+            ascent: metrics.ascent as f64 * scale,
+            descent: metrics.descent as f64 * scale,
+            cap_height: metrics.capHeight as f64 * scale,
+            x_height: metrics.xHeight as f64 * scale,
+            underline_position: underline_thickness / 2.0
+                - metrics.underlinePosition as f64 * scale,
+            underline_thickness,
+            strikeout_position: strikeout_thickness / 2.0
+                - metrics.strikethroughPosition as f64 * scale,
+            strikeout_thickness,
+        })
+    }
+
+    fn find_family(&self, name: &str) -> Option<FontFamily> {
         let wname = name.to_wide_null();
         let mut idx = u32::max_value();
         let mut exists = 0_i32;
 
-        let family = unsafe {
+        unsafe {
             let hr = self.0.FindFamilyName(wname.as_ptr(), &mut idx, &mut exists);
             if SUCCEEDED(hr) && exists != 0 {
                 let mut family = null_mut();
                 let hr = self.0.GetFontFamily(idx, &mut family);
-                wrap(hr, family, FontFamily).ok()
+                wrap(hr, family, FontFamily, "GetFontFamily").ok()
             } else {
                 eprintln!(
                     "failed to find family name {}: err {} not_found: {}",
@@ -169,9 +215,23 @@ impl FontCollection {
                 );
                 None
             }
-        }?;
+        }
+    }
 
-        family.family_name().ok()
+    /// Returns the family of a font face used in this collection.
+    fn family_for_font_face(&self, face: *mut IDWriteFontFace) -> Option<PietFontFamily> {
+        unsafe {
+            let mut font = null_mut();
+            let hr = self.0.GetFontFromFontFace(face, &mut font);
+            let font: ComPtr<IDWriteFont> =
+                wrap(hr, font, |ptr| ptr, "GetFontFromFontFace").ok()?;
+            let mut family = null_mut();
+            let hr = font.GetFontFamily(&mut family);
+            wrap(hr, family, FontFamily, "GetFontFamily")
+                .ok()?
+                .family_name()
+                .ok()
+        }
     }
 }
 
@@ -183,7 +243,7 @@ impl FontFamily {
             let mut names = null_mut();
             let hr = self.0.GetFamilyNames(&mut names);
             if !SUCCEEDED(hr) {
-                return Err(hr.into());
+                return Err(Error::new(hr, "GetFamilyNames"));
             }
 
             let names: ComPtr<IDWriteLocalizedStrings> = ComPtr::from_raw(names);
@@ -206,7 +266,7 @@ impl FontFamily {
             }
 
             if !SUCCEEDED(hr) {
-                return Err(hr.into());
+                return Err(Error::new(hr, "FindLocaleName"));
             }
 
             // if locale doesn't exist, just choose the first
@@ -218,7 +278,7 @@ impl FontFamily {
             let hr = names.GetStringLength(index, &mut length);
 
             if !SUCCEEDED(hr) {
-                return Err(hr.into());
+                return Err(Error::new(hr, "GetStringLength"));
             }
 
             let mut wide_name: Vec<u16> = Vec::with_capacity(length as usize + 1);
@@ -231,7 +291,7 @@ impl FontFamily {
 
                 Ok(PietFontFamily::new_unchecked(name))
             } else {
-                Err(hr.into())
+                Err(Error::new(hr, "GetString"))
             }
         }
     }
@@ -259,7 +319,7 @@ impl TextFormat {
                 &mut ptr,
             );
 
-            let r = wrap(hr, ptr, TextFormat)?;
+            let r = wrap(hr, ptr, TextFormat, "CreateTextFormat")?;
             Ok(r)
         }
     }
@@ -301,7 +361,7 @@ impl TextLayout {
                 MAX_LAYOUT_CONSTRAINT,
                 &mut ptr,
             );
-            wrap(hr, ptr, TextLayout)
+            wrap(hr, ptr, TextLayout, "CreateTextLayout")
         }
     }
 
@@ -316,6 +376,52 @@ impl TextLayout {
 
         unsafe {
             self.0.SetTextAlignment(alignment);
+            // only lines that are wrapped at the max width are justified
+            if alignment == DWRITE_TEXT_ALIGNMENT_JUSTIFIED {
+                self.0.SetWordWrapping(DWRITE_WORD_WRAPPING_WRAP);
+            }
+        }
+    }
+
+    /// Set the base reading direction for this entire layout.
+    ///
+    /// DirectWrite's leading and trailing alignments are relative to this.
+    pub(crate) fn set_reading_direction(&mut self, rtl: bool) {
+        let direction = if rtl {
+            DWRITE_READING_DIRECTION_RIGHT_TO_LEFT
+        } else {
+            DWRITE_READING_DIRECTION_LEFT_TO_RIGHT
+        };
+        unsafe {
+            self.0.SetReadingDirection(direction);
+        }
+    }
+
+    /// Lay the text out in vertical lines, from top to bottom, which are
+    /// placed from right to left.
+    ///
+    /// This requires Windows 8.1 or later.
+    pub(crate) fn set_vertical(&mut self) -> Result<(), Error> {
+        unsafe {
+            let hr = self
+                .0
+                .SetReadingDirection(DWRITE_READING_DIRECTION_TOP_TO_BOTTOM);
+            if !SUCCEEDED(hr) {
+                return Err(Error::new(hr, "SetReadingDirection"));
+            }
+            let hr = self.0.SetFlowDirection(DWRITE_FLOW_DIRECTION_RIGHT_TO_LEFT);
+            if SUCCEEDED(hr) {
+                Ok(())
+            } else {
+                Err(Error::new(hr, "SetFlowDirection"))
+            }
+        }
+    }
+
+    /// Set the uniform distance between tab stops for this entire layout.
+    pub(crate) fn set_incremental_tab_stop(&mut self, interval: f64) {
+        unsafe {
+            self.0.SetIncrementalTabStop(interval as f32);
         }
     }
 
@@ -336,6 +442,15 @@ impl TextLayout {
         }
     }
 
+    /// Set the locale for a range of this layout, as a BCP 47 tag.
+    pub(crate) fn set_locale(&mut self, start: usize, len: usize, locale: &str) {
+        let range = make_text_range(start, len);
+        let wide_locale = locale.to_wide_null();
+        unsafe {
+            self.0.SetLocaleName(wide_locale.as_ptr(), range);
+        }
+    }
+
     pub(crate) fn set_italic(&mut self, start: usize, len: usize, ital: bool) {
         let range = make_text_range(start, len);
         let val = if ital {
@@ -356,6 +471,122 @@ impl TextLayout {
         }
     }
 
+    pub(crate) fn set_strikethrough(&mut self, start: usize, len: usize, flag: bool) {
+        let range = make_text_range(start, len);
+        let flag = if flag { TRUE } else { FALSE };
+        unsafe {
+            self.0.SetStrikethrough(flag, range);
+        }
+    }
+
+    /// Use a uniform line height and baseline for every line of the layout.
+    pub(crate) fn set_line_spacing(&mut self, height: f64, baseline: f64) {
+        unsafe {
+            self.0.SetLineSpacing(
+                DWRITE_LINE_SPACING_METHOD_UNIFORM,
+                height as f32,
+                baseline as f32,
+            );
+        }
+    }
+
+    pub(crate) fn set_letter_spacing(&mut self, start: usize, len: usize, spacing: f32) {
+        let range = make_text_range(start, len);
+        // character spacing requires IDWriteTextLayout1, which is Windows 8+
+        if let Ok(layout) = self.0.cast::<IDWriteTextLayout1>() {
+            unsafe {
+                layout.SetCharacterSpacing(0.0, spacing, 0.0, range);
+            }
+        }
+    }
+
+    pub(crate) fn set_font_features(
+        &mut self,
+        dwrite: &DwriteFactory,
+        start: usize,
+        len: usize,
+        features: &FontFeatures,
+    ) {
+        let range = make_text_range(start, len);
+        unsafe {
+            let mut ptr: *mut IDWriteTypography = null_mut();
+            let hr = dwrite.0.CreateTypography(&mut ptr);
+            if !SUCCEEDED(hr) {
+                return;
+            }
+            let typography = ComPtr::from_raw(ptr);
+            for (tag, value) in features.iter() {
+                let feature = DWRITE_FONT_FEATURE {
+                    // DWRITE_MAKE_OPENTYPE_TAG puts the first byte in the low bits
+                    nameTag: u32::from_le_bytes(tag),
+                    parameter: value,
+                };
+                typography.AddFontFeature(feature);
+            }
+            // SetTypography holds its own reference to the typography
+            self.0.SetTypography(typography.as_raw(), range);
+        }
+    }
+
+    /// Use custom font fallback for this layout.
+    ///
+    /// Each of `fallbacks` maps the name of a family used in the layout to
+    /// the names of the families to try, in order, for characters that family
+    /// lacks; anything else uses the system fallback. If a family has more
+    /// than one list, the first is used.
+    pub(crate) fn set_font_fallbacks(
+        &mut self,
+        dwrite: &DwriteFactory,
+        fallbacks: &[(String, Vec<String>)],
+    ) {
+        // custom font fallback requires IDWriteFactory2, which is Windows 8.1+
+        let (factory, layout) = match (
+            dwrite.0.cast::<IDWriteFactory2>(),
+            self.0.cast::<IDWriteTextLayout2>(),
+        ) {
+            (Ok(factory), Ok(layout)) => (factory, layout),
+            _ => return,
+        };
+        unsafe {
+            let mut ptr: *mut IDWriteFontFallbackBuilder = null_mut();
+            let hr = factory.CreateFontFallbackBuilder(&mut ptr);
+            if !SUCCEEDED(hr) {
+                return;
+            }
+            let builder = ComPtr::from_raw(ptr);
+            let all_chars = DWRITE_UNICODE_RANGE {
+                first: 0,
+                last: 0x10_FFFF,
+            };
+            for (base, targets) in fallbacks {
+                let base = base.to_wide_null();
+                let targets: Vec<Vec<u16>> = targets.iter().map(|t| t.to_wide_null()).collect();
+                let mut target_ptrs: Vec<*const u16> = targets.iter().map(|t| t.as_ptr()).collect();
+                builder.AddMapping(
+                    &all_chars,
+                    1,
+                    target_ptrs.as_mut_ptr(),
+                    target_ptrs.len() as u32,
+                    null_mut(),
+                    null(),
+                    base.as_ptr(),
+                    1.0,
+                );
+            }
+            let mut ptr: *mut IDWriteFontFallback = null_mut();
+            if SUCCEEDED(factory.GetSystemFontFallback(&mut ptr)) {
+                let system = ComPtr::from_raw(ptr);
+                builder.AddMappings(system.as_raw());
+            }
+            let mut ptr: *mut IDWriteFontFallback = null_mut();
+            if SUCCEEDED(builder.CreateFontFallback(&mut ptr)) {
+                // SetFontFallback holds its own reference to the fallback
+                let fallback = ComPtr::from_raw(ptr);
+                layout.SetFontFallback(fallback.as_raw());
+            }
+        }
+    }
+
     pub(crate) fn set_size(&mut self, start: usize, len: usize, size: f32) {
         let range = make_text_range(start, len);
         unsafe {
@@ -442,7 +673,54 @@ impl TextLayout {
             if SUCCEEDED(hr) {
                 Ok(())
             } else {
-                Err(hr.into())
+                Err(Error::new(hr, "SetMaxWidth"))
+            }
+        }
+    }
+
+    pub fn get_max_width(&self) -> f64 {
+        unsafe { self.0.GetMaxWidth() as f64 }
+    }
+
+    pub fn set_max_height(&mut self, max_height: f64) -> Result<(), Error> {
+        let max_height = if !max_height.is_finite() {
+            MAX_LAYOUT_CONSTRAINT
+        } else {
+            max_height as f32
+        };
+
+        unsafe {
+            let hr = self.0.SetMaxHeight(max_height);
+
+            if SUCCEEDED(hr) {
+                Ok(())
+            } else {
+                Err(Error::new(hr, "SetMaxHeight"))
+            }
+        }
+    }
+
+    /// Trim text that overflows the layout's max height, optionally ending
+    /// the last visible line with an ellipsis.
+    pub(crate) fn set_trimming(&mut self, dwrite: &DwriteFactory, ellipsis: bool) {
+        let trimming = DWRITE_TRIMMING {
+            granularity: DWRITE_TRIMMING_GRANULARITY_CHARACTER,
+            delimiter: 0,
+            delimiterCount: 0,
+        };
+        unsafe {
+            let mut sign: *mut IDWriteInlineObject = null_mut();
+            if ellipsis {
+                let format = self.0.as_raw() as *mut IDWriteTextFormat;
+                let hr = dwrite.0.CreateEllipsisTrimmingSign(format, &mut sign);
+                if !SUCCEEDED(hr) {
+                    sign = null_mut();
+                }
+            }
+            self.0.SetTrimming(&trimming, sign);
+            // SetTrimming holds its own reference to the sign
+            if !sign.is_null() {
+                drop(ComPtr::from_raw(sign));
             }
         }
     }
@@ -468,6 +746,43 @@ impl TextLayout {
         }
     }
 
+    /// Get the metrics of the regions covering a range of the text. `start` and
+    /// `len` are in utf16.
+    ///
+    /// There is at least one region for each line in the range, and more if
+    /// the range includes runs of different directions.
+    pub fn hit_test_text_range(&self, start: usize, len: usize) -> Vec<HitTestMetrics> {
+        let mut buf: Vec<DWRITE_HIT_TEST_METRICS> = Vec::with_capacity(1);
+        unsafe {
+            let mut actual_count = 0;
+            let mut hr = self.0.HitTestTextRange(
+                start as u32,
+                len as u32,
+                0.0,
+                0.0,
+                buf.as_mut_ptr(),
+                buf.capacity() as u32,
+                &mut actual_count,
+            );
+            if hr == E_NOT_SUFFICIENT_BUFFER {
+                buf.reserve(actual_count as usize);
+                hr = self.0.HitTestTextRange(
+                    start as u32,
+                    len as u32,
+                    0.0,
+                    0.0,
+                    buf.as_mut_ptr(),
+                    actual_count,
+                    &mut actual_count,
+                );
+            }
+            if SUCCEEDED(hr) {
+                buf.set_len(actual_count as usize);
+            }
+        }
+        buf.into_iter().map(Into::into).collect()
+    }
+
     pub fn hit_test_text_position(
         &self,
         position: u32,
@@ -491,6 +806,382 @@ impl TextLayout {
             })
         }
     }
+
+    /// Returns the shaped glyph runs of this layout, including any trimming
+    /// sign. The glyphs' clusters are utf16 offsets into the layout's text.
+    pub(crate) fn glyph_runs(&self, fonts: &FontCollection) -> Vec<GlyphRun> {
+        let mut collector = GlyphRunCollector {
+            vtbl: &GLYPH_RUN_COLLECTOR_VTBL,
+            fonts: Some(fonts),
+            runs: Vec::new(),
+            outline: None,
+        };
+        if self.draw_to_collector(&mut collector) {
+            collector.runs
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Returns the outlines of the glyphs of this layout, including any
+    /// trimming sign.
+    pub(crate) fn outline(&self) -> BezPath {
+        let mut collector = GlyphRunCollector {
+            vtbl: &GLYPH_RUN_COLLECTOR_VTBL,
+            fonts: None,
+            runs: Vec::new(),
+            outline: Some(BezPath::new()),
+        };
+        if self.draw_to_collector(&mut collector) {
+            collector.outline.unwrap_or_default()
+        } else {
+            BezPath::new()
+        }
+    }
+
+    fn draw_to_collector(&self, collector: &mut GlyphRunCollector) -> bool {
+        unsafe {
+            let renderer = collector as *mut GlyphRunCollector as *mut IDWriteTextRenderer;
+            SUCCEEDED(self.0.Draw(null_mut(), renderer, 0.0, 0.0))
+        }
+    }
+}
+
+/// A minimal `IDWriteTextRenderer` that records glyph runs, or their
+/// outlines if `outline` is set, instead of drawing them.
+///
+/// This only lives on the stack for the duration of a call to `Draw`, so
+/// it does no reference counting.
+#[repr(C)]
+struct GlyphRunCollector<'a> {
+    vtbl: *const IDWriteTextRendererVtbl,
+    fonts: Option<&'a FontCollection>,
+    runs: Vec<GlyphRun>,
+    outline: Option<BezPath>,
+}
+
+static GLYPH_RUN_COLLECTOR_VTBL: IDWriteTextRendererVtbl = IDWriteTextRendererVtbl {
+    parent: IDWritePixelSnappingVtbl {
+        parent: IUnknownVtbl {
+            QueryInterface: collector_query_interface,
+            AddRef: stack_add_ref,
+            Release: stack_release,
+        },
+        IsPixelSnappingDisabled: collector_is_pixel_snapping_disabled,
+        GetCurrentTransform: collector_get_current_transform,
+        GetPixelsPerDip: collector_get_pixels_per_dip,
+    },
+    DrawGlyphRun: collector_draw_glyph_run,
+    DrawUnderline: collector_draw_underline,
+    DrawStrikethrough: collector_draw_strikethrough,
+    DrawInlineObject: collector_draw_inline_object,
+};
+
+unsafe extern "system" fn collector_query_interface(
+    this: *mut IUnknown,
+    riid: REFIID,
+    out: *mut *mut c_void,
+) -> HRESULT {
+    let riid = &*riid;
+    if IsEqualIID(riid, &IUnknown::uuidof())
+        || IsEqualIID(riid, &IDWritePixelSnapping::uuidof())
+        || IsEqualIID(riid, &IDWriteTextRenderer::uuidof())
+    {
+        *out = this as *mut c_void;
+        S_OK
+    } else {
+        *out = null_mut();
+        E_NOINTERFACE
+    }
+}
+
+unsafe extern "system" fn stack_add_ref(_this: *mut IUnknown) -> ULONG {
+    1
+}
+
+unsafe extern "system" fn stack_release(_this: *mut IUnknown) -> ULONG {
+    1
+}
+
+unsafe extern "system" fn collector_is_pixel_snapping_disabled(
+    _this: *mut IDWritePixelSnapping,
+    _ctx: *mut c_void,
+    is_disabled: *mut BOOL,
+) -> HRESULT {
+    *is_disabled = TRUE;
+    S_OK
+}
+
+unsafe extern "system" fn collector_get_current_transform(
+    _this: *mut IDWritePixelSnapping,
+    _ctx: *mut c_void,
+    transform: *mut DWRITE_MATRIX,
+) -> HRESULT {
+    *transform = DWRITE_MATRIX {
+        m11: 1.0,
+        m12: 0.0,
+        m21: 0.0,
+        m22: 1.0,
+        dx: 0.0,
+        dy: 0.0,
+    };
+    S_OK
+}
+
+unsafe extern "system" fn collector_get_pixels_per_dip(
+    _this: *mut IDWritePixelSnapping,
+    _ctx: *mut c_void,
+    pixels_per_dip: *mut FLOAT,
+) -> HRESULT {
+    *pixels_per_dip = 1.0;
+    S_OK
+}
+
+unsafe extern "system" fn collector_draw_glyph_run(
+    this: *mut IDWriteTextRenderer,
+    _ctx: *mut c_void,
+    x: FLOAT,
+    y: FLOAT,
+    _mode: DWRITE_MEASURING_MODE,
+    run: *const DWRITE_GLYPH_RUN,
+    desc: *const DWRITE_GLYPH_RUN_DESCRIPTION,
+    _effect: *mut IUnknown,
+) -> HRESULT {
+    let this = &mut *(this as *mut GlyphRunCollector);
+    let run = &*run;
+    let count = run.glyphCount as usize;
+    if count == 0 {
+        return S_OK;
+    }
+
+    if let Some(path) = this.outline.as_mut() {
+        let mut sink = OutlineSink {
+            vtbl: &OUTLINE_SINK_VTBL,
+            path,
+            origin: Point::new(x as f64, y as f64),
+        };
+        let sink_ptr = &mut sink as *mut OutlineSink as *mut ID2D1SimplifiedGeometrySink;
+        return (*run.fontFace).GetGlyphRunOutline(
+            run.fontEmSize,
+            run.glyphIndices,
+            run.glyphAdvances,
+            run.glyphOffsets,
+            run.glyphCount,
+            run.isSideways,
+            (run.bidiLevel % 2 == 1) as BOOL,
+            sink_ptr,
+        );
+    }
+
+    // the cluster map gives the first glyph of each utf16 unit's cluster;
+    // every glyph up to the next cluster's first glyph belongs to it.
+    let mut clusters = vec![0; count];
+    if let Some(desc) = desc.as_ref().filter(|d| !d.clusterMap.is_null()) {
+        let map = std::slice::from_raw_parts(desc.clusterMap, desc.stringLength as usize);
+        let mut utf16_pos = 0;
+        while utf16_pos < map.len() {
+            let first_glyph = map[utf16_pos] as usize;
+            let mut next = utf16_pos + 1;
+            while next < map.len() && map[next] as usize == first_glyph {
+                next += 1;
+            }
+            let end_glyph = map.get(next).map(|g| *g as usize).unwrap_or(count);
+            for cluster in clusters.iter_mut().take(end_glyph).skip(first_glyph) {
+                *cluster = desc.textPosition as usize + utf16_pos;
+            }
+            utf16_pos = next;
+        }
+    }
+
+    let rtl = run.bidiLevel % 2 == 1;
+    let ids = std::slice::from_raw_parts(run.glyphIndices, count);
+    let advances = std::slice::from_raw_parts(run.glyphAdvances, count);
+    let offsets = if run.glyphOffsets.is_null() {
+        None
+    } else {
+        Some(std::slice::from_raw_parts(run.glyphOffsets, count))
+    };
+
+    let mut pen = x as f64;
+    let mut glyphs = Vec::with_capacity(count);
+    for i in 0..count {
+        if rtl {
+            pen -= advances[i] as f64;
+        }
+        let (dx, dy) = offsets
+            .map(|o| (o[i].advanceOffset as f64, o[i].ascenderOffset as f64))
+            .unwrap_or_default();
+        let dx = if rtl { -dx } else { dx };
+        glyphs.push(Glyph {
+            id: ids[i] as u32,
+            position: Point::new(pen + dx, y as f64 - dy),
+            cluster: clusters[i],
+        });
+        if !rtl {
+            pen += advances[i] as f64;
+        }
+    }
+
+    let font = this
+        .fonts
+        .and_then(|fonts| fonts.family_for_font_face(run.fontFace))
+        .unwrap_or(PietFontFamily::SYSTEM_UI);
+    this.runs.push(GlyphRun {
+        font,
+        font_size: run.fontEmSize as f64,
+        glyphs,
+    });
+    S_OK
+}
+
+unsafe extern "system" fn collector_draw_underline(
+    _this: *mut IDWriteTextRenderer,
+    _ctx: *mut c_void,
+    _x: FLOAT,
+    _y: FLOAT,
+    _underline: *const DWRITE_UNDERLINE,
+    _effect: *mut IUnknown,
+) -> HRESULT {
+    S_OK
+}
+
+unsafe extern "system" fn collector_draw_strikethrough(
+    _this: *mut IDWriteTextRenderer,
+    _ctx: *mut c_void,
+    _x: FLOAT,
+    _y: FLOAT,
+    _strikethrough: *const DWRITE_STRIKETHROUGH,
+    _effect: *mut IUnknown,
+) -> HRESULT {
+    S_OK
+}
+
+/// Inline objects (such as the trimming ellipsis) draw themselves by calling
+/// back into the renderer, so their glyphs are collected as well.
+unsafe extern "system" fn collector_draw_inline_object(
+    this: *mut IDWriteTextRenderer,
+    ctx: *mut c_void,
+    x: FLOAT,
+    y: FLOAT,
+    inline: *mut IDWriteInlineObject,
+    sideways: BOOL,
+    rtl: BOOL,
+    effect: *mut IUnknown,
+) -> HRESULT {
+    (*inline).Draw(ctx, this, x, y, sideways, rtl, effect)
+}
+
+/// An `ID2D1SimplifiedGeometrySink` that appends glyph outlines to a path,
+/// translated to the origin of their glyph run.
+///
+/// Like `GlyphRunCollector`, this only lives on the stack.
+#[repr(C)]
+struct OutlineSink<'a> {
+    vtbl: *const ID2D1SimplifiedGeometrySinkVtbl,
+    path: &'a mut BezPath,
+    origin: Point,
+}
+
+impl OutlineSink<'_> {
+    fn point(&self, point: D2D1_POINT_2F) -> Point {
+        self.origin + Vec2::new(point.x as f64, point.y as f64)
+    }
+}
+
+static OUTLINE_SINK_VTBL: ID2D1SimplifiedGeometrySinkVtbl = ID2D1SimplifiedGeometrySinkVtbl {
+    parent: IUnknownVtbl {
+        QueryInterface: outline_sink_query_interface,
+        AddRef: stack_add_ref,
+        Release: stack_release,
+    },
+    SetFillMode: outline_sink_set_fill_mode,
+    SetSegmentFlags: outline_sink_set_segment_flags,
+    BeginFigure: outline_sink_begin_figure,
+    AddLines: outline_sink_add_lines,
+    AddBeziers: outline_sink_add_beziers,
+    EndFigure: outline_sink_end_figure,
+    Close: outline_sink_close,
+};
+
+unsafe extern "system" fn outline_sink_query_interface(
+    this: *mut IUnknown,
+    riid: REFIID,
+    out: *mut *mut c_void,
+) -> HRESULT {
+    let riid = &*riid;
+    if IsEqualIID(riid, &IUnknown::uuidof())
+        || IsEqualIID(riid, &ID2D1SimplifiedGeometrySink::uuidof())
+    {
+        *out = this as *mut c_void;
+        S_OK
+    } else {
+        *out = null_mut();
+        E_NOINTERFACE
+    }
+}
+
+unsafe extern "system" fn outline_sink_set_fill_mode(
+    _this: *mut ID2D1SimplifiedGeometrySink,
+    _mode: D2D1_FILL_MODE,
+) {
+}
+
+unsafe extern "system" fn outline_sink_set_segment_flags(
+    _this: *mut ID2D1SimplifiedGeometrySink,
+    _flags: D2D1_PATH_SEGMENT,
+) {
+}
+
+unsafe extern "system" fn outline_sink_begin_figure(
+    this: *mut ID2D1SimplifiedGeometrySink,
+    start: D2D1_POINT_2F,
+    _begin: D2D1_FIGURE_BEGIN,
+) {
+    let this = &mut *(this as *mut OutlineSink);
+    let start = this.point(start);
+    this.path.move_to(start);
+}
+
+unsafe extern "system" fn outline_sink_add_lines(
+    this: *mut ID2D1SimplifiedGeometrySink,
+    points: *const D2D1_POINT_2F,
+    count: u32,
+) {
+    let this = &mut *(this as *mut OutlineSink);
+    for point in std::slice::from_raw_parts(points, count as usize) {
+        let point = this.point(*point);
+        this.path.line_to(point);
+    }
+}
+
+unsafe extern "system" fn outline_sink_add_beziers(
+    this: *mut ID2D1SimplifiedGeometrySink,
+    beziers: *const D2D1_BEZIER_SEGMENT,
+    count: u32,
+) {
+    let this = &mut *(this as *mut OutlineSink);
+    for bez in std::slice::from_raw_parts(beziers, count as usize) {
+        let (p1, p2, p3) = (
+            this.point(bez.point1),
+            this.point(bez.point2),
+            this.point(bez.point3),
+        );
+        this.path.curve_to(p1, p2, p3);
+    }
+}
+
+unsafe extern "system" fn outline_sink_end_figure(
+    this: *mut ID2D1SimplifiedGeometrySink,
+    end: D2D1_FIGURE_END,
+) {
+    let this = &mut *(this as *mut OutlineSink);
+    if end == D2D1_FIGURE_END_CLOSED {
+        this.path.close_path();
+    }
+}
+
+unsafe extern "system" fn outline_sink_close(_this: *mut ID2D1SimplifiedGeometrySink) -> HRESULT {
+    S_OK
 }
 
 #[derive(Copy, Clone)]
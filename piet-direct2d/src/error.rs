@@ -1,56 +1,60 @@
-//! Error conversion from D2D/DWrite to piet Error.
+//! The error type for failed Direct2D, DirectWrite and Direct3D calls.
 
 use std::fmt;
 
-use directwrite::error::DWriteError;
+use winapi::shared::winerror::HRESULT;
 
-use piet::Error;
-
-/// The direct2d error type doesn't implement any error traits, so we newtype.
+/// A failed Direct2D, DirectWrite or Direct3D call.
+///
+/// This keeps the `HRESULT`, along with the name of the call that returned
+/// it. It is the error wrapped by [`piet::Error::BackendError`] for these
+/// failures; get it with [`piet::Error::backend_error_downcast`].
 ///
-/// TODO: investigate getting either std::error::Error or maybe failure added
-/// to direct2d.
-#[derive(Debug)]
-struct WrappedD2DError(direct2d::Error);
+/// [`piet::Error::BackendError`]: ../piet/enum.Error.html#variant.BackendError
+/// [`piet::Error::backend_error_downcast`]: ../piet/enum.Error.html#method.backend_error_downcast
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct D2DError {
+    hresult: HRESULT,
+    context: &'static str,
+}
 
-#[derive(Debug)]
-struct WrappedDWriteError(DWriteError);
+impl D2DError {
+    /// An error with the `HRESULT` returned by the named call.
+    pub fn new(hresult: HRESULT, context: &'static str) -> D2DError {
+        D2DError { hresult, context }
+    }
 
-impl std::error::Error for WrappedD2DError {}
-impl std::error::Error for WrappedDWriteError {}
+    /// The `HRESULT` returned by the call.
+    pub fn hresult(&self) -> HRESULT {
+        self.hresult
+    }
 
-impl fmt::Display for WrappedD2DError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Direct2D error: {}", self.0)
+    /// The name of the call that failed, such as `"CreateTextLayout"`.
+    pub fn context(&self) -> &'static str {
+        self.context
     }
 }
 
-impl fmt::Display for WrappedDWriteError {
+impl fmt::Debug for D2DError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "DirectWrite error: {}", self.0)
+        write!(
+            f,
+            "D2DError({}, hresult {:08x})",
+            self.context, self.hresult
+        )
     }
 }
 
-// Discussion question: is there a clean way to get this to automatically
-// happen when the `?` macro is used on a D2DResult?
-pub trait WrapError<T> {
-    fn wrap(self) -> Result<T, Error>;
-}
-
-impl<T> WrapError<T> for Result<T, direct2d::Error> {
-    fn wrap(self) -> Result<T, Error> {
-        self.map_err(|e| {
-            let e: Box<dyn std::error::Error> = Box::new(WrappedD2DError(e));
-            e.into()
-        })
+impl fmt::Display for D2DError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} failed: hresult {:08x}", self.context, self.hresult)
     }
 }
 
-impl<T> WrapError<T> for Result<T, DWriteError> {
-    fn wrap(self) -> Result<T, Error> {
-        self.map_err(|e| {
-            let e: Box<dyn std::error::Error> = Box::new(WrappedDWriteError(e));
-            e.into()
-        })
+impl std::error::Error for D2DError {}
+
+impl From<D2DError> for piet::Error {
+    fn from(e: D2DError) -> piet::Error {
+        piet::Error::BackendError(Box::new(e))
     }
 }
@@ -9,36 +9,42 @@ mod conv;
 pub mod d2d;
 pub mod d3d;
 pub mod dwrite;
+mod error;
 mod text;
 
 use std::borrow::Cow;
 use std::ops::Deref;
 
+use winapi::shared::dxgiformat::{DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_R8G8B8A8_UNORM};
 use winapi::um::d2d1::{
-    D2D1_BITMAP_INTERPOLATION_MODE_LINEAR, D2D1_BITMAP_INTERPOLATION_MODE_NEAREST_NEIGHBOR,
-    D2D1_DRAW_TEXT_OPTIONS_NONE, D2D1_LINEAR_GRADIENT_BRUSH_PROPERTIES,
-    D2D1_RADIAL_GRADIENT_BRUSH_PROPERTIES,
+    D2D1_DRAW_TEXT_OPTIONS_NONE, D2D1_ELLIPSE, D2D1_EXTEND_MODE_CLAMP,
+    D2D1_LINEAR_GRADIENT_BRUSH_PROPERTIES, D2D1_RADIAL_GRADIENT_BRUSH_PROPERTIES, D2D1_RECT_U,
+    D2D1_ROUNDED_RECT,
+};
+use winapi::um::d2d1_1::{
+    D2D1_COMPOSITE_MODE_SOURCE_OVER, D2D1_INTERPOLATION_MODE_HIGH_QUALITY_CUBIC,
+    D2D1_INTERPOLATION_MODE_LINEAR, D2D1_INTERPOLATION_MODE_NEAREST_NEIGHBOR,
 };
-use winapi::um::d2d1_1::{D2D1_COMPOSITE_MODE_SOURCE_OVER, D2D1_INTERPOLATION_MODE_LINEAR};
 use winapi::um::dcommon::{D2D1_ALPHA_MODE_IGNORE, D2D1_ALPHA_MODE_PREMULTIPLIED};
 
-use piet::kurbo::{Affine, PathEl, Point, Rect, Shape};
+use piet::kurbo::{Affine, BezPath, PathEl, Point, Rect, Shape, Size, Vec2};
 
 use piet::{
-    Color, Error, FixedGradient, ImageFormat, InterpolationMode, IntoBrush, RenderContext,
-    StrokeStyle,
+    util, BlendMode, Color, Error, FixedGradient, FixedSweepGradient, Image, ImageBuf, ImageFormat,
+    InterpolationMode, IntoBrush, LayerMask, RenderContext, ShadowStyle, StrokeStyle, TileMode,
 };
 
 use crate::d2d::wrap_unit;
 pub use crate::d2d::{D2DDevice, D2DFactory, DeviceContext as D2DDeviceContext};
 pub use crate::dwrite::DwriteFactory;
+pub use crate::error::D2DError;
 pub use crate::text::{D2DText, D2DTextLayout, D2DTextLayoutBuilder};
 
 use crate::conv::{
-    affine_to_matrix3x2f, color_to_colorf, convert_stroke_style, gradient_stop_to_d2d,
-    rect_to_rectf, to_point2f,
+    affine_to_matrix3x2f, color_to_colorf, convert_extend, convert_stroke_style, convert_tile_mode,
+    gradient_stop_to_d2d, rect_to_rectf, to_point2f,
 };
-use crate::d2d::{Bitmap, Brush, DeviceContext, FillRule, PathGeometry};
+use crate::d2d::{Bitmap, Brush, DeviceContext, FillRule, PathGeometry, Primitive};
 
 pub struct D2DRenderContext<'a> {
     factory: &'a D2DFactory,
@@ -48,6 +54,9 @@ pub struct D2DRenderContext<'a> {
     /// The context state stack. There is always at least one, until finishing.
     ctx_stack: Vec<CtxState>,
 
+    /// Brushes and images are drawn at this opacity.
+    opacity: util::OpacityStack,
+
     err: Result<(), Error>,
 }
 
@@ -55,6 +64,9 @@ pub struct D2DRenderContext<'a> {
 struct CtxState {
     transform: Affine,
 
+    /// The bounds of the clip, before the transform.
+    clip: Option<Rect>,
+
     // Note: when we start pushing both layers and axis aligned clips, this will
     // need to keep track of which is which. But for now, keep it simple.
     n_layers_pop: usize,
@@ -75,6 +87,7 @@ impl<'b, 'a: 'b> D2DRenderContext<'a> {
             inner_text,
             rt,
             ctx_stack: vec![CtxState::default()],
+            opacity: util::OpacityStack::new(),
             err: Ok(()),
         }
     }
@@ -87,6 +100,17 @@ impl<'b, 'a: 'b> D2DRenderContext<'a> {
         }
     }
 
+    /// Push a state for a layer that was just pushed, so that the layer is
+    /// popped, and composited, on restore.
+    fn push_layer_state(&mut self) {
+        let new_state = CtxState {
+            transform: self.current_transform(),
+            clip: self.ctx_stack.last().unwrap().clip,
+            n_layers_pop: 1,
+        };
+        self.ctx_stack.push(new_state);
+    }
+
     /// Check whether drawing operations have finished.
     ///
     /// Clients should call this before extracting or presenting the contents of
@@ -146,6 +170,39 @@ fn path_from_shape(
     Ok(path)
 }
 
+/// The Direct2D primitive for a shape, if there is one; these are drawn
+/// without building a path geometry.
+fn primitive_from_shape(shape: &impl Shape) -> Option<Primitive> {
+    if let Some(rect) = shape.as_rect() {
+        Some(Primitive::Rect(rect_to_rectf(rect)))
+    } else if let Some(rounded) = shape.as_rounded_rect() {
+        let radius = rounded.radius() as f32;
+        Some(Primitive::RoundedRect(D2D1_ROUNDED_RECT {
+            rect: rect_to_rectf(rounded.rect()),
+            radiusX: radius,
+            radiusY: radius,
+        }))
+    } else if let Some(circle) = shape.as_circle() {
+        let radius = circle.radius as f32;
+        Some(Primitive::Ellipse(D2D1_ELLIPSE {
+            point: to_point2f(circle.center),
+            radiusX: radius,
+            radiusY: radius,
+        }))
+    } else if let Some(line) = shape.as_line() {
+        Some(Primitive::Line(to_point2f(line.p0), to_point2f(line.p1)))
+    } else {
+        None
+    }
+}
+
+impl Image for Bitmap {
+    fn size(&self) -> Size {
+        let size = self.get_size();
+        Size::new(size.width as f64, size.height as f64)
+    }
+}
+
 impl<'a> RenderContext for D2DRenderContext<'a> {
     type Brush = Brush;
 
@@ -170,14 +227,16 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
     }
 
     fn gradient(&mut self, gradient: impl Into<FixedGradient>) -> Result<Brush, Error> {
-        match gradient.into() {
+        match util::resolve_color_interpolation(gradient.into()) {
             FixedGradient::Linear(linear) => {
                 let props = D2D1_LINEAR_GRADIENT_BRUSH_PROPERTIES {
                     startPoint: to_point2f(linear.start),
                     endPoint: to_point2f(linear.end),
                 };
                 let stops: Vec<_> = linear.stops.iter().map(gradient_stop_to_d2d).collect();
-                let stops = self.rt.create_gradient_stops(&stops)?;
+                let stops = self
+                    .rt
+                    .create_gradient_stops(&stops, convert_extend(linear.extend))?;
                 let result = self.rt.create_linear_gradient(&props, &stops)?;
                 Ok(result)
             }
@@ -185,20 +244,62 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
                 let props = D2D1_RADIAL_GRADIENT_BRUSH_PROPERTIES {
                     center: to_point2f(radial.center),
                     gradientOriginOffset: to_point2f(radial.origin_offset),
-                    radiusX: radial.radius as f32,
-                    radiusY: radial.radius as f32,
+                    radiusX: radial.radii.x as f32,
+                    radiusY: radial.radii.y as f32,
                 };
                 let stops: Vec<_> = radial.stops.iter().map(gradient_stop_to_d2d).collect();
-                let stops = self.rt.create_gradient_stops(&stops)?;
+                let stops = self
+                    .rt
+                    .create_gradient_stops(&stops, convert_extend(radial.extend))?;
                 let result = self.rt.create_radial_gradient(&props, &stops)?;
                 Ok(result)
             }
+            FixedGradient::Sweep(sweep) => self.sweep_gradient(&sweep),
         }
     }
 
+    fn make_brush_cached(
+        &mut self,
+        key: u64,
+        gradient: impl FnOnce() -> FixedGradient,
+    ) -> Result<Brush, Error> {
+        if let Some(brush) = self.rt.brush_cache().borrow_mut().get(key) {
+            return Ok(brush);
+        }
+        let gradient = gradient();
+        // sweep gradients are rasterized for the current transform, so
+        // they can't be reused
+        let cache = !matches!(gradient, FixedGradient::Sweep(_));
+        let brush = self.gradient(gradient)?;
+        if cache {
+            self.rt
+                .brush_cache()
+                .borrow_mut()
+                .insert(key, brush.clone());
+        }
+        Ok(brush)
+    }
+
+    fn make_image_brush(
+        &mut self,
+        image: &Bitmap,
+        tile: TileMode,
+        transform: Affine,
+    ) -> Result<Brush, Error> {
+        let brush = self.rt.create_bitmap_brush(
+            image,
+            convert_tile_mode(tile),
+            affine_to_matrix3x2f(transform),
+        )?;
+        Ok(brush)
+    }
+
     fn fill(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
-        // TODO: various special-case shapes, for efficiency
         let brush = brush.make_brush(self, || shape.bounding_box());
+        brush.set_opacity(self.opacity.current() as f32);
+        if let Some(primitive) = primitive_from_shape(&shape) {
+            return self.rt.fill_primitive(&primitive, &brush);
+        }
         match path_from_shape(self.factory, true, shape, FillRule::NonZero) {
             Ok(path) => self.rt.fill_geometry(&path, &brush, None),
             Err(e) => self.err = Err(e),
@@ -206,8 +307,12 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
     }
 
     fn fill_even_odd(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
-        // TODO: various special-case shapes, for efficiency
         let brush = brush.make_brush(self, || shape.bounding_box());
+        brush.set_opacity(self.opacity.current() as f32);
+        // the primitives don't overlap themselves, so the fill rule doesn't matter
+        if let Some(primitive) = primitive_from_shape(&shape) {
+            return self.rt.fill_primitive(&primitive, &brush);
+        }
         match path_from_shape(self.factory, true, shape, FillRule::EvenOdd) {
             Ok(path) => self.rt.fill_geometry(&path, &brush, None),
             Err(e) => self.err = Err(e),
@@ -216,7 +321,11 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
 
     fn stroke(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, width: f64) {
         let brush = brush.make_brush(self, || shape.bounding_box());
-        // TODO: various special-case shapes, for efficiency
+        brush.set_opacity(self.opacity.current() as f32);
+        let width = width as f32;
+        if let Some(primitive) = primitive_from_shape(&shape) {
+            return self.rt.draw_primitive(&primitive, &brush, width, None);
+        }
         let path = match path_from_shape(self.factory, false, shape, FillRule::EvenOdd) {
             Ok(path) => path,
             Err(e) => {
@@ -224,7 +333,6 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
                 return;
             }
         };
-        let width = width as f32;
         self.rt.draw_geometry(&path, &*brush, width, None);
     }
 
@@ -235,8 +343,31 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
         width: f64,
         style: &StrokeStyle,
     ) {
+        if let Some(mode) = style.snap_mode {
+            // the transform maps to DIPs, which may not be device pixels
+            let transform =
+                Affine::scale(self.rt.get_dpi_scale() as f64) * self.current_transform();
+            let (path, width) = util::snap_stroke(shape, width, mode, transform);
+            let style = StrokeStyle {
+                snap_mode: None,
+                ..style.clone()
+            };
+            return self.stroke_styled(path, brush, width, &style);
+        }
         let brush = brush.make_brush(self, || shape.bounding_box());
-        // TODO: various special-case shapes, for efficiency
+        brush.set_opacity(self.opacity.current() as f32);
+        let width = width as f32;
+        let d2d_style = convert_stroke_style(self.factory, style, width)
+            .expect("stroke style conversion failed");
+        // Direct2D starts the dashes of its primitives in different places
+        // than the paths of the shapes, so only undashed strokes use them.
+        if style.dash.is_none() {
+            if let Some(primitive) = primitive_from_shape(&shape) {
+                return self
+                    .rt
+                    .draw_primitive(&primitive, &brush, width, Some(&d2d_style));
+            }
+        }
         let path = match path_from_shape(self.factory, false, shape, FillRule::EvenOdd) {
             Ok(path) => path,
             Err(e) => {
@@ -244,30 +375,16 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
                 return;
             }
         };
-        let width = width as f32;
-        let style = convert_stroke_style(self.factory, style, width)
-            .expect("stroke style conversion failed");
-        self.rt.draw_geometry(&path, &*brush, width, Some(&style));
+        self.rt
+            .draw_geometry(&path, &*brush, width, Some(&d2d_style));
     }
 
     fn clip(&mut self, shape: impl Shape) {
-        // TODO: set size based on bbox of shape.
-        let layer = match self.rt.create_layer(None) {
-            Ok(layer) => layer,
-            Err(e) => {
-                self.err = Err(e.into());
-                return;
-            }
-        };
-        let path = match path_from_shape(self.factory, true, shape, FillRule::NonZero) {
-            Ok(path) => path,
-            Err(e) => {
-                self.err = Err(e);
-                return;
-            }
-        };
-        self.rt.push_layer_mask(&path, &layer);
-        self.ctx_stack.last_mut().unwrap().n_layers_pop += 1;
+        self.clip_with_rule(shape, FillRule::NonZero);
+    }
+
+    fn clip_even_odd(&mut self, shape: impl Shape) {
+        self.clip_with_rule(shape, FillRule::EvenOdd);
     }
 
     fn text(&mut self) -> &mut Self::Text {
@@ -275,6 +392,24 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
     }
 
     fn draw_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>) {
+        if self.opacity.is_translucent() {
+            // the colors of the text are brushes inside the layout, so the
+            // layout is faded as a whole, in a layer
+            let layer = match self.rt.create_layer(None) {
+                Ok(layer) => layer,
+                Err(e) => {
+                    self.err = Err(e.into());
+                    return;
+                }
+            };
+            let opacity = std::mem::take(&mut self.opacity);
+            self.rt
+                .push_layer(&layer, None, opacity.current() as f32, None);
+            self.draw_text(layout, pos);
+            self.rt.pop_layer();
+            self.opacity = opacity;
+            return;
+        }
         // TODO: bounding box for text
         let mut line_metrics = Vec::with_capacity(1);
         layout.layout.get_line_metrics(&mut line_metrics);
@@ -283,25 +418,96 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
             return;
         }
 
-        let pos = to_point2f(pos.into());
-        let text_options = D2D1_DRAW_TEXT_OPTIONS_NONE;
-        // this is used for regions that don't have other colors set;
-        // we could be doing this elsewhere but here seems fine
-        let black_brush = self.solid_brush(Color::BLACK);
+        let pos = pos.into();
+        for (rect, color) in layout.background_rects() {
+            self.fill(rect + pos.to_vec2(), &color);
+        }
 
-        self.rt
-            .draw_text_layout(pos, &layout.layout, &black_brush, text_options);
+        let shadows = layout.shadow_rects();
+        if !shadows.is_empty() {
+            let outline = layout.outline();
+            for (rects, shadow) in shadows {
+                self.draw_text_shadow(&outline, &rects, pos, shadow);
+            }
+        }
+
+        for (path, style, color, width) in layout.underline_paths() {
+            self.stroke_styled(
+                Affine::translate(pos.to_vec2()) * path,
+                &color,
+                width,
+                &style,
+            );
+        }
+
+        let shifts = layout.baseline_shift_rects();
+        if shifts.is_empty() {
+            return self.draw_layout(layout, pos);
+        }
+
+        // dwrite has no baseline shift, so we draw the layout with the shifted
+        // ranges masked out, and then again for each shifted range, translated
+        // and clipped to the region of that range.
+        let mut mask = BezPath::new();
+        let outer = layout.image_bounds() + pos.to_vec2();
+        for rect in std::iter::once(outer).chain(shifts.iter().map(|(r, _)| *r + pos.to_vec2())) {
+            for el in rect.to_bez_path(BEZ_TOLERANCE) {
+                mask.push(el);
+            }
+        }
+        self.draw_layout_masked(layout, pos, mask, FillRule::EvenOdd);
+        for (rect, shift) in shifts {
+            let offset = Vec2::new(0.0, -shift);
+            let clip = rect + pos.to_vec2() + offset;
+            self.draw_layout_masked(layout, pos + offset, clip, FillRule::NonZero);
+        }
     }
 
     fn save(&mut self) -> Result<(), Error> {
         let new_state = CtxState {
             transform: self.current_transform(),
+            clip: self.ctx_stack.last().unwrap().clip,
             n_layers_pop: 0,
         };
         self.ctx_stack.push(new_state);
         Ok(())
     }
 
+    fn save_layer(&mut self, opacity: f64) -> Result<(), Error> {
+        let layer = self.rt.create_layer(None)?;
+        self.rt.push_layer(&layer, None, opacity as f32, None);
+        self.push_layer_state();
+        Ok(())
+    }
+
+    fn save_layer_with_mask(&mut self, mask: LayerMask<Bitmap>) -> Result<(), Error> {
+        let layer = self.rt.create_layer(None)?;
+        match mask {
+            LayerMask::Shape(path) => {
+                let path = path_from_shape(self.factory, true, path, FillRule::NonZero)?;
+                self.rt.push_layer(&layer, Some(&path), 1.0, None);
+            }
+            LayerMask::Image(image, rect) => {
+                // the brush clamps at the edges, so we also clip to the rect
+                let size = image.get_size();
+                let transform = Affine::translate(rect.origin().to_vec2())
+                    * Affine::scale_non_uniform(
+                        rect.width() / size.width as f64,
+                        rect.height() / size.height as f64,
+                    );
+                let brush = self.rt.create_bitmap_brush(
+                    image,
+                    D2D1_EXTEND_MODE_CLAMP,
+                    affine_to_matrix3x2f(transform),
+                )?;
+                let path = path_from_shape(self.factory, true, rect, FillRule::NonZero)?;
+                self.rt.push_layer(&layer, Some(&path), 1.0, Some(&brush));
+            }
+        }
+        self.push_layer_state();
+        Ok(())
+    }
+
     fn restore(&mut self) -> Result<(), Error> {
         if self.ctx_stack.len() <= 1 {
             return Err(Error::StackUnbalance);
@@ -313,6 +519,23 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
         Ok(())
     }
 
+    fn set_blend_mode(&mut self, mode: BlendMode) -> Result<(), Error> {
+        // Direct2D's primitive blends don't correspond to any of the other
+        // modes, and blend effects would need every draw to go through a layer.
+        match mode {
+            BlendMode::Normal => Ok(()),
+            _ => Err(Error::NotSupported),
+        }
+    }
+
+    fn push_opacity(&mut self, alpha: f64) {
+        self.opacity.push(alpha);
+    }
+
+    fn pop_opacity(&mut self) -> Result<(), Error> {
+        self.opacity.pop()
+    }
+
     // Discussion question: should this subsume EndDraw, with BeginDraw on
     // D2DRenderContext creation? I'm thinking not, as the shell might want
     // to do other stuff, possibly related to incremental paint.
@@ -335,6 +558,11 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
         self.ctx_stack.last().unwrap().transform
     }
 
+    fn current_clip_bounds(&self) -> Option<Rect> {
+        let state = self.ctx_stack.last().unwrap();
+        util::user_clip_bounds(state.clip, state.transform)
+    }
+
     fn make_image(
         &mut self,
         width: usize,
@@ -344,10 +572,17 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
     ) -> Result<Self::Image, Error> {
         // TODO: this method _really_ needs error checking, so much can go wrong...
         let alpha_mode = match format {
-            ImageFormat::Rgb => D2D1_ALPHA_MODE_IGNORE,
-            ImageFormat::RgbaPremul | ImageFormat::RgbaSeparate => D2D1_ALPHA_MODE_PREMULTIPLIED,
+            ImageFormat::Rgb | ImageFormat::Grayscale => D2D1_ALPHA_MODE_IGNORE,
+            ImageFormat::RgbaPremul
+            | ImageFormat::RgbaSeparate
+            | ImageFormat::Bgra
+            | ImageFormat::BgraPremul => D2D1_ALPHA_MODE_PREMULTIPLIED,
             _ => return Err(Error::NotSupported),
         };
+        let pixel_format = match format {
+            ImageFormat::Bgra | ImageFormat::BgraPremul => DXGI_FORMAT_B8G8R8A8_UNORM,
+            _ => DXGI_FORMAT_R8G8B8A8_UNORM,
+        };
         let buf = match format {
             ImageFormat::Rgb => {
                 let mut new_buf = vec![255; width * height * 4];
@@ -358,7 +593,18 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
                 }
                 Cow::from(new_buf)
             }
-            ImageFormat::RgbaSeparate => {
+            // DXGI_FORMAT_A8_UNORM is alpha only, so we expand grayscale
+            ImageFormat::Grayscale => {
+                let mut new_buf = vec![255; width * height * 4];
+                for i in 0..width * height {
+                    new_buf[i * 4 + 0] = buf[i];
+                    new_buf[i * 4 + 1] = buf[i];
+                    new_buf[i * 4 + 2] = buf[i];
+                }
+                Cow::from(new_buf)
+            }
+            // premultiplying doesn't depend on the order of the color channels
+            ImageFormat::RgbaSeparate | ImageFormat::Bgra => {
                 let mut new_buf = vec![255; width * height * 4];
                 // TODO (performance): this would be soooo much faster with SIMD
                 fn premul(x: u8, a: u8) -> u8 {
@@ -374,14 +620,78 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
                 }
                 Cow::from(new_buf)
             }
-            ImageFormat::RgbaPremul => Cow::from(buf),
+            ImageFormat::RgbaPremul | ImageFormat::BgraPremul => Cow::from(buf),
             // This should be unreachable, we caught it above.
             _ => return Err(Error::NotSupported),
         };
-        let bitmap = self.rt.create_bitmap(width, height, &buf, alpha_mode)?;
+        let bitmap =
+            self.rt
+                .create_bitmap(width, height, &buf, width * 4, pixel_format, alpha_mode)?;
+        Ok(bitmap)
+    }
+
+    fn make_image_with_stride(
+        &mut self,
+        width: usize,
+        height: usize,
+        stride: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<Self::Image, Error> {
+        let pixel_format = match format {
+            ImageFormat::RgbaPremul => DXGI_FORMAT_R8G8B8A8_UNORM,
+            ImageFormat::BgraPremul => DXGI_FORMAT_B8G8R8A8_UNORM,
+            // other formats are converted anyway, so we pack them first
+            _ => {
+                let buf = util::pack_image_rows(width, height, stride, buf, format)?;
+                return self.make_image(width, height, &buf, format);
+            }
+        };
+        util::check_image_stride(width, height, stride, buf.len(), format)?;
+        let bitmap = self.rt.create_bitmap(
+            width,
+            height,
+            buf,
+            stride,
+            pixel_format,
+            D2D1_ALPHA_MODE_PREMULTIPLIED,
+        )?;
+        Ok(bitmap)
+    }
+
+    fn capture_image_area(&mut self, src_rect: impl Into<Rect>) -> Result<Self::Image, Error> {
+        let size = self.rt.get_pixel_size();
+        let size = Size::new(size.width as f64, size.height as f64);
+        // the transform maps to DIPs, which may not be device pixels
+        let dpi_scale = self.rt.get_dpi_scale() as f64;
+        let transform = Affine::scale(dpi_scale) * self.current_transform();
+        let rect = util::capture_rect(src_rect.into(), transform, size)?;
+        let rect = D2D1_RECT_U {
+            left: rect.x0 as u32,
+            top: rect.y0 as u32,
+            right: rect.x1 as u32,
+            bottom: rect.y1 as u32,
+        };
+        let bitmap = self.rt.copy_from_target(rect)?;
         Ok(bitmap)
     }
 
+    fn to_image_buf(
+        &mut self,
+        image: &Self::Image,
+        format: ImageFormat,
+    ) -> Result<ImageBuf, Error> {
+        let (buf, pixel_format) = self.rt.read_bitmap(image)?;
+        let size = image.get_pixel_size();
+        let src_format = if pixel_format.format == DXGI_FORMAT_B8G8R8A8_UNORM {
+            ImageFormat::BgraPremul
+        } else {
+            ImageFormat::RgbaPremul
+        };
+        let buf = ImageBuf::from_raw(buf, src_format, size.width as usize, size.height as usize);
+        Ok(buf.to_format(format))
+    }
+
     #[inline]
     fn draw_image(
         &mut self,
@@ -389,7 +699,8 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
         dst_rect: impl Into<Rect>,
         interp: InterpolationMode,
     ) {
-        draw_image(self.rt, image, None, dst_rect.into(), interp);
+        let opacity = self.opacity.current() as f32;
+        draw_image(self.rt, image, None, dst_rect.into(), interp, opacity);
     }
 
     #[inline]
@@ -400,33 +711,170 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
         dst_rect: impl Into<Rect>,
         interp: InterpolationMode,
     ) {
+        let opacity = self.opacity.current() as f32;
         draw_image(
             self.rt,
             image,
             Some(src_rect.into()),
             dst_rect.into(),
             interp,
+            opacity,
         );
     }
 
-    fn blurred_rect(&mut self, rect: Rect, blur_radius: f64, brush: &impl IntoBrush<Self>) {
-        let brush = brush.make_brush(self, || rect);
-        if let Err(e) = self.blurred_rect_raw(rect, blur_radius, brush) {
-            eprintln!("error in drawing blurred rect: {:?}", e);
+    fn blurred_shape(&mut self, shape: impl Shape, blur_radius: f64, brush: &impl IntoBrush<Self>) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        brush.set_opacity(self.opacity.current() as f32);
+        if let Err(e) = self.blurred_shape_raw(shape, blur_radius, brush) {
+            eprintln!("error in drawing blurred shape: {:?}", e);
         }
     }
 }
 
 impl<'a> D2DRenderContext<'a> {
+    fn clip_with_rule(&mut self, shape: impl Shape, fill_rule: FillRule) {
+        let state = self.ctx_stack.last_mut().unwrap();
+        let bounds = util::intersect_clip_bounds(state.clip, shape.bounding_box(), state.transform);
+        state.clip = Some(bounds);
+        // TODO: set size based on bbox of shape.
+        let layer = match self.rt.create_layer(None) {
+            Ok(layer) => layer,
+            Err(e) => {
+                self.err = Err(e.into());
+                return;
+            }
+        };
+        let path = match path_from_shape(self.factory, true, shape, fill_rule) {
+            Ok(path) => path,
+            Err(e) => {
+                self.err = Err(e);
+                return;
+            }
+        };
+        self.rt.push_layer(&layer, Some(&path), 1.0, None);
+        self.ctx_stack.last_mut().unwrap().n_layers_pop += 1;
+    }
+
+    fn draw_layout(&mut self, layout: &D2DTextLayout, pos: Point) {
+        let text_options = D2D1_DRAW_TEXT_OPTIONS_NONE;
+        // this is used for regions that don't have other colors set;
+        // we could be doing this elsewhere but here seems fine
+        let black_brush = self.solid_brush(Color::BLACK);
+
+        self.rt
+            .draw_text_layout(to_point2f(pos), &layout.layout, &black_brush, text_options);
+    }
+
+    /// Draw a text layout, masked to the given shape.
+    fn draw_layout_masked(
+        &mut self,
+        layout: &D2DTextLayout,
+        pos: Point,
+        mask: impl Shape,
+        fill_rule: FillRule,
+    ) {
+        let layer = match self.rt.create_layer(None) {
+            Ok(layer) => layer,
+            Err(e) => {
+                self.err = Err(e.into());
+                return;
+            }
+        };
+        let path = match path_from_shape(self.factory, true, mask, fill_rule) {
+            Ok(path) => path,
+            Err(e) => {
+                self.err = Err(e);
+                return;
+            }
+        };
+        self.rt.push_layer(&layer, Some(&path), 1.0, None);
+        self.draw_layout(layout, pos);
+        self.rt.pop_layer();
+    }
+
+    /// Direct2D has no sweep gradients, so we rasterize the gradient into a
+    /// bitmap covering the render target, and draw with a bitmap brush.
+    ///
+    /// The bitmap is rendered for the current transform, so the brush will
+    /// be blurry (or clipped) if it is used after the transform changes.
+    fn sweep_gradient(&mut self, sweep: &FixedSweepGradient) -> Result<Brush, Error> {
+        let size = self.rt.get_size();
+        let width = (size.width.ceil() as usize).max(1);
+        let height = (size.height.ceil() as usize).max(1);
+        let transform = self.current_transform();
+        let inverse = transform.inverse();
+        let mut buf = vec![0u8; width * height * 4];
+        for y in 0..height {
+            for x in 0..width {
+                let device_pt = Point::new(x as f64 + 0.5, y as f64 + 0.5);
+                let (r, g, b, a) = sweep.color_at(inverse * device_pt).as_rgba8();
+                let premul = |c: u8| ((c as u16 * a as u16 + 127) / 255) as u8;
+                let off = (y * width + x) * 4;
+                buf[off] = premul(r);
+                buf[off + 1] = premul(g);
+                buf[off + 2] = premul(b);
+                buf[off + 3] = a;
+            }
+        }
+        let bitmap = self.rt.create_bitmap(
+            width,
+            height,
+            &buf,
+            width * 4,
+            DXGI_FORMAT_R8G8B8A8_UNORM,
+            D2D1_ALPHA_MODE_PREMULTIPLIED,
+        )?;
+        let brush = self.rt.create_bitmap_brush(
+            &bitmap,
+            D2D1_EXTEND_MODE_CLAMP,
+            affine_to_matrix3x2f(inverse),
+        )?;
+        Ok(brush)
+    }
+
     // This is split out to unify error reporting, as there are lots of opportunities for
     // errors in resource creation.
-    fn blurred_rect_raw(
+    /// Draw a text shadow, from the blurred coverage of the outlines of a
+    /// layout's glyphs inside `rects`.
+    fn draw_text_shadow(
         &mut self,
-        rect: Rect,
+        outline: &BezPath,
+        rects: &[Rect],
+        pos: Point,
+        shadow: &ShadowStyle,
+    ) {
+        let size = util::size_for_blurred_shape_in_rects(outline, rects, shadow.blur);
+        let (width, height) = (size.width as usize, size.height as usize);
+        if width == 0 || height == 0 {
+            return;
+        }
+        let mut coverage = vec![0; width * height];
+        let rect =
+            util::compute_blurred_shape_in_rects(outline, rects, shadow.blur, width, &mut coverage);
+        let mut buf = Vec::with_capacity(width * height * 4);
+        for &a in &coverage {
+            buf.extend_from_slice(&[0, 0, 0, a]);
+        }
+        let rect = rect + pos.to_vec2() + shadow.offset;
+        let result = self
+            .make_image(width, height, &buf, ImageFormat::RgbaSeparate)
+            .and_then(|image| {
+                self.save_layer_with_mask(LayerMask::Image(&image, rect))?;
+                self.fill(rect, &shadow.color);
+                self.restore()
+            });
+        if let Err(e) = result {
+            self.err = Err(e);
+        }
+    }
+
+    fn blurred_shape_raw(
+        &mut self,
+        shape: impl Shape,
         blur_radius: f64,
         brush: Cow<Brush>,
     ) -> Result<(), Error> {
-        let rect_exp = rect.expand();
+        let rect_exp = shape.bounding_box().expand();
         let widthf = rect_exp.width() as f32;
         let heightf = rect_exp.height() as f32;
         // Note: we're being fairly dumb about choosing the bitmap size, not taking
@@ -440,15 +888,17 @@ impl<'a> D2DRenderContext<'a> {
             b: 0.0,
             a: 0.0,
         };
-        let draw_rect = rect_to_rectf(rect - rect_exp.origin().to_vec2());
+        let offset = Affine::translate(-rect_exp.origin().to_vec2());
+        let path = offset * shape.into_bez_path(BEZ_TOLERANCE);
+        let geom = path_from_shape(self.factory, true, path, FillRule::NonZero)?;
         unsafe {
             brt.BeginDraw();
             brt.Clear(&clear_color);
-            brt.FillRectangle(&draw_rect, brush.as_raw());
+            brt.fill_geometry(&geom, &brush);
             let mut tag1 = 0;
             let mut tag2 = 0;
             let hr = brt.EndDraw(&mut tag1, &mut tag2);
-            wrap_unit(hr)?;
+            wrap_unit(hr, "EndDraw")?;
         }
         // It might be slightly cleaner to create the effect on `brt`, but it should
         // be fine, as it's "compatible".
@@ -482,10 +932,12 @@ fn draw_image<'a>(
     src_rect: Option<Rect>,
     dst_rect: Rect,
     interp: InterpolationMode,
+    opacity: f32,
 ) {
     let interp = match interp {
-        InterpolationMode::NearestNeighbor => D2D1_BITMAP_INTERPOLATION_MODE_NEAREST_NEIGHBOR,
-        InterpolationMode::Bilinear => D2D1_BITMAP_INTERPOLATION_MODE_LINEAR,
+        InterpolationMode::NearestNeighbor => D2D1_INTERPOLATION_MODE_NEAREST_NEIGHBOR,
+        InterpolationMode::Bilinear => D2D1_INTERPOLATION_MODE_LINEAR,
+        InterpolationMode::Bicubic => D2D1_INTERPOLATION_MODE_HIGH_QUALITY_CUBIC,
     };
     let src_rect = match src_rect {
         Some(src_rect) => Some(rect_to_rectf(src_rect)),
@@ -494,7 +946,7 @@ fn draw_image<'a>(
     rt.draw_bitmap(
         &image,
         &rect_to_rectf(dst_rect),
-        1.0,
+        opacity,
         interp,
         src_rect.as_ref(),
     );
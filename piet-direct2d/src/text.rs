@@ -2,18 +2,22 @@
 
 mod lines;
 
+use std::cell::OnceCell;
 use std::convert::TryInto;
 use std::ops::{Range, RangeBounds};
+use std::sync::Arc;
 
 pub use d2d::{D2DDevice, D2DFactory, DeviceContext as D2DDeviceContext};
 pub use dwrite::DwriteFactory;
 use wio::wide::ToWide;
 
-use piet::kurbo::{Insets, Point, Rect, Size};
+use piet::kurbo::{BezPath, Insets, Point, Rect, Size, Vec2};
 use piet::util;
 use piet::{
-    Error, FontFamily, HitTestPoint, HitTestPosition, LineMetric, Text, TextAlignment,
-    TextAttribute, TextLayout, TextLayoutBuilder,
+    Affinity, Color, Error, FontFamily, FontMetrics, GlyphRun, HitTestPoint, HitTestPosition,
+    IntoLayoutStorage, LayoutStorage, LineMetric, LineSpacing, ShadowStyle, StrokeStyle, TabStops,
+    Text, TextAlignment, TextAttribute, TextDirection, TextLayout, TextLayoutBuilder,
+    TextOrientation, TextStorage, TruncationMode, UnderlineStyle,
 };
 
 use crate::conv;
@@ -28,19 +32,64 @@ pub struct D2DText {
 
 #[derive(Clone)]
 pub struct D2DTextLayout {
-    pub text: String,
+    pub text: Arc<dyn LayoutStorage>,
     // currently calculated on build
     line_metrics: Vec<LineMetric>,
     size: Size,
     /// insets that, when applied to our layout rect, generates our inking/image rect.
     inking_insets: Insets,
+    max_lines: Option<usize>,
+    /// whether this layout has vertical lines; DirectWrite reports our size
+    /// and hit test positions in the unrotated space, which is what we want.
+    vertical: bool,
+    /// ranges of the text with a background color; dwrite has no background
+    /// attribute, so we paint these before drawing the layout.
+    backgrounds: Vec<(Range<usize>, Color)>,
+    /// ranges of the text with a nonzero baseline shift, which we also
+    /// handle when drawing.
+    baseline_shifts: Vec<(Range<usize>, f64)>,
+    /// underlines with a style or color that dwrite can't draw, which we
+    /// draw ourselves, with their color.
+    underlines: Vec<(Range<usize>, UnderlineStyle, Color)>,
+    /// ranges of the text with a shadow, which we draw from the outlines of
+    /// the glyphs before drawing the layout.
+    shadows: Vec<(Range<usize>, ShadowStyle)>,
     pub layout: dwrite::TextLayout,
+    dwrite: DwriteFactory,
+    /// the glyph runs, made the first time they are asked for.
+    glyph_runs: OnceCell<Vec<GlyphRun>>,
 }
 
 pub struct D2DTextLayoutBuilder {
-    text: String,
+    text: Arc<dyn LayoutStorage>,
     layout: Result<dwrite::TextLayout, Error>,
     len_utf16: usize,
+    line_spacing: LineSpacing,
+    max_lines: Option<usize>,
+    truncation: TruncationMode,
+    direction: TextDirection,
+    orientation: TextOrientation,
+    background_color: Option<Color>,
+    backgrounds: Vec<(Range<usize>, Color)>,
+    baseline_shift: f64,
+    baseline_shifts: Vec<(Range<usize>, f64)>,
+    shadow: Option<ShadowStyle>,
+    shadows: Vec<(Range<usize>, ShadowStyle)>,
+    /// the default underline attributes, and the foreground color used for
+    /// underlines we draw ourselves.
+    underline_defaults: util::LayoutDefaults,
+    underlines: Vec<(Range<usize>, bool)>,
+    underline_colors: Vec<(Range<usize>, Color)>,
+    underline_styles: Vec<(Range<usize>, UnderlineStyle)>,
+    /// word spacing is not yet supported, and causes `build` to fail.
+    has_word_spacing: bool,
+    /// DirectWrite chooses its own line breaks, so custom break
+    /// opportunities also cause `build` to fail.
+    has_break_opportunities: bool,
+    /// the resolved names of each `FontFamilyList`'s primary family and its
+    /// fallbacks, which are set on the layout in `build`.
+    font_fallbacks: Vec<(String, Vec<String>)>,
+    dwrite: DwriteFactory,
     device: d2d::DeviceContext,
 }
 
@@ -76,17 +125,51 @@ impl Text for D2DText {
             .and_then(|fonts| fonts.font_family(family_name))
     }
 
-    fn new_text_layout(&mut self, text: &str) -> Self::TextLayoutBuilder {
+    fn font_families(&mut self) -> Vec<FontFamily> {
+        self.dwrite
+            .system_font_collection()
+            .map(|fonts| fonts.families())
+            .unwrap_or_default()
+    }
+
+    fn font_metrics(&mut self, family: &FontFamily, size: f64) -> Option<FontMetrics> {
+        self.dwrite
+            .system_font_collection()
+            .ok()?
+            .font_metrics(resolve_family_name(family), size)
+    }
+
+    fn new_text_layout(&mut self, text: impl IntoLayoutStorage) -> Self::TextLayoutBuilder {
         let width = f32::INFINITY;
-        let wide_str = text.to_wide();
+        let text = text.into_layout_storage();
+        let wide_str = text.as_str().to_wide();
         let layout = TextFormat::new(&self.dwrite, &[], util::DEFAULT_FONT_SIZE as f32)
             .and_then(|format| dwrite::TextLayout::new(&self.dwrite, format, width, &wide_str))
             .map_err(Into::into);
 
         D2DTextLayoutBuilder {
             layout,
-            text: text.to_owned(),
+            text: Arc::new(text),
             len_utf16: wide_str.len(),
+            line_spacing: LineSpacing::Default,
+            max_lines: None,
+            truncation: TruncationMode::Clip,
+            direction: TextDirection::default(),
+            orientation: TextOrientation::Horizontal,
+            background_color: None,
+            backgrounds: Vec::new(),
+            baseline_shift: 0.0,
+            baseline_shifts: Vec::new(),
+            shadow: None,
+            shadows: Vec::new(),
+            underline_defaults: Default::default(),
+            underlines: Vec::new(),
+            underline_colors: Vec::new(),
+            underline_styles: Vec::new(),
+            has_word_spacing: false,
+            has_break_opportunities: false,
+            font_fallbacks: Vec::new(),
+            dwrite: self.dwrite.clone(),
             device: self.device.clone(),
         }
     }
@@ -113,6 +196,51 @@ impl TextLayoutBuilder for D2DTextLayoutBuilder {
         self
     }
 
+    fn text_direction(mut self, direction: TextDirection) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    fn orientation(mut self, orientation: TextOrientation) -> Self {
+        self.orientation = orientation;
+        self
+    }
+
+    fn line_spacing(mut self, spacing: LineSpacing) -> Self {
+        self.line_spacing = spacing;
+        self
+    }
+
+    fn max_lines(mut self, max_lines: usize) -> Self {
+        self.max_lines = Some(max_lines.max(1));
+        self
+    }
+
+    fn truncate(mut self, mode: TruncationMode) -> Self {
+        self.truncation = mode;
+        self
+    }
+
+    fn tab_stops(mut self, stops: TabStops) -> Self {
+        // DirectWrite only supports uniformly spaced stops; for an explicit list
+        // we use the first stop, which is exact if the stops are evenly spaced.
+        let interval = match stops {
+            TabStops::Uniform(interval) => interval,
+            TabStops::Explicit(stops) => stops.first().copied().unwrap_or_default(),
+        };
+        if interval > 0.0 {
+            if let Ok(layout) = self.layout.as_mut() {
+                layout.set_incremental_tab_stop(interval);
+            }
+        }
+        self
+    }
+
+    fn break_opportunities(mut self, _breaks: impl FnOnce(&str) -> Vec<usize>) -> Self {
+        self.has_break_opportunities = true;
+        self
+    }
+
     fn default_attribute(mut self, attribute: impl Into<TextAttribute>) -> Self {
         self.add_attribute_shared(attribute.into(), None);
         self
@@ -123,7 +251,7 @@ impl TextLayoutBuilder for D2DTextLayoutBuilder {
         range: impl RangeBounds<usize>,
         attribute: impl Into<TextAttribute>,
     ) -> Self {
-        let range = util::resolve_range(range, self.text.len());
+        let range = util::resolve_range(range, self.text.as_str().len());
         let attribute = attribute.into();
 
         self.add_attribute_shared(attribute, Some(range));
@@ -131,8 +259,51 @@ impl TextLayoutBuilder for D2DTextLayoutBuilder {
     }
 
     fn build(self) -> Result<Self::Out, Error> {
-        let layout = self.layout?;
-        let line_metrics = lines::fetch_line_metrics(&self.text, &layout);
+        let vertical = self.orientation == TextOrientation::VerticalRightToLeft;
+        // we truncate with the max height, which doesn't limit vertical lines.
+        if self.has_word_spacing
+            || self.has_break_opportunities
+            || (vertical && self.max_lines.is_some())
+        {
+            return Err(Error::NotSupported);
+        }
+        let mut layout = self.layout?;
+        if !self.font_fallbacks.is_empty() {
+            layout.set_font_fallbacks(&self.dwrite, &self.font_fallbacks);
+        }
+        if vertical {
+            layout.set_vertical().map_err(|_| Error::NotSupported)?;
+            let line_height = layout.get_max_width();
+            fit_vertical_lines(&mut layout, line_height)?;
+        } else {
+            // DirectWrite doesn't detect the paragraph direction itself.
+            layout.set_reading_direction(self.direction.is_rtl(self.text.as_str()));
+        }
+        let underlines = resolve_custom_underlines(
+            self.text.as_str(),
+            &mut layout,
+            &self.underline_defaults,
+            self.underlines,
+            self.underline_colors,
+            self.underline_styles,
+        );
+        let mut line_metrics = lines::fetch_line_metrics(self.text.as_str(), &layout);
+        if self.line_spacing != LineSpacing::Default {
+            // DirectWrite's proportional spacing requires IDWriteTextLayout3,
+            // so we derive a uniform spacing from the natural metrics of the first line.
+            if let Some(first) = line_metrics.first() {
+                let (height, baseline) =
+                    util::resolve_line_spacing(self.line_spacing, first.height, first.baseline);
+                layout.set_line_spacing(height, baseline);
+                line_metrics = lines::fetch_line_metrics(self.text.as_str(), &layout);
+            }
+        }
+        if self.max_lines.is_some() {
+            let ellipsis = self.truncation == TruncationMode::EllipsisEnd;
+            layout.set_trimming(&self.dwrite, ellipsis);
+            line_metrics =
+                fetch_truncated_line_metrics(self.text.as_str(), &mut layout, self.max_lines)?;
+        }
         let text_metrics = layout.get_metrics();
         let overhang = layout.get_overhang_metrics();
 
@@ -147,12 +318,35 @@ impl TextLayoutBuilder for D2DTextLayoutBuilder {
             overhang_height - size.height,
         );
 
+        let backgrounds = util::resolve_spans(
+            self.background_color,
+            self.text.as_str().len(),
+            self.backgrounds,
+        );
+        let baseline_shifts = util::resolve_spans(
+            Some(self.baseline_shift),
+            self.text.as_str().len(),
+            self.baseline_shifts,
+        )
+        .into_iter()
+        .filter(|(_, shift)| *shift != 0.0)
+        .collect();
+        let shadows = util::resolve_spans(self.shadow, self.text.as_str().len(), self.shadows);
+
         Ok(D2DTextLayout {
             text: self.text,
             line_metrics,
             layout,
             size,
             inking_insets,
+            max_lines: self.max_lines,
+            vertical,
+            backgrounds,
+            baseline_shifts,
+            underlines,
+            shadows,
+            dwrite: self.dwrite,
+            glyph_runs: OnceCell::new(),
         })
     }
 }
@@ -160,14 +354,61 @@ impl TextLayoutBuilder for D2DTextLayoutBuilder {
 impl D2DTextLayoutBuilder {
     /// used for both range and default attributes
     fn add_attribute_shared(&mut self, attr: TextAttribute, range: Option<Range<usize>>) {
+        if let TextAttribute::BackgroundColor(color) = attr {
+            match range {
+                Some(range) => self.backgrounds.push((range, color)),
+                None => self.background_color = Some(color),
+            }
+            return;
+        }
+        if let TextAttribute::BaselineShift(shift) = attr {
+            match range {
+                Some(range) => self.baseline_shifts.push((range, shift)),
+                None => self.baseline_shift = shift,
+            }
+            return;
+        }
+        if let TextAttribute::Shadow(shadow) = attr {
+            match range {
+                Some(range) => self.shadows.push((range, shadow)),
+                None => self.shadow = Some(shadow),
+            }
+            return;
+        }
+        if let TextAttribute::WordSpacing(spacing) = attr {
+            self.has_word_spacing |= spacing != 0.0;
+            return;
+        }
+        // underlines are resolved in `build`, as a style or color may mean
+        // we have to draw them ourselves.
+        match (&attr, range.clone()) {
+            (TextAttribute::Underline(flag), Some(range)) => {
+                return self.underlines.push((range, *flag))
+            }
+            (TextAttribute::UnderlineColor(color), Some(range)) => {
+                return self.underline_colors.push((range, color.clone()))
+            }
+            (TextAttribute::UnderlineStyle(style), Some(range)) => {
+                return self.underline_styles.push((range, *style))
+            }
+            (TextAttribute::Underline(_), None)
+            | (TextAttribute::UnderlineColor(_), None)
+            | (TextAttribute::UnderlineStyle(_), None) => {
+                return self.underline_defaults.set(attr.clone());
+            }
+            (TextAttribute::ForegroundColor(_), None) => {
+                self.underline_defaults.set(attr.clone());
+            }
+            _ => (),
+        }
         if let Ok(layout) = self.layout.as_mut() {
             let (start, len) = match range {
                 Some(range) => {
-                    let start = util::count_utf16(&self.text[..range.start]);
-                    let len = if range.end == self.text.len() {
+                    let start = util::count_utf16(&self.text.as_str()[..range.start]);
+                    let len = if range.end == self.text.as_str().len() {
                         self.len_utf16
                     } else {
-                        util::count_utf16(&self.text[range])
+                        util::count_utf16(&self.text.as_str()[range])
                     };
                     (start, len)
                 }
@@ -179,16 +420,43 @@ impl D2DTextLayoutBuilder {
                     let family_name = resolve_family_name(&font);
                     layout.set_font_family(start, len, family_name);
                 }
+                TextAttribute::FontFamilies(families) => {
+                    let family_name = resolve_family_name(families.primary());
+                    layout.set_font_family(start, len, family_name);
+                    if !families.fallbacks().is_empty() {
+                        let fallbacks = families
+                            .fallbacks()
+                            .iter()
+                            .map(|family| resolve_family_name(family).to_owned())
+                            .collect();
+                        self.font_fallbacks
+                            .push((family_name.to_owned(), fallbacks));
+                    }
+                }
                 TextAttribute::Size(size) => layout.set_size(start, len, size as f32),
                 TextAttribute::Weight(weight) => layout.set_weight(start, len, weight),
                 TextAttribute::Italic(flag) => layout.set_italic(start, len, flag),
-                TextAttribute::Underline(flag) => layout.set_underline(start, len, flag),
+                TextAttribute::Strikethrough(flag) => layout.set_strikethrough(start, len, flag),
+                TextAttribute::LetterSpacing(spacing) => {
+                    layout.set_letter_spacing(start, len, spacing as f32)
+                }
+                TextAttribute::FontFeatures(features) => {
+                    layout.set_font_features(&self.dwrite, start, len, &features)
+                }
+                TextAttribute::Locale(locale) => layout.set_locale(start, len, &locale),
                 TextAttribute::ForegroundColor(color) => {
                     if let Ok(brush) = self.device.create_solid_color(conv::color_to_colorf(color))
                     {
                         layout.set_foregound_brush(start, len, brush)
                     }
                 }
+                TextAttribute::BackgroundColor(_)
+                | TextAttribute::BaselineShift(_)
+                | TextAttribute::Shadow(_)
+                | TextAttribute::WordSpacing(_)
+                | TextAttribute::Underline(_)
+                | TextAttribute::UnderlineColor(_)
+                | TextAttribute::UnderlineStyle(_) => unreachable!(),
             }
         }
     }
@@ -204,11 +472,18 @@ impl TextLayout for D2DTextLayout {
     }
 
     fn image_bounds(&self) -> Rect {
-        self.size.to_rect() + self.inking_insets
+        let bounds = self.size.to_rect() + self.inking_insets;
+        let bounds = self
+            .baseline_shift_rects()
+            .into_iter()
+            .fold(bounds, |bounds, (rect, shift)| {
+                bounds.union(rect - Vec2::new(0.0, shift))
+            });
+        util::bounds_with_shadows(bounds, self.shadows.iter().map(|(_, shadow)| shadow))
     }
 
     fn text(&self) -> &str {
-        &self.text
+        self.text.as_str()
     }
 
     /// given a new max width, update width of text layout to fit within the max width
@@ -216,16 +491,26 @@ impl TextLayout for D2DTextLayout {
     fn update_width(&mut self, new_width: impl Into<Option<f64>>) -> Result<(), Error> {
         let new_width = new_width.into().unwrap_or(std::f64::INFINITY);
 
-        self.layout.set_max_width(new_width)?;
-        self.line_metrics = lines::fetch_line_metrics(&self.text, &self.layout);
+        self.glyph_runs = OnceCell::new();
+        if self.vertical {
+            fit_vertical_lines(&mut self.layout, new_width)?;
+        } else {
+            self.layout.set_max_width(new_width)?;
+        }
+        self.line_metrics =
+            fetch_truncated_line_metrics(self.text.as_str(), &mut self.layout, self.max_lines)?;
 
         Ok(())
     }
 
     fn line_text(&self, line_number: usize) -> Option<&str> {
-        self.line_metrics
-            .get(line_number)
-            .map(|lm| &self.text[lm.start_offset..(lm.end_offset - lm.trailing_whitespace)])
+        self.line_metrics.get(line_number).map(|lm| {
+            &self.text.as_str()[lm.start_offset..(lm.end_offset - lm.trailing_whitespace)]
+        })
+    }
+
+    fn line_text_range(&self, line_number: usize) -> Option<Range<usize>> {
+        self.line_metrics.get(line_number).map(LineMetric::range)
     }
 
     fn line_metric(&self, line_number: usize) -> Option<LineMetric> {
@@ -238,24 +523,25 @@ impl TextLayout for D2DTextLayout {
 
     fn hit_test_point(&self, point: Point) -> HitTestPoint {
         // Before hit testing, need to convert point.y to have 0.0 at upper left corner (dwrite
-        // style) instead of at first line baseline.
-        let first_baseline = self
-            .line_metrics
-            .get(0)
-            .map(|lm| lm.baseline)
-            .unwrap_or(0.0);
+        // style) instead of at first line baseline. Vertical lines have no such offset.
+        let first_baseline = match self.line_metrics.get(0) {
+            Some(lm) if !self.vertical => lm.baseline,
+            _ => 0.0,
+        };
         let y = point.y + first_baseline;
 
         // lossy from f64 to f32, but shouldn't have too much impact
         let htp = self.layout.hit_test_point(point.x as f32, y as f32);
 
         // Round up to next grapheme cluster boundary if directwrite
-        // reports a trailing hit.
-        let text_position_16 = if htp.is_trailing_hit {
-            htp.metrics.text_position + htp.metrics.length
+        // reports a trailing hit; the position then belongs with the
+        // grapheme before it.
+        let (text_position_16, affinity) = if htp.is_trailing_hit {
+            let position = htp.metrics.text_position + htp.metrics.length;
+            (position as usize, Affinity::Upstream)
         } else {
-            htp.metrics.text_position
-        } as usize;
+            (htp.metrics.text_position as usize, Affinity::Downstream)
+        };
 
         // Convert text position from utf-16 code units to
         // utf-8 code units.
@@ -264,14 +550,16 @@ impl TextLayout for D2DTextLayout {
         //
         // TODO ask about text_position, it looks like windows returns last index;
         // can't use the text_position of last index from directwrite, it has an extra code unit.
-        let text_position = util::count_until_utf16(&self.text, text_position_16)
-            .unwrap_or_else(|| self.text.len());
+        let text_position = util::count_until_utf16(self.text.as_str(), text_position_16)
+            .unwrap_or_else(|| self.text.as_str().len())
+            .min(self.visible_end());
 
-        HitTestPoint::new(text_position, htp.is_inside)
+        HitTestPoint::new(text_position, htp.is_inside, affinity)
     }
 
     // Can panic if text position is not at a code point boundary, or if it's out of bounds.
     fn hit_test_text_position(&self, text_position: usize) -> Option<HitTestPosition> {
+        let text_position = text_position.min(self.visible_end());
         // Note: Directwrite will just return the line width if text position is
         // out of bounds. This is what want for piet; return line width for the last text position
         // (equal to line.len()). This is basically returning line width for the last cursor
@@ -279,7 +567,7 @@ impl TextLayout for D2DTextLayout {
 
         // Now convert the utf8 index to utf16.
         // This can panic;
-        let idx_16 = util::count_utf16(&self.text[0..text_position]);
+        let idx_16 = util::count_utf16(&self.text.as_str()[0..text_position]);
         let line = util::line_number_for_position(&self.line_metrics, text_position);
         // panic or Result are also fine options for dealing with overflow. Using Option here
         // because it's already present and convenient.
@@ -294,6 +582,225 @@ impl TextLayout for D2DTextLayout {
                 HitTestPosition::new(Point::new(hit.point_x as f64, hit.point_y as f64), line)
             })
     }
+
+    fn hit_test_text_position_with_affinity(
+        &self,
+        text_position: usize,
+        affinity: Affinity,
+    ) -> Option<HitTestPosition> {
+        let text_position = text_position.min(self.visible_end());
+        let text = self.text.as_str();
+        // an upstream position is the trailing edge of the previous grapheme,
+        // unless that is a newline.
+        let prev = match affinity {
+            Affinity::Upstream => util::prev_grapheme_offset(text, text_position)
+                .filter(|&prev| !text[prev..text_position].ends_with(&['\n', '\r'][..])),
+            Affinity::Downstream => None,
+        };
+        let prev = match prev {
+            Some(prev) => prev,
+            None => return self.hit_test_text_position(text_position),
+        };
+        let idx_16 = util::count_utf16(&text[0..prev]).try_into().ok()?;
+        let line = util::line_number_for_position(&self.line_metrics, prev);
+        self.layout.hit_test_text_position(idx_16, true).map(|hit| {
+            HitTestPosition::new(Point::new(hit.point_x as f64, hit.point_y as f64), line)
+        })
+    }
+
+    fn rects_for_range(&self, range: impl RangeBounds<usize>) -> Vec<Rect> {
+        let mut result = Vec::new();
+        for (line, range, newline) in
+            util::line_ranges(self.text.as_str(), &self.line_metrics, range)
+        {
+            let end = range.end;
+            let mut rects = self.text_range_rects(range);
+            rects.sort_by(|a, b| a.x0.partial_cmp(&b.x0).unwrap());
+            result.extend(rects);
+            if newline {
+                if let Some(hit) = self.hit_test_text_position(end) {
+                    let lm = &self.line_metrics[line];
+                    result.push(util::newline_rect(lm, hit.point.x));
+                }
+            }
+        }
+        result
+    }
+
+    fn glyph_runs(&self) -> &[GlyphRun] {
+        self.glyph_runs.get_or_init(|| {
+            let fonts = match self.dwrite.system_font_collection() {
+                Ok(fonts) => fonts,
+                Err(_) => return Vec::new(),
+            };
+            let text = self.text.as_str();
+            let mut runs = self.layout.glyph_runs(&fonts);
+            for glyph in runs.iter_mut().flat_map(|run| run.glyphs.iter_mut()) {
+                glyph.cluster = util::count_until_utf16(text, glyph.cluster).unwrap_or(text.len());
+            }
+            runs
+        })
+    }
+
+    fn outline(&self) -> BezPath {
+        self.layout.outline()
+    }
+}
+
+impl D2DTextLayout {
+    /// Returns the rects (relative to the layout's origin) covering the text
+    /// with a nonzero baseline shift, before shifting, and the shift of each.
+    pub(crate) fn baseline_shift_rects(&self) -> Vec<(Rect, f64)> {
+        let mut result = Vec::new();
+        for (range, shift) in &self.baseline_shifts {
+            for rect in self.text_range_rects(range.clone()) {
+                result.push((rect, *shift));
+            }
+        }
+        result
+    }
+
+    /// Returns the shadows of this layout, with the rects (relative to the
+    /// layout's origin) of the text that casts each.
+    pub(crate) fn shadow_rects(&self) -> Vec<(Vec<Rect>, &ShadowStyle)> {
+        self.shadows
+            .iter()
+            .map(|(range, shadow)| (self.text_range_rects(range.clone()), shadow))
+            .filter(|(rects, _)| !rects.is_empty())
+            .collect()
+    }
+
+    /// Returns the rects (relative to the layout's origin) and colors of the
+    /// backgrounds of this layout.
+    pub(crate) fn background_rects(&self) -> Vec<(Rect, Color)> {
+        let mut result = Vec::new();
+        for (range, color) in &self.backgrounds {
+            for rect in self.text_range_rects(range.clone()) {
+                result.push((rect, color.clone()));
+            }
+        }
+        result
+    }
+
+    /// The underlines that we draw ourselves, relative to the layout's origin,
+    /// as paths to be stroked with their style, with their colors and widths.
+    ///
+    /// We don't have access to the font's underline metrics here, so these
+    /// are approximated from the metrics of the line.
+    pub(crate) fn underline_paths(&self) -> Vec<(BezPath, StrokeStyle, Color, f64)> {
+        let mut result = Vec::new();
+        for (range, style, color) in &self.underlines {
+            for rect in self.text_range_rects(range.clone()) {
+                let line = self
+                    .line_metrics
+                    .iter()
+                    .rev()
+                    .find(|lm| lm.y_offset <= rect.y0 + 0.5)
+                    .or_else(|| self.line_metrics.first());
+                let lm = match line {
+                    Some(lm) => lm,
+                    None => continue,
+                };
+                let descent = lm.height - lm.baseline;
+                let thickness = (descent / 6.0).max(1.0);
+                let y = lm.y_offset + lm.baseline + descent / 3.0;
+                let (path, stroke) =
+                    util::underline_geometry(*style, rect.x0, rect.x1, y, thickness);
+                result.push((path, stroke, color.clone(), thickness));
+            }
+        }
+        result
+    }
+
+    /// The rects covering a range of the text, from the top to the bottom of
+    /// each line. There may be more than one rect per line, if the range
+    /// includes runs of different directions.
+    fn text_range_rects(&self, range: Range<usize>) -> Vec<Rect> {
+        let start = util::count_utf16(&self.text.as_str()[..range.start]);
+        let len = util::count_utf16(&self.text.as_str()[range]);
+        self.layout
+            .hit_test_text_range(start, len)
+            .into_iter()
+            .map(|metrics| {
+                Rect::from_origin_size(
+                    (metrics.left as f64, metrics.top as f64),
+                    (metrics.width as f64, metrics.height as f64),
+                )
+            })
+            .collect()
+    }
+
+    /// The end of the text that is not hidden by `max_lines`.
+    fn visible_end(&self) -> usize {
+        self.line_metrics
+            .last()
+            .map(|lm| lm.end_offset)
+            .unwrap_or_else(|| self.text.as_str().len())
+    }
+}
+
+/// Set dwrite's underline on the ranges it can draw itself, and return those
+/// with a style or color, which it can't.
+fn resolve_custom_underlines(
+    text: &str,
+    layout: &mut dwrite::TextLayout,
+    defaults: &util::LayoutDefaults,
+    underlines: Vec<(Range<usize>, bool)>,
+    colors: Vec<(Range<usize>, Color)>,
+    styles: Vec<(Range<usize>, UnderlineStyle)>,
+) -> Vec<(Range<usize>, UnderlineStyle, Color)> {
+    let mut custom = Vec::new();
+    for (range, style, color) in
+        util::resolve_underlines(defaults, text.len(), underlines, colors, styles)
+    {
+        if style == UnderlineStyle::Solid && color.is_none() {
+            let start = util::count_utf16(&text[..range.start]);
+            let len = util::count_utf16(&text[range]);
+            layout.set_underline(start, len, true);
+        } else {
+            let color = color.unwrap_or_else(|| defaults.fg_color.clone());
+            custom.push((range, style, color));
+        }
+    }
+    custom
+}
+
+/// Fetch the line metrics for a layout, limiting it to `max_lines` if necessary.
+///
+/// The layout's max height is set to the bottom of the last visible line, so
+/// that DirectWrite trims the text that follows it.
+/// Lay out vertical lines of at most `line_height`, and shrink the layout's
+/// width to fit them.
+///
+/// Vertical lines are placed from the right edge of the layout, so we first
+/// lay them out in a layout with no width, and then make it as wide as they are.
+fn fit_vertical_lines(layout: &mut dwrite::TextLayout, line_height: f64) -> Result<(), Error> {
+    layout.set_max_height(line_height)?;
+    layout.set_max_width(0.0)?;
+    let width = layout.get_metrics().width as f64;
+    layout.set_max_width(width)?;
+    Ok(())
+}
+
+fn fetch_truncated_line_metrics(
+    text: &str,
+    layout: &mut dwrite::TextLayout,
+    max_lines: Option<usize>,
+) -> Result<Vec<LineMetric>, Error> {
+    let max_lines = match max_lines {
+        Some(max_lines) => max_lines,
+        None => return Ok(lines::fetch_line_metrics(text, layout)),
+    };
+
+    layout.set_max_height(f64::INFINITY)?;
+    let mut line_metrics = lines::fetch_line_metrics(text, layout);
+    if line_metrics.len() > max_lines {
+        let last = &line_metrics[max_lines - 1];
+        layout.set_max_height(last.y_offset + last.height)?;
+        line_metrics = lines::fetch_line_metrics(text, layout);
+        line_metrics.truncate(max_lines);
+    }
+    Ok(line_metrics)
 }
 
 //  this is not especially robust, but all of these are preinstalled on win 7+
@@ -309,6 +816,7 @@ fn resolve_family_name(family: &FontFamily) -> &str {
 #[cfg(test)]
 mod test {
     use super::*;
+    use piet::FontFamilyList;
 
     macro_rules! assert_close {
         ($val:expr, $target:expr, $tolerance:expr) => {{
@@ -886,6 +1394,282 @@ mod test {
         assert_eq!(pt.is_inside, false);
     }
 
+    #[test]
+    fn trailing_whitespace_width() {
+        let mut text = D2DText::new_for_test();
+        let font = text.font_family("Segoe UI").unwrap();
+        let layout = text
+            .new_text_layout("hello   ")
+            .font(font.clone(), 12.0)
+            .build()
+            .unwrap();
+        let x = |pos| layout.hit_test_text_position(pos).unwrap().point.x;
+        let line = layout.line_metric(0).unwrap();
+        assert_eq!(line.trailing_whitespace, 3);
+        assert_close!(line.trailing_whitespace_width, x(8) - x(5), 0.01);
+        let spaces = line.trailing_whitespace_width;
+
+        let wrapped = text
+            .new_text_layout("hello   world")
+            .font(font, 12.0)
+            .max_width(40.0)
+            .build()
+            .unwrap();
+        assert_eq!(wrapped.line_count(), 2);
+        let line_0 = wrapped.line_metric(0).unwrap();
+        assert_eq!(line_0.trailing_whitespace, 3);
+        assert_close!(line_0.trailing_whitespace_width, spaces, 0.01);
+        assert_close!(
+            wrapped.line_metric(1).unwrap().trailing_whitespace_width,
+            0.0,
+            0.01
+        );
+    }
+
+    #[test]
+    fn line_text_range() {
+        let mut text = D2DText::new_for_test();
+        let layout = text
+            .new_text_layout("piet text\r\nis the best text\n")
+            .font(text.font_family("Segoe UI").unwrap(), 12.0)
+            .max_width(60.0)
+            .build()
+            .unwrap();
+        assert!(layout.line_count() > 3);
+        assert_eq!(layout.line_text_range(layout.line_count()), None);
+        // the hard break, with its \r\n, ends a line
+        assert!((0..layout.line_count()).any(|i| layout.line_text_range(i).unwrap().end == 11));
+
+        let mut end = 0;
+        for line in 0..layout.line_count() {
+            let range = layout.line_text_range(line).unwrap();
+            assert_eq!(range.start, end);
+            assert_eq!(range, layout.line_metric(line).unwrap().range());
+            end = range.end;
+        }
+        assert_eq!(end, layout.text().len());
+    }
+
+    #[test]
+    fn font_metrics() {
+        let mut text = D2DText::new_for_test();
+        let family = text.font_family("Segoe UI").unwrap();
+        let metrics = text.font_metrics(&family, 20.0).unwrap();
+        assert!(metrics.ascent > metrics.cap_height);
+        assert!(metrics.cap_height > metrics.x_height);
+        assert!(metrics.x_height > 0.0);
+        assert!(metrics.descent > 0.0);
+        assert!(metrics.underline_position > 0.0);
+        assert!(metrics.strikeout_position < 0.0);
+        assert!(metrics.underline_thickness > 0.0);
+
+        let small = text.font_metrics(&family, 10.0).unwrap();
+        assert_close!(small.cap_height * 2.0, metrics.cap_height, 0.01);
+        let generic = text.font_metrics(&FontFamily::SYSTEM_UI, 20.0).unwrap();
+        assert_eq!(generic, metrics);
+    }
+
+    #[test]
+    fn break_opportunities_unsupported() {
+        let mut text = D2DText::new_for_test();
+        let layout = text
+            .new_text_layout("/usr/local/share")
+            .break_opportunities(|_| vec![5])
+            .build();
+        assert!(matches!(layout, Err(Error::NotSupported)));
+    }
+
+    #[test]
+    #[cfg(feature = "send-layouts")]
+    fn layout_from_another_thread() {
+        let layout = std::thread::spawn(|| {
+            let mut text = D2DText::new_for_test();
+            text.new_text_layout("sent between threads")
+                .font(FontFamily::SYSTEM_UI, 16.0)
+                .build()
+                .unwrap()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(layout.line_count(), 1);
+        let end = layout.hit_test_text_position(layout.text().len()).unwrap();
+        assert_close!(end.point.x, layout.size().width, 1.0);
+    }
+
+    #[test]
+    fn font_families() {
+        let mut text = D2DText::new_for_test();
+        let families = text.font_families();
+        assert!(families.iter().any(|family| family.name() == "Segoe UI"));
+        for pair in families.windows(2) {
+            assert!(pair[0].name() <= pair[1].name());
+        }
+        for family in families {
+            let found = text.font_family(family.name()).unwrap();
+            assert_eq!(found.name(), family.name());
+        }
+    }
+
+    #[test]
+    fn locale_selects_fallback() {
+        // the same characters are drawn with different fonts in Chinese
+        // and in Japanese
+        let mut text = D2DText::new_for_test();
+        let mut font_for = |locale| {
+            let layout = text.new_text_layout("直骨").locale(locale).build().unwrap();
+            layout.glyph_runs()[0].font.clone()
+        };
+        let chinese = font_for("zh-CN");
+        let japanese = font_for("ja-JP");
+        assert_ne!(chinese, japanese);
+
+        // a range attribute applies only to its range
+        let layout = text
+            .new_text_layout("直骨 直骨")
+            .locale("zh-CN")
+            .range_attribute(7.., TextAttribute::Locale("ja-JP".into()))
+            .build()
+            .unwrap();
+        let runs = layout.glyph_runs();
+        assert_eq!(runs[0].font, chinese);
+        assert_eq!(runs.last().unwrap().font, japanese);
+    }
+
+    #[test]
+    fn font_fallbacks() {
+        let mut text = D2DText::new_for_test();
+        let families = FontFamilyList::new(text.font_family("Segoe UI").unwrap())
+            .with(text.font_family("MS Mincho").unwrap());
+        let layout = text
+            .new_text_layout("piet 日本語")
+            .default_attribute(families)
+            .build()
+            .unwrap();
+        let runs = layout.glyph_runs();
+        assert_eq!(runs[0].font.name(), "Segoe UI");
+        let fallback = runs.iter().find(|run| run.glyphs[0].cluster == 5).unwrap();
+        assert_eq!(fallback.font.name(), "MS Mincho");
+    }
+
+    #[test]
+    fn vertical_lines() {
+        let mut text = D2DText::new_for_test();
+        let layout = text
+            .new_text_layout("縦書きの文章です")
+            .font(text.font_family("MS Mincho").unwrap(), 20.0)
+            .max_width(45.0)
+            .orientation(TextOrientation::VerticalRightToLeft)
+            .build()
+            .unwrap();
+        // two characters fit in each line
+        assert_eq!(layout.line_count(), 4);
+        let size = layout.size();
+        assert!(size.height <= 45.0);
+        assert!(size.width > size.height);
+
+        // the text starts at the top right, and ends at the bottom left
+        let first = layout.hit_test_point(Point::new(size.width - 5.0, 5.0));
+        assert_eq!(first.idx, 0);
+        let last = layout.text().len() - "す".len();
+        let last = layout.hit_test_text_position(last).unwrap();
+        assert!(last.point.x < size.width / 4.0);
+        assert!(last.point.y > 10.0);
+    }
+
+    #[test]
+    fn hit_test_affinity() {
+        let mut text = D2DText::new_for_test();
+        let layout = text
+            .new_text_layout("piet text is the best text\nmore")
+            .font(text.font_family("Segoe UI").unwrap(), 12.0)
+            .max_width(60.0)
+            .build()
+            .unwrap();
+        assert!(layout.line_count() > 2);
+        let line_0 = layout.line_metric(0).unwrap();
+        let line_1 = layout.line_metric(1).unwrap();
+
+        let hit = layout.hit_test_point(Point::new(200.0, line_0.y_offset + 1.0));
+        assert_eq!(hit.idx, line_1.start_offset);
+        assert_eq!(hit.affinity, Affinity::Upstream);
+
+        let upstream = layout
+            .hit_test_text_position_with_affinity(line_1.start_offset, Affinity::Upstream)
+            .unwrap();
+        let downstream = layout
+            .hit_test_text_position_with_affinity(line_1.start_offset, Affinity::Downstream)
+            .unwrap();
+        assert_eq!(upstream.line, 0);
+        assert_eq!(downstream.line, 1);
+        assert!(upstream.point.x > downstream.point.x);
+    }
+
+    #[test]
+    fn glyph_runs() {
+        let mut text = D2DText::new_for_test();
+        let layout = text
+            .new_text_layout("piet text\nis best")
+            .font(text.font_family("Segoe UI").unwrap(), 16.0)
+            .build()
+            .unwrap();
+        let runs = layout.glyph_runs();
+        assert!(!runs.is_empty());
+        assert_eq!(runs[0].font.name(), "Segoe UI");
+        assert_eq!(runs[0].font_size, 16.0);
+        assert_eq!(runs[0].glyphs[0].cluster, 0);
+        assert!(runs.iter().any(|run| run.glyphs[0].cluster == 10));
+
+        let line_0 = layout.line_metric(0).unwrap();
+        let baseline = line_0.y_offset + line_0.baseline;
+        for glyph in &runs[0].glyphs {
+            assert!((glyph.position.y - baseline).abs() < 0.01);
+        }
+        for pair in runs[0].glyphs.windows(2) {
+            assert!(pair[0].cluster < pair[1].cluster);
+            assert!(pair[0].position.x < pair[1].position.x);
+        }
+    }
+
+    #[test]
+    fn outline() {
+        use piet::kurbo::Shape;
+        let mut text = D2DText::new_for_test();
+        let layout = text
+            .new_text_layout("piet text\nis best")
+            .font(text.font_family("Segoe UI").unwrap(), 16.0)
+            .build()
+            .unwrap();
+        let outline = layout.outline();
+        assert!(!outline.elements().is_empty());
+        // the outlines are inside the layout's inked area
+        let bounds = outline.bounding_box();
+        let image_bounds = layout.image_bounds().inflate(1.0, 1.0);
+        assert_eq!(image_bounds.union(bounds), image_bounds);
+        assert!(bounds.y1 > layout.line_metric(1).unwrap().y_offset);
+    }
+
+    #[test]
+    fn rects_for_range() {
+        let mut text = D2DText::new_for_test();
+        let layout = text
+            .new_text_layout("piet text\nis the best")
+            .font(text.font_family("Segoe UI").unwrap(), 12.0)
+            .build()
+            .unwrap();
+        let x = |pos| layout.hit_test_text_position(pos).unwrap().point.x;
+        let line_1 = layout.line_metric(1).unwrap();
+
+        let rects = layout.rects_for_range(2..13);
+        assert_eq!(rects.len(), 3);
+        assert_close!(rects[0].x0, x(2), 0.01);
+        assert_close!(rects[0].x1, x(9), 0.01);
+        // the newline
+        assert_close!(rects[1].x0, x(9), 0.01);
+        assert_close!(rects[2].x0, 0.0, 0.01);
+        assert_close!(rects[2].y0, line_1.y_offset, 0.01);
+        assert_close!(rects[2].height(), line_1.height, 0.01);
+    }
+
     #[test]
     fn missing_font_is_missing() {
         let mut text = D2DText::new_for_test();
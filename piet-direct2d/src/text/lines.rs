@@ -6,6 +6,7 @@ pub(crate) fn fetch_line_metrics(text: &str, layout: &dwrite::TextLayout) -> Vec
     layout.get_line_metrics(&mut raw_line_metrics);
 
     let mut offset_utf8 = 0;
+    let mut offset_utf16 = 0;
     let mut y_offset = 0.0;
 
     let mut out = Vec::with_capacity(raw_line_metrics.len());
@@ -19,12 +20,19 @@ pub(crate) fn fetch_line_metrics(text: &str, layout: &dwrite::TextLayout) -> Vec
         );
 
         let end_offset = offset_utf8 + non_ws_len_8 + ws_len_8;
+        let ws_start_16 = offset_utf16 + raw_metric.length - raw_metric.trailingWhitespaceLength;
+        let trailing_whitespace_width = trailing_whitespace_width(
+            layout,
+            ws_start_16,
+            raw_metric.trailingWhitespaceLength - raw_metric.newlineLength,
+        );
 
         #[allow(deprecated)]
         let metric = LineMetric {
             start_offset: offset_utf8,
             end_offset,
             trailing_whitespace: ws_len_8,
+            trailing_whitespace_width,
             height: raw_metric.height as f64,
             y_offset,
             baseline: raw_metric.baseline as f64,
@@ -32,11 +40,29 @@ pub(crate) fn fetch_line_metrics(text: &str, layout: &dwrite::TextLayout) -> Vec
 
         y_offset += metric.height;
         offset_utf8 = end_offset;
+        offset_utf16 += raw_metric.length;
         out.push(metric);
     }
     out
 }
 
+/// The width of `len_16` utf-16 code units of whitespace starting at `start_16`.
+///
+/// The dwrite line metrics only include the width of the whole layout, so we
+/// measure from the leading edge of the first character to the trailing edge of
+/// the last one, which is on the same line even if the line is wrapped.
+fn trailing_whitespace_width(layout: &dwrite::TextLayout, start_16: u32, len_16: u32) -> f64 {
+    if len_16 == 0 {
+        return 0.0;
+    }
+    let start = layout.hit_test_text_position(start_16, false);
+    let end = layout.hit_test_text_position(start_16 + len_16 - 1, true);
+    match (start, end) {
+        (Some(start), Some(end)) => (end.point_x - start.point_x).abs() as f64,
+        _ => 0.0,
+    }
+}
+
 // handles the weirdness where we're dealing with lengths but count_until_utf16 deals
 // with offsets
 fn len_and_ws_len_utf8(s: &str, total_len_16: u32, ws_len_16: u32) -> (usize, usize) {
@@ -55,7 +81,7 @@ mod test {
     fn test_metrics_with_width(
         width: f64,
         expected: Vec<LineMetric>,
-        input: &str,
+        input: &'static str,
         text_layout: &mut D2DText,
         font: &FontFamily,
         font_size: f64,
@@ -66,7 +92,11 @@ mod test {
             .font(font.clone(), font_size)
             .build()
             .unwrap();
-        let line_metrics = fetch_line_metrics(input, &layout.layout);
+        let mut line_metrics = fetch_line_metrics(input, &layout.layout);
+        // the width of the whitespace depends on the font, so it isn't compared
+        for lm in &mut line_metrics {
+            lm.trailing_whitespace_width = 0.0;
+        }
 
         println!("{:#?}", layout.line_metrics);
         assert_eq!(line_metrics, expected);
@@ -98,6 +128,7 @@ mod test {
                 y_offset: 0.0,
                 baseline: 12.949_218_75,
                 height: 15.960_937_5,
+                ..Default::default()
             },
             LineMetric {
                 start_offset: 5,
@@ -106,6 +137,7 @@ mod test {
                 y_offset: 15.960_937_5,
                 baseline: 12.949_218_75,
                 height: 15.960_937_5,
+                ..Default::default()
             },
             LineMetric {
                 start_offset: 10,
@@ -114,6 +146,7 @@ mod test {
                 y_offset: 31.921_875,
                 baseline: 12.949_218_75,
                 height: 15.960_937_5,
+                ..Default::default()
             },
             LineMetric {
                 start_offset: 15,
@@ -122,6 +155,7 @@ mod test {
                 y_offset: 47.882_812_5,
                 baseline: 12.949_218_75,
                 height: 15.960_937_5,
+                ..Default::default()
             },
         ];
 
@@ -134,6 +168,7 @@ mod test {
                 y_offset: 0.0,
                 baseline: 12.949_218_75,
                 height: 15.960_937_5,
+                ..Default::default()
             },
             LineMetric {
                 start_offset: 10,
@@ -142,6 +177,7 @@ mod test {
                 y_offset: 15.960_937_5,
                 baseline: 12.949_218_75,
                 height: 15.960_937_5,
+                ..Default::default()
             },
         ];
 
@@ -153,6 +189,7 @@ mod test {
             y_offset: 0.0,
             baseline: 12.949_218_75,
             height: 15.960_937_5,
+            ..Default::default()
         }];
 
         let empty_input = "";
@@ -163,6 +200,7 @@ mod test {
             y_offset: 0.0,
             baseline: 12.949_218_75,
             height: 15.960_937_5,
+            ..Default::default()
         }];
 
         // setup dwrite layout
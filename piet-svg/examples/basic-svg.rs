@@ -9,8 +9,9 @@ fn main() {
         .nth(1)
         .and_then(|s| s.parse::<usize>().ok())
         .unwrap_or(0);
-    let mut piet = piet_svg::RenderContext::new();
-    samples::get(test_picture_number).draw(&mut piet).unwrap();
+    let sample = samples::get(test_picture_number);
+    let mut piet = piet_svg::RenderContext::new(sample.size());
+    sample.draw(&mut piet).unwrap();
     piet.finish().unwrap();
     piet.write(io::stdout()).unwrap();
 }
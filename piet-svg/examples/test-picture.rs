@@ -0,0 +1,40 @@
+//! Render the samples to PNG files, by rasterizing the SVG documents.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use piet::kurbo::{Rect, Size};
+use piet::{samples, ImageFormat, RenderContext};
+
+const HIDPI: f64 = 2.0;
+const FILE_PREFIX: &str = "svg-test-";
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    samples::samples_main(run_sample, FILE_PREFIX)
+}
+
+fn run_sample(idx: usize, base_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let sample = samples::get(idx);
+    let size = sample.size();
+
+    let file_name = format!("{}{}.png", FILE_PREFIX, idx);
+    let path = base_dir.join(file_name);
+
+    // the samples are drawn at HIDPI times their size, as on the other backends
+    let user_size = Size::new(size.width / HIDPI, size.height / HIDPI);
+    let mut piet_context = piet_svg::RenderContext::new(size).with_view_box(user_size.to_rect());
+    sample.draw(&mut piet_context)?;
+    piet_context.finish()?;
+    let image = piet_context.capture_image_area(Rect::from_origin_size((0.0, 0.0), user_size))?;
+    let buf = piet_context.to_image_buf(&image, ImageFormat::RgbaSeparate)?;
+
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, buf.width() as u32, buf.height() as u32);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()?
+        .write_image_data(buf.raw_pixels())
+        .map_err(Into::into)
+}
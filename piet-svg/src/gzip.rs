@@ -0,0 +1,89 @@
+//! Gzip compression, for writing `.svgz` files.
+
+use std::io::{self, Write};
+
+use deflate::write::DeflateEncoder;
+
+/// How hard gzip-compressed output is compressed.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Compression {
+    /// Compress quickly, at some cost in size.
+    Fast,
+    /// A balance of speed and size. This is the default.
+    #[default]
+    Default,
+    /// Compress to the smallest size, more slowly.
+    Best,
+}
+
+/// A writer that gzip-compresses what is written to it.
+pub(crate) struct GzipWriter<W: Write> {
+    encoder: DeflateEncoder<W>,
+    crc: crc32fast::Hasher,
+    /// The length of the uncompressed data, modulo 2^32.
+    len: u32,
+}
+
+impl<W: Write> GzipWriter<W> {
+    /// Write the gzip header to `writer`, and wrap it.
+    pub(crate) fn new(mut writer: W, compression: Compression) -> io::Result<Self> {
+        let (level, extra_flags) = match compression {
+            Compression::Fast => (deflate::Compression::Fast, 4),
+            Compression::Default => (deflate::Compression::Default, 0),
+            Compression::Best => (deflate::Compression::Best, 2),
+        };
+        // deflate, with no name, comment or modification time, from an
+        // unknown OS
+        writer.write_all(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, extra_flags, 255])?;
+        Ok(GzipWriter {
+            encoder: DeflateEncoder::new(writer, level),
+            crc: crc32fast::Hasher::new(),
+            len: 0,
+        })
+    }
+
+    /// Write the rest of the compressed data and the gzip trailer, and
+    /// return the writer.
+    pub(crate) fn finish(self) -> io::Result<W> {
+        let mut writer = self.encoder.finish()?;
+        writer.write_all(&self.crc.finalize().to_le_bytes())?;
+        writer.write_all(&self.len.to_le_bytes())?;
+        Ok(writer)
+    }
+}
+
+impl<W: Write> Write for GzipWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.encoder.write(buf)?;
+        self.crc.update(&buf[..written]);
+        self.len = self.len.wrapping_add(written as u32);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.encoder.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gzip_format() {
+        let data = b"<svg>hello, hello, hello</svg>\n".repeat(10);
+        for &compression in &[Compression::Fast, Compression::Default, Compression::Best] {
+            let mut gzip = GzipWriter::new(Vec::new(), compression).unwrap();
+            gzip.write_all(&data).unwrap();
+            let out = gzip.finish().unwrap();
+            assert_eq!(out[..4], [0x1f, 0x8b, 8, 0]);
+            assert!(out.len() < data.len());
+            let (deflated, trailer) = out[10..].split_at(out.len() - 18);
+            let inflated = miniz_oxide::inflate::decompress_to_vec(deflated).unwrap();
+            assert_eq!(inflated, data);
+            let crc = crc32fast::hash(&data);
+            assert_eq!(trailer[..4], crc.to_le_bytes());
+            assert_eq!(trailer[4..], (data.len() as u32).to_le_bytes());
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! Images for the svg backend, embedded as PNG data URIs or written to
+//! PNG files.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use piet::kurbo::Size;
+use piet::{Error, ImageBuf, ImageFormat};
+
+/// How images are referenced by the `<image>` elements that draw them.
+#[derive(Clone, Debug, Default)]
+pub enum ImageMode {
+    /// Embed each image in the document, as a PNG `data:` URI. This is the
+    /// default.
+    #[default]
+    Embed,
+    /// Write each image to a PNG file in `dir`, named by a hash of its
+    /// contents, and reference it by that path.
+    ///
+    /// The directory is created if it doesn't exist. A relative `dir` is
+    /// both where the files are written, relative to the current
+    /// directory, and the path in the `href`, so it should also be relative
+    /// to where the document is saved.
+    External { dir: PathBuf },
+}
+
+/// An SVG image.
+#[derive(Clone)]
+pub struct Image {
+    buf: ImageBuf,
+    png: Arc<[u8]>,
+    /// A hash of the PNG data, for naming external files.
+    hash: u64,
+}
+
+impl Image {
+    pub(crate) fn new(buf: ImageBuf) -> Result<Image, Error> {
+        let png = encode_png(&buf)?;
+        let mut hasher = DefaultHasher::new();
+        hasher.write(&png);
+        Ok(Image {
+            buf,
+            png: png.into(),
+            hash: hasher.finish(),
+        })
+    }
+
+    pub(crate) fn buf(&self) -> &ImageBuf {
+        &self.buf
+    }
+
+    pub(crate) fn png(&self) -> &[u8] {
+        &self.png
+    }
+
+    /// The name of the file the image is written to in external mode.
+    pub(crate) fn file_name(&self) -> String {
+        format!("{:016x}.png", self.hash)
+    }
+
+    /// The image as a `data:` URI.
+    pub(crate) fn data_uri(&self) -> String {
+        format!("data:image/png;base64,{}", base64(&self.png))
+    }
+}
+
+impl piet::Image for Image {
+    fn size(&self) -> Size {
+        self.buf.size()
+    }
+}
+
+/// Encode an image as PNG, with separate alpha.
+fn encode_png(buf: &ImageBuf) -> Result<Vec<u8>, Error> {
+    let rgba = buf.to_format(ImageFormat::RgbaSeparate);
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, buf.width() as u32, buf.height() as u32);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(rgba.raw_pixels()))
+        .map_err(|e| Error::BackendError(Box::new(e)))?;
+    Ok(png)
+}
+
+/// Standard base64, with padding.
+pub(crate) fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len() / 3 * 4 + 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+}
@@ -1,21 +1,45 @@
 //! SVG output support for piet
 //!
-//! Text and images are unimplemented and will always return errors.
+//! Images are embedded as PNG data URIs, or written to PNG files; see
+//! [`ImageMode`](enum.ImageMode.html). Text is drawn in the viewer's fonts,
+//! or in embedded copies of the fonts loaded with `load_font`; see
+//! [`embed_fonts`](struct.RenderContext.html#method.embed_fonts). It can
+//! also be drawn as paths; see [`TextMode`](enum.TextMode.html). Styles
+//! that are used repeatedly can be set with classes rather than attributes;
+//! see [`StyleMode`](enum.StyleMode.html).
+//!
+//! Drawing that only viewers of SVG can do, such as strokes that keep their
+//! width when zoomed, is in
+//! [`SvgRenderContextExt`](trait.SvgRenderContextExt.html).
+//!
+//! With the `raster` feature, `capture_image_area` rasterizes the document
+//! drawn so far, except for its text and filters.
 
 #![deny(clippy::trivially_copy_pass_by_ref)]
 
+mod gzip;
+mod image;
+#[cfg(feature = "raster")]
+mod raster;
 mod text;
 
 use std::borrow::Cow;
-use std::{io, mem};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write as _};
+use std::path::PathBuf;
+use std::{fs, mem};
 
-use piet::kurbo::{Affine, Point, Rect, Shape};
+use piet::kurbo::{Affine, PathEl, Point, Rect, Shape, Size};
 use piet::{
-    Color, Error, FixedGradient, ImageFormat, InterpolationMode, IntoBrush, LineCap, LineJoin,
-    StrokeStyle,
+    util, BlendMode, Color, Error, FixedGradient, GradientExtend, ImageBuf, ImageFormat,
+    InterpolationMode, IntoBrush, LayerMask, LineCap, LineJoin, StrokeStyle, TileMode,
 };
+use svg::node::element::{Definitions, Description, Element, Group, Title};
 use svg::node::Node;
 
+pub use crate::gzip::Compression;
+pub use crate::image::{Image, ImageMode};
 pub use crate::text::{Text, TextLayout};
 
 type Result<T> = std::result::Result<T, Error>;
@@ -24,29 +48,718 @@ type Result<T> = std::result::Result<T, Error>;
 pub struct RenderContext {
     stack: Vec<State>,
     state: State,
-    doc: svg::Document,
+    /// The clip paths and gradients.
+    defs: Definitions,
+    /// The group the drawing goes into when there is no clip or transform.
+    root: Group,
+    /// The groups of the clips and transforms that haven't been restored,
+    /// innermost last; drawing goes into the innermost group.
+    groups: Vec<OpenGroup>,
     next_id: u64,
     text: Text,
+    opacity: util::OpacityStack,
+    image_mode: ImageMode,
+    /// The files written in external image mode.
+    image_files: HashSet<PathBuf>,
+    /// The blur filters, by the bits of their standard deviations and
+    /// margins.
+    blur_filters: HashMap<(u64, u64), Id>,
+    /// The ids of the gradients, by their markup without the id, so that
+    /// identical ones are shared.
+    shared_defs: HashMap<String, Id>,
+    embed_fonts: bool,
+    text_mode: TextMode,
+    style_mode: StyleMode,
+    /// The classes of the paint properties used so far, by their
+    /// declarations; `None` for those used once, which have no class.
+    style_classes: HashMap<String, Option<String>>,
+    /// The rules of the classes, in the order they were made.
+    style_rules: Vec<String>,
+    /// The lowercase names of the families whose fonts are embedded.
+    embedded_fonts: HashSet<String>,
+    /// The first error since the last `status` or `finish`.
+    error: Option<Error>,
+    /// Where the drawing is written as it goes, if it's streamed.
+    stream: Option<Stream>,
+    size: Size,
+    unit: Unit,
+    /// The area of user space shown, if it isn't the size.
+    view_box: Option<Rect>,
+    precision: Precision,
+    title: Option<String>,
+    description: Option<String>,
+    /// Extra attributes of the root element, in the order they were added.
+    root_attributes: Vec<(String, String)>,
+}
+
+/// A group that the drawing goes into until it's restored.
+struct OpenGroup {
+    group: Group,
+    /// The transform of the group's content.
+    xf: Affine,
+    kind: GroupKind,
+}
+
+/// What an open group does to its content.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum GroupKind {
+    Transform,
+    Clip,
+    Blend,
+}
+
+/// The unit of the width and height of a document.
+///
+/// With the default view box, one unit of user space is one of these.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Unit {
+    /// CSS pixels, 96 to the inch. This is the default.
+    #[default]
+    Px,
+    /// Points, 72 to the inch.
+    Pt,
+    /// Picas, 6 to the inch.
+    Pc,
+    /// Millimeters.
+    Mm,
+    /// Centimeters.
+    Cm,
+    /// Inches.
+    In,
+}
+
+impl Unit {
+    fn suffix(self) -> &'static str {
+        match self {
+            Unit::Px => "",
+            Unit::Pt => "pt",
+            Unit::Pc => "pc",
+            Unit::Mm => "mm",
+            Unit::Cm => "cm",
+            Unit::In => "in",
+        }
+    }
+}
+
+/// How text is drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum TextMode {
+    /// As `<text>` elements, which the viewer draws with its fonts. This is
+    /// the default.
+    #[default]
+    Text,
+    /// As `<path>` elements with the outlines of the glyphs, one for each run
+    /// of text in a color, so the drawing doesn't depend on the viewer's
+    /// fonts.
+    ///
+    /// Only text in TrueType fonts loaded with `load_font` has outlines, so
+    /// lines with any other text are still drawn as `<text>` elements.
+    Paths,
+}
+
+/// How the fill, stroke and opacity of shapes and text are set.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum StyleMode {
+    /// With presentation attributes on each element. This is the default.
+    #[default]
+    Inline,
+    /// With classes in a `<style>` element, for the styles that are used
+    /// more than once, which makes documents with many shapes in a few
+    /// styles smaller. Each class gets a generated name, such as `c3`.
+    ///
+    /// Gradient fills and strokes are still `url()` references to their
+    /// definitions.
+    Classes,
+}
+
+/// The output of a streaming [`RenderContext`].
+///
+/// [`RenderContext`]: struct.RenderContext.html
+struct Stream {
+    writer: io::BufWriter<Output>,
+    /// Whether the start tag of the document has been written.
+    started: bool,
+    /// The number of clip groups whose start tags have been written.
+    groups: usize,
+    /// The number of class rules that have been written.
+    style_rules: usize,
+}
+
+/// Where a stream is written, compressed or not.
+enum Output {
+    Plain(Box<dyn io::Write>),
+    Gzip(Box<gzip::GzipWriter<Box<dyn io::Write>>>),
+}
+
+impl Output {
+    /// Write the end of any compressed data, and flush.
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Output::Plain(mut writer) => writer.flush(),
+            Output::Gzip(gzip) => gzip.finish()?.flush(),
+        }
+    }
+}
+
+impl io::Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(writer) => writer.write(buf),
+            Output::Gzip(gzip) => gzip.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(writer) => writer.flush(),
+            Output::Gzip(gzip) => gzip.flush(),
+        }
+    }
 }
 
 impl RenderContext {
-    /// Construct an empty `RenderContext`
-    #[allow(clippy::new_without_default)]
-    pub fn new() -> Self {
+    /// Construct an empty `RenderContext` for a document of the given size,
+    /// in pixels unless other units are set.
+    pub fn new(size: Size) -> Self {
         Self {
             stack: Vec::new(),
             state: State::default(),
-            doc: svg::Document::new(),
+            defs: Definitions::new(),
+            root: Group::new(),
+            groups: Vec::new(),
             next_id: 0,
             text: Text::new(),
+            opacity: util::OpacityStack::new(),
+            image_mode: ImageMode::default(),
+            image_files: HashSet::new(),
+            blur_filters: HashMap::new(),
+            shared_defs: HashMap::new(),
+            embed_fonts: false,
+            text_mode: TextMode::default(),
+            style_mode: StyleMode::default(),
+            style_classes: HashMap::new(),
+            style_rules: Vec::new(),
+            embedded_fonts: HashSet::new(),
+            error: None,
+            stream: None,
+            size,
+            unit: Unit::default(),
+            view_box: None,
+            precision: Precision::default(),
+            title: None,
+            description: None,
+            root_attributes: Vec::new(),
         }
     }
 
+    /// Set the units of the document's size.
+    pub fn with_units(mut self, unit: Unit) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    /// Set the area of user space that is shown, scaled to fill the
+    /// document; by default, it is the rectangle from the origin to the
+    /// size.
+    pub fn with_view_box(mut self, view_box: Rect) -> Self {
+        self.view_box = Some(view_box);
+        self
+    }
+
+    /// Set the size of the document, in its units.
+    ///
+    /// A streaming `RenderContext` writes its size when drawing starts, so
+    /// after that this has no effect. The default view box follows the size.
+    pub fn set_size(&mut self, size: Size) {
+        self.size = size;
+    }
+
+    /// Construct a `RenderContext` that writes the drawing to `writer` as it
+    /// goes, rather than building the document in memory.
+    ///
+    /// The start of the document is written when drawing starts, and each
+    /// element as it is drawn, after any clip paths and gradients it uses;
+    /// `finish` writes the end of the document and flushes the writer.
+    /// Writing is buffered, and errors are reported by the next call to
+    /// `status` or `finish`. Anything drawn after `finish` is discarded.
+    pub fn new_streaming(size: Size, writer: impl io::Write + 'static) -> Self {
+        let writer: Box<dyn io::Write> = Box::new(writer);
+        RenderContext::new(size).with_stream(Output::Plain(writer))
+    }
+
+    /// Construct a streaming `RenderContext` that writes the drawing to
+    /// `writer` gzip-compressed, as an `.svgz` file.
+    ///
+    /// This is like [`new_streaming`], but the gzip header is written
+    /// straight away, and `finish` also writes the end of the compressed
+    /// data.
+    ///
+    /// [`new_streaming`]: #method.new_streaming
+    pub fn new_streaming_compressed(
+        size: Size,
+        writer: impl io::Write + 'static,
+        compression: Compression,
+    ) -> Self {
+        let writer: Box<dyn io::Write> = Box::new(writer);
+        let ctx = RenderContext::new(size);
+        match gzip::GzipWriter::new(writer, compression) {
+            Ok(gzip) => ctx.with_stream(Output::Gzip(Box::new(gzip))),
+            Err(e) => {
+                let mut ctx = ctx.with_stream(Output::Plain(Box::new(io::sink())));
+                ctx.check_written(Err(e));
+                ctx
+            }
+        }
+    }
+
+    fn with_stream(mut self, output: Output) -> Self {
+        self.stream = Some(Stream {
+            writer: io::BufWriter::new(output),
+            started: false,
+            groups: 0,
+            style_rules: 0,
+        });
+        self
+    }
+
+    /// Set how images are referenced; they are embedded by default.
+    ///
+    /// In [`ImageMode::External`], failing to write an image is reported by
+    /// the next call to `status` or `finish`, and the image isn't drawn.
+    ///
+    /// [`ImageMode::External`]: enum.ImageMode.html#variant.External
+    pub fn set_image_mode(&mut self, mode: ImageMode) {
+        self.image_mode = mode;
+    }
+
+    /// Set whether the fonts loaded with `load_font` are embedded in the
+    /// document, so that it doesn't depend on the viewer having them.
+    ///
+    /// Each font that drawn text uses is embedded once, when the text is
+    /// drawn, as an `@font-face` rule in a `<style>` element with the font
+    /// data as a `data:` URI. This is off by default, as fonts can be large.
+    pub fn embed_fonts(&mut self, embed: bool) {
+        self.embed_fonts = embed;
+    }
+
+    /// Set the number of decimal places that coordinates, transforms and
+    /// dimensions are written with; the default is 3.
+    ///
+    /// Numbers are rounded to the nearest multiple of the precision. The
+    /// dimensions of rectangles and images are the distances between their
+    /// rounded edges, so shapes that share an edge still do.
+    pub fn set_precision(&mut self, decimal_places: u8) {
+        self.precision = Precision(decimal_places);
+    }
+
+    /// Set how text is drawn; it is drawn as `<text>` elements by default.
+    ///
+    /// This only changes the drawing: text is measured and hit tested in
+    /// the same way in either mode.
+    pub fn text_mode(&mut self, mode: TextMode) {
+        self.text_mode = mode;
+    }
+
+    /// Set how the fill, stroke and opacity of shapes and text are set;
+    /// they are attributes of each element by default.
+    ///
+    /// A style only gets a class once it's used again, so changing the
+    /// mode only affects what's drawn afterwards.
+    pub fn style_mode(&mut self, mode: StyleMode) {
+        self.style_mode = mode;
+    }
+
+    /// Set the title of the document, written as a `<title>` element.
+    ///
+    /// The title and description are the first children of the root
+    /// element, whenever they are set. A streaming `RenderContext` writes
+    /// them with its start tag, when drawing starts, so after that they
+    /// have no effect.
+    pub fn set_title(&mut self, title: &str) {
+        self.title = Some(title.to_owned());
+    }
+
+    /// Set the description of the document, written as a `<desc>` element
+    /// after the title.
+    pub fn set_description(&mut self, description: &str) {
+        self.description = Some(description.to_owned());
+    }
+
+    /// Add an attribute to the root element, such as a namespace
+    /// declaration for metadata, replacing any attribute of the same name.
+    ///
+    /// Like the title, a streaming `RenderContext` writes the root
+    /// attributes when drawing starts.
+    pub fn add_root_attribute(&mut self, name: &str, value: &str) {
+        self.root_attributes.retain(|(n, _)| n != name);
+        self.root_attributes
+            .push((name.to_owned(), value.to_owned()));
+    }
+
     /// Write graphics rendered so far to an `std::io::Write` impl, such as `std::fs::File`
     ///
     /// Additional rendering can be done afterwards.
+    ///
+    /// A streaming `RenderContext` has already written its drawing, so this
+    /// returns an error.
     pub fn write(&self, writer: impl io::Write) -> io::Result<()> {
-        svg::write(writer, &self.doc)
+        if self.stream.is_some() {
+            let msg = "the drawing has been streamed";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+        // close the groups still in effect
+        let mut content = self.root.clone();
+        let mut inner: Option<Group> = None;
+        for open in self.groups.iter().rev() {
+            let mut group = open.group.clone();
+            if let Some(inner) = inner.take() {
+                group.append(inner);
+            }
+            inner = Some(group);
+        }
+        if let Some(inner) = inner {
+            content.append(inner);
+        }
+        let mut doc = self.document();
+        for child in self.metadata() {
+            doc = doc.add(child);
+        }
+        let mut defs = self.defs.clone();
+        if !self.style_rules.is_empty() {
+            defs.append(style_element(&self.style_rules));
+        }
+        let doc = doc.add(defs).add(content);
+        svg::write(writer, &doc)
+    }
+
+    /// Write graphics rendered so far gzip-compressed, as an `.svgz` file.
+    ///
+    /// The compressed data is that of [`write`], which has the same errors.
+    ///
+    /// [`write`]: #method.write
+    pub fn write_compressed(
+        &self,
+        writer: impl io::Write,
+        compression: Compression,
+    ) -> io::Result<()> {
+        let mut gzip = gzip::GzipWriter::new(writer, compression)?;
+        self.write(&mut gzip)?;
+        gzip.finish()?.flush()
+    }
+
+    /// The `<title>` and `<desc>` elements, those that are set.
+    fn metadata(&self) -> Vec<Element> {
+        let title = self.title.as_ref().map(|title| {
+            Title::new()
+                .add(svg::node::Text::new(text::escape(title)))
+                .get_inner()
+                .clone()
+        });
+        let description = self.description.as_ref().map(|description| {
+            Description::new()
+                .add(svg::node::Text::new(text::escape(description)))
+                .get_inner()
+                .clone()
+        });
+        title.into_iter().chain(description).collect()
+    }
+
+    /// The root element, without any content.
+    fn document(&self) -> svg::Document {
+        let unit = self.unit.suffix();
+        let view_box = self.view_box.unwrap_or_else(|| self.size.to_rect());
+        let precision = self.precision;
+        let view_box = format!(
+            "{} {} {} {}",
+            precision.num(view_box.x0),
+            precision.num(view_box.y0),
+            precision.span(view_box.x0, view_box.x1),
+            precision.span(view_box.y0, view_box.y1)
+        );
+        let mut doc = svg::Document::new()
+            .set(
+                "width",
+                format!("{}{}", precision.num(self.size.width), unit),
+            )
+            .set(
+                "height",
+                format!("{}{}", precision.num(self.size.height), unit),
+            )
+            .set("viewBox", view_box);
+        for (name, value) in &self.root_attributes {
+            doc = doc.set(name.as_str(), text::escape(value));
+        }
+        doc
+    }
+
+    /// The group that drawing goes into.
+    fn content(&mut self) -> &mut Group {
+        match self.groups.last_mut() {
+            Some(open) => &mut open.group,
+            None => &mut self.root,
+        }
+    }
+
+    /// The transform of the content of the innermost group.
+    fn group_xf(&self) -> Affine {
+        self.groups.last().map(|open| open.xf).unwrap_or_default()
+    }
+
+    /// Before drawing, open a group with the current transform, unless the
+    /// innermost group already has it.
+    fn sync_transform(&mut self) {
+        if self.group_xf() == self.state.xf {
+            return;
+        }
+        // a transform group opened since the last save is replaced, rather
+        // than nesting another one in it
+        let saved = self.stack.last().map(|state| state.groups).unwrap_or(0);
+        if self.groups.len() > saved && self.groups.last().unwrap().kind == GroupKind::Transform {
+            self.close_group();
+        }
+        if self.group_xf() != self.state.xf {
+            let xf = self.group_xf().inverse() * self.state.xf;
+            self.groups.push(OpenGroup {
+                group: Group::new().set("transform", self.precision.xf(xf)),
+                xf: self.state.xf,
+                kind: GroupKind::Transform,
+            });
+        }
+        self.state.groups = self.groups.len();
+    }
+
+    /// Close the innermost group, adding it to its parent.
+    fn close_group(&mut self) {
+        let open = self.groups.pop().unwrap();
+        match &mut self.stream {
+            // only a group that has been started has to be ended
+            Some(stream) if stream.groups > self.groups.len() => {
+                stream.groups = self.groups.len();
+                let written = writeln!(stream.writer, "</g>");
+                self.check_written(written);
+            }
+            Some(_) => {}
+            None => {
+                if !open.group.get_inner().get_children().is_empty() {
+                    self.content().append(open.group);
+                }
+            }
+        }
+    }
+
+    /// When streaming, write what has been drawn since the last flush: the
+    /// new definitions, the start tags of new groups, and then the new
+    /// content, which is all in the innermost group.
+    fn flush(&mut self) {
+        let start = match &self.stream {
+            Some(stream) if !stream.started => Some(start_tag(self.document().get_inner())),
+            Some(_) => None,
+            None => return,
+        };
+        let metadata = match start {
+            Some(_) => self.metadata(),
+            None => Vec::new(),
+        };
+        let stream = self.stream.as_mut().unwrap();
+        let mut written = Ok(());
+        if let Some(start) = start {
+            stream.started = true;
+            written = writeln!(stream.writer, "{}", start);
+            for child in metadata {
+                written = written.and_then(|_| writeln!(stream.writer, "{}", child));
+            }
+        }
+        if stream.style_rules < self.style_rules.len() {
+            let rules = &self.style_rules[stream.style_rules..];
+            self.defs.append(style_element(rules));
+            stream.style_rules = self.style_rules.len();
+        }
+        if !self.defs.get_inner().get_children().is_empty() {
+            let defs = mem::replace(&mut self.defs, Definitions::new());
+            written = written.and_then(|_| writeln!(stream.writer, "{}", defs));
+        }
+        for open in &self.groups[stream.groups..] {
+            let tag = start_tag(open.group.get_inner());
+            written = written.and_then(|_| writeln!(stream.writer, "{}", tag));
+        }
+        stream.groups = self.groups.len();
+        // the start tag has been written, so the group can be emptied
+        // without keeping its attributes
+        let content = match self.groups.last_mut() {
+            Some(open) => &mut open.group,
+            None => &mut self.root,
+        };
+        let content = mem::replace(content, Group::new());
+        for child in content.get_inner().get_children() {
+            written = written.and_then(|_| writeln!(stream.writer, "{}", child));
+        }
+        self.check_written(written);
+    }
+
+    /// Record a failure to write to the stream.
+    fn check_written(&mut self, written: io::Result<()>) {
+        if let Err(e) = written {
+            self.error.get_or_insert(Error::BackendError(Box::new(e)));
+        }
+    }
+
+    fn clip_with_rule(&mut self, shape: impl Shape, rule: Option<&str>) {
+        let bounds = util::intersect_clip_bounds(
+            self.state.clip_bounds,
+            shape.bounding_box(),
+            self.state.xf,
+        );
+        self.state.clip_bounds = Some(bounds);
+        // the clip path is in the space of the group it's applied to
+        self.sync_transform();
+        let id = self.new_id();
+        let mut clip = svg::node::element::ClipPath::new().set("id", id);
+        let attrs = Attrs {
+            clip_rule: rule,
+            ..Attrs::default()
+        };
+        add_shape(&mut clip, shape, &attrs, self.precision);
+        self.defs.append(clip);
+        // the drawing until the next restore goes into a group with the
+        // clip, inside the groups of any earlier clips and transforms
+        self.groups.push(OpenGroup {
+            group: Group::new().set("clip-path", format!("url(#{})", id.to_string())),
+            xf: self.state.xf,
+            kind: GroupKind::Clip,
+        });
+        self.state.groups = self.groups.len();
+        self.flush();
+    }
+
+    /// The `opacity` attribute for drawing, if it isn't 1.
+    fn opacity(&self) -> Option<f64> {
+        Some(self.opacity.current()).filter(|&alpha| alpha < 1.0)
+    }
+
+    /// The `href` of an image, writing it to a file first in external mode.
+    fn image_href(&mut self, image: &Image) -> Option<String> {
+        let dir = match &self.image_mode {
+            ImageMode::Embed => return Some(image.data_uri()),
+            ImageMode::External { dir } => dir,
+        };
+        let path = dir.join(image.file_name());
+        let href = path.to_string_lossy().replace('\\', "/");
+        if !self.image_files.contains(&path) {
+            let written = fs::create_dir_all(dir).and_then(|_| fs::write(&path, image.png()));
+            if let Err(e) = written {
+                self.error.get_or_insert(Error::BackendError(Box::new(e)));
+                return None;
+            }
+            self.image_files.insert(path);
+        }
+        Some(href)
+    }
+
+    /// The filter for blurring a shape with the given bounds.
+    fn blur_filter(&mut self, blur_radius: f64, bounds: Rect) -> Id {
+        // the raster backends blur with a Gaussian with a standard
+        // deviation of radius / sqrt(2), integrated exactly for rects
+        let std_dev = blur_radius * std::f64::consts::FRAC_1_SQRT_2;
+        // the filter region is the bounding box with a margin of a fraction
+        // of its size; the blur spreads about three standard deviations, and
+        // the fraction is rounded up to a power of two so filters are shared
+        let spread = 3.0 * std_dev;
+        let fraction = (spread / bounds.width()).max(spread / bounds.height());
+        let mut margin = 0.5;
+        while margin < fraction {
+            margin *= 2.0;
+        }
+        let key = (std_dev.to_bits(), margin.to_bits());
+        if let Some(&id) = self.blur_filters.get(&key) {
+            return id;
+        }
+        let id = self.new_id();
+        let mut blur = Element::new("feGaussianBlur");
+        blur.assign("stdDeviation", self.precision.num(std_dev));
+        let filter = svg::node::element::Filter::new()
+            .set("id", id)
+            .set("x", format!("{}%", -margin * 100.0))
+            .set("y", format!("{}%", -margin * 100.0))
+            .set("width", format!("{}%", (1.0 + 2.0 * margin) * 100.0))
+            .set("height", format!("{}%", (1.0 + 2.0 * margin) * 100.0))
+            .add(blur);
+        self.defs.append(filter);
+        self.blur_filters.insert(key, id);
+        id
+    }
+
+    /// The id of a definition, which is added unless an identical one
+    /// already has been.
+    fn shared_def(&mut self, mut def: impl Node) -> Id {
+        let markup = def.to_string();
+        if let Some(&id) = self.shared_defs.get(&markup) {
+            return id;
+        }
+        let id = self.new_id();
+        def.assign("id", id);
+        self.defs.append(def);
+        self.shared_defs.insert(markup, id);
+        id
+    }
+
+    /// Draw a shape into the group that drawing goes into.
+    fn add_shape(&mut self, shape: impl Shape, mut attrs: Attrs) {
+        let precision = self.precision;
+        attrs.class = self.style_class(&attrs);
+        add_shape(self.content(), shape, &attrs, precision);
+    }
+
+    /// In class style mode, the class that sets the paint properties of
+    /// `attrs`, once they've been used before; the first element with them
+    /// sets them with attributes, so unique ones stay inline.
+    fn style_class(&mut self, attrs: &Attrs) -> Option<String> {
+        if self.style_mode != StyleMode::Classes {
+            return None;
+        }
+        let declarations: Vec<String> = attrs
+            .paint(self.precision)
+            .into_iter()
+            .map(|(name, value)| format!("{}: {}", name, value))
+            .collect();
+        let next = self.style_rules.len();
+        match self.style_classes.entry(declarations.join("; ")) {
+            Entry::Occupied(mut entry) => {
+                if entry.get().is_none() {
+                    let class = format!("c{}", next);
+                    let rule = format!(".{} {{ {} }}", class, entry.key());
+                    self.style_rules.push(rule);
+                    entry.insert(Some(class));
+                }
+                entry.get().clone()
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(None);
+                None
+            }
+        }
+    }
+
+    /// Stroke a shape, with a width in the viewer's pixels if it's
+    /// `non_scaling`.
+    fn stroke_with(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+        non_scaling: bool,
+    ) {
+        self.sync_transform();
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        let opacity = self.opacity();
+        let attrs = Attrs {
+            stroke: Some((brush.into_owned(), width, style)),
+            opacity,
+            non_scaling_stroke: non_scaling,
+            ..Attrs::default()
+        };
+        self.add_shape(shape, attrs);
+        self.flush();
     }
 
     fn new_id(&mut self) -> Id {
@@ -65,7 +778,10 @@ impl piet::RenderContext for RenderContext {
     type Image = Image;
 
     fn status(&mut self) -> Result<()> {
-        Ok(())
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
     }
 
     fn clear(&mut self, color: Color) {
@@ -74,10 +790,13 @@ impl piet::RenderContext for RenderContext {
             .set("height", "100%")
             .set("fill", fmt_color(&color))
             .set("fill-opacity", fmt_opacity(&color));
-        if let Some(id) = self.state.clip {
-            rect.assign("clip-path", format!("url(#{})", id.to_string()));
+        // the canvas is cleared regardless of the transform
+        let xf = self.group_xf();
+        if xf != Affine::default() {
+            rect.assign("transform", self.precision.xf(xf.inverse()));
         }
-        self.doc.append(rect);
+        self.content().append(rect);
+        self.flush();
     }
 
     fn solid_brush(&mut self, color: Color) -> Brush {
@@ -87,16 +806,18 @@ impl piet::RenderContext for RenderContext {
     }
 
     fn gradient(&mut self, gradient: impl Into<FixedGradient>) -> Result<Brush> {
-        let id = self.new_id();
-        match gradient.into() {
+        // gradients are in the user space of the shapes they fill, which is
+        // inside the group with the transform when they're drawn, so they
+        // follow the transform at fill time as on the raster backends
+        let id = match util::resolve_color_interpolation(gradient.into()) {
             FixedGradient::Linear(x) => {
                 let mut gradient = svg::node::element::LinearGradient::new()
                     .set("gradientUnits", "userSpaceOnUse")
-                    .set("id", id)
-                    .set("x1", x.start.x)
-                    .set("y1", x.start.y)
-                    .set("x2", x.end.x)
-                    .set("y2", x.end.y);
+                    .set("x1", self.precision.num(x.start.x))
+                    .set("y1", self.precision.num(x.start.y))
+                    .set("x2", self.precision.num(x.end.x))
+                    .set("y2", self.precision.num(x.end.y))
+                    .set("spreadMethod", fmt_extend(x.extend));
                 for stop in x.stops {
                     gradient.append(
                         svg::node::element::Stop::new()
@@ -105,17 +826,28 @@ impl piet::RenderContext for RenderContext {
                             .set("stop-opacity", fmt_opacity(&stop.color)),
                     );
                 }
-                self.doc.append(gradient);
+                self.shared_def(gradient)
             }
             FixedGradient::Radial(x) => {
+                // SVG gradients are circles; ellipses are stretched ones
+                let (x, transform) = x.to_circular();
+                // the focal point must be in the circle, as SVG 1.1 viewers
+                // would move it there anyway
+                let mut focus = x.origin_offset;
+                if focus.hypot() > x.radii.x {
+                    focus *= x.radii.x / focus.hypot();
+                }
                 let mut gradient = svg::node::element::RadialGradient::new()
                     .set("gradientUnits", "userSpaceOnUse")
-                    .set("id", id)
-                    .set("cx", x.center.x)
-                    .set("cy", x.center.y)
-                    .set("fx", x.center.x + x.origin_offset.x)
-                    .set("fy", x.center.y + x.origin_offset.y)
-                    .set("r", x.radius);
+                    .set("cx", self.precision.num(x.center.x))
+                    .set("cy", self.precision.num(x.center.y))
+                    .set("fx", self.precision.num(x.center.x + focus.x))
+                    .set("fy", self.precision.num(x.center.y + focus.y))
+                    .set("r", self.precision.num(x.radii.x))
+                    .set("spreadMethod", fmt_extend(x.extend));
+                if transform != Affine::default() {
+                    gradient = gradient.set("gradientTransform", self.precision.xf(transform));
+                }
                 for stop in x.stops {
                     gradient.append(
                         svg::node::element::Stop::new()
@@ -124,70 +856,70 @@ impl piet::RenderContext for RenderContext {
                             .set("stop-opacity", fmt_opacity(&stop.color)),
                     );
                 }
-                self.doc.append(gradient);
+                self.shared_def(gradient)
             }
-        }
+            // SVG has no sweep gradients.
+            FixedGradient::Sweep(_) => return Err(Error::NotSupported),
+        };
         Ok(Brush {
             kind: BrushKind::Ref(id),
         })
     }
 
+    fn make_image_brush(
+        &mut self,
+        _image: &Image,
+        _tile: TileMode,
+        _transform: Affine,
+    ) -> Result<Brush> {
+        Err(Error::NotSupported)
+    }
+
     fn fill(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
+        self.sync_transform();
         let brush = brush.make_brush(self, || shape.bounding_box());
-        add_shape(
-            &mut self.doc,
-            shape,
-            &Attrs {
-                xf: self.state.xf,
-                clip: self.state.clip,
-                fill: Some((brush.into_owned(), None)),
-                ..Attrs::default()
-            },
-        );
+        let opacity = self.opacity();
+        let attrs = Attrs {
+            fill: Some((brush.into_owned(), None)),
+            opacity,
+            ..Attrs::default()
+        };
+        self.add_shape(shape, attrs);
+        self.flush();
     }
 
     fn fill_even_odd(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
+        self.sync_transform();
         let brush = brush.make_brush(self, || shape.bounding_box());
-        add_shape(
-            &mut self.doc,
-            shape,
-            &Attrs {
-                xf: self.state.xf,
-                clip: self.state.clip,
-                fill: Some((brush.into_owned(), Some("evenodd"))),
-                ..Attrs::default()
-            },
-        );
+        let opacity = self.opacity();
+        let attrs = Attrs {
+            fill: Some((brush.into_owned(), Some("evenodd"))),
+            opacity,
+            ..Attrs::default()
+        };
+        self.add_shape(shape, attrs);
+        self.flush();
     }
 
     fn clip(&mut self, shape: impl Shape) {
-        let id = self.new_id();
-        let mut clip = svg::node::element::ClipPath::new().set("id", id);
-        add_shape(
-            &mut clip,
-            shape,
-            &Attrs {
-                xf: self.state.xf,
-                clip: self.state.clip,
-                ..Attrs::default()
-            },
-        );
-        self.doc.append(clip);
-        self.state.clip = Some(id);
+        self.clip_with_rule(shape, None);
+    }
+
+    fn clip_even_odd(&mut self, shape: impl Shape) {
+        self.clip_with_rule(shape, Some("evenodd"));
     }
 
     fn stroke(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, width: f64) {
+        self.sync_transform();
         let brush = brush.make_brush(self, || shape.bounding_box());
-        add_shape(
-            &mut self.doc,
-            shape,
-            &Attrs {
-                xf: self.state.xf,
-                clip: self.state.clip,
-                stroke: Some((brush.into_owned(), width, &StrokeStyle::new())),
-                ..Attrs::default()
-            },
-        );
+        let opacity = self.opacity();
+        let attrs = Attrs {
+            stroke: Some((brush.into_owned(), width, &StrokeStyle::new())),
+            opacity,
+            ..Attrs::default()
+        };
+        self.add_shape(shape, attrs);
+        self.flush();
     }
 
     fn stroke_styled(
@@ -197,25 +929,79 @@ impl piet::RenderContext for RenderContext {
         width: f64,
         style: &StrokeStyle,
     ) {
-        let brush = brush.make_brush(self, || shape.bounding_box());
-        add_shape(
-            &mut self.doc,
-            shape,
-            &Attrs {
-                xf: self.state.xf,
-                clip: self.state.clip,
-                stroke: Some((brush.into_owned(), width, style)),
-                ..Attrs::default()
-            },
-        );
+        if let Some(mode) = style.snap_mode {
+            let transform = self.state.xf;
+            let (path, width) = util::snap_stroke(shape, width, mode, transform);
+            let style = StrokeStyle {
+                snap_mode: None,
+                ..style.clone()
+            };
+            return self.stroke_styled(path, brush, width, &style);
+        }
+        self.stroke_with(shape, brush, width, style, false);
     }
 
     fn text(&mut self) -> &mut Self::Text {
         &mut self.text
     }
 
-    fn draw_text(&mut self, _layout: &Self::TextLayout, _pos: impl Into<Point>) {
-        unimplemented!()
+    fn draw_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>) {
+        let pos = pos.into();
+        let brush = Brush {
+            kind: BrushKind::Solid(layout.style().color.clone()),
+        };
+        if self.embed_fonts {
+            for family in layout.families() {
+                let (family, data) = match self.text.font_data(family) {
+                    Some(font) => font,
+                    None => continue,
+                };
+                if self.embedded_fonts.insert(family.name().to_lowercase()) {
+                    let face = text::font_face(family, data);
+                    self.defs.append(svg::node::element::Style::new(face));
+                }
+            }
+        }
+        self.sync_transform();
+        let opacity = self.opacity();
+        for (line, origin) in layout.lines() {
+            if line.is_empty() {
+                continue;
+            }
+            let outlines = match self.text_mode {
+                TextMode::Paths => layout.line_outlines(line.clone(), &mut self.text),
+                TextMode::Text => None,
+            };
+            if let Some(outlines) = outlines {
+                for (outline, color) in outlines {
+                    let brush = Brush {
+                        kind: BrushKind::Solid(color),
+                    };
+                    let attrs = Attrs {
+                        fill: Some((brush, None)),
+                        opacity,
+                        ..Attrs::default()
+                    };
+                    let outline = Affine::translate(pos.to_vec2()) * outline;
+                    self.add_shape(outline, attrs);
+                }
+                continue;
+            }
+            let mut node = svg::node::element::Text::new()
+                .set("x", self.precision.num(pos.x + origin.x))
+                .set("y", self.precision.num(pos.y + origin.y));
+            layout.style().apply_to(&mut node);
+            let mut attrs = Attrs {
+                fill: Some((brush.clone(), None)),
+                opacity,
+                ..Attrs::default()
+            };
+            attrs.class = self.style_class(&attrs);
+            attrs.apply_to(&mut node, self.precision);
+            node.append(svg::node::Text::new(layout.markup(line)));
+            self.content().append(node);
+        }
+        self.flush();
     }
 
     fn save(&mut self) -> Result<()> {
@@ -226,13 +1012,83 @@ impl piet::RenderContext for RenderContext {
 
     fn restore(&mut self) -> Result<()> {
         self.state = self.stack.pop().ok_or_else(|| Error::StackUnbalance)?;
+        while self.groups.len() > self.state.groups {
+            self.close_group();
+        }
         Ok(())
     }
 
-    fn finish(&mut self) -> Result<()> {
+    fn save_layer(&mut self, _opacity: f64) -> Result<()> {
+        Err(Error::NotSupported)
+    }
+
+    fn save_layer_with_mask(&mut self, _mask: LayerMask<Image>) -> Result<()> {
+        Err(Error::NotSupported)
+    }
+
+    fn set_blend_mode(&mut self, mode: BlendMode) -> Result<()> {
+        let css = match mode {
+            BlendMode::Normal => None,
+            BlendMode::Multiply => Some("multiply"),
+            BlendMode::Screen => Some("screen"),
+            BlendMode::Overlay => Some("overlay"),
+            BlendMode::Darken => Some("darken"),
+            BlendMode::Lighten => Some("lighten"),
+            BlendMode::Clear | BlendMode::SourceIn | BlendMode::DestinationOut => {
+                // the export is best-effort, so this isn't an error
+                eprintln!(
+                    "blend mode {:?} has no CSS equivalent; drawing normally",
+                    mode
+                );
+                None
+            }
+        };
+        // a blend group opened since the last save is replaced, rather than
+        // nesting another one in it
+        let saved = self.stack.last().map(|state| state.groups).unwrap_or(0);
+        if self.groups.len() > saved && self.groups.last().unwrap().kind == GroupKind::Blend {
+            self.close_group();
+        }
+        // the drawing until the next restore is blended as a whole
+        if let Some(css) = css {
+            let group = Group::new()
+                .set("isolation", "isolate")
+                .set("style", format!("mix-blend-mode: {}", css));
+            self.groups.push(OpenGroup {
+                group,
+                xf: self.group_xf(),
+                kind: GroupKind::Blend,
+            });
+        }
+        self.state.groups = self.groups.len();
         Ok(())
     }
 
+    fn push_opacity(&mut self, alpha: f64) {
+        self.opacity.push(alpha);
+    }
+
+    fn pop_opacity(&mut self) -> Result<()> {
+        self.opacity.pop()
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.flush();
+        if self.stream.is_some() {
+            while !self.groups.is_empty() {
+                self.close_group();
+            }
+            let stream = self.stream.as_mut().unwrap();
+            let sink = io::BufWriter::new(Output::Plain(Box::new(io::sink())));
+            let mut writer = mem::replace(&mut stream.writer, sink);
+            let written = writeln!(writer, "</svg>")
+                .and_then(|_| writer.into_inner().map_err(|e| e.into_error()))
+                .and_then(Output::finish);
+            self.check_written(written);
+        }
+        self.status()
+    }
+
     fn transform(&mut self, transform: Affine) {
         self.state.xf *= transform;
     }
@@ -241,16 +1097,55 @@ impl piet::RenderContext for RenderContext {
         self.state.xf
     }
 
+    fn current_clip_bounds(&self) -> Option<Rect> {
+        util::user_clip_bounds(self.state.clip_bounds, self.state.xf)
+    }
+
     fn make_image(
         &mut self,
-        _width: usize,
-        _height: usize,
-        _buf: &[u8],
-        _format: ImageFormat,
+        width: usize,
+        height: usize,
+        buf: &[u8],
+        format: ImageFormat,
     ) -> Result<Self::Image> {
+        let len = width
+            .checked_mul(height)
+            .and_then(|len| len.checked_mul(format.bytes_per_pixel()));
+        if len != Some(buf.len()) {
+            return Err(Error::InvalidInput);
+        }
+        Image::new(ImageBuf::from_raw(buf, format, width, height))
+    }
+
+    #[cfg(feature = "raster")]
+    fn capture_image_area(&mut self, src_rect: impl Into<Rect>) -> Result<Image> {
+        // a streamed drawing isn't kept to be read back
+        if self.stream.is_some() {
+            return Err(Error::NotSupported);
+        }
+        let mut markup = Vec::new();
+        self.write(&mut markup)
+            .map_err(|e| Error::BackendError(Box::new(e)))?;
+        let doc = raster::Document::parse(&String::from_utf8_lossy(&markup))?;
+        let area = (doc.view_transform() * self.state.xf)
+            .transform_rect_bbox(src_rect.into())
+            .expand()
+            .intersect(doc.size().to_rect());
+        if area.width() <= 0.0 || area.height() <= 0.0 {
+            return Err(Error::InvalidInput);
+        }
+        Image::new(doc.render(area))
+    }
+
+    #[cfg(not(feature = "raster"))]
+    fn capture_image_area(&mut self, _src_rect: impl Into<Rect>) -> Result<Image> {
         Err(Error::NotSupported)
     }
 
+    fn to_image_buf(&mut self, image: &Image, format: ImageFormat) -> Result<ImageBuf> {
+        Ok(image.buf().to_format(format))
+    }
+
     #[inline]
     fn draw_image(
         &mut self,
@@ -272,125 +1167,338 @@ impl piet::RenderContext for RenderContext {
         draw_image(self, image, Some(src_rect.into()), dst_rect.into(), interp);
     }
 
-    fn blurred_rect(&mut self, _rect: Rect, _blur_radius: f64, _brush: &impl IntoBrush<Self>) {
-        unimplemented!()
+    fn blurred_shape(&mut self, shape: impl Shape, blur_radius: f64, brush: &impl IntoBrush<Self>) {
+        // the filter region is relative to the bounding box, so nothing is
+        // drawn for a shape with no area
+        let bounds = shape.bounding_box();
+        if bounds.area() == 0.0 {
+            return;
+        }
+        if blur_radius <= 0.0 {
+            return self.fill(shape, brush);
+        }
+        self.sync_transform();
+        let brush = brush.make_brush(self, || bounds);
+        let filter = self.blur_filter(blur_radius, bounds);
+        let opacity = self.opacity();
+        let attrs = Attrs {
+            fill: Some((brush.into_owned(), None)),
+            opacity,
+            filter: Some(filter),
+            ..Attrs::default()
+        };
+        self.add_shape(shape, attrs);
+        self.flush();
     }
 }
 
 fn draw_image(
-    _ctx: &mut RenderContext,
-    _image: &<RenderContext as piet::RenderContext>::Image,
-    _src_rect: Option<Rect>,
-    _dst_rect: Rect,
-    _interp: InterpolationMode,
+    ctx: &mut RenderContext,
+    image: &<RenderContext as piet::RenderContext>::Image,
+    src_rect: Option<Rect>,
+    dst_rect: Rect,
+    interp: InterpolationMode,
 ) {
-    unimplemented!()
+    let size = piet::Image::size(image);
+    let src_rect = src_rect.unwrap_or_else(|| size.to_rect()).abs();
+    let dst_rect = dst_rect.abs();
+    if src_rect.area() == 0.0 {
+        return;
+    }
+    ctx.sync_transform();
+    let href = match ctx.image_href(image) {
+        Some(href) => href,
+        None => return,
+    };
+    // an area of the image is drawn by drawing all of it, scaled so that
+    // the area fills `dst_rect`, clipped to `dst_rect`.
+    let scale_x = dst_rect.width() / src_rect.width();
+    let scale_y = dst_rect.height() / src_rect.height();
+    let x = dst_rect.x0 - src_rect.x0 * scale_x;
+    let y = dst_rect.y0 - src_rect.y0 * scale_y;
+    let precision = ctx.precision;
+    let mut node = svg::node::element::Image::new()
+        .set("href", href)
+        .set("x", precision.num(x))
+        .set("y", precision.num(y))
+        .set("width", precision.span(x, x + size.width * scale_x))
+        .set("height", precision.span(y, y + size.height * scale_y))
+        .set("preserveAspectRatio", "none");
+    if interp == InterpolationMode::NearestNeighbor {
+        node.assign("image-rendering", "pixelated");
+    }
+    if let Some(opacity) = ctx.opacity() {
+        node.assign("opacity", opacity);
+    }
+    if src_rect != size.to_rect() {
+        let id = ctx.new_id();
+        let mut clip = svg::node::element::ClipPath::new().set("id", id);
+        add_shape(&mut clip, dst_rect, &Attrs::default(), precision);
+        ctx.defs.append(clip);
+        node.assign("clip-path", format!("url(#{})", id.to_string()));
+    }
+    ctx.content().append(node);
+    ctx.flush();
+}
+
+/// Drawing that only the SVG backend supports, because the viewer does it.
+///
+/// These are not part of `piet::RenderContext`, as the raster backends
+/// have nothing to do them with; import this trait to use them.
+pub trait SvgRenderContextExt: piet::RenderContext {
+    /// Stroke a shape with a width that doesn't change when the viewer
+    /// zooms, as with hairlines in maps and technical drawings.
+    ///
+    /// The width, and the lengths of any dashes, are in the pixels of the
+    /// viewer rather than in user space, so they ignore the transform and
+    /// the view box too. This sets `vector-effect="non-scaling-stroke"`
+    /// on the element.
+    fn stroke_non_scaling(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, width: f64);
+
+    /// Stroke a shape with a width that doesn't change when the viewer
+    /// zooms, in a style; see [`stroke_non_scaling`].
+    ///
+    /// The style's snap mode is ignored, as the stroke isn't in user space.
+    ///
+    /// [`stroke_non_scaling`]: #tymethod.stroke_non_scaling
+    fn stroke_styled_non_scaling(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+    );
+}
+
+impl SvgRenderContextExt for RenderContext {
+    fn stroke_non_scaling(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, width: f64) {
+        self.stroke_with(shape, brush, width, &StrokeStyle::new(), true);
+    }
+
+    fn stroke_styled_non_scaling(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        let style = StrokeStyle {
+            snap_mode: None,
+            ..style.clone()
+        };
+        self.stroke_with(shape, brush, width, &style, true);
+    }
 }
 
 #[derive(Default)]
 struct Attrs<'a> {
-    xf: Affine,
-    clip: Option<Id>,
     fill: Option<(Brush, Option<&'a str>)>,
+    clip_rule: Option<&'a str>,
     stroke: Option<(Brush, f64, &'a StrokeStyle)>,
+    opacity: Option<f64>,
+    filter: Option<Id>,
+    /// Whether the stroke width is in the viewer's pixels.
+    non_scaling_stroke: bool,
+    /// The class that sets the paint properties, instead of attributes.
+    class: Option<String>,
 }
 
 impl Attrs<'_> {
+    fn apply_to(&self, node: &mut impl Node, precision: Precision) {
+        match self.class {
+            Some(ref class) => node.assign("class", class.clone()),
+            None => {
+                for (name, value) in self.paint(precision) {
+                    node.assign(name, value);
+                }
+            }
+        }
+        if let Some(rule) = self.clip_rule {
+            node.assign("clip-rule", rule);
+        }
+        if let Some(filter) = self.filter {
+            node.assign("filter", format!("url(#{})", filter.to_string()));
+        }
+        if self.non_scaling_stroke {
+            node.assign("vector-effect", "non-scaling-stroke");
+        }
+    }
+
+    /// The properties of the fill, stroke and opacity, which are set by
+    /// the class in class style mode.
     // allow clippy warning for `width != 1.0` in if statement
     #[allow(clippy::float_cmp)]
-    fn apply_to(&self, node: &mut impl Node) {
-        node.assign("transform", xf_val(&self.xf));
-        if let Some(id) = self.clip {
-            node.assign("clip-path", format!("url(#{})", id.to_string()));
-        }
+    fn paint(&self, precision: Precision) -> Vec<(&'static str, String)> {
+        let mut paint = Vec::new();
         if let Some((ref brush, rule)) = self.fill {
-            node.assign("fill", brush.color());
+            paint.push(("fill", brush.color().into()));
             if let Some(opacity) = brush.opacity() {
-                node.assign("fill-opacity", opacity);
+                paint.push(("fill-opacity", opacity.into()));
             }
             if let Some(rule) = rule {
-                node.assign("fill-rule", rule);
+                paint.push(("fill-rule", rule.to_owned()));
             }
         } else {
-            node.assign("fill", "none");
+            paint.push(("fill", "none".to_owned()));
+        }
+        if let Some(opacity) = self.opacity {
+            paint.push(("opacity", opacity.to_string()));
         }
         if let Some((ref stroke, width, style)) = self.stroke {
-            node.assign("stroke", stroke.color());
+            paint.push(("stroke", stroke.color().into()));
             if let Some(opacity) = stroke.opacity() {
-                node.assign("stroke-opacity", opacity);
+                paint.push(("stroke-opacity", opacity.into()));
             }
             if width != 1.0 {
-                node.assign("stroke-width", width);
+                paint.push(("stroke-width", precision.num(width)));
             }
             match style.line_join {
                 None | Some(LineJoin::Miter) => {}
-                Some(LineJoin::Round) => {
-                    node.assign("stroke-linejoin", "round");
-                }
-                Some(LineJoin::Bevel) => {
-                    node.assign("stroke-linejoin", "bevel");
-                }
+                Some(LineJoin::Round) => paint.push(("stroke-linejoin", "round".to_owned())),
+                Some(LineJoin::Bevel) => paint.push(("stroke-linejoin", "bevel".to_owned())),
             }
             match style.line_cap {
                 None | Some(LineCap::Butt) => {}
-                Some(LineCap::Round) => {
-                    node.assign("stroke-linecap", "round");
-                }
-                Some(LineCap::Square) => {
-                    node.assign("stroke-linecap", "square");
-                }
+                Some(LineCap::Round) => paint.push(("stroke-linecap", "round".to_owned())),
+                Some(LineCap::Square) => paint.push(("stroke-linecap", "square".to_owned())),
             }
-            if let Some((ref array, offset)) = style.dash {
-                node.assign("stroke-dasharray", array.clone());
-                if offset != 0.0 {
-                    node.assign("stroke-dashoffset", offset);
+            match style.dash {
+                Some((ref array, offset)) if !array.is_empty() => {
+                    // an odd-length pattern is repeated, as on canvas
+                    let array = if array.len() % 2 == 1 {
+                        array.repeat(2)
+                    } else {
+                        array.clone()
+                    };
+                    let array: Vec<String> = array.iter().map(|&x| precision.num(x)).collect();
+                    paint.push(("stroke-dasharray", array.join(" ")));
+                    if offset != 0.0 {
+                        paint.push(("stroke-dashoffset", precision.num(offset)));
+                    }
                 }
+                _ => {}
             }
-            if let Some(limit) = style.miter_limit {
-                node.assign("stroke-miterlimit", limit);
+            // SVG's default limit is 4, not ours
+            if matches!(style.line_join, None | Some(LineJoin::Miter)) {
+                let limit = style
+                    .miter_limit
+                    .unwrap_or(StrokeStyle::DEFAULT_MITER_LIMIT);
+                paint.push(("stroke-miterlimit", limit.to_string()));
             }
         }
+        paint
+    }
+}
+
+/// A `<style>` element with the rules of classes.
+fn style_element(rules: &[String]) -> svg::node::element::Style {
+    svg::node::element::Style::new(rules.join("\n"))
+}
+
+/// The start tag of an element, with its attributes but not its children.
+fn start_tag(element: &Element) -> String {
+    let mut empty = Element::new(element.get_name().as_str());
+    for (name, value) in element.get_attributes() {
+        empty.assign(name.as_str(), value.clone());
     }
+    let tag = empty.to_string();
+    format!("{}>", tag.trim_end_matches("/>"))
 }
 
-fn xf_val(xf: &Affine) -> svg::node::Value {
-    let xf = xf.as_coeffs();
-    format!(
-        "matrix({} {} {} {} {} {})",
-        xf[0], xf[1], xf[2], xf[3], xf[4], xf[5]
-    )
-    .into()
+/// The number of decimal places that numbers are written with.
+#[derive(Clone, Copy, Debug)]
+struct Precision(u8);
+
+impl Default for Precision {
+    fn default() -> Self {
+        Precision(3)
+    }
+}
+
+impl Precision {
+    /// A number rounded to the precision, without trailing zeros or an
+    /// exponent.
+    fn num(self, x: f64) -> String {
+        let mut text = format!("{:.*}", self.0 as usize, x);
+        if text.contains('.') {
+            let len = text.trim_end_matches('0').trim_end_matches('.').len();
+            text.truncate(len);
+        }
+        if text == "-0" {
+            text.remove(0);
+        }
+        text
+    }
+
+    /// The distance from `start` to `end` once they're both rounded.
+    fn span(self, start: f64, end: f64) -> String {
+        let round = |x| self.num(x).parse::<f64>().unwrap_or(x);
+        self.num(round(end) - round(start))
+    }
+
+    fn xf(self, xf: Affine) -> String {
+        let coeffs: Vec<String> = xf.as_coeffs().iter().map(|&x| self.num(x)).collect();
+        format!("matrix({})", coeffs.join(" "))
+    }
+
+    /// The path data of a shape.
+    fn path(self, shape: impl Shape) -> String {
+        let mut data = String::new();
+        let mut push = |command, points: &[Point]| {
+            data.push(command);
+            for (i, p) in points.iter().enumerate() {
+                if i > 0 {
+                    data.push(' ');
+                }
+                data.push_str(&self.num(p.x));
+                data.push(' ');
+                data.push_str(&self.num(p.y));
+            }
+        };
+        for el in shape.into_bez_path(1e-3).elements() {
+            match *el {
+                PathEl::MoveTo(p) => push('M', &[p]),
+                PathEl::LineTo(p) => push('L', &[p]),
+                PathEl::QuadTo(p1, p2) => push('Q', &[p1, p2]),
+                PathEl::CurveTo(p1, p2, p3) => push('C', &[p1, p2, p3]),
+                PathEl::ClosePath => push('Z', &[]),
+            }
+        }
+        data
+    }
 }
 
-fn add_shape(node: &mut impl Node, shape: impl Shape, attrs: &Attrs) {
+fn add_shape(node: &mut impl Node, shape: impl Shape, attrs: &Attrs, precision: Precision) {
+    let num = |x| precision.num(x);
     if let Some(circle) = shape.as_circle() {
         let mut x = svg::node::element::Circle::new()
-            .set("cx", circle.center.x)
-            .set("cy", circle.center.y)
-            .set("r", circle.radius);
-        attrs.apply_to(&mut x);
+            .set("cx", num(circle.center.x))
+            .set("cy", num(circle.center.y))
+            .set("r", num(circle.radius));
+        attrs.apply_to(&mut x, precision);
         node.append(x);
     } else if let Some(rect) = shape.as_rounded_rect() {
+        let (radius, rect) = (rect.radius(), rect.rect());
         let mut x = svg::node::element::Rectangle::new()
-            .set("x", rect.origin().x)
-            .set("y", rect.origin().y)
-            .set("width", rect.width())
-            .set("height", rect.height())
-            .set("rx", rect.radius())
-            .set("ry", rect.radius());
-        attrs.apply_to(&mut x);
+            .set("x", num(rect.x0))
+            .set("y", num(rect.y0))
+            .set("width", precision.span(rect.x0, rect.x1))
+            .set("height", precision.span(rect.y0, rect.y1))
+            .set("rx", num(radius))
+            .set("ry", num(radius));
+        attrs.apply_to(&mut x, precision);
         node.append(x);
     } else if let Some(rect) = shape.as_rect() {
         let mut x = svg::node::element::Rectangle::new()
-            .set("x", rect.origin().x)
-            .set("y", rect.origin().y)
-            .set("width", rect.width())
-            .set("height", rect.height());
-        attrs.apply_to(&mut x);
+            .set("x", num(rect.x0))
+            .set("y", num(rect.y0))
+            .set("width", precision.span(rect.x0, rect.x1))
+            .set("height", precision.span(rect.y0, rect.y1));
+        attrs.apply_to(&mut x, precision);
         node.append(x);
     } else {
-        let mut path = svg::node::element::Path::new().set("d", shape.into_bez_path(1e-3).to_svg());
-        attrs.apply_to(&mut path);
+        let mut path = svg::node::element::Path::new().set("d", precision.path(shape));
+        attrs.apply_to(&mut path, precision);
         node.append(path)
     }
 }
@@ -398,7 +1506,10 @@ fn add_shape(node: &mut impl Node, shape: impl Shape, attrs: &Attrs) {
 #[derive(Debug, Clone, Default)]
 struct State {
     xf: Affine,
-    clip: Option<Id>,
+    /// The number of clip groups open in this state.
+    groups: usize,
+    /// The bounds of the clip, before any transforms.
+    clip_bounds: Option<Rect>,
 }
 
 /// An SVG brush
@@ -453,8 +1564,13 @@ fn fmt_opacity(color: &Color) -> String {
     }
 }
 
-/// SVG image (unimplemented)
-pub struct Image(());
+fn fmt_extend(extend: GradientExtend) -> &'static str {
+    match extend {
+        GradientExtend::Pad => "pad",
+        GradientExtend::Repeat => "repeat",
+        GradientExtend::Reflect => "reflect",
+    }
+}
 
 #[derive(Debug, Copy, Clone)]
 struct Id(u64);
@@ -483,3 +1599,1013 @@ impl From<Id> for svg::node::Value {
         x.to_string().into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use piet::kurbo::Vec2;
+    use piet::{RenderContext as _, Text as _, TextAttribute, TextLayoutBuilder as _};
+
+    #[test]
+    fn draw_text_lines() {
+        let mut rc = RenderContext::new(Size::new(400.0, 200.0));
+        let layout = rc
+            .text()
+            .new_text_layout("one & two\nthree")
+            .font(piet::FontFamily::SERIF, 10.0)
+            .build()
+            .unwrap();
+        rc.draw_text(&layout, (5.0, 5.0));
+        let mut out = Vec::new();
+        rc.write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.matches("<text").count(), 2);
+        assert!(out.contains("one &amp; two"));
+        assert!(out.contains(r#"font-family="serif""#));
+        assert!(out.contains(r#"font-size="10""#));
+    }
+
+    #[test]
+    fn embedded_fonts() {
+        let data = text::tests::test_font("Test Sans");
+        let draw = |embed| {
+            let mut rc = RenderContext::new(Size::new(400.0, 200.0));
+            rc.embed_fonts(embed);
+            let family = rc.text().load_font(&data).unwrap();
+            // loaded, but not drawn with
+            rc.text()
+                .load_font(&text::tests::test_font("Unused"))
+                .unwrap();
+            for text in &["one", "two"] {
+                let layout = rc
+                    .text()
+                    .new_text_layout(*text)
+                    .font(family.clone(), 10.0)
+                    .range_attribute(1.., piet::FontFamily::SERIF)
+                    .build()
+                    .unwrap();
+                rc.draw_text(&layout, (5.0, 5.0));
+            }
+            to_string(&rc)
+        };
+        assert!(!draw(false).contains("<style"));
+
+        let out = draw(true);
+        assert_eq!(out.matches("<style").count(), 1);
+        let face = &out[out.find("@font-face").unwrap()..];
+        let family = &face[face.find("font-family: ").unwrap() + "font-family: ".len()..];
+        let family = &family[..family.find(';').unwrap()];
+        assert_eq!(family, "'Test Sans'");
+        assert_eq!(
+            out.matches(&format!("font-family=\"{}\"", family)).count(),
+            2
+        );
+        let uri = "url(data:font/ttf;base64,";
+        let base64 = &face[face.find(uri).unwrap() + uri.len()..];
+        assert_eq!(decode_base64(&base64[..base64.find(')').unwrap()]), data);
+    }
+
+    #[test]
+    fn text_paths() {
+        let mut rc = RenderContext::new(Size::new(400.0, 200.0));
+        rc.text_mode(TextMode::Paths);
+        let family = rc
+            .text()
+            .load_font(&text::tests::test_font("Test"))
+            .unwrap();
+        // the line with 'n', which isn't in the font, can't be outlined
+        let layout = rc
+            .text()
+            .new_text_layout("A A\nnA")
+            .font(family, 10.0)
+            .range_attribute(2..3, TextAttribute::ForegroundColor(Color::rgb8(255, 0, 0)))
+            .range_attribute(2..3, TextAttribute::Underline(true))
+            .build()
+            .unwrap();
+        rc.draw_text(&layout, (5.0, 5.0));
+        let out = to_string(&rc);
+        assert_eq!(out.matches("<text").count(), 1);
+        assert!(out.contains("\nnA\n</text>"));
+        let paths: Vec<&str> = out
+            .split("<path d=\"")
+            .skip(1)
+            .map(|path| &path[..path.find('"').unwrap()])
+            .collect();
+        assert_eq!(paths.len(), 2);
+        // the first 'A' is at the origin of the layout, 8 above its baseline
+        assert_eq!(paths[0], "M5 13L10 13L10 6L5 6Z");
+        // the underlined 'A' has a second subpath, of the underline
+        assert_eq!(paths[1].matches('M').count(), 2);
+        assert!(out.contains(r##"fill="#ff0000""##));
+    }
+
+    #[test]
+    fn document_metadata() {
+        let mut rc = RenderContext::new(Size::new(10.0, 10.0));
+        rc.fill(Rect::new(0.0, 0.0, 5.0, 5.0), &Color::BLACK);
+        // metadata can be set after drawing
+        rc.set_title("Fish & <chips>");
+        rc.set_description("Größe: 10×10 — 魚");
+        rc.add_root_attribute("data-note", "a < b & \"c\"");
+        rc.add_root_attribute("xml:lang", "en");
+        rc.add_root_attribute("xml:lang", "de");
+        rc.set_title("Fish & <chips>, again");
+        let out = to_string(&rc);
+        let attrs = root_attrs(&out);
+        assert!(attrs.contains(&("data-note", "a &lt; b &amp; &quot;c&quot;")));
+        assert!(attrs.contains(&("xml:lang", "de")));
+        assert_eq!(
+            attrs.iter().filter(|(name, _)| *name == "xml:lang").count(),
+            1
+        );
+        // the title and description are the first children
+        let root = out.find("<svg ").unwrap();
+        let children = out[root + out[root..].find('>').unwrap() + 1..].trim_start();
+        assert!(children.starts_with(
+            "<title>\nFish &amp; &lt;chips&gt;, again\n</title>\n<desc>\nGröße: 10×10 — 魚\n</desc>\n"
+        ));
+        assert_eq!(out.matches("<title>").count(), 1);
+        assert_eq!(out.matches("<desc>").count(), 1);
+    }
+
+    #[test]
+    fn stream_metadata() {
+        let out = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut rc = RenderContext::new_streaming(Size::new(10.0, 10.0), Shared(out.clone()));
+        rc.set_title("<π>");
+        rc.add_root_attribute("data-note", "&");
+        rc.fill(Rect::new(0.0, 0.0, 5.0, 5.0), &Color::BLACK);
+        rc.finish().unwrap();
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        assert!(root_attrs(&out).contains(&("data-note", "&amp;")));
+        let start = out.find('>').unwrap() + 1;
+        assert!(out[start..].starts_with("\n<title>\n&lt;π&gt;\n</title>\n"));
+        assert!(!out.contains("<desc>"));
+    }
+
+    #[cfg(feature = "raster")]
+    #[test]
+    fn capture_image_area() {
+        use piet::RenderContext as _;
+        let mut rc = RenderContext::new(Size::new(20.0, 20.0))
+            .with_view_box(Rect::new(0.0, 0.0, 10.0, 10.0));
+        rc.fill(Rect::new(0.0, 0.0, 5.0, 10.0), &Color::rgb8(0xff, 0, 0));
+        rc.stroke(
+            piet::kurbo::Line::new((5.0, 7.5), (10.0, 7.5)),
+            &Color::rgb8(0, 0, 0xff),
+            1.0,
+        );
+        // the area is in the current coordinate space, and the image is of
+        // the pixels under it, two to each unit
+        rc.transform(Affine::translate((4.0, 5.0)));
+        let image = rc
+            .capture_image_area(Rect::new(0.0, 0.0, 4.0, 5.0))
+            .unwrap();
+        let buf = rc.to_image_buf(&image, ImageFormat::RgbaSeparate).unwrap();
+        assert_eq!((buf.width(), buf.height()), (8, 10));
+        let pixel = |x: usize, y: usize| {
+            let i = (y * buf.width() + x) * 4;
+            buf.raw_pixels()[i..i + 4].to_vec()
+        };
+        assert_eq!(pixel(0, 0), [0xff, 0, 0, 0xff]);
+        assert_eq!(pixel(3, 0), [0, 0, 0, 0]);
+        assert_eq!(pixel(3, 5), [0, 0, 0xff, 0xff]);
+
+        // the area is clipped to the document
+        let image = rc
+            .capture_image_area(Rect::new(0.0, 0.0, 20.0, 20.0))
+            .unwrap();
+        assert_eq!(piet::Image::size(&image), Size::new(12.0, 10.0));
+        let outside = rc.capture_image_area(Rect::new(10.0, 10.0, 20.0, 20.0));
+        assert!(matches!(outside, Err(Error::InvalidInput)));
+
+        let mut rc = RenderContext::new_streaming(Size::new(10.0, 10.0), io::sink());
+        let streamed = rc.capture_image_area(Rect::new(0.0, 0.0, 10.0, 10.0));
+        assert!(matches!(streamed, Err(Error::NotSupported)));
+    }
+
+    #[test]
+    fn class_styles() {
+        let draw = |rc: &mut RenderContext| {
+            for i in 0..10 {
+                let x = i as f64 * 10.0;
+                rc.fill(Rect::new(x, 0.0, x + 5.0, 5.0), &Color::rgb8(0xff, 0, 0));
+                let line = piet::kurbo::Line::new((x, 10.0), (x + 5.0, 10.0));
+                rc.stroke(line, &Color::BLACK, 2.0);
+            }
+            rc.fill(Rect::new(0.0, 20.0, 5.0, 25.0), &Color::WHITE);
+        };
+        let mut inline = RenderContext::new(Size::new(100.0, 30.0));
+        draw(&mut inline);
+        let mut classes = RenderContext::new(Size::new(100.0, 30.0));
+        classes.style_mode(StyleMode::Classes);
+        draw(&mut classes);
+        let (inline, out) = (to_string(&inline), to_string(&classes));
+        assert!(out.len() < inline.len());
+        assert!(!inline.contains("<style>"));
+        let style = &out[out.find("<style>").unwrap()..out.find("</style>").unwrap()];
+        assert_eq!(
+            style.lines().skip(1).collect::<Vec<_>>(),
+            [
+                ".c0 { fill: #ff0000; fill-opacity: 1 }",
+                ".c1 { fill: none; stroke: #000000; stroke-opacity: 1; \
+                 stroke-width: 2; stroke-miterlimit: 10 }",
+            ]
+        );
+        // the first element in a style sets it with attributes, as does
+        // the only one in a unique style
+        assert_eq!(out.matches("class=\"c0\"").count(), 9);
+        assert_eq!(out.matches("class=\"c1\"").count(), 9);
+        assert_eq!(out.matches("fill=\"#ff0000\"").count(), 1);
+        assert_eq!(out.matches("fill=\"#ffffff\"").count(), 1);
+
+        // a streamed class is defined before the elements that have it
+        let buf = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut rc = RenderContext::new_streaming(Size::new(100.0, 30.0), Shared(buf.clone()));
+        rc.style_mode(StyleMode::Classes);
+        draw(&mut rc);
+        rc.finish().unwrap();
+        let streamed = String::from_utf8(buf.borrow().clone()).unwrap();
+        let rule = streamed.find(".c0 {").unwrap();
+        assert!(rule < streamed.find("class=\"c0\"").unwrap());
+        assert_eq!(streamed.matches("<style>").count(), 2);
+    }
+
+    #[cfg(feature = "raster")]
+    #[test]
+    fn class_styles_rasterize() {
+        for &idx in &[0, 11, 30] {
+            let sample = piet::samples::get::<RenderContext>(idx);
+            let capture = |mode| {
+                let mut rc = RenderContext::new(sample.size());
+                rc.style_mode(mode);
+                sample.draw(&mut rc).unwrap();
+                assert_eq!(
+                    to_string(&rc).contains("class="),
+                    mode == StyleMode::Classes
+                );
+                let area = sample.size().to_rect();
+                let image = rc.capture_image_area(area).unwrap();
+                let buf = rc.to_image_buf(&image, ImageFormat::RgbaPremul).unwrap();
+                buf.raw_pixels().to_vec()
+            };
+            assert!(capture(StyleMode::Inline) == capture(StyleMode::Classes));
+        }
+    }
+
+    fn to_string(rc: &RenderContext) -> String {
+        let mut out = Vec::new();
+        rc.write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /// The attributes of the root element.
+    fn root_attrs(out: &str) -> Vec<(&str, &str)> {
+        let start = out.find("<svg ").unwrap() + "<svg ".len();
+        let root = &out[start..];
+        root[..root.find('>').unwrap()]
+            .split_terminator("\" ")
+            .map(|attr| {
+                let mut parts = attr.splitn(2, "=\"");
+                let name = parts.next().unwrap();
+                (name, parts.next().unwrap().trim_end_matches('"'))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn document_size() {
+        let rc = RenderContext::new(Size::new(210.0, 297.0)).with_units(Unit::Mm);
+        assert_eq!(
+            root_attrs(&to_string(&rc)),
+            [
+                ("height", "297mm"),
+                ("viewBox", "0 0 210 297"),
+                ("width", "210mm"),
+                ("xmlns", "http://www.w3.org/2000/svg"),
+            ]
+        );
+        let view_box = Rect::new(-10.0, -10.0, 390.0, 290.0);
+        let mut rc = RenderContext::new(Size::new(4.0, 3.0))
+            .with_units(Unit::In)
+            .with_view_box(view_box);
+        rc.set_size(Size::new(8.0, 6.0));
+        let out = to_string(&rc);
+        assert_eq!(
+            &root_attrs(&out)[..3],
+            [
+                ("height", "6in"),
+                ("viewBox", "-10 -10 400 300"),
+                ("width", "8in"),
+            ]
+        );
+        // pixels have no suffix
+        let rc = RenderContext::new(Size::new(100.5, 50.0));
+        let out = to_string(&rc);
+        assert_eq!(
+            &root_attrs(&out)[..3],
+            [
+                ("height", "50"),
+                ("viewBox", "0 0 100.5 50"),
+                ("width", "100.5"),
+            ]
+        );
+    }
+
+    /// The transform of each `rect` drawn in the output, from its groups.
+    fn rect_transforms(out: &str) -> Vec<Affine> {
+        let mut groups = vec![Affine::default()];
+        let mut rects = Vec::new();
+        let content = &out[out.find("</defs>").unwrap()..];
+        for line in content.lines() {
+            let xf = *groups.last().unwrap();
+            if line.starts_with("<g transform=\"matrix(") {
+                let start = "<g transform=\"matrix(".len();
+                let end = line.find(")\"").unwrap();
+                let coeffs: Vec<f64> = line[start..end]
+                    .split(' ')
+                    .map(|c| c.parse().unwrap())
+                    .collect();
+                let mut local = [0.0; 6];
+                local.copy_from_slice(&coeffs);
+                groups.push(xf * Affine::new(local));
+            } else if line.starts_with("<g") {
+                groups.push(xf);
+            } else if line == "</g>" {
+                groups.pop();
+            } else if line.starts_with("<rect") {
+                assert!(!line.contains("transform"));
+                rects.push(xf);
+            }
+        }
+        rects
+    }
+
+    #[test]
+    fn current_transforms() {
+        let mut rc = RenderContext::new(Size::new(400.0, 200.0))
+            .with_view_box(Rect::new(0.0, 0.0, 200.0, 100.0));
+        // the view box isn't part of the transform of user space
+        assert_eq!(rc.current_transform(), Affine::default());
+        rc.transform(Affine::translate((10.0, 20.0)));
+        rc.save().unwrap();
+        rc.transform(Affine::scale(2.0));
+        assert_eq!(
+            rc.current_transform(),
+            Affine::new([2.0, 0.0, 0.0, 2.0, 10.0, 20.0])
+        );
+        let p = rc.current_transform() * Point::new(1.0, 1.0);
+        assert_eq!(p, Point::new(12.0, 22.0));
+        rc.save().unwrap();
+        rc.transform(Affine::new([0.0, 1.0, -1.0, 0.0, 5.0, 0.0]));
+        // a quarter turn and a shift of (5, 0), inside the scale
+        assert_eq!(
+            rc.current_transform(),
+            Affine::new([0.0, 2.0, -2.0, 0.0, 20.0, 20.0])
+        );
+        rc.fill(Rect::new(0.0, 0.0, 1.0, 1.0), &Color::BLACK);
+        rc.restore().unwrap();
+        assert_eq!(
+            rc.current_transform(),
+            Affine::new([2.0, 0.0, 0.0, 2.0, 10.0, 20.0])
+        );
+        rc.restore().unwrap();
+        assert_eq!(rc.current_transform(), Affine::translate((10.0, 20.0)));
+        assert!(rc.restore().is_err());
+        assert_eq!(rc.current_transform(), Affine::translate((10.0, 20.0)));
+    }
+
+    #[test]
+    fn transform_groups() {
+        let mut rc = RenderContext::new(Size::new(400.0, 200.0));
+        // enough to compare the composed transforms closely
+        rc.set_precision(12);
+        let mut expected = Vec::new();
+        let rect = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let mut fill = |rc: &mut RenderContext| {
+            rc.fill(rect, &Color::BLACK);
+            expected.push(rc.current_transform());
+        };
+        fill(&mut rc);
+        rc.save().unwrap();
+        rc.transform(Affine::translate((10.0, 0.0)));
+        fill(&mut rc);
+        rc.save().unwrap();
+        rc.transform(Affine::rotate(0.5));
+        rc.clip(rect);
+        fill(&mut rc);
+        rc.transform(Affine::scale(2.0));
+        fill(&mut rc);
+        rc.restore().unwrap();
+        fill(&mut rc);
+        rc.transform(Affine::translate((0.0, 5.0)));
+        fill(&mut rc);
+        rc.restore().unwrap();
+        fill(&mut rc);
+        let out = to_string(&rc);
+        let rects = rect_transforms(&out);
+        assert_eq!(rects.len(), expected.len());
+        for (actual, expected) in rects.iter().zip(&expected) {
+            let (actual, expected) = (actual.as_coeffs(), expected.as_coeffs());
+            for (a, e) in actual.iter().zip(&expected) {
+                assert!((a - e).abs() < 1e-9, "{:?} != {:?}", actual, expected);
+            }
+        }
+        // a second transform without a save replaces the group
+        assert_eq!(out.matches("<g transform").count(), 4);
+        assert!(out.contains("</g>\n<g transform=\"matrix(1 0 0 1 10 5)\">"));
+        // the clip path is in the space of the rotated group
+        let clip = &out[out.find("<clipPath").unwrap()..out.find("</clipPath>").unwrap()];
+        assert!(!clip.contains("transform"));
+    }
+
+    #[test]
+    fn nested_clips() {
+        let mut rc = RenderContext::new(Size::new(400.0, 200.0));
+        rc.save().unwrap();
+        rc.clip(Rect::new(0.0, 0.0, 10.0, 10.0));
+        rc.clip(Rect::new(5.0, 5.0, 20.0, 20.0));
+        rc.fill(Rect::new(0.0, 0.0, 1.0, 1.0), &Color::BLACK);
+        // an open clip is closed when writing
+        let out = to_string(&rc);
+        assert!(out.contains(concat!(
+            "<g clip-path=\"url(#a)\">\n",
+            "<g clip-path=\"url(#b)\">\n",
+            "<rect fill=\"#000000\"",
+        )));
+        rc.restore().unwrap();
+        rc.fill(Rect::new(0.0, 0.0, 2.0, 2.0), &Color::WHITE);
+        let out = to_string(&rc);
+        let defs = &out[out.find("<defs>").unwrap()..out.find("</defs>").unwrap()];
+        assert_eq!(defs.matches("<clipPath").count(), 2);
+        assert!(out.contains("</g>\n</g>\n<rect fill=\"#ffffff\""));
+    }
+
+    #[test]
+    fn clip_sample() {
+        let mut rc = RenderContext::new(Size::new(400.0, 200.0));
+        piet::samples::get(30).draw(&mut rc).unwrap();
+        let out = to_string(&rc);
+        assert_eq!(out.matches("<clipPath").count(), 2);
+        assert_eq!(out.matches("clip-rule=\"evenodd\"").count(), 1);
+        assert_eq!(out.matches("<g clip-path").count(), 2);
+    }
+
+    #[test]
+    fn blend_modes() {
+        let mut rc = RenderContext::new(Size::new(340.0, 340.0));
+        piet::samples::get(21).draw(&mut rc).unwrap();
+        let out = to_string(&rc);
+        for mode in &["multiply", "screen", "overlay", "darken", "lighten"] {
+            let style = format!(
+                "<g isolation=\"isolate\" style=\"mix-blend-mode: {}\">",
+                mode
+            );
+            assert_eq!(out.matches(&style).count(), 1);
+        }
+        // the modes without CSS equivalents are drawn normally
+        assert_eq!(out.matches("mix-blend-mode").count(), 5);
+
+        // setting another mode replaces the group, and restoring closes it
+        let mut rc = RenderContext::new(Size::new(400.0, 200.0));
+        let rect = Rect::new(0.0, 0.0, 10.0, 10.0);
+        rc.save().unwrap();
+        rc.set_blend_mode(BlendMode::Multiply).unwrap();
+        rc.set_blend_mode(BlendMode::Screen).unwrap();
+        rc.fill(rect, &Color::BLACK);
+        rc.set_blend_mode(BlendMode::Normal).unwrap();
+        rc.fill(rect, &Color::WHITE);
+        rc.restore().unwrap();
+        let out = to_string(&rc);
+        assert!(!out.contains("multiply"));
+        let group = out.find("mix-blend-mode: screen").unwrap();
+        let end = group + out[group..].find("</g>").unwrap();
+        assert!(out[group..end].contains("#000000"));
+        assert!(out[end..].contains("#ffffff"));
+    }
+
+    #[cfg(feature = "raster")]
+    #[test]
+    fn blend_sample_rasterizes() {
+        let mut rc = RenderContext::new(Size::new(340.0, 340.0));
+        piet::samples::get(21).draw(&mut rc).unwrap();
+        let image = rc
+            .capture_image_area(Rect::new(0.0, 0.0, 340.0, 340.0))
+            .unwrap();
+        let buf = rc.to_image_buf(&image, ImageFormat::RgbaSeparate).unwrap();
+        // where the blue circle of the multiply cell is over the red one
+        let i = (70 * buf.width() + 150) * 4;
+        let pixel = &buf.raw_pixels()[i..i + 4];
+        let (red, blue, alpha) = ([0xe0, 0x20, 0x20], [0x20, 0x40, 0xe0], 0xc0 as f64 / 255.0);
+        for c in 0..3 {
+            let (dst, src) = (red[c] as f64 / 255.0, blue[c] as f64 / 255.0);
+            let expected = dst * (1.0 - alpha) + alpha * src * dst;
+            // within rounding of the blended colors
+            assert!((pixel[c] as f64 - expected * 255.0).abs() < 2.0);
+        }
+        assert_eq!(pixel[3], 0xff);
+    }
+
+    #[test]
+    fn fill_rule_sample() {
+        let mut rc = RenderContext::new(Size::new(400.0, 200.0));
+        piet::samples::get(32).draw(&mut rc).unwrap();
+        let out = to_string(&rc);
+        // only the even-odd fill has a rule
+        assert_eq!(out.matches("fill-rule=").count(), 1);
+        assert_eq!(out.matches("fill-rule=\"evenodd\"").count(), 1);
+        assert!(!out.contains("clip-rule="));
+    }
+
+    #[cfg(feature = "raster")]
+    #[test]
+    fn fill_rule_sample_rasterizes() {
+        let mut rc = RenderContext::new(Size::new(400.0, 200.0));
+        piet::samples::get(32).draw(&mut rc).unwrap();
+        let image = rc
+            .capture_image_area(Rect::new(0.0, 0.0, 400.0, 200.0))
+            .unwrap();
+        let buf = rc.to_image_buf(&image, ImageFormat::RgbaSeparate).unwrap();
+        let pixel = |x: usize, y: usize| {
+            let i = (y * buf.width() + x) * 4;
+            buf.raw_pixels()[i..i + 4].to_vec()
+        };
+        // the middle of the star is filled with the nonzero rule only
+        assert_eq!(pixel(50, 54), [0x30, 0x80, 0xc0, 0xff]);
+        assert_eq!(pixel(150, 54), [0xff, 0xff, 0xff, 0xff]);
+        // the points are filled with either rule
+        assert_eq!(pixel(50, 20), [0x30, 0x80, 0xc0, 0xff]);
+        assert_eq!(pixel(150, 20), [0x30, 0x80, 0xc0, 0xff]);
+    }
+
+    fn stroke_attrs(style: &StrokeStyle) -> String {
+        let mut rc = RenderContext::new(Size::new(400.0, 200.0));
+        let line = piet::kurbo::Line::new((0.0, 0.0), (10.0, 0.0));
+        rc.stroke_styled(line, &Color::BLACK, 2.0, style);
+        let out = to_string(&rc);
+        let start = out.find("<path ").unwrap() + "<path ".len();
+        let path = &out[start..];
+        let path = &path[..path.find("/>").unwrap()];
+        let attrs: Vec<_> = path
+            .split_terminator("\" ")
+            .filter(|attr| attr.starts_with("stroke-") && !attr.starts_with("stroke-opacity"))
+            .map(|attr| format!("{}\"", attr.trim_end_matches('"')))
+            .collect();
+        attrs.join(" ")
+    }
+
+    #[test]
+    fn stroke_styles() {
+        assert_eq!(
+            stroke_attrs(&StrokeStyle::new()),
+            r#"stroke-miterlimit="10" stroke-width="2""#
+        );
+        let style = StrokeStyle::new()
+            .line_cap(LineCap::Round)
+            .line_join(LineJoin::Bevel);
+        assert_eq!(
+            stroke_attrs(&style),
+            r#"stroke-linecap="round" stroke-linejoin="bevel" stroke-width="2""#
+        );
+        let style = StrokeStyle::new()
+            .line_cap(LineCap::Square)
+            .line_join(LineJoin::Miter)
+            .miter_limit(3.0)
+            .dash(vec![4.0, 2.0], 1.5);
+        assert_eq!(
+            stroke_attrs(&style),
+            concat!(
+                r#"stroke-dasharray="4 2" stroke-dashoffset="1.5" "#,
+                r#"stroke-linecap="square" stroke-miterlimit="3" stroke-width="2""#
+            )
+        );
+        let style = StrokeStyle::new()
+            .line_join(LineJoin::Round)
+            .dash(vec![5.0, 1.0, 2.0], 0.0);
+        assert_eq!(
+            stroke_attrs(&style),
+            r#"stroke-dasharray="5 1 2 5 1 2" stroke-linejoin="round" stroke-width="2""#
+        );
+        let style = StrokeStyle::new()
+            .line_join(LineJoin::Round)
+            .dash(Vec::new(), 3.0);
+        assert_eq!(
+            stroke_attrs(&style),
+            r#"stroke-linejoin="round" stroke-width="2""#
+        );
+    }
+
+    #[test]
+    fn non_scaling_strokes() {
+        let mut rc = RenderContext::new(Size::new(400.0, 200.0));
+        let line = piet::kurbo::Line::new((0.0, 0.0), (10.0, 0.0));
+        rc.stroke(line, &Color::BLACK, 1.0);
+        rc.stroke_non_scaling(line, &Color::BLACK, 1.0);
+        let dashed = StrokeStyle::new().dash(vec![2.0, 1.0], 0.0);
+        rc.stroke_styled_non_scaling(line, &Color::BLACK, 0.5, &dashed);
+        let out = to_string(&rc);
+        assert_eq!(
+            out.matches("vector-effect=\"non-scaling-stroke\"").count(),
+            2
+        );
+        let last = &out[out.rfind("<path ").unwrap()..];
+        assert!(last.contains("stroke-dasharray=\"2 1\""));
+        assert!(last.contains("vector-effect=\"non-scaling-stroke\""));
+    }
+
+    fn decode_base64(text: &str) -> Vec<u8> {
+        const ALPHABET: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let digits: Vec<u32> = text
+            .trim_end_matches('=')
+            .chars()
+            .map(|c| ALPHABET.find(c).unwrap() as u32)
+            .collect();
+        let mut out = Vec::new();
+        for chunk in digits.chunks(4) {
+            let bits = chunk
+                .iter()
+                .enumerate()
+                .fold(0, |bits, (i, d)| bits | d << (18 - 6 * i));
+            out.extend_from_slice(&bits.to_be_bytes()[1..chunk.len()]);
+        }
+        out
+    }
+
+    #[test]
+    fn images_round_trip() {
+        let pixels = [255, 0, 0, 255, 0, 0, 255, 128, 0, 0, 0, 0, 10, 20, 30, 40];
+        for &format in &[ImageFormat::RgbaSeparate, ImageFormat::Grayscale] {
+            let pixels = &pixels[..4 * format.bytes_per_pixel()];
+            let mut rc = RenderContext::new(Size::new(400.0, 200.0));
+            let image = rc.make_image(2, 2, pixels, format).unwrap();
+            rc.draw_image(
+                &image,
+                Rect::new(0.0, 0.0, 4.0, 4.0),
+                InterpolationMode::Bilinear,
+            );
+            let out = to_string(&rc);
+            let start = out.find("base64,").unwrap() + "base64,".len();
+            let end = start + out[start..].find('"').unwrap();
+            let png = decode_base64(&out[start..end]);
+
+            let (info, mut reader) = png::Decoder::new(&png[..]).read_info().unwrap();
+            let mut decoded = vec![0; info.buffer_size()];
+            reader.next_frame(&mut decoded).unwrap();
+            let expected = ImageBuf::from_raw(pixels, format, 2, 2);
+            let expected = expected.to_format(ImageFormat::RgbaSeparate);
+            assert_eq!((info.width, info.height), (2, 2));
+            assert_eq!(decoded, expected.raw_pixels());
+        }
+    }
+
+    #[test]
+    fn image_sample() {
+        let mut rc = RenderContext::new(Size::new(400.0, 200.0));
+        piet::samples::get(2).draw(&mut rc).unwrap();
+        let out = to_string(&rc);
+        assert_eq!(out.matches("<image").count(), 21);
+        assert_eq!(out.matches("image-rendering=\"pixelated\"").count(), 7);
+        assert!(!out.contains("clip-path"));
+    }
+
+    #[test]
+    fn blur_filters() {
+        let mut rc = RenderContext::new(Size::new(400.0, 200.0));
+        let rect = Rect::new(0.0, 0.0, 100.0, 50.0);
+        rc.blurred_rect(rect, 4.0, &Color::BLACK);
+        rc.blurred_rect(rect + Vec2::new(0.0, 100.0), 4.0, &Color::WHITE);
+        rc.blurred_rect(rect, 2.0, &Color::BLACK);
+        // a small rect needs a larger filter region
+        rc.blurred_rect(Rect::new(0.0, 0.0, 10.0, 10.0), 4.0, &Color::BLACK);
+        rc.blurred_rect(Rect::new(0.0, 0.0, 10.0, 0.0), 4.0, &Color::BLACK);
+        let out = to_string(&rc);
+        assert_eq!(out.matches("<filter").count(), 3);
+        assert_eq!(out.matches("filter=\"url(#a)\"").count(), 2);
+        // 4 / sqrt(2), rounded
+        assert!(out.contains("<feGaussianBlur stdDeviation=\"2.828\"/>"));
+        assert!(out.contains("height=\"200%\" id=\"a\" width=\"200%\" x=\"-50%\" y=\"-50%\""));
+        assert!(out.contains("height=\"300%\" id=\"c\" width=\"300%\" x=\"-100%\" y=\"-100%\""));
+    }
+
+    #[test]
+    fn blur_samples() {
+        let mut rc = RenderContext::new(Size::new(400.0, 200.0));
+        piet::samples::get(0).draw(&mut rc).unwrap();
+        assert_eq!(to_string(&rc).matches("<filter").count(), 1);
+        let mut rc = RenderContext::new(Size::new(400.0, 200.0));
+        piet::samples::get(25).draw(&mut rc).unwrap();
+        let out = to_string(&rc);
+        assert_eq!(out.matches("<feGaussianBlur").count(), 1);
+        assert_eq!(out.matches(" filter=").count(), 3);
+    }
+
+    #[test]
+    fn gradients_under_transforms() {
+        let mut rc = RenderContext::new(Size::new(400.0, 200.0));
+        rc.set_precision(6);
+        let brush = rc
+            .gradient(piet::FixedLinearGradient {
+                start: Point::new(0.0, 0.0),
+                end: Point::new(10.0, 0.0),
+                stops: vec![
+                    piet::GradientStop {
+                        pos: 0.0,
+                        color: Color::BLACK,
+                    },
+                    piet::GradientStop {
+                        pos: 1.0,
+                        color: Color::WHITE,
+                    },
+                ],
+                extend: GradientExtend::Pad,
+                interpolation: piet::ColorInterpolation::Srgb,
+            })
+            .unwrap();
+        rc.transform(Affine::rotate(std::f64::consts::FRAC_PI_2));
+        rc.fill(Rect::new(0.0, 0.0, 10.0, 10.0), &brush);
+        let out = to_string(&rc);
+        // the gradient's coordinates are in the rect's user space, which
+        // the group rotates
+        assert!(out.contains(
+            r#"<linearGradient gradientUnits="userSpaceOnUse" id="a" spreadMethod="pad" x1="0" x2="10" y1="0" y2="0">"#
+        ));
+        assert!(!out.contains("gradientTransform"));
+        let group = out.find("<g transform=\"matrix(0 1 -1 0 0 0)\">").unwrap();
+        let rect = out.find("<rect fill=\"url(#a)\"").unwrap();
+        assert!(group < rect);
+    }
+
+    #[test]
+    fn radial_focus() {
+        let stops = vec![
+            piet::GradientStop {
+                pos: 0.0,
+                color: Color::BLACK,
+            },
+            piet::GradientStop {
+                pos: 1.0,
+                color: Color::WHITE,
+            },
+        ];
+        let radial_attrs = |gradient: piet::FixedRadialGradient| {
+            let mut rc = RenderContext::new(Size::new(400.0, 200.0));
+            rc.gradient(gradient).unwrap();
+            let out = to_string(&rc);
+            let start = out.find("<radialGradient ").unwrap() + "<radialGradient ".len();
+            let end = start + out[start..].find('>').unwrap();
+            out[start..end].to_owned()
+        };
+        let circle = piet::FixedRadialGradient::new((50.0, 50.0), 10.0, stops);
+        let inside = piet::FixedRadialGradient {
+            origin_offset: Vec2::new(-3.0, 4.0),
+            ..circle.clone()
+        };
+        assert!(radial_attrs(inside).contains(r#"fx="47" fy="54""#));
+        // a focus outside the circle is moved onto it
+        let outside = piet::FixedRadialGradient {
+            origin_offset: Vec2::new(30.0, 40.0),
+            ..circle.clone()
+        };
+        assert!(radial_attrs(outside).contains(r#"fx="56" fy="58""#));
+        // an ellipse is a circle stretched by the transform, with the focus
+        // in the circle's space
+        let ellipse = piet::FixedRadialGradient {
+            radii: Vec2::new(10.0, 5.0),
+            origin_offset: Vec2::new(0.0, 4.0),
+            ..circle
+        };
+        let attrs = radial_attrs(ellipse);
+        assert!(attrs.contains(r#"fx="50" fy="58""#));
+        assert!(attrs.contains(r#"gradientTransform="matrix(1 0 0 0.5 0 25)""#));
+        assert!(attrs.contains(r#"r="10""#));
+    }
+
+    #[test]
+    fn shared_gradients() {
+        let mut rc = RenderContext::new(Size::new(400.0, 200.0));
+        let stops = vec![
+            piet::GradientStop {
+                pos: 0.0,
+                color: Color::BLACK,
+            },
+            piet::GradientStop {
+                pos: 1.0,
+                color: Color::WHITE,
+            },
+        ];
+        let linear = piet::FixedLinearGradient {
+            start: Point::new(0.0, 0.0),
+            end: Point::new(0.0, 10.0),
+            stops: stops.clone(),
+            extend: GradientExtend::Pad,
+            interpolation: piet::ColorInterpolation::Srgb,
+        };
+        for i in 0..100 {
+            let brush = rc.gradient(linear.clone()).unwrap();
+            rc.fill(Rect::new(i as f64, 0.0, i as f64 + 1.0, 10.0), &brush);
+        }
+        let radial = piet::FixedRadialGradient::new((5.0, 5.0), 5.0, stops);
+        for _ in 0..2 {
+            let brush = rc.gradient(radial.clone()).unwrap();
+            rc.fill(Rect::new(0.0, 0.0, 10.0, 10.0), &brush);
+        }
+        let other = piet::FixedLinearGradient {
+            extend: GradientExtend::Repeat,
+            ..linear
+        };
+        rc.gradient(other).unwrap();
+        let out = to_string(&rc);
+        assert_eq!(out.matches("<linearGradient").count(), 2);
+        assert_eq!(out.matches("<radialGradient").count(), 1);
+        assert_eq!(out.matches("fill=\"url(#a)\"").count(), 100);
+        assert_eq!(out.matches("fill=\"url(#b)\"").count(), 2);
+    }
+
+    #[test]
+    fn number_precision() {
+        let precision = Precision::default();
+        assert_eq!(precision.num(103.48500000000001), "103.485");
+        assert_eq!(precision.num(2.0), "2");
+        assert_eq!(precision.num(0.1 + 0.2), "0.3");
+        assert_eq!(precision.num(0.0015), "0.002");
+        assert_eq!(precision.num(-0.0001), "0");
+        assert_eq!(precision.num(1e21), "1000000000000000000000");
+        assert_eq!(Precision(0).num(2.5), "2");
+        // rects that share an edge still do once rounded
+        assert_eq!(precision.span(0.0004, 1.0006), "1.001");
+        assert_eq!(precision.num(1.0006), "1.001");
+
+        let draw = |precision| {
+            let mut rc = RenderContext::new(Size::new(400.0, 200.0));
+            if let Some(precision) = precision {
+                rc.set_precision(precision);
+            }
+            piet::samples::get(0).draw(&mut rc).unwrap();
+            to_string(&rc)
+        };
+        let (rounded, full) = (draw(None), draw(Some(17)));
+        assert!(rounded.len() * 4 < full.len() * 3);
+        for number in 0..piet::samples::SAMPLE_COUNT {
+            let mut rc = RenderContext::new(Size::new(400.0, 200.0));
+            // some samples use features we don't support; what they draw
+            // before that is still checked
+            let _ = piet::samples::get(number).draw(&mut rc);
+            // leaving out hex colors and the data of embedded images
+            let out = to_string(&rc);
+            let values = out
+                .split('"')
+                .filter(|value| !value.starts_with("data:") && !value.starts_with('#'));
+            let exponent = values
+                .flat_map(|value| value.as_bytes().windows(3))
+                .any(|w| {
+                    w[0].is_ascii_digit() && w[1] == b'e' && (w[2] == b'-' || w[2].is_ascii_digit())
+                });
+            assert!(!exponent, "sample {} has a number with an exponent", number);
+        }
+    }
+
+    #[test]
+    fn external_images() {
+        let dir = std::env::temp_dir().join(format!("piet-svg-test-{}", std::process::id()));
+        let images_dir = dir.join("images");
+        let mut rc = RenderContext::new(Size::new(400.0, 200.0));
+        rc.set_image_mode(ImageMode::External {
+            dir: images_dir.clone(),
+        });
+        let red = rc.make_image(1, 1, &[255, 0, 0], ImageFormat::Rgb).unwrap();
+        let blue = rc.make_image(1, 1, &[0, 0, 255], ImageFormat::Rgb).unwrap();
+        let rect = Rect::new(0.0, 0.0, 1.0, 1.0);
+        for image in &[&red, &blue, &red] {
+            rc.draw_image(image, rect, InterpolationMode::Bilinear);
+        }
+        rc.finish().unwrap();
+        let out = to_string(&rc);
+        let red_path = images_dir.join(red.file_name());
+        let red_href = format!(
+            r#"href="{}""#,
+            red_path.to_string_lossy().replace('\\', "/")
+        );
+        assert_eq!(out.matches(&red_href).count(), 2);
+        assert!(!out.contains("data:"));
+        assert_eq!(std::fs::read_dir(&images_dir).unwrap().count(), 2);
+        assert_eq!(std::fs::read(&red_path).unwrap(), red.png());
+
+        // a file in the way of the directory
+        let blocked = dir.join("blocked");
+        std::fs::write(&blocked, b"").unwrap();
+        rc.set_image_mode(ImageMode::External { dir: blocked });
+        rc.draw_image(&red, rect, InterpolationMode::Bilinear);
+        let err = rc.finish().unwrap_err();
+        assert!(err.backend_error_downcast::<io::Error>().is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A writer that counts the bytes written through it.
+    struct Counter(std::rc::Rc<std::cell::Cell<usize>>);
+
+    impl io::Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.set(self.0.get() + buf.len());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn stream_many_rects() {
+        let count = std::rc::Rc::new(std::cell::Cell::new(0));
+        let size = Size::new(1000.0, 1000.0);
+        let mut rc = RenderContext::new_streaming(size, Counter(count.clone()));
+        for i in 0..100_000 {
+            let x = (i % 1000) as f64;
+            rc.fill(Rect::new(x, 0.0, x + 1.0, 1.0), &Color::BLACK);
+        }
+        // everything but the buffer has been written, and nothing is kept
+        assert!(count.get() > 100_000 * 40);
+        assert!(rc.root.get_inner().get_children().is_empty());
+        let before = count.get();
+        rc.finish().unwrap();
+        assert!(count.get() > before);
+        assert!(rc.write(Vec::new()).is_err());
+    }
+
+    /// A writer into a buffer that outlives the render context.
+    struct Shared(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl io::Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// The data of a gzip stream, checking its header and trailer.
+    fn gunzip(data: &[u8]) -> Vec<u8> {
+        assert_eq!(data[..3], [0x1f, 0x8b, 8]);
+        let (deflated, trailer) = data[10..].split_at(data.len() - 18);
+        let inflated = miniz_oxide::inflate::decompress_to_vec(deflated).unwrap();
+        assert_eq!(trailer[..4], crc32fast::hash(&inflated).to_le_bytes());
+        assert_eq!(trailer[4..], (inflated.len() as u32).to_le_bytes());
+        inflated
+    }
+
+    #[test]
+    fn compressed_output() {
+        let size = piet::samples::get::<RenderContext>(0).size();
+        let mut rc = RenderContext::new(size);
+        piet::samples::get(0).draw(&mut rc).ok();
+        let mut plain = Vec::new();
+        rc.write(&mut plain).unwrap();
+        let mut compressed = Vec::new();
+        rc.write_compressed(&mut compressed, Compression::Best)
+            .unwrap();
+        assert!(compressed.len() < plain.len());
+        assert_eq!(gunzip(&compressed), plain);
+
+        // streaming, compared with streaming the same drawing uncompressed
+        let plain = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let compressed = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut rc = RenderContext::new_streaming(size, Shared(plain.clone()));
+        piet::samples::get(0).draw(&mut rc).ok();
+        rc.finish().unwrap();
+        let mut rc = RenderContext::new_streaming_compressed(
+            size,
+            Shared(compressed.clone()),
+            Compression::Fast,
+        );
+        piet::samples::get(0).draw(&mut rc).ok();
+        rc.finish().unwrap();
+        assert_eq!(gunzip(&compressed.borrow()), *plain.borrow());
+    }
+
+    #[test]
+    fn stream_clips() {
+        let out = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let size = Size::new(400.0, 200.0);
+        let mut rc = RenderContext::new_streaming(size, Shared(out.clone()));
+        piet::samples::get(30).draw(&mut rc).unwrap();
+        rc.save().unwrap();
+        rc.clip(Rect::new(0.0, 0.0, 10.0, 10.0));
+        rc.fill(Rect::new(0.0, 0.0, 20.0, 20.0), &Color::BLACK);
+        // the open clip is closed by finish
+        rc.finish().unwrap();
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        assert!(out.starts_with("<svg height=\"200\" viewBox=\"0 0 400 200\" width=\"400\""));
+        assert!(out.ends_with("</svg>\n"));
+        assert_eq!(out.matches("<clipPath").count(), 3);
+        assert_eq!(out.matches("<g clip-path").count(), 3);
+        assert_eq!(out.matches("</g>").count(), 3);
+        // each gradient and clip path is defined before it's used
+        for id in &["a", "b", "c", "d"] {
+            let def = out.find(&format!("id=\"{}\"", id)).unwrap();
+            let used = out.find(&format!("url(#{})", id)).unwrap();
+            assert!(def < used, "#{} is used before it's defined", id);
+        }
+    }
+}
@@ -0,0 +1,1518 @@
+//! Rasterizing documents, for `capture_image_area`.
+//!
+//! This reads back the markup that the render context writes, so it draws
+//! what a viewer would, but it only understands the subset of SVG that this
+//! backend writes: shapes filled and stroked with colors and gradients, in
+//! attributes or classes, images, transforms, clip paths, blend modes and
+//! non-scaling strokes. Text isn't drawn, and filters are ignored.
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::{fs, mem};
+
+use piet::kurbo::{Affine, BezPath, Circle, PathEl, Point, Rect, Shape, Size, Vec2};
+use piet::{Error, ImageBuf, ImageFormat};
+use svg::node::element::tag::Type;
+use svg::parser::{Event, Parser};
+
+/// The largest distance of flattened curves from the true ones, in pixels.
+const TOLERANCE: f64 = 0.05;
+/// The number of rows of samples in each row of pixels.
+const SUBSAMPLES: usize = 16;
+
+/// A parsed document.
+pub(crate) struct Document {
+    root: Element,
+    /// The size in pixels, rounded up.
+    size: Size,
+    /// The transform from user space to pixels.
+    view_xf: Affine,
+    /// The size of the view box, that percentages are of.
+    viewport: Size,
+}
+
+/// An element of a parsed document.
+struct Element {
+    name: String,
+    attrs: HashMap<String, String>,
+    children: Vec<Element>,
+}
+
+impl Document {
+    pub(crate) fn parse(markup: &str) -> Result<Document, Error> {
+        let mut stack: Vec<Element> = Vec::new();
+        let mut root = None;
+        let mut style = String::new();
+        for event in Parser::new(markup) {
+            let (name, kind, attrs) = match event {
+                Event::Tag(name, kind, attrs) => (name, kind, attrs),
+                Event::Text(text) if stack.last().map(|e| e.name.as_str()) == Some("style") => {
+                    style.push_str(text);
+                    continue;
+                }
+                Event::Error(e) => return Err(Error::BackendError(Box::new(e))),
+                _ => continue,
+            };
+            let element = Element {
+                name: name.to_owned(),
+                attrs: attrs.into_iter().map(|(k, v)| (k, v.into())).collect(),
+                children: Vec::new(),
+            };
+            let done = match kind {
+                Type::Start => {
+                    stack.push(element);
+                    continue;
+                }
+                Type::Empty => element,
+                Type::End => match stack.pop() {
+                    Some(element) => element,
+                    None => continue,
+                },
+            };
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(done),
+                None => root = Some(done),
+            }
+        }
+        let mut root = root
+            .filter(|root| root.name == "svg")
+            .ok_or(Error::InvalidInput)?;
+        root.apply_styles(&class_rules(&style));
+        let size = Size::new(length(root.attr("width")), length(root.attr("height")));
+        let view_box = root.attr("viewBox").map(numbers).unwrap_or_default();
+        let view_box = match view_box[..] {
+            [x, y, width, height] => Rect::new(x, y, x + width, y + height),
+            _ => size.to_rect(),
+        };
+        // the view box is scaled to fit, and centered
+        let scale = (size.width / view_box.width()).min(size.height / view_box.height());
+        let scale = if scale.is_finite() { scale } else { 0.0 };
+        let offset = (size.to_vec2() - view_box.size().to_vec2() * scale) / 2.0;
+        let view_xf = Affine::translate(offset)
+            * Affine::scale(scale)
+            * Affine::translate(-view_box.origin().to_vec2());
+        Ok(Document {
+            size: Size::new(size.width.ceil(), size.height.ceil()),
+            view_xf,
+            viewport: view_box.size(),
+            root,
+        })
+    }
+
+    /// The size of the document in pixels, rounded up.
+    pub(crate) fn size(&self) -> Size {
+        self.size
+    }
+
+    /// The transform from user space to pixels.
+    pub(crate) fn view_transform(&self) -> Affine {
+        self.view_xf
+    }
+
+    /// Draw an area of the document, in pixels; it should have integer
+    /// bounds.
+    pub(crate) fn render(&self, area: Rect) -> ImageBuf {
+        let mut ids = HashMap::new();
+        self.root.collect_ids(&mut ids);
+        let renderer = Renderer {
+            ids,
+            viewport: self.viewport,
+        };
+        let mut layer = Layer::new(area.width() as usize, area.height() as usize);
+        let xf = Affine::translate(-area.origin().to_vec2()) * self.view_xf;
+        renderer.draw_children(&self.root, xf, &mut layer);
+        layer.into_image_buf()
+    }
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.get(name).map(String::as_str)
+    }
+
+    /// A numeric attribute, or `default` if it is missing or invalid.
+    fn num(&self, name: &str, default: f64) -> f64 {
+        self.attr(name)
+            .and_then(|x| x.trim().parse().ok())
+            .unwrap_or(default)
+    }
+
+    /// Set the properties of the classes and `style` attributes of this
+    /// element and its descendants, which override their attributes.
+    fn apply_styles(&mut self, rules: &HashMap<&str, Vec<(&str, &str)>>) {
+        let classes = self.attr("class").unwrap_or_default().to_owned();
+        for class in classes.split_whitespace() {
+            for &(name, value) in rules.get(class).into_iter().flatten() {
+                self.attrs.insert(name.to_owned(), value.to_owned());
+            }
+        }
+        let style = self.attr("style").unwrap_or_default().to_owned();
+        for (name, value) in declarations(&style) {
+            self.attrs.insert(name.to_owned(), value.to_owned());
+        }
+        for child in &mut self.children {
+            child.apply_styles(rules);
+        }
+    }
+
+    fn collect_ids<'a>(&'a self, ids: &mut HashMap<&'a str, &'a Element>) {
+        if let Some(id) = self.attr("id") {
+            ids.insert(id, self);
+        }
+        for child in &self.children {
+            child.collect_ids(ids);
+        }
+    }
+}
+
+struct Renderer<'a> {
+    ids: HashMap<&'a str, &'a Element>,
+    viewport: Size,
+}
+
+impl<'a> Renderer<'a> {
+    /// The element referred to by a `url(#id)`.
+    fn lookup(&self, url: &str) -> Option<&'a Element> {
+        let id = url.trim().strip_prefix("url(#")?.strip_suffix(')')?;
+        self.ids.get(id).copied()
+    }
+
+    fn draw_children(&self, parent: &Element, xf: Affine, layer: &mut Layer) {
+        for child in &parent.children {
+            self.draw(child, xf, layer);
+        }
+    }
+
+    fn draw(&self, element: &Element, xf: Affine, layer: &mut Layer) {
+        let xf = xf * element.attr("transform").map(transform).unwrap_or_default();
+        match element.attr("mix-blend-mode").and_then(Blend::parse) {
+            Some(blend) => {
+                // the element is drawn on its own, and then blended
+                let mut isolated = Layer::new(layer.width, layer.height);
+                self.draw_unblended(element, xf, &mut isolated);
+                layer.blend(&isolated, blend);
+            }
+            None => self.draw_unblended(element, xf, layer),
+        }
+    }
+
+    fn draw_unblended(&self, element: &Element, xf: Affine, layer: &mut Layer) {
+        let clip = element.attr("clip-path").and_then(|url| self.lookup(url));
+        match clip {
+            Some(clip) if clip.name == "clipPath" => {
+                let mut clipped = Layer::new(layer.width, layer.height);
+                self.draw_unclipped(element, xf, &mut clipped);
+                let mask = self.clip_mask(clip, xf, layer.width, layer.height);
+                layer.composite(&clipped, &mask);
+            }
+            _ => self.draw_unclipped(element, xf, layer),
+        }
+    }
+
+    fn draw_unclipped(&self, element: &Element, xf: Affine, layer: &mut Layer) {
+        match element.name.as_str() {
+            "g" => self.draw_children(element, xf, layer),
+            "rect" | "circle" | "path" => self.draw_shape(element, xf, layer),
+            "image" => self.draw_image(element, xf, layer),
+            // definitions, metadata and text
+            _ => {}
+        }
+    }
+
+    /// The outline of a shape element, in its user space.
+    fn shape(&self, element: &Element) -> Option<BezPath> {
+        let (width, height) = (self.viewport.width, self.viewport.height);
+        match element.name.as_str() {
+            "rect" => {
+                let x = percentage(element.attr("x"), width);
+                let y = percentage(element.attr("y"), height);
+                let w = percentage(element.attr("width"), width);
+                let h = percentage(element.attr("height"), height);
+                let rect = Rect::new(x, y, x + w, y + h);
+                let radius = element.num("rx", 0.0).min(w / 2.0).min(h / 2.0);
+                if radius > 0.0 {
+                    Some(rect.to_rounded_rect(radius).into_bez_path(1e-3))
+                } else {
+                    Some(rect.into_bez_path(0.0))
+                }
+            }
+            "circle" => {
+                let center = Point::new(element.num("cx", 0.0), element.num("cy", 0.0));
+                let circle = Circle::new(center, element.num("r", 0.0));
+                Some(circle.into_bez_path(1e-3))
+            }
+            "path" => element.attr("d").map(path),
+            _ => None,
+        }
+    }
+
+    fn draw_shape(&self, element: &Element, xf: Affine, layer: &mut Layer) {
+        let shape = match self.shape(element) {
+            Some(shape) => shape,
+            None => return,
+        };
+        let opacity = element.num("opacity", 1.0);
+        let fill_opacity = element.num("fill-opacity", 1.0) * opacity;
+        let fill = self.shader(element.attr("fill").unwrap_or("#000000"), fill_opacity, xf);
+        if let Some(fill) = fill {
+            let polygons = fill_polygons(&(xf * shape.clone()));
+            let even_odd = element.attr("fill-rule") == Some("evenodd");
+            layer.paint(&Coverage::new(&polygons, even_odd, layer), &fill);
+        }
+        let stroke_opacity = element.num("stroke-opacity", 1.0) * opacity;
+        let stroke = self.shader(element.attr("stroke").unwrap_or("none"), stroke_opacity, xf);
+        if let Some(stroke) = stroke {
+            let style = StrokeStyle::from_attrs(element);
+            let polygons: Vec<Vec<Point>> =
+                if element.attr("vector-effect") == Some("non-scaling-stroke") {
+                    // the stroke is in pixels
+                    let polygons = style.polygons(&(xf * shape), TOLERANCE);
+                    polygons.into_iter().map(orient).collect()
+                } else {
+                    let scale = max_scale(xf);
+                    if scale == 0.0 {
+                        return;
+                    }
+                    style
+                        .polygons(&shape, TOLERANCE / scale)
+                        .into_iter()
+                        .map(|polygon| orient(polygon.into_iter().map(|p| xf * p).collect()))
+                        .collect()
+                };
+            layer.paint(&Coverage::new(&polygons, false, layer), &stroke);
+        }
+    }
+
+    fn draw_image(&self, element: &Element, xf: Affine, layer: &mut Layer) {
+        let href = element.attr("href").or_else(|| element.attr("xlink:href"));
+        let image = match href.and_then(decode_image) {
+            Some(image) => image,
+            None => return,
+        };
+        let (x, y) = (element.num("x", 0.0), element.num("y", 0.0));
+        let (width, height) = (element.num("width", 0.0), element.num("height", 0.0));
+        let rect = Rect::new(x, y, x + width, y + height);
+        if rect.area() == 0.0 || image.width == 0 || image.height == 0 {
+            return;
+        }
+        let to_user = Affine::new([
+            width / image.width as f64,
+            0.0,
+            0.0,
+            height / image.height as f64,
+            x,
+            y,
+        ]);
+        let smooth = element.attr("image-rendering") != Some("pixelated");
+        let polygons = fill_polygons(&(xf * rect.into_bez_path(0.0)));
+        let shader = Shader {
+            kind: ShaderKind::Image(image, smooth),
+            inv: (xf * to_user).inverse(),
+            opacity: element.num("opacity", 1.0) as f32,
+        };
+        layer.paint(&Coverage::new(&polygons, false, layer), &shader);
+    }
+
+    /// The coverage of each pixel by a clip path.
+    fn clip_mask(&self, clip: &Element, xf: Affine, width: usize, height: usize) -> Vec<f32> {
+        let mut mask = vec![0.0; width * height];
+        for child in &clip.children {
+            let shape = match self.shape(child) {
+                Some(shape) => shape,
+                None => continue,
+            };
+            let xf = xf * child.attr("transform").map(transform).unwrap_or_default();
+            let polygons = fill_polygons(&(xf * shape));
+            let even_odd = child.attr("clip-rule") == Some("evenodd");
+            let coverage = Coverage::new(&polygons, even_odd, &Layer::new(width, height));
+            coverage.for_each(|x, y, value| {
+                let covered = &mut mask[y * width + x];
+                *covered = (*covered + value).min(1.0);
+            });
+        }
+        mask
+    }
+
+    /// The shader for a fill or stroke, or `None` for no paint.
+    fn shader(&self, paint: &str, opacity: f64, xf: Affine) -> Option<Shader> {
+        if paint == "none" {
+            return None;
+        }
+        if let Some(color) = color(paint) {
+            return Some(Shader::solid(premultiply(color, opacity)));
+        }
+        let gradient = self.lookup(paint)?;
+        let mut stops: Vec<(f64, [f32; 4])> = Vec::new();
+        for stop in gradient
+            .children
+            .iter()
+            .filter(|child| child.name == "stop")
+        {
+            let last = stops.last().map(|&(offset, _)| offset).unwrap_or(0.0);
+            let offset = percentage(stop.attr("offset"), 1.0).clamp(0.0, 1.0);
+            let color = stop.attr("stop-color").and_then(color).unwrap_or([0.0; 3]);
+            let opacity = stop.num("stop-opacity", 1.0) * opacity;
+            stops.push((offset.max(last), premultiply(color, opacity)));
+        }
+        match stops[..] {
+            [] => return None,
+            [(_, color)] => return Some(Shader::solid(color)),
+            _ => {}
+        }
+        let spread = match gradient.attr("spreadMethod") {
+            Some("repeat") => Spread::Repeat,
+            Some("reflect") => Spread::Reflect,
+            _ => Spread::Pad,
+        };
+        let gradient_xf = gradient
+            .attr("gradientTransform")
+            .map(transform)
+            .unwrap_or_default();
+        let geometry = match gradient.name.as_str() {
+            "linearGradient" => {
+                let start = Point::new(gradient.num("x1", 0.0), gradient.num("y1", 0.0));
+                let end = Point::new(gradient.num("x2", 0.0), gradient.num("y2", 0.0));
+                Geometry::Linear {
+                    start,
+                    direction: end - start,
+                }
+            }
+            "radialGradient" => {
+                let center = Point::new(gradient.num("cx", 0.0), gradient.num("cy", 0.0));
+                let focus = Point::new(gradient.num("fx", center.x), gradient.num("fy", center.y));
+                Geometry::Radial {
+                    focus,
+                    to_center: center - focus,
+                    radius: gradient.num("r", 0.0),
+                }
+            }
+            _ => return None,
+        };
+        Some(Shader {
+            kind: ShaderKind::Gradient(geometry, stops, spread),
+            inv: (xf * gradient_xf).inverse(),
+            opacity: 1.0,
+        })
+    }
+}
+
+/// Premultiplied RGBA pixels.
+struct Layer {
+    width: usize,
+    height: usize,
+    pixels: Vec<[f32; 4]>,
+}
+
+impl Layer {
+    fn new(width: usize, height: usize) -> Layer {
+        Layer {
+            width,
+            height,
+            pixels: vec![[0.0; 4]; width * height],
+        }
+    }
+
+    /// Paint the covered pixels, over what is already there.
+    fn paint(&mut self, coverage: &Coverage, shader: &Shader) {
+        let width = self.width;
+        let pixels = &mut self.pixels;
+        coverage.for_each(|x, y, value| {
+            let color = shader.color(Point::new(x as f64 + 0.5, y as f64 + 0.5));
+            over(&mut pixels[y * width + x], color, value);
+        });
+    }
+
+    /// Draw another layer over this one, masked by the coverage of each
+    /// pixel.
+    fn composite(&mut self, layer: &Layer, mask: &[f32]) {
+        for ((dst, src), &value) in self.pixels.iter_mut().zip(&layer.pixels).zip(mask) {
+            if value > 0.0 {
+                over(dst, *src, value);
+            }
+        }
+    }
+
+    /// Blend another layer with this one.
+    fn blend(&mut self, layer: &Layer, blend: Blend) {
+        for (dst, src) in self.pixels.iter_mut().zip(&layer.pixels) {
+            let (src_alpha, dst_alpha) = (src[3], dst[3]);
+            if src_alpha == 0.0 {
+                continue;
+            }
+            for i in 0..3 {
+                let blended = if dst_alpha == 0.0 {
+                    0.0
+                } else {
+                    blend.apply(src[i] / src_alpha, dst[i] / dst_alpha)
+                };
+                dst[i] = src[i] * (1.0 - dst_alpha)
+                    + dst[i] * (1.0 - src_alpha)
+                    + src_alpha * dst_alpha * blended;
+            }
+            dst[3] = src_alpha + dst_alpha * (1.0 - src_alpha);
+        }
+    }
+
+    fn into_image_buf(self) -> ImageBuf {
+        let bytes: Vec<u8> = self
+            .pixels
+            .iter()
+            .flat_map(|pixel| pixel.iter())
+            .map(|&x| (x.clamp(0.0, 1.0) * 255.0).round() as u8)
+            .collect();
+        ImageBuf::from_raw(bytes, ImageFormat::RgbaPremul, self.width, self.height)
+    }
+}
+
+/// A separable blend mode of CSS, other than `normal`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Blend {
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+}
+
+impl Blend {
+    fn parse(value: &str) -> Option<Blend> {
+        match value.trim() {
+            "multiply" => Some(Blend::Multiply),
+            "screen" => Some(Blend::Screen),
+            "overlay" => Some(Blend::Overlay),
+            "darken" => Some(Blend::Darken),
+            "lighten" => Some(Blend::Lighten),
+            _ => None,
+        }
+    }
+
+    /// Blend a component of a color with one of the color under it, both
+    /// not premultiplied.
+    fn apply(self, src: f32, dst: f32) -> f32 {
+        let screen = |a: f32, b: f32| a + b - a * b;
+        match self {
+            Blend::Multiply => src * dst,
+            Blend::Screen => screen(src, dst),
+            Blend::Overlay if dst <= 0.5 => src * 2.0 * dst,
+            Blend::Overlay => screen(src, 2.0 * dst - 1.0),
+            Blend::Darken => src.min(dst),
+            Blend::Lighten => src.max(dst),
+        }
+    }
+}
+
+/// Draw a premultiplied color over a pixel, scaled by `coverage`.
+fn over(dst: &mut [f32; 4], src: [f32; 4], coverage: f32) {
+    let keep = 1.0 - src[3] * coverage;
+    for (dst, src) in dst.iter_mut().zip(&src) {
+        *dst = src * coverage + *dst * keep;
+    }
+}
+
+fn premultiply(color: [f32; 3], opacity: f64) -> [f32; 4] {
+    let alpha = opacity.clamp(0.0, 1.0) as f32;
+    [color[0] * alpha, color[1] * alpha, color[2] * alpha, alpha]
+}
+
+/// The coverage of the pixels in the bounds of a shape.
+struct Coverage {
+    x0: usize,
+    y0: usize,
+    width: usize,
+    values: Vec<f32>,
+}
+
+#[derive(Clone, Copy)]
+struct Edge {
+    top: Point,
+    bottom: Point,
+    winding: i32,
+}
+
+impl Coverage {
+    /// The coverage of the pixels of a layer by the area inside polygons,
+    /// by the nonzero or even-odd rule.
+    ///
+    /// Each row of pixels is sampled along `SUBSAMPLES` lines, and the
+    /// coverage along each line is exact.
+    fn new(polygons: &[Vec<Point>], even_odd: bool, layer: &Layer) -> Coverage {
+        let mut edges = Vec::new();
+        let mut bounds: Option<Rect> = None;
+        for polygon in polygons {
+            for (i, &p0) in polygon.iter().enumerate() {
+                let p1 = polygon[(i + 1) % polygon.len()];
+                if !(p0.x.is_finite() && p0.y.is_finite() && p1.x.is_finite() && p1.y.is_finite()) {
+                    continue;
+                }
+                bounds = Some(match bounds {
+                    Some(bounds) => bounds.union_pt(p0),
+                    None => Rect::from_points(p0, p0),
+                });
+                let (top, bottom, winding) = match p0.y.partial_cmp(&p1.y) {
+                    Some(std::cmp::Ordering::Less) => (p0, p1, 1),
+                    Some(std::cmp::Ordering::Greater) => (p1, p0, -1),
+                    _ => continue,
+                };
+                edges.push(Edge {
+                    top,
+                    bottom,
+                    winding,
+                });
+            }
+        }
+        let bounds = bounds.unwrap_or_default().expand().intersect(Rect::new(
+            0.0,
+            0.0,
+            layer.width as f64,
+            layer.height as f64,
+        ));
+        let empty = Coverage {
+            x0: 0,
+            y0: 0,
+            width: 0,
+            values: Vec::new(),
+        };
+        if bounds.width() <= 0.0 || bounds.height() <= 0.0 {
+            return empty;
+        }
+        let (x0, y0) = (bounds.x0 as usize, bounds.y0 as usize);
+        let (width, height) = (bounds.width() as usize, bounds.height() as usize);
+        edges.sort_by(|a, b| a.top.y.partial_cmp(&b.top.y).unwrap());
+
+        let mut values = vec![0.0f32; width * height];
+        // changes in the coverage of whole pixels, from left to right
+        let mut steps = vec![0.0f32; width + 1];
+        let mut active: Vec<Edge> = Vec::new();
+        let mut next = 0;
+        let mut crossings: Vec<(f64, i32)> = Vec::new();
+        let weight = 1.0 / SUBSAMPLES as f32;
+        for (row, cells) in values.chunks_mut(width).enumerate() {
+            steps.iter_mut().for_each(|step| *step = 0.0);
+            for k in 0..SUBSAMPLES {
+                let y = (y0 + row) as f64 + (k as f64 + 0.5) / SUBSAMPLES as f64;
+                while next < edges.len() && edges[next].top.y <= y {
+                    active.push(edges[next]);
+                    next += 1;
+                }
+                active.retain(|edge| edge.bottom.y > y);
+                crossings.clear();
+                crossings.extend(active.iter().map(|edge| {
+                    let t = (y - edge.top.y) / (edge.bottom.y - edge.top.y);
+                    let x = edge.top.x + t * (edge.bottom.x - edge.top.x);
+                    (x - x0 as f64, edge.winding)
+                }));
+                crossings.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+                let mut winding = 0;
+                for i in 1..crossings.len() {
+                    winding += crossings[i - 1].1;
+                    let inside = if even_odd {
+                        winding % 2 != 0
+                    } else {
+                        winding != 0
+                    };
+                    if !inside {
+                        continue;
+                    }
+                    let start = crossings[i - 1].0.max(0.0).min(width as f64);
+                    let end = crossings[i].0.max(0.0).min(width as f64);
+                    if end <= start {
+                        continue;
+                    }
+                    let (first, last) = (start as usize, end as usize);
+                    if first == last {
+                        cells[first] += (end - start) as f32 * weight;
+                        continue;
+                    }
+                    cells[first] += (first as f64 + 1.0 - start) as f32 * weight;
+                    steps[first + 1] += weight;
+                    steps[last] -= weight;
+                    if last < width {
+                        cells[last] += (end - last as f64) as f32 * weight;
+                    }
+                }
+            }
+            let mut whole = 0.0;
+            for (cell, step) in cells.iter_mut().zip(&steps) {
+                whole += step;
+                *cell = (*cell + whole).min(1.0);
+            }
+        }
+        Coverage {
+            x0,
+            y0,
+            width,
+            values,
+        }
+    }
+
+    /// Call `f` with the position and coverage of each covered pixel.
+    fn for_each(&self, mut f: impl FnMut(usize, usize, f32)) {
+        if self.width == 0 {
+            return;
+        }
+        for (i, &value) in self.values.iter().enumerate() {
+            if value > 0.0 {
+                f(self.x0 + i % self.width, self.y0 + i / self.width, value);
+            }
+        }
+    }
+}
+
+/// A solid color, gradient or image, in a space that pixels are mapped to.
+struct Shader {
+    kind: ShaderKind,
+    /// The transform from pixels to the space of the shader.
+    inv: Affine,
+    opacity: f32,
+}
+
+enum ShaderKind {
+    Solid([f32; 4]),
+    /// The stops are premultiplied colors at increasing offsets.
+    Gradient(Geometry, Vec<(f64, [f32; 4])>, Spread),
+    /// Premultiplied RGBA pixels, and whether they're interpolated.
+    Image(RgbaImage, bool),
+}
+
+/// How the parameter of a gradient varies.
+enum Geometry {
+    /// The parameter of a linear gradient is the distance along
+    /// `direction`, from 0 at `start` to 1 at `start + direction`.
+    Linear { start: Point, direction: Vec2 },
+    /// The parameter of a radial gradient is 0 at the focus and 1 on the
+    /// circle, with circles in between interpolated from the focus.
+    Radial {
+        focus: Point,
+        to_center: Vec2,
+        radius: f64,
+    },
+}
+
+#[derive(Clone, Copy)]
+enum Spread {
+    Pad,
+    Repeat,
+    Reflect,
+}
+
+impl Shader {
+    fn solid(color: [f32; 4]) -> Shader {
+        Shader {
+            kind: ShaderKind::Solid(color),
+            inv: Affine::default(),
+            opacity: 1.0,
+        }
+    }
+
+    /// The premultiplied color at the center of a pixel.
+    fn color(&self, p: Point) -> [f32; 4] {
+        let p = self.inv * p;
+        let (geometry, stops, spread) = match &self.kind {
+            ShaderKind::Solid(color) => return *color,
+            ShaderKind::Image(image, smooth) => {
+                let color = image.sample(p, *smooth);
+                return [
+                    color[0] * self.opacity,
+                    color[1] * self.opacity,
+                    color[2] * self.opacity,
+                    color[3] * self.opacity,
+                ];
+            }
+            ShaderKind::Gradient(geometry, stops, spread) => (geometry, stops, *spread),
+        };
+        let t = match *geometry {
+            Geometry::Linear { start, direction } => {
+                let length = direction.hypot2();
+                if length == 0.0 {
+                    f64::INFINITY
+                } else {
+                    (p - start).dot(direction) / length
+                }
+            }
+            Geometry::Radial {
+                focus,
+                to_center,
+                radius,
+            } => radial_parameter(p - focus, to_center, radius),
+        };
+        let t = match spread {
+            _ if !t.is_finite() => 1.0,
+            Spread::Pad => t,
+            Spread::Repeat => t - t.floor(),
+            Spread::Reflect => {
+                let t = t.rem_euclid(2.0);
+                if t > 1.0 {
+                    2.0 - t
+                } else {
+                    t
+                }
+            }
+        };
+        let (first, last) = (stops[0], stops[stops.len() - 1]);
+        if t <= first.0 {
+            return first.1;
+        }
+        if t >= last.0 {
+            return last.1;
+        }
+        for pair in stops.windows(2) {
+            let ((t0, c0), (t1, c1)) = (pair[0], pair[1]);
+            if t < t1 {
+                let s = if t1 > t0 {
+                    ((t - t0) / (t1 - t0)) as f32
+                } else {
+                    1.0
+                };
+                let mut color = [0.0; 4];
+                for i in 0..4 {
+                    color[i] = c0[i] + (c1[i] - c0[i]) * s;
+                }
+                return color;
+            }
+        }
+        last.1
+    }
+}
+
+/// The parameter of a radial gradient at `q` from the focus: the `t` for
+/// which `q` is on the circle around `to_center * t` with radius
+/// `radius * t`.
+fn radial_parameter(q: Vec2, to_center: Vec2, radius: f64) -> f64 {
+    if radius <= 0.0 {
+        return f64::INFINITY;
+    }
+    // |q - t e|^2 = (t r)^2 is a quadratic in t
+    let a = to_center.hypot2() - radius * radius;
+    let b = q.dot(to_center);
+    let c = q.hypot2();
+    if a.abs() < 1e-9 {
+        // the focus is on the circle
+        return if b > 0.0 {
+            c / (2.0 * b)
+        } else {
+            f64::INFINITY
+        };
+    }
+    let discriminant = b * b - a * c;
+    if discriminant < 0.0 {
+        return f64::INFINITY;
+    }
+    (b - discriminant.sqrt()) / a
+}
+
+/// An image decoded from the document.
+struct RgbaImage {
+    width: usize,
+    height: usize,
+    /// Premultiplied RGBA.
+    pixels: Vec<[f32; 4]>,
+}
+
+impl RgbaImage {
+    fn pixel(&self, x: f64, y: f64) -> [f32; 4] {
+        let x = (x.max(0.0) as usize).min(self.width - 1);
+        let y = (y.max(0.0) as usize).min(self.height - 1);
+        self.pixels[y * self.width + x]
+    }
+
+    fn sample(&self, p: Point, smooth: bool) -> [f32; 4] {
+        if !smooth {
+            return self.pixel(p.x, p.y);
+        }
+        let (x, y) = (p.x - 0.5, p.y - 0.5);
+        let (fx, fy) = ((x - x.floor()) as f32, (y - y.floor()) as f32);
+        let (x, y) = (x.floor(), y.floor());
+        let corners = [
+            (self.pixel(x, y), (1.0 - fx) * (1.0 - fy)),
+            (self.pixel(x + 1.0, y), fx * (1.0 - fy)),
+            (self.pixel(x, y + 1.0), (1.0 - fx) * fy),
+            (self.pixel(x + 1.0, y + 1.0), fx * fy),
+        ];
+        let mut color = [0.0; 4];
+        for (pixel, weight) in corners.iter() {
+            for i in 0..4 {
+                color[i] += pixel[i] * weight;
+            }
+        }
+        color
+    }
+}
+
+/// Decode a PNG image from a `data:` URI or a file.
+fn decode_image(href: &str) -> Option<RgbaImage> {
+    let data = match href.strip_prefix("data:image/png;base64,") {
+        Some(data) => decode_base64(data)?,
+        None => fs::read(href).ok()?,
+    };
+    let mut decoder = png::Decoder::new(&data[..]);
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let (info, mut reader) = decoder.read_info().ok()?;
+    let mut buf = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buf).ok()?;
+    if info.bit_depth != png::BitDepth::Eight {
+        return None;
+    }
+    let channels = match info.color_type {
+        png::ColorType::RGBA => 4,
+        png::ColorType::RGB => 3,
+        _ => return None,
+    };
+    let pixels = buf
+        .chunks(channels)
+        .map(|pixel| {
+            let alpha = pixel.get(3).map(|&a| a as f32 / 255.0).unwrap_or(1.0);
+            let channel = |i: usize| pixel[i] as f32 / 255.0 * alpha;
+            [channel(0), channel(1), channel(2), alpha]
+        })
+        .collect();
+    Some(RgbaImage {
+        width: info.width as usize,
+        height: info.height as usize,
+        pixels,
+    })
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut data = Vec::with_capacity(text.len() / 4 * 3);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for byte in text.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            _ => return None,
+        };
+        bits = bits << 6 | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            data.push((bits >> count) as u8);
+        }
+    }
+    Some(data)
+}
+
+/// How a shape is stroked.
+struct StrokeStyle {
+    half_width: f64,
+    join: Join,
+    miter_limit: f64,
+    cap: Cap,
+    /// The dash pattern, and the distance into it that the stroke starts.
+    dash: Option<(Vec<f64>, f64)>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Join {
+    Miter,
+    Round,
+    Bevel,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Cap {
+    Butt,
+    Round,
+    Square,
+}
+
+impl StrokeStyle {
+    fn from_attrs(element: &Element) -> StrokeStyle {
+        let join = match element.attr("stroke-linejoin") {
+            Some("round") => Join::Round,
+            Some("bevel") => Join::Bevel,
+            _ => Join::Miter,
+        };
+        let cap = match element.attr("stroke-linecap") {
+            Some("round") => Cap::Round,
+            Some("square") => Cap::Square,
+            _ => Cap::Butt,
+        };
+        let dashes = element
+            .attr("stroke-dasharray")
+            .map(numbers)
+            .unwrap_or_default();
+        let valid = dashes.iter().all(|&x| x >= 0.0) && dashes.iter().sum::<f64>() > 0.0;
+        let dash = if valid {
+            // an odd number of dashes is repeated
+            let dashes = if dashes.len() % 2 == 1 {
+                dashes.repeat(2)
+            } else {
+                dashes
+            };
+            Some((dashes, element.num("stroke-dashoffset", 0.0)))
+        } else {
+            None
+        };
+        StrokeStyle {
+            half_width: element.num("stroke-width", 1.0).max(0.0) / 2.0,
+            join,
+            miter_limit: element.num("stroke-miterlimit", 4.0),
+            cap,
+            dash,
+        }
+    }
+
+    /// The outline of the stroke of a shape, as convex polygons whose union
+    /// is the stroke.
+    fn polygons(&self, shape: &BezPath, tolerance: f64) -> Vec<Vec<Point>> {
+        let mut polygons = Vec::new();
+        if self.half_width == 0.0 {
+            return polygons;
+        }
+        for (points, closed) in polylines(shape, tolerance) {
+            match &self.dash {
+                Some((dashes, offset)) => {
+                    for dash in dash(&points, closed, dashes, *offset) {
+                        self.stroke_polyline(&dash, false, tolerance, &mut polygons);
+                    }
+                }
+                None => self.stroke_polyline(&points, closed, tolerance, &mut polygons),
+            }
+        }
+        polygons
+    }
+
+    fn stroke_polyline(
+        &self,
+        points: &[Point],
+        closed: bool,
+        tolerance: f64,
+        polygons: &mut Vec<Vec<Point>>,
+    ) {
+        let w = self.half_width;
+        if points.len() == 1 {
+            // a subpath of zero length only has caps
+            let p = points[0];
+            match self.cap {
+                Cap::Butt => {}
+                Cap::Round => polygons.push(circle(p, w, tolerance)),
+                Cap::Square => polygons.push(vec![
+                    Point::new(p.x - w, p.y - w),
+                    Point::new(p.x + w, p.y - w),
+                    Point::new(p.x + w, p.y + w),
+                    Point::new(p.x - w, p.y + w),
+                ]),
+            }
+            return;
+        }
+        let mut segments: Vec<(Point, Point)> = points.windows(2).map(|s| (s[0], s[1])).collect();
+        if closed && points.len() > 2 {
+            segments.push((points[points.len() - 1], points[0]));
+        }
+        let direction = |(a, b): (Point, Point)| (b - a).normalize();
+        for &segment in &segments {
+            let normal = perp(direction(segment)) * w;
+            let (a, b) = segment;
+            polygons.push(vec![a + normal, b + normal, b - normal, a - normal]);
+        }
+        for pair in segments.windows(2) {
+            self.join(
+                pair[0].1,
+                direction(pair[0]),
+                direction(pair[1]),
+                tolerance,
+                polygons,
+            );
+        }
+        if closed && segments.len() > 2 {
+            let (first, last) = (segments[0], segments[segments.len() - 1]);
+            self.join(
+                first.0,
+                direction(last),
+                direction(first),
+                tolerance,
+                polygons,
+            );
+        } else {
+            let (first, last) = (segments[0], segments[segments.len() - 1]);
+            self.cap(first.0, -direction(first), tolerance, polygons);
+            self.cap(last.1, direction(last), tolerance, polygons);
+        }
+    }
+
+    /// The join at `p` between segments in the directions `d0` and `d1`.
+    fn join(&self, p: Point, d0: Vec2, d1: Vec2, tolerance: f64, polygons: &mut Vec<Vec<Point>>) {
+        let w = self.half_width;
+        let cross = d0.cross(d1);
+        if cross.abs() < 1e-12 && d0.dot(d1) > 0.0 {
+            return;
+        }
+        if self.join == Join::Round {
+            polygons.push(circle(p, w, tolerance));
+            return;
+        }
+        // the offsets on the outside of the turn
+        let side = if cross > 0.0 { -w } else { w };
+        let (a, b) = (p + perp(d0) * side, p + perp(d1) * side);
+        let bisector = (a - p) + (b - p);
+        // the ratio of the miter length to the stroke width
+        let ratio = 2.0 * w / bisector.hypot();
+        if self.join == Join::Miter && ratio <= self.miter_limit {
+            let miter = p + bisector * (ratio * ratio / 2.0);
+            polygons.push(orient(vec![p, a, miter, b]));
+        } else {
+            polygons.push(orient(vec![p, a, b]));
+        }
+    }
+
+    /// The cap at the end `p` of a stroke going in the direction `d`.
+    fn cap(&self, p: Point, d: Vec2, tolerance: f64, polygons: &mut Vec<Vec<Point>>) {
+        let w = self.half_width;
+        match self.cap {
+            Cap::Butt => {}
+            Cap::Round => polygons.push(circle(p, w, tolerance)),
+            Cap::Square => {
+                let (n, d) = (perp(d) * w, d * w);
+                polygons.push(vec![p + n, p + n + d, p - n + d, p - n]);
+            }
+        }
+    }
+}
+
+fn perp(v: Vec2) -> Vec2 {
+    Vec2::new(-v.y, v.x)
+}
+
+/// A polygon with its vertices in the order of increasing angle, so that
+/// the windings of overlapping polygons add up.
+fn orient(mut polygon: Vec<Point>) -> Vec<Point> {
+    let mut area = 0.0;
+    for (i, p0) in polygon.iter().enumerate() {
+        let p1 = polygon[(i + 1) % polygon.len()];
+        area += p0.to_vec2().cross(p1.to_vec2());
+    }
+    if area < 0.0 {
+        polygon.reverse();
+    }
+    polygon
+}
+
+fn circle(center: Point, radius: f64, tolerance: f64) -> Vec<Point> {
+    let step = 2.0 * (1.0 - tolerance / radius).max(-1.0).acos();
+    let count = ((2.0 * PI / step).ceil() as usize).clamp(8, 1024);
+    (0..count)
+        .map(|i| center + Vec2::from_angle(2.0 * PI * i as f64 / count as f64) * radius)
+        .collect()
+}
+
+/// The pieces of a polyline that are drawn by a dash pattern.
+fn dash(points: &[Point], closed: bool, dashes: &[f64], offset: f64) -> Vec<Vec<Point>> {
+    let total: f64 = dashes.iter().sum();
+    let mut phase = offset.rem_euclid(total);
+    let mut index = 0;
+    while phase > 0.0 && phase >= dashes[index] {
+        phase -= dashes[index];
+        index = (index + 1) % dashes.len();
+    }
+    let mut remaining = dashes[index] - phase;
+    let mut pieces = Vec::new();
+    let mut piece = if index % 2 == 0 {
+        vec![points[0]]
+    } else {
+        Vec::new()
+    };
+    let push = |piece: &mut Vec<Point>, p: Point| {
+        if piece.last() != Some(&p) {
+            piece.push(p);
+        }
+    };
+    let closing = if closed {
+        Some((points[points.len() - 1], points[0]))
+    } else {
+        None
+    };
+    let segments = points.windows(2).map(|s| (s[0], s[1])).chain(closing);
+    for (mut a, b) in segments {
+        let mut length = (b - a).hypot();
+        loop {
+            if remaining > length {
+                remaining -= length;
+                if index % 2 == 0 {
+                    push(&mut piece, b);
+                }
+                break;
+            }
+            let t = if length > 0.0 {
+                remaining / length
+            } else {
+                0.0
+            };
+            let p = a + (b - a) * t;
+            if index % 2 == 0 {
+                push(&mut piece, p);
+                pieces.push(mem::take(&mut piece));
+            } else {
+                piece = vec![p];
+            }
+            length -= remaining;
+            a = p;
+            index = (index + 1) % dashes.len();
+            remaining = dashes[index];
+        }
+    }
+    if index % 2 == 0 && !piece.is_empty() {
+        pieces.push(piece);
+    }
+    pieces
+}
+
+/// The subpaths of a path in pixels, flattened to polygons for filling.
+fn fill_polygons(path: &BezPath) -> Vec<Vec<Point>> {
+    polylines(path, TOLERANCE)
+        .into_iter()
+        .map(|(points, _)| points)
+        .collect()
+}
+
+/// The subpaths of a path, flattened to polylines, and whether they're
+/// closed.
+fn polylines(path: &BezPath, tolerance: f64) -> Vec<(Vec<Point>, bool)> {
+    let mut lines = Vec::new();
+    let mut current: Vec<Point> = Vec::new();
+    // whether the current subpath has any segments, even of zero length
+    let mut drawn = false;
+    path.flatten(tolerance, |el| match el {
+        PathEl::MoveTo(p) => {
+            if drawn {
+                lines.push((mem::take(&mut current), false));
+            }
+            current = vec![p];
+            drawn = false;
+        }
+        PathEl::LineTo(p) => {
+            if current.last() != Some(&p) {
+                current.push(p);
+            }
+            drawn = true;
+        }
+        PathEl::ClosePath => {
+            if let Some(&start) = current.first() {
+                if current.len() > 1 && current.last() == Some(&start) {
+                    current.pop();
+                }
+                lines.push((mem::replace(&mut current, vec![start]), true));
+            }
+            drawn = false;
+        }
+        _ => {}
+    });
+    if drawn {
+        lines.push((current, false));
+    }
+    lines
+}
+
+/// The largest factor that a transform scales distances by.
+fn max_scale(xf: Affine) -> f64 {
+    let [a, b, c, d, _, _] = xf.as_coeffs();
+    let sum = a * a + b * b + c * c + d * d;
+    let det = a * d - b * c;
+    ((sum + (sum * sum - 4.0 * det * det).max(0.0).sqrt()) / 2.0).sqrt()
+}
+
+/// A `matrix(...)` transform; this backend doesn't write others.
+fn transform(value: &str) -> Affine {
+    let args = value
+        .trim()
+        .strip_prefix("matrix(")
+        .and_then(|value| value.strip_suffix(')'))
+        .map(numbers)
+        .unwrap_or_default();
+    match args[..] {
+        [a, b, c, d, e, f] => Affine::new([a, b, c, d, e, f]),
+        _ => Affine::default(),
+    }
+}
+
+/// A `#rrggbb` color.
+fn color(value: &str) -> Option<[f32; 3]> {
+    let hex = value.strip_prefix('#').filter(|hex| hex.len() == 6)?;
+    let rgb = u32::from_str_radix(hex, 16).ok()?;
+    let channel = |shift: u32| ((rgb >> shift) & 0xff) as f32 / 255.0;
+    Some([channel(16), channel(8), channel(0)])
+}
+
+/// A length in pixels.
+fn length(value: Option<&str>) -> f64 {
+    const UNITS: &[(&str, f64)] = &[
+        ("px", 1.0),
+        ("pt", 96.0 / 72.0),
+        ("pc", 16.0),
+        ("mm", 96.0 / 25.4),
+        ("cm", 96.0 / 2.54),
+        ("in", 96.0),
+    ];
+    let value = value.unwrap_or("").trim();
+    for (suffix, pixels) in UNITS {
+        if let Some(x) = value.strip_suffix(suffix) {
+            return x.trim().parse::<f64>().unwrap_or(0.0) * pixels;
+        }
+    }
+    value.parse().unwrap_or(0.0)
+}
+
+/// A number, or a percentage of `reference`.
+fn percentage(value: Option<&str>, reference: f64) -> f64 {
+    let value = value.unwrap_or("").trim();
+    match value.strip_suffix('%') {
+        Some(x) => x.parse::<f64>().unwrap_or(0.0) / 100.0 * reference,
+        None => value.parse().unwrap_or(0.0),
+    }
+}
+
+/// A list of numbers separated by spaces or commas.
+/// The declarations of the rules of a style sheet with class selectors,
+/// by class; other rules, such as font faces, are ignored.
+fn class_rules(style: &str) -> HashMap<&str, Vec<(&str, &str)>> {
+    let mut rules = HashMap::new();
+    for rule in style.split('}') {
+        let (selector, block) = match rule.find('{') {
+            Some(i) => (rule[..i].trim(), &rule[i + 1..]),
+            None => continue,
+        };
+        let class = match selector.strip_prefix('.') {
+            Some(class) => class,
+            None => continue,
+        };
+        rules
+            .entry(class)
+            .or_insert_with(Vec::new)
+            .extend(declarations(block));
+    }
+    rules
+}
+
+/// The properties and values of a block of declarations.
+fn declarations(block: &str) -> impl Iterator<Item = (&str, &str)> {
+    block.split(';').filter_map(|declaration| {
+        let i = declaration.find(':')?;
+        Some((declaration[..i].trim(), declaration[i + 1..].trim()))
+    })
+}
+
+fn numbers(value: &str) -> Vec<f64> {
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|x| !x.is_empty())
+        .filter_map(|x| x.parse().ok())
+        .collect()
+}
+
+/// Path data with absolute `M`, `L`, `Q`, `C` and `Z` commands, as this
+/// backend writes it.
+fn path(data: &str) -> BezPath {
+    let mut path = BezPath::new();
+    let mut command = ' ';
+    let mut args = Vec::new();
+    for token in tokens(data) {
+        let x = match token {
+            Token::Command(c) => {
+                if c == 'Z' {
+                    path.close_path();
+                }
+                command = c;
+                args.clear();
+                continue;
+            }
+            Token::Number(x) => x,
+        };
+        args.push(x);
+        let p = |i: usize| Point::new(args[2 * i], args[2 * i + 1]);
+        match (command, args.len()) {
+            ('M', 2) => {
+                path.move_to(p(0));
+                // more points after a move are lines
+                command = 'L';
+            }
+            ('L', 2) => path.line_to(p(0)),
+            ('Q', 4) => path.quad_to(p(0), p(1)),
+            ('C', 6) => path.curve_to(p(0), p(1), p(2)),
+            _ => continue,
+        }
+        args.clear();
+    }
+    path
+}
+
+enum Token {
+    Command(char),
+    Number(f64),
+}
+
+fn tokens(data: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut number = String::new();
+    let end_number = |number: &mut String, tokens: &mut Vec<Token>| {
+        if !number.is_empty() {
+            tokens.push(Token::Number(number.parse().unwrap_or(0.0)));
+            number.clear();
+        }
+    };
+    for c in data.chars() {
+        match c {
+            '0'..='9' | '.' | 'e' | '+' => number.push(c),
+            // a minus sign starts a number, unless it's of an exponent
+            '-' => {
+                if !number.ends_with('e') {
+                    end_number(&mut number, &mut tokens);
+                }
+                number.push(c);
+            }
+            c if c.is_ascii_alphabetic() => {
+                end_number(&mut number, &mut tokens);
+                tokens.push(Token::Command(c));
+            }
+            _ => end_number(&mut number, &mut tokens),
+        }
+    }
+    end_number(&mut number, &mut tokens);
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Render a document with a view box of 0 0 8 8 at 8×8 pixels.
+    fn render(content: &str) -> ImageBuf {
+        let markup = format!(
+            r#"<svg height="8" viewBox="0 0 8 8" width="8" xmlns="http://www.w3.org/2000/svg">
+{}
+</svg>"#,
+            content
+        );
+        let doc = Document::parse(&markup).unwrap();
+        doc.render(doc.size().to_rect())
+    }
+
+    fn pixel(buf: &ImageBuf, x: usize, y: usize) -> [u8; 4] {
+        let i = (y * buf.width() + x) * 4;
+        let pixels = buf.raw_pixels();
+        [pixels[i], pixels[i + 1], pixels[i + 2], pixels[i + 3]]
+    }
+
+    #[test]
+    fn fill_coverage() {
+        let buf = render(r##"<path d="M1.5 1L4 1L4 4L1.5 4Z" fill="#ff0000" fill-opacity="1"/>"##);
+        assert_eq!(pixel(&buf, 2, 2), [255, 0, 0, 255]);
+        // half covered
+        assert_eq!(pixel(&buf, 1, 2), [128, 0, 0, 128]);
+        assert_eq!(pixel(&buf, 4, 2), [0, 0, 0, 0]);
+
+        // a square with a square hole, drawn in the same direction
+        let rings = "M0 0L8 0L8 8L0 8ZM2 2L6 2L6 6L2 6Z";
+        let nonzero = render(&format!(r#"<path d="{}"/>"#, rings));
+        assert_eq!(pixel(&nonzero, 4, 4), [0, 0, 0, 255]);
+        let even_odd = render(&format!(r#"<path d="{}" fill-rule="evenodd"/>"#, rings));
+        assert_eq!(pixel(&even_odd, 4, 4), [0, 0, 0, 0]);
+        assert_eq!(pixel(&even_odd, 1, 1), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn strokes() {
+        // a width of 2, centered on the line
+        let buf = render(r##"<path d="M0 4L8 4" fill="none" stroke="#0000ff" stroke-width="2"/>"##);
+        assert_eq!(pixel(&buf, 4, 3), [0, 0, 255, 255]);
+        assert_eq!(pixel(&buf, 4, 4), [0, 0, 255, 255]);
+        assert_eq!(pixel(&buf, 4, 2), [0, 0, 0, 0]);
+        assert_eq!(pixel(&buf, 4, 5), [0, 0, 0, 0]);
+
+        // dashes of 2 with gaps of 2, and square caps in the gaps
+        let dashes =
+            r##"<path d="M0 4.5L8 4.5" fill="none" stroke="#000000" stroke-dasharray="2 2"/>"##;
+        let buf = render(dashes);
+        let row: Vec<u8> = (0..8).map(|x| pixel(&buf, x, 4)[3]).collect();
+        assert_eq!(row, [255, 255, 0, 0, 255, 255, 0, 0]);
+        let buf = render(&dashes.replace("/>", r#" stroke-linecap="square"/>"#));
+        let row: Vec<u8> = (0..8).map(|x| pixel(&buf, x, 4)[3]).collect();
+        assert_eq!(row, [255, 255, 128, 128, 255, 255, 128, 128]);
+
+        // the miter of a right angle reaches the corner of the square
+        let corner = r##"<path d="M1 7L1 1L7 1" fill="none" stroke="#000000" stroke-width="2"/>"##;
+        assert_eq!(pixel(&render(corner), 0, 0)[3], 255);
+        let bevel = corner.replace("/>", r#" stroke-linejoin="bevel"/>"#);
+        assert_eq!(pixel(&render(&bevel), 0, 0)[3], 128);
+
+        // a non-scaling stroke is as wide in pixels under a scale
+        let scaled = r##"<g transform="matrix(4 0 0 4 0 0)">
+<path d="M0 1L2 1" fill="none" stroke="#000000" stroke-width="2" vector-effect="non-scaling-stroke"/>
+</g>"##;
+        let column: Vec<u8> = (0..8).map(|y| pixel(&render(scaled), 4, y)[3]).collect();
+        assert_eq!(column, [0, 0, 0, 255, 255, 0, 0, 0]);
+    }
+
+    #[test]
+    fn gradients_and_clips() {
+        let buf = render(
+            r##"<defs>
+<linearGradient gradientUnits="userSpaceOnUse" id="a" spreadMethod="reflect" x1="0" x2="4" y1="0" y2="0">
+<stop offset="0" stop-color="#000000" stop-opacity="1"/>
+<stop offset="1" stop-color="#ffffff" stop-opacity="1"/>
+</linearGradient>
+<clipPath id="b">
+<rect height="8" width="4" x="0" y="0"/>
+</clipPath>
+</defs>
+<g transform="matrix(1 0 0 1 0 0)">
+<rect fill="url(#a)" height="8" width="8" x="0" y="0"/>
+</g>
+<g clip-path="url(#b)">
+<rect fill="#ff0000" height="4" width="8" x="0" y="4"/>
+</g>"##,
+        );
+        // pixel centers at t = 1/8 and 3/8, and reflected after 1
+        assert_eq!(pixel(&buf, 0, 0), [32, 32, 32, 255]);
+        assert_eq!(pixel(&buf, 1, 0), [96, 96, 96, 255]);
+        assert_eq!(pixel(&buf, 7, 0), [32, 32, 32, 255]);
+        // the red rect is drawn over the gradient only in the clip
+        assert_eq!(pixel(&buf, 3, 6), [255, 0, 0, 255]);
+        assert_eq!(pixel(&buf, 5, 6), [159, 159, 159, 255]);
+    }
+
+    #[test]
+    fn blend_modes() {
+        let group = |mode: &str| {
+            format!(
+                r##"<rect width="8" height="8" fill="#808080"/>
+<g isolation="isolate" style="mix-blend-mode: {}">
+<rect width="4" height="8" fill="#ff8000"/>
+</g>"##,
+                mode
+            )
+        };
+        let expected = [
+            ("multiply", [128, 64, 0]),
+            ("screen", [255, 192, 128]),
+            ("overlay", [255, 129, 0]),
+            ("darken", [128, 128, 0]),
+            ("lighten", [255, 128, 128]),
+            ("normal", [255, 128, 0]),
+        ];
+        for &(mode, [r, g, b]) in &expected {
+            let buf = render(&group(mode));
+            let [r1, g1, b1, a] = pixel(&buf, 1, 1);
+            let close = |x: u8, y: u8| (x as i32 - y as i32).abs() <= 1;
+            assert!(close(r, r1) && close(g, g1) && close(b, b1), "{}", mode);
+            assert_eq!(a, 255);
+            // outside the blended drawing, the backdrop is unchanged
+            assert_eq!(pixel(&buf, 6, 1), [128, 128, 128, 255]);
+        }
+    }
+
+    #[test]
+    fn path_data() {
+        let path = path("M1 2L-3-4.5Q0 .5 1e1 2C1 2 3 4 5 6Z");
+        let els = path.elements();
+        assert_eq!(els.len(), 5);
+        assert!(matches!(els[1], PathEl::LineTo(p) if p == Point::new(-3.0, -4.5)));
+        assert!(
+            matches!(els[2], PathEl::QuadTo(p, q) if p == Point::new(0.0, 0.5) && q == Point::new(10.0, 2.0))
+        );
+        assert!(matches!(els[4], PathEl::ClosePath));
+    }
+}
@@ -1,20 +1,55 @@
 //! Text functionality for Piet svg backend
+//!
+//! Text is drawn as SVG `<text>` elements, one per line, and rendered by the
+//! viewer with whatever fonts it has. Layouts are measured as by the
+//! [`NullRenderContext`](piet::NullRenderContext): with the metrics of the
+//! fonts loaded with `load_font`, and approximately for other families, so
+//! the measurements are consistent, but may not match the viewer. Loaded
+//! fonts can be embedded in the document so that the viewer has them too,
+//! or text in them can be drawn as the outlines of its glyphs.
 
-use std::ops::RangeBounds;
+use std::ops::{Range, RangeBounds};
+use std::sync::Arc;
 
-use piet::kurbo::{Point, Rect, Size};
-use piet::{Error, FontFamily, HitTestPoint, HitTestPosition, LineMetric, TextAttribute};
+use piet::kurbo::{BezPath, Point, Rect, Shape, Size};
+use piet::util::{self, LayoutDefaults};
+use piet::{
+    Affinity, Color, Error, FontFamily, FontFamilyInner, FontMetrics, FontWeight, GlyphRun,
+    HitTestPoint, HitTestPosition, IntoLayoutStorage, LineMetric, NullText, NullTextLayout,
+    NullTextLayoutBuilder, TextAttribute, TextLayout as _, TextStorage,
+};
+use svg::node::Node;
 
 type Result<T> = std::result::Result<T, Error>;
 
-/// SVG text (unimplemented)
+/// SVG text
 #[derive(Clone)]
-pub struct Text;
+pub struct Text {
+    /// The text system used for measurement.
+    inner: NullText,
+    /// The data of the loaded fonts, for embedding, with the families
+    /// `load_font` returned; a family loaded again replaces its font.
+    fonts: Vec<(FontFamily, Arc<[u8]>)>,
+}
 
 impl Text {
     #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
-        Text
+        Text {
+            inner: NullText::new(),
+            fonts: Vec::new(),
+        }
+    }
+
+    /// The loaded font of a family, if there is one.
+    pub(crate) fn font_data(&self, family: &FontFamily) -> Option<(&FontFamily, &[u8])> {
+        if !matches!(family.inner(), FontFamilyInner::Named(_)) {
+            return None;
+        }
+        self.fonts
+            .iter()
+            .find(|(loaded, _)| loaded.name().eq_ignore_ascii_case(family.name()))
+            .map(|(loaded, data)| (loaded, &data[..]))
     }
 }
 
@@ -22,91 +57,554 @@ impl piet::Text for Text {
     type TextLayout = TextLayout;
     type TextLayoutBuilder = TextLayoutBuilder;
 
-    fn font_family(&mut self, _family_name: &str) -> Option<FontFamily> {
-        Some(FontFamily::default())
+    fn font_family(&mut self, family_name: &str) -> Option<FontFamily> {
+        // any family may be available to the viewer
+        self.inner.font_family(family_name)
+    }
+
+    fn font_families(&mut self) -> Vec<FontFamily> {
+        self.inner.font_families()
     }
 
-    fn new_text_layout(&mut self, _text: &str) -> TextLayoutBuilder {
-        TextLayoutBuilder
+    fn load_font(&mut self, data: &[u8]) -> Result<FontFamily> {
+        let family = self.inner.load_font(data)?;
+        self.fonts
+            .retain(|(loaded, _)| !loaded.name().eq_ignore_ascii_case(family.name()));
+        self.fonts.push((family.clone(), data.into()));
+        Ok(family)
+    }
+
+    fn new_text_layout(&mut self, text: impl IntoLayoutStorage) -> TextLayoutBuilder {
+        let text: Arc<str> = text.into_layout_storage().as_str().into();
+        TextLayoutBuilder {
+            inner: self.inner.new_text_layout(text.clone()),
+            text,
+            defaults: LayoutDefaults::default(),
+            range_attributes: Vec::new(),
+        }
+    }
+
+    fn font_metrics(&mut self, family: &FontFamily, size: f64) -> Option<FontMetrics> {
+        self.inner.font_metrics(family, size)
     }
 }
 
-pub struct TextLayoutBuilder;
+pub struct TextLayoutBuilder {
+    /// The layout used for measurement.
+    inner: NullTextLayoutBuilder,
+    text: Arc<str>,
+    defaults: LayoutDefaults,
+    /// The range attributes, in the order they were added, with their
+    /// ranges expanded to grapheme boundaries.
+    range_attributes: Vec<(Range<usize>, TextAttribute)>,
+}
 
 impl piet::TextLayoutBuilder for TextLayoutBuilder {
     type Out = TextLayout;
 
-    fn max_width(self, _width: f64) -> Self {
+    fn max_width(mut self, width: f64) -> Self {
+        self.inner = self.inner.max_width(width);
         self
     }
 
-    fn alignment(self, _alignment: piet::TextAlignment) -> Self {
+    fn alignment(mut self, alignment: piet::TextAlignment) -> Self {
+        self.inner = self.inner.alignment(alignment);
         self
     }
 
-    fn default_attribute(self, _attribute: impl Into<TextAttribute>) -> Self {
+    fn text_direction(mut self, direction: piet::TextDirection) -> Self {
+        self.inner = self.inner.text_direction(direction);
+        self
+    }
+
+    fn orientation(mut self, orientation: piet::TextOrientation) -> Self {
+        self.inner = self.inner.orientation(orientation);
+        self
+    }
+
+    fn line_spacing(mut self, spacing: piet::LineSpacing) -> Self {
+        self.inner = self.inner.line_spacing(spacing);
+        self
+    }
+
+    fn max_lines(mut self, max_lines: usize) -> Self {
+        self.inner = self.inner.max_lines(max_lines);
+        self
+    }
+
+    fn truncate(mut self, mode: piet::TruncationMode) -> Self {
+        self.inner = self.inner.truncate(mode);
+        self
+    }
+
+    fn tab_stops(mut self, stops: piet::TabStops) -> Self {
+        self.inner = self.inner.tab_stops(stops);
+        self
+    }
+
+    fn break_opportunities(mut self, breaks: impl FnOnce(&str) -> Vec<usize>) -> Self {
+        self.inner = self.inner.break_opportunities(breaks);
+        self
+    }
+
+    fn default_attribute(mut self, attribute: impl Into<TextAttribute>) -> Self {
+        let attribute = attribute.into();
+        self.inner = self.inner.default_attribute(attribute.clone());
+        self.defaults.set(attribute);
         self
     }
 
     fn range_attribute(
-        self,
-        _range: impl RangeBounds<usize>,
-        _attribute: impl Into<TextAttribute>,
+        mut self,
+        range: impl RangeBounds<usize>,
+        attribute: impl Into<TextAttribute>,
     ) -> Self {
+        let range = util::resolve_range(range, self.text.len());
+        let range = util::snap_to_graphemes(&self.text, range);
+        let attribute = attribute.into();
+        self.inner = self.inner.range_attribute(range.clone(), attribute.clone());
+        self.range_attributes.push((range, attribute));
         self
     }
 
     fn build(self) -> Result<TextLayout> {
-        Err(Error::NotSupported)
+        let style = Style::from_defaults(&self.defaults);
+        let mut bounds = vec![0, self.text.len()];
+        for (range, _) in &self.range_attributes {
+            bounds.push(range.start);
+            bounds.push(range.end);
+        }
+        bounds.sort_unstable();
+        bounds.dedup();
+        let runs = bounds
+            .windows(2)
+            .map(|run| {
+                let mut run_style = style.clone();
+                for (range, attribute) in &self.range_attributes {
+                    if range.start <= run[0] && range.end >= run[1] {
+                        run_style.set(attribute);
+                    }
+                }
+                (run[0]..run[1], run_style)
+            })
+            .collect();
+        Ok(TextLayout {
+            inner: self.inner.build()?,
+            style,
+            runs,
+        })
     }
 }
 
-/// SVG text layout (unimplemented)
+/// SVG text layout
 #[derive(Clone)]
-pub struct TextLayout;
+pub struct TextLayout {
+    inner: NullTextLayout,
+    /// The style of the `<text>` elements, from the default attributes.
+    style: Style,
+    /// The style of each run of the text, in order.
+    runs: Vec<(Range<usize>, Style)>,
+}
+
+/// The presentation attributes of some text.
+#[derive(Clone)]
+pub(crate) struct Style {
+    families: Vec<FontFamily>,
+    size: f64,
+    weight: FontWeight,
+    italic: bool,
+    underline: bool,
+    strikethrough: bool,
+    pub(crate) color: Color,
+}
+
+impl Style {
+    fn from_defaults(defaults: &LayoutDefaults) -> Style {
+        Style {
+            families: Some(defaults.font.clone())
+                .into_iter()
+                .chain(defaults.font_fallbacks.iter().cloned())
+                .collect(),
+            size: defaults.font_size,
+            weight: defaults.weight,
+            italic: defaults.italic,
+            underline: defaults.underline,
+            strikethrough: defaults.strikethrough,
+            color: defaults.fg_color.clone(),
+        }
+    }
+
+    /// Apply a range attribute; attributes that can't be expressed in SVG
+    /// are ignored.
+    fn set(&mut self, attribute: &TextAttribute) {
+        match attribute {
+            TextAttribute::Font(family) => self.families = vec![family.clone()],
+            TextAttribute::FontFamilies(families) => {
+                self.families = families.iter().cloned().collect()
+            }
+            TextAttribute::Size(size) => self.size = *size,
+            TextAttribute::Weight(weight) => self.weight = *weight,
+            TextAttribute::Italic(flag) => self.italic = *flag,
+            TextAttribute::Underline(flag) => self.underline = *flag,
+            TextAttribute::Strikethrough(flag) => self.strikethrough = *flag,
+            TextAttribute::ForegroundColor(color) => self.color = color.clone(),
+            _ => (),
+        }
+    }
+
+    /// Set the font attributes of a `<text>` element; the fill is set with
+    /// the element's other drawing attributes.
+    pub(crate) fn apply_to(&self, node: &mut impl Node) {
+        for (name, value) in self.attributes(None) {
+            node.assign(name, value);
+        }
+    }
+
+    /// The attributes of a run of text in this style: all of them for a
+    /// `<text>` element, or those that differ from the `<text>` element's
+    /// `base` style for a `<tspan>`.
+    ///
+    /// Decorations are only set on runs, as children can't remove their
+    /// parent's decorations.
+    #[allow(clippy::float_cmp)]
+    fn attributes(&self, base: Option<&Style>) -> Vec<(&'static str, String)> {
+        let mut attrs = Vec::new();
+        if base.map(|base| &base.families) != Some(&self.families) {
+            let families: Vec<String> = self.families.iter().map(fmt_family).collect();
+            attrs.push(("font-family", families.join(", ")));
+        }
+        if base.map(|base| base.size) != Some(self.size) {
+            attrs.push(("font-size", self.size.to_string()));
+        }
+        let base_weight = base.map_or(FontWeight::REGULAR, |base| base.weight);
+        if base_weight != self.weight {
+            attrs.push(("font-weight", self.weight.to_raw().to_string()));
+        }
+        let base_italic = base.map(|base| base.italic).unwrap_or_default();
+        if base_italic != self.italic {
+            let style = if self.italic { "italic" } else { "normal" };
+            attrs.push(("font-style", style.to_owned()));
+        }
+        if let Some(base) = base {
+            if base.color.as_rgba_u32() != self.color.as_rgba_u32() {
+                attrs.push(("fill", crate::fmt_color(&self.color)));
+                attrs.push(("fill-opacity", crate::fmt_opacity(&self.color)));
+            }
+            match (self.underline, self.strikethrough) {
+                (true, true) => attrs.push(("text-decoration", "underline line-through".into())),
+                (true, false) => attrs.push(("text-decoration", "underline".into())),
+                (false, true) => attrs.push(("text-decoration", "line-through".into())),
+                (false, false) => (),
+            }
+        }
+        attrs
+    }
+}
+
+/// A family name for CSS; names that aren't generic families are quoted.
+fn fmt_family(family: &FontFamily) -> String {
+    match family.inner() {
+        FontFamilyInner::Named(name) => format!("'{}'", name.replace('\'', "\\'")),
+        _ => family.name().to_owned(),
+    }
+}
+
+/// The escaped content of a `<style>` element with an `@font-face` rule for
+/// a font, with its data as a `data:` URI.
+pub(crate) fn font_face(family: &FontFamily, data: &[u8]) -> String {
+    let (mime, format) = match data.get(0..4) {
+        Some(b"OTTO") => ("font/otf", "opentype"),
+        Some(b"ttcf") => ("font/collection", "collection"),
+        _ => ("font/ttf", "truetype"),
+    };
+    escape(&format!(
+        "@font-face {{ font-family: {}; src: url(data:{};base64,{}) format('{}'); }}",
+        fmt_family(family),
+        mime,
+        crate::image::base64(data),
+        format
+    ))
+}
+
+/// Escape text for the content or an attribute of an element.
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl TextLayout {
+    pub(crate) fn style(&self) -> &Style {
+        &self.style
+    }
+
+    /// The font families of the text and its runs.
+    pub(crate) fn families(&self) -> impl Iterator<Item = &FontFamily> {
+        let runs = self.runs.iter().map(|(_, style)| style);
+        Some(&self.style)
+            .into_iter()
+            .chain(runs)
+            .flat_map(|style| &style.families)
+    }
+
+    /// The range of each line without any trailing line break, and the
+    /// position of the start of its baseline.
+    pub(crate) fn lines(&self) -> Vec<(Range<usize>, Point)> {
+        (0..self.line_count())
+            .filter_map(|line_number| {
+                let metric = self.line_metric(line_number)?;
+                let text = self.line_text(line_number)?;
+                let len = text.trim_end_matches(&['\n', '\r'][..]).len();
+                let point = self.hit_test_text_position(metric.start_offset)?.point;
+                Some((metric.start_offset..metric.start_offset + len, point))
+            })
+            .collect()
+    }
+
+    /// The outline of each run of a line of text, with its decorations, and
+    /// the run's color, or `None` if some of the line has no outlines.
+    pub(crate) fn line_outlines(
+        &self,
+        line: Range<usize>,
+        text: &mut Text,
+    ) -> Option<Vec<(BezPath, Color)>> {
+        let mut outlines = Vec::new();
+        for (run, style) in &self.runs {
+            let run = run.start.max(line.start)..run.end.min(line.end);
+            if run.start >= run.end {
+                continue;
+            }
+            let mut path = self.inner.range_outline(run.clone())?;
+            let family = style.families.first();
+            let metrics =
+                family.and_then(|family| piet::Text::font_metrics(text, family, style.size));
+            if let Some(metrics) = metrics {
+                let start = self.hit_test_text_position(run.start)?.point;
+                let end = self.hit_test_text_position(run.end)?.point;
+                let mut decorate = |position: f64, thickness: f64| {
+                    let y = start.y + position;
+                    let rect = Rect::new(start.x, y - thickness / 2.0, end.x, y + thickness / 2.0);
+                    path.extend(rect.to_bez_path(0.1));
+                };
+                if style.underline {
+                    decorate(metrics.underline_position, metrics.underline_thickness);
+                }
+                if style.strikethrough {
+                    decorate(metrics.strikeout_position, metrics.strikeout_thickness);
+                }
+            }
+            outlines.push((path, style.color.clone()));
+        }
+        Some(outlines)
+    }
+
+    /// The content of a `<text>` element for a range of the text: the
+    /// escaped text, with a `<tspan>` for each run that differs from the
+    /// element's style.
+    ///
+    /// This is written out by hand, as the `svg` crate puts each child on
+    /// its own line, which would add spaces between the runs.
+    pub(crate) fn markup(&self, range: Range<usize>) -> String {
+        let text = self.text();
+        let mut markup = String::new();
+        for (run, style) in &self.runs {
+            let start = run.start.max(range.start);
+            let end = run.end.min(range.end);
+            if start >= end {
+                continue;
+            }
+            let attrs = style.attributes(Some(&self.style));
+            if attrs.is_empty() {
+                markup.push_str(&escape(&text[start..end]));
+                continue;
+            }
+            markup.push_str("<tspan");
+            for (name, value) in attrs {
+                markup.push_str(&format!(r#" {}="{}""#, name, escape(&value)));
+            }
+            markup.push('>');
+            markup.push_str(&escape(&text[start..end]));
+            markup.push_str("</tspan>");
+        }
+        markup
+    }
+}
 
 impl piet::TextLayout for TextLayout {
     fn width(&self) -> f64 {
-        unimplemented!()
+        self.inner.size().width
     }
 
     fn size(&self) -> Size {
-        unimplemented!()
+        self.inner.size()
     }
 
     fn image_bounds(&self) -> Rect {
-        unimplemented!()
+        self.inner.image_bounds()
     }
 
-    #[allow(clippy::unimplemented)]
-    fn update_width(&mut self, _new_width: impl Into<Option<f64>>) -> Result<()> {
-        unimplemented!();
+    fn update_width(&mut self, new_width: impl Into<Option<f64>>) -> Result<()> {
+        self.inner.update_width(new_width)
     }
 
-    #[allow(clippy::unimplemented)]
-    fn line_text(&self, _line_number: usize) -> Option<&str> {
-        unimplemented!();
+    fn line_text(&self, line_number: usize) -> Option<&str> {
+        self.inner.line_text(line_number)
     }
 
-    #[allow(clippy::unimplemented)]
-    fn line_metric(&self, _line_number: usize) -> Option<LineMetric> {
-        unimplemented!();
+    fn line_metric(&self, line_number: usize) -> Option<LineMetric> {
+        self.inner.line_metric(line_number)
     }
 
-    #[allow(clippy::unimplemented)]
     fn line_count(&self) -> usize {
-        unimplemented!();
+        self.inner.line_count()
+    }
+
+    fn hit_test_point(&self, point: Point) -> HitTestPoint {
+        self.inner.hit_test_point(point)
     }
 
-    fn hit_test_point(&self, _point: Point) -> HitTestPoint {
-        unimplemented!()
+    fn hit_test_text_position(&self, text_position: usize) -> Option<HitTestPosition> {
+        self.inner.hit_test_text_position(text_position)
     }
 
-    fn hit_test_text_position(&self, _text_position: usize) -> Option<HitTestPosition> {
-        unimplemented!()
+    fn hit_test_text_position_with_affinity(
+        &self,
+        text_position: usize,
+        affinity: Affinity,
+    ) -> Option<HitTestPosition> {
+        self.inner
+            .hit_test_text_position_with_affinity(text_position, affinity)
     }
 
     fn text(&self) -> &str {
-        unimplemented!()
+        self.inner.text()
+    }
+
+    fn glyph_runs(&self) -> &[GlyphRun] {
+        self.inner.glyph_runs()
+    }
+
+    fn outline(&self) -> BezPath {
+        self.inner.outline()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use piet::{Text as _, TextLayoutBuilder as _};
+
+    /// A minimal font with a family name of `family`, advances of 600
+    /// units of 1000, and a glyph for 'A' that is a 500 by 700 rectangle;
+    /// other characters are measured as the fallback.
+    pub(crate) fn test_font(family: &str) -> Vec<u8> {
+        fn be16(out: &mut Vec<u8>, x: u16) {
+            out.extend_from_slice(&x.to_be_bytes());
+        }
+        let mut head = vec![0; 54];
+        head[18..20].copy_from_slice(&1000u16.to_be_bytes());
+        let mut hhea = vec![0; 36];
+        hhea[4..6].copy_from_slice(&800i16.to_be_bytes());
+        hhea[6..8].copy_from_slice(&(-200i16).to_be_bytes());
+        hhea[34..36].copy_from_slice(&1u16.to_be_bytes());
+        let hmtx = vec![2, 0x58, 0, 0];
+        let maxp = vec![0, 0, 0x50, 0, 0, 2];
+        let name_bytes: Vec<u8> = family.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        let mut name = Vec::new();
+        for &x in &[0, 1, 18, 3, 1, 0x409, 1, name_bytes.len() as u16, 0] {
+            be16(&mut name, x);
+        }
+        name.extend_from_slice(&name_bytes);
+        // a format 12 subtable with one group, for 'A'
+        let mut cmap = Vec::new();
+        for &x in &[
+            0, 1, 3, 10, 0, 12, 12, 0, 0, 28, 0, 0, 0, 1, 0, 0x41, 0, 0x41, 0, 1,
+        ] {
+            be16(&mut cmap, x);
+        }
+        // one contour of four points on the curve
+        let mut glyf = Vec::new();
+        for &x in &[1, 0, 0, 0, 0, 3, 0, 0x101, 0x101, 0, 500, 0, -500i16 as u16] {
+            be16(&mut glyf, x);
+        }
+        for &x in &[0, 0, 700, 0] {
+            be16(&mut glyf, x);
+        }
+        let mut loca = Vec::new();
+        for &x in &[0, 0, 17] {
+            be16(&mut loca, x);
+        }
+
+        let tables: [(&[u8; 4], Vec<u8>); 8] = [
+            (b"cmap", cmap),
+            (b"glyf", glyf),
+            (b"head", head),
+            (b"hhea", hhea),
+            (b"hmtx", hmtx),
+            (b"loca", loca),
+            (b"maxp", maxp),
+            (b"name", name),
+        ];
+        let mut font = Vec::new();
+        for &x in &[0, 1, tables.len() as u16, 0, 0, 0] {
+            be16(&mut font, x);
+        }
+        let mut offset = 12 + 16 * tables.len();
+        for (tag, table) in &tables {
+            font.extend_from_slice(&tag[..]);
+            font.extend_from_slice(&[0; 4]);
+            font.extend_from_slice(&(offset as u32).to_be_bytes());
+            font.extend_from_slice(&(table.len() as u32).to_be_bytes());
+            offset += table.len();
+        }
+        for (_, table) in &tables {
+            font.extend_from_slice(table);
+        }
+        font
+    }
+
+    #[test]
+    fn range_attributes_as_tspans() {
+        let layout = Text::new()
+            .new_text_layout("plain bold <red>")
+            .default_attribute(TextAttribute::Underline(true))
+            .range_attribute(0..5, TextAttribute::Underline(false))
+            .range_attribute(6..10, FontWeight::BOLD)
+            .range_attribute(11.., TextAttribute::Underline(false))
+            .range_attribute(11.., TextAttribute::ForegroundColor(Color::rgb8(255, 0, 0)))
+            .build()
+            .unwrap();
+        assert_eq!(
+            layout.markup(0..layout.text().len()),
+            concat!(
+                "plain",
+                r#"<tspan text-decoration="underline"> </tspan>"#,
+                r#"<tspan font-weight="700" text-decoration="underline">bold</tspan>"#,
+                r#"<tspan text-decoration="underline"> </tspan>"#,
+                r##"<tspan fill="#ff0000" fill-opacity="1">&lt;red&gt;</tspan>"##,
+            )
+        );
+    }
+
+    #[test]
+    fn ranges_snap_to_graphemes() {
+        // the combining accent can't be split from its letter
+        let layout = Text::new()
+            .new_text_layout("cafe\u{301}s")
+            .range_attribute(4..5, FontWeight::BOLD)
+            .build()
+            .unwrap();
+        assert_eq!(
+            layout.markup(0..layout.text().len()),
+            "caf<tspan font-weight=\"700\">e\u{301}</tspan>s"
+        );
     }
 }
@@ -7,21 +7,23 @@
 mod text;
 
 use std::borrow::Cow;
+use std::f64::consts::{FRAC_PI_2, PI};
 use std::fmt;
-use std::ops::Deref;
+use std::ops::{Deref, Range};
 
-use js_sys::{Float64Array, Reflect};
+use js_sys::{Float64Array, Function, Reflect};
 use wasm_bindgen::{Clamped, JsCast, JsValue};
 use web_sys::{
-    CanvasGradient, CanvasRenderingContext2d, CanvasWindingRule, DomMatrix, HtmlCanvasElement,
-    ImageData, Window,
+    CanvasGradient, CanvasPattern, CanvasRenderingContext2d, CanvasWindingRule, DomMatrix,
+    HtmlCanvasElement, ImageData, Window,
 };
 
-use piet::kurbo::{Affine, PathEl, Point, Rect, Shape};
+use piet::kurbo::{Affine, BezPath, PathEl, Point, Rect, Shape, Size, Vec2};
 
 use piet::{
-    Color, Error, FixedGradient, GradientStop, ImageFormat, InterpolationMode, IntoBrush, LineCap,
-    LineJoin, RenderContext, StrokeStyle,
+    util, BlendMode, Color, Error, FixedGradient, GradientExtend, GradientStop, Image, ImageBuf,
+    ImageFormat, InterpolationMode, IntoBrush, LayerMask, LineCap, LineJoin, RenderContext,
+    ShadowStyle, StrokeStyle, TileMode,
 };
 
 pub use text::{WebFont, WebTextLayout, WebTextLayoutBuilder};
@@ -32,6 +34,29 @@ pub struct WebRenderContext {
     window: Window,
     text: WebText,
     err: Result<(), Error>,
+    /// For each `save` or `save_layer`, the layer it started, if any.
+    layer_stack: Vec<Option<Layer>>,
+    /// The bounds of the clip, in canvas pixels.
+    clip_bounds: Option<Rect>,
+    /// For each `save` or `save_layer`, the clip bounds to restore.
+    clip_stack: Vec<Option<Rect>>,
+    /// Kept as the global alpha of the canvas, which isn't restored with
+    /// the context state.
+    opacity: util::OpacityStack,
+}
+
+/// An offscreen canvas that drawing is redirected to, until restore.
+struct Layer {
+    /// The context the layer is composited onto.
+    parent: CanvasRenderingContext2d,
+    opacity: f64,
+    /// The mask, and the transform in effect when the layer was started.
+    mask: Option<(Mask, Affine)>,
+}
+
+enum Mask {
+    Shape(BezPath),
+    Image(HtmlCanvasElement, Rect),
 }
 
 impl WebRenderContext {
@@ -41,6 +66,10 @@ impl WebRenderContext {
             window,
             text: WebText::new(ctx),
             err: Ok(()),
+            layer_stack: Vec::new(),
+            clip_bounds: None,
+            clip_stack: Vec::new(),
+            opacity: util::OpacityStack::new(),
         }
     }
 }
@@ -60,6 +89,7 @@ impl WebText {
 pub enum Brush {
     Solid(u32),
     Gradient(CanvasGradient),
+    Pattern(CanvasPattern),
 }
 
 pub struct WebImage {
@@ -70,29 +100,64 @@ pub struct WebImage {
     height: u32,
 }
 
-#[derive(Debug)]
-struct WrappedJs(JsValue);
+impl Image for WebImage {
+    fn size(&self) -> Size {
+        Size::new(self.width as f64, self.height as f64)
+    }
+}
 
-trait WrapError<T> {
-    fn wrap(self) -> Result<T, Error>;
+/// An error thrown by the canvas API, with the operation that failed.
+///
+/// This is the error wrapped by [`piet::Error::BackendError`] for canvas
+/// failures; get it with [`piet::Error::backend_error_downcast`].
+///
+/// [`piet::Error::BackendError`]: ../piet/enum.Error.html#variant.BackendError
+/// [`piet::Error::backend_error_downcast`]: ../piet/enum.Error.html#method.backend_error_downcast
+#[derive(Debug)]
+pub struct WebError {
+    operation: &'static str,
+    value: JsValue,
 }
 
-impl std::error::Error for WrappedJs {}
+impl WebError {
+    /// An error with the value thrown by the given operation.
+    pub fn new(operation: &'static str, value: JsValue) -> WebError {
+        WebError { operation, value }
+    }
+
+    /// The operation that failed, usually the name of the canvas method.
+    pub fn operation(&self) -> &'static str {
+        self.operation
+    }
 
-impl fmt::Display for WrappedJs {
+    /// The value that was thrown, usually a `DOMException`.
+    pub fn value(&self) -> &JsValue {
+        &self.value
+    }
+}
+
+impl fmt::Display for WebError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Canvas error: {:?}", self.0)
+        write!(f, "{} failed: {:?}", self.operation, self.value)
     }
 }
 
-// Discussion question: a blanket impl here should be pretty doable.
+impl std::error::Error for WebError {}
+
+impl From<WebError> for Error {
+    fn from(e: WebError) -> Error {
+        Error::BackendError(Box::new(e))
+    }
+}
+
+trait WrapError<T> {
+    /// Convert the error, naming the operation that failed.
+    fn wrap(self, operation: &'static str) -> Result<T, Error>;
+}
 
 impl<T> WrapError<T> for Result<T, JsValue> {
-    fn wrap(self) -> Result<T, Error> {
-        self.map_err(|e| {
-            let e: Box<dyn std::error::Error> = Box::new(WrappedJs(e));
-            e.into()
-        })
+    fn wrap(self, operation: &'static str) -> Result<T, Error> {
+        self.map_err(|value| WebError::new(operation, value).into())
     }
 }
 
@@ -134,7 +199,12 @@ impl RenderContext for WebRenderContext {
         };
         let shape = Rect::new(0.0, 0.0, width as f64, height as f64);
         let brush = self.solid_brush(color);
+        // clearing ignores the blend mode and opacity
+        self.ctx.save();
+        let _ = self.ctx.set_global_composite_operation("source-over");
+        self.ctx.set_global_alpha(1.0);
         self.fill(shape, &brush);
+        self.ctx.restore();
     }
 
     fn solid_brush(&mut self, color: Color) -> Brush {
@@ -142,28 +212,100 @@ impl RenderContext for WebRenderContext {
     }
 
     fn gradient(&mut self, gradient: impl Into<FixedGradient>) -> Result<Brush, Error> {
-        match gradient.into() {
+        match util::resolve_color_interpolation(gradient.into()) {
             FixedGradient::Linear(linear) => {
-                let (x0, y0) = (linear.start.x, linear.start.y);
-                let (x1, y1) = (linear.end.x, linear.end.y);
-                let mut lg = self.ctx.create_linear_gradient(x0, y0, x1, y1);
-                set_gradient_stops(&mut lg, &linear.stops);
+                let (start, end, stops) = match linear.extend {
+                    GradientExtend::Pad => (linear.start, linear.end, linear.stops),
+                    extend => {
+                        // canvas can only pad gradients, so we stretch the gradient
+                        // over the visible area, repeating the stops.
+                        let periods = util::linear_gradient_periods(&linear, self.visible_rect());
+                        let axis = linear.end - linear.start;
+                        let start = linear.start + axis * periods.start as f64;
+                        let end = linear.start + axis * periods.end as f64;
+                        (start, end, repeat_stops(&linear.stops, periods, extend))
+                    }
+                };
+                let mut lg = self
+                    .ctx
+                    .create_linear_gradient(start.x, start.y, end.x, end.y);
+                set_gradient_stops(&mut lg, &stops);
                 Ok(Brush::Gradient(lg))
             }
             FixedGradient::Radial(radial) => {
-                let (xc, yc) = (radial.center.x, radial.center.y);
-                let (xo, yo) = (radial.origin_offset.x, radial.origin_offset.y);
-                let r = radial.radius;
+                // canvas gradients can't be transformed, so only circles work
+                if radial.radii.x != radial.radii.y {
+                    return Err(Error::NotSupported);
+                }
+                let origin = radial.center + radial.origin_offset;
+                let (center, radius, stops) = match radial.extend {
+                    GradientExtend::Pad => (radial.center, radial.radii.x, radial.stops),
+                    extend => {
+                        let periods = util::radial_gradient_periods(&radial, self.visible_rect());
+                        let scale = periods.end as f64;
+                        let center = origin - radial.origin_offset * scale;
+                        let stops = repeat_stops(&radial.stops, periods, extend);
+                        (center, radial.radii.x * scale, stops)
+                    }
+                };
                 let mut rg = self
                     .ctx
-                    .create_radial_gradient(xc + xo, yc + yo, 0.0, xc, yc, r)
-                    .wrap()?;
-                set_gradient_stops(&mut rg, &radial.stops);
+                    .create_radial_gradient(origin.x, origin.y, 0.0, center.x, center.y, radius)
+                    .wrap("createRadialGradient")?;
+                set_gradient_stops(&mut rg, &stops);
                 Ok(Brush::Gradient(rg))
             }
+            FixedGradient::Sweep(sweep) => {
+                // web-sys has no binding for createConicGradient yet, and
+                // not every browser supports it, so we look it up ourselves.
+                let create = Reflect::get(self.ctx.as_ref(), &"createConicGradient".into())
+                    .wrap("createConicGradient")?
+                    .dyn_into::<Function>()
+                    .map_err(|_| Error::NotSupported)?;
+                let mut sg = create
+                    .call3(
+                        self.ctx.as_ref(),
+                        &sweep.start_angle.into(),
+                        &sweep.center.x.into(),
+                        &sweep.center.y.into(),
+                    )
+                    .wrap("createConicGradient")?
+                    .dyn_into::<CanvasGradient>()
+                    .map_err(|_| Error::NotSupported)?;
+                set_gradient_stops(&mut sg, &sweep.stops);
+                Ok(Brush::Gradient(sg))
+            }
         }
     }
 
+    fn make_image_brush(
+        &mut self,
+        image: &WebImage,
+        tile: TileMode,
+        transform: Affine,
+    ) -> Result<Brush, Error> {
+        let tile = match tile {
+            TileMode::Repeat => image.inner.clone(),
+            // canvas patterns only repeat, so we mirror the image ourselves
+            TileMode::Reflect => self.reflected_tile(image)?,
+        };
+        let pattern = self
+            .ctx
+            .create_pattern_with_html_canvas_element(&tile, "repeat")
+            .wrap("createPattern")?
+            .ok_or(Error::NotSupported)?;
+        // web-sys only binds the older setTransform, taking an SVGMatrix.
+        let set_transform = Reflect::get(pattern.as_ref(), &"setTransform".into())
+            .wrap("setTransform")?
+            .dyn_into::<Function>()
+            .map_err(|_| Error::NotSupported)?;
+        let matrix = DomMatrix::new_with_array64(&mut transform.as_coeffs()).wrap("DOMMatrix")?;
+        set_transform
+            .call1(pattern.as_ref(), &matrix)
+            .wrap("setTransform")?;
+        Ok(Brush::Pattern(pattern))
+    }
+
     fn fill(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
         let brush = brush.make_brush(self, || shape.bounding_box());
         self.set_path(shape);
@@ -181,11 +323,17 @@ impl RenderContext for WebRenderContext {
     }
 
     fn clip(&mut self, shape: impl Shape) {
-        self.set_path(shape);
+        self.set_clip_path(shape);
         self.ctx
             .clip_with_canvas_winding_rule(CanvasWindingRule::Nonzero);
     }
 
+    fn clip_even_odd(&mut self, shape: impl Shape) {
+        self.set_clip_path(shape);
+        self.ctx
+            .clip_with_canvas_winding_rule(CanvasWindingRule::Evenodd);
+    }
+
     fn stroke(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, width: f64) {
         let brush = brush.make_brush(self, || shape.bounding_box());
         self.set_path(shape);
@@ -201,8 +349,23 @@ impl RenderContext for WebRenderContext {
         width: f64,
         style: &StrokeStyle,
     ) {
+        if let Some(mode) = style.snap_mode {
+            let transform = self.current_transform();
+            let (path, width) = util::snap_stroke(shape, width, mode, transform);
+            let style = StrokeStyle {
+                snap_mode: None,
+                ..style.clone()
+            };
+            return self.stroke_styled(path, brush, width, &style);
+        }
         let brush = brush.make_brush(self, || shape.bounding_box());
-        self.set_path(shape);
+        if style.dash.is_some() {
+            // the canvas' arcs aren't quite as long as the curves of the
+            // shape's path, which would move the dashes along them
+            self.set_path(shape.into_bez_path(1e-3));
+        } else {
+            self.set_path(shape);
+        }
         self.set_stroke(width, Some(style));
         self.set_brush(&*brush.deref(), false);
         self.ctx.stroke();
@@ -215,30 +378,143 @@ impl RenderContext for WebRenderContext {
     fn draw_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>) {
         // TODO: bounding box for text
         self.ctx.set_font(&layout.font.get_font_string());
+        text::set_letter_spacing(&self.ctx, layout.letter_spacing);
+        text::set_text_direction(&self.ctx, layout.rtl);
         let pos = pos.into();
+
+        let backgrounds = layout.background_rects();
+        if !backgrounds.is_empty() {
+            // filling changes the fill style, which is also used for the text
+            self.ctx.save();
+            for (rect, color) in backgrounds {
+                self.fill(rect + pos.to_vec2(), &color);
+            }
+            self.ctx.restore();
+        }
+        for lm in &layout.line_metrics {
+            for (x, run, ink, shadow) in layout.shadow_runs(lm) {
+                let origin = Point::new(pos.x + x, pos.y);
+                self.draw_text_shadow(run, origin, ink + pos.to_vec2(), shadow);
+            }
+        }
         for lm in &layout.line_metrics {
             let draw_line = self
                 .ctx
-                .fill_text(&layout.text[lm.start_offset..lm.end_offset], pos.x, pos.y)
-                .wrap();
+                .fill_text(
+                    &layout.text.as_str()[lm.start_offset..lm.end_offset],
+                    pos.x,
+                    pos.y,
+                )
+                .wrap("fillText");
 
             if let Err(e) = draw_line {
                 self.err = Err(e);
             }
+
+            for rect in layout.strikethrough_rects(lm) {
+                let rect = rect + pos.to_vec2();
+                self.ctx
+                    .fill_rect(rect.x0, rect.y0, rect.width(), rect.height());
+            }
+
+            let underlines = layout.underline_paths(lm);
+            if !underlines.is_empty() {
+                // stroking changes the stroke state, which isn't ours to change
+                self.ctx.save();
+                let thickness = layout.decoration_thickness();
+                for (path, style, color) in underlines {
+                    let path = Affine::translate(pos.to_vec2()) * path;
+                    match color {
+                        Some(color) => self.stroke_styled(path, &color, thickness, &style),
+                        None => {
+                            // like the text, use the current fill style
+                            self.set_path(path);
+                            self.set_stroke(thickness, Some(&style));
+                            self.ctx.set_stroke_style(&self.ctx.fill_style());
+                            self.ctx.stroke();
+                        }
+                    }
+                }
+                self.ctx.restore();
+            }
+        }
+
+        if let Some(ellipsis) = layout.ellipsis {
+            if let Err(e) = self
+                .ctx
+                .fill_text(util::ELLIPSIS, pos.x + ellipsis.x, pos.y)
+                .wrap("fillText")
+            {
+                self.err = Err(e);
+            }
         }
     }
 
     fn save(&mut self) -> Result<(), Error> {
         self.ctx.save();
+        self.layer_stack.push(None);
+        self.clip_stack.push(self.clip_bounds);
         Ok(())
     }
 
+    fn save_layer(&mut self, opacity: f64) -> Result<(), Error> {
+        self.push_layer(opacity, None)
+    }
+
+    fn save_layer_with_mask(&mut self, mask: LayerMask<WebImage>) -> Result<(), Error> {
+        let mask = match mask {
+            LayerMask::Shape(path) => Mask::Shape(path),
+            LayerMask::Image(image, rect) => Mask::Image(image.inner.clone(), rect),
+        };
+        let transform = self.current_transform();
+        self.push_layer(1.0, Some((mask, transform)))
+    }
+
     fn restore(&mut self) -> Result<(), Error> {
-        self.ctx.restore();
+        match self.layer_stack.pop() {
+            Some(Some(layer)) => self.pop_layer(layer)?,
+            Some(None) => self.ctx.restore(),
+            None => return Err(Error::StackUnbalance),
+        }
+        self.clip_bounds = self.clip_stack.pop().unwrap_or_default();
+        self.apply_opacity();
+        Ok(())
+    }
+
+    fn set_blend_mode(&mut self, mode: BlendMode) -> Result<(), Error> {
+        let op = match mode {
+            BlendMode::Normal => "source-over",
+            BlendMode::Multiply => "multiply",
+            BlendMode::Screen => "screen",
+            BlendMode::Overlay => "overlay",
+            BlendMode::Darken => "darken",
+            BlendMode::Lighten => "lighten",
+            // canvas used to have "clear", but it was removed from the spec
+            BlendMode::Clear => return Err(Error::NotSupported),
+            BlendMode::SourceIn => "source-in",
+            BlendMode::DestinationOut => "destination-out",
+        };
+        self.ctx
+            .set_global_composite_operation(op)
+            .wrap("globalCompositeOperation")
+    }
+
+    fn push_opacity(&mut self, alpha: f64) {
+        // the canvas fades each drawing operation by its global alpha
+        self.opacity.push(alpha);
+        self.apply_opacity();
+    }
+
+    fn pop_opacity(&mut self) -> Result<(), Error> {
+        self.opacity.pop()?;
+        self.apply_opacity();
         Ok(())
     }
 
     fn finish(&mut self) -> Result<(), Error> {
+        if self.layer_stack.iter().any(Option::is_some) {
+            return Err(Error::StackUnbalance);
+        }
         self.status()
     }
 
@@ -251,6 +527,10 @@ impl RenderContext for WebRenderContext {
         matrix_to_affine(self.ctx.get_transform().unwrap())
     }
 
+    fn current_clip_bounds(&self) -> Option<Rect> {
+        util::user_clip_bounds(self.clip_bounds, self.current_transform())
+    }
+
     fn make_image(
         &mut self,
         width: usize,
@@ -268,7 +548,7 @@ impl RenderContext for WebRenderContext {
             // See https://github.com/rustwasm/wasm-bindgen/issues/1005 for an issue that might
             // also resolve the need to clone.
             ImageFormat::RgbaSeparate => buf.to_vec(),
-            ImageFormat::RgbaPremul => {
+            ImageFormat::RgbaPremul | ImageFormat::BgraPremul => {
                 fn unpremul(x: u8, a: u8) -> u8 {
                     if a == 0 {
                         0
@@ -277,16 +557,32 @@ impl RenderContext for WebRenderContext {
                         y.min(255) as u8
                     }
                 }
+                // ImageData is always RGBA, so BGRA is reordered as we go
+                let (r, b) = if format == ImageFormat::BgraPremul {
+                    (2, 0)
+                } else {
+                    (0, 2)
+                };
                 let mut new_buf = vec![0; width * height * 4];
                 for i in 0..width * height {
                     let a = buf[i * 4 + 3];
-                    new_buf[i * 4 + 0] = unpremul(buf[i * 4 + 0], a);
+                    new_buf[i * 4 + 0] = unpremul(buf[i * 4 + r], a);
                     new_buf[i * 4 + 1] = unpremul(buf[i * 4 + 1], a);
-                    new_buf[i * 4 + 2] = unpremul(buf[i * 4 + 2], a);
+                    new_buf[i * 4 + 2] = unpremul(buf[i * 4 + b], a);
                     new_buf[i * 4 + 3] = a;
                 }
                 new_buf
             }
+            ImageFormat::Bgra => {
+                let mut new_buf = vec![0; width * height * 4];
+                for i in 0..width * height {
+                    new_buf[i * 4 + 0] = buf[i * 4 + 2];
+                    new_buf[i * 4 + 1] = buf[i * 4 + 1];
+                    new_buf[i * 4 + 2] = buf[i * 4 + 0];
+                    new_buf[i * 4 + 3] = buf[i * 4 + 3];
+                }
+                new_buf
+            }
             ImageFormat::Rgb => {
                 let mut new_buf = vec![0; width * height * 4];
                 for i in 0..width * height {
@@ -297,17 +593,29 @@ impl RenderContext for WebRenderContext {
                 }
                 new_buf
             }
+            ImageFormat::Grayscale => {
+                let mut new_buf = vec![0; width * height * 4];
+                for i in 0..width * height {
+                    new_buf[i * 4 + 0] = buf[i];
+                    new_buf[i * 4 + 1] = buf[i];
+                    new_buf[i * 4 + 2] = buf[i];
+                    new_buf[i * 4 + 3] = 255;
+                }
+                new_buf
+            }
             _ => Vec::new(),
         };
-        let image_data =
-            ImageData::new_with_u8_clamped_array(Clamped(&mut buf), width as u32).wrap()?;
+        let image_data = ImageData::new_with_u8_clamped_array(Clamped(&mut buf), width as u32)
+            .wrap("ImageData")?;
         let context = canvas
             .get_context("2d")
             .unwrap()
             .unwrap()
             .dyn_into::<web_sys::CanvasRenderingContext2d>()
             .unwrap();
-        context.put_image_data(&image_data, 0.0, 0.0).wrap()?;
+        context
+            .put_image_data(&image_data, 0.0, 0.0)
+            .wrap("putImageData")?;
         Ok(WebImage {
             inner: canvas,
             width: width as u32,
@@ -315,6 +623,49 @@ impl RenderContext for WebRenderContext {
         })
     }
 
+    fn capture_image_area(&mut self, src_rect: impl Into<Rect>) -> Result<Self::Image, Error> {
+        let canvas = self.ctx.canvas().ok_or(Error::NotSupported)?;
+        let size = Size::new(canvas.width() as f64, canvas.height() as f64);
+        let rect = util::capture_rect(src_rect.into(), self.current_transform(), size)?;
+        let image_data = self
+            .ctx
+            .get_image_data(rect.x0, rect.y0, rect.width(), rect.height())
+            .wrap("getImageData")?;
+        let (width, height) = (rect.width() as u32, rect.height() as u32);
+        let (canvas, ctx) = self.create_canvas(width, height)?;
+        ctx.put_image_data(&image_data, 0.0, 0.0)
+            .wrap("putImageData")?;
+        Ok(WebImage {
+            inner: canvas,
+            width,
+            height,
+        })
+    }
+
+    fn to_image_buf(
+        &mut self,
+        image: &Self::Image,
+        format: ImageFormat,
+    ) -> Result<ImageBuf, Error> {
+        let context = image
+            .inner
+            .get_context("2d")
+            .unwrap()
+            .unwrap()
+            .dyn_into::<CanvasRenderingContext2d>()
+            .unwrap();
+        let image_data = context
+            .get_image_data(0.0, 0.0, image.width as f64, image.height as f64)
+            .wrap("getImageData")?;
+        let buf = ImageBuf::from_raw(
+            image_data.data().0,
+            ImageFormat::RgbaSeparate,
+            image.width as usize,
+            image.height as usize,
+        );
+        Ok(buf.to_format(format))
+    }
+
     #[inline]
     fn draw_image(
         &mut self,
@@ -336,19 +687,45 @@ impl RenderContext for WebRenderContext {
         draw_image(self, image, Some(src_rect.into()), dst_rect.into(), interp);
     }
 
-    fn blurred_rect(&mut self, rect: Rect, blur_radius: f64, brush: &impl IntoBrush<Self>) {
-        let brush = brush.make_brush(self, || rect);
+    fn blurred_shape(&mut self, shape: impl Shape, blur_radius: f64, brush: &impl IntoBrush<Self>) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
         self.ctx.set_shadow_blur(blur_radius);
         let color = match *brush {
             Brush::Solid(rgba) => format_color(rgba),
-            // Gradients not yet implemented.
-            Brush::Gradient(_) => "#f0f".into(),
+            // Gradients and patterns not yet implemented.
+            Brush::Gradient(_) | Brush::Pattern(_) => "#f0f".into(),
         };
         self.ctx.set_shadow_color(&color);
+        self.set_path(shape);
         self.ctx
-            .fill_rect(rect.x0, rect.y0, rect.width(), rect.height());
+            .fill_with_canvas_winding_rule(CanvasWindingRule::Nonzero);
         self.ctx.set_shadow_color("none");
     }
+
+    fn fill_with_shadow(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        shadow: &ShadowStyle,
+    ) {
+        let brush = brush.make_brush(self, || shape.bounding_box());
+        // canvas shadows ignore the transform, so they are given in pixels;
+        // `shadowBlur` is twice the standard deviation.
+        let [a, b, c, d, _, _] = self.current_transform().as_coeffs();
+        let Vec2 { x, y } = shadow.offset;
+        let scale = (a * d - b * c).abs().sqrt();
+        self.ctx.save();
+        self.ctx.set_shadow_offset_x(a * x + c * y);
+        self.ctx.set_shadow_offset_y(b * x + d * y);
+        self.ctx.set_shadow_blur(2.0 * shadow.blur * scale);
+        self.ctx
+            .set_shadow_color(&format_color(shadow.color.as_rgba_u32()));
+        self.set_path(shape);
+        self.set_brush(&brush, true);
+        self.ctx
+            .fill_with_canvas_winding_rule(CanvasWindingRule::Nonzero);
+        self.ctx.restore();
+    }
 }
 
 fn draw_image(
@@ -356,11 +733,21 @@ fn draw_image(
     image: &<WebRenderContext as RenderContext>::Image,
     src_rect: Option<Rect>,
     dst_rect: Rect,
-    _interp: InterpolationMode,
+    interp: InterpolationMode,
 ) {
     let result = ctx.with_save(|rc| {
         // TODO: Implement InterpolationMode::NearestNeighbor in software
         //       See for inspiration http://phrogz.net/tmp/canvas_image_zoom.html
+        if interp == InterpolationMode::Bicubic {
+            // web-sys has no binding for imageSmoothingQuality; browsers
+            // that don't support it ignore it.
+            Reflect::set(
+                rc.ctx.as_ref(),
+                &"imageSmoothingQuality".into(),
+                &"high".into(),
+            )
+            .wrap("imageSmoothingQuality")?;
+        }
         let src_rect = match src_rect {
             Some(src_rect) => src_rect,
             None => Rect::new(0.0, 0.0, image.width as f64, image.height as f64),
@@ -377,7 +764,7 @@ fn draw_image(
                 dst_rect.width(),
                 dst_rect.height(),
             )
-            .wrap()
+            .wrap("drawImage")
     });
     if let Err(e) = result {
         ctx.err = Err(e);
@@ -410,6 +797,32 @@ fn format_color(rgba: u32) -> String {
     }
 }
 
+/// Lay out `stops` once for each of `periods`, scaled to fit in a single
+/// gradient spanning all of them.
+fn repeat_stops(
+    stops: &[GradientStop],
+    periods: Range<i64>,
+    extend: GradientExtend,
+) -> Vec<GradientStop> {
+    let count = (periods.end - periods.start) as f32;
+    let mut result = Vec::with_capacity(stops.len() * count as usize);
+    for k in periods.clone() {
+        let offset = (k - periods.start) as f32;
+        if extend == GradientExtend::Reflect && k.rem_euclid(2) == 1 {
+            result.extend(stops.iter().rev().map(|stop| GradientStop {
+                pos: (offset + 1.0 - stop.pos) / count,
+                color: stop.color.clone(),
+            }));
+        } else {
+            result.extend(stops.iter().map(|stop| GradientStop {
+                pos: (offset + stop.pos) / count,
+                color: stop.color.clone(),
+            }));
+        }
+    }
+    result
+}
+
 fn set_gradient_stops(dst: &mut CanvasGradient, src: &[GradientStop]) {
     for stop in src {
         // TODO: maybe get error?
@@ -419,6 +832,40 @@ fn set_gradient_stops(dst: &mut CanvasGradient, src: &[GradientStop]) {
 }
 
 impl WebRenderContext {
+    /// Draw the shadow of a run of text, with its baseline starting at `pos`;
+    /// `ink` is the inked rect of the run.
+    ///
+    /// Canvas casts a shadow from whatever it draws, so we draw the glyphs
+    /// off the left edge of the canvas, and move the shadow back by the same
+    /// distance. Shadows ignore the transform, so they are given in pixels,
+    /// and `shadowBlur` is twice the standard deviation.
+    fn draw_text_shadow(&mut self, run: &str, pos: Point, ink: Rect, shadow: &ShadowStyle) {
+        let transform = self.current_transform();
+        let [a, b, c, d, tx, ty] = transform.as_coeffs();
+        let Vec2 { x, y } = shadow.offset;
+        let shift = transform.transform_rect_bbox(ink).x1.max(0.0).ceil() + 1.0;
+        self.ctx.save();
+        if let Err(e) = self
+            .ctx
+            .set_transform(a, b, c, d, tx - shift, ty)
+            .wrap("setTransform")
+        {
+            self.err = Err(e);
+        }
+        self.ctx.set_shadow_offset_x(a * x + c * y + shift);
+        self.ctx.set_shadow_offset_y(b * x + d * y);
+        self.ctx
+            .set_shadow_blur(2.0 * shadow.blur * (a * d - b * c).abs().sqrt());
+        self.ctx
+            .set_shadow_color(&format_color(shadow.color.as_rgba_u32()));
+        // the shadow's alpha is the glyphs' alpha times its color's alpha
+        self.ctx.set_fill_style_str("#000");
+        if let Err(e) = self.ctx.fill_text(run, pos.x, pos.y).wrap("fillText") {
+            self.err = Err(e);
+        }
+        self.ctx.restore();
+    }
+
     /// Set the source pattern to the brush.
     ///
     /// Web canvas is super stateful, and we're trying to have more retained stuff.
@@ -432,10 +879,23 @@ impl WebRenderContext {
         }
     }
 
+    /// The visible area of the canvas, in user space.
+    fn visible_rect(&self) -> Rect {
+        let (width, height) = match self.ctx.canvas() {
+            Some(canvas) => (canvas.width(), canvas.height()),
+            None => (0, 0),
+        };
+        let device_rect = Rect::new(0.0, 0.0, width as f64, height as f64);
+        self.current_transform()
+            .inverse()
+            .transform_rect_bbox(device_rect)
+    }
+
     fn brush_value(&self, brush: &Brush) -> JsValue {
         match *brush {
             Brush::Solid(rgba) => JsValue::from_str(&format_color(rgba)),
             Brush::Gradient(ref gradient) => JsValue::from(gradient),
+            Brush::Pattern(ref pattern) => JsValue::from(pattern),
         }
     }
 
@@ -456,7 +916,9 @@ impl WebRenderContext {
             .unwrap_or(LineCap::Butt);
         self.ctx.set_line_cap(convert_line_cap(line_cap));
 
-        let miter_limit = style.and_then(|style| style.miter_limit).unwrap_or(10.0);
+        let miter_limit = style
+            .and_then(|style| style.miter_limit)
+            .unwrap_or(StrokeStyle::DEFAULT_MITER_LIMIT);
         self.ctx.set_miter_limit(miter_limit);
 
         let (dash_segs, dash_offset) = style
@@ -480,10 +942,155 @@ impl WebRenderContext {
         self.ctx.set_line_dash_offset(dash_offset);
     }
 
+    /// Create an offscreen canvas, and its context.
+    fn create_canvas(
+        &self,
+        width: u32,
+        height: u32,
+    ) -> Result<(HtmlCanvasElement, CanvasRenderingContext2d), Error> {
+        let document = self.window.document().unwrap();
+        let element = document.create_element("canvas").unwrap();
+        let canvas = element.dyn_into::<HtmlCanvasElement>().unwrap();
+        canvas.set_width(width);
+        canvas.set_height(height);
+        let ctx = canvas
+            .get_context("2d")
+            .wrap("getContext")?
+            .unwrap()
+            .dyn_into::<CanvasRenderingContext2d>()
+            .unwrap();
+        Ok((canvas, ctx))
+    }
+
+    /// A tile of four copies of `image`, mirrored horizontally and vertically.
+    fn reflected_tile(&self, image: &WebImage) -> Result<HtmlCanvasElement, Error> {
+        let (canvas, ctx) = self.create_canvas(image.width * 2, image.height * 2)?;
+        let (w, h) = (image.width as f64, image.height as f64);
+        for &(sx, sy) in &[(1.0, 1.0), (-1.0, 1.0), (1.0, -1.0), (-1.0, -1.0)] {
+            // mirror about the center of the tile
+            ctx.set_transform(sx, 0.0, 0.0, sy, w, h)
+                .wrap("setTransform")?;
+            ctx.draw_image_with_html_canvas_element(&image.inner, -w, -h)
+                .wrap("drawImage")?;
+        }
+        Ok(canvas)
+    }
+
+    /// Redirect drawing to a new layer.
+    fn push_layer(&mut self, opacity: f64, mask: Option<(Mask, Affine)>) -> Result<(), Error> {
+        // the layer is an offscreen canvas the size of this one, with the
+        // same transform; it is drawn back untransformed on restore.
+        let (width, height) = match self.ctx.canvas() {
+            Some(canvas) => (canvas.width(), canvas.height()),
+            None => return Err(Error::NotSupported),
+        };
+        let (_, ctx) = self.create_canvas(width, height)?;
+        let a = self.current_transform().as_coeffs();
+        ctx.set_transform(a[0], a[1], a[2], a[3], a[4], a[5])
+            .wrap("setTransform")?;
+        let parent = std::mem::replace(&mut self.ctx, ctx);
+        parent.save();
+        self.apply_opacity();
+        self.layer_stack.push(Some(Layer {
+            parent,
+            opacity,
+            mask,
+        }));
+        self.clip_stack.push(self.clip_bounds);
+        Ok(())
+    }
+
+    /// Set the global alpha of the canvas to the current opacity.
+    fn apply_opacity(&mut self) {
+        self.ctx.set_global_alpha(self.opacity.current());
+    }
+
+    /// Mask the current layer, and composite it onto its parent.
+    fn pop_layer(&mut self, layer: Layer) -> Result<(), Error> {
+        if let Some((mask, transform)) = layer.mask {
+            self.ctx.set_global_alpha(1.0);
+            let a = transform.as_coeffs();
+            self.ctx
+                .set_transform(a[0], a[1], a[2], a[3], a[4], a[5])
+                .wrap("setTransform")?;
+            // keep only the parts of the layer covered by the mask
+            self.ctx
+                .set_global_composite_operation("destination-in")
+                .wrap("globalCompositeOperation")?;
+            match mask {
+                Mask::Shape(path) => {
+                    self.set_path(path);
+                    self.set_brush(&Brush::Solid(0x0000_00ff), true);
+                    self.ctx
+                        .fill_with_canvas_winding_rule(CanvasWindingRule::Nonzero);
+                }
+                Mask::Image(image, rect) => self
+                    .ctx
+                    .draw_image_with_html_canvas_element_and_dw_and_dh(
+                        &image,
+                        rect.x0,
+                        rect.y0,
+                        rect.width(),
+                        rect.height(),
+                    )
+                    .wrap("drawImage")?,
+            }
+        }
+        let ctx = std::mem::replace(&mut self.ctx, layer.parent);
+        self.ctx.save();
+        self.ctx
+            .set_transform(1.0, 0.0, 0.0, 1.0, 0.0, 0.0)
+            .wrap("setTransform")?;
+        self.ctx.set_global_alpha(layer.opacity);
+        if let Some(canvas) = ctx.canvas() {
+            self.ctx
+                .draw_image_with_html_canvas_element(&canvas, 0.0, 0.0)
+                .wrap("drawImage")?;
+        }
+        self.ctx.restore();
+        self.ctx.restore();
+        Ok(())
+    }
+
+    /// Record the bounds of a new clip, and set its path.
+    fn set_clip_path(&mut self, shape: impl Shape) {
+        let transform = self.current_transform();
+        let bounds = util::intersect_clip_bounds(self.clip_bounds, shape.bounding_box(), transform);
+        self.clip_bounds = Some(bounds);
+        self.set_path(shape);
+    }
+
     fn set_path(&mut self, shape: impl Shape) {
         // This shouldn't be necessary, we always leave the context in no-path
         // state. But just in case, and it should be harmless.
         self.ctx.begin_path();
+        // the canvas draws its own rects and arcs faster than paths
+        if let Some(rect) = shape.as_rect() {
+            self.ctx.rect(rect.x0, rect.y0, rect.width(), rect.height());
+            return;
+        }
+        if let Some(circle) = shape.as_circle().filter(|c| c.radius >= 0.0) {
+            let (x, y) = (circle.center.x, circle.center.y);
+            let _ = self.ctx.arc(x, y, circle.radius, 0.0, 2.0 * PI);
+            self.ctx.close_path();
+            return;
+        }
+        if let Some(rounded) = shape.as_rounded_rect() {
+            let (rect, r) = (rounded.rect(), rounded.radius());
+            // the corners in the same order as kurbo's path
+            let corners = [
+                (rect.x0 + r, rect.y0 + r),
+                (rect.x1 - r, rect.y0 + r),
+                (rect.x1 - r, rect.y1 - r),
+                (rect.x0 + r, rect.y1 - r),
+            ];
+            for (i, &(x, y)) in corners.iter().enumerate() {
+                let start = FRAC_PI_2 * (i + 2) as f64;
+                let _ = self.ctx.arc(x, y, r, start, start + FRAC_PI_2);
+            }
+            self.ctx.close_path();
+            return;
+        }
         for el in shape.to_bez_path(1e-3) {
             match el {
                 PathEl::MoveTo(p) => self.ctx.move_to(p.x, p.y),
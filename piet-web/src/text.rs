@@ -4,15 +4,20 @@ mod grapheme;
 mod lines;
 
 use std::borrow::Cow;
-use std::ops::RangeBounds;
+use std::ops::{Range, RangeBounds};
+use std::rc::Rc;
 
+use js_sys::Reflect;
+use wasm_bindgen::JsValue;
 use web_sys::CanvasRenderingContext2d;
 
-use piet::kurbo::{Point, Rect, Size};
+use piet::kurbo::{BezPath, Point, Rect, Size};
 
 use piet::{
-    util, Error, FontFamily, HitTestPoint, HitTestPosition, LineMetric, Text, TextAttribute,
-    TextLayout, TextLayoutBuilder,
+    util, Affinity, Color, Error, FontFamily, FontMetrics, HitTestPoint, HitTestPosition,
+    IntoLayoutStorage, LineMetric, LineSpacing, ShadowStyle, StrokeStyle, Text, TextAttribute,
+    TextDirection, TextLayout, TextLayoutBuilder, TextOrientation, TextStorage, TruncationMode,
+    UnderlineStyle,
 };
 use unicode_segmentation::UnicodeSegmentation;
 
@@ -32,18 +37,55 @@ pub struct WebTextLayout {
     ctx: CanvasRenderingContext2d,
     // TODO like cairo, should this be pub(crate)?
     pub font: WebFont,
-    pub text: String,
+    pub text: Rc<dyn TextStorage>,
 
     // Calculated on build
     pub(crate) line_metrics: Vec<LineMetric>,
     size: Size,
+    pub(crate) letter_spacing: f64,
+    /// the resolved base direction of the text.
+    pub(crate) rtl: bool,
+    max_lines: Option<usize>,
+    truncation: TruncationMode,
+    /// the position of the ellipsis baseline, if the text was truncated
+    /// with `TruncationMode::EllipsisEnd`.
+    pub(crate) ellipsis: Option<Point>,
+    underlines: Vec<(Range<usize>, UnderlineStyle, Option<Color>)>,
+    strikethroughs: Vec<Range<usize>>,
+    backgrounds: Vec<(Range<usize>, Color)>,
+    shadows: Vec<(Range<usize>, ShadowStyle)>,
+    /// the offsets at which lines may break, if they were set with
+    /// `break_opportunities`.
+    break_opportunities: Option<Vec<usize>>,
 }
 
 pub struct WebTextLayoutBuilder {
     ctx: CanvasRenderingContext2d,
     font: WebFont,
-    text: String,
+    text: Rc<dyn TextStorage>,
     width: f64,
+    line_spacing: LineSpacing,
+    max_lines: Option<usize>,
+    truncation: TruncationMode,
+    direction: TextDirection,
+    orientation: TextOrientation,
+    defaults: util::LayoutDefaults,
+    /// word spacing is not yet supported, and causes `build` to fail.
+    has_word_spacing: bool,
+    /// canvas text can't apply font features, so these also cause `build` to fail.
+    has_font_features: bool,
+    /// canvas letter spacing applies to a whole layout, so ranges with
+    /// different spacings cause `build` to fail.
+    letter_spacings: Vec<(Range<usize>, f64)>,
+    underlines: Vec<(Range<usize>, bool)>,
+    underline_colors: Vec<(Range<usize>, Color)>,
+    underline_styles: Vec<(Range<usize>, UnderlineStyle)>,
+    strikethroughs: Vec<(Range<usize>, bool)>,
+    backgrounds: Vec<(Range<usize>, Color)>,
+    shadows: Vec<(Range<usize>, ShadowStyle)>,
+    /// the offsets at which lines may break, if they were set with
+    /// `break_opportunities`.
+    break_opportunities: Option<Vec<usize>>,
 }
 
 /// https://developer.mozilla.org/en-US/docs/Web/CSS/font-style
@@ -63,14 +105,73 @@ impl Text for WebText {
         Some(FontFamily::new_unchecked(family_name))
     }
 
-    fn new_text_layout(&mut self, text: &str) -> Self::TextLayoutBuilder {
+    fn font_families(&mut self) -> Vec<FontFamily> {
+        // the browser doesn't expose the installed fonts
+        vec![
+            FontFamily::MONOSPACE,
+            FontFamily::SANS_SERIF,
+            FontFamily::SERIF,
+            FontFamily::SYSTEM_UI,
+        ]
+    }
+
+    fn font_metrics(&mut self, family: &FontFamily, size: f64) -> Option<FontMetrics> {
+        // canvas only measures the glyphs of some text, so like the line
+        // metrics, the ascent, descent and decorations are approximated.
+        let mut font = WebFont::new(family.clone());
+        font.size = size;
+        let prev_font = self.ctx.font();
+        self.ctx.set_font(&font.get_font_string());
+        let cap_height = self
+            .ctx
+            .measure_text("H")
+            .ok()?
+            .actual_bounding_box_ascent();
+        let x_height = self
+            .ctx
+            .measure_text("x")
+            .ok()?
+            .actual_bounding_box_ascent();
+        self.ctx.set_font(&prev_font);
+
+        let height = size * 1.2;
+        let (thickness, underline_position, strikeout_position) = decoration_metrics(size);
+        Some(FontMetrics {
+            ascent: height * 0.8,
+            descent: height * 0.2,
+            cap_height,
+            x_height,
+            underline_position,
+            underline_thickness: thickness,
+            strikeout_position,
+            strikeout_thickness: thickness,
+        })
+    }
+
+    fn new_text_layout(&mut self, text: impl IntoLayoutStorage) -> Self::TextLayoutBuilder {
         WebTextLayoutBuilder {
             // TODO: it's very likely possible to do this without cloning ctx, but
             // I couldn't figure out the lifetime errors from a `&'a` reference.
             ctx: self.ctx.clone(),
             font: WebFont::new(FontFamily::default()),
-            text: text.to_owned(),
+            text: Rc::new(text.into_layout_storage()),
             width: f64::INFINITY,
+            line_spacing: LineSpacing::Default,
+            max_lines: None,
+            truncation: TruncationMode::Clip,
+            direction: TextDirection::default(),
+            orientation: TextOrientation::Horizontal,
+            defaults: util::LayoutDefaults::default(),
+            has_word_spacing: false,
+            letter_spacings: Vec::new(),
+            has_font_features: false,
+            break_opportunities: None,
+            underlines: Vec::new(),
+            underline_colors: Vec::new(),
+            underline_styles: Vec::new(),
+            strikethroughs: Vec::new(),
+            backgrounds: Vec::new(),
+            shadows: Vec::new(),
         }
     }
 }
@@ -116,22 +217,118 @@ impl TextLayoutBuilder for WebTextLayoutBuilder {
         self
     }
 
-    fn default_attribute(self, _attribute: impl Into<TextAttribute>) -> Self {
-        web_sys::console::log_1(&"Text attributes not yet implemented for web".into());
+    fn text_direction(mut self, direction: TextDirection) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    fn orientation(mut self, orientation: TextOrientation) -> Self {
+        self.orientation = orientation;
+        self
+    }
+
+    fn line_spacing(mut self, spacing: LineSpacing) -> Self {
+        self.line_spacing = spacing;
+        self
+    }
+
+    fn max_lines(mut self, max_lines: usize) -> Self {
+        self.max_lines = Some(max_lines.max(1));
+        self
+    }
+
+    fn truncate(mut self, mode: TruncationMode) -> Self {
+        self.truncation = mode;
+        self
+    }
+
+    fn tab_stops(self, _stops: piet::TabStops) -> Self {
+        web_sys::console::log_1(&"TextLayout tab stops unsupported on web".into());
+        self
+    }
+
+    fn break_opportunities(mut self, breaks: impl FnOnce(&str) -> Vec<usize>) -> Self {
+        let text = self.text.as_str();
+        self.break_opportunities = Some(util::resolve_break_opportunities(text, breaks(text)));
+        self
+    }
+
+    fn default_attribute(mut self, attribute: impl Into<TextAttribute>) -> Self {
+        match attribute.into() {
+            attr @ TextAttribute::Underline(_)
+            | attr @ TextAttribute::UnderlineColor(_)
+            | attr @ TextAttribute::UnderlineStyle(_)
+            | attr @ TextAttribute::Strikethrough(_)
+            | attr @ TextAttribute::LetterSpacing(_)
+            | attr @ TextAttribute::BackgroundColor(_)
+            | attr @ TextAttribute::Shadow(_) => self.defaults.set(attr),
+            TextAttribute::WordSpacing(spacing) => self.has_word_spacing |= spacing != 0.0,
+            TextAttribute::FontFeatures(features) => self.has_font_features |= !features.is_empty(),
+            _ => web_sys::console::log_1(&"Text attributes not yet implemented for web".into()),
+        }
         self
     }
 
     fn range_attribute(
-        self,
-        _range: impl RangeBounds<usize>,
-        _attribute: impl Into<TextAttribute>,
+        mut self,
+        range: impl RangeBounds<usize>,
+        attribute: impl Into<TextAttribute>,
     ) -> Self {
-        web_sys::console::log_1(&"Text attributes not yet implemented for web".into());
+        let range = util::resolve_range(range, self.text.as_str().len());
+        match attribute.into() {
+            TextAttribute::Underline(flag) => self.underlines.push((range, flag)),
+            TextAttribute::UnderlineColor(color) => self.underline_colors.push((range, color)),
+            TextAttribute::UnderlineStyle(style) => self.underline_styles.push((range, style)),
+            TextAttribute::Strikethrough(flag) => self.strikethroughs.push((range, flag)),
+            TextAttribute::BackgroundColor(color) => self.backgrounds.push((range, color)),
+            TextAttribute::Shadow(shadow) => self.shadows.push((range, shadow)),
+            TextAttribute::LetterSpacing(spacing) => self.letter_spacings.push((range, spacing)),
+            TextAttribute::WordSpacing(spacing) => self.has_word_spacing |= spacing != 0.0,
+            TextAttribute::FontFeatures(features) => self.has_font_features |= !features.is_empty(),
+            _ => web_sys::console::log_1(&"Text attributes not yet implemented for web".into()),
+        }
         self
     }
 
     fn build(self) -> Result<Self::Out, Error> {
+        let len = self.text.as_str().len();
+        let letter_spacings = util::resolve_spans(
+            Some(self.defaults.letter_spacing),
+            len,
+            self.letter_spacings,
+        );
+        let letter_spacing = letter_spacings
+            .first()
+            .map_or(self.defaults.letter_spacing, |(_, spacing)| *spacing);
+        let mixed_letter_spacing = letter_spacings
+            .iter()
+            .any(|(_, spacing)| *spacing != letter_spacing);
+        if self.line_spacing != LineSpacing::Default
+            || self.has_word_spacing
+            || mixed_letter_spacing
+            || self.has_font_features
+            || self.orientation != TextOrientation::Horizontal
+        {
+            return Err(Error::NotSupported);
+        }
+
+        let rtl = self.direction.is_rtl(self.text.as_str());
         self.ctx.set_font(&self.font.get_font_string());
+        set_letter_spacing(&self.ctx, letter_spacing);
+        set_text_direction(&self.ctx, rtl);
+
+        let underlines = util::resolve_underlines(
+            &self.defaults,
+            len,
+            self.underlines,
+            self.underline_colors,
+            self.underline_styles,
+        );
+        let strikethroughs =
+            util::resolve_flag_ranges(self.defaults.strikethrough, len, self.strikethroughs);
+        let backgrounds =
+            util::resolve_spans(self.defaults.background_color, len, self.backgrounds);
+        let shadows = util::resolve_spans(self.defaults.shadow, len, self.shadows);
 
         let mut layout = WebTextLayout {
             ctx: self.ctx,
@@ -139,6 +336,16 @@ impl TextLayoutBuilder for WebTextLayoutBuilder {
             text: self.text,
             line_metrics: Vec::new(),
             size: Size::ZERO,
+            letter_spacing,
+            rtl,
+            max_lines: self.max_lines,
+            truncation: self.truncation,
+            ellipsis: None,
+            underlines,
+            strikethroughs,
+            backgrounds,
+            shadows,
+            break_opportunities: self.break_opportunities,
         };
 
         layout.update_width(self.width)?;
@@ -158,28 +365,45 @@ impl TextLayout for WebTextLayout {
 
     fn image_bounds(&self) -> Rect {
         //FIXME: figure out actual image bounds on web?
-        self.size.to_rect()
+        let shadows = self.shadows.iter().map(|(_, shadow)| shadow);
+        util::bounds_with_shadows(self.size.to_rect(), shadows)
     }
 
     fn text(&self) -> &str {
-        &self.text
+        self.text.as_str()
     }
 
     fn update_width(&mut self, new_width: impl Into<Option<f64>>) -> Result<(), Error> {
         let new_width = new_width.into().unwrap_or(std::f64::INFINITY);
 
-        let line_metrics =
-            lines::calculate_line_metrics(&self.text, &self.ctx, new_width, self.font.size);
+        self.line_metrics = lines::calculate_line_metrics(
+            self.text.as_str(),
+            &self.ctx,
+            self.break_opportunities.as_deref(),
+            new_width,
+            self.font.size,
+        );
+        self.truncate_lines(new_width);
 
-        let max_width = line_metrics
+        let ellipsis_width = self
+            .ellipsis
+            .map(|pos| pos.x + text_width(util::ELLIPSIS, &self.ctx))
+            .unwrap_or_default();
+        let max_width = self
+            .line_metrics
             .iter()
-            .map(|lm| text_width(&self.text[lm.start_offset..lm.end_offset], &self.ctx))
-            .fold(0., f64::max);
-        let height = line_metrics
+            .map(|lm| {
+                text_width(
+                    &self.text.as_str()[lm.start_offset..lm.end_offset],
+                    &self.ctx,
+                )
+            })
+            .fold(ellipsis_width, f64::max);
+        let height = self
+            .line_metrics
             .last()
             .map(|l| l.y_offset + l.height)
             .unwrap_or_default();
-        self.line_metrics = line_metrics;
         self.size = Size::new(max_width, height);
         Ok(())
     }
@@ -187,7 +411,11 @@ impl TextLayout for WebTextLayout {
     fn line_text(&self, line_number: usize) -> Option<&str> {
         self.line_metrics
             .get(line_number)
-            .map(|lm| &self.text[lm.start_offset..lm.end_offset])
+            .map(|lm| &self.text.as_str()[lm.start_offset..lm.end_offset])
+    }
+
+    fn line_text_range(&self, line_number: usize) -> Option<Range<usize>> {
+        self.line_metrics.get(line_number).map(LineMetric::range)
     }
 
     fn line_metric(&self, line_number: usize) -> Option<LineMetric> {
@@ -203,7 +431,7 @@ impl TextLayout for WebTextLayout {
         // with the border of the grapheme cluster.
 
         // null case
-        if self.text.is_empty() {
+        if self.text.as_str().is_empty() {
             return HitTestPoint::default();
         }
 
@@ -238,7 +466,7 @@ impl TextLayout for WebTextLayout {
 
         // Then for the line, do hit test point
         // Trailing whitespace is remove for the line
-        let line = &self.text[lm.start_offset..lm.end_offset];
+        let line = &self.text.as_str()[lm.start_offset..lm.end_offset];
 
         let mut htp = hit_test_line_point(&self.ctx, line, point);
         htp.idx += lm.start_offset;
@@ -258,12 +486,194 @@ impl TextLayout for WebTextLayout {
         let y_pos = lm.y_offset + lm.baseline;
         // Then for the line, do text position
         // Trailing whitespace is removed for the line
-        let line = &self.text[lm.range()];
+        let line = &self.text.as_str()[lm.range()];
         let line_position = text_position - lm.start_offset;
 
         hit_test_line_position(&self.ctx, line, line_position)
             .map(|x_pos| HitTestPosition::new(Point::new(x_pos, y_pos), line_num))
     }
+
+    fn hit_test_text_position_with_affinity(
+        &self,
+        text_position: usize,
+        affinity: Affinity,
+    ) -> Option<HitTestPosition> {
+        util::hit_test_text_position_with_affinity(self, text_position, affinity)
+    }
+
+    fn rects_for_range(&self, range: impl RangeBounds<usize>) -> Vec<Rect> {
+        self.range_rects(range, true)
+    }
+}
+
+impl WebTextLayout {
+    /// Drop any lines past `max_lines`, making room for the ellipsis if needed.
+    fn truncate_lines(&mut self, width: f64) {
+        self.ellipsis = None;
+        let max_lines = match self.max_lines {
+            Some(max_lines) if self.line_metrics.len() > max_lines => max_lines,
+            _ => return,
+        };
+        self.line_metrics.truncate(max_lines);
+        if self.truncation != TruncationMode::EllipsisEnd {
+            return;
+        }
+
+        let ellipsis_width = text_width(util::ELLIPSIS, &self.ctx);
+        let lm = self.line_metrics.last_mut().unwrap();
+        let line = &self.text.as_str()[lm.start_offset..lm.end_offset - lm.trailing_whitespace];
+        // remove graphemes from the end of the line until the ellipsis fits
+        let mut end = line.len();
+        while end > 0 && text_width(&line[..end], &self.ctx) + ellipsis_width > width {
+            end = line[..end]
+                .grapheme_indices(true)
+                .next_back()
+                .map(|(idx, _)| idx)
+                .unwrap_or(0);
+        }
+        let visible = line[..end].trim_end();
+        lm.end_offset = lm.start_offset + visible.len();
+        lm.trailing_whitespace = 0;
+        lm.trailing_whitespace_width = 0.0;
+
+        let x = text_width(visible, &self.ctx);
+        self.ellipsis = Some(Point::new(x, lm.y_offset + lm.baseline));
+    }
+
+    /// The rects covering a range of the text, from the top to the bottom of
+    /// each line, including the rects of any newlines if `newlines` is `true`.
+    fn range_rects(&self, range: impl RangeBounds<usize>, newlines: bool) -> Vec<Rect> {
+        let mut rects = Vec::new();
+        for (line_number, range, newline) in
+            util::line_ranges(self.text.as_str(), &self.line_metrics, range)
+        {
+            let lm = &self.line_metrics[line_number];
+            let line = &self.text.as_str()[lm.range()];
+            let x = |pos: usize| {
+                hit_test_line_position(&self.ctx, line, pos - lm.start_offset).unwrap_or_default()
+            };
+            let x1 = x(range.end);
+            if !range.is_empty() {
+                rects.push(Rect::new(
+                    x(range.start),
+                    lm.y_offset,
+                    x1,
+                    lm.y_offset + lm.height,
+                ));
+            }
+            if newline && newlines {
+                rects.push(util::newline_rect(lm, x1));
+            }
+        }
+        rects
+    }
+
+    /// Returns the rects (relative to the layout's origin) and colors of the
+    /// backgrounds of this layout.
+    pub(crate) fn background_rects(&self) -> Vec<(Rect, Color)> {
+        let mut result = Vec::new();
+        for (range, color) in &self.backgrounds {
+            for rect in self.range_rects(range.clone(), false) {
+                if rect.width() > 0.0 {
+                    result.push((rect, color.clone()));
+                }
+            }
+        }
+        result
+    }
+
+    /// Returns the shadowed runs of text on a line, with their shadows and
+    /// their inked rects, relative to the start of that line's baseline.
+    ///
+    /// Each run is drawn with its baseline starting at the left of its
+    /// advance, which is not the left of its inked rect.
+    pub(crate) fn shadow_runs(&self, lm: &LineMetric) -> Vec<(f64, &str, Rect, &ShadowStyle)> {
+        let text = self.text.as_str();
+        let mut runs = Vec::new();
+        for (range, shadow) in &self.shadows {
+            let (x, _) = match self.decoration_span(lm, range) {
+                Some(span) => span,
+                None => continue,
+            };
+            let start = range.start.max(lm.start_offset);
+            let end = range.end.min(lm.end_offset - lm.trailing_whitespace);
+            let run = &text[start..end];
+            if let Ok(m) = self.ctx.measure_text(run) {
+                let ink = Rect::new(
+                    x - m.actual_bounding_box_left(),
+                    -m.actual_bounding_box_ascent(),
+                    x + m.actual_bounding_box_right(),
+                    m.actual_bounding_box_descent(),
+                );
+                runs.push((x, run, ink, shadow));
+            }
+        }
+        runs
+    }
+
+    /// The thickness of decoration lines, and the offsets from the baseline
+    /// to the centers of the underline and the strikethrough.
+    ///
+    /// Canvas doesn't expose decoration metrics, so these are approximated
+    /// from the font size.
+    fn decoration_metrics(&self) -> (f64, f64, f64) {
+        decoration_metrics(self.font.size)
+    }
+
+    /// The thickness of underline and strikethrough lines.
+    pub(crate) fn decoration_thickness(&self) -> f64 {
+        self.decoration_metrics().0
+    }
+
+    /// Returns the start and end x positions of the visible part of `range`
+    /// on a line, if it touches that line.
+    fn decoration_span(&self, lm: &LineMetric, range: &Range<usize>) -> Option<(f64, f64)> {
+        let line = &self.text.as_str()[lm.range()];
+        let start = range.start.max(lm.start_offset);
+        let end = range.end.min(lm.end_offset - lm.trailing_whitespace);
+        if start >= end {
+            return None;
+        }
+        let x0 =
+            hit_test_line_position(&self.ctx, line, start - lm.start_offset).unwrap_or_default();
+        let x1 = hit_test_line_position(&self.ctx, line, end - lm.start_offset).unwrap_or_default();
+        Some((x0, x1))
+    }
+
+    /// Returns the rects covering the strikethrough decorations of a line,
+    /// relative to the start of that line's baseline.
+    pub(crate) fn strikethrough_rects(&self, lm: &LineMetric) -> Vec<Rect> {
+        let (thickness, _, offset) = self.decoration_metrics();
+        let y0 = offset - thickness / 2.0;
+        self.strikethroughs
+            .iter()
+            .filter_map(|range| self.decoration_span(lm, range))
+            .map(|(x0, x1)| Rect::new(x0, y0, x1, y0 + thickness))
+            .collect()
+    }
+
+    /// Returns the underlines of a line, relative to the start of that line's
+    /// baseline.
+    ///
+    /// Each is a path to be stroked with its `StrokeStyle` at the width given by
+    /// [`decoration_thickness`], in its color if it has one, or else in the
+    /// text's color.
+    ///
+    /// [`decoration_thickness`]: #method.decoration_thickness
+    pub(crate) fn underline_paths(
+        &self,
+        lm: &LineMetric,
+    ) -> Vec<(BezPath, StrokeStyle, Option<Color>)> {
+        let (thickness, offset, _) = self.decoration_metrics();
+        let mut paths = Vec::new();
+        for (range, style, color) in &self.underlines {
+            if let Some((x0, x1)) = self.decoration_span(lm, range) {
+                let (path, stroke) = util::underline_geometry(*style, x0, x1, offset, thickness);
+                paths.push((path, stroke, color.clone()));
+            }
+        }
+        paths
+    }
 }
 
 // NOTE this is the same as the old, non-line-aware version of hit_test_point
@@ -290,7 +700,7 @@ fn hit_test_line_point(ctx: &CanvasRenderingContext2d, text: &str, point: Point)
 
     // first test beyond ends
     if point.x > end_bounds.trailing {
-        return HitTestPoint::new(text.len(), false);
+        return HitTestPoint::new(text.len(), false, Affinity::Upstream);
     }
 
     if point.x <= start_bounds.leading {
@@ -371,6 +781,40 @@ fn hit_test_line_position(
     }
 }
 
+/// Set the canvas `letterSpacing` property.
+///
+/// This is not yet supported by all browsers; where it is missing this does
+/// nothing, and text is both measured and drawn without the extra spacing.
+pub(crate) fn set_letter_spacing(ctx: &CanvasRenderingContext2d, spacing: f64) {
+    let _ = Reflect::set(
+        ctx.as_ref(),
+        &JsValue::from("letterSpacing"),
+        &JsValue::from(format!("{}px", spacing)),
+    );
+}
+
+/// Set the base direction used when measuring and drawing text.
+///
+/// The text is always positioned from its left edge; by default the canvas
+/// aligns text to the `start` edge, which is the right edge in
+/// right-to-left text.
+pub(crate) fn set_text_direction(ctx: &CanvasRenderingContext2d, rtl: bool) {
+    let direction = if rtl { "rtl" } else { "ltr" };
+    let _ = Reflect::set(
+        ctx.as_ref(),
+        &JsValue::from("direction"),
+        &JsValue::from(direction),
+    );
+    ctx.set_text_align("left");
+}
+
+/// The thickness of decoration lines, and the offsets from the baseline to
+/// the centers of the underline and the strikethrough, approximated from
+/// the font size.
+fn decoration_metrics(size: f64) -> (f64, f64, f64) {
+    ((size / 16.0).max(1.0), size * 0.1, size * -0.3)
+}
+
 pub(crate) fn text_width(text: &str, ctx: &CanvasRenderingContext2d) -> f64 {
     ctx.measure_text(text)
         .map(|m| m.width())
@@ -425,6 +869,20 @@ pub(crate) mod test {
         assert!(x <= max && x >= min);
     }
 
+    #[wasm_bindgen_test]
+    pub fn test_font_metrics() {
+        let (_window, context) = setup_ctx();
+        let mut text = WebText::new(context);
+
+        let font = text.font_family("sans-serif").unwrap();
+        let metrics = text.font_metrics(&font, 20.0).unwrap();
+        assert!(metrics.ascent > metrics.cap_height);
+        assert!(metrics.cap_height > metrics.x_height);
+        assert!(metrics.x_height > 0.0);
+        assert!(metrics.underline_position > 0.0);
+        assert!(metrics.strikeout_position < 0.0);
+    }
+
     #[wasm_bindgen_test]
     pub fn test_hit_test_text_position_basic() {
         let (_window, context) = setup_ctx();
@@ -1065,4 +1523,28 @@ pub(crate) mod test {
         assert_eq!(pt.idx, 5);
         assert_eq!(pt.is_inside, false);
     }
+
+    #[wasm_bindgen_test]
+    pub fn test_rects_for_range() {
+        let (_window, context) = setup_ctx();
+        let mut text = WebText::new(context);
+
+        let font = text.font_family("sans-serif").unwrap();
+        let layout = text
+            .new_text_layout("piet text\nis the best")
+            .font(font, 14.0)
+            .build()
+            .unwrap();
+        let x = |pos| layout.hit_test_text_position(pos).unwrap().point.x;
+        let line_1 = layout.line_metric(1).unwrap();
+
+        let rects = layout.rects_for_range(2..13);
+        assert_eq!(rects.len(), 3);
+        assert_close_to(rects[0].x0, x(2), 0.01);
+        assert_close_to(rects[0].x1, x(9), 0.01);
+        // the newline
+        assert_close_to(rects[1].x0, x(9), 0.01);
+        assert_close_to(rects[2].x0, 0.0, 0.01);
+        assert_close_to(rects[2].y0, line_1.y_offset, 0.01);
+    }
 }
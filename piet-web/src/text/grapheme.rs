@@ -1,4 +1,4 @@
-use piet::HitTestPoint;
+use piet::{Affinity, HitTestPoint};
 use unicode_segmentation::UnicodeSegmentation;
 use web_sys::CanvasRenderingContext2d;
 
@@ -47,12 +47,13 @@ pub(crate) fn point_x_in_grapheme(
         // Round up to next grapheme boundary if
         let midpoint = leading + ((trailing - leading) / 2.0);
         let is_inside = true;
-        let idx = if point_x >= midpoint {
-            next_idx
+        // a hit on the trailing half belongs with this grapheme
+        let (idx, affinity) = if point_x >= midpoint {
+            (next_idx, Affinity::Upstream)
         } else {
-            curr_idx
+            (curr_idx, Affinity::Downstream)
         };
-        Some(HitTestPoint::new(idx, is_inside))
+        Some(HitTestPoint::new(idx, is_inside, affinity))
     } else {
         None
     }
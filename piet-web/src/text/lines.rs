@@ -15,6 +15,7 @@ use super::{text_width, LineMetric};
 pub(crate) fn calculate_line_metrics(
     text: &str,
     ctx: &CanvasRenderingContext2d,
+    break_opportunities: Option<&[usize]>,
     width: f64,
     font_size: f64,
 ) -> Vec<LineMetric> {
@@ -54,7 +55,7 @@ pub(crate) fn calculate_line_metrics(
     let height = font_size * 1.2;
     let baseline = height * 0.8;
 
-    for (line_break, is_hard_break) in LineBreakIterator::new(text) {
+    for (line_break, is_hard_break) in line_breaks(text, break_opportunities) {
         if !is_hard_break {
             // this section is for soft breaks
             let curr_str = &text[line_start..line_break];
@@ -77,6 +78,7 @@ pub(crate) fn calculate_line_metrics(
                 // first do the line to prev break
                 add_line_metric(
                     text,
+                    ctx,
                     line_start,
                     prev_break,
                     baseline,
@@ -97,6 +99,7 @@ pub(crate) fn calculate_line_metrics(
                 if curr_width > width {
                     add_line_metric(
                         text,
+                        ctx,
                         prev_break,
                         line_break,
                         baseline,
@@ -134,6 +137,7 @@ pub(crate) fn calculate_line_metrics(
                 if prev_break != line_start {
                     add_line_metric(
                         text,
+                        ctx,
                         line_start,
                         prev_break,
                         baseline,
@@ -149,6 +153,7 @@ pub(crate) fn calculate_line_metrics(
             // now do the hard break
             add_line_metric(
                 text,
+                ctx,
                 line_start,
                 line_break,
                 baseline,
@@ -164,8 +169,30 @@ pub(crate) fn calculate_line_metrics(
     line_metrics
 }
 
+/// The offsets at which `text` may be broken, and whether each break is
+/// mandatory; `break_opportunities`, if set, replaces the soft breaks found
+/// by the unicode line breaking algorithm.
+fn line_breaks(text: &str, break_opportunities: Option<&[usize]>) -> Vec<(usize, bool)> {
+    let breaks = LineBreakIterator::new(text);
+    match break_opportunities {
+        None => breaks.collect(),
+        Some(soft_breaks) => {
+            let mut breaks: Vec<_> = breaks
+                .filter(|(_, is_hard_break)| *is_hard_break)
+                .chain(soft_breaks.iter().map(|offset| (*offset, false)))
+                .collect();
+            // a hard break takes precedence over a soft break at the same offset
+            breaks.sort_by_key(|(offset, is_hard_break)| (*offset, !is_hard_break));
+            breaks.dedup_by_key(|(offset, _)| *offset);
+            breaks
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn add_line_metric(
     text: &str,
+    ctx: &CanvasRenderingContext2d,
     start_offset: usize,
     end_offset: usize,
     baseline: f64,
@@ -175,12 +202,14 @@ fn add_line_metric(
 ) {
     let line = &text[start_offset..end_offset];
     let trailing_whitespace = count_trailing_whitespace(line);
+    let trailing_whitespace_width = measure_trailing_whitespace(line, ctx);
 
     #[allow(deprecated)]
     let line_metric = LineMetric {
         start_offset,
         end_offset,
         trailing_whitespace,
+        trailing_whitespace_width,
         baseline,
         height,
         y_offset: *y_offset,
@@ -191,6 +220,16 @@ fn add_line_metric(
 
 // TODO: is non-breaking space trailing whitespace? Check with dwrite and
 // coretext
+/// The width of the whitespace at the end of a line, not including any newline.
+fn measure_trailing_whitespace(line: &str, ctx: &CanvasRenderingContext2d) -> f64 {
+    let content = line.trim_end();
+    let whitespace_end = line.trim_end_matches(&['\n', '\r'][..]).len();
+    if whitespace_end <= content.len() {
+        return 0.0;
+    }
+    text_width(&line[..whitespace_end], ctx) - text_width(content, ctx)
+}
+
 fn count_trailing_whitespace(line: &str) -> usize {
     line.chars().rev().take_while(|c| c.is_whitespace()).count()
 }
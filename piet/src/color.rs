@@ -1,6 +1,7 @@
 //! A simple representation of color
 
-use std::fmt::{Debug, Formatter};
+use std::fmt::{self, Debug, Formatter};
+use std::str::FromStr;
 
 /// A datatype representing color.
 ///
@@ -156,6 +157,119 @@ impl Color {
         Color::hlc(h, c, l).with_alpha(a)
     }
 
+    /// Create a color from HSL (hue, saturation, lightness) and alpha.
+    ///
+    /// The hue `h` is an angle in degrees, with 0 red, 120 green and 240
+    /// blue, and wraps around outside 0..360. Saturation `s`, lightness `l`,
+    /// and alpha `a` are in the range 0.0 to 1.0.
+    ///
+    /// This works on the non-linear sRGB values, using the algorithm from
+    /// [CSS Color 4](https://www.w3.org/TR/css-color-4/#hsl-to-rgb), so the
+    /// result matches CSS `hsla()`.
+    pub fn hsla<F: Into<f64>>(h: F, s: F, l: F, a: impl Into<f64>) -> Color {
+        let h = h.into().rem_euclid(360.);
+        let s = s.into().clamp(0.0, 1.0);
+        let l = l.into().clamp(0.0, 1.0);
+        let f = |n: f64| {
+            let k = (n + h / 30.) % 12.;
+            let a = s * l.min(1. - l);
+            l - a * (k - 3.).min(9. - k).clamp(-1., 1.)
+        };
+        Color::rgba(f(0.), f(8.), f(4.), a.into())
+    }
+
+    /// Create a color from HSV (hue, saturation, value) and alpha.
+    ///
+    /// The hue `h` is an angle in degrees, as for [`hsla`]. Saturation `s`,
+    /// value `v`, and alpha `a` are in the range 0.0 to 1.0.
+    ///
+    /// Like [`hsla`], this works on the non-linear sRGB values. CSS has no
+    /// HSV function, but this is the same model as its HWB colors.
+    ///
+    /// [`hsla`]: #method.hsla
+    pub fn hsva<F: Into<f64>>(h: F, s: F, v: F, a: impl Into<f64>) -> Color {
+        let h = h.into().rem_euclid(360.);
+        let s = s.into().clamp(0.0, 1.0);
+        let v = v.into().clamp(0.0, 1.0);
+        let f = |n: f64| {
+            let k = (n + h / 60.) % 6.;
+            v - v * s * k.min(4. - k).clamp(0., 1.)
+        };
+        Color::rgba(f(5.), f(3.), f(1.), a.into())
+    }
+
+    /// Convert a color to HSL (hue, saturation, lightness) and alpha.
+    ///
+    /// This is the inverse of [`hsla`]. The hue is in degrees, in the range
+    /// 0.0 to 360.0, and is 0 for greys; the other values are in the range
+    /// 0.0 to 1.0.
+    ///
+    /// [`hsla`]: #method.hsla
+    pub fn to_hsla(&self) -> (f64, f64, f64, f64) {
+        let (r, g, b, a) = self.as_rgba();
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let l = (max + min) / 2.;
+        let s = if max == min || l == 0. || l == 1. {
+            0.
+        } else {
+            (max - l) / l.min(1. - l)
+        };
+        (hue(r, g, b, max, min), s, l, a)
+    }
+
+    /// Convert a color to HSV (hue, saturation, value) and alpha.
+    ///
+    /// This is the inverse of [`hsva`], with the hue as for [`to_hsla`].
+    ///
+    /// [`hsva`]: #method.hsva
+    /// [`to_hsla`]: #method.to_hsla
+    pub fn to_hsva(&self) -> (f64, f64, f64, f64) {
+        let (r, g, b, a) = self.as_rgba();
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let s = if max == 0. { 0. } else { (max - min) / max };
+        (hue(r, g, b, max, min), s, max, a)
+    }
+
+    /// Interpolate between this color and `other`, in the given color space.
+    ///
+    /// `t` is clamped to the range 0.0 to 1.0; 0.0 gives this color exactly,
+    /// and 1.0 gives `other`. Alpha is always interpolated linearly.
+    ///
+    /// ```
+    /// use piet::{Color, ColorSpaceLerp};
+    ///
+    /// let mid = Color::BLACK.lerp(Color::WHITE, 0.5, ColorSpaceLerp::Srgb);
+    /// assert_eq!(mid.as_rgba_u32(), 0x808080ff);
+    /// ```
+    pub fn lerp(self, other: Color, t: f64, space: ColorSpaceLerp) -> Color {
+        if t <= 0.0 || t.is_nan() {
+            return self;
+        }
+        if t >= 1.0 {
+            return other;
+        }
+        let (r0, g0, b0, a0) = self.as_rgba();
+        let (r1, g1, b1, a1) = other.as_rgba();
+        let lerp = |x: f64, y: f64| x + (y - x) * t;
+        let a = lerp(a0, a1);
+        match space {
+            ColorSpaceLerp::Srgb => Color::rgba(lerp(r0, r1), lerp(g0, g1), lerp(b0, b1), a),
+            ColorSpaceLerp::LinearSrgb => {
+                let mix =
+                    |x: f64, y: f64| linear_to_srgb(lerp(srgb_to_linear(x), srgb_to_linear(y)));
+                Color::rgba(mix(r0, r1), mix(g0, g1), mix(b0, b1), a)
+            }
+            ColorSpaceLerp::Oklab => {
+                let [l0, u0, v0] = srgb_to_oklab([r0, g0, b0]);
+                let [l1, u1, v1] = srgb_to_oklab([r1, g1, b1]);
+                let [r, g, b] = oklab_to_srgb([lerp(l0, l1), lerp(u0, u1), lerp(v0, v1)]);
+                Color::rgba(r, g, b, a)
+            }
+        }
+    }
+
     /// Change just the alpha value of a color.
     ///
     /// The `a` value represents alpha in the range 0.0 to 1.0.
@@ -204,4 +318,655 @@ impl Color {
 
     /// Opaque black.
     pub const BLACK: Color = Color::rgb8(0, 0, 0);
+
+    /// Parse a color from a CSS color string.
+    ///
+    /// This accepts hex colors with 3, 4, 6 or 8 digits (`#f80`, `#ff880080`),
+    /// the `rgb()` and `rgba()` functions with number or percentage components
+    /// (`rgb(255, 136, 0)`, `rgba(100%, 50%, 0%, 0.5)`), and the CSS named
+    /// colors (`darkorange`, `transparent`). Names and hex digits are
+    /// case-insensitive, and surrounding whitespace is ignored.
+    ///
+    /// Components outside their range are clamped, as in CSS.
+    ///
+    /// ```
+    /// use piet::Color;
+    ///
+    /// let orange = Color::from_css_str("#ff8800").unwrap();
+    /// assert_eq!(orange.as_rgba_u32(), 0xff8800ff);
+    /// let shadow = Color::from_css_str("rgba(0, 0, 0, 0.5)").unwrap();
+    /// assert_eq!(shadow.as_rgba_u32(), 0x00000080);
+    /// ```
+    pub fn from_css_str(s: &str) -> Result<Color, ColorParseError> {
+        let s = s.trim();
+        if let Some(hex) = s.strip_prefix('#') {
+            parse_hex(hex)
+        } else if let Some(open) = s.find('(') {
+            let name = s[..open].trim_end();
+            let args = s[open + 1..]
+                .strip_suffix(')')
+                .ok_or(ColorParseError::InvalidFunction)?;
+            if name.eq_ignore_ascii_case("rgb") || name.eq_ignore_ascii_case("rgba") {
+                parse_rgb_args(args)
+            } else {
+                Err(ColorParseError::InvalidFunction)
+            }
+        } else {
+            parse_named(s)
+        }
+    }
+}
+
+impl FromStr for Color {
+    type Err = ColorParseError;
+
+    fn from_str(s: &str) -> Result<Color, ColorParseError> {
+        Color::from_css_str(s)
+    }
+}
+
+/// Colors are `#rrggbbaa` strings in human-readable formats, and `u32`
+/// values, as from [`as_rgba_u32`], in others. Any CSS color string can be
+/// deserialized.
+///
+/// [`as_rgba_u32`]: enum.Color.html#method.as_rgba_u32
+#[cfg(feature = "serde")]
+impl serde::Serialize for Color {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(&format_args!("#{:08x}", self.as_rgba_u32()))
+        } else {
+            serializer.serialize_u32(self.as_rgba_u32())
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Color {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+        if deserializer.is_human_readable() {
+            let s = String::deserialize(deserializer)?;
+            Color::from_css_str(&s).map_err(serde::de::Error::custom)
+        } else {
+            u32::deserialize(deserializer).map(Color::from_rgba32_u32)
+        }
+    }
+}
+
+/// An error parsing a CSS color string, from [`Color::from_css_str`].
+///
+/// [`Color::from_css_str`]: enum.Color.html#method.from_css_str
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ColorParseError {
+    /// A `#` was not followed by 3, 4, 6 or 8 hex digits.
+    InvalidHex,
+    /// A function was not `rgb()` or `rgba()`, or its arguments were malformed.
+    InvalidFunction,
+    /// The string was not a known color name.
+    UnknownName,
+}
+
+impl fmt::Display for ColorParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ColorParseError::InvalidHex => write!(f, "Invalid hex color"),
+            ColorParseError::InvalidFunction => write!(f, "Invalid color function"),
+            ColorParseError::UnknownName => write!(f, "Unknown color name"),
+        }
+    }
+}
+
+impl std::error::Error for ColorParseError {}
+
+/// The color space in which [`Color::lerp`] interpolates.
+///
+/// [`Color::lerp`]: enum.Color.html#method.lerp
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpaceLerp {
+    /// Interpolate the non-linear sRGB components, as most platforms do.
+    ///
+    /// This is cheap, but midpoints between saturated colors are dark and
+    /// desaturated.
+    Srgb,
+    /// Interpolate in linear light, which is how light physically mixes.
+    LinearSrgb,
+    /// Interpolate in [Oklab](https://bottosson.github.io/posts/oklab/), a
+    /// perceptual color space, which keeps midpoints evenly bright.
+    Oklab,
+}
+
+fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f64) -> f64 {
+    if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Convert non-linear sRGB to Oklab, with the matrices from
+/// https://bottosson.github.io/posts/oklab/
+#[allow(clippy::unreadable_literal, clippy::excessive_precision)]
+fn srgb_to_oklab(rgb: [f64; 3]) -> [f64; 3] {
+    let [r, g, b] = [
+        srgb_to_linear(rgb[0]),
+        srgb_to_linear(rgb[1]),
+        srgb_to_linear(rgb[2]),
+    ];
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+/// The inverse of [`srgb_to_oklab`]. Colors outside of sRGB are not clipped.
+#[allow(clippy::unreadable_literal, clippy::excessive_precision)]
+fn oklab_to_srgb([l, a, b]: [f64; 3]) -> [f64; 3] {
+    let l_ = (l + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m_ = (l - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s_ = (l - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+    [
+        linear_to_srgb(4.0767416621 * l_ - 3.3077115913 * m_ + 0.2309699292 * s_),
+        linear_to_srgb(-1.2684380046 * l_ + 2.6097574011 * m_ - 0.3413193965 * s_),
+        linear_to_srgb(-0.0041960863 * l_ - 0.7034186147 * m_ + 1.7076147010 * s_),
+    ]
+}
+
+/// The hue shared by HSL and HSV, in degrees.
+fn hue(r: f64, g: f64, b: f64, max: f64, min: f64) -> f64 {
+    let d = max - min;
+    if d == 0. {
+        return 0.;
+    }
+    let h = if max == r {
+        (g - b) / d + if g < b { 6. } else { 0. }
+    } else if max == g {
+        (b - r) / d + 2.
+    } else {
+        (r - g) / d + 4.
+    };
+    h * 60.
+}
+
+fn parse_hex(hex: &str) -> Result<Color, ColorParseError> {
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(ColorParseError::InvalidHex);
+    }
+    let digits = u32::from_str_radix(hex, 16).map_err(|_| ColorParseError::InvalidHex)?;
+    // short forms repeat each digit: #f80 is #ff8800
+    let expand = |x: u32| {
+        let mut out = 0;
+        for i in (0..4).rev() {
+            out = (out << 8) | (((x >> (i * 4)) & 0xf) * 0x11);
+        }
+        out
+    };
+    let rgba = match hex.len() {
+        3 => expand(digits << 4 | 0xf),
+        4 => expand(digits),
+        6 => digits << 8 | 0xff,
+        8 => digits,
+        _ => return Err(ColorParseError::InvalidHex),
+    };
+    Ok(Color::from_rgba32_u32(rgba))
+}
+
+/// The arguments of `rgb()` or `rgba()`, without the parentheses.
+///
+/// Both functions take an optional alpha, as in CSS Color 4.
+fn parse_rgb_args(args: &str) -> Result<Color, ColorParseError> {
+    let mut values = [1.0; 4];
+    let mut n = 0;
+    for arg in args.split(',') {
+        if n == 4 {
+            return Err(ColorParseError::InvalidFunction);
+        }
+        let arg = arg.trim();
+        let (number, scale) = match arg.strip_suffix('%') {
+            Some(percent) => (percent, 0.01),
+            // color components are 0..=255, alpha is 0..=1
+            None if n < 3 => (arg, 1.0 / 255.0),
+            None => (arg, 1.0),
+        };
+        let value: f64 = number
+            .parse()
+            .map_err(|_| ColorParseError::InvalidFunction)?;
+        if !value.is_finite() {
+            return Err(ColorParseError::InvalidFunction);
+        }
+        values[n] = value * scale;
+        n += 1;
+    }
+    if n < 3 {
+        return Err(ColorParseError::InvalidFunction);
+    }
+    let [r, g, b, a] = values;
+    Ok(Color::rgba(r, g, b, a))
+}
+
+fn parse_named(name: &str) -> Result<Color, ColorParseError> {
+    if name.eq_ignore_ascii_case("transparent") {
+        return Ok(Color::rgba8(0, 0, 0, 0));
+    }
+    let name = name.to_ascii_lowercase();
+    NAMED_COLORS
+        .binary_search_by_key(&name.as_str(), |&(n, _)| n)
+        .map(|i| Color::from_rgba32_u32(NAMED_COLORS[i].1 << 8 | 0xff))
+        .map_err(|_| ColorParseError::UnknownName)
+}
+
+/// The CSS named colors, as 24-bit rgb values, sorted by name.
+#[allow(clippy::unreadable_literal)]
+const NAMED_COLORS: &[(&str, u32)] = &[
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<u32, ColorParseError> {
+        Color::from_css_str(s).map(|c| c.as_rgba_u32())
+    }
+
+    fn assert_close(a: &Color, b: &Color) {
+        let (a, b) = (a.as_rgba_u32(), b.as_rgba_u32());
+        let close = (0..4).all(|i| {
+            let channel = |x: u32| (x >> (i * 8)) as u8 as i16;
+            (channel(a) - channel(b)).abs() <= 1
+        });
+        assert!(close, "{:08x} != {:08x}", a, b);
+    }
+
+    #[test]
+    fn hsl_hsv_values() {
+        let cases = [
+            (0., 0xff0000ff),
+            (60., 0xffff00ff),
+            (120., 0x00ff00ff),
+            (240., 0x0000ffff),
+            (360., 0xff0000ff),
+            (-120., 0x0000ffff),
+            (480., 0x00ff00ff),
+        ];
+        for &(h, rgba) in &cases {
+            assert_eq!(Color::hsla(h, 1., 0.5, 1.).as_rgba_u32(), rgba);
+            assert_eq!(Color::hsva(h, 1., 1., 1.).as_rgba_u32(), rgba);
+        }
+        assert_eq!(Color::hsla(120., 1., 0.25, 1.).as_rgba_u32(), 0x008000ff);
+        assert_eq!(Color::hsla(30., 0., 0.5, 0.5).as_rgba_u32(), 0x80808080);
+        assert_eq!(Color::hsva(30., 0.5, 0., 1.).as_rgba_u32(), 0x000000ff);
+        assert_eq!(Color::rgb8(0xff, 0x80, 0).to_hsla().0.round(), 30.);
+        assert_eq!(
+            Color::grey8(0x40).to_hsva(),
+            (0., 0., 0x40 as f64 / 255., 1.)
+        );
+    }
+
+    #[test]
+    fn hsl_hsv_round_trip() {
+        for h in (0..360).step_by(3) {
+            for s in 0..=10 {
+                for x in 0..=10 {
+                    let (h, s, x) = (h as f64, s as f64 / 10., x as f64 / 10.);
+                    let hsl = Color::hsla(h, s, x, 0.5);
+                    let (h1, s1, l1, a1) = hsl.to_hsla();
+                    assert_close(&Color::hsla(h1, s1, l1, a1), &hsl);
+                    let hsv = Color::hsva(h, s, x, 0.5);
+                    let (h1, s1, v1, a1) = hsv.to_hsva();
+                    assert_close(&Color::hsva(h1, s1, v1, a1), &hsv);
+                }
+            }
+        }
+        for r in (0..=255).step_by(15) {
+            for g in (0..=255).step_by(15) {
+                for b in (0..=255).step_by(15) {
+                    let color = Color::rgb8(r, g, b);
+                    let (h, s, l, a) = color.to_hsla();
+                    assert!((0. ..360.).contains(&h));
+                    assert_close(&Color::hsla(h, s, l, a), &color);
+                    let (h, s, v, a) = color.to_hsva();
+                    assert_close(&Color::hsva(h, s, v, a), &color);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lerp_endpoints() {
+        let (a, b) = (
+            Color::rgba8(0xff, 0x10, 0x20, 0x80),
+            Color::rgb8(3, 0x80, 0xfe),
+        );
+        for &space in &[
+            ColorSpaceLerp::Srgb,
+            ColorSpaceLerp::LinearSrgb,
+            ColorSpaceLerp::Oklab,
+        ] {
+            let lerp = |t| a.clone().lerp(b.clone(), t, space).as_rgba_u32();
+            assert_eq!(lerp(0.0), a.as_rgba_u32());
+            assert_eq!(lerp(1.0), b.as_rgba_u32());
+            assert_eq!(lerp(-2.0), a.as_rgba_u32());
+            assert_eq!(lerp(5.0), b.as_rgba_u32());
+            assert_eq!(lerp(0.5) & 0xff, 0xc0);
+        }
+    }
+
+    #[test]
+    fn lerp_midpoints() {
+        let mid = |a: Color, b: Color, space| a.lerp(b, 0.5, space).as_rgba_u32();
+        let (black, white) = (Color::BLACK, Color::WHITE);
+        assert_eq!(
+            mid(black.clone(), white.clone(), ColorSpaceLerp::Srgb),
+            0x808080ff
+        );
+        // linear 0.5 is sRGB 0.735
+        assert_eq!(
+            mid(black.clone(), white.clone(), ColorSpaceLerp::LinearSrgb),
+            0xbcbcbcff
+        );
+        // Oklab lightness is perceptual: L 0.5 is linear 0.125, sRGB 0.389
+        assert_eq!(mid(black, white, ColorSpaceLerp::Oklab), 0x636363ff);
+        let (red, blue) = (Color::rgb8(0xff, 0, 0), Color::rgb8(0, 0, 0xff));
+        assert_eq!(
+            mid(red.clone(), blue.clone(), ColorSpaceLerp::Srgb),
+            0x800080ff
+        );
+        assert_eq!(
+            mid(red.clone(), blue.clone(), ColorSpaceLerp::LinearSrgb),
+            0xbc00bcff
+        );
+        // as CSS color-mix(in oklab, red, blue)
+        assert_eq!(mid(red, blue, ColorSpaceLerp::Oklab), 0x8c53a2ff);
+    }
+
+    #[test]
+    fn oklab_reference_values() {
+        // published values, from the CSS Color 4 spec and the Oklab post
+        let cases = [
+            ([1., 1., 1.], [1.0, 0.0, 0.0]),
+            ([1., 0., 0.], [0.627955, 0.224863, 0.125846]),
+            ([0., 1., 0.], [0.866440, -0.233888, 0.179498]),
+            ([0., 0., 1.], [0.452014, -0.032457, -0.311528]),
+        ];
+        for &(rgb, lab) in &cases {
+            let result = srgb_to_oklab(rgb);
+            for i in 0..3 {
+                assert!((result[i] - lab[i]).abs() < 1e-4, "{:?} {:?}", rgb, result);
+            }
+            let back = oklab_to_srgb(lab);
+            for i in 0..3 {
+                assert!((back[i] - rgb[i]).abs() < 1e-3, "{:?} {:?}", lab, back);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_hex_colors() {
+        assert_eq!(parse("#f80"), Ok(0xff8800ff));
+        assert_eq!(parse("#f808"), Ok(0xff880088));
+        assert_eq!(parse("#ff8800"), Ok(0xff8800ff));
+        assert_eq!(parse("#ff880080"), Ok(0xff880080));
+        assert_eq!(parse("#FF8800"), Ok(0xff8800ff));
+        assert_eq!(parse("#aBcDeF"), Ok(0xabcdefff));
+        for bad in &[
+            "#", "#f", "#ff", "#fffff", "#fffffff", "#ff88000", "#ggg", "#+ff", "# fff",
+        ] {
+            assert_eq!(parse(bad), Err(ColorParseError::InvalidHex), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn parse_rgb_functions() {
+        assert_eq!(parse("rgb(10, 20, 30)"), Ok(0x0a141eff));
+        assert_eq!(parse("rgb(10,20,30)"), Ok(0x0a141eff));
+        assert_eq!(parse("rgba(0,0,0,0.5)"), Ok(0x00000080));
+        assert_eq!(parse("rgba(0, 0, 0, 50%)"), Ok(0x00000080));
+        assert_eq!(parse("rgb(100%, 50%, 0%)"), Ok(0xff8000ff));
+        assert_eq!(parse("rgb(100%, 128, 0)"), Ok(0xff8000ff));
+        assert_eq!(parse("rgb(10.4, 20.6, 30)"), Ok(0x0a151eff));
+        // rgb takes an alpha, and rgba can leave it out
+        assert_eq!(parse("rgb(1, 2, 3, 0)"), Ok(0x01020300));
+        assert_eq!(parse("rgba(1, 2, 3)"), Ok(0x010203ff));
+        assert_eq!(parse("RGBA(1, 2, 3, 1)"), Ok(0x010203ff));
+        for bad in &[
+            "rgb()",
+            "rgb(1, 2)",
+            "rgb(1, 2, 3, 4, 5)",
+            "rgb(1, 2, 3",
+            "rgb(1 2 3)",
+            "rgb(1, , 3)",
+            "rgb(1, 2, 3,)",
+            "rgb(a, b, c)",
+            "rgb(1, 2, inf)",
+            "rgb(1, 2, NaN)",
+            "rgb(1, 2, 3%%)",
+            "hsl(0, 0%, 0%)",
+        ] {
+            assert_eq!(parse(bad), Err(ColorParseError::InvalidFunction), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn clamp_components() {
+        assert_eq!(parse("rgb(300, -20, 255)"), Ok(0xff00ffff));
+        assert_eq!(parse("rgb(150%, -5%, 0%)"), Ok(0xff0000ff));
+        assert_eq!(parse("rgba(0, 0, 0, 2)"), Ok(0x000000ff));
+        assert_eq!(parse("rgba(0, 0, 0, -1)"), Ok(0x00000000));
+        assert_eq!(parse("rgba(0, 0, 0, 200%)"), Ok(0x000000ff));
+    }
+
+    #[test]
+    fn whitespace() {
+        assert_eq!(parse("  #ff8800\t"), Ok(0xff8800ff));
+        assert_eq!(parse("\nred "), Ok(0xff0000ff));
+        assert_eq!(parse(" rgb( 10 ,\t20 ,30 ) "), Ok(0x0a141eff));
+        assert_eq!(parse("rgb (10, 20, 30)"), Ok(0x0a141eff));
+        assert_eq!(parse(""), Err(ColorParseError::UnknownName));
+        assert_eq!(parse("   "), Err(ColorParseError::UnknownName));
+        assert_eq!(parse("dark orange"), Err(ColorParseError::UnknownName));
+    }
+
+    #[test]
+    fn named_colors() {
+        assert!(NAMED_COLORS.windows(2).all(|w| w[0].0 < w[1].0));
+        assert_eq!(NAMED_COLORS.len(), 148);
+        for &(name, rgb) in NAMED_COLORS {
+            assert_eq!(parse(name), Ok(rgb << 8 | 0xff));
+            assert_eq!(parse(&name.to_ascii_uppercase()), Ok(rgb << 8 | 0xff));
+        }
+        assert_eq!(parse("RebeccaPurple"), Ok(0x663399ff));
+        assert_eq!(parse("Transparent"), Ok(0x00000000));
+        assert_eq!(parse("grey"), parse("gray"));
+        assert_eq!(parse("notacolor"), Err(ColorParseError::UnknownName));
+        assert_eq!(
+            "white".parse::<Color>().map(|c| c.as_rgba_u32()),
+            Ok(0xffffffff)
+        );
+        assert!("#12".parse::<Color>().is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let color = Color::rgba8(0x12, 0x34, 0x56, 0x78);
+        let json = serde_json::to_string(&color).unwrap();
+        assert_eq!(json, "\"#12345678\"");
+        let back: Color = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.as_rgba_u32(), 0x12345678);
+        let named: Color = serde_json::from_str("\"rebeccapurple\"").unwrap();
+        assert_eq!(named.as_rgba_u32(), 0x663399ff);
+        assert!(serde_json::from_str::<Color>("\"#12\"").is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_binary_round_trip() {
+        let color = Color::rgba8(0x12, 0x34, 0x56, 0x78);
+        let bytes = bincode::serialize(&color).unwrap();
+        assert_eq!(bytes, 0x12345678u32.to_le_bytes());
+        let back: Color = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(back.as_rgba_u32(), 0x12345678);
+        let white: Color = bincode::deserialize(&u32::MAX.to_le_bytes()).unwrap();
+        assert_eq!(white.as_rgba_u32(), Color::WHITE.as_rgba_u32());
+    }
 }
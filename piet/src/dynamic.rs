@@ -0,0 +1,381 @@
+//! An object-safe render context, for code that can't be generic over the
+//! backend.
+//!
+//! [`RenderContext`] takes `impl Trait` arguments and has associated types, so
+//! it can't be used as a trait object. [`DynRenderContext`] covers the same
+//! drawing operations with concrete argument types: shapes are [`BezPath`]s,
+//! brushes are [`PaintBrush`]es, and images are [`DynImage`]s. Wrap any render
+//! context in [`Dyn`] to get one:
+//!
+//! ```
+//! use piet::dynamic::{Dyn, DynRenderContext};
+//! use piet::kurbo::Rect;
+//! use piet::{Color, NullRenderContext};
+//!
+//! fn draw_plugin(rc: &mut dyn DynRenderContext) {
+//!     rc.fill_shape(Rect::new(0.0, 0.0, 10.0, 10.0), Color::BLACK);
+//! }
+//!
+//! let mut rc = NullRenderContext::new();
+//! draw_plugin(&mut Dyn::new(&mut rc));
+//! ```
+//!
+//! Text is not available through this interface yet.
+//!
+//! [`RenderContext`]: ../trait.RenderContext.html
+//! [`DynRenderContext`]: trait.DynRenderContext.html
+//! [`BezPath`]: ../kurbo/struct.BezPath.html
+//! [`PaintBrush`]: ../enum.PaintBrush.html
+//! [`DynImage`]: struct.DynImage.html
+//! [`Dyn`]: struct.Dyn.html
+
+use std::any::Any;
+use std::rc::Rc;
+
+use kurbo::{Affine, BezPath, Rect, Shape, Size};
+
+use crate::{
+    BlendMode, Color, Error, Image, ImageBuf, ImageFormat, InterpolationMode, LayerMask,
+    PaintBrush, RenderContext, ShadowStyle, StrokeStyle,
+};
+
+/// The tolerance for converting shapes to paths.
+const TOLERANCE: f64 = 1e-3;
+
+/// An image made by a [`DynRenderContext`].
+///
+/// This holds the backend's image. It can only be drawn by a context of the
+/// same backend; anything else ignores it, or returns
+/// [`Error::InvalidInput`].
+///
+/// [`DynRenderContext`]: trait.DynRenderContext.html
+/// [`Error::InvalidInput`]: ../enum.Error.html#variant.InvalidInput
+#[derive(Clone)]
+pub struct DynImage {
+    image: Rc<dyn Any>,
+    size: Size,
+}
+
+impl DynImage {
+    /// Wrap a backend image.
+    pub fn new<I: Image + 'static>(image: I) -> DynImage {
+        let size = image.size();
+        DynImage {
+            image: Rc::new(image),
+            size,
+        }
+    }
+
+    /// The backend image, if it is an `I`.
+    pub fn downcast_ref<I: Image + 'static>(&self) -> Option<&I> {
+        self.image.downcast_ref()
+    }
+}
+
+impl Image for DynImage {
+    fn size(&self) -> Size {
+        self.size
+    }
+}
+
+/// The drawing operations of [`RenderContext`], as an object-safe trait.
+///
+/// The methods are the same as those of [`RenderContext`], with concrete
+/// argument types; see the [module docs](index.html).
+///
+/// [`RenderContext`]: ../trait.RenderContext.html
+pub trait DynRenderContext {
+    /// See [`RenderContext::status`](../trait.RenderContext.html#tymethod.status).
+    fn status(&mut self) -> Result<(), Error>;
+
+    /// See [`RenderContext::clear`](../trait.RenderContext.html#tymethod.clear).
+    fn clear(&mut self, color: Color);
+
+    /// See [`RenderContext::stroke`](../trait.RenderContext.html#tymethod.stroke).
+    fn stroke(&mut self, shape: &BezPath, brush: &PaintBrush, width: f64);
+
+    /// See [`RenderContext::stroke_styled`](../trait.RenderContext.html#tymethod.stroke_styled).
+    fn stroke_styled(
+        &mut self,
+        shape: &BezPath,
+        brush: &PaintBrush,
+        width: f64,
+        style: &StrokeStyle,
+    );
+
+    /// See [`RenderContext::fill`](../trait.RenderContext.html#tymethod.fill).
+    fn fill(&mut self, shape: &BezPath, brush: &PaintBrush);
+
+    /// See [`RenderContext::fill_even_odd`](../trait.RenderContext.html#tymethod.fill_even_odd).
+    fn fill_even_odd(&mut self, shape: &BezPath, brush: &PaintBrush);
+
+    /// See [`RenderContext::clip`](../trait.RenderContext.html#tymethod.clip).
+    fn clip(&mut self, shape: &BezPath);
+
+    /// See [`RenderContext::clip_even_odd`](../trait.RenderContext.html#tymethod.clip_even_odd).
+    fn clip_even_odd(&mut self, shape: &BezPath);
+
+    /// See [`RenderContext::save`](../trait.RenderContext.html#tymethod.save).
+    fn save(&mut self) -> Result<(), Error>;
+
+    /// See [`RenderContext::restore`](../trait.RenderContext.html#tymethod.restore).
+    fn restore(&mut self) -> Result<(), Error>;
+
+    /// See [`RenderContext::save_layer`](../trait.RenderContext.html#tymethod.save_layer).
+    fn save_layer(&mut self, opacity: f64) -> Result<(), Error>;
+
+    /// See [`RenderContext::save_layer_with_mask`](../trait.RenderContext.html#tymethod.save_layer_with_mask).
+    fn save_layer_with_mask(&mut self, mask: LayerMask<DynImage>) -> Result<(), Error>;
+
+    /// See [`RenderContext::set_blend_mode`](../trait.RenderContext.html#tymethod.set_blend_mode).
+    fn set_blend_mode(&mut self, mode: BlendMode) -> Result<(), Error>;
+
+    /// See [`RenderContext::push_opacity`](../trait.RenderContext.html#tymethod.push_opacity).
+    fn push_opacity(&mut self, alpha: f64);
+
+    /// See [`RenderContext::pop_opacity`](../trait.RenderContext.html#tymethod.pop_opacity).
+    fn pop_opacity(&mut self) -> Result<(), Error>;
+
+    /// See [`RenderContext::finish`](../trait.RenderContext.html#tymethod.finish).
+    fn finish(&mut self) -> Result<(), Error>;
+
+    /// See [`RenderContext::transform`](../trait.RenderContext.html#tymethod.transform).
+    fn transform(&mut self, transform: Affine);
+
+    /// See [`RenderContext::make_image`](../trait.RenderContext.html#tymethod.make_image).
+    fn make_image(
+        &mut self,
+        width: usize,
+        height: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<DynImage, Error>;
+
+    /// See [`RenderContext::capture_image_area`](../trait.RenderContext.html#tymethod.capture_image_area).
+    fn capture_image_area(&mut self, src_rect: Rect) -> Result<DynImage, Error>;
+
+    /// See [`RenderContext::to_image_buf`](../trait.RenderContext.html#tymethod.to_image_buf).
+    fn to_image_buf(&mut self, image: &DynImage, format: ImageFormat) -> Result<ImageBuf, Error>;
+
+    /// See [`RenderContext::draw_image`](../trait.RenderContext.html#tymethod.draw_image).
+    fn draw_image(&mut self, image: &DynImage, dst_rect: Rect, interp: InterpolationMode);
+
+    /// See [`RenderContext::draw_image_area`](../trait.RenderContext.html#tymethod.draw_image_area).
+    fn draw_image_area(
+        &mut self,
+        image: &DynImage,
+        src_rect: Rect,
+        dst_rect: Rect,
+        interp: InterpolationMode,
+    );
+
+    /// See [`RenderContext::blurred_rect`](../trait.RenderContext.html#method.blurred_rect).
+    fn blurred_rect(&mut self, rect: Rect, blur_radius: f64, brush: &PaintBrush);
+
+    /// See [`RenderContext::blurred_shape`](../trait.RenderContext.html#method.blurred_shape).
+    fn blurred_shape(&mut self, shape: &BezPath, blur_radius: f64, brush: &PaintBrush);
+
+    /// See [`RenderContext::fill_with_shadow`](../trait.RenderContext.html#method.fill_with_shadow).
+    fn fill_with_shadow(&mut self, shape: &BezPath, brush: &PaintBrush, shadow: &ShadowStyle);
+
+    /// See [`RenderContext::current_transform`](../trait.RenderContext.html#tymethod.current_transform).
+    fn current_transform(&self) -> Affine;
+
+    /// See [`RenderContext::current_clip_bounds`](../trait.RenderContext.html#tymethod.current_clip_bounds).
+    fn current_clip_bounds(&self) -> Option<Rect>;
+}
+
+impl<'a> dyn DynRenderContext + 'a {
+    /// Fill any shape, with anything that converts to a brush.
+    pub fn fill_shape(&mut self, shape: impl Shape, brush: impl Into<PaintBrush>) {
+        self.fill(&to_path(shape), &brush.into());
+    }
+
+    /// Stroke any shape, with anything that converts to a brush.
+    pub fn stroke_shape(&mut self, shape: impl Shape, brush: impl Into<PaintBrush>, width: f64) {
+        self.stroke(&to_path(shape), &brush.into(), width);
+    }
+
+    /// Clip to any shape.
+    pub fn clip_shape(&mut self, shape: impl Shape) {
+        self.clip(&to_path(shape));
+    }
+}
+
+/// Convert a shape to a path, for the methods of [`DynRenderContext`].
+///
+/// [`DynRenderContext`]: trait.DynRenderContext.html
+pub fn to_path(shape: impl Shape) -> BezPath {
+    shape.into_bez_path(TOLERANCE)
+}
+
+/// A [`DynRenderContext`] that draws to a [`RenderContext`].
+///
+/// [`DynRenderContext`]: trait.DynRenderContext.html
+/// [`RenderContext`]: ../trait.RenderContext.html
+pub struct Dyn<'a, R: RenderContext> {
+    rc: &'a mut R,
+}
+
+impl<'a, R: RenderContext> Dyn<'a, R> {
+    /// Draw to `rc`.
+    pub fn new(rc: &'a mut R) -> Dyn<'a, R> {
+        Dyn { rc }
+    }
+
+    /// The render context being drawn to.
+    pub fn inner(&mut self) -> &mut R {
+        self.rc
+    }
+}
+
+impl<'a, R: RenderContext> Dyn<'a, R>
+where
+    R::Image: 'static,
+{
+    fn image<'b>(&self, image: &'b DynImage) -> Result<&'b R::Image, Error> {
+        image.downcast_ref().ok_or(Error::InvalidInput)
+    }
+}
+
+impl<'a, R: RenderContext> DynRenderContext for Dyn<'a, R>
+where
+    R::Image: 'static,
+{
+    fn status(&mut self) -> Result<(), Error> {
+        self.rc.status()
+    }
+
+    fn clear(&mut self, color: Color) {
+        self.rc.clear(color)
+    }
+
+    fn stroke(&mut self, shape: &BezPath, brush: &PaintBrush, width: f64) {
+        self.rc.stroke(shape, brush, width)
+    }
+
+    fn stroke_styled(
+        &mut self,
+        shape: &BezPath,
+        brush: &PaintBrush,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        self.rc.stroke_styled(shape, brush, width, style)
+    }
+
+    fn fill(&mut self, shape: &BezPath, brush: &PaintBrush) {
+        self.rc.fill(shape, brush)
+    }
+
+    fn fill_even_odd(&mut self, shape: &BezPath, brush: &PaintBrush) {
+        self.rc.fill_even_odd(shape, brush)
+    }
+
+    fn clip(&mut self, shape: &BezPath) {
+        self.rc.clip(shape)
+    }
+
+    fn clip_even_odd(&mut self, shape: &BezPath) {
+        self.rc.clip_even_odd(shape)
+    }
+
+    fn save(&mut self) -> Result<(), Error> {
+        self.rc.save()
+    }
+
+    fn restore(&mut self) -> Result<(), Error> {
+        self.rc.restore()
+    }
+
+    fn save_layer(&mut self, opacity: f64) -> Result<(), Error> {
+        self.rc.save_layer(opacity)
+    }
+
+    fn save_layer_with_mask(&mut self, mask: LayerMask<DynImage>) -> Result<(), Error> {
+        let mask = match mask {
+            LayerMask::Shape(path) => LayerMask::Shape(path),
+            LayerMask::Image(image, rect) => LayerMask::Image(self.image(image)?, rect),
+        };
+        self.rc.save_layer_with_mask(mask)
+    }
+
+    fn set_blend_mode(&mut self, mode: BlendMode) -> Result<(), Error> {
+        self.rc.set_blend_mode(mode)
+    }
+
+    fn push_opacity(&mut self, alpha: f64) {
+        self.rc.push_opacity(alpha)
+    }
+
+    fn pop_opacity(&mut self) -> Result<(), Error> {
+        self.rc.pop_opacity()
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.rc.finish()
+    }
+
+    fn transform(&mut self, transform: Affine) {
+        self.rc.transform(transform)
+    }
+
+    fn make_image(
+        &mut self,
+        width: usize,
+        height: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<DynImage, Error> {
+        let image = self.rc.make_image(width, height, buf, format)?;
+        Ok(DynImage::new(image))
+    }
+
+    fn capture_image_area(&mut self, src_rect: Rect) -> Result<DynImage, Error> {
+        let image = self.rc.capture_image_area(src_rect)?;
+        Ok(DynImage::new(image))
+    }
+
+    fn to_image_buf(&mut self, image: &DynImage, format: ImageFormat) -> Result<ImageBuf, Error> {
+        let image = self.image(image)?;
+        self.rc.to_image_buf(image, format)
+    }
+
+    fn draw_image(&mut self, image: &DynImage, dst_rect: Rect, interp: InterpolationMode) {
+        if let Ok(image) = self.image(image) {
+            self.rc.draw_image(image, dst_rect, interp)
+        }
+    }
+
+    fn draw_image_area(
+        &mut self,
+        image: &DynImage,
+        src_rect: Rect,
+        dst_rect: Rect,
+        interp: InterpolationMode,
+    ) {
+        if let Ok(image) = self.image(image) {
+            self.rc.draw_image_area(image, src_rect, dst_rect, interp)
+        }
+    }
+
+    fn blurred_rect(&mut self, rect: Rect, blur_radius: f64, brush: &PaintBrush) {
+        self.rc.blurred_rect(rect, blur_radius, brush)
+    }
+
+    fn blurred_shape(&mut self, shape: &BezPath, blur_radius: f64, brush: &PaintBrush) {
+        self.rc.blurred_shape(shape, blur_radius, brush)
+    }
+
+    fn fill_with_shadow(&mut self, shape: &BezPath, brush: &PaintBrush, shadow: &ShadowStyle) {
+        self.rc.fill_with_shadow(shape, brush, shadow)
+    }
+
+    fn current_transform(&self) -> Affine {
+        self.rc.current_transform()
+    }
+
+    fn current_clip_bounds(&self) -> Option<Rect> {
+        self.rc.current_clip_bounds()
+    }
+}
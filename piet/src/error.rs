@@ -3,6 +3,13 @@
 use std::fmt;
 
 /// An error that can occur while rendering 2D graphics.
+///
+/// Errors from the platform are reported as [`BackendError`], wrapping an
+/// error type defined by the backend, which names the operation that failed.
+/// Use [`backend_error_downcast`] to get at it.
+///
+/// [`BackendError`]: #variant.BackendError
+/// [`backend_error_downcast`]: #method.backend_error_downcast
 #[derive(Debug)]
 #[non_exhaustive]
 pub enum Error {
@@ -34,7 +41,26 @@ impl fmt::Display for Error {
     }
 }
 
-impl std::error::Error for Error {}
+impl Error {
+    /// The backend error, if this is a [`BackendError`] of type `T`.
+    ///
+    /// [`BackendError`]: #variant.BackendError
+    pub fn backend_error_downcast<T: std::error::Error + 'static>(&self) -> Option<&T> {
+        match self {
+            Error::BackendError(e) => e.downcast_ref(),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::BackendError(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
 
 impl From<Box<dyn std::error::Error>> for Error {
     fn from(e: Box<dyn std::error::Error>) -> Error {
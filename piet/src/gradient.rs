@@ -18,19 +18,25 @@
 //! anywhere you can use the fixed ones, and they will be automatically
 //! resolved appropriately.
 //!
+//! Sweep (or 'conic') gradients, where the color varies with the angle
+//! around a center point, are only available in the fixed representation,
+//! as [`FixedSweepGradient`].
+//!
 //! [`LinearGradient`]: struct.LinearGradient.html
 //! [`RadialGradient`]: struct.RadialGradient.html
 //! [`FixedLinearGradient`]: struct.FixedLinearGradient.html
 //! [`FixedRadialGradient`]: struct.FixedRadialGradient.html
+//! [`FixedSweepGradient`]: struct.FixedSweepGradient.html
 //! [unit square]: https://en.wikipedia.org/wiki/Unit_square
 
 use std::borrow::Cow;
+use std::f64::consts::PI;
 
-use kurbo::{Point, Rect, Size, Vec2};
+use kurbo::{Affine, Point, Rect, Size, Vec2};
 
 use crate::{IntoBrush, RenderContext};
 
-use crate::Color;
+use crate::{Color, ColorSpaceLerp};
 
 /// Specification of a linear gradient.
 ///
@@ -47,8 +53,15 @@ pub struct FixedLinearGradient {
     pub end: Point,
     /// The stops.
     ///
-    /// There must be at least two for the gradient to be valid.
+    /// Stops out of order or out of range are fixed up before drawing; see
+    /// [`GradientStops::normalized`].
+    ///
+    /// [`GradientStops::normalized`]: trait.GradientStops.html#method.normalized
     pub stops: Vec<GradientStop>,
+    /// How the gradient is drawn beyond the start and end points.
+    pub extend: GradientExtend,
+    /// How colors are interpolated between stops.
+    pub interpolation: ColorInterpolation,
 }
 
 /// Specification of a radial gradient in image-space.
@@ -64,29 +77,111 @@ pub struct FixedRadialGradient {
     pub center: Point,
     /// The offset of the origin relative to the center.
     pub origin_offset: Vec2,
-    /// The radius.
+    /// The horizontal and vertical radii.
+    ///
+    /// The ellipse with these radii around the center corresponds to pos 1.0;
+    /// if they are equal, as with [`new`], it is a circle.
     ///
-    /// The circle with this radius from the center corresponds to pos 1.0.
-    pub radius: f64,
+    /// [`new`]: #method.new
+    pub radii: Vec2,
     /// The stops (see similar field in [`LinearGradient`](struct.LinearGradient.html)).
     pub stops: Vec<GradientStop>,
+    /// How the gradient is drawn beyond the ellipse of `radii`.
+    pub extend: GradientExtend,
+    /// How colors are interpolated between stops.
+    pub interpolation: ColorInterpolation,
+}
+
+/// How a gradient is drawn outside of the range of its stops.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GradientExtend {
+    /// The colors of the first and last stops are extended indefinitely.
+    ///
+    /// This is what you get from the generic gradients.
+    Pad,
+    /// The gradient is repeated.
+    Repeat,
+    /// The gradient is repeated, with every other repetition reversed.
+    Reflect,
+}
+
+/// The color space in which a gradient is interpolated.
+///
+/// Interpolating in sRGB, which is what every platform does natively, makes
+/// gradients between saturated colors (say, red to green) look muddy in the
+/// middle. Interpolating in linear RGB or Oklab avoids this.
+///
+/// No backend can interpolate in these spaces directly, so instead each pair of
+/// stops is split into `subdivisions` segments, with the intermediate stops
+/// computed with [`Color::lerp`]; see [`util::resolve_color_interpolation`].
+///
+/// [`Color::lerp`]: enum.Color.html#method.lerp
+///
+/// [`util::resolve_color_interpolation`]: util/fn.resolve_color_interpolation.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorInterpolation {
+    /// Interpolate in sRGB.
+    ///
+    /// This is what you get by default.
+    Srgb,
+    /// Interpolate in linear RGB, approximated with `subdivisions` segments
+    /// between each pair of stops.
+    LinearRgb { subdivisions: usize },
+    /// Interpolate in Oklab, approximated with `subdivisions` segments
+    /// between each pair of stops.
+    Oklab { subdivisions: usize },
+}
+
+impl ColorInterpolation {
+    /// Interpolate in linear RGB, with a number of subdivisions that is
+    /// indistinguishable from the real thing in most cases.
+    pub const LINEAR_RGB: ColorInterpolation = ColorInterpolation::LinearRgb { subdivisions: 16 };
+
+    /// Interpolate in Oklab, with a number of subdivisions that is
+    /// indistinguishable from the real thing in most cases.
+    pub const OKLAB: ColorInterpolation = ColorInterpolation::Oklab { subdivisions: 16 };
+}
+
+/// Specification of a sweep (or 'conic') gradient in image-space.
+///
+/// The color at a point is determined by the angle of that point around
+/// `center`. The stops cover one full turn: pos 0.0 is at `start_angle`,
+/// and pos 1.0 is back at `start_angle` after a full clockwise sweep.
+/// For a seamless gradient, the first and last stops should have the
+/// same color.
+#[derive(Debug, Clone)]
+pub struct FixedSweepGradient {
+    /// The center.
+    pub center: Point,
+    /// The angle corresponding to pos 0.0, in radians.
+    ///
+    /// An angle of 0.0 points along the positive x axis; positive angles
+    /// are clockwise in the default y-down coordinate system.
+    pub start_angle: f64,
+    /// The stops (see similar field in [`LinearGradient`](struct.LinearGradient.html)).
+    pub stops: Vec<GradientStop>,
+    /// How colors are interpolated between stops.
+    pub interpolation: ColorInterpolation,
 }
 
 /// Any fixed gradient.
 ///
 /// This is provided as a convenience, so that we can provide API that
-/// accept both [`FixedLinearGradient`] and [`FixedRadialGradient`].
-/// You should not construct this type dirctly; rather construct one of those
-/// types, both of which impl `Into<FixedGradient>`.
+/// accept [`FixedLinearGradient`], [`FixedRadialGradient`] and
+/// [`FixedSweepGradient`]. You should not construct this type dirctly; rather
+/// construct one of those types, all of which impl `Into<FixedGradient>`.
 ///
 /// [`FixedLinearGradient`]: struct.FixedLinearGradient.html
 /// [`FixedRadialGradient`]: struct.FixedRadialGradient.html
+/// [`FixedSweepGradient`]: struct.FixedSweepGradient.html
 #[derive(Debug, Clone)]
 pub enum FixedGradient {
     /// A linear gradient.
     Linear(FixedLinearGradient),
     /// A radial gradient.
     Radial(FixedRadialGradient),
+    /// A sweep gradient.
+    Sweep(FixedSweepGradient),
 }
 
 /// Specification of a gradient stop.
@@ -101,6 +196,23 @@ pub struct GradientStop {
 /// A flexible, ergonomic way to describe gradient stops.
 pub trait GradientStops {
     fn to_vec(self) -> Vec<GradientStop>;
+
+    /// The stops, fixed up the way CSS fixes up its gradients.
+    ///
+    /// Backends normalize stops before drawing, so this only needs to be
+    /// called to check stops in advance. The stops are sorted by position,
+    /// keeping stops at the same position in order, and the positions are
+    /// clamped to `0.0..=1.0`; a stop without a position (a `NaN`) takes
+    /// the position of the stop before it. If the first stop is after 0.0 or
+    /// the last is before 1.0, it is repeated there, so the result always
+    /// has at least two stops, from 0.0 to 1.0. With no stops at all, the
+    /// gradient is transparent.
+    fn normalized(self) -> Vec<GradientStop>
+    where
+        Self: Sized,
+    {
+        normalize_stops(self.to_vec())
+    }
 }
 
 /// A description of a linear gradient in the unit rect, which can be resolved
@@ -118,6 +230,7 @@ pub struct LinearGradient {
     start: UnitPoint,
     end: UnitPoint,
     stops: Vec<GradientStop>,
+    interpolation: ColorInterpolation,
 }
 
 /// A description of a radial gradient in the unit rect, which can be resolved
@@ -154,6 +267,7 @@ pub struct RadialGradient {
     radius: f64,
     stops: Vec<GradientStop>,
     scale_mode: ScaleMode,
+    interpolation: ColorInterpolation,
 }
 
 /// Mappings from the unit square into a non-square rectangle.
@@ -295,9 +409,19 @@ impl LinearGradient {
             start,
             end,
             stops: stops.to_vec(),
+            interpolation: ColorInterpolation::Srgb,
         }
     }
 
+    /// A builder-style method for changing the [`ColorInterpolation`] of the
+    /// gradient.
+    ///
+    /// [`ColorInterpolation`]: enum.ColorInterpolation.html
+    pub fn with_interpolation(mut self, interpolation: ColorInterpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
+
     // maybe these should be public API? that was my original intention but I'm not
     // sure there's a clear use, so keeping them private for now.
     /// Generate a [`FixedLinearGradient`] by mapping points in the unit square
@@ -309,6 +433,8 @@ impl LinearGradient {
             start: self.start.resolve(rect),
             end: self.end.resolve(rect),
             stops: self.stops.clone(),
+            extend: GradientExtend::Pad,
+            interpolation: self.interpolation,
         }
     }
 }
@@ -330,6 +456,7 @@ impl RadialGradient {
             radius,
             stops: stops.to_vec(),
             scale_mode: ScaleMode::Fill,
+            interpolation: ColorInterpolation::Srgb,
         }
     }
 
@@ -363,6 +490,15 @@ impl RadialGradient {
         self
     }
 
+    /// A builder-style method for changing the [`ColorInterpolation`] of the
+    /// gradient.
+    ///
+    /// [`ColorInterpolation`]: enum.ColorInterpolation.html
+    pub fn with_interpolation(mut self, interpolation: ColorInterpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
+
     /// Generate a [`FixedRadialGradient`] by mapping points in the unit square
     /// onto points in `rect`.
     ///
@@ -381,10 +517,69 @@ impl RadialGradient {
         FixedRadialGradient {
             center,
             origin_offset,
-            radius,
+            radii: Vec2::new(radius, radius),
             stops: self.stops.clone(),
+            extend: GradientExtend::Pad,
+            interpolation: self.interpolation,
+        }
+    }
+}
+
+impl FixedRadialGradient {
+    /// Creates a circular gradient, with the origin at the center, which
+    /// pads its colors.
+    pub fn new(center: impl Into<Point>, radius: f64, stops: impl GradientStops) -> Self {
+        FixedRadialGradient {
+            center: center.into(),
+            origin_offset: Vec2::ZERO,
+            radii: Vec2::new(radius, radius),
+            stops: stops.to_vec(),
+            extend: GradientExtend::Pad,
+            interpolation: ColorInterpolation::Srgb,
         }
     }
+
+    /// Split this gradient into a circular gradient, and the transform that
+    /// stretches it into this one.
+    ///
+    /// Backends that can only draw circular gradients draw the circular one
+    /// with the transform applied to the gradient, but not to the shape being
+    /// filled. The circle has a radius of `radii.x`, and the transform scales
+    /// vertically around the center; it is the identity if the gradient is
+    /// already circular, or if either radius is zero.
+    pub fn to_circular(&self) -> (FixedRadialGradient, Affine) {
+        let Vec2 { x: rx, y: ry } = self.radii;
+        if rx == ry || rx == 0.0 || ry == 0.0 {
+            let radius = rx.max(ry);
+            let circle = FixedRadialGradient {
+                radii: Vec2::new(radius, radius),
+                ..self.clone()
+            };
+            return (circle, Affine::default());
+        }
+        let scale = ry / rx;
+        let transform = Affine::translate(self.center.to_vec2())
+            * Affine::scale_non_uniform(1.0, scale)
+            * Affine::translate(-self.center.to_vec2());
+        let circle = FixedRadialGradient {
+            origin_offset: Vec2::new(self.origin_offset.x, self.origin_offset.y / scale),
+            radii: Vec2::new(rx, rx),
+            ..self.clone()
+        };
+        (circle, transform)
+    }
+}
+
+impl FixedSweepGradient {
+    /// Compute the color of the gradient at `point`.
+    ///
+    /// Not every platform can draw sweep gradients natively; backends that
+    /// cannot use this to rasterize the gradient themselves.
+    pub fn color_at(&self, point: Point) -> Color {
+        let delta = point - self.center;
+        let angle = (delta.y.atan2(delta.x) - self.start_angle).rem_euclid(2.0 * PI);
+        interpolate_stops(&self.stops, angle / (2.0 * PI), self.interpolation)
+    }
 }
 
 impl From<FixedLinearGradient> for FixedGradient {
@@ -399,6 +594,12 @@ impl From<FixedRadialGradient> for FixedGradient {
     }
 }
 
+impl From<FixedSweepGradient> for FixedGradient {
+    fn from(src: FixedSweepGradient) -> FixedGradient {
+        FixedGradient::Sweep(src)
+    }
+}
+
 impl<P: RenderContext> IntoBrush<P> for FixedGradient {
     fn make_brush<'a>(&'a self, piet: &mut P, _bbox: impl FnOnce() -> Rect) -> Cow<'a, P::Brush> {
         // Also, at some point we might want to be smarter about the extra clone here.
@@ -427,8 +628,138 @@ impl<P: RenderContext> IntoBrush<P> for RadialGradient {
     }
 }
 
+/// Interpolate the color at `pos`.
+fn interpolate_stops(stops: &[GradientStop], pos: f64, interpolation: ColorInterpolation) -> Color {
+    let first = match stops.first() {
+        Some(stop) => stop,
+        None => return Color::rgba8(0, 0, 0, 0),
+    };
+    if pos <= first.pos as f64 {
+        return first.color.clone();
+    }
+    for pair in stops.windows(2) {
+        let (lo, hi) = (&pair[0], &pair[1]);
+        if pos <= hi.pos as f64 {
+            let span = (hi.pos - lo.pos) as f64;
+            let t = if span > 0.0 {
+                (pos - lo.pos as f64) / span
+            } else {
+                1.0
+            };
+            return lerp_color(&lo.color, &hi.color, t, interpolation);
+        }
+    }
+    stops[stops.len() - 1].color.clone()
+}
+
+fn normalize_stops(mut stops: Vec<GradientStop>) -> Vec<GradientStop> {
+    let mut prev = 0.0;
+    for stop in &mut stops {
+        if stop.pos.is_nan() {
+            stop.pos = prev;
+        }
+        stop.pos = stop.pos.clamp(0.0, 1.0);
+        prev = stop.pos;
+    }
+    // `sort_by` is stable, so hard edges between stops at the same
+    // position are kept.
+    stops.sort_by(|a, b| a.pos.partial_cmp(&b.pos).unwrap());
+    let (first, last) = match (stops.first(), stops.last()) {
+        (Some(first), Some(last)) => (first.clone(), last.clone()),
+        _ => {
+            let transparent = Color::rgba8(0, 0, 0, 0);
+            return GradientStops::to_vec((transparent.clone(), transparent));
+        }
+    };
+    if first.pos > 0.0 {
+        stops.insert(0, GradientStop { pos: 0.0, ..first });
+    }
+    if last.pos < 1.0 || stops.len() < 2 {
+        stops.push(GradientStop { pos: 1.0, ..last });
+    }
+    stops
+}
+
+/// Interpolate between two colors, in the given color space.
+pub(crate) fn lerp_color(a: &Color, b: &Color, t: f64, interpolation: ColorInterpolation) -> Color {
+    let space = match interpolation {
+        ColorInterpolation::Srgb => ColorSpaceLerp::Srgb,
+        ColorInterpolation::LinearRgb { .. } => ColorSpaceLerp::LinearSrgb,
+        ColorInterpolation::Oklab { .. } => ColorSpaceLerp::Oklab,
+    };
+    a.clone().lerp(b.clone(), t, space)
+}
+
 fn equalize_sides_preserving_center(rect: Rect, new_len: f64) -> Rect {
     let size = Size::new(new_len, new_len);
     let origin = rect.center() - size.to_vec2() / 2.;
     Rect::from_origin_size(origin, size)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stops(stops: &[(f32, u32)]) -> Vec<GradientStop> {
+        stops
+            .iter()
+            .map(|&(pos, rgba)| GradientStop {
+                pos,
+                color: Color::from_rgba32_u32(rgba),
+            })
+            .collect()
+    }
+
+    fn positions(stops: &[GradientStop]) -> Vec<(f32, u32)> {
+        stops
+            .iter()
+            .map(|stop| (stop.pos, stop.color.as_rgba_u32()))
+            .collect()
+    }
+
+    fn normalized(input: &[(f32, u32)]) -> Vec<(f32, u32)> {
+        positions(&stops(input).normalized())
+    }
+
+    #[test]
+    fn normalize_valid_stops() {
+        let input = [(0.0, 1), (0.5, 2), (1.0, 3)];
+        assert_eq!(normalized(&input), input);
+    }
+
+    #[test]
+    fn normalize_unsorted_stops() {
+        assert_eq!(
+            normalized(&[(1.0, 1), (0.5, 2), (0.0, 3), (0.5, 4)]),
+            [(0.0, 3), (0.5, 2), (0.5, 4), (1.0, 1)]
+        );
+    }
+
+    #[test]
+    fn normalize_out_of_range_stops() {
+        assert_eq!(
+            normalized(&[(-1.0, 1), (0.5, 2), (2.0, 3)]),
+            [(0.0, 1), (0.5, 2), (1.0, 3)]
+        );
+        assert_eq!(
+            normalized(&[(0.0, 1), (f32::NAN, 2), (1.0, 3)]),
+            [(0.0, 1), (0.0, 2), (1.0, 3)]
+        );
+    }
+
+    #[test]
+    fn normalize_missing_endpoints() {
+        assert_eq!(
+            normalized(&[(0.25, 1), (0.75, 2)]),
+            [(0.0, 1), (0.25, 1), (0.75, 2), (1.0, 2)]
+        );
+    }
+
+    #[test]
+    fn normalize_too_few_stops() {
+        assert_eq!(normalized(&[(0.5, 1)]), [(0.0, 1), (0.5, 1), (1.0, 1)]);
+        assert_eq!(normalized(&[(0.0, 1)]), [(0.0, 1), (1.0, 1)]);
+        assert_eq!(normalized(&[(1.0, 1)]), [(0.0, 1), (1.0, 1)]);
+        assert_eq!(normalized(&[]), [(0.0, 0), (1.0, 0)]);
+    }
+}
@@ -0,0 +1,169 @@
+//! Images in CPU memory.
+
+use std::sync::Arc;
+
+use crate::kurbo::Size;
+use crate::{Error, Image, ImageFormat, RenderContext};
+
+/// An image held in CPU memory, as returned by [`to_image_buf`].
+///
+/// The pixels are tightly packed, row by row, in the image's format.
+///
+/// [`to_image_buf`]: trait.RenderContext.html#tymethod.to_image_buf
+#[derive(Clone)]
+pub struct ImageBuf {
+    pixels: Arc<[u8]>,
+    width: usize,
+    height: usize,
+    format: ImageFormat,
+}
+
+impl ImageBuf {
+    /// Create an image from raw pixels.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pixels` isn't exactly `width * height` pixels long.
+    pub fn from_raw(
+        pixels: impl Into<Arc<[u8]>>,
+        format: ImageFormat,
+        width: usize,
+        height: usize,
+    ) -> ImageBuf {
+        let pixels = pixels.into();
+        assert_eq!(
+            pixels.len(),
+            width * height * format.bytes_per_pixel(),
+            "buffer doesn't match the image size"
+        );
+        ImageBuf {
+            pixels,
+            width,
+            height,
+            format,
+        }
+    }
+
+    /// The raw pixels of the image.
+    pub fn raw_pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// The width of the image, in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// The height of the image, in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The size of the image, in pixels.
+    pub fn size(&self) -> Size {
+        Size::new(self.width as f64, self.height as f64)
+    }
+
+    /// The format of the pixels.
+    pub fn format(&self) -> ImageFormat {
+        self.format
+    }
+
+    /// Convert the image to another format.
+    ///
+    /// Formats without alpha are composited over black, and grayscale is
+    /// the average of the color channels.
+    pub fn to_format(&self, format: ImageFormat) -> ImageBuf {
+        if format == self.format {
+            return self.clone();
+        }
+        let src_bpp = self.format.bytes_per_pixel();
+        let mut pixels = Vec::with_capacity(self.width * self.height * format.bytes_per_pixel());
+        for src in self.pixels.chunks(src_bpp) {
+            let rgba = decode_premul(src, self.format);
+            encode_premul(rgba, format, &mut pixels);
+        }
+        ImageBuf::from_raw(pixels, format, self.width, self.height)
+    }
+
+    /// Create a backend image from this image.
+    pub fn to_image<R: RenderContext>(&self, rc: &mut R) -> Result<R::Image, Error> {
+        rc.make_image(self.width, self.height, &self.pixels, self.format)
+    }
+}
+
+impl Image for ImageBuf {
+    fn size(&self) -> Size {
+        ImageBuf::size(self)
+    }
+}
+
+fn premul(x: u8, a: u8) -> u8 {
+    let y = (x as u16) * (a as u16);
+    ((y + (y >> 8) + 0x80) >> 8) as u8
+}
+
+fn unpremul(x: u8, a: u8) -> u8 {
+    if a == 0 {
+        0
+    } else {
+        let y = (x as u32 * 255 + (a as u32 / 2)) / (a as u32);
+        y.min(255) as u8
+    }
+}
+
+/// One pixel, as premultiplied RGBA.
+fn decode_premul(px: &[u8], format: ImageFormat) -> [u8; 4] {
+    match format {
+        ImageFormat::Rgb => [px[0], px[1], px[2], 255],
+        ImageFormat::RgbaSeparate => {
+            let a = px[3];
+            [premul(px[0], a), premul(px[1], a), premul(px[2], a), a]
+        }
+        ImageFormat::RgbaPremul => [px[0], px[1], px[2], px[3]],
+        ImageFormat::Grayscale => [px[0], px[0], px[0], 255],
+        ImageFormat::Bgra => {
+            let a = px[3];
+            [premul(px[2], a), premul(px[1], a), premul(px[0], a), a]
+        }
+        ImageFormat::BgraPremul => [px[2], px[1], px[0], px[3]],
+    }
+}
+
+fn encode_premul([r, g, b, a]: [u8; 4], format: ImageFormat, out: &mut Vec<u8>) {
+    match format {
+        ImageFormat::Rgb => out.extend_from_slice(&[r, g, b]),
+        ImageFormat::RgbaSeparate => {
+            out.extend_from_slice(&[unpremul(r, a), unpremul(g, a), unpremul(b, a), a])
+        }
+        ImageFormat::RgbaPremul => out.extend_from_slice(&[r, g, b, a]),
+        ImageFormat::Grayscale => out.push(((r as u16 + g as u16 + b as u16) / 3) as u8),
+        ImageFormat::Bgra => {
+            out.extend_from_slice(&[unpremul(b, a), unpremul(g, a), unpremul(r, a), a])
+        }
+        ImageFormat::BgraPremul => out.extend_from_slice(&[b, g, r, a]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert_formats() {
+        let premul = ImageBuf::from_raw(vec![20, 40, 60, 128], ImageFormat::RgbaPremul, 1, 1);
+        let bgra = premul.to_format(ImageFormat::BgraPremul);
+        assert_eq!(bgra.raw_pixels(), &[60, 40, 20, 128]);
+        let separate = premul.to_format(ImageFormat::RgbaSeparate);
+        assert_eq!(separate.raw_pixels(), &[40, 80, 120, 128]);
+        assert_eq!(
+            separate.to_format(ImageFormat::RgbaPremul).raw_pixels(),
+            premul.raw_pixels()
+        );
+        assert_eq!(
+            premul.to_format(ImageFormat::Rgb).raw_pixels(),
+            &[20, 40, 60]
+        );
+        assert_eq!(premul.to_format(ImageFormat::Grayscale).raw_pixels(), &[40]);
+    }
+}
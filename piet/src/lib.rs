@@ -4,6 +4,10 @@
 
 pub use kurbo;
 
+pub mod dynamic;
+#[cfg(feature = "record")]
+pub mod record;
+
 /// utilities shared by various backends
 pub mod util;
 
@@ -11,8 +15,10 @@ mod color;
 mod conv;
 mod error;
 mod gradient;
+mod image;
 mod null_renderer;
 mod render_context;
+mod save_guard;
 mod shapes;
 mod text;
 
@@ -23,7 +29,9 @@ pub use crate::color::*;
 pub use crate::conv::*;
 pub use crate::error::*;
 pub use crate::gradient::*;
+pub use crate::image::*;
 pub use crate::null_renderer::*;
 pub use crate::render_context::*;
+pub use crate::save_guard::*;
 pub use crate::shapes::*;
 pub use crate::text::*;
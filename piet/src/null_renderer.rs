@@ -1,44 +1,112 @@
 //! A render context that does nothing.
 
 use std::borrow::Cow;
-use std::ops::RangeBounds;
 
 use kurbo::{Affine, Point, Rect, Shape, Size};
 
 use crate::{
-    Color, Error, FixedGradient, FontFamily, HitTestPoint, HitTestPosition, ImageFormat,
-    InterpolationMode, IntoBrush, LineMetric, RenderContext, StrokeStyle, Text, TextAttribute,
-    TextLayout, TextLayoutBuilder,
+    util, BlendMode, Color, Error, FixedGradient, Image, ImageBuf, ImageFormat, InterpolationMode,
+    IntoBrush, LayerMask, RenderContext, StrokeStyle, TileMode,
 };
 
+mod font;
+mod text;
+
+pub use self::text::{NullText, NullTextLayout, NullTextLayoutBuilder};
+
 /// A render context that doesn't render.
 ///
-/// This is useful largely for doc tests, but is made public in case
-/// it might come in handy.
-#[doc(hidden)]
-pub struct NullRenderContext(NullText);
+/// This is useful for layout passes and tests that need text measurements
+/// but have no surface to draw to, such as computing preferred sizes before
+/// a window exists, or testing on a headless machine.
+///
+/// Text is measured without any system fonts, approximating every family as
+/// a generic sans-serif, serif or monospace font from embedded metrics,
+/// unless it was loaded with [`load_font`]; the measurements are plausible,
+/// but won't match any backend exactly.
+///
+/// Drawing does nothing, but the arguments are still checked, and the
+/// context keeps track of its transform and clip. Invalid arguments to
+/// methods that can't return an error, such as a non-finite stroke width,
+/// are reported by the next call to [`status`] or [`finish`], and
+/// [`finish`] also reports unbalanced saves, so this can be used to check
+/// drawing code for mistakes.
+///
+/// [`load_font`]: trait.Text.html#method.load_font
+/// [`status`]: trait.RenderContext.html#tymethod.status
+/// [`finish`]: trait.RenderContext.html#tymethod.finish
+pub struct NullRenderContext {
+    text: NullText,
+    state: State,
+    /// The state before each save or layer that hasn't been restored.
+    state_stack: Vec<State>,
+    opacity: util::OpacityStack,
+    /// The first invalid argument since the last `status` or `finish`.
+    error: Option<Error>,
+}
 
+/// The brush of a [`NullRenderContext`].
+///
+/// [`NullRenderContext`]: struct.NullRenderContext.html
 #[derive(Clone)]
-#[doc(hidden)]
 pub struct NullBrush;
-#[doc(hidden)]
-pub struct NullImage;
 
+/// An image made by a [`NullRenderContext`]; it has a size, but no pixels.
+///
+/// [`NullRenderContext`]: struct.NullRenderContext.html
 #[derive(Clone)]
-#[doc(hidden)]
-pub struct NullText;
+pub struct NullImage {
+    size: Size,
+}
 
-#[doc(hidden)]
-#[derive(Clone)]
-pub struct NullTextLayout;
-#[doc(hidden)]
-pub struct NullTextLayoutBuilder;
+#[derive(Clone, Copy, Default)]
+struct State {
+    transform: Affine,
+    /// The bounds of the clip, before any transforms.
+    clip: Option<Rect>,
+}
+
+impl Image for NullImage {
+    fn size(&self) -> Size {
+        self.size
+    }
+}
 
 impl NullRenderContext {
     #[allow(clippy::new_without_default)]
     pub fn new() -> NullRenderContext {
-        NullRenderContext(NullText)
+        NullRenderContext {
+            text: NullText::new(),
+            state: State::default(),
+            state_stack: Vec::new(),
+            opacity: util::OpacityStack::new(),
+            error: None,
+        }
+    }
+
+    /// Record an invalid argument, to be reported by the next `status`.
+    fn check(&mut self, valid: bool) {
+        if !valid && self.error.is_none() {
+            self.error = Some(Error::InvalidInput);
+        }
+    }
+
+    fn check_shape(&mut self, shape: &impl Shape) {
+        self.check(is_finite_rect(shape.bounding_box()));
     }
+
+    fn check_brush(&mut self, shape: &impl Shape, brush: &impl IntoBrush<Self>) {
+        self.check_shape(shape);
+        brush.make_brush(self, || shape.bounding_box());
+    }
+}
+
+fn is_finite_rect(rect: Rect) -> bool {
+    rect.x0.is_finite() && rect.y0.is_finite() && rect.x1.is_finite() && rect.y1.is_finite()
+}
+
+fn is_finite_affine(affine: Affine) -> bool {
+    affine.as_coeffs().iter().all(|c| c.is_finite())
 }
 
 impl RenderContext for NullRenderContext {
@@ -48,7 +116,10 @@ impl RenderContext for NullRenderContext {
     type TextLayout = NullTextLayout;
 
     fn status(&mut self) -> Result<(), Error> {
-        Ok(())
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
     }
 
     fn solid_brush(&mut self, _color: Color) -> Self::Brush {
@@ -59,154 +130,192 @@ impl RenderContext for NullRenderContext {
         Ok(NullBrush)
     }
 
+    fn make_image_brush(
+        &mut self,
+        _image: &Self::Image,
+        _tile: TileMode,
+        transform: Affine,
+    ) -> Result<Self::Brush, Error> {
+        if !is_finite_affine(transform) {
+            return Err(Error::InvalidInput);
+        }
+        Ok(NullBrush)
+    }
+
     fn clear(&mut self, _color: Color) {}
 
-    fn stroke(&mut self, _shape: impl Shape, _brush: &impl IntoBrush<Self>, _width: f64) {}
+    fn stroke(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, width: f64) {
+        self.check_brush(&shape, brush);
+        self.check(width.is_finite() && width >= 0.0);
+    }
 
     fn stroke_styled(
         &mut self,
-        _shape: impl Shape,
-        _brush: &impl IntoBrush<Self>,
-        _width: f64,
-        _style: &StrokeStyle,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
     ) {
+        self.check_brush(&shape, brush);
+        self.check(width.is_finite() && width >= 0.0);
+        if let Some((pattern, offset)) = &style.dash {
+            let valid = pattern.iter().all(|d| d.is_finite() && *d >= 0.0);
+            self.check(valid && offset.is_finite());
+        }
     }
 
-    fn fill(&mut self, _shape: impl Shape, _brush: &impl IntoBrush<Self>) {}
-
-    fn fill_even_odd(&mut self, _shape: impl Shape, _brush: &impl IntoBrush<Self>) {}
-
-    fn clip(&mut self, _shape: impl Shape) {}
-
-    fn text(&mut self) -> &mut Self::Text {
-        &mut self.0
+    fn fill(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
+        self.check_brush(&shape, brush);
     }
 
-    fn draw_text(&mut self, _layout: &Self::TextLayout, _pos: impl Into<Point>) {}
-
-    fn save(&mut self) -> Result<(), Error> {
-        Ok(())
-    }
-    fn restore(&mut self) -> Result<(), Error> {
-        Ok(())
-    }
-    fn finish(&mut self) -> Result<(), Error> {
-        Ok(())
+    fn fill_even_odd(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
+        self.check_brush(&shape, brush);
     }
-    fn transform(&mut self, _transform: Affine) {}
 
-    fn make_image(
-        &mut self,
-        _width: usize,
-        _height: usize,
-        _buf: &[u8],
-        _format: ImageFormat,
-    ) -> Result<Self::Image, Error> {
-        Ok(NullImage)
-    }
-    fn draw_image(
-        &mut self,
-        _image: &Self::Image,
-        _dst_rect: impl Into<Rect>,
-        _interp: InterpolationMode,
-    ) {
+    fn clip(&mut self, shape: impl Shape) {
+        self.check_shape(&shape);
+        self.state.clip = Some(util::intersect_clip_bounds(
+            self.state.clip,
+            shape.bounding_box(),
+            self.state.transform,
+        ));
     }
-    fn draw_image_area(
-        &mut self,
-        _image: &Self::Image,
-        _src_rect: impl Into<Rect>,
-        _dst_rect: impl Into<Rect>,
-        _interp: InterpolationMode,
-    ) {
-    }
-
-    fn blurred_rect(&mut self, _rect: Rect, _blur_radius: f64, _brush: &impl IntoBrush<Self>) {}
 
-    fn current_transform(&self) -> Affine {
-        Affine::default()
+    fn clip_even_odd(&mut self, shape: impl Shape) {
+        self.clip(shape);
     }
-}
 
-impl Text for NullText {
-    type TextLayout = NullTextLayout;
-    type TextLayoutBuilder = NullTextLayoutBuilder;
+    fn text(&mut self) -> &mut Self::Text {
+        &mut self.text
+    }
 
-    fn new_text_layout(&mut self, _text: &str) -> Self::TextLayoutBuilder {
-        NullTextLayoutBuilder
+    fn draw_text(&mut self, _layout: &Self::TextLayout, pos: impl Into<Point>) {
+        let pos = pos.into();
+        self.check(pos.x.is_finite() && pos.y.is_finite());
     }
 
-    fn font_family(&mut self, _family_name: &str) -> Option<FontFamily> {
-        Some(FontFamily::default())
+    fn save(&mut self) -> Result<(), Error> {
+        self.state_stack.push(self.state);
+        Ok(())
     }
-}
 
-impl TextLayoutBuilder for NullTextLayoutBuilder {
-    type Out = NullTextLayout;
+    fn restore(&mut self) -> Result<(), Error> {
+        self.state = self.state_stack.pop().ok_or(Error::StackUnbalance)?;
+        Ok(())
+    }
 
-    fn max_width(self, _width: f64) -> Self {
-        self
+    fn save_layer(&mut self, opacity: f64) -> Result<(), Error> {
+        if !opacity.is_finite() {
+            return Err(Error::InvalidInput);
+        }
+        self.save()
     }
 
-    fn alignment(self, _alignment: crate::TextAlignment) -> Self {
-        self
+    fn save_layer_with_mask(&mut self, mask: LayerMask<Self::Image>) -> Result<(), Error> {
+        let bounds = match mask {
+            LayerMask::Shape(path) => path.bounding_box(),
+            LayerMask::Image(_, rect) => rect,
+        };
+        if !is_finite_rect(bounds) {
+            return Err(Error::InvalidInput);
+        }
+        self.save()
     }
 
-    fn default_attribute(self, _attribute: impl Into<TextAttribute>) -> Self {
-        self
+    fn set_blend_mode(&mut self, _mode: BlendMode) -> Result<(), Error> {
+        Ok(())
     }
 
-    fn range_attribute(
-        self,
-        _range: impl RangeBounds<usize>,
-        _attribute: impl Into<TextAttribute>,
-    ) -> Self {
-        self
+    fn push_opacity(&mut self, alpha: f64) {
+        self.check(alpha.is_finite());
+        self.opacity.push(alpha);
     }
 
-    fn build(self) -> Result<Self::Out, Error> {
-        Ok(NullTextLayout)
+    fn pop_opacity(&mut self) -> Result<(), Error> {
+        self.opacity.pop()
     }
-}
 
-impl TextLayout for NullTextLayout {
-    fn width(&self) -> f64 {
-        42.0
+    fn finish(&mut self) -> Result<(), Error> {
+        self.status()?;
+        if !self.state_stack.is_empty() {
+            return Err(Error::StackUnbalance);
+        }
+        Ok(())
     }
 
-    fn size(&self) -> Size {
-        Size::ZERO
+    fn transform(&mut self, transform: Affine) {
+        self.check(is_finite_affine(transform));
+        self.state.transform *= transform;
     }
 
-    fn image_bounds(&self) -> Rect {
-        Rect::ZERO
+    fn make_image(
+        &mut self,
+        width: usize,
+        height: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<Self::Image, Error> {
+        let len = width
+            .checked_mul(height)
+            .and_then(|len| len.checked_mul(format.bytes_per_pixel()));
+        if len != Some(buf.len()) {
+            return Err(Error::InvalidInput);
+        }
+        Ok(NullImage {
+            size: Size::new(width as f64, height as f64),
+        })
     }
 
-    fn update_width(&mut self, _new_width: impl Into<Option<f64>>) -> Result<(), Error> {
-        Ok(())
+    fn capture_image_area(&mut self, src_rect: impl Into<Rect>) -> Result<Self::Image, Error> {
+        let rect = self.state.transform.transform_rect_bbox(src_rect.into());
+        if !is_finite_rect(rect) || rect.area() <= 0.0 {
+            return Err(Error::InvalidInput);
+        }
+        Ok(NullImage {
+            size: rect.expand().size(),
+        })
     }
 
-    fn line_text(&self, _line_number: usize) -> Option<&str> {
-        None
+    fn to_image_buf(
+        &mut self,
+        image: &Self::Image,
+        format: ImageFormat,
+    ) -> Result<ImageBuf, Error> {
+        let (width, height) = (image.size.width as usize, image.size.height as usize);
+        let pixels = vec![0; width * height * format.bytes_per_pixel()];
+        Ok(ImageBuf::from_raw(pixels, format, width, height))
     }
 
-    fn line_metric(&self, _line_number: usize) -> Option<LineMetric> {
-        None
+    fn draw_image(
+        &mut self,
+        _image: &Self::Image,
+        dst_rect: impl Into<Rect>,
+        _interp: InterpolationMode,
+    ) {
+        self.check(is_finite_rect(dst_rect.into()));
     }
 
-    fn line_count(&self) -> usize {
-        0
+    fn draw_image_area(
+        &mut self,
+        _image: &Self::Image,
+        src_rect: impl Into<Rect>,
+        dst_rect: impl Into<Rect>,
+        _interp: InterpolationMode,
+    ) {
+        self.check(is_finite_rect(src_rect.into()) && is_finite_rect(dst_rect.into()));
     }
 
-    fn hit_test_point(&self, _point: Point) -> HitTestPoint {
-        HitTestPoint::default()
+    fn blurred_shape(&mut self, shape: impl Shape, blur_radius: f64, brush: &impl IntoBrush<Self>) {
+        self.check_brush(&shape, brush);
+        self.check(blur_radius.is_finite() && blur_radius >= 0.0);
     }
 
-    fn hit_test_text_position(&self, _text_position: usize) -> Option<HitTestPosition> {
-        None
+    fn current_transform(&self) -> Affine {
+        self.state.transform
     }
 
-    fn text(&self) -> &str {
-        ""
+    fn current_clip_bounds(&self) -> Option<Rect> {
+        util::user_clip_bounds(self.state.clip, self.state.transform)
     }
 }
 
@@ -219,3 +328,42 @@ impl IntoBrush<NullRenderContext> for NullBrush {
         Cow::Borrowed(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kurbo::Line;
+
+    #[test]
+    fn report_invalid_arguments() {
+        let mut rc = NullRenderContext::new();
+        rc.stroke(Line::new((0.0, 0.0), (10.0, 10.0)), &Color::BLACK, 1.0);
+        assert!(rc.status().is_ok());
+        rc.stroke(Line::new((0.0, 0.0), (10.0, 10.0)), &Color::BLACK, f64::NAN);
+        assert!(matches!(rc.status(), Err(Error::InvalidInput)));
+        assert!(rc.status().is_ok());
+        rc.fill(Rect::new(0.0, 0.0, f64::INFINITY, 1.0), &Color::BLACK);
+        assert!(matches!(rc.finish(), Err(Error::InvalidInput)));
+        assert!(rc
+            .make_image(2, 2, &[0; 12], ImageFormat::RgbaPremul)
+            .is_err());
+    }
+
+    #[test]
+    fn unbalanced_saves() {
+        let mut rc = NullRenderContext::new();
+        assert!(matches!(rc.restore(), Err(Error::StackUnbalance)));
+        rc.save().unwrap();
+        rc.transform(Affine::translate((5.0, 0.0)));
+        rc.clip(Rect::new(0.0, 0.0, 10.0, 10.0));
+        assert_eq!(
+            rc.current_clip_bounds(),
+            Some(Rect::new(0.0, 0.0, 10.0, 10.0))
+        );
+        assert!(matches!(rc.finish(), Err(Error::StackUnbalance)));
+        rc.restore().unwrap();
+        assert_eq!(rc.current_transform(), Affine::default());
+        assert_eq!(rc.current_clip_bounds(), None);
+        rc.finish().unwrap();
+    }
+}
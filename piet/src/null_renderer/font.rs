@@ -0,0 +1,644 @@
+//! Reading the metrics and outlines of TrueType and OpenType fonts.
+//!
+//! Only what measurement needs is read: the family name, the vertical
+//! metrics, the character map and the advance widths. Fonts are checked
+//! when they're parsed, so lookups in them can't fail. Glyph outlines are
+//! read from the `glyf` table, if the font has one; fonts with CFF outlines
+//! have none.
+
+use crate::kurbo::{Affine, BezPath, Point};
+
+/// A font loaded from its data, as in `Text::load_font`.
+pub(crate) struct Font {
+    data: Vec<u8>,
+    family: String,
+    units_per_em: u16,
+    ascent: i16,
+    descent: i16,
+    cap_height: Option<i16>,
+    x_height: Option<i16>,
+    /// The offset and format of the Unicode character map.
+    cmap: (usize, u16),
+    /// The offset of the horizontal metrics.
+    hmtx: usize,
+    num_h_metrics: u16,
+    /// The offsets of the `loca` and `glyf` tables, whether the glyph
+    /// locations are long, and the number of glyphs.
+    glyf: Option<(usize, usize, bool, u16)>,
+}
+
+/// The deepest nesting of composite glyphs that is followed.
+const MAX_COMPONENT_DEPTH: u32 = 8;
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_i16(data: &[u8], offset: usize) -> Option<i16> {
+    read_u16(data, offset).map(|x| x as i16)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// The offset of the table with the given tag in the font at `font`.
+fn find_table(data: &[u8], font: usize, tag: [u8; 4]) -> Option<usize> {
+    let num_tables = read_u16(data, font + 4)? as usize;
+    (0..num_tables)
+        .map(|i| font + 12 + 16 * i)
+        .find(|&record| data.get(record..record + 4) == Some(&tag[..]))
+        .and_then(|record| read_u32(data, record + 8))
+        .map(|offset| offset as usize)
+}
+
+impl Font {
+    /// Parse a font, or the first font of a collection.
+    pub(crate) fn parse(data: &[u8]) -> Option<Font> {
+        let font = match data.get(0..4)? {
+            b"ttcf" => read_u32(data, 12)? as usize,
+            _ => 0,
+        };
+        let table = |tag| find_table(data, font, tag);
+        let head = table(*b"head")?;
+        let hhea = table(*b"hhea")?;
+        let hmtx = table(*b"hmtx")?;
+        let name = table(*b"name")?;
+        let cmap = table(*b"cmap")?;
+        let units_per_em = read_u16(data, head + 18).filter(|&units| units > 0)?;
+        let num_h_metrics = read_u16(data, hhea + 34).filter(|&n| n > 0)?;
+        // every advance is in the data, so lookups can't fail
+        data.get(hmtx..hmtx + 4 * num_h_metrics as usize)?;
+        let (cap_height, x_height) = match table(*b"OS/2") {
+            Some(os2) if read_u16(data, os2)? >= 2 => {
+                (read_i16(data, os2 + 88), read_i16(data, os2 + 86))
+            }
+            _ => (None, None),
+        };
+        let glyf = match (table(*b"loca"), table(*b"glyf"), table(*b"maxp")) {
+            (Some(loca), Some(glyf), Some(maxp)) => {
+                let long = read_i16(data, head + 50)? != 0;
+                Some((loca, glyf, long, read_u16(data, maxp + 4)?))
+            }
+            _ => None,
+        };
+        Some(Font {
+            family: read_family(data, name)?,
+            units_per_em,
+            ascent: read_i16(data, hhea + 4)?,
+            descent: read_i16(data, hhea + 6)?,
+            cap_height,
+            x_height,
+            cmap: find_cmap(data, cmap)?,
+            hmtx,
+            num_h_metrics,
+            glyf,
+            data: data.to_vec(),
+        })
+    }
+
+    /// The family name of the font.
+    pub(crate) fn family(&self) -> &str {
+        &self.family
+    }
+
+    pub(crate) fn units_per_em(&self) -> f64 {
+        self.units_per_em as f64
+    }
+
+    /// The ascent, in font units.
+    pub(crate) fn ascent(&self) -> f64 {
+        self.ascent as f64
+    }
+
+    /// The descent, in font units, as a positive number.
+    pub(crate) fn descent(&self) -> f64 {
+        -(self.descent as f64)
+    }
+
+    /// The cap height, in font units, if the font has it.
+    pub(crate) fn cap_height(&self) -> Option<f64> {
+        self.cap_height.map(f64::from)
+    }
+
+    /// The x-height, in font units, if the font has it.
+    pub(crate) fn x_height(&self) -> Option<f64> {
+        self.x_height.map(f64::from)
+    }
+
+    /// The glyph of a character, if the font has one.
+    pub(crate) fn glyph(&self, c: char) -> Option<u16> {
+        let (offset, format) = self.cmap;
+        let glyph = match format {
+            4 => cmap_format_4(&self.data, offset, c as u32),
+            _ => cmap_format_12(&self.data, offset, c as u32),
+        };
+        glyph.filter(|&glyph| glyph != 0)
+    }
+
+    /// The advance of a glyph, in font units.
+    pub(crate) fn advance(&self, glyph: u16) -> f64 {
+        let index = glyph.min(self.num_h_metrics - 1) as usize;
+        read_u16(&self.data, self.hmtx + 4 * index).unwrap_or_default() as f64
+    }
+
+    /// The outline of a glyph, in font units with y up, if the font has
+    /// outlines; a glyph that can't be read has an empty outline.
+    pub(crate) fn outline(&self, glyph: u16) -> Option<BezPath> {
+        self.glyf?;
+        let mut path = BezPath::new();
+        match self.append_outline(glyph, Affine::default(), 0, &mut path) {
+            Some(()) => Some(path),
+            None => Some(BezPath::new()),
+        }
+    }
+
+    /// Append the transformed outline of a glyph to `path`, returning
+    /// `None` if it can't be read.
+    fn append_outline(&self, glyph: u16, xf: Affine, depth: u32, path: &mut BezPath) -> Option<()> {
+        let (loca, glyf, long, num_glyphs) = self.glyf?;
+        if glyph >= num_glyphs {
+            return None;
+        }
+        let data = &self.data[..];
+        let (start, end) = if long {
+            let start = read_u32(data, loca + 4 * glyph as usize)?;
+            (
+                start as usize,
+                read_u32(data, loca + 4 * glyph as usize + 4)? as usize,
+            )
+        } else {
+            let start = read_u16(data, loca + 2 * glyph as usize)? as usize * 2;
+            (
+                start,
+                read_u16(data, loca + 2 * glyph as usize + 2)? as usize * 2,
+            )
+        };
+        if end <= start {
+            // an empty glyph, such as a space
+            return Some(());
+        }
+        let offset = glyf + start;
+        let data = data.get(..glyf + end)?;
+        let contours = read_i16(data, offset)?;
+        if contours >= 0 {
+            append_simple_glyph(data, offset, contours as usize, xf, path)
+        } else if depth < MAX_COMPONENT_DEPTH {
+            self.append_composite_glyph(offset + 10, xf, depth, path)
+        } else {
+            None
+        }
+    }
+
+    /// Append the components of a composite glyph, from the first at
+    /// `offset`.
+    fn append_composite_glyph(
+        &self,
+        mut offset: usize,
+        xf: Affine,
+        depth: u32,
+        path: &mut BezPath,
+    ) -> Option<()> {
+        const ARG_1_AND_2_ARE_WORDS: u16 = 0x1;
+        const ARGS_ARE_XY_VALUES: u16 = 0x2;
+        const WE_HAVE_A_SCALE: u16 = 0x8;
+        const MORE_COMPONENTS: u16 = 0x20;
+        const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x40;
+        const WE_HAVE_A_TWO_BY_TWO: u16 = 0x80;
+        let data = &self.data[..];
+        let f2dot14 = |offset| read_i16(data, offset).map(|x| x as f64 / 16384.0);
+        loop {
+            let flags = read_u16(data, offset)?;
+            let glyph = read_u16(data, offset + 2)?;
+            offset += 4;
+            let (dx, dy) = if flags & ARG_1_AND_2_ARE_WORDS != 0 {
+                offset += 4;
+                (read_i16(data, offset - 4)?, read_i16(data, offset - 2)?)
+            } else {
+                offset += 2;
+                let args = data.get(offset - 2..offset)?;
+                (args[0] as i8 as i16, args[1] as i8 as i16)
+            };
+            // components positioned by matching points are left in place
+            let (dx, dy) = if flags & ARGS_ARE_XY_VALUES != 0 {
+                (dx as f64, dy as f64)
+            } else {
+                (0.0, 0.0)
+            };
+            let [a, b, c, d] = if flags & WE_HAVE_A_SCALE != 0 {
+                offset += 2;
+                let scale = f2dot14(offset - 2)?;
+                [scale, 0.0, 0.0, scale]
+            } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+                offset += 4;
+                [f2dot14(offset - 4)?, 0.0, 0.0, f2dot14(offset - 2)?]
+            } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+                offset += 8;
+                [
+                    f2dot14(offset - 8)?,
+                    f2dot14(offset - 6)?,
+                    f2dot14(offset - 4)?,
+                    f2dot14(offset - 2)?,
+                ]
+            } else {
+                [1.0, 0.0, 0.0, 1.0]
+            };
+            let component = xf * Affine::new([a, b, c, d, dx, dy]);
+            self.append_outline(glyph, component, depth + 1, path)?;
+            if flags & MORE_COMPONENTS == 0 {
+                return Some(());
+            }
+        }
+    }
+}
+
+/// Append the contours of a simple glyph, whose header is at `offset`.
+fn append_simple_glyph(
+    data: &[u8],
+    offset: usize,
+    contours: usize,
+    xf: Affine,
+    path: &mut BezPath,
+) -> Option<()> {
+    const ON_CURVE: u8 = 0x1;
+    const X_SHORT: u8 = 0x2;
+    const Y_SHORT: u8 = 0x4;
+    const REPEAT: u8 = 0x8;
+    const X_SAME_OR_POSITIVE: u8 = 0x10;
+    const Y_SAME_OR_POSITIVE: u8 = 0x20;
+    let ends = offset + 10;
+    let points = match contours {
+        0 => return Some(()),
+        _ => read_u16(data, ends + 2 * (contours - 1))? as usize + 1,
+    };
+    let instructions = read_u16(data, ends + 2 * contours)? as usize;
+    let mut offset = ends + 2 * contours + 2 + instructions;
+
+    let mut flags = Vec::with_capacity(points);
+    while flags.len() < points {
+        let flag = *data.get(offset)?;
+        offset += 1;
+        let repeat = if flag & REPEAT != 0 {
+            offset += 1;
+            *data.get(offset - 1)? as usize
+        } else {
+            0
+        };
+        for _ in 0..=repeat {
+            flags.push(flag);
+        }
+    }
+    flags.truncate(points);
+    let mut read_coords = |short, same_or_positive| {
+        let mut value = 0i32;
+        let mut coords = Vec::with_capacity(points);
+        for &flag in &flags {
+            if flag & short != 0 {
+                let delta = *data.get(offset)? as i32;
+                offset += 1;
+                value += if flag & same_or_positive != 0 {
+                    delta
+                } else {
+                    -delta
+                };
+            } else if flag & same_or_positive == 0 {
+                value += read_i16(data, offset)? as i32;
+                offset += 2;
+            }
+            coords.push(value as f64);
+        }
+        Some(coords)
+    };
+    let xs = read_coords(X_SHORT, X_SAME_OR_POSITIVE)?;
+    let ys = read_coords(Y_SHORT, Y_SAME_OR_POSITIVE)?;
+
+    let mut start = 0;
+    for contour in 0..contours {
+        let end = read_u16(data, ends + 2 * contour)? as usize + 1;
+        if end <= start || end > points {
+            return None;
+        }
+        let contour: Vec<(Point, bool)> = (start..end)
+            .map(|i| (xf * Point::new(xs[i], ys[i]), flags[i] & ON_CURVE != 0))
+            .collect();
+        append_contour(&contour, path);
+        start = end;
+    }
+    Some(())
+}
+
+/// Append a closed contour of quadratic curves, given by its points and
+/// whether each is on the curve; between two control points is an implied
+/// point on the curve, halfway between them.
+fn append_contour(points: &[(Point, bool)], path: &mut BezPath) {
+    let first = points[0];
+    let last = points[points.len() - 1];
+    // start from a point on the curve
+    let (start, rest) = if first.1 {
+        (first.0, &points[1..])
+    } else if last.1 {
+        (last.0, &points[..points.len() - 1])
+    } else {
+        (first.0.midpoint(last.0), points)
+    };
+    path.move_to(start);
+    let mut control: Option<Point> = None;
+    for &(point, on_curve) in rest {
+        match (control, on_curve) {
+            (Some(c), true) => {
+                path.quad_to(c, point);
+                control = None;
+            }
+            (Some(c), false) => {
+                path.quad_to(c, c.midpoint(point));
+                control = Some(point);
+            }
+            (None, true) => path.line_to(point),
+            (None, false) => control = Some(point),
+        }
+    }
+    if let Some(c) = control {
+        path.quad_to(c, start);
+    }
+    path.close_path();
+}
+
+/// The family name in the `name` table, preferring the typographic family
+/// and English names.
+fn read_family(data: &[u8], name: usize) -> Option<String> {
+    let count = read_u16(data, name + 2)? as usize;
+    let strings = name + read_u16(data, name + 4)? as usize;
+    let mut best: Option<(u32, String)> = None;
+    for i in 0..count {
+        let record = name + 6 + 12 * i;
+        let platform = read_u16(data, record)?;
+        let encoding = read_u16(data, record + 2)?;
+        let language = read_u16(data, record + 4)?;
+        let name_id = read_u16(data, record + 6)?;
+        let length = read_u16(data, record + 8)? as usize;
+        let start = strings + read_u16(data, record + 10)? as usize;
+        let bytes = match data.get(start..start + length) {
+            Some(bytes) => bytes,
+            None => continue,
+        };
+        let text = match (platform, encoding) {
+            (0, _) | (3, 0) | (3, 1) | (3, 10) => {
+                let units: Vec<u16> = bytes
+                    .chunks_exact(2)
+                    .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+                    .collect();
+                String::from_utf16_lossy(&units)
+            }
+            // Mac Roman, read as Latin-1, which agrees for ASCII names
+            (1, 0) => bytes.iter().map(|&b| b as char).collect(),
+            _ => continue,
+        };
+        let rank = match name_id {
+            16 => 0,
+            1 => 2,
+            _ => continue,
+        } + match (platform, language) {
+            (3, 0x409) | (1, 0) | (0, _) => 0,
+            _ => 1,
+        };
+        if !text.is_empty() && best.as_ref().map(|best| rank < best.0) != Some(false) {
+            best = Some((rank, text));
+        }
+    }
+    best.map(|(_, text)| text)
+}
+
+/// The offset and format of the best Unicode subtable of the `cmap` table.
+fn find_cmap(data: &[u8], cmap: usize) -> Option<(usize, u16)> {
+    let count = read_u16(data, cmap + 2)? as usize;
+    let mut best: Option<(u32, usize, u16)> = None;
+    for i in 0..count {
+        let record = cmap + 4 + 8 * i;
+        let platform = read_u16(data, record)?;
+        let encoding = read_u16(data, record + 2)?;
+        let offset = cmap + read_u32(data, record + 4)? as usize;
+        let format = read_u16(data, offset)?;
+        let rank = match (platform, encoding, format) {
+            (3, 10, 12) | (0, 4, 12) | (0, 6, 12) => 0,
+            (3, 1, 4) | (0, 3, 4) => 1,
+            (0, _, 4) => 2,
+            _ => continue,
+        };
+        if best.map(|best| rank < best.0) != Some(false) {
+            best = Some((rank, offset, format));
+        }
+    }
+    let (_, offset, format) = best?;
+    // check the lengths of the subtable's arrays, which lookups rely on
+    let len = match format {
+        4 => {
+            let seg_count = read_u16(data, offset + 6)? as usize / 2;
+            16 + 8 * seg_count
+        }
+        _ => 16 + 12 * read_u32(data, offset + 12)? as usize,
+    };
+    data.get(offset..offset.checked_add(len)?)?;
+    Some((offset, format))
+}
+
+/// Look up a character in a segment mapping to delta values.
+fn cmap_format_4(data: &[u8], offset: usize, c: u32) -> Option<u16> {
+    if c > 0xffff {
+        return None;
+    }
+    let c = c as u16;
+    let seg_count = read_u16(data, offset + 6)? as usize / 2;
+    let ends = offset + 14;
+    let starts = ends + 2 * seg_count + 2;
+    let deltas = starts + 2 * seg_count;
+    let range_offsets = deltas + 2 * seg_count;
+    // the segments are sorted by their ends
+    let (mut lo, mut hi) = (0, seg_count);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if read_u16(data, ends + 2 * mid)? < c {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    let seg = lo;
+    if seg == seg_count || read_u16(data, starts + 2 * seg)? > c {
+        return None;
+    }
+    let delta = read_u16(data, deltas + 2 * seg)?;
+    let range_offset = read_u16(data, range_offsets + 2 * seg)? as usize;
+    if range_offset == 0 {
+        return Some(c.wrapping_add(delta));
+    }
+    let start = read_u16(data, starts + 2 * seg)?;
+    let glyph_offset = range_offsets + 2 * seg + range_offset + 2 * (c - start) as usize;
+    match read_u16(data, glyph_offset)? {
+        0 => None,
+        glyph => Some(glyph.wrapping_add(delta)),
+    }
+}
+
+/// Look up a character in a segmented coverage table.
+fn cmap_format_12(data: &[u8], offset: usize, c: u32) -> Option<u16> {
+    let groups = read_u32(data, offset + 12)? as usize;
+    let (mut lo, mut hi) = (0, groups);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        let group = offset + 16 + 12 * mid;
+        if read_u32(data, group + 4)? < c {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    let group = offset + 16 + 12 * lo;
+    if lo == groups || read_u32(data, group)? > c {
+        return None;
+    }
+    let glyph = read_u32(data, group + 8)? + (c - read_u32(data, group)?);
+    Some(glyph as u16)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::kurbo::{PathEl, Rect, Shape};
+
+    /// A minimal font with a format 4 character map for 'A' to 'C', whose
+    /// glyphs are 1 to 3 with advances of 600, 700 and 800 units of 1000,
+    /// and a family name of `family`.
+    ///
+    /// Glyph 1 is a 500 by 700 rectangle, glyph 2 is a curve through the
+    /// midpoints of the rectangle's edges, with only control points, and
+    /// glyph 3 is glyph 1 moved right by 100.
+    pub(crate) fn test_font(family: &str) -> Vec<u8> {
+        fn be16(out: &mut Vec<u8>, x: u16) {
+            out.extend_from_slice(&x.to_be_bytes());
+        }
+        let mut head = vec![0; 54];
+        head[18..20].copy_from_slice(&1000u16.to_be_bytes());
+        let mut hhea = vec![0; 36];
+        hhea[4..6].copy_from_slice(&800i16.to_be_bytes());
+        hhea[6..8].copy_from_slice(&(-200i16).to_be_bytes());
+        hhea[34..36].copy_from_slice(&4u16.to_be_bytes());
+        let mut hmtx = Vec::new();
+        for &advance in &[500u16, 600, 700, 800] {
+            be16(&mut hmtx, advance);
+            be16(&mut hmtx, 0);
+        }
+        let mut os2 = vec![0; 96];
+        os2[0..2].copy_from_slice(&2u16.to_be_bytes());
+        os2[86..88].copy_from_slice(&500i16.to_be_bytes());
+        os2[88..90].copy_from_slice(&700i16.to_be_bytes());
+        let name_bytes: Vec<u8> = family.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        let mut name = Vec::new();
+        for &x in &[0, 1, 18, 3, 1, 0x409, 1, name_bytes.len() as u16, 0] {
+            be16(&mut name, x);
+        }
+        name.extend_from_slice(&name_bytes);
+        let mut glyf = Vec::new();
+        let simple_glyph = |glyf: &mut Vec<u8>, on_curve: u16, xs: [i16; 4], ys: [i16; 4]| {
+            let flags = on_curve << 8 | on_curve;
+            for &x in &[1, 0, 0, 0, 0, 3, 0, flags, flags] {
+                be16(glyf, x);
+            }
+            for &delta in xs.iter().chain(&ys) {
+                be16(glyf, delta as u16);
+            }
+        };
+        simple_glyph(&mut glyf, 1, [0, 500, 0, -500], [0, 0, 700, 0]);
+        simple_glyph(&mut glyf, 0, [250, 250, -250, -250], [0, 350, 350, -350]);
+        // one component, with word offsets as x and y values
+        for &x in &[-1i16 as u16, 0, 0, 0, 0, 0x3, 1, 100, 0] {
+            be16(&mut glyf, x);
+        }
+        let maxp = vec![0, 0, 0x50, 0, 0, 4];
+        // short offsets, in words, of glyphs 0 (empty) to 3
+        let mut loca = Vec::new();
+        for &x in &[0, 0, 17, 34, 43] {
+            be16(&mut loca, x);
+        }
+        // two segments: 'A'..='C' mapped by delta, and the final 0xffff
+        let mut cmap = Vec::new();
+        for &x in &[0, 1, 3, 1, 0, 12] {
+            be16(&mut cmap, x);
+        }
+        for &x in &[4, 32, 0, 4, 4, 1, 0, 0x43, 0xffff, 0, 0x41, 0xffff] {
+            be16(&mut cmap, x);
+        }
+        for &x in &[(1u16).wrapping_sub(0x41), 1, 0, 0] {
+            be16(&mut cmap, x);
+        }
+
+        let tables: [(&[u8; 4], Vec<u8>); 9] = [
+            (b"OS/2", os2),
+            (b"cmap", cmap),
+            (b"glyf", glyf),
+            (b"head", head),
+            (b"hhea", hhea),
+            (b"hmtx", hmtx),
+            (b"loca", loca),
+            (b"maxp", maxp),
+            (b"name", name),
+        ];
+        let mut font = Vec::new();
+        for &x in &[0, 1, tables.len() as u16, 0, 0, 0] {
+            be16(&mut font, x);
+        }
+        let mut offset = 12 + 16 * tables.len();
+        for (tag, table) in &tables {
+            font.extend_from_slice(&tag[..]);
+            font.extend_from_slice(&[0; 4]);
+            font.extend_from_slice(&(offset as u32).to_be_bytes());
+            font.extend_from_slice(&(table.len() as u32).to_be_bytes());
+            offset += table.len();
+        }
+        for (_, table) in &tables {
+            font.extend_from_slice(table);
+        }
+        font
+    }
+
+    #[test]
+    fn parse_font() {
+        let font = Font::parse(&test_font("Test Sans")).unwrap();
+        assert_eq!(font.family(), "Test Sans");
+        assert_eq!(font.units_per_em(), 1000.0);
+        assert_eq!((font.ascent(), font.descent()), (800.0, 200.0));
+        assert_eq!(
+            (font.cap_height(), font.x_height()),
+            (Some(700.0), Some(500.0))
+        );
+        assert_eq!(font.glyph('A'), Some(1));
+        assert_eq!(font.glyph('C'), Some(3));
+        assert_eq!(font.glyph('D'), None);
+        assert_eq!(font.glyph('\u{1f600}'), None);
+        assert_eq!(font.advance(2), 700.0);
+        // glyphs past the metrics have the last advance
+        assert_eq!(font.advance(9), 800.0);
+        assert!(Font::parse(b"not a font").is_none());
+
+        assert!(font.outline(0).unwrap().elements().is_empty());
+        let rect = font.outline(1).unwrap();
+        assert_eq!(rect.bounding_box(), Rect::new(0.0, 0.0, 500.0, 700.0));
+        assert_eq!(rect.elements().len(), 5);
+        // the curve is drawn from the implied point between the last and
+        // first control points, and passes through the edges' midpoints
+        let curve = font.outline(2).unwrap();
+        assert!(matches!(
+            curve.elements()[..2],
+            [PathEl::MoveTo(p0), PathEl::QuadTo(p1, p2)]
+                if p0 == Point::new(125.0, 175.0)
+                    && p1 == Point::new(250.0, 0.0)
+                    && p2 == Point::new(375.0, 175.0)
+        ));
+        assert_eq!(curve.elements().len(), 6);
+        let moved = font.outline(3).unwrap();
+        assert_eq!(moved.bounding_box(), Rect::new(100.0, 0.0, 600.0, 700.0));
+        assert!(font.outline(4).unwrap().elements().is_empty());
+        assert!(Font::parse(&test_font("Test")[..100]).is_none());
+    }
+}
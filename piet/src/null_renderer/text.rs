@@ -0,0 +1,1174 @@
+//! Approximate text measurement, without any system fonts.
+//!
+//! Text is measured with embedded advance widths for the printable ASCII
+//! characters, from the standard metrics of Helvetica, Times and Courier;
+//! these stand in for any sans-serif, serif and monospace family
+//! respectively. Other characters are measured as an average lowercase
+//! letter, or as a full em for wide (East Asian and emoji) characters, and
+//! each grapheme is measured by its first character.
+//!
+//! Families loaded with `load_font` are measured with the advances and
+//! metrics of their fonts, falling back to the sans-serif metrics for any
+//! characters they don't have, as a viewer would fall back to another font.
+//! Only text in loaded fonts has glyphs and outlines.
+
+use std::cell::OnceCell;
+use std::ops::{Range, RangeBounds};
+use std::sync::Arc;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::font::Font;
+use crate::kurbo::{Affine, BezPath, Point, Rect, Size};
+use crate::util::{self, LayoutDefaults};
+use crate::{
+    Affinity, Error, FontFamily, FontFamilyInner, FontMetrics, FontWeight, Glyph, GlyphRun,
+    HitTestPoint, HitTestPosition, IntoLayoutStorage, LineMetric, LineSpacing, ShadowStyle,
+    TabStops, Text, TextAlignment, TextAttribute, TextDirection, TextLayout, TextLayoutBuilder,
+    TextOrientation, TextStorage, TruncationMode,
+};
+
+/// The text system of a [`NullRenderContext`].
+///
+/// This measures text approximately, without any system fonts; see the
+/// [`NullRenderContext`] docs.
+///
+/// [`NullRenderContext`]: struct.NullRenderContext.html
+#[derive(Clone, Default)]
+pub struct NullText {
+    /// The fonts loaded with `load_font`, most recent last.
+    fonts: Vec<Arc<Font>>,
+}
+
+/// A text layout made by [`NullText`].
+///
+/// [`NullText`]: struct.NullText.html
+#[derive(Clone)]
+pub struct NullTextLayout {
+    text: Arc<str>,
+    /// The style of each run of the text, in order.
+    runs: Vec<(Range<usize>, Style)>,
+    /// The style of empty text.
+    default_style: Style,
+    letter_spacing: f64,
+    line_spacing: LineSpacing,
+    alignment: TextAlignment,
+    tab_stops: TabStops,
+    break_opportunities: Option<Vec<usize>>,
+    max_lines: Option<usize>,
+    line_metrics: Vec<LineMetric>,
+    /// The x offset of each line, from its alignment.
+    line_x: Vec<f64>,
+    size: Size,
+    image_bounds: Rect,
+    /// The shadows of the text, which grow its image bounds.
+    shadows: Vec<ShadowStyle>,
+    /// The glyph runs, made the first time they are asked for.
+    glyph_runs: OnceCell<Vec<GlyphRun>>,
+}
+
+/// The builder for a [`NullTextLayout`].
+///
+/// [`NullTextLayout`]: struct.NullTextLayout.html
+pub struct NullTextLayoutBuilder {
+    text: Arc<str>,
+    fonts: Vec<Arc<Font>>,
+    defaults: LayoutDefaults,
+    /// The range attributes that affect measurement, in the order they were
+    /// added.
+    range_attributes: Vec<(Range<usize>, TextAttribute)>,
+    shadows: Vec<ShadowStyle>,
+    width: f64,
+    alignment: TextAlignment,
+    direction: TextDirection,
+    line_spacing: LineSpacing,
+    max_lines: Option<usize>,
+    tab_stops: Option<TabStops>,
+    break_opportunities: Option<Vec<usize>>,
+}
+
+/// The metrics of a generic font family, in thousandths of an em.
+struct Metrics {
+    /// The advances of the characters from `' '` to `'~'`.
+    widths: [u16; 95],
+    bold_widths: [u16; 95],
+    ascent: u16,
+    descent: u16,
+    cap_height: u16,
+    x_height: u16,
+}
+
+#[derive(Clone)]
+struct Style {
+    face: Face,
+    size: f64,
+    bold: bool,
+}
+
+/// What a style is measured with.
+#[derive(Clone)]
+enum Face {
+    Generic(&'static Metrics),
+    Loaded(Arc<Font>),
+}
+
+#[rustfmt::skip]
+static SANS_SERIF: Metrics = Metrics {
+    widths: [
+        278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278,
+        556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556,
+        1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778,
+        667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556,
+        333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556,
+        556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+    ],
+    bold_widths: [
+        278, 333, 474, 556, 556, 889, 722, 238, 333, 333, 389, 584, 278, 333, 278, 278,
+        556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 333, 333, 584, 584, 584, 611,
+        975, 722, 722, 722, 722, 667, 611, 778, 722, 278, 556, 722, 611, 833, 722, 778,
+        667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 333, 278, 333, 584, 556,
+        333, 556, 611, 556, 611, 556, 333, 611, 611, 278, 278, 556, 278, 889, 611, 611,
+        611, 611, 389, 556, 333, 611, 556, 778, 556, 556, 500, 389, 280, 389, 584,
+    ],
+    ascent: 905,
+    descent: 212,
+    cap_height: 716,
+    x_height: 519,
+};
+
+#[rustfmt::skip]
+static SERIF: Metrics = Metrics {
+    widths: [
+        250, 333, 408, 500, 500, 833, 778, 180, 333, 333, 500, 564, 250, 333, 250, 278,
+        500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 278, 278, 564, 564, 564, 444,
+        921, 722, 667, 667, 722, 611, 556, 722, 722, 333, 389, 722, 611, 889, 722, 722,
+        556, 722, 667, 556, 611, 722, 722, 944, 722, 722, 611, 333, 278, 333, 469, 500,
+        333, 444, 500, 444, 500, 444, 333, 500, 500, 278, 278, 500, 278, 778, 500, 500,
+        500, 500, 333, 389, 278, 500, 500, 722, 500, 500, 444, 480, 200, 480, 541,
+    ],
+    bold_widths: [
+        250, 333, 555, 500, 500, 1000, 833, 278, 333, 333, 500, 570, 250, 333, 250, 278,
+        500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 333, 333, 570, 570, 570, 500,
+        930, 722, 667, 722, 722, 667, 611, 778, 778, 389, 500, 778, 667, 944, 722, 778,
+        611, 778, 722, 556, 667, 722, 722, 1000, 722, 722, 667, 333, 278, 333, 581, 500,
+        333, 500, 556, 444, 556, 444, 333, 500, 556, 278, 333, 556, 278, 833, 556, 500,
+        556, 556, 444, 389, 333, 556, 500, 722, 500, 500, 444, 394, 220, 394, 520,
+    ],
+    ascent: 891,
+    descent: 216,
+    cap_height: 662,
+    x_height: 448,
+};
+
+static MONOSPACE: Metrics = Metrics {
+    widths: [600; 95],
+    bold_widths: [600; 95],
+    ascent: 833,
+    descent: 300,
+    cap_height: 571,
+    x_height: 423,
+};
+
+impl Metrics {
+    fn for_family(family: &FontFamily) -> &'static Metrics {
+        match family.inner() {
+            FontFamilyInner::Serif => &SERIF,
+            FontFamilyInner::SansSerif | FontFamilyInner::SystemUi => &SANS_SERIF,
+            FontFamilyInner::Monospace => &MONOSPACE,
+            FontFamilyInner::Named(name) => {
+                let name = name.to_lowercase();
+                let has = |words: &[&str]| words.iter().any(|word| name.contains(word));
+                if has(&["mono", "courier", "consol", "menlo", "code"]) {
+                    &MONOSPACE
+                } else if has(&["sans", "gothic"]) {
+                    &SANS_SERIF
+                } else if has(&["serif", "times", "georgia", "garamond", "charter", "roman"]) {
+                    &SERIF
+                } else {
+                    &SANS_SERIF
+                }
+            }
+        }
+    }
+
+    /// The advance of a character, in thousandths of an em.
+    fn advance(&self, c: char, bold: bool) -> f64 {
+        let widths = if bold {
+            &self.bold_widths
+        } else {
+            &self.widths
+        };
+        let units = match c {
+            ' '..='~' => widths[c as usize - 0x20],
+            '\u{a0}' => widths[0],
+            '\u{200b}'..='\u{200d}' | '\u{2060}' | '\u{feff}' => 0,
+            c if is_wide(c) => 1000,
+            _ => widths['n' as usize - 0x20],
+        };
+        units as f64
+    }
+}
+
+/// Whether `c` is an East Asian wide character or an emoji.
+fn is_wide(c: char) -> bool {
+    matches!(
+        c,
+        '\u{1100}'..='\u{115f}'
+            | '\u{2e80}'..='\u{a4cf}'
+            | '\u{ac00}'..='\u{d7a3}'
+            | '\u{f900}'..='\u{faff}'
+            | '\u{fe30}'..='\u{fe4f}'
+            | '\u{ff00}'..='\u{ff60}'
+            | '\u{ffe0}'..='\u{ffe6}'
+            | '\u{1f300}'..='\u{1f64f}'
+            | '\u{1f900}'..='\u{1f9ff}'
+            | '\u{20000}'..='\u{3fffd}'
+    )
+}
+
+impl Style {
+    /// The style of a family, measured with the most recently loaded font
+    /// of that family, if there is one.
+    fn new(family: &FontFamily, size: f64, weight: FontWeight, fonts: &[Arc<Font>]) -> Style {
+        let loaded = match family.inner() {
+            FontFamilyInner::Named(name) => fonts
+                .iter()
+                .rev()
+                .find(|font| font.family().eq_ignore_ascii_case(name)),
+            _ => None,
+        };
+        let face = match loaded {
+            Some(font) => Face::Loaded(font.clone()),
+            None => Face::Generic(Metrics::for_family(family)),
+        };
+        Style {
+            face,
+            size,
+            bold: weight.to_raw() > FontWeight::MEDIUM.to_raw(),
+        }
+    }
+
+    /// The size of a font unit.
+    fn scale(&self) -> f64 {
+        match &self.face {
+            Face::Generic(_) => self.size / 1000.0,
+            Face::Loaded(font) => self.size / font.units_per_em(),
+        }
+    }
+
+    fn ascent(&self) -> f64 {
+        match &self.face {
+            Face::Generic(metrics) => metrics.ascent as f64 * self.scale(),
+            Face::Loaded(font) => font.ascent() * self.scale(),
+        }
+    }
+
+    fn descent(&self) -> f64 {
+        match &self.face {
+            Face::Generic(metrics) => metrics.descent as f64 * self.scale(),
+            Face::Loaded(font) => font.descent() * self.scale(),
+        }
+    }
+
+    /// The advance of a grapheme, measured by its first character.
+    fn advance(&self, grapheme: &str) -> f64 {
+        let c = match grapheme.chars().next() {
+            Some(c) if !is_hard_break(c) => c,
+            _ => return 0.0,
+        };
+        match &self.face {
+            Face::Generic(metrics) => metrics.advance(c, self.bold) * self.scale(),
+            Face::Loaded(font) => match font.glyph(c) {
+                Some(glyph) => font.advance(glyph) * self.scale(),
+                None => SANS_SERIF.advance(c, self.bold) * self.size / 1000.0,
+            },
+        }
+    }
+
+    fn font_metrics(&self) -> FontMetrics {
+        let ascent = self.ascent();
+        let descent = self.descent();
+        let (cap_height, x_height) = match &self.face {
+            Face::Generic(metrics) => (metrics.cap_height as f64, metrics.x_height as f64),
+            // fonts without them are given the proportions of the
+            // sans-serif metrics
+            Face::Loaded(font) => (
+                font.cap_height().unwrap_or(font.ascent() * 0.79),
+                font.x_height().unwrap_or(font.ascent() * 0.57),
+            ),
+        };
+        // approximated as in the cairo backend
+        let thickness = (ascent / 14.0).max(1.0);
+        FontMetrics {
+            ascent,
+            descent,
+            cap_height: cap_height * self.scale(),
+            x_height: x_height * self.scale(),
+            underline_position: descent * 0.5,
+            underline_thickness: thickness,
+            strikeout_position: ascent * -0.3,
+            strikeout_thickness: thickness,
+        }
+    }
+}
+
+fn is_hard_break(c: char) -> bool {
+    matches!(c, '\n' | '\r' | '\u{2028}' | '\u{2029}')
+}
+
+impl NullText {
+    pub fn new() -> NullText {
+        NullText::default()
+    }
+}
+
+impl Text for NullText {
+    type TextLayout = NullTextLayout;
+    type TextLayoutBuilder = NullTextLayoutBuilder;
+
+    fn font_family(&mut self, family_name: &str) -> Option<FontFamily> {
+        let loaded = self
+            .fonts
+            .iter()
+            .rev()
+            .find(|font| font.family().eq_ignore_ascii_case(family_name));
+        if let Some(font) = loaded {
+            return Some(FontFamily::new_unchecked(font.family()));
+        }
+        // every other family is available, measured as the closest generic
+        // family
+        let family = match family_name {
+            "serif" => FontFamily::SERIF,
+            "sans-serif" => FontFamily::SANS_SERIF,
+            "system-ui" => FontFamily::SYSTEM_UI,
+            "monospace" => FontFamily::MONOSPACE,
+            name => FontFamily::new_unchecked(name),
+        };
+        Some(family)
+    }
+
+    fn font_families(&mut self) -> Vec<FontFamily> {
+        let mut families = vec![
+            FontFamily::MONOSPACE,
+            FontFamily::SANS_SERIF,
+            FontFamily::SERIF,
+            FontFamily::SYSTEM_UI,
+        ];
+        for font in &self.fonts {
+            let family = FontFamily::new_unchecked(font.family());
+            if !families.contains(&family) {
+                families.push(family);
+            }
+        }
+        families
+    }
+
+    fn load_font(&mut self, data: &[u8]) -> Result<FontFamily, Error> {
+        let font = Font::parse(data).ok_or(Error::MissingFont)?;
+        let family = FontFamily::new_unchecked(font.family());
+        self.fonts.push(Arc::new(font));
+        Ok(family)
+    }
+
+    fn new_text_layout(&mut self, text: impl IntoLayoutStorage) -> Self::TextLayoutBuilder {
+        NullTextLayoutBuilder {
+            text: text.into_layout_storage().as_str().into(),
+            fonts: self.fonts.clone(),
+            defaults: LayoutDefaults::default(),
+            range_attributes: Vec::new(),
+            shadows: Vec::new(),
+            width: f64::INFINITY,
+            alignment: TextAlignment::default(),
+            direction: TextDirection::default(),
+            line_spacing: LineSpacing::Default,
+            max_lines: None,
+            tab_stops: None,
+            break_opportunities: None,
+        }
+    }
+
+    fn font_metrics(&mut self, family: &FontFamily, size: f64) -> Option<FontMetrics> {
+        let style = Style::new(family, size, FontWeight::default(), &self.fonts);
+        Some(style.font_metrics())
+    }
+}
+
+impl TextLayoutBuilder for NullTextLayoutBuilder {
+    type Out = NullTextLayout;
+
+    fn max_width(mut self, width: f64) -> Self {
+        self.width = width;
+        self
+    }
+
+    fn alignment(mut self, alignment: TextAlignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
+
+    fn text_direction(mut self, direction: TextDirection) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    fn orientation(self, _orientation: TextOrientation) -> Self {
+        // all text is measured horizontally
+        self
+    }
+
+    fn line_spacing(mut self, spacing: LineSpacing) -> Self {
+        self.line_spacing = spacing;
+        self
+    }
+
+    fn max_lines(mut self, max_lines: usize) -> Self {
+        self.max_lines = Some(max_lines.max(1));
+        self
+    }
+
+    fn truncate(self, _mode: TruncationMode) -> Self {
+        // truncated lines are dropped, without measuring an ellipsis
+        self
+    }
+
+    fn tab_stops(mut self, stops: TabStops) -> Self {
+        self.tab_stops = Some(stops);
+        self
+    }
+
+    fn break_opportunities(mut self, breaks: impl FnOnce(&str) -> Vec<usize>) -> Self {
+        let text = &self.text;
+        self.break_opportunities = Some(util::resolve_break_opportunities(text, breaks(text)));
+        self
+    }
+
+    fn default_attribute(mut self, attribute: impl Into<TextAttribute>) -> Self {
+        self.defaults.set(attribute);
+        self
+    }
+
+    fn range_attribute(
+        mut self,
+        range: impl RangeBounds<usize>,
+        attribute: impl Into<TextAttribute>,
+    ) -> Self {
+        let range = util::resolve_range(range, self.text.len());
+        let attribute = attribute.into();
+        if let TextAttribute::Shadow(shadow) = &attribute {
+            if !range.is_empty() {
+                self.shadows.push(shadow.clone());
+            }
+        }
+        // only the attributes that change the font are measured
+        if matches!(
+            attribute,
+            TextAttribute::Font(_)
+                | TextAttribute::FontFamilies(_)
+                | TextAttribute::Size(_)
+                | TextAttribute::Weight(_)
+        ) {
+            self.range_attributes.push((range, attribute));
+        }
+        self
+    }
+
+    fn build(self) -> Result<Self::Out, Error> {
+        let defaults = &self.defaults;
+        let fonts = &self.fonts;
+        let default_style = Style::new(&defaults.font, defaults.font_size, defaults.weight, fonts);
+
+        let mut bounds = vec![0, self.text.len()];
+        for (range, _) in &self.range_attributes {
+            bounds.push(range.start);
+            bounds.push(range.end);
+        }
+        bounds.sort_unstable();
+        bounds.dedup();
+        let runs = bounds
+            .windows(2)
+            .map(|run| {
+                let (mut font, mut size, mut weight) =
+                    (&defaults.font, defaults.font_size, defaults.weight);
+                for (range, attribute) in &self.range_attributes {
+                    if range.start > run[0] || range.end < run[1] {
+                        continue;
+                    }
+                    match attribute {
+                        TextAttribute::Font(family) => font = family,
+                        TextAttribute::FontFamilies(families) => font = families.primary(),
+                        TextAttribute::Size(new_size) => size = *new_size,
+                        TextAttribute::Weight(new_weight) => weight = *new_weight,
+                        _ => (),
+                    }
+                }
+                (run[0]..run[1], Style::new(font, size, weight, fonts))
+            })
+            .collect();
+
+        // without bidi, right-to-left text is laid out from the right edge
+        let rtl = self.direction.is_rtl(&self.text);
+        let alignment = match self.alignment {
+            TextAlignment::Start if rtl => TextAlignment::End,
+            TextAlignment::End if rtl => TextAlignment::Start,
+            other => other,
+        };
+        let tab_stops = self
+            .tab_stops
+            .unwrap_or_else(|| TabStops::Uniform(default_style.advance(" ") * 8.0));
+
+        let mut layout = NullTextLayout {
+            text: self.text,
+            runs,
+            default_style,
+            letter_spacing: defaults.letter_spacing,
+            line_spacing: self.line_spacing,
+            alignment,
+            tab_stops,
+            break_opportunities: self.break_opportunities,
+            max_lines: self.max_lines,
+            line_metrics: Vec::new(),
+            line_x: Vec::new(),
+            size: Size::ZERO,
+            image_bounds: Rect::ZERO,
+            shadows: defaults
+                .shadow
+                .iter()
+                .cloned()
+                .chain(self.shadows)
+                .collect(),
+            glyph_runs: OnceCell::new(),
+        };
+        layout.update_width(self.width)?;
+        Ok(layout)
+    }
+}
+
+impl NullTextLayout {
+    /// The glyphs of the layout, in runs of the same style.
+    fn make_glyph_runs(&self) -> Vec<GlyphRun> {
+        let mut runs: Vec<(&Style, GlyphRun)> = Vec::new();
+        for (font, style, glyph) in self.glyphs(0..self.text.len()).0 {
+            match runs.last_mut() {
+                Some((run_style, run)) if std::ptr::eq(*run_style, style) => run.glyphs.push(glyph),
+                _ => runs.push((
+                    style,
+                    GlyphRun {
+                        font: FontFamily::new_unchecked(font.family()),
+                        font_size: style.size,
+                        glyphs: vec![glyph],
+                    },
+                )),
+            }
+        }
+        runs.into_iter().map(|(_, run)| run).collect()
+    }
+
+    fn style_at(&self, offset: usize) -> &Style {
+        self.runs
+            .iter()
+            .find(|(range, _)| range.contains(&offset))
+            .or_else(|| self.runs.last())
+            .map(|(_, style)| style)
+            .unwrap_or(&self.default_style)
+    }
+
+    /// The offset and x position of each grapheme in `range`, which starts
+    /// a line, and the position of the end of the range.
+    fn positions(&self, range: Range<usize>) -> Vec<(usize, f64)> {
+        let mut x = 0.0;
+        let mut positions = Vec::new();
+        for (offset, grapheme) in self.text[range.clone()].grapheme_indices(true) {
+            let offset = range.start + offset;
+            positions.push((offset, x));
+            x = if grapheme == "\t" {
+                self.tab_stops.next_stop(x)
+            } else if grapheme.starts_with(is_hard_break) {
+                x
+            } else {
+                x + self.style_at(offset).advance(grapheme) + self.letter_spacing
+            };
+        }
+        positions.push((range.end, x));
+        positions
+    }
+
+    /// The advance of `range`, which starts a line.
+    fn advance(&self, range: Range<usize>) -> f64 {
+        self.positions(range)
+            .last()
+            .map(|p| p.1)
+            .unwrap_or_default()
+    }
+
+    /// The advance of `range`, which starts a line, without any trailing
+    /// whitespace.
+    fn content_advance(&self, range: Range<usize>) -> f64 {
+        let content = self.text[range.clone()].trim_end().len();
+        self.advance(range.start..range.start + content)
+    }
+
+    /// The soft break opportunities strictly inside `range`.
+    fn soft_breaks(&self, range: Range<usize>) -> Vec<usize> {
+        if let Some(breaks) = &self.break_opportunities {
+            return breaks
+                .iter()
+                .copied()
+                .filter(|offset| range.start < *offset && *offset < range.end)
+                .collect();
+        }
+        // break after each run of spaces
+        let text = &self.text[range.clone()];
+        let mut prev_space = false;
+        let mut breaks = Vec::new();
+        for (offset, c) in text.char_indices() {
+            let space = c.is_whitespace();
+            if prev_space && !space {
+                breaks.push(range.start + offset);
+            }
+            prev_space = space;
+        }
+        breaks
+    }
+
+    /// The ranges of the paragraphs of the text, each including the hard
+    /// break that ends it.
+    fn paragraphs(&self) -> Vec<Range<usize>> {
+        let text = &self.text;
+        let mut paragraphs = Vec::new();
+        let mut start = 0;
+        let mut chars = text.char_indices().peekable();
+        while let Some((offset, c)) = chars.next() {
+            if !is_hard_break(c) {
+                continue;
+            }
+            let mut end = offset + c.len_utf8();
+            if c == '\r' && chars.peek().map(|p| p.1) == Some('\n') {
+                chars.next();
+                end += 1;
+            }
+            paragraphs.push(start..end);
+            start = end;
+        }
+        if start < text.len() || paragraphs.is_empty() {
+            paragraphs.push(start..text.len());
+        }
+        paragraphs
+    }
+
+    fn line_ranges(&self, width: f64) -> Vec<Range<usize>> {
+        let mut lines = Vec::new();
+        for paragraph in self.paragraphs() {
+            let mut line_start = paragraph.start;
+            // the last break at which the current line fits
+            let mut fit = None;
+            let ends = self.soft_breaks(paragraph.clone());
+            for end in ends.into_iter().chain(Some(paragraph.end)) {
+                if self.content_advance(line_start..end) > width {
+                    if let Some(fit) = fit.take() {
+                        lines.push(line_start..fit);
+                        line_start = fit;
+                    }
+                    // a single word wider than the line gets a line of its own
+                    if self.content_advance(line_start..end) > width {
+                        lines.push(line_start..end);
+                        line_start = end;
+                        continue;
+                    }
+                }
+                fit = Some(end);
+            }
+            if line_start < paragraph.end || paragraph.is_empty() {
+                lines.push(line_start..paragraph.end);
+            }
+        }
+        lines
+    }
+
+    fn line_metric_for(&self, range: Range<usize>, y_offset: f64) -> LineMetric {
+        let mut styles: Vec<&Style> = self
+            .runs
+            .iter()
+            .filter(|(run, _)| run.start < range.end && run.end > range.start)
+            .map(|(_, style)| style)
+            .collect();
+        if styles.is_empty() {
+            styles.push(self.style_at(range.start));
+        }
+        let ascent = styles.iter().map(|s| s.ascent()).fold(0.0, f64::max);
+        let descent = styles.iter().map(|s| s.descent()).fold(0.0, f64::max);
+        let (height, baseline) =
+            util::resolve_line_spacing(self.line_spacing, ascent + descent, ascent);
+
+        let line = &self.text[range.clone()];
+        let content = line.trim_end().len();
+        let whitespace_end = line.trim_end_matches(is_hard_break).len().max(content);
+        let trailing_whitespace_width = self.advance(range.start..range.start + whitespace_end)
+            - self.content_advance(range.clone());
+        LineMetric {
+            start_offset: range.start,
+            end_offset: range.end,
+            trailing_whitespace: line.len() - content,
+            trailing_whitespace_width,
+            baseline,
+            height,
+            y_offset,
+        }
+    }
+
+    /// The end of the line's text, before any hard break.
+    fn line_end(&self, line: &LineMetric) -> usize {
+        let text = &self.text[line.range()];
+        line.start_offset + text.trim_end_matches(is_hard_break).len()
+    }
+
+    /// The glyphs of the graphemes starting in `range` that are drawn with
+    /// loaded fonts, with their fonts and styles, and whether all the other
+    /// graphemes are whitespace.
+    fn glyphs(&self, range: Range<usize>) -> (Vec<(&Font, &Style, Glyph)>, bool) {
+        let mut glyphs = Vec::new();
+        let mut complete = true;
+        for (line, line_x) in self.line_metrics.iter().zip(&self.line_x) {
+            let end = self.line_end(line);
+            if line.start_offset >= range.end || end <= range.start {
+                continue;
+            }
+            let y = line.y_offset + line.baseline;
+            for pair in self.positions(line.start_offset..end).windows(2) {
+                let ((offset, x), (next, _)) = (pair[0], pair[1]);
+                if !range.contains(&offset) {
+                    continue;
+                }
+                let grapheme = &self.text[offset..next];
+                let style = self.style_at(offset);
+                let glyph = match &style.face {
+                    Face::Loaded(font) if grapheme != "\t" => grapheme
+                        .chars()
+                        .next()
+                        .and_then(|c| font.glyph(c))
+                        .map(|glyph| (&**font, glyph)),
+                    _ => None,
+                };
+                match glyph {
+                    Some((font, id)) => glyphs.push((
+                        font,
+                        style,
+                        Glyph {
+                            id: id as u32,
+                            position: Point::new(line_x + x, y),
+                            cluster: offset,
+                        },
+                    )),
+                    None => complete &= grapheme.chars().all(char::is_whitespace),
+                }
+            }
+        }
+        (glyphs, complete)
+    }
+
+    /// The outlines of the glyphs of the graphemes starting in `range`, and
+    /// whether all the graphemes without outlines are whitespace.
+    fn glyph_outlines(&self, range: Range<usize>) -> (BezPath, bool) {
+        let (glyphs, mut complete) = self.glyphs(range);
+        let mut path = BezPath::new();
+        for (font, style, glyph) in glyphs {
+            match font.outline(glyph.id as u16) {
+                Some(outline) => {
+                    let scale = style.scale();
+                    let xf = Affine::translate(glyph.position.to_vec2())
+                        * Affine::scale_non_uniform(scale, -scale);
+                    path.extend(xf * outline);
+                }
+                None => complete = false,
+            }
+        }
+        (path, complete)
+    }
+
+    /// Returns the outlines of the glyphs of the graphemes that start in
+    /// `range`, relative to the layout's origin, or `None` if some of them
+    /// have no outlines.
+    ///
+    /// Only text in fonts loaded with `load_font` that have TrueType
+    /// outlines can be outlined, apart from whitespace, which needs none.
+    pub fn range_outline(&self, range: impl RangeBounds<usize>) -> Option<BezPath> {
+        let range = util::resolve_range(range, self.text.len());
+        match self.glyph_outlines(range) {
+            (path, true) => Some(path),
+            _ => None,
+        }
+    }
+}
+
+impl TextLayout for NullTextLayout {
+    fn width(&self) -> f64 {
+        self.size.width
+    }
+
+    fn size(&self) -> Size {
+        self.size
+    }
+
+    fn image_bounds(&self) -> Rect {
+        self.image_bounds
+    }
+
+    fn text(&self) -> &str {
+        &self.text
+    }
+
+    fn update_width(&mut self, new_width: impl Into<Option<f64>>) -> Result<(), Error> {
+        let new_width = new_width.into().unwrap_or(f64::INFINITY);
+        if new_width.is_nan() || new_width < 0.0 {
+            return Err(Error::InvalidInput);
+        }
+
+        let mut ranges = self.line_ranges(new_width);
+        if let Some(max_lines) = self.max_lines {
+            ranges.truncate(max_lines);
+        }
+        self.glyph_runs = OnceCell::new();
+        let mut y_offset = 0.0;
+        self.line_metrics = ranges
+            .into_iter()
+            .map(|range| {
+                let line = self.line_metric_for(range, y_offset);
+                y_offset += line.height;
+                line
+            })
+            .collect();
+
+        let widths: Vec<f64> = self
+            .line_metrics
+            .iter()
+            .map(|line| self.content_advance(line.range()))
+            .collect();
+        let width = widths.iter().copied().fold(0.0, f64::max);
+        let available = if new_width.is_finite() {
+            new_width
+        } else {
+            width
+        };
+        self.line_x = widths
+            .iter()
+            .map(|line_width| match self.alignment {
+                TextAlignment::Start | TextAlignment::Justified => 0.0,
+                TextAlignment::End => available - line_width,
+                TextAlignment::Center => (available - line_width) / 2.0,
+            })
+            .collect();
+
+        self.size = Size::new(width, y_offset);
+        self.image_bounds = self
+            .line_metrics
+            .iter()
+            .zip(&self.line_x)
+            .zip(&widths)
+            .filter(|(_, width)| **width > 0.0)
+            .map(|((line, x), width)| {
+                Rect::new(*x, line.y_offset, x + width, line.y_offset + line.height)
+            })
+            .fold(None, |bounds: Option<Rect>, rect| {
+                Some(bounds.map_or(rect, |bounds| bounds.union(rect)))
+            })
+            .unwrap_or(Rect::ZERO);
+        self.image_bounds = util::bounds_with_shadows(self.image_bounds, &self.shadows);
+        Ok(())
+    }
+
+    fn line_text(&self, line_number: usize) -> Option<&str> {
+        self.line_metrics
+            .get(line_number)
+            .map(|line| &self.text[line.range()])
+    }
+
+    fn line_metric(&self, line_number: usize) -> Option<LineMetric> {
+        self.line_metrics.get(line_number).cloned()
+    }
+
+    fn line_count(&self) -> usize {
+        self.line_metrics.len()
+    }
+
+    fn hit_test_point(&self, point: Point) -> HitTestPoint {
+        let last = match self.line_metrics.last() {
+            Some(last) if !self.text.is_empty() => last,
+            _ => return HitTestPoint::default(),
+        };
+        let (y_inside, line_number) = if point.y < 0.0 {
+            (false, 0)
+        } else if point.y >= last.y_offset + last.height {
+            (false, self.line_metrics.len() - 1)
+        } else {
+            let line_number = self
+                .line_metrics
+                .iter()
+                .position(|line| point.y < line.y_offset + line.height)
+                .unwrap_or_default();
+            (true, line_number)
+        };
+
+        let line = &self.line_metrics[line_number];
+        let end = self.line_end(line);
+        let x = point.x - self.line_x[line_number];
+        let positions = self.positions(line.start_offset..end);
+        if x < 0.0 {
+            return HitTestPoint::new(line.start_offset, false, Affinity::Downstream);
+        }
+        for pair in positions.windows(2) {
+            let ((start, x0), (next, x1)) = (pair[0], pair[1]);
+            if x < x1 {
+                let idx = if x < (x0 + x1) / 2.0 { start } else { next };
+                return HitTestPoint::new(idx, y_inside, Affinity::Downstream);
+            }
+        }
+        HitTestPoint::new(end, false, Affinity::Upstream)
+    }
+
+    fn hit_test_text_position(&self, idx: usize) -> Option<HitTestPosition> {
+        if idx > self.text.len() || self.line_metrics.is_empty() {
+            return None;
+        }
+        let line_number = util::line_number_for_position(&self.line_metrics, idx);
+        let line = &self.line_metrics[line_number];
+        let end = idx.min(self.line_end(line));
+        // the position of the grapheme containing idx
+        let x = self
+            .positions(line.start_offset..self.line_end(line))
+            .into_iter()
+            .take_while(|(offset, _)| *offset <= end)
+            .last()
+            .map(|p| p.1)
+            .unwrap_or_default();
+        let point = Point::new(self.line_x[line_number] + x, line.y_offset + line.baseline);
+        Some(HitTestPosition::new(point, line_number))
+    }
+
+    fn hit_test_text_position_with_affinity(
+        &self,
+        idx: usize,
+        affinity: Affinity,
+    ) -> Option<HitTestPosition> {
+        util::hit_test_text_position_with_affinity(self, idx, affinity)
+    }
+
+    fn glyph_runs(&self) -> &[GlyphRun] {
+        self.glyph_runs.get_or_init(|| self.make_glyph_runs())
+    }
+
+    fn outline(&self) -> BezPath {
+        self.glyph_outlines(0..self.text.len()).0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kurbo::Shape;
+
+    fn sans_layout(text: &'static str, width: f64) -> NullTextLayout {
+        NullText::new()
+            .new_text_layout(text)
+            .font(FontFamily::SANS_SERIF, 10.0)
+            .max_width(width)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn measure_text() {
+        let layout = sans_layout("Hi", f64::INFINITY);
+        // 'H' is 0.722em and 'i' is 0.222em
+        assert!((layout.size().width - 9.44).abs() < 1e-9);
+        assert_eq!(layout.line_count(), 1);
+        let metrics = NullText::new()
+            .font_metrics(&FontFamily::SANS_SERIF, 10.0)
+            .unwrap();
+        let line = layout.line_metric(0).unwrap();
+        assert!((line.height - (metrics.ascent + metrics.descent)).abs() < 1e-9);
+        assert!((line.baseline - metrics.ascent).abs() < 1e-9);
+
+        let mono = NullText::new()
+            .new_text_layout("iiii")
+            .font(FontFamily::MONOSPACE, 10.0)
+            .build()
+            .unwrap();
+        assert!((mono.size().width - 24.0).abs() < 1e-9);
+        let bold = NullText::new()
+            .new_text_layout("Hi")
+            .font(FontFamily::SANS_SERIF, 10.0)
+            .default_attribute(FontWeight::BOLD)
+            .build()
+            .unwrap();
+        assert!(bold.size().width > layout.size().width);
+    }
+
+    #[test]
+    fn borrowed_text() {
+        // text that isn't `'static` is copied into the layout
+        let owned = String::from("Hi");
+        let layout = NullText::new()
+            .new_text_layout(owned.as_str())
+            .font(FontFamily::SANS_SERIF, 10.0)
+            .build()
+            .unwrap();
+        let borrowed = NullText::new().new_text_layout(&owned).build().unwrap();
+        drop(owned);
+        assert_eq!(layout.text(), "Hi");
+        assert_eq!(borrowed.text(), "Hi");
+        assert_eq!(layout.size(), sans_layout("Hi", f64::INFINITY).size());
+    }
+
+    #[test]
+    fn shared_text() {
+        fn storage<T: crate::TextStorage>(text: T) -> String {
+            text.as_str().to_owned()
+        }
+        // `Rc<str>` is storage with every feature, but only makes layouts
+        // when they needn't be `Send`
+        let rc: std::rc::Rc<str> = "Hi".into();
+        assert_eq!(storage(rc.clone()), "Hi");
+        let arc: Arc<str> = "Hi".into();
+        let layout = NullText::new()
+            .new_text_layout(arc.clone())
+            .build()
+            .unwrap();
+        assert_eq!(layout.text(), &*arc);
+        #[cfg(not(feature = "send-layouts"))]
+        {
+            let layout = NullText::new().new_text_layout(rc).build().unwrap();
+            assert_eq!(layout.text(), "Hi");
+        }
+    }
+
+    #[test]
+    fn wrap_lines() {
+        let layout = sans_layout("one two three\nfour", 40.0);
+        let lines: Vec<_> = (0..layout.line_count())
+            .map(|i| layout.line_text(i).unwrap())
+            .collect();
+        assert_eq!(lines, ["one two ", "three\n", "four"]);
+        let line = layout.line_metric(0).unwrap();
+        assert_eq!(line.trailing_whitespace, 1);
+        assert!((line.trailing_whitespace_width - 2.78).abs() < 1e-9);
+        assert!(layout.size().width <= 40.0);
+        assert_eq!(layout.line_metric(1).unwrap().y_offset, line.height);
+
+        // a word wider than the line is not broken
+        let layout = sans_layout("unbreakable", 10.0);
+        assert_eq!(layout.line_count(), 1);
+        assert!(layout.size().width > 10.0);
+    }
+
+    #[test]
+    fn affinity_at_soft_break() {
+        let layout = sans_layout("one two three\nfour", 40.0);
+        let upstream = layout
+            .hit_test_text_position_with_affinity(8, Affinity::Upstream)
+            .unwrap();
+        assert_eq!(upstream.line, 0);
+        assert!(upstream.point.x > 0.0);
+        let downstream = layout
+            .hit_test_text_position_with_affinity(8, Affinity::Downstream)
+            .unwrap();
+        let plain = layout.hit_test_text_position(8).unwrap();
+        assert_eq!(
+            (downstream.point, downstream.line),
+            (plain.point, plain.line)
+        );
+        // after a hard break, the affinity makes no difference
+        let upstream = layout
+            .hit_test_text_position_with_affinity(14, Affinity::Upstream)
+            .unwrap();
+        assert_eq!(upstream.line, 2);
+    }
+
+    #[test]
+    fn hit_testing() {
+        let layout = sans_layout("one two three", 40.0);
+        for idx in [0, 2, 4, 8, 13].iter().copied() {
+            let pos = layout.hit_test_text_position(idx).unwrap();
+            assert_eq!(layout.hit_test_point(pos.point).idx, idx);
+        }
+        let hit = layout.hit_test_point(Point::new(100.0, 1.0));
+        assert_eq!(hit.idx, 8);
+        assert!(!hit.is_inside);
+        assert_eq!(layout.hit_test_point(Point::new(-5.0, 1.0)).idx, 0);
+        assert!(layout.hit_test_text_position(14).is_none());
+    }
+
+    #[test]
+    fn loaded_fonts() {
+        let mut text = NullText::new();
+        let data = super::super::font::tests::test_font("Test Sans");
+        let family = text.load_font(&data).unwrap();
+        assert_eq!(family.name(), "Test Sans");
+        assert_eq!(text.font_family("test sans"), Some(family.clone()));
+        assert!(text.font_families().contains(&family));
+        assert!(text.load_font(b"not a font").is_err());
+
+        // the glyphs for 'A', 'B' and 'C' advance 0.6, 0.7 and 0.8em, and
+        // 'n' isn't in the font, so it's measured as sans-serif
+        let layout = text
+            .new_text_layout("ABCn")
+            .font(family.clone(), 10.0)
+            .build()
+            .unwrap();
+        assert!((layout.size().width - 26.56).abs() < 1e-9);
+        let line = layout.line_metric(0).unwrap();
+        assert!((line.height - 10.0).abs() < 1e-9);
+        assert!((line.baseline - 8.0).abs() < 1e-9);
+        // 'B' is from 6 to 13
+        assert_eq!(layout.hit_test_point(Point::new(9.0, 5.0)).idx, 1);
+        assert_eq!(layout.hit_test_point(Point::new(10.0, 5.0)).idx, 2);
+        let metrics = text.font_metrics(&family, 10.0).unwrap();
+        assert!((metrics.cap_height - 7.0).abs() < 1e-9);
+
+        // other text systems haven't loaded the font
+        let unloaded = NullText::new()
+            .new_text_layout("CCC")
+            .font(family, 10.0)
+            .build()
+            .unwrap();
+        assert!((unloaded.size().width - 24.0).abs() > 1.0);
+    }
+
+    #[test]
+    fn glyph_outlines() {
+        let mut text = NullText::new();
+        let data = super::super::font::tests::test_font("Test Sans");
+        let family = text.load_font(&data).unwrap();
+        let layout = text
+            .new_text_layout("AB Cn")
+            .font(family.clone(), 10.0)
+            .build()
+            .unwrap();
+        // the space and 'n' aren't in the font
+        let runs = layout.glyph_runs();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].font, family);
+        let glyphs: Vec<_> = runs[0].glyphs.iter().map(|g| (g.id, g.cluster)).collect();
+        assert_eq!(glyphs, [(1, 0), (2, 1), (3, 3)]);
+        assert_eq!(runs[0].glyphs[1].position, Point::new(6.0, 8.0));
+
+        // the rectangle of 'A' is 5 by 7 above the baseline
+        let outline = layout.range_outline(..1).unwrap();
+        assert_eq!(outline.bounding_box(), Rect::new(0.0, 1.0, 5.0, 8.0));
+        assert!(layout.range_outline(..4).is_some());
+        assert!(layout.range_outline(3..).is_none());
+        assert_eq!(layout.outline().elements().len(), 16);
+    }
+
+    #[test]
+    fn alignment() {
+        let layout = NullText::new()
+            .new_text_layout("Hi")
+            .font(FontFamily::SANS_SERIF, 10.0)
+            .max_width(100.0)
+            .alignment(TextAlignment::End)
+            .build()
+            .unwrap();
+        let pos = layout.hit_test_text_position(0).unwrap();
+        assert!((pos.point.x - (100.0 - 9.44)).abs() < 1e-9);
+        assert!((layout.image_bounds().x1 - 100.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,1000 @@
+//! Recording drawing commands, to replay them later.
+//!
+//! A [`RecordingRenderContext`] is a render context that doesn't draw;
+//! instead, it keeps a [`DisplayList`] of everything drawn to it, which can
+//! be replayed into any other render context, any number of times:
+//!
+//! ```
+//! use piet::kurbo::Rect;
+//! use piet::record::RecordingRenderContext;
+//! use piet::{Color, NullRenderContext, RenderContext};
+//!
+//! // the text system is used to measure text while recording
+//! let mut target = NullRenderContext::new();
+//! let mut recorder = RecordingRenderContext::new(target.text().clone());
+//! recorder.fill(Rect::new(0.0, 0.0, 10.0, 10.0), &Color::BLACK);
+//! let list = recorder.into_display_list();
+//!
+//! list.replay(&mut target).unwrap();
+//! ```
+//!
+//! Images are kept as [`ImageBuf`]s, and made into backend images when
+//! replayed. Text layouts are kept as the calls used to build them, and are
+//! rebuilt by the text system of the context they are replayed into. Layouts
+//! made while recording are measured with the text system passed to
+//! [`RecordingRenderContext::new`]; use the one from the context the list
+//! will be replayed into, for the best match. Fonts loaded while recording
+//! must also be available to the replaying context.
+//!
+//! [`capture_image_area`] isn't supported, as nothing is drawn while
+//! recording.
+//!
+//! [`RecordingRenderContext`]: struct.RecordingRenderContext.html
+//! [`DisplayList`]: struct.DisplayList.html
+//! [`ImageBuf`]: ../struct.ImageBuf.html
+//! [`RecordingRenderContext::new`]: struct.RecordingRenderContext.html#method.new
+//! [`capture_image_area`]: ../trait.RenderContext.html#tymethod.capture_image_area
+
+use std::borrow::Cow;
+use std::ops::{Range, RangeBounds};
+use std::path::Path;
+use std::sync::Arc;
+
+use kurbo::{Affine, BezPath, Circle, Line, PathEl, Point, Rect, RoundedRect, Shape, Size};
+
+use crate::{
+    util, Affinity, BlendMode, Color, Error, FixedGradient, FontFamily, FontMetrics, GlyphRun,
+    HitTestPoint, HitTestPosition, ImageBuf, ImageFormat, InterpolationMode, IntoBrush,
+    IntoLayoutStorage, LayerMask, LineMetric, LineSpacing, RenderContext, StrokeStyle, TabStops,
+    Text, TextAlignment, TextAttribute, TextDirection, TextLayout, TextLayoutBuilder,
+    TextOrientation, TextStorage, TileMode, TruncationMode,
+};
+
+/// The tolerance for converting shapes to paths.
+const TOLERANCE: f64 = 1e-3;
+
+/// A brush used by a [`RecordingRenderContext`].
+///
+/// [`RecordingRenderContext`]: struct.RecordingRenderContext.html
+#[derive(Clone)]
+pub enum RecordedBrush {
+    /// A solid color.
+    Solid(Color),
+    /// A gradient, with its geometry resolved.
+    Gradient(FixedGradient),
+    /// An image, tiled and transformed.
+    Image {
+        image: ImageBuf,
+        tile: TileMode,
+        transform: Affine,
+    },
+}
+
+/// A shape, in a [`DisplayList`].
+///
+/// Rects, rounded rects, circles and lines are kept as they are, so that
+/// backends can draw them with their own primitives when they are replayed,
+/// as they would have been drawn directly; other shapes are kept as paths.
+///
+/// [`DisplayList`]: struct.DisplayList.html
+#[derive(Clone, Debug)]
+pub enum RecordedShape {
+    Rect(Rect),
+    RoundedRect(RoundedRect),
+    Circle(Circle),
+    Line(Line),
+    Path(BezPath),
+}
+
+impl RecordedShape {
+    /// Record a shape.
+    pub fn new(shape: impl Shape) -> RecordedShape {
+        if let Some(rect) = shape.as_rect() {
+            RecordedShape::Rect(rect)
+        } else if let Some(rounded) = shape.as_rounded_rect() {
+            RecordedShape::RoundedRect(rounded)
+        } else if let Some(circle) = shape.as_circle() {
+            RecordedShape::Circle(circle)
+        } else if let Some(line) = shape.as_line() {
+            RecordedShape::Line(line)
+        } else {
+            RecordedShape::Path(shape.into_bez_path(TOLERANCE))
+        }
+    }
+}
+
+/// Forward a `Shape` method to the shape of each variant.
+macro_rules! forward_shape {
+    ($self:ident, $shape:ident => $e:expr) => {
+        match $self {
+            RecordedShape::Rect($shape) => $e,
+            RecordedShape::RoundedRect($shape) => $e,
+            RecordedShape::Circle($shape) => $e,
+            RecordedShape::Line($shape) => $e,
+            RecordedShape::Path($shape) => $e,
+        }
+    };
+}
+
+impl Shape for RecordedShape {
+    type BezPathIter = std::vec::IntoIter<PathEl>;
+
+    fn to_bez_path(&self, tolerance: f64) -> Self::BezPathIter {
+        let path: Vec<_> = forward_shape!(self, shape => shape.to_bez_path(tolerance).collect());
+        path.into_iter()
+    }
+
+    fn area(&self) -> f64 {
+        forward_shape!(self, shape => shape.area())
+    }
+
+    fn perimeter(&self, accuracy: f64) -> f64 {
+        forward_shape!(self, shape => shape.perimeter(accuracy))
+    }
+
+    fn winding(&self, pt: Point) -> i32 {
+        forward_shape!(self, shape => shape.winding(pt))
+    }
+
+    fn bounding_box(&self) -> Rect {
+        forward_shape!(self, shape => shape.bounding_box())
+    }
+
+    fn as_line(&self) -> Option<Line> {
+        forward_shape!(self, shape => shape.as_line())
+    }
+
+    fn as_rect(&self) -> Option<Rect> {
+        forward_shape!(self, shape => shape.as_rect())
+    }
+
+    fn as_rounded_rect(&self) -> Option<RoundedRect> {
+        forward_shape!(self, shape => shape.as_rounded_rect())
+    }
+
+    fn as_circle(&self) -> Option<Circle> {
+        forward_shape!(self, shape => shape.as_circle())
+    }
+
+    fn as_path_slice(&self) -> Option<&[PathEl]> {
+        forward_shape!(self, shape => shape.as_path_slice())
+    }
+}
+
+/// The mask of a layer, in a [`DisplayList`].
+///
+/// [`DisplayList`]: struct.DisplayList.html
+#[derive(Clone)]
+pub enum RecordedMask {
+    /// See [`LayerMask::Shape`](../enum.LayerMask.html#variant.Shape).
+    Shape(BezPath),
+    /// See [`LayerMask::Image`](../enum.LayerMask.html#variant.Image).
+    Image(ImageBuf, Rect),
+}
+
+/// One recorded drawing command.
+///
+/// Each corresponds to the [`RenderContext`] method of the same name. Shapes
+/// are recorded as [`RecordedShape`]s, and brushes have been resolved against
+/// the shape they were used with.
+///
+/// [`RenderContext`]: ../trait.RenderContext.html
+/// [`RecordedShape`]: enum.RecordedShape.html
+#[derive(Clone)]
+#[non_exhaustive]
+pub enum DrawOp {
+    Clear(Color),
+    Fill {
+        shape: RecordedShape,
+        brush: RecordedBrush,
+    },
+    FillEvenOdd {
+        shape: RecordedShape,
+        brush: RecordedBrush,
+    },
+    /// A stroke; the style is `None` for [`stroke`], as opposed to
+    /// [`stroke_styled`].
+    ///
+    /// [`stroke`]: ../trait.RenderContext.html#tymethod.stroke
+    /// [`stroke_styled`]: ../trait.RenderContext.html#tymethod.stroke_styled
+    Stroke {
+        shape: RecordedShape,
+        brush: RecordedBrush,
+        width: f64,
+        style: Option<StrokeStyle>,
+    },
+    Clip(RecordedShape),
+    ClipEvenOdd(RecordedShape),
+    DrawText {
+        layout: TextLayoutSpec,
+        pos: Point,
+    },
+    Save,
+    SaveLayer(f64),
+    SaveLayerWithMask(RecordedMask),
+    Restore,
+    SetBlendMode(BlendMode),
+    PushOpacity(f64),
+    PopOpacity,
+    Transform(Affine),
+    /// An image; `src_rect` is `None` for [`draw_image`], as opposed to
+    /// [`draw_image_area`].
+    ///
+    /// [`draw_image`]: ../trait.RenderContext.html#tymethod.draw_image
+    /// [`draw_image_area`]: ../trait.RenderContext.html#tymethod.draw_image_area
+    DrawImage {
+        image: ImageBuf,
+        src_rect: Option<Rect>,
+        dst_rect: Rect,
+        interp: InterpolationMode,
+    },
+    BlurredRect {
+        rect: Rect,
+        blur_radius: f64,
+        brush: RecordedBrush,
+    },
+    BlurredShape {
+        shape: BezPath,
+        blur_radius: f64,
+        brush: RecordedBrush,
+    },
+}
+
+/// A list of drawing commands, recorded by a [`RecordingRenderContext`].
+///
+/// [`RecordingRenderContext`]: struct.RecordingRenderContext.html
+#[derive(Clone, Default)]
+pub struct DisplayList {
+    ops: Vec<DrawOp>,
+}
+
+impl DisplayList {
+    /// The recorded commands, in order.
+    pub fn ops(&self) -> &[DrawOp] {
+        &self.ops
+    }
+
+    /// Draw the recorded commands to `rc`.
+    ///
+    /// The commands are drawn in the current coordinate space of `rc`, and
+    /// its state is restored afterwards, even if the recording left saves
+    /// or layers unbalanced.
+    pub fn replay(&self, rc: &mut impl RenderContext) -> Result<(), Error> {
+        rc.save()?;
+        let (mut depth, mut opacities) = (0, 0);
+        let result = self.replay_ops(rc, &mut depth, &mut opacities);
+        for _ in 0..opacities {
+            rc.pop_opacity()?;
+        }
+        for _ in 0..depth {
+            rc.restore()?;
+        }
+        rc.restore()?;
+        result
+    }
+
+    fn replay_ops<R: RenderContext>(
+        &self,
+        rc: &mut R,
+        depth: &mut usize,
+        opacities: &mut usize,
+    ) -> Result<(), Error> {
+        for op in &self.ops {
+            match op {
+                DrawOp::Clear(color) => rc.clear(color.clone()),
+                DrawOp::Fill { shape, brush } => {
+                    let brush = brush.to_brush(rc)?;
+                    rc.fill(shape, &brush);
+                }
+                DrawOp::FillEvenOdd { shape, brush } => {
+                    let brush = brush.to_brush(rc)?;
+                    rc.fill_even_odd(shape, &brush);
+                }
+                DrawOp::Stroke {
+                    shape,
+                    brush,
+                    width,
+                    style,
+                } => {
+                    let brush = brush.to_brush(rc)?;
+                    match style {
+                        Some(style) => rc.stroke_styled(shape, &brush, *width, style),
+                        None => rc.stroke(shape, &brush, *width),
+                    }
+                }
+                DrawOp::Clip(shape) => rc.clip(shape),
+                DrawOp::ClipEvenOdd(shape) => rc.clip_even_odd(shape),
+                DrawOp::DrawText { layout, pos } => {
+                    let layout = layout.build(rc.text())?;
+                    rc.draw_text(&layout, *pos);
+                }
+                DrawOp::Save => {
+                    rc.save()?;
+                    *depth += 1;
+                }
+                DrawOp::SaveLayer(opacity) => {
+                    rc.save_layer(*opacity)?;
+                    *depth += 1;
+                }
+                DrawOp::SaveLayerWithMask(RecordedMask::Shape(path)) => {
+                    rc.save_layer_with_mask(LayerMask::Shape(path.clone()))?;
+                    *depth += 1;
+                }
+                DrawOp::SaveLayerWithMask(RecordedMask::Image(image, rect)) => {
+                    let image = image.to_image(rc)?;
+                    rc.save_layer_with_mask(LayerMask::Image(&image, *rect))?;
+                    *depth += 1;
+                }
+                DrawOp::Restore => {
+                    rc.restore()?;
+                    *depth -= 1;
+                }
+                DrawOp::SetBlendMode(mode) => rc.set_blend_mode(*mode)?,
+                DrawOp::PushOpacity(alpha) => {
+                    rc.push_opacity(*alpha);
+                    *opacities += 1;
+                }
+                DrawOp::PopOpacity => {
+                    rc.pop_opacity()?;
+                    *opacities -= 1;
+                }
+                DrawOp::Transform(transform) => rc.transform(*transform),
+                DrawOp::DrawImage {
+                    image,
+                    src_rect,
+                    dst_rect,
+                    interp,
+                } => {
+                    let image = image.to_image(rc)?;
+                    match src_rect {
+                        Some(src_rect) => rc.draw_image_area(&image, *src_rect, *dst_rect, *interp),
+                        None => rc.draw_image(&image, *dst_rect, *interp),
+                    }
+                }
+                DrawOp::BlurredRect {
+                    rect,
+                    blur_radius,
+                    brush,
+                } => {
+                    let brush = brush.to_brush(rc)?;
+                    rc.blurred_rect(*rect, *blur_radius, &brush);
+                }
+                DrawOp::BlurredShape {
+                    shape,
+                    blur_radius,
+                    brush,
+                } => {
+                    let brush = brush.to_brush(rc)?;
+                    rc.blurred_shape(shape, *blur_radius, &brush);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RecordedBrush {
+    fn to_brush<R: RenderContext>(&self, rc: &mut R) -> Result<R::Brush, Error> {
+        match self {
+            RecordedBrush::Solid(color) => Ok(rc.solid_brush(color.clone())),
+            RecordedBrush::Gradient(gradient) => rc.gradient(gradient.clone()),
+            RecordedBrush::Image {
+                image,
+                tile,
+                transform,
+            } => {
+                let image = image.to_image(rc)?;
+                rc.make_image_brush(&image, *tile, *transform)
+            }
+        }
+    }
+}
+
+/// A render context that records a [`DisplayList`].
+///
+/// See the [module docs](index.html).
+///
+/// [`DisplayList`]: struct.DisplayList.html
+pub struct RecordingRenderContext<T: Text> {
+    text: RecordingText<T>,
+    ops: Vec<DrawOp>,
+    state: State,
+    /// For each save or layer, the state before it, and whether it was a
+    /// layer.
+    state_stack: Vec<(State, bool)>,
+    /// The number of opacities pushed and not yet popped.
+    opacities: usize,
+}
+
+#[derive(Clone, Copy, Default)]
+struct State {
+    transform: Affine,
+    /// The bounds of the clip, before any transforms.
+    clip: Option<Rect>,
+}
+
+impl<T: Text> RecordingRenderContext<T> {
+    /// Start recording, measuring text with `text`.
+    pub fn new(text: T) -> RecordingRenderContext<T> {
+        RecordingRenderContext {
+            text: RecordingText { inner: text },
+            ops: Vec::new(),
+            state: State::default(),
+            state_stack: Vec::new(),
+            opacities: 0,
+        }
+    }
+
+    /// The commands recorded so far.
+    pub fn display_list(&self) -> DisplayList {
+        DisplayList {
+            ops: self.ops.clone(),
+        }
+    }
+
+    /// Stop recording, returning the recorded commands.
+    pub fn into_display_list(self) -> DisplayList {
+        DisplayList { ops: self.ops }
+    }
+
+    fn brush(&mut self, brush: &impl IntoBrush<Self>, shape: &impl Shape) -> RecordedBrush {
+        brush.make_brush(self, || shape.bounding_box()).into_owned()
+    }
+
+    fn push_state(&mut self, op: DrawOp, layer: bool) {
+        self.state_stack.push((self.state, layer));
+        self.ops.push(op);
+    }
+
+    fn add_clip_bounds(&mut self, shape: &impl Shape) {
+        self.state.clip = Some(util::intersect_clip_bounds(
+            self.state.clip,
+            shape.bounding_box(),
+            self.state.transform,
+        ));
+    }
+}
+
+impl<T: Text> RenderContext for RecordingRenderContext<T> {
+    type Brush = RecordedBrush;
+    type Image = ImageBuf;
+    type Text = RecordingText<T>;
+    type TextLayout = RecordedTextLayout<T::TextLayout>;
+
+    fn status(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn solid_brush(&mut self, color: Color) -> RecordedBrush {
+        RecordedBrush::Solid(color)
+    }
+
+    fn gradient(&mut self, gradient: impl Into<FixedGradient>) -> Result<RecordedBrush, Error> {
+        Ok(RecordedBrush::Gradient(gradient.into()))
+    }
+
+    fn make_image_brush(
+        &mut self,
+        image: &ImageBuf,
+        tile: TileMode,
+        transform: Affine,
+    ) -> Result<RecordedBrush, Error> {
+        Ok(RecordedBrush::Image {
+            image: image.clone(),
+            tile,
+            transform,
+        })
+    }
+
+    fn clear(&mut self, color: Color) {
+        self.ops.push(DrawOp::Clear(color));
+    }
+
+    fn stroke(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, width: f64) {
+        let brush = self.brush(brush, &shape);
+        self.ops.push(DrawOp::Stroke {
+            shape: RecordedShape::new(shape),
+            brush,
+            width,
+            style: None,
+        });
+    }
+
+    fn stroke_styled(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+    ) {
+        let brush = self.brush(brush, &shape);
+        self.ops.push(DrawOp::Stroke {
+            shape: RecordedShape::new(shape),
+            brush,
+            width,
+            style: Some(style.clone()),
+        });
+    }
+
+    fn fill(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
+        let brush = self.brush(brush, &shape);
+        self.ops.push(DrawOp::Fill {
+            shape: RecordedShape::new(shape),
+            brush,
+        });
+    }
+
+    fn fill_even_odd(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>) {
+        let brush = self.brush(brush, &shape);
+        self.ops.push(DrawOp::FillEvenOdd {
+            shape: RecordedShape::new(shape),
+            brush,
+        });
+    }
+
+    fn clip(&mut self, shape: impl Shape) {
+        self.add_clip_bounds(&shape);
+        self.ops.push(DrawOp::Clip(RecordedShape::new(shape)));
+    }
+
+    fn clip_even_odd(&mut self, shape: impl Shape) {
+        self.add_clip_bounds(&shape);
+        self.ops
+            .push(DrawOp::ClipEvenOdd(RecordedShape::new(shape)));
+    }
+
+    fn text(&mut self) -> &mut RecordingText<T> {
+        &mut self.text
+    }
+
+    fn draw_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>) {
+        self.ops.push(DrawOp::DrawText {
+            layout: TextLayoutSpec::clone(&layout.spec),
+            pos: pos.into(),
+        });
+    }
+
+    fn save(&mut self) -> Result<(), Error> {
+        self.push_state(DrawOp::Save, false);
+        Ok(())
+    }
+
+    fn restore(&mut self) -> Result<(), Error> {
+        let (state, _) = self.state_stack.pop().ok_or(Error::StackUnbalance)?;
+        self.state = state;
+        self.ops.push(DrawOp::Restore);
+        Ok(())
+    }
+
+    fn save_layer(&mut self, opacity: f64) -> Result<(), Error> {
+        self.push_state(DrawOp::SaveLayer(opacity), true);
+        Ok(())
+    }
+
+    fn save_layer_with_mask(&mut self, mask: LayerMask<ImageBuf>) -> Result<(), Error> {
+        let mask = match mask {
+            LayerMask::Shape(path) => RecordedMask::Shape(path),
+            LayerMask::Image(image, rect) => RecordedMask::Image(image.clone(), rect),
+        };
+        self.push_state(DrawOp::SaveLayerWithMask(mask), true);
+        Ok(())
+    }
+
+    fn set_blend_mode(&mut self, mode: BlendMode) -> Result<(), Error> {
+        self.ops.push(DrawOp::SetBlendMode(mode));
+        Ok(())
+    }
+
+    fn push_opacity(&mut self, alpha: f64) {
+        self.ops.push(DrawOp::PushOpacity(alpha));
+        self.opacities += 1;
+    }
+
+    fn pop_opacity(&mut self) -> Result<(), Error> {
+        if self.opacities == 0 {
+            return Err(Error::StackUnbalance);
+        }
+        self.ops.push(DrawOp::PopOpacity);
+        self.opacities -= 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        if self.state_stack.iter().any(|&(_, layer)| layer) {
+            return Err(Error::StackUnbalance);
+        }
+        Ok(())
+    }
+
+    fn transform(&mut self, transform: Affine) {
+        self.state.transform *= transform;
+        self.ops.push(DrawOp::Transform(transform));
+    }
+
+    fn make_image(
+        &mut self,
+        width: usize,
+        height: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<ImageBuf, Error> {
+        if buf.len() != width * height * format.bytes_per_pixel() {
+            return Err(Error::InvalidInput);
+        }
+        Ok(ImageBuf::from_raw(buf, format, width, height))
+    }
+
+    fn capture_image_area(&mut self, _src_rect: impl Into<Rect>) -> Result<ImageBuf, Error> {
+        Err(Error::NotSupported)
+    }
+
+    fn to_image_buf(&mut self, image: &ImageBuf, format: ImageFormat) -> Result<ImageBuf, Error> {
+        Ok(image.to_format(format))
+    }
+
+    fn draw_image(
+        &mut self,
+        image: &ImageBuf,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        self.ops.push(DrawOp::DrawImage {
+            image: image.clone(),
+            src_rect: None,
+            dst_rect: dst_rect.into(),
+            interp,
+        });
+    }
+
+    fn draw_image_area(
+        &mut self,
+        image: &ImageBuf,
+        src_rect: impl Into<Rect>,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        self.ops.push(DrawOp::DrawImage {
+            image: image.clone(),
+            src_rect: Some(src_rect.into()),
+            dst_rect: dst_rect.into(),
+            interp,
+        });
+    }
+
+    fn blurred_rect(&mut self, rect: Rect, blur_radius: f64, brush: &impl IntoBrush<Self>) {
+        let brush = self.brush(brush, &rect);
+        self.ops.push(DrawOp::BlurredRect {
+            rect,
+            blur_radius,
+            brush,
+        });
+    }
+
+    fn blurred_shape(&mut self, shape: impl Shape, blur_radius: f64, brush: &impl IntoBrush<Self>) {
+        let brush = self.brush(brush, &shape);
+        self.ops.push(DrawOp::BlurredShape {
+            shape: shape.into_bez_path(TOLERANCE),
+            blur_radius,
+            brush,
+        });
+    }
+
+    fn current_transform(&self) -> Affine {
+        self.state.transform
+    }
+
+    fn current_clip_bounds(&self) -> Option<Rect> {
+        util::user_clip_bounds(self.state.clip, self.state.transform)
+    }
+}
+
+impl<T: Text> IntoBrush<RecordingRenderContext<T>> for RecordedBrush {
+    fn make_brush<'b>(
+        &'b self,
+        _piet: &mut RecordingRenderContext<T>,
+        _bbox: impl FnOnce() -> Rect,
+    ) -> Cow<'b, RecordedBrush> {
+        Cow::Borrowed(self)
+    }
+}
+
+/// The text system of a [`RecordingRenderContext`].
+///
+/// This measures text with the text system it wraps.
+///
+/// [`RecordingRenderContext`]: struct.RecordingRenderContext.html
+#[derive(Clone)]
+pub struct RecordingText<T> {
+    inner: T,
+}
+
+/// A text layout built by a [`RecordingText`].
+///
+/// This is a layout from the wrapped text system, along with the calls used
+/// to build it.
+///
+/// [`RecordingText`]: struct.RecordingText.html
+#[derive(Clone)]
+pub struct RecordedTextLayout<L> {
+    inner: L,
+    spec: Arc<TextLayoutSpec>,
+}
+
+/// The builder for a [`RecordedTextLayout`].
+///
+/// [`RecordedTextLayout`]: struct.RecordedTextLayout.html
+pub struct RecordingTextLayoutBuilder<B> {
+    inner: B,
+    spec: TextLayoutSpec,
+}
+
+/// How to build a text layout, with any text system.
+#[derive(Clone)]
+pub struct TextLayoutSpec {
+    text: Arc<str>,
+    calls: Vec<BuilderCall>,
+    /// The width passed to [`TextLayout::update_width`] after building, if any.
+    ///
+    /// [`TextLayout::update_width`]: ../trait.TextLayout.html#tymethod.update_width
+    new_width: Option<Option<f64>>,
+}
+
+/// A recorded call to a [`TextLayoutBuilder`] method.
+///
+/// [`TextLayoutBuilder`]: ../trait.TextLayoutBuilder.html
+#[derive(Clone)]
+enum BuilderCall {
+    MaxWidth(f64),
+    Alignment(TextAlignment),
+    TextDirection(TextDirection),
+    Orientation(TextOrientation),
+    LineSpacing(LineSpacing),
+    MaxLines(usize),
+    Truncate(TruncationMode),
+    TabStops(TabStops),
+    BreakOpportunities(Vec<usize>),
+    DefaultAttribute(TextAttribute),
+    RangeAttribute(Range<usize>, TextAttribute),
+}
+
+impl TextLayoutSpec {
+    /// The text of the layout.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Build the layout with `text`.
+    pub fn build<T: Text>(&self, text: &mut T) -> Result<T::TextLayout, Error> {
+        let mut builder = text.new_text_layout(self.text.clone());
+        for call in &self.calls {
+            builder = match call.clone() {
+                BuilderCall::MaxWidth(width) => builder.max_width(width),
+                BuilderCall::Alignment(alignment) => builder.alignment(alignment),
+                BuilderCall::TextDirection(direction) => builder.text_direction(direction),
+                BuilderCall::Orientation(orientation) => builder.orientation(orientation),
+                BuilderCall::LineSpacing(spacing) => builder.line_spacing(spacing),
+                BuilderCall::MaxLines(max_lines) => builder.max_lines(max_lines),
+                BuilderCall::Truncate(mode) => builder.truncate(mode),
+                BuilderCall::TabStops(stops) => builder.tab_stops(stops),
+                BuilderCall::BreakOpportunities(breaks) => builder.break_opportunities(|_| breaks),
+                BuilderCall::DefaultAttribute(attribute) => builder.default_attribute(attribute),
+                BuilderCall::RangeAttribute(range, attribute) => {
+                    builder.range_attribute(range, attribute)
+                }
+            };
+        }
+        let mut layout = builder.build()?;
+        if let Some(new_width) = self.new_width {
+            layout.update_width(new_width)?;
+        }
+        Ok(layout)
+    }
+}
+
+impl<T: Text> Text for RecordingText<T> {
+    type TextLayoutBuilder = RecordingTextLayoutBuilder<T::TextLayoutBuilder>;
+    type TextLayout = RecordedTextLayout<T::TextLayout>;
+
+    fn font_family(&mut self, family_name: &str) -> Option<FontFamily> {
+        self.inner.font_family(family_name)
+    }
+
+    fn font_families(&mut self) -> Vec<FontFamily> {
+        self.inner.font_families()
+    }
+
+    fn load_font(&mut self, data: &[u8]) -> Result<FontFamily, Error> {
+        self.inner.load_font(data)
+    }
+
+    fn load_font_from_path(&mut self, path: &Path) -> Result<FontFamily, Error> {
+        self.inner.load_font_from_path(path)
+    }
+
+    fn new_text_layout(&mut self, text: impl IntoLayoutStorage) -> Self::TextLayoutBuilder {
+        let text: Arc<str> = text.into_layout_storage().as_str().into();
+        RecordingTextLayoutBuilder {
+            inner: self.inner.new_text_layout(text.clone()),
+            spec: TextLayoutSpec {
+                text,
+                calls: Vec::new(),
+                new_width: None,
+            },
+        }
+    }
+
+    fn font_metrics(&mut self, family: &FontFamily, size: f64) -> Option<FontMetrics> {
+        self.inner.font_metrics(family, size)
+    }
+}
+
+impl<B: TextLayoutBuilder> RecordingTextLayoutBuilder<B> {
+    fn record(mut self, call: BuilderCall, f: impl FnOnce(B) -> B) -> Self {
+        self.spec.calls.push(call);
+        self.inner = f(self.inner);
+        self
+    }
+}
+
+impl<B: TextLayoutBuilder> TextLayoutBuilder for RecordingTextLayoutBuilder<B> {
+    type Out = RecordedTextLayout<B::Out>;
+
+    fn max_width(self, width: f64) -> Self {
+        self.record(BuilderCall::MaxWidth(width), |b| b.max_width(width))
+    }
+
+    fn alignment(self, alignment: TextAlignment) -> Self {
+        self.record(BuilderCall::Alignment(alignment), |b| {
+            b.alignment(alignment)
+        })
+    }
+
+    fn text_direction(self, direction: TextDirection) -> Self {
+        self.record(BuilderCall::TextDirection(direction), |b| {
+            b.text_direction(direction)
+        })
+    }
+
+    fn orientation(self, orientation: TextOrientation) -> Self {
+        self.record(BuilderCall::Orientation(orientation), |b| {
+            b.orientation(orientation)
+        })
+    }
+
+    fn line_spacing(self, spacing: LineSpacing) -> Self {
+        self.record(BuilderCall::LineSpacing(spacing), |b| {
+            b.line_spacing(spacing)
+        })
+    }
+
+    fn max_lines(self, max_lines: usize) -> Self {
+        self.record(BuilderCall::MaxLines(max_lines), |b| b.max_lines(max_lines))
+    }
+
+    fn truncate(self, mode: TruncationMode) -> Self {
+        self.record(BuilderCall::Truncate(mode), |b| b.truncate(mode))
+    }
+
+    fn tab_stops(self, stops: TabStops) -> Self {
+        self.record(BuilderCall::TabStops(stops.clone()), |b| b.tab_stops(stops))
+    }
+
+    fn break_opportunities(self, breaks: impl FnOnce(&str) -> Vec<usize>) -> Self {
+        let breaks = breaks(&self.spec.text);
+        self.record(BuilderCall::BreakOpportunities(breaks.clone()), |b| {
+            b.break_opportunities(|_| breaks)
+        })
+    }
+
+    fn default_attribute(self, attribute: impl Into<TextAttribute>) -> Self {
+        let attribute = attribute.into();
+        self.record(BuilderCall::DefaultAttribute(attribute.clone()), |b| {
+            b.default_attribute(attribute)
+        })
+    }
+
+    fn range_attribute(
+        self,
+        range: impl RangeBounds<usize>,
+        attribute: impl Into<TextAttribute>,
+    ) -> Self {
+        let range = util::resolve_range(range, self.spec.text.len());
+        let attribute = attribute.into();
+        let call = BuilderCall::RangeAttribute(range.clone(), attribute.clone());
+        self.record(call, |b| b.range_attribute(range, attribute))
+    }
+
+    fn build(self) -> Result<Self::Out, Error> {
+        Ok(RecordedTextLayout {
+            inner: self.inner.build()?,
+            spec: Arc::new(self.spec),
+        })
+    }
+}
+
+impl<L: TextLayout> TextLayout for RecordedTextLayout<L> {
+    #[allow(deprecated)]
+    fn width(&self) -> f64 {
+        self.inner.width()
+    }
+
+    fn size(&self) -> Size {
+        self.inner.size()
+    }
+
+    fn image_bounds(&self) -> Rect {
+        self.inner.image_bounds()
+    }
+
+    fn text(&self) -> &str {
+        self.inner.text()
+    }
+
+    fn update_width(&mut self, new_width: impl Into<Option<f64>>) -> Result<(), Error> {
+        let new_width = new_width.into();
+        self.inner.update_width(new_width)?;
+        Arc::make_mut(&mut self.spec).new_width = Some(new_width);
+        Ok(())
+    }
+
+    fn line_text(&self, line_number: usize) -> Option<&str> {
+        self.inner.line_text(line_number)
+    }
+
+    fn line_text_range(&self, line_number: usize) -> Option<Range<usize>> {
+        self.inner.line_text_range(line_number)
+    }
+
+    fn line_metric(&self, line_number: usize) -> Option<LineMetric> {
+        self.inner.line_metric(line_number)
+    }
+
+    fn line_count(&self) -> usize {
+        self.inner.line_count()
+    }
+
+    fn hit_test_point(&self, point: Point) -> HitTestPoint {
+        self.inner.hit_test_point(point)
+    }
+
+    fn hit_test_text_position(&self, idx: usize) -> Option<HitTestPosition> {
+        self.inner.hit_test_text_position(idx)
+    }
+
+    fn hit_test_text_position_with_affinity(
+        &self,
+        idx: usize,
+        affinity: Affinity,
+    ) -> Option<HitTestPosition> {
+        self.inner
+            .hit_test_text_position_with_affinity(idx, affinity)
+    }
+
+    fn next_grapheme_offset(&self, text_position: usize) -> Option<usize> {
+        self.inner.next_grapheme_offset(text_position)
+    }
+
+    fn prev_grapheme_offset(&self, text_position: usize) -> Option<usize> {
+        self.inner.prev_grapheme_offset(text_position)
+    }
+
+    fn next_word_offset(&self, text_position: usize) -> Option<usize> {
+        self.inner.next_word_offset(text_position)
+    }
+
+    fn prev_word_offset(&self, text_position: usize) -> Option<usize> {
+        self.inner.prev_word_offset(text_position)
+    }
+
+    fn rects_for_range(&self, range: impl RangeBounds<usize>) -> Vec<Rect> {
+        self.inner.rects_for_range(range)
+    }
+
+    fn glyph_runs(&self) -> &[GlyphRun] {
+        self.inner.glyph_runs()
+    }
+
+    fn outline(&self) -> BezPath {
+        self.inner.outline()
+    }
+}
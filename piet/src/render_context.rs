@@ -2,11 +2,12 @@
 
 use std::borrow::Cow;
 
-use kurbo::{Affine, Point, Rect, Shape};
+use kurbo::{Affine, BezPath, Insets, Point, Rect, Shape, Size};
 
 use crate::{
-    Color, Error, FixedGradient, FixedLinearGradient, FixedRadialGradient, LinearGradient,
-    RadialGradient, StrokeStyle, Text, TextLayout,
+    util, Color, Error, FixedGradient, FixedLinearGradient, FixedRadialGradient,
+    FixedSweepGradient, ImageBuf, LinearGradient, RadialGradient, SaveGuard, ShadowStyle,
+    StrokeStyle, Text, TextLayout,
 };
 
 /// A requested interpolation mode for drawing images.
@@ -16,11 +17,174 @@ pub enum InterpolationMode {
     NearestNeighbor,
     /// Use bilinear interpolation.
     Bilinear,
+    /// Use bicubic, or similar high quality, interpolation. This is slower,
+    /// but looks better, especially when scaling images down.
+    ///
+    /// Backends may approximate this with the best filter they have, and
+    /// fall back to bilinear interpolation if they have nothing better.
+    Bicubic,
+}
+
+/// How an image brush repeats its image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TileMode {
+    /// Repeat the image in both directions.
+    Repeat,
+    /// Repeat the image in both directions, mirroring every other copy so
+    /// that the edges of neighboring tiles match.
+    Reflect,
+}
+
+/// A rotation of an image, in quarter turns.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ImageRotation {
+    /// Don't rotate the image. This is the default.
+    #[default]
+    None,
+    /// Rotate the image a quarter turn clockwise.
+    Clockwise90,
+    /// Rotate the image a half turn.
+    Rotate180,
+    /// Rotate the image a quarter turn counterclockwise.
+    Clockwise270,
+}
+
+/// How [`draw_image_with`] orients an image in its destination rect.
+///
+/// The image is flipped first, and then rotated; when it's rotated by a
+/// quarter turn, its width is fitted to the height of the rect, and its
+/// height to the width. Together these cover every EXIF orientation.
+///
+/// [`draw_image_with`]: trait.RenderContext.html#method.draw_image_with
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ImageDrawOptions {
+    /// Mirror the image left to right.
+    pub flip_x: bool,
+    /// Mirror the image top to bottom, as for rows stored bottom-up.
+    pub flip_y: bool,
+    /// Rotate the image.
+    pub rotation: ImageRotation,
+}
+
+impl ImageDrawOptions {
+    /// Options that draw the image as it is.
+    pub fn new() -> ImageDrawOptions {
+        ImageDrawOptions::default()
+    }
+
+    /// Builder-style method to mirror the image left to right.
+    pub fn flip_x(mut self, flip_x: bool) -> Self {
+        self.flip_x = flip_x;
+        self
+    }
+
+    /// Builder-style method to mirror the image top to bottom.
+    pub fn flip_y(mut self, flip_y: bool) -> Self {
+        self.flip_y = flip_y;
+        self
+    }
+
+    /// Builder-style method to rotate the image.
+    pub fn rotation(mut self, rotation: ImageRotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// The rect to draw the unoriented image into, and the transform that
+    /// maps it onto `dst_rect` with these options.
+    fn layout(self, dst_rect: Rect) -> (Rect, Affine) {
+        let center = dst_rect.center();
+        let size = dst_rect.size();
+        // exact coefficients, so that the edges stay on the pixel grid
+        let (rotate, size) = match self.rotation {
+            ImageRotation::None => ([1.0, 0.0, 0.0, 1.0], size),
+            ImageRotation::Clockwise90 => {
+                ([0.0, 1.0, -1.0, 0.0], Size::new(size.height, size.width))
+            }
+            ImageRotation::Rotate180 => ([-1.0, 0.0, 0.0, -1.0], size),
+            ImageRotation::Clockwise270 => {
+                ([0.0, -1.0, 1.0, 0.0], Size::new(size.height, size.width))
+            }
+        };
+        let flip_x = if self.flip_x { -1.0 } else { 1.0 };
+        let flip_y = if self.flip_y { -1.0 } else { 1.0 };
+        let [a, b, c, d] = rotate;
+        let transform = Affine::translate(center.to_vec2())
+            * Affine::new([a, b, c, d, 0.0, 0.0])
+            * Affine::new([flip_x, 0.0, 0.0, flip_y, 0.0, 0.0])
+            * Affine::translate(-center.to_vec2());
+        (Rect::from_center_size(center, size), transform)
+    }
+}
+
+/// How newly drawn content is combined with what has already been drawn.
+///
+/// Not every backend supports every mode; setting an unsupported mode
+/// returns [`Error::NotSupported`]. Currently:
+///
+/// - cairo, CoreGraphics and the web canvas support every mode except that
+///   the web canvas does not support `Clear`;
+/// - SVG supports the modes that CSS has, with `mix-blend-mode`, and draws
+///   normally with `Clear`, `SourceIn` and `DestinationOut`, logging a
+///   warning; what is drawn with a mode is blended as a whole, rather than
+///   shape by shape;
+/// - Direct2D supports only `Normal`.
+///
+/// [`Error::NotSupported`]: enum.Error.html#variant.NotSupported
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlendMode {
+    /// Draw over existing content. This is the default.
+    Normal,
+    /// Multiply the colors; the result is at least as dark as either.
+    Multiply,
+    /// Multiply the inverses of the colors; the result is at least as light
+    /// as either.
+    Screen,
+    /// Multiply or screen, depending on the existing color.
+    Overlay,
+    /// Keep the darker of the two colors.
+    Darken,
+    /// Keep the lighter of the two colors.
+    Lighten,
+    /// Erase existing content, wherever something is drawn.
+    Clear,
+    /// Draw only where there is existing content, replacing it.
+    ///
+    /// Note that this erases existing content outside of what is drawn.
+    SourceIn,
+    /// Erase existing content by the opacity of what is drawn.
+    DestinationOut,
+}
+
+/// A mask for a layer started with [`save_layer_with_mask`].
+///
+/// When the layer is composited, the masked out parts of it are dropped,
+/// and parts that are only partially covered by the mask are faded.
+///
+/// [`save_layer_with_mask`]: trait.RenderContext.html#tymethod.save_layer_with_mask
+#[derive(Clone)]
+pub enum LayerMask<'a, I> {
+    /// Mask to a shape. Unlike [`clip`], the antialiased edges of the shape
+    /// are used as partial coverage.
+    ///
+    /// [`clip`]: trait.RenderContext.html#tymethod.clip
+    Shape(BezPath),
+    /// Mask by the alpha channel of an image, drawn into the given rect.
+    /// Everything outside the rect is masked out.
+    Image(&'a I, Rect),
+}
+
+impl<'a, I> LayerMask<'a, I> {
+    /// A mask covering `shape`.
+    pub fn shape(shape: impl Shape) -> Self {
+        LayerMask::Shape(shape.into_bez_path(1e-3))
+    }
 }
 
 /// The pixel format for bitmap images.
 #[derive(Clone, Copy, Debug, PartialEq)]
 #[non_exhaustive]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ImageFormat {
     /// 3 bytes per pixel, in RGB order.
     Rgb,
@@ -28,17 +192,41 @@ pub enum ImageFormat {
     RgbaSeparate,
     /// 4 bytes per pixel, in RGBA order, with premultiplied alpha.
     RgbaPremul,
+    /// 1 byte per pixel, a luminance value, with no alpha.
+    Grayscale,
+    /// 4 bytes per pixel, in BGRA order, with separate alpha.
+    ///
+    /// On the web canvas, which only takes RGBA, this costs an extra pass
+    /// over the pixels to reorder them.
+    Bgra,
+    /// 4 bytes per pixel, in BGRA order, with premultiplied alpha.
+    ///
+    /// This is the native format of cairo and Direct2D, so it is the
+    /// fastest format for them.
+    BgraPremul,
 }
 
 impl ImageFormat {
     pub fn bytes_per_pixel(self) -> usize {
         match self {
+            ImageFormat::Grayscale => 1,
             ImageFormat::Rgb => 3,
-            ImageFormat::RgbaPremul | ImageFormat::RgbaSeparate => 4,
+            ImageFormat::RgbaPremul
+            | ImageFormat::RgbaSeparate
+            | ImageFormat::Bgra
+            | ImageFormat::BgraPremul => 4,
         }
     }
 }
 
+/// A bitmap image, created with [`make_image`].
+///
+/// [`make_image`]: trait.RenderContext.html#tymethod.make_image
+pub trait Image {
+    /// The size of the image, in pixels.
+    fn size(&self) -> Size;
+}
+
 /// The main trait for rendering graphics.
 ///
 /// This trait provides an API for drawing 2D graphics. In basic usage, it
@@ -64,7 +252,7 @@ where
     type TextLayout: TextLayout;
 
     /// The associated type of an image.
-    type Image;
+    type Image: Image;
 
     /// Report an internal error.
     ///
@@ -85,6 +273,41 @@ where
     /// Create a new gradient brush.
     fn gradient(&mut self, gradient: impl Into<FixedGradient>) -> Result<Self::Brush, Error>;
 
+    /// Create a gradient brush, reusing the brush made for the same `key`
+    /// before, if the backend keeps a cache of them.
+    ///
+    /// `gradient` is only called if there is no brush for `key`, so a widget
+    /// that draws the same gradient on every frame doesn't need to build the
+    /// gradient, or the backend brush, each time. `key` must identify the
+    /// gradient; [`util::gradient_key`] makes one from the gradient itself.
+    ///
+    /// The cairo and Direct2D backends keep a bounded cache, which on
+    /// Direct2D is dropped along with its device context. Other backends
+    /// create a new brush every time.
+    ///
+    /// [`util::gradient_key`]: util/fn.gradient_key.html
+    fn make_brush_cached(
+        &mut self,
+        key: u64,
+        gradient: impl FnOnce() -> FixedGradient,
+    ) -> Result<Self::Brush, Error> {
+        let _ = key;
+        self.gradient(gradient())
+    }
+
+    /// Create a new brush that fills with tiled copies of an image.
+    ///
+    /// `transform` maps the image, with each pixel one unit square, to the
+    /// coordinate space of the shapes drawn with the brush. As with other
+    /// brushes, the current transform applies when drawing, so the tiles
+    /// follow it without seams.
+    fn make_image_brush(
+        &mut self,
+        image: &Self::Image,
+        tile: TileMode,
+        transform: Affine,
+    ) -> Result<Self::Brush, Error>;
+
     /// Clear the canvas with the given color.
     ///
     /// Note: only opaque colors are meaningful.
@@ -114,6 +337,12 @@ where
     /// are clipped by the shape.
     fn clip(&mut self, shape: impl Shape);
 
+    /// Clip to a shape, using the even-odd fill rule.
+    ///
+    /// This is like [`clip`](#tymethod.clip), but the holes of shapes
+    /// such as rings are left out of the clip.
+    fn clip_even_odd(&mut self, shape: impl Shape);
+
     fn text(&mut self) -> &mut Self::Text;
 
     /// Draw a text layout.
@@ -130,8 +359,11 @@ where
     /// Prefer [`with_save`](#method.with_save) if possible, as that statically
     /// enforces balance of save/restore pairs.
     ///
-    /// The context state currently consists of a clip region and an affine
-    /// transform, but is expected to grow in the near future.
+    /// The context state currently consists of a clip region, an affine
+    /// transform and a [`BlendMode`], but is expected to grow in the near
+    /// future.
+    ///
+    /// [`BlendMode`]: enum.BlendMode.html
     fn save(&mut self) -> Result<(), Error>;
 
     /// Restore the context state.
@@ -140,6 +372,29 @@ where
     /// that method for details.
     fn restore(&mut self) -> Result<(), Error>;
 
+    /// Save the context state, and start drawing into a layer.
+    ///
+    /// Everything drawn until the matching [`restore`](#method.restore) is
+    /// drawn into an offscreen group, which `restore` then composites onto
+    /// the existing content with `opacity` (and the current blend mode).
+    /// Unlike drawing each shape with a translucent brush, this fades a
+    /// group of shapes as a whole, without overlaps showing through.
+    ///
+    /// Layers can be nested. [`finish`](#method.finish) returns
+    /// [`Error::StackUnbalance`] if a layer is still open.
+    ///
+    /// Prefer [`with_layer`](#method.with_layer) if possible.
+    ///
+    /// [`Error::StackUnbalance`]: enum.Error.html#variant.StackUnbalance
+    fn save_layer(&mut self, opacity: f64) -> Result<(), Error>;
+
+    /// Save the context state, and start drawing into a masked layer.
+    ///
+    /// This is like [`save_layer`](#method.save_layer), but the layer is
+    /// composited through `mask` instead of with a uniform opacity. The
+    /// mask is positioned by the current transform, at the time of this call.
+    fn save_layer_with_mask(&mut self, mask: LayerMask<Self::Image>) -> Result<(), Error>;
+
     /// Do graphics operations with the context state saved and then restored.
     ///
     /// Equivalent to [`save`](#method.save), calling `f`, then
@@ -150,6 +405,119 @@ where
         f(self).and(self.restore())
     }
 
+    /// Save the context state, and restore it when the returned guard is
+    /// dropped.
+    ///
+    /// The guard derefs to the render context. This is an alternative to
+    /// [`with_save`](#method.with_save) that works with early returns and
+    /// `?`; if saving fails, the error is logged and nothing is restored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use piet::{Color, Error, RenderContext};
+    /// use piet::kurbo::{Affine, Rect};
+    ///
+    /// fn draw_scaled(rc: &mut impl RenderContext) -> Result<(), Error> {
+    ///     let mut rc = rc.save_guard();
+    ///     rc.transform(Affine::scale(2.0));
+    ///     rc.fill(Rect::new(0.0, 0.0, 10.0, 10.0), &Color::WHITE);
+    ///     rc.status()
+    /// }
+    /// # draw_scaled(&mut piet::NullRenderContext::new()).unwrap();
+    /// ```
+    fn save_guard(&mut self) -> SaveGuard<'_, Self> {
+        SaveGuard::new(self)
+    }
+
+    /// Do graphics operations in a layer with the given opacity.
+    ///
+    /// Equivalent to [`save_layer`](#method.save_layer), calling `f`, then
+    /// [`restore`](#method.restore).
+    fn with_layer(
+        &mut self,
+        opacity: f64,
+        f: impl FnOnce(&mut Self) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.save_layer(opacity)?;
+        f(self).and(self.restore())
+    }
+
+    /// Set the [`BlendMode`] used for subsequent drawing.
+    ///
+    /// This is part of the context state, and is reset by
+    /// [`restore`](#method.restore). Returns [`Error::NotSupported`] if the
+    /// backend cannot draw with this mode.
+    ///
+    /// [`BlendMode`]: enum.BlendMode.html
+    /// [`Error::NotSupported`]: enum.Error.html#variant.NotSupported
+    fn set_blend_mode(&mut self, mode: BlendMode) -> Result<(), Error>;
+
+    /// Do graphics operations with the given [`BlendMode`].
+    ///
+    /// The context state is saved before setting the blend mode, and
+    /// restored afterwards; see [`with_save`](#method.with_save).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use piet::{BlendMode, Color, RenderContext};
+    /// use piet::kurbo::Circle;
+    ///
+    /// # let mut render_ctx = piet::NullRenderContext::new();
+    /// render_ctx.fill(Circle::new((50.0, 50.0), 40.0), &Color::WHITE);
+    /// render_ctx.with_blend_mode(BlendMode::DestinationOut, |rc| {
+    ///     // punch a hole in the circle
+    ///     rc.fill(Circle::new((50.0, 50.0), 20.0), &Color::BLACK);
+    ///     Ok(())
+    /// }).unwrap();
+    /// ```
+    ///
+    /// [`BlendMode`]: enum.BlendMode.html
+    fn with_blend_mode(
+        &mut self,
+        mode: BlendMode,
+        f: impl FnOnce(&mut Self) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.with_save(|rc| {
+            rc.set_blend_mode(mode)?;
+            f(rc)
+        })
+    }
+
+    /// Multiply the opacity of everything drawn until the matching
+    /// [`pop_opacity`](#method.pop_opacity) by `alpha`.
+    ///
+    /// Opacities are kept on a stack; nested calls multiply together. Each
+    /// shape, text layout and image is faded on its own, as if its brush
+    /// were translucent, so shapes that overlap show through each other;
+    /// use [`save_layer`](#method.save_layer) to fade a group of shapes as
+    /// a whole. The stack is separate from the context state, and isn't
+    /// affected by [`save`](#method.save) and [`restore`](#method.restore).
+    /// [`clear`](#tymethod.clear) ignores it.
+    ///
+    /// `alpha` is clamped to the range `0.0..=1.0`.
+    fn push_opacity(&mut self, alpha: f64);
+
+    /// Pop an opacity pushed by [`push_opacity`](#method.push_opacity).
+    ///
+    /// Returns [`Error::StackUnbalance`] if there is nothing to pop.
+    ///
+    /// [`Error::StackUnbalance`]: enum.Error.html#variant.StackUnbalance
+    fn pop_opacity(&mut self) -> Result<(), Error>;
+
+    /// Do graphics operations with the opacity multiplied by `alpha`; see
+    /// [`push_opacity`](#method.push_opacity).
+    fn with_opacity(
+        &mut self,
+        alpha: f64,
+        f: impl FnOnce(&mut Self) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.push_opacity(alpha);
+        // Always try to pop the opacity, even if `f` errored.
+        f(self).and(self.pop_opacity())
+    }
+
     /// Finish any pending operations.
     ///
     /// This will generally be called by a shell after all user drawing
@@ -172,6 +540,48 @@ where
         format: ImageFormat,
     ) -> Result<Self::Image, Error>;
 
+    /// Create a new image from a pixel buffer with padded rows.
+    ///
+    /// Each row of `buf` starts `stride` bytes after the previous one. The
+    /// `stride` must be at least `width * format.bytes_per_pixel()`, and
+    /// `buf` must hold `height` rows; otherwise, this returns
+    /// [`Error::InvalidInput`]. The last row doesn't need to be padded.
+    ///
+    /// The default implementation copies the rows into a packed buffer and
+    /// calls [`make_image`].
+    ///
+    /// [`Error::InvalidInput`]: enum.Error.html#variant.InvalidInput
+    /// [`make_image`]: #tymethod.make_image
+    fn make_image_with_stride(
+        &mut self,
+        width: usize,
+        height: usize,
+        stride: usize,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<Self::Image, Error> {
+        let buf = util::pack_image_rows(width, height, stride, buf, format)?;
+        self.make_image(width, height, &buf, format)
+    }
+
+    /// Create an image from the pixels currently rendered in `src_rect`.
+    ///
+    /// The `src_rect` is in the current coordinate space, and the image
+    /// covers the device pixels under it, so it may be larger than
+    /// `src_rect` when the content is scaled. The area is clipped to the
+    /// surface; if nothing is left, this returns [`Error::InvalidInput`].
+    ///
+    /// [`Error::InvalidInput`]: enum.Error.html#variant.InvalidInput
+    fn capture_image_area(&mut self, src_rect: impl Into<Rect>) -> Result<Self::Image, Error>;
+
+    /// Read the pixels of an image back into CPU memory.
+    ///
+    /// The pixels are converted to `format`. Reading back an image in the
+    /// format it was created with returns the same pixels, except that
+    /// backends may not keep exact values for separate alpha.
+    fn to_image_buf(&mut self, image: &Self::Image, format: ImageFormat)
+        -> Result<ImageBuf, Error>;
+
     /// Draw an image.
     ///
     /// The `image` is scaled to the provided `dst_rect`.
@@ -195,14 +605,178 @@ where
         interp: InterpolationMode,
     );
 
+    /// Draw an image, flipped or rotated as given by `options`.
+    ///
+    /// This draws with [`draw_image`] under a transform, clipped to
+    /// `dst_rect`, so that bottom-up rows, such as from video frames and
+    /// OpenGL, and EXIF orientations don't need to be reordered first.
+    ///
+    /// [`draw_image`]: #tymethod.draw_image
+    fn draw_image_with(
+        &mut self,
+        image: &Self::Image,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+        options: ImageDrawOptions,
+    ) {
+        let dst_rect = dst_rect.into();
+        let (rect, transform) = options.layout(dst_rect);
+        let mut rc = self.save_guard();
+        rc.clip(dst_rect);
+        rc.transform(transform);
+        rc.draw_image(image, rect, interp);
+    }
+
+    /// Draw a specified area of an image, flipped or rotated as given by
+    /// `options`.
+    ///
+    /// See [`draw_image_with`](#method.draw_image_with).
+    fn draw_image_area_with(
+        &mut self,
+        image: &Self::Image,
+        src_rect: impl Into<Rect>,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+        options: ImageDrawOptions,
+    ) {
+        let dst_rect = dst_rect.into();
+        let (rect, transform) = options.layout(dst_rect);
+        let mut rc = self.save_guard();
+        rc.clip(dst_rect);
+        rc.transform(transform);
+        rc.draw_image_area(image, src_rect, rect, interp);
+    }
+
+    /// Draw a nine-slice image, for scalable borders and backgrounds.
+    ///
+    /// The `insets` divide `image` into a grid of nine areas. The corners
+    /// are drawn unscaled at the corners of `dst_rect`, the edges are
+    /// stretched along one axis and the center is stretched to fill the
+    /// rest. If `dst_rect` is too small to fit the corners, they are
+    /// scaled down to fit.
+    fn draw_image_nine(
+        &mut self,
+        image: &Self::Image,
+        insets: Insets,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        let dst_rect = dst_rect.into();
+        if insets == Insets::ZERO {
+            self.draw_image(image, dst_rect, interp);
+            return;
+        }
+        let src_rect = image.size().to_rect();
+        let src_cols = nine_slice_edges(src_rect.x0, src_rect.x1, insets.x0, insets.x1);
+        let src_rows = nine_slice_edges(src_rect.y0, src_rect.y1, insets.y0, insets.y1);
+        let dst_rect = dst_rect.abs();
+        // the dst insets are the clamped source insets, so the corners keep
+        // the same proportions when scaled down
+        let dst_cols = nine_slice_edges(
+            dst_rect.x0,
+            dst_rect.x1,
+            src_cols[1] - src_cols[0],
+            src_cols[3] - src_cols[2],
+        );
+        let dst_rows = nine_slice_edges(
+            dst_rect.y0,
+            dst_rect.y1,
+            src_rows[1] - src_rows[0],
+            src_rows[3] - src_rows[2],
+        );
+        for row in 0..3 {
+            for col in 0..3 {
+                let src = Rect::new(
+                    src_cols[col],
+                    src_rows[row],
+                    src_cols[col + 1],
+                    src_rows[row + 1],
+                );
+                let dst = Rect::new(
+                    dst_cols[col],
+                    dst_rows[row],
+                    dst_cols[col + 1],
+                    dst_rows[row + 1],
+                );
+                if src.area() > 0.0 && dst.area() > 0.0 {
+                    self.draw_image_area(image, src, dst, interp);
+                }
+            }
+        }
+    }
+
     /// Draw a rectangle with Gaussian blur.
     ///
     /// The blur radius is sometimes referred to as the "standard deviation" of
     /// the blur.
-    fn blurred_rect(&mut self, rect: Rect, blur_radius: f64, brush: &impl IntoBrush<Self>);
+    ///
+    /// This is equivalent to [`blurred_shape`](#method.blurred_shape) with
+    /// the rect.
+    fn blurred_rect(&mut self, rect: Rect, blur_radius: f64, brush: &impl IntoBrush<Self>) {
+        self.blurred_shape(rect, blur_radius, brush)
+    }
+
+    /// Draw a shape with Gaussian blur, for instance as a drop shadow.
+    ///
+    /// The default implementation rasterizes and blurs the shape on the CPU,
+    /// with [`util::compute_blurred_shape`], and draws the result as the mask
+    /// of a layer filled with `brush`. Backends may override it to blur
+    /// natively.
+    ///
+    /// [`util::compute_blurred_shape`]: util/fn.compute_blurred_shape.html
+    fn blurred_shape(&mut self, shape: impl Shape, blur_radius: f64, brush: &impl IntoBrush<Self>) {
+        let size = util::size_for_blurred_shape(&shape, blur_radius);
+        let (width, height) = (size.width as usize, size.height as usize);
+        let mut coverage = vec![0; width * height];
+        let rect = util::compute_blurred_shape(&shape, blur_radius, width, &mut coverage);
+        let mut buf = Vec::with_capacity(width * height * 4);
+        for &a in &coverage {
+            buf.extend_from_slice(&[0, 0, 0, a]);
+        }
+        // there is nowhere to report errors; if the backend can't make images
+        // or masked layers, nothing is drawn.
+        let _ = self
+            .make_image(width, height, &buf, ImageFormat::RgbaSeparate)
+            .and_then(|image| {
+                self.save_layer_with_mask(LayerMask::Image(&image, rect))?;
+                self.fill(rect, brush);
+                self.restore()
+            });
+    }
+
+    /// Fill a shape with a drop shadow beneath it.
+    ///
+    /// The shadow is drawn first, in full, and isn't clipped to the outside
+    /// of the shape, so it shows through a translucent fill. Its offset and
+    /// blur are in user space, and scale with the current transform.
+    ///
+    /// The default implementation draws the shadow with
+    /// [`blurred_shape`](#method.blurred_shape), and then fills the shape.
+    /// Backends with native shadows may use them instead.
+    fn fill_with_shadow(
+        &mut self,
+        shape: impl Shape,
+        brush: &impl IntoBrush<Self>,
+        shadow: &ShadowStyle,
+    ) {
+        if self.save().is_ok() {
+            self.transform(Affine::translate(shadow.offset));
+            let color = self.solid_brush(shadow.color.clone());
+            self.blurred_shape(&shape, shadow.blur, &color);
+            let _ = self.restore();
+        }
+        self.fill(shape, brush);
+    }
 
     /// Returns the transformations currently applied to the context.
     fn current_transform(&self) -> Affine;
+
+    /// The bounds of the current clip, in the current coordinate space.
+    ///
+    /// This is a conservative bounding box of the intersection of all
+    /// clips in effect, or `None` if nothing is clipped. Drawing outside of
+    /// it has no effect.
+    fn current_clip_bounds(&self) -> Option<Rect>;
 }
 
 /// A trait for various types that can be used as brushes. These include
@@ -306,3 +880,26 @@ impl From<FixedRadialGradient> for PaintBrush {
         PaintBrush::Fixed(src.into())
     }
 }
+
+impl From<FixedSweepGradient> for PaintBrush {
+    fn from(src: FixedSweepGradient) -> PaintBrush {
+        PaintBrush::Fixed(src.into())
+    }
+}
+
+/// The edges of the three slices of the span from `start` to `end`, given
+/// the sizes of the first and last slices.
+///
+/// If they don't fit in the span, the first and last slices are scaled down
+/// by the same factor, and the middle slice is empty.
+fn nine_slice_edges(start: f64, end: f64, first: f64, last: f64) -> [f64; 4] {
+    let span = end - start;
+    let (first, last) = (first.max(0.0), last.max(0.0));
+    let total = first + last;
+    let scale = if total > span && total > 0.0 {
+        span / total
+    } else {
+        1.0
+    };
+    [start, start + first * scale, end - last * scale, end]
+}
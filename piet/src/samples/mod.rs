@@ -22,11 +22,33 @@ mod picture_9;
 mod picture_10;
 mod picture_11;
 mod picture_12;
+mod picture_13;
+mod picture_14;
+mod picture_15;
+mod picture_16;
+mod picture_17;
+mod picture_18;
+mod picture_19;
+mod picture_20;
+mod picture_21;
+mod picture_22;
+mod picture_23;
+mod picture_24;
+mod picture_25;
+mod picture_26;
+mod picture_27;
+mod picture_28;
+mod picture_29;
+mod picture_30;
+mod picture_31;
+mod picture_32;
+mod picture_33;
+mod picture_34;
 
 type BoxErr = Box<dyn std::error::Error>;
 
 /// The total number of samples in this module.
-pub const SAMPLE_COUNT: usize = 13;
+pub const SAMPLE_COUNT: usize = 35;
 
 /// file we save an os fingerprint to
 pub const GENERATED_BY: &str = "GENERATED_BY";
@@ -47,6 +69,28 @@ pub fn get<R: RenderContext>(number: usize) -> SamplePicture<R> {
         10 => SamplePicture::new(picture_10::SIZE, picture_10::draw),
         11 => SamplePicture::new(picture_11::SIZE, picture_11::draw),
         12 => SamplePicture::new(picture_12::SIZE, picture_12::draw),
+        13 => SamplePicture::new(picture_13::SIZE, picture_13::draw),
+        14 => SamplePicture::new(picture_14::SIZE, picture_14::draw),
+        15 => SamplePicture::new(picture_15::SIZE, picture_15::draw),
+        16 => SamplePicture::new(picture_16::SIZE, picture_16::draw),
+        17 => SamplePicture::new(picture_17::SIZE, picture_17::draw),
+        18 => SamplePicture::new(picture_18::SIZE, picture_18::draw),
+        19 => SamplePicture::new(picture_19::SIZE, picture_19::draw),
+        20 => SamplePicture::new(picture_20::SIZE, picture_20::draw),
+        21 => SamplePicture::new(picture_21::SIZE, picture_21::draw),
+        22 => SamplePicture::new(picture_22::SIZE, picture_22::draw),
+        23 => SamplePicture::new(picture_23::SIZE, picture_23::draw),
+        24 => SamplePicture::new(picture_24::SIZE, picture_24::draw),
+        25 => SamplePicture::new(picture_25::SIZE, picture_25::draw),
+        26 => SamplePicture::new(picture_26::SIZE, picture_26::draw),
+        27 => SamplePicture::new(picture_27::SIZE, picture_27::draw),
+        28 => SamplePicture::new(picture_28::SIZE, picture_28::draw),
+        29 => SamplePicture::new(picture_29::SIZE, picture_29::draw),
+        30 => SamplePicture::new(picture_30::SIZE, picture_30::draw),
+        31 => SamplePicture::new(picture_31::SIZE, picture_31::draw),
+        32 => SamplePicture::new(picture_32::SIZE, picture_32::draw),
+        33 => SamplePicture::new(picture_33::SIZE, picture_33::draw),
+        34 => SamplePicture::new(picture_34::SIZE, picture_34::draw),
         _ => panic!("No sample #{} exists", number),
     }
 }
@@ -44,11 +44,11 @@ pub fn draw(rc: &mut impl RenderContext) -> Result<(), Error> {
 
     rc.stroke(Line::new((80.0, 12.0), (80.0 + w, 12.0)), &RED_ALPHA, 1.0);
 
-    rc.with_save(|rc| {
+    {
+        let mut rc = rc.save_guard();
         rc.transform(Affine::rotate(0.1));
         rc.draw_text(&layout, (80.0, 10.0));
-        Ok(())
-    })?;
+    }
 
     rc.blurred_rect(Rect::new(155.0, 55.0, 185.0, 85.0), 5.0, &Color::BLACK);
 
@@ -0,0 +1,40 @@
+//! Mixed underline and strikethrough ranges, composed with foreground colors.
+
+use crate::kurbo::{Size, Vec2};
+use crate::{Color, Error, FontFamily, RenderContext, Text, TextAttribute, TextLayoutBuilder};
+
+pub const SIZE: Size = Size::new(480., 360.);
+
+static TEXT: &str = r#"-    let old_value = compute(input);
++    let new_value = compute(input)?;
+Some of this text is underlined, some of it is struck through, and some of it is both; the decorations should follow the color of the text they decorate, and wrap with it across lines."#;
+
+const RED: Color = Color::rgb8(0xc0, 0x20, 0x20);
+const GREEN: Color = Color::rgb8(0x20, 0x90, 0x20);
+const BLUE: Color = Color::rgb8(0x20, 0x40, 0xd0);
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(Color::WHITE);
+    let text = rc.text();
+    let layout = text
+        .new_text_layout(TEXT)
+        .max_width(400.0)
+        .font(FontFamily::SYSTEM_UI, 16.0)
+        .range_attribute(0..36, TextAttribute::ForegroundColor(RED))
+        .range_attribute(5..36, TextAttribute::Strikethrough(true))
+        .range_attribute(37..74, TextAttribute::ForegroundColor(GREEN))
+        .range_attribute(42..74, TextAttribute::Underline(true))
+        .range_attribute(96..106, TextAttribute::Underline(true))
+        .range_attribute(122..136, TextAttribute::Strikethrough(true))
+        .range_attribute(156..160, TextAttribute::Underline(true))
+        .range_attribute(156..160, TextAttribute::Strikethrough(true))
+        .range_attribute(162..201, TextAttribute::ForegroundColor(BLUE))
+        .range_attribute(162..237, TextAttribute::Strikethrough(true))
+        .range_attribute(185..195, TextAttribute::Strikethrough(false))
+        .build()?;
+
+    let text_pos = Vec2::new(16.0, 16.0);
+    rc.draw_text(&layout, text_pos.to_point());
+
+    Ok(())
+}
@@ -0,0 +1,35 @@
+//! Justified text, with guides at the edges of the layout's max width.
+
+use crate::kurbo::{Line, Size, Vec2};
+use crate::{Color, Error, FontFamily, RenderContext, Text, TextAlignment, TextLayoutBuilder};
+
+pub const SIZE: Size = Size::new(480., 360.);
+
+static TEXT: &str = "Justified text is stretched so that every line but the last in a paragraph fills the full width of the layout, with the extra space shared between the gaps between words.\nA short last line stays at the start.\nSo does a paragraph that fits on one line.";
+
+const WIDTH: f64 = 320.0;
+const GUIDE_COLOR: Color = Color::rgb8(0xd0, 0x80, 0x80);
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(Color::WHITE);
+    let text = rc.text();
+    let layout = text
+        .new_text_layout(TEXT)
+        .max_width(WIDTH)
+        .alignment(TextAlignment::Justified)
+        .font(FontFamily::SERIF, 16.0)
+        .build()?;
+
+    let text_pos = Vec2::new(80.0, 32.0);
+    let height = SIZE.height - 2.0 * text_pos.y;
+    for x in &[text_pos.x, text_pos.x + WIDTH] {
+        rc.stroke(
+            Line::new((*x, text_pos.y), (*x, text_pos.y + height)),
+            &GUIDE_COLOR,
+            1.0,
+        );
+    }
+    rc.draw_text(&layout, text_pos.to_point());
+
+    Ok(())
+}
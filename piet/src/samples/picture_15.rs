@@ -0,0 +1,34 @@
+//! Tab stops, used to line up a two-column table.
+
+use crate::kurbo::{Line, Size, Vec2};
+use crate::{Color, Error, FontFamily, RenderContext, TabStops, Text, TextLayoutBuilder};
+
+pub const SIZE: Size = Size::new(400., 200.);
+
+static TEXT: &str =
+    "Backend\tText\nCairo\tToy text\nCoreGraphics\tCoreText\nDirect2D\tDirectWrite\nWeb\tCanvas";
+
+const COLUMN_X: f64 = 140.0;
+const GUIDE_COLOR: Color = Color::rgb8(0xd0, 0x80, 0x80);
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(Color::WHITE);
+    let text = rc.text();
+    let layout = text
+        .new_text_layout(TEXT)
+        .tab_stops(TabStops::Explicit(vec![COLUMN_X]))
+        .font(FontFamily::SANS_SERIF, 16.0)
+        .build()?;
+
+    let text_pos = Vec2::new(40.0, 32.0);
+    let height = SIZE.height - 2.0 * text_pos.y;
+    let x = text_pos.x + COLUMN_X;
+    rc.stroke(
+        Line::new((x, text_pos.y), (x, text_pos.y + height)),
+        &GUIDE_COLOR,
+        1.0,
+    );
+    rc.draw_text(&layout, text_pos.to_point());
+
+    Ok(())
+}
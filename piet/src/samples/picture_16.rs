@@ -0,0 +1,60 @@
+//! Font features: a column of figures, set with proportional and then with
+//! tabular figures.
+
+use crate::kurbo::{Line, Size, Vec2};
+use crate::{
+    Color, Error, FontFamily, FontFeatures, RenderContext, Text, TextAlignment, TextAttribute,
+    TextLayoutBuilder,
+};
+
+pub const SIZE: Size = Size::new(400., 240.);
+
+static TEXT: &str = "1,111.11\n4,089.50\n17.71\n10,000.00\n861.19";
+
+const COLUMN_WIDTH: f64 = 120.0;
+const GUIDE_COLOR: Color = Color::rgb8(0xd0, 0x80, 0x80);
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(Color::WHITE);
+    let text = rc.text();
+
+    let columns = [
+        ("Proportional", FontFeatures::PROPORTIONAL_NUMS),
+        ("Tabular", FontFeatures::TABULAR_NUMS),
+    ];
+
+    let mut layouts = Vec::new();
+    for (title, features) in columns.iter() {
+        let title = text
+            .new_text_layout(*title)
+            .max_width(COLUMN_WIDTH)
+            .alignment(TextAlignment::End)
+            .font(FontFamily::SANS_SERIF, 14.0)
+            .default_attribute(TextAttribute::Italic(true))
+            .build()?;
+        let figures = text
+            .new_text_layout(TEXT)
+            .max_width(COLUMN_WIDTH)
+            .alignment(TextAlignment::End)
+            .font(FontFamily::SANS_SERIF, 18.0)
+            .default_attribute(features.clone())
+            .build()?;
+        layouts.push((title, figures));
+    }
+
+    let mut x = 60.0;
+    for (title, figures) in &layouts {
+        let text_pos = Vec2::new(x, 64.0);
+        rc.draw_text(title, (x, 32.0));
+        rc.draw_text(figures, text_pos.to_point());
+        let guide_x = x + COLUMN_WIDTH;
+        rc.stroke(
+            Line::new((guide_x, text_pos.y), (guide_x, SIZE.height - 32.0)),
+            &GUIDE_COLOR,
+            1.0,
+        );
+        x += COLUMN_WIDTH + 80.0;
+    }
+
+    Ok(())
+}
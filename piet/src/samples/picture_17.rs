@@ -0,0 +1,78 @@
+//! Text direction: mixed-direction text laid out left-to-right, right-to-left,
+//! and with the direction of its first strong character, with `Start` and
+//! `End` alignment.
+
+use crate::kurbo::{Rect, Size};
+use crate::{
+    Color, Error, FontFamily, RenderContext, Text, TextAlignment, TextAttribute, TextDirection,
+    TextLayout, TextLayoutBuilder,
+};
+
+pub const SIZE: Size = Size::new(480., 400.);
+
+static TEXTS: &[&str] = &[
+    "123 \u{5e9}\u{5dc}\u{5d5}\u{5dd}",
+    "\u{5e9}\u{5dc}\u{5d5}\u{5dd} piet, 2020",
+];
+
+const COLUMN_WIDTH: f64 = 180.0;
+const GUIDE_COLOR: Color = Color::rgb8(0xd0, 0x80, 0x80);
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(Color::WHITE);
+    let text = rc.text();
+
+    let directions = [
+        ("LeftToRight", TextDirection::LeftToRight),
+        ("RightToLeft", TextDirection::RightToLeft),
+        ("Auto", TextDirection::Auto),
+    ];
+    let alignments = [TextAlignment::Start, TextAlignment::End];
+
+    let mut labels = Vec::new();
+    let mut layouts = Vec::new();
+    for (name, direction) in directions.iter() {
+        let label = text
+            .new_text_layout(*name)
+            .font(FontFamily::SANS_SERIF, 12.0)
+            .default_attribute(TextAttribute::Italic(true))
+            .build()?;
+        labels.push(label);
+        for sample in TEXTS {
+            for alignment in alignments.iter() {
+                let layout = text
+                    .new_text_layout(*sample)
+                    .max_width(COLUMN_WIDTH)
+                    .alignment(*alignment)
+                    .text_direction(*direction)
+                    .font(FontFamily::SANS_SERIF, 16.0)
+                    .build()?;
+                layouts.push(layout);
+            }
+        }
+    }
+
+    let mut y = 20.0;
+    let mut layouts = layouts.iter();
+    for label in &labels {
+        rc.draw_text(label, (20.0, y));
+        y += label.size().height + 4.0;
+        for _ in TEXTS {
+            let mut x = 40.0;
+            let mut row_height: f64 = 0.0;
+            for _ in alignments.iter() {
+                let layout = layouts.next().unwrap();
+                let height = layout.size().height;
+                let guide = Rect::new(x, y, x + COLUMN_WIDTH, y + height);
+                rc.stroke(guide, &GUIDE_COLOR, 1.0);
+                rc.draw_text(layout, (x, y));
+                row_height = row_height.max(height);
+                x += COLUMN_WIDTH + 40.0;
+            }
+            y += row_height + 8.0;
+        }
+        y += 12.0;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,59 @@
+//! Text outlines: the glyph outlines of a layout, stroked, filled with a
+//! gradient, and stroked with a dash pattern over the text itself.
+
+use crate::kurbo::{Affine, Point, Size, Vec2};
+use crate::{
+    Color, Error, FontFamily, FontWeight, LinearGradient, RenderContext, StrokeStyle, Text,
+    TextAttribute, TextLayout, TextLayoutBuilder, UnitPoint,
+};
+
+pub const SIZE: Size = Size::new(400., 300.);
+
+const TEXT: &str = "Outlines";
+const STROKE_COLOR: Color = Color::rgb8(0x20, 0x40, 0x80);
+const TEXT_COLOR: Color = Color::rgb8(0xe0, 0xc0, 0x40);
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(Color::WHITE);
+    let layout = rc
+        .text()
+        .new_text_layout(TEXT)
+        .font(FontFamily::SERIF, 64.0)
+        .default_attribute(FontWeight::BOLD)
+        .default_attribute(TextAttribute::ForegroundColor(TEXT_COLOR))
+        .build()?;
+    let outline = layout.outline();
+
+    // a plain stroke
+    let offset = Vec2::new(20.0, 10.0);
+    rc.stroke(
+        Affine::translate(offset) * outline.clone(),
+        &STROKE_COLOR,
+        2.0,
+    );
+
+    // filled with a gradient, then stroked
+    let offset = Vec2::new(20.0, 100.0);
+    let path = Affine::translate(offset) * outline.clone();
+    let gradient = LinearGradient::new(
+        UnitPoint::TOP,
+        UnitPoint::BOTTOM,
+        (Color::rgb8(0x80, 0xd0, 0xff), Color::rgb8(0x20, 0x40, 0xa0)),
+    );
+    rc.fill(&path, &gradient);
+    rc.stroke(&path, &Color::BLACK, 1.0);
+
+    // the text itself, with a dashed outline on top
+    let origin = Point::new(20.0, 190.0);
+    rc.draw_text(&layout, origin);
+    let mut style = StrokeStyle::new();
+    style.set_dash(vec![4.0, 2.0], 0.0);
+    rc.stroke_styled(
+        Affine::translate(origin.to_vec2()) * outline,
+        &STROKE_COLOR,
+        1.5,
+        &style,
+    );
+
+    Ok(())
+}
@@ -0,0 +1,29 @@
+//! A hue wheel, drawn with a sweep gradient.
+
+use crate::kurbo::{Circle, Point, Size};
+use crate::{Color, ColorInterpolation, Error, FixedSweepGradient, GradientStops, RenderContext};
+
+pub const SIZE: Size = Size::new(200., 200.);
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(Color::WHITE);
+    let center = Point::new(100.0, 100.0);
+    let hues: &[Color] = &[
+        Color::rgb8(0xff, 0x00, 0x00),
+        Color::rgb8(0xff, 0xff, 0x00),
+        Color::rgb8(0x00, 0xff, 0x00),
+        Color::rgb8(0x00, 0xff, 0xff),
+        Color::rgb8(0x00, 0x00, 0xff),
+        Color::rgb8(0xff, 0x00, 0xff),
+        Color::rgb8(0xff, 0x00, 0x00),
+    ];
+    let gradient = rc.gradient(FixedSweepGradient {
+        center,
+        start_angle: 0.0,
+        stops: GradientStops::to_vec(hues),
+        interpolation: ColorInterpolation::Srgb,
+    })?;
+    rc.fill(Circle::new(center, 90.0), &gradient);
+    rc.fill(Circle::new(center, 30.0), &Color::WHITE);
+    Ok(())
+}
@@ -1,4 +1,7 @@
 //! A bunch of image test cases.
+//!
+//! Each row uses a different interpolation mode to scale the images up;
+//! on the right, a finely detailed image is scaled down with each mode.
 
 use crate::kurbo::{Rect, Size};
 use crate::{Color, Error, ImageFormat, InterpolationMode, RenderContext};
@@ -8,16 +11,21 @@ pub const SIZE: Size = Size::new(400., 200.);
 pub fn draw(rc: &mut impl RenderContext) -> Result<(), Error> {
     rc.clear(Color::WHITE);
 
-    let mut y = 5.0;
-    for &mode in &[
+    let modes = [
         InterpolationMode::NearestNeighbor,
         InterpolationMode::Bilinear,
-    ] {
+        InterpolationMode::Bicubic,
+    ];
+    let mut y = 5.0;
+    for &mode in &modes {
         let mut x = 5.0;
         for &format in &[
             ImageFormat::RgbaSeparate,
             ImageFormat::RgbaPremul,
             ImageFormat::Rgb,
+            ImageFormat::Grayscale,
+            ImageFormat::Bgra,
+            ImageFormat::BgraPremul,
         ] {
             let image_data = make_image_data(16, 16, format);
             let image = rc.make_image(16, 16, &image_data, format)?;
@@ -26,10 +34,37 @@ pub fn draw(rc: &mut impl RenderContext) -> Result<(), Error> {
         }
         y += 50.0;
     }
+
+    let rings = rc.make_image(128, 128, &make_rings_data(128), ImageFormat::Rgb)?;
+    let mut y = 5.0;
+    for &mode in &modes {
+        rc.draw_image(&rings, Rect::new(310.0, y, 350.0, y + 40.0), mode);
+        y += 50.0;
+    }
     Ok(())
 }
 
+/// Thin concentric rings, which alias badly when scaled down.
+fn make_rings_data(size: usize) -> Vec<u8> {
+    let mut result = Vec::with_capacity(size * size * 3);
+    let center = size as f64 / 2.0;
+    for y in 0..size {
+        for x in 0..size {
+            let r2 = (x as f64 - center).powi(2) + (y as f64 - center).powi(2);
+            // alternate black and white every two pixels of radius
+            let v = ((r2.sqrt() as usize >> 1) & 1) as u8 * 255;
+            result.extend_from_slice(&[v, v, v]);
+        }
+    }
+    result
+}
+
 fn make_image_data(width: usize, height: usize, format: ImageFormat) -> Vec<u8> {
+    fn premul(x: u8, a: u8) -> u8 {
+        let y = (x as u16) * (a as u16);
+        ((y + (y >> 8) + 0x80) >> 8) as u8
+    }
+
     let bytes_per_pixel = format.bytes_per_pixel();
     let mut result = vec![0; width * height * bytes_per_pixel];
     for y in 0..height {
@@ -51,10 +86,6 @@ fn make_image_data(width: usize, height: usize, format: ImageFormat) -> Vec<u8>
                     result[ix + 3] = a;
                 }
                 ImageFormat::RgbaPremul => {
-                    fn premul(x: u8, a: u8) -> u8 {
-                        let y = (x as u16) * (a as u16);
-                        ((y + (y >> 8) + 0x80) >> 8) as u8
-                    }
                     result[ix + 0] = premul(r, a);
                     result[ix + 1] = premul(g, a);
                     result[ix + 2] = premul(b, a);
@@ -65,6 +96,21 @@ fn make_image_data(width: usize, height: usize, format: ImageFormat) -> Vec<u8>
                     result[ix + 1] = g;
                     result[ix + 2] = b;
                 }
+                ImageFormat::Bgra => {
+                    result[ix + 0] = b;
+                    result[ix + 1] = g;
+                    result[ix + 2] = r;
+                    result[ix + 3] = a;
+                }
+                ImageFormat::BgraPremul => {
+                    result[ix + 0] = premul(b, a);
+                    result[ix + 1] = premul(g, a);
+                    result[ix + 2] = premul(r, a);
+                    result[ix + 3] = a;
+                }
+                ImageFormat::Grayscale => {
+                    result[ix] = ((r as u16 + g as u16 + b as u16) / 3) as u8;
+                }
             }
         }
     }
@@ -0,0 +1,50 @@
+//! Gradient extend modes: pad, repeat and reflect, for linear (top) and
+//! radial (bottom) gradients.
+
+use crate::kurbo::{Rect, Size, Vec2};
+use crate::{
+    Color, ColorInterpolation, Error, FixedLinearGradient, FixedRadialGradient, GradientExtend,
+    GradientStops, RenderContext,
+};
+
+pub const SIZE: Size = Size::new(340., 230.);
+
+const CELL: f64 = 100.;
+const GAP: f64 = 10.;
+const COLORS: (Color, Color) = (Color::rgb8(0x20, 0x40, 0xa0), Color::rgb8(0xf0, 0xe0, 0x80));
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(Color::WHITE);
+    let modes = [
+        GradientExtend::Pad,
+        GradientExtend::Repeat,
+        GradientExtend::Reflect,
+    ];
+    for (i, &extend) in modes.iter().enumerate() {
+        let x = GAP + i as f64 * (CELL + GAP);
+
+        let rect = Rect::from_origin_size((x, GAP), (CELL, CELL));
+        let center = rect.center();
+        let linear = rc.gradient(FixedLinearGradient {
+            start: center - Vec2::new(10., 10.),
+            end: center + Vec2::new(10., 10.),
+            stops: GradientStops::to_vec(COLORS),
+            extend,
+            interpolation: ColorInterpolation::Srgb,
+        })?;
+        rc.fill(rect, &linear);
+
+        let rect = Rect::from_origin_size((x, CELL + 2. * GAP), (CELL, CELL));
+        let center = rect.center();
+        let radial = rc.gradient(FixedRadialGradient {
+            center,
+            origin_offset: Vec2::new(4., -4.),
+            radii: Vec2::new(15., 15.),
+            stops: GradientStops::to_vec(COLORS),
+            extend,
+            interpolation: ColorInterpolation::Srgb,
+        })?;
+        rc.fill(rect, &radial);
+    }
+    Ok(())
+}
@@ -0,0 +1,53 @@
+//! Blend modes: in each cell, a blue circle is drawn with a different
+//! blend mode over a red and a yellow circle.
+//!
+//! Cells for modes the backend doesn't support are left empty.
+
+use crate::kurbo::{Circle, Point, Rect, Size, Vec2};
+use crate::{BlendMode, Color, Error, RenderContext};
+
+pub const SIZE: Size = Size::new(340., 340.);
+
+const CELL: f64 = 100.;
+const GAP: f64 = 10.;
+const MODES: &[BlendMode] = &[
+    BlendMode::Normal,
+    BlendMode::Multiply,
+    BlendMode::Screen,
+    BlendMode::Overlay,
+    BlendMode::Darken,
+    BlendMode::Lighten,
+    BlendMode::Clear,
+    BlendMode::SourceIn,
+    BlendMode::DestinationOut,
+];
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(Color::WHITE);
+    for (i, &mode) in MODES.iter().enumerate() {
+        let origin = Point::new(
+            GAP + (i % 3) as f64 * (CELL + GAP),
+            GAP + (i / 3) as f64 * (CELL + GAP),
+        );
+        let cell = Rect::from_origin_size(origin, (CELL, CELL));
+        let center = cell.center();
+        let mut rc = rc.save_guard();
+        // keep modes like SourceIn from affecting other cells
+        rc.clip(cell);
+        rc.fill(cell, &Color::grey8(0xe0));
+        let red = Circle::new(center + Vec2::new(-15., -10.), 30.);
+        rc.fill(red, &Color::rgb8(0xe0, 0x20, 0x20));
+        let yellow = Circle::new(center + Vec2::new(15., -10.), 30.);
+        rc.fill(yellow, &Color::rgb8(0xf0, 0xd0, 0x20));
+        let blue = Circle::new(center + Vec2::new(0., 15.), 30.);
+        let result = rc.with_blend_mode(mode, |rc| {
+            rc.fill(blue, &Color::rgba8(0x20, 0x40, 0xe0, 0xc0));
+            Ok(())
+        });
+        match result {
+            Err(Error::NotSupported) => rc.fill(cell, &Color::WHITE),
+            other => other?,
+        }
+    }
+    Ok(())
+}
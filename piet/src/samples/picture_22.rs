@@ -0,0 +1,38 @@
+//! Group opacity: on the left, overlapping circles are each drawn with
+//! a translucent brush; on the right, the same circles are drawn opaque
+//! in a layer with the same opacity, so the overlaps don't show.
+//!
+//! If the backend doesn't support layers, the right side is left empty.
+
+use crate::kurbo::{Circle, Point, Size, Vec2};
+use crate::{Color, Error, RenderContext};
+
+pub const SIZE: Size = Size::new(400., 200.);
+
+const OPACITY: f64 = 0.5;
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(Color::WHITE);
+    let translucent = Color::rgb8(0x20, 0x60, 0xc0).with_alpha(OPACITY);
+    for circle in circles(Point::new(100., 100.)) {
+        rc.fill(circle, &translucent);
+    }
+
+    let opaque = Color::rgb8(0x20, 0x60, 0xc0);
+    let result = rc.with_layer(OPACITY, |rc| {
+        for circle in circles(Point::new(300., 100.)) {
+            rc.fill(circle, &opaque);
+        }
+        Ok(())
+    });
+    match result {
+        Err(Error::NotSupported) => Ok(()),
+        other => other,
+    }
+}
+
+fn circles(center: Point) -> impl Iterator<Item = Circle> {
+    [(-25., -20.), (25., -20.), (0., 25.)]
+        .iter()
+        .map(move |&(x, y)| Circle::new(center + Vec2::new(x, y), 45.))
+}
@@ -0,0 +1,58 @@
+//! Masked layers: on the left, a gradient is masked by an image with a
+//! radial falloff in alpha, for a soft vignette; on the right, the same
+//! gradient is masked by a circle.
+//!
+//! If the backend doesn't support layers, the cells are left empty.
+
+use crate::kurbo::{Circle, Rect, Size};
+use crate::{Color, Error, ImageFormat, LayerMask, LinearGradient, RenderContext, UnitPoint};
+
+pub const SIZE: Size = Size::new(400., 200.);
+
+const MASK_SIZE: usize = 64;
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(Color::WHITE);
+    let gradient = LinearGradient::new(
+        UnitPoint::TOP_LEFT,
+        UnitPoint::BOTTOM_RIGHT,
+        (Color::rgb8(0xf0, 0x80, 0x20), Color::rgb8(0x40, 0x20, 0xa0)),
+    );
+
+    let image = rc.make_image(
+        MASK_SIZE,
+        MASK_SIZE,
+        &radial_falloff(),
+        ImageFormat::RgbaSeparate,
+    )?;
+    let left = Rect::new(20., 20., 180., 180.);
+    let result = rc.save_layer_with_mask(LayerMask::Image(&image, left));
+    if let Err(Error::NotSupported) = result {
+        return Ok(());
+    }
+    result?;
+    rc.fill(left, &gradient);
+    rc.restore()?;
+
+    let right = Rect::new(220., 20., 380., 180.);
+    rc.save_layer_with_mask(LayerMask::shape(Circle::new(right.center(), 70.)))?;
+    rc.fill(right, &gradient);
+    rc.restore()
+}
+
+/// Black, with an alpha of 1 at the center falling off to 0 at the edges.
+fn radial_falloff() -> Vec<u8> {
+    let mut buf = Vec::with_capacity(MASK_SIZE * MASK_SIZE * 4);
+    let center = (MASK_SIZE as f64 - 1.) / 2.;
+    for y in 0..MASK_SIZE {
+        for x in 0..MASK_SIZE {
+            let dx = (x as f64 - center) / center;
+            let dy = (y as f64 - center) / center;
+            let t = (1. - (dx * dx + dy * dy).sqrt()).max(0.);
+            // smoothstep, for a softer edge
+            let alpha = t * t * (3. - 2. * t);
+            buf.extend_from_slice(&[0, 0, 0, (alpha * 255.).round() as u8]);
+        }
+    }
+    buf
+}
@@ -0,0 +1,45 @@
+//! Image brushes: a small tile, repeated on the left and reflected on the
+//! right, fills shapes under a rotation, and strokes an outline.
+
+use crate::kurbo::{Affine, Circle, Rect, Size, Vec2};
+use crate::{Color, Error, ImageFormat, RenderContext, TileMode};
+
+pub const SIZE: Size = Size::new(400., 200.);
+
+const TILE: usize = 16;
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(Color::WHITE);
+    let image = rc.make_image(TILE, TILE, &make_tile(), ImageFormat::RgbaSeparate)?;
+    // each tile pixel is two units across
+    let scale = Affine::scale(2.0);
+    let repeat = rc.make_image_brush(&image, TileMode::Repeat, scale)?;
+    let reflect = rc.make_image_brush(&image, TileMode::Reflect, scale)?;
+
+    for (x, brush) in [(100., &repeat), (300., &reflect)].iter().copied() {
+        {
+            let mut rc = rc.save_guard();
+            rc.transform(Affine::translate(Vec2::new(x, 100.)) * Affine::rotate(0.3));
+            rc.fill(Rect::new(-60., -60., 60., 60.), brush);
+        }
+        rc.stroke(Circle::new((x, 100.), 85.), brush, 10.);
+    }
+    Ok(())
+}
+
+/// A tile that is brighter toward its bottom right, with a dark edge along
+/// its top and left, so that mirroring is visible.
+fn make_tile() -> Vec<u8> {
+    let mut buf = Vec::with_capacity(TILE * TILE * 4);
+    for y in 0..TILE {
+        for x in 0..TILE {
+            let pixel = if x < 2 || y < 2 {
+                [0x20, 0x20, 0x40, 0xff]
+            } else {
+                [(x * 16) as u8, 0x80, (y * 16) as u8, 0xff]
+            };
+            buf.extend_from_slice(&pixel);
+        }
+    }
+    buf
+}
@@ -0,0 +1,49 @@
+//! Drop shadows for non-rectangular shapes: a rounded rect, a circle, and a
+//! star, each drawn over a blurred, offset copy of itself.
+
+use crate::kurbo::{Affine, BezPath, Circle, Point, RoundedRect, Shape, Size, Vec2};
+use crate::{Color, Error, RenderContext};
+
+pub const SIZE: Size = Size::new(400., 160.);
+
+const SHADOW_OFFSET: Vec2 = Vec2::new(6., 8.);
+const SHADOW_COLOR: Color = Color::rgba8(0, 0, 0, 0x90);
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(Color::WHITE);
+
+    let rounded = RoundedRect::new(20., 30., 120., 130., 16.);
+    draw_with_shadow(rc, rounded, Color::rgb8(0x40, 0x80, 0xe0));
+
+    let circle = Circle::new((200., 80.), 50.);
+    draw_with_shadow(rc, circle, Color::rgb8(0xe0, 0x60, 0x40));
+
+    let star = star(Point::new(320., 80.), 55., 22., 5);
+    draw_with_shadow(rc, star, Color::rgb8(0xf0, 0xc0, 0x20));
+
+    Ok(())
+}
+
+fn draw_with_shadow<R: RenderContext>(rc: &mut R, shape: impl Shape, color: Color) {
+    let shadow =
+        Affine::translate(SHADOW_OFFSET) * BezPath::from_vec(shape.to_bez_path(0.1).collect());
+    rc.blurred_shape(shadow, 6., &SHADOW_COLOR);
+    rc.fill(shape, &color);
+}
+
+fn star(center: Point, outer: f64, inner: f64, points: usize) -> BezPath {
+    let mut path = BezPath::new();
+    let n = points * 2;
+    for i in 0..n {
+        let angle = i as f64 * std::f64::consts::PI / points as f64 - std::f64::consts::FRAC_PI_2;
+        let radius = if i % 2 == 0 { outer } else { inner };
+        let pt = center + radius * Vec2::new(angle.cos(), angle.sin());
+        if i == 0 {
+            path.move_to(pt);
+        } else {
+            path.line_to(pt);
+        }
+    }
+    path.close_path();
+    path
+}
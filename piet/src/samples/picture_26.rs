@@ -0,0 +1,56 @@
+//! Nine-slice images: a small image of a rounded border, stretched to cells
+//! of several sizes. The corners keep their shape; the last cell is too
+//! small for them, so they are scaled down.
+
+use crate::kurbo::{Insets, Rect, Size};
+use crate::{Color, Error, ImageFormat, InterpolationMode, RenderContext};
+
+pub const SIZE: Size = Size::new(400., 200.);
+
+const IMAGE_SIZE: usize = 24;
+const RADIUS: f64 = 8.;
+const BORDER: f64 = 3.;
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(Color::WHITE);
+    let image = rc.make_image(
+        IMAGE_SIZE,
+        IMAGE_SIZE,
+        &rounded_border(),
+        ImageFormat::RgbaSeparate,
+    )?;
+    let insets = Insets::uniform(RADIUS);
+    let interp = InterpolationMode::Bilinear;
+    rc.draw_image_nine(&image, insets, Rect::new(20., 20., 220., 80.), interp);
+    rc.draw_image_nine(&image, insets, Rect::new(240., 20., 320., 180.), interp);
+    rc.draw_image_nine(&image, insets, Rect::new(20., 100., 60., 140.), interp);
+    rc.draw_image_nine(&image, insets, Rect::new(80., 100., 200., 180.), interp);
+    rc.draw_image_nine(&image, insets, Rect::new(340., 20., 350., 30.), interp);
+    Ok(())
+}
+
+/// A light fill with a dark border, and rounded, antialiased corners.
+fn rounded_border() -> Vec<u8> {
+    let mut buf = Vec::with_capacity(IMAGE_SIZE * IMAGE_SIZE * 4);
+    let size = IMAGE_SIZE as f64;
+    for y in 0..IMAGE_SIZE {
+        for x in 0..IMAGE_SIZE {
+            let px = x as f64 + 0.5;
+            let py = y as f64 + 0.5;
+            // distance inside the edge of the rounded rect
+            let cx = px.clamp(RADIUS, size - RADIUS);
+            let cy = py.clamp(RADIUS, size - RADIUS);
+            let depth = RADIUS - ((px - cx).powi(2) + (py - cy).powi(2)).sqrt();
+            let alpha = depth.clamp(0., 1.);
+            let border = (BORDER - depth).clamp(0., 1.);
+            let mix = |fill: f64, edge: f64| (fill + (edge - fill) * border).round() as u8;
+            buf.extend_from_slice(&[
+                mix(230., 30.),
+                mix(238., 60.),
+                mix(250., 140.),
+                (alpha * 255.).round() as u8,
+            ]);
+        }
+    }
+    buf
+}
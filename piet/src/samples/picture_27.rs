@@ -0,0 +1,39 @@
+//! Pixel snapping: grids of hairlines at scales of 1, 1.25 and 2. The top
+//! row is drawn as is, and the bottom row with `SnapMode::HalfPixel`, which
+//! should be crisp at every scale.
+
+use crate::kurbo::{Affine, Line, Size};
+use crate::{Color, Error, RenderContext, SnapMode, StrokeStyle};
+
+pub const SIZE: Size = Size::new(370., 230.);
+
+const SCALES: &[f64] = &[1.0, 1.25, 2.0];
+const CELL: f64 = 100.;
+const GAP: f64 = 10.;
+const SPACING: f64 = 7.;
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(Color::WHITE);
+    let plain = StrokeStyle::new();
+    let snapped = StrokeStyle::new().snap_mode(SnapMode::HalfPixel);
+    for (i, &scale) in SCALES.iter().enumerate() {
+        let x = GAP + i as f64 * (CELL + 2. * GAP);
+        for (j, style) in [&plain, &snapped].iter().enumerate() {
+            let y = GAP + j as f64 * (CELL + GAP);
+            let mut rc = rc.save_guard();
+            rc.transform(Affine::translate((x, y)) * Affine::scale(scale));
+            draw_grid(&mut *rc, CELL / scale, style);
+        }
+    }
+    Ok(())
+}
+
+fn draw_grid<R: RenderContext>(rc: &mut R, size: f64, style: &StrokeStyle) {
+    let n = (size / SPACING) as usize;
+    let end = n as f64 * SPACING;
+    for k in 0..=n {
+        let pos = k as f64 * SPACING;
+        rc.stroke_styled(Line::new((pos, 0.), (pos, end)), &Color::BLACK, 1., style);
+        rc.stroke_styled(Line::new((0., pos), (end, pos)), &Color::BLACK, 1., style);
+    }
+}
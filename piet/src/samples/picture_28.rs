@@ -0,0 +1,24 @@
+//! Dash offsets: the same dashed rect and line, with dash offsets of 0, 2
+//! and 4. Each step moves the dashes back along the path by two units.
+
+use crate::kurbo::{Line, Rect, Size};
+use crate::{Color, Error, LineCap, RenderContext, StrokeStyle};
+
+pub const SIZE: Size = Size::new(400., 160.);
+
+const DASHES: &[f64] = &[12., 6.];
+const OFFSETS: &[f64] = &[0., 2., 4.];
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(Color::WHITE);
+    let color = Color::rgb8(0x20, 0x40, 0x80);
+    for (i, &offset) in OFFSETS.iter().enumerate() {
+        let x = 20. + i as f64 * 130.;
+        let style = StrokeStyle::new()
+            .dash(DASHES.to_vec(), offset)
+            .line_cap(LineCap::Butt);
+        rc.stroke_styled(Rect::new(x, 20., x + 100., 100.), &color, 4., &style);
+        rc.stroke_styled(Line::new((x, 130.), (x + 100., 130.)), &color, 4., &style);
+    }
+    Ok(())
+}
@@ -0,0 +1,45 @@
+//! Miter limits: a zig-zag with joins of several angles, stroked with miter
+//! joins at limits of 1, 2, 4, the default, and 20. Joins whose miters are
+//! too long for the limit are beveled; the last row has the sharpest joins
+//! mitered.
+
+use crate::kurbo::{BezPath, Size};
+use crate::{Color, Error, LineJoin, RenderContext, StrokeStyle};
+
+pub const SIZE: Size = Size::new(400., 360.);
+
+const LIMITS: &[Option<f64>] = &[Some(1.), Some(2.), Some(4.), None, Some(20.)];
+/// The width of each zig or zag, for joins that get sharper to the right.
+const WIDTHS: &[f64] = &[60., 40., 24., 12., 6.];
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(Color::WHITE);
+    let color = Color::rgb8(0x30, 0x30, 0x30);
+    for (i, &limit) in LIMITS.iter().enumerate() {
+        let mut style = StrokeStyle::new().line_join(LineJoin::Miter);
+        if let Some(limit) = limit {
+            style.set_miter_limit(limit);
+        }
+        let y = 30. + i as f64 * 68.;
+        rc.stroke_styled(zig_zag(30., y, 40.), &color, 6., &style);
+    }
+    Ok(())
+}
+
+fn zig_zag(x: f64, y: f64, height: f64) -> BezPath {
+    let mut path = BezPath::new();
+    path.move_to((x, y + height));
+    let mut x = x;
+    for (i, &width) in WIDTHS.iter().enumerate() {
+        // two joins at this angle, at the top and the bottom
+        x += width / 2.;
+        path.line_to((x, y));
+        x += width / 2.;
+        path.line_to((x, y + height));
+        if i + 1 < WIDTHS.len() {
+            x += 20.;
+            path.line_to((x, y + height));
+        }
+    }
+    path
+}
@@ -19,13 +19,11 @@ pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
         for line_join in &[LineJoin::Bevel, LineJoin::Miter, LineJoin::Round] {
             let width = 5.0;
             let mut style = StrokeStyle::new();
-            rc.with_save(|rc| {
-                rc.transform(Affine::translate((x, y)));
-                style.set_line_cap(*line_cap);
-                style.set_line_join(*line_join);
-                rc.stroke_styled(&path, &brush, width, &style);
-                Ok(())
-            })?;
+            style.set_line_cap(*line_cap);
+            style.set_line_join(*line_join);
+            let mut rc = rc.save_guard();
+            rc.transform(Affine::translate((x, y)));
+            rc.stroke_styled(&path, &brush, width, &style);
             x += 30.0;
         }
         y += 30.0;
@@ -0,0 +1,44 @@
+//! Clipping with fill rules: a gradient clipped to a ring made of two
+//! circles, with the nonzero rule on the left, which fills the hole, and the
+//! even-odd rule on the right, which leaves it out.
+
+use crate::kurbo::{BezPath, Circle, Point, Rect, Shape, Size};
+use crate::{
+    Color, ColorInterpolation, Error, FixedLinearGradient, GradientExtend, GradientStops,
+    RenderContext,
+};
+
+pub const SIZE: Size = Size::new(400., 200.);
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(Color::WHITE);
+    let gradient = rc.gradient(FixedLinearGradient {
+        start: Point::new(0., 20.),
+        end: Point::new(0., 180.),
+        stops: GradientStops::to_vec((
+            Color::rgb8(0xe0, 0x40, 0x30),
+            Color::rgb8(0x30, 0x40, 0xe0),
+        )),
+        extend: GradientExtend::Pad,
+        interpolation: ColorInterpolation::Srgb,
+    })?;
+    for (i, &even_odd) in [false, true].iter().enumerate() {
+        let center = Point::new(100. + i as f64 * 200., 100.);
+        rc.save()?;
+        if even_odd {
+            rc.clip_even_odd(ring(center));
+        } else {
+            rc.clip(ring(center));
+        }
+        rc.fill(Rect::from_center_size(center, (200., 200.)), &gradient);
+        rc.restore()?;
+    }
+    Ok(())
+}
+
+/// Two circles, both drawn in the same direction.
+fn ring(center: Point) -> BezPath {
+    let mut path = Circle::new(center, 80.).into_bez_path(0.1);
+    path.extend(Circle::new(center, 40.).to_bez_path(0.1));
+    path
+}
@@ -0,0 +1,49 @@
+//! Cards with drop shadows: a plain card, a card with a gradient fill under
+//! a rotation, and a translucent card, whose shadow shows through it.
+
+use crate::kurbo::{Affine, Point, Rect, RoundedRect, Size, Vec2};
+use crate::{
+    Color, ColorInterpolation, Error, FixedLinearGradient, GradientExtend, GradientStops,
+    RenderContext, ShadowStyle,
+};
+
+pub const SIZE: Size = Size::new(480., 220.);
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(Color::rgb8(0xe8, 0xe8, 0xec));
+    let card = RoundedRect::from_rect(Rect::new(0., 0., 120., 150.), 10.);
+    let shadow = ShadowStyle::new((4., 6.), 6., Color::rgba8(0, 0, 0, 0x60));
+
+    rc.with_save(|rc| {
+        rc.transform(Affine::translate((30., 35.)));
+        rc.fill_with_shadow(card, &Color::WHITE, &shadow);
+        rc.fill(Rect::new(15., 20., 105., 30.), &Color::grey8(0xa0));
+        rc.fill(Rect::new(15., 40., 80., 48.), &Color::grey8(0xc8));
+        Ok(())
+    })?;
+
+    let gradient = rc.gradient(FixedLinearGradient {
+        start: Point::new(0., 0.),
+        end: Point::new(0., 150.),
+        stops: GradientStops::to_vec((
+            Color::rgb8(0x50, 0x90, 0xe0),
+            Color::rgb8(0x30, 0x50, 0xa0),
+        )),
+        extend: GradientExtend::Pad,
+        interpolation: ColorInterpolation::Srgb,
+    })?;
+    rc.with_save(|rc| {
+        let center = Vec2::new(60., 75.);
+        rc.transform(Affine::translate((240., 110.)) * Affine::rotate(-0.1));
+        rc.transform(Affine::translate(-center));
+        rc.fill_with_shadow(card, &gradient, &shadow);
+        Ok(())
+    })?;
+
+    rc.with_save(|rc| {
+        rc.transform(Affine::translate((330., 35.)));
+        let translucent = Color::rgba8(0xff, 0xff, 0xff, 0x80);
+        rc.fill_with_shadow(card, &translucent, &shadow);
+        Ok(())
+    })
+}
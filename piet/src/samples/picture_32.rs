@@ -0,0 +1,41 @@
+//! Filling with fill rules: a self-intersecting five-pointed star, filled
+//! with the nonzero rule on the left, which fills the pentagon in the
+//! middle, and with the even-odd rule on the right, which leaves it out.
+
+use crate::kurbo::{BezPath, Point, Size, Vec2};
+use crate::{Color, Error, RenderContext};
+
+pub const SIZE: Size = Size::new(400., 200.);
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(Color::WHITE);
+    let fill = Color::rgb8(0x30, 0x80, 0xc0);
+    let outline = Color::rgb8(0x20, 0x30, 0x40);
+    for (i, &even_odd) in [false, true].iter().enumerate() {
+        let star = star(Point::new(50. + i as f64 * 100., 54.), 42.);
+        if even_odd {
+            rc.fill_even_odd(&star, &fill);
+        } else {
+            rc.fill(&star, &fill);
+        }
+        rc.stroke(&star, &outline, 1.);
+    }
+    Ok(())
+}
+
+/// A pentagram: five points on a circle, each joined to the points two
+/// away from it, so the path crosses itself.
+fn star(center: Point, radius: f64) -> BezPath {
+    let mut path = BezPath::new();
+    for i in 0..5 {
+        let angle = (i * 2) as f64 * std::f64::consts::PI * 2. / 5. - std::f64::consts::FRAC_PI_2;
+        let pt = center + radius * Vec2::new(angle.cos(), angle.sin());
+        if i == 0 {
+            path.move_to(pt);
+        } else {
+            path.line_to(pt);
+        }
+    }
+    path.close_path();
+    path
+}
@@ -0,0 +1,26 @@
+//! Mixed scripts: lines of Latin text with Greek, Cyrillic, Arabic, CJK and
+//! emoji, which a single font rarely covers, so backends must fall back to
+//! other fonts for some clusters.
+
+use crate::kurbo::Size;
+use crate::{Color, Error, FontFamily, RenderContext, Text, TextAttribute, TextLayoutBuilder};
+
+pub const SIZE: Size = Size::new(480., 200.);
+
+static TEXT: &str = "Latin, Ελληνικά, Кириллица\n\
+                     Arabic: مرحبا\n\
+                     CJK: 漢字 かな 한글\n\
+                     Emoji: 😀 🎉 ✓";
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(Color::WHITE);
+    let layout = rc
+        .text()
+        .new_text_layout(TEXT)
+        .font(FontFamily::SERIF, 14.0)
+        .default_attribute(TextAttribute::ForegroundColor(Color::BLACK))
+        .max_width(220.0)
+        .build()?;
+    rc.draw_text(&layout, (10.0, 10.0));
+    Ok(())
+}
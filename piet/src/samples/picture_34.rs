@@ -0,0 +1,45 @@
+//! Bidirectional text: Hebrew and Arabic mixed with Latin text and numbers,
+//! in left-to-right and right-to-left paragraphs, with the selection of a
+//! range that crosses a change of direction.
+
+use crate::kurbo::{Size, Vec2};
+use crate::{
+    Color, Error, FontFamily, RenderContext, Text, TextAttribute, TextDirection, TextLayout,
+    TextLayoutBuilder,
+};
+
+pub const SIZE: Size = Size::new(480., 240.);
+
+static LTR_TEXT: &str = "Hebrew: שלום עולם, and back\n\
+                         Arabic: مرحبا بالعالم 123";
+
+static RTL_TEXT: &str = "שלום world עולם\n\
+                         מספר 42 כאן.";
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(Color::WHITE);
+    let ltr = rc
+        .text()
+        .new_text_layout(LTR_TEXT)
+        .font(FontFamily::SANS_SERIF, 14.0)
+        .default_attribute(TextAttribute::ForegroundColor(Color::BLACK))
+        .build()?;
+    let rtl = rc
+        .text()
+        .new_text_layout(RTL_TEXT)
+        .font(FontFamily::SANS_SERIF, 14.0)
+        .default_attribute(TextAttribute::ForegroundColor(Color::BLACK))
+        .text_direction(TextDirection::RightToLeft)
+        .build()?;
+
+    // from the second Hebrew word to the end of the Latin text after it
+    let start = LTR_TEXT.find("עולם").unwrap();
+    let end = LTR_TEXT.find(" back").unwrap();
+    let selection = Color::rgb8(0xb4, 0xd5, 0xfe);
+    for rect in ltr.rects_for_range(start..end) {
+        rc.fill(rect + Vec2::new(10.0, 10.0), &selection);
+    }
+    rc.draw_text(&ltr, (10.0, 10.0));
+    rc.draw_text(&rtl, (10.0, 70.0));
+    Ok(())
+}
@@ -2,8 +2,8 @@
 
 use crate::kurbo::{Point, Rect, Size, Vec2};
 use crate::{
-    Color, Error, FixedGradient, FixedLinearGradient, FixedRadialGradient, GradientStop,
-    RenderContext,
+    Color, ColorInterpolation, Error, FixedGradient, FixedLinearGradient, FixedRadialGradient,
+    GradientExtend, GradientStop, RenderContext,
 };
 
 pub const SIZE: Size = Size::new(400., 200.);
@@ -23,10 +23,18 @@ pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
     let gradient = rc.gradient(FixedGradient::Radial(FixedRadialGradient {
         center: Point::new(30.0, 30.0),
         origin_offset: Vec2::new(10.0, 10.0),
-        radius: 30.0,
+        radii: Vec2::new(30.0, 30.0),
         stops,
+        extend: GradientExtend::Pad,
+        interpolation: ColorInterpolation::Srgb,
     }))?;
     rc.fill(Rect::new(0.0, 0.0, 60.0, 60.0), &gradient);
+    let ellipse = rc.gradient(FixedRadialGradient {
+        origin_offset: Vec2::new(20.0, 5.0),
+        radii: Vec2::new(60.0, 30.0),
+        ..FixedRadialGradient::new((140.0, 30.0), 0.0, (Color::WHITE, Color::BLACK))
+    })?;
+    rc.fill(Rect::new(80.0, 0.0, 200.0, 60.0), &ellipse);
     let stops2 = vec![
         GradientStop {
             pos: 0.0,
@@ -41,6 +49,8 @@ pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
         start: Point::new(0.0, 0.0),
         end: Point::new(60.0, 0.0),
         stops: stops2,
+        extend: GradientExtend::Pad,
+        interpolation: ColorInterpolation::Srgb,
     }))?;
     rc.fill(Rect::new(0.0, 80.0, 60.0, 100.0), &gradient2);
     Ok(())
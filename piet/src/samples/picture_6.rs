@@ -2,8 +2,8 @@
 
 use crate::kurbo::{Circle, Point, Rect, RoundedRect, Size, Vec2};
 use crate::{
-    Color, Error, FixedGradient, FixedLinearGradient, FixedRadialGradient, GradientStop, LineCap,
-    LineJoin, RenderContext, StrokeStyle,
+    Color, ColorInterpolation, Error, FixedGradient, FixedLinearGradient, FixedRadialGradient,
+    GradientExtend, GradientStop, LineCap, LineJoin, RenderContext, StrokeStyle,
 };
 
 pub const SIZE: Size = Size::new(400., 200.);
@@ -20,8 +20,10 @@ pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
     let radial_gradient = rc.gradient(FixedGradient::Radial(FixedRadialGradient {
         center: Point::new(30.0, 30.0),
         origin_offset: Vec2::new(10.0, 10.0),
-        radius: 40.0,
+        radii: Vec2::new(40.0, 40.0),
         stops: create_gradient_stops(),
+        extend: GradientExtend::Pad,
+        interpolation: ColorInterpolation::Srgb,
     }))?;
     rc.stroke(
         Circle::new(Point::new(30.0, 20.0), 15.0),
@@ -40,6 +42,8 @@ pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
         start: Point::new(60.0, 10.0),
         end: Point::new(100.0, 90.0),
         stops: create_gradient_stops(),
+        extend: GradientExtend::Pad,
+        interpolation: ColorInterpolation::Srgb,
     }))?;
     rc.stroke_styled(
         RoundedRect::new(60.0, 0.0, 100.0, 30.0, 7.0),
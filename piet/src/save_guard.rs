@@ -0,0 +1,66 @@
+//! Restoring the context state when a scope ends.
+
+use std::ops::{Deref, DerefMut};
+
+use crate::{Error, RenderContext};
+
+/// A render context with its state saved, restored when the guard is dropped.
+///
+/// This is returned by [`save_guard`], and derefs to the render context, so
+/// drawing can continue through it. Unlike [`with_save`], the state is
+/// restored on every path out of the scope, including early returns with `?`.
+///
+/// An error from restoring when the guard is dropped is logged; use
+/// [`restore`] to handle it instead.
+///
+/// [`save_guard`]: trait.RenderContext.html#method.save_guard
+/// [`with_save`]: trait.RenderContext.html#method.with_save
+/// [`restore`]: #method.restore
+pub struct SaveGuard<'a, R: RenderContext + ?Sized> {
+    rc: &'a mut R,
+    /// Whether the state was saved, and still needs to be restored.
+    saved: bool,
+}
+
+impl<'a, R: RenderContext + ?Sized> SaveGuard<'a, R> {
+    pub(crate) fn new(rc: &'a mut R) -> SaveGuard<'a, R> {
+        let saved = match rc.save() {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("error saving render context state: {}", e);
+                false
+            }
+        };
+        SaveGuard { rc, saved }
+    }
+
+    /// Restore the context state now, returning any error.
+    pub fn restore(mut self) -> Result<(), Error> {
+        self.saved = false;
+        self.rc.restore()
+    }
+}
+
+impl<'a, R: RenderContext + ?Sized> Deref for SaveGuard<'a, R> {
+    type Target = R;
+
+    fn deref(&self) -> &R {
+        self.rc
+    }
+}
+
+impl<'a, R: RenderContext + ?Sized> DerefMut for SaveGuard<'a, R> {
+    fn deref_mut(&mut self) -> &mut R {
+        self.rc
+    }
+}
+
+impl<'a, R: RenderContext + ?Sized> Drop for SaveGuard<'a, R> {
+    fn drop(&mut self) {
+        if self.saved {
+            if let Err(e) = self.rc.restore() {
+                eprintln!("error restoring render context state: {}", e);
+            }
+        }
+    }
+}
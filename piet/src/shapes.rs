@@ -1,5 +1,9 @@
 //! Options for drawing paths.
 
+use kurbo::Vec2;
+
+use crate::Color;
+
 /// Options for drawing stroked lines.
 /// Most of these are self explanatory, but some aren't.
 ///
@@ -11,21 +15,31 @@
 ///
 /// `miter_limit` controls how corners are drawn when `line_join` is set to
 /// Miter. Will draw corners as `Bevel` instead of `Miter` if the limit is
-/// reached. See the reference below on how `miter_limit` is calculated.
+/// reached. See the reference below on how `miter_limit` is calculated. If
+/// it isn't set, it is [`DEFAULT_MITER_LIMIT`], as in SVG and cairo.
+///
+/// `snap_mode` moves the stroke onto the pixel grid, so that thin lines are
+/// crisp; see [`SnapMode`].
 ///
 /// See
 /// https://www.adobe.com/content/dam/acom/en/devnet/actionscript/articles/psrefman.pdf
 /// for more information and examples
+///
+/// [`DEFAULT_MITER_LIMIT`]: #associatedconstant.DEFAULT_MITER_LIMIT
+/// [`SnapMode`]: enum.SnapMode.html
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StrokeStyle {
     pub line_join: Option<LineJoin>,
     pub line_cap: Option<LineCap>,
     pub dash: Option<(Vec<f64>, f64)>,
     pub miter_limit: Option<f64>,
+    pub snap_mode: Option<SnapMode>,
 }
 
 /// Options for angled joins in strokes.
 #[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LineJoin {
     Miter,
     Round,
@@ -34,13 +48,32 @@ pub enum LineJoin {
 
 /// Options for the cap of stroked lines.
 #[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LineCap {
     Butt,
     Round,
     Square,
 }
 
+/// Options for aligning strokes to device pixels.
+///
+/// Snapping only applies when the current transform is axis-aligned, as
+/// with scaling and translation; otherwise the stroke is drawn as is.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SnapMode {
+    /// Move points so that the edges of the stroke fall on pixel edges.
+    ///
+    /// Strokes that are an odd number of device pixels wide are centered on
+    /// pixel centers, and even widths on pixel edges. The width is rounded
+    /// to whole device pixels, if the transform scales both axes equally.
+    HalfPixel,
+}
+
 impl StrokeStyle {
+    /// The miter limit used when `miter_limit` isn't set.
+    pub const DEFAULT_MITER_LIMIT: f64 = 10.0;
+
     #[allow(clippy::new_without_default)]
     pub fn new() -> StrokeStyle {
         StrokeStyle {
@@ -48,6 +81,7 @@ impl StrokeStyle {
             line_cap: None,
             dash: None,
             miter_limit: None,
+            snap_mode: None,
         }
     }
 
@@ -82,6 +116,14 @@ impl StrokeStyle {
         self
     }
 
+    /// Builder-style method to set the [`SnapMode`].
+    ///
+    /// [`SnapMode`]: enum.SnapMode.html
+    pub fn snap_mode(mut self, snap_mode: SnapMode) -> Self {
+        self.snap_mode = Some(snap_mode);
+        self
+    }
+
     pub fn set_line_join(&mut self, line_join: LineJoin) {
         self.line_join = Some(line_join);
     }
@@ -97,4 +139,103 @@ impl StrokeStyle {
     pub fn set_miter_limit(&mut self, miter_limit: f64) {
         self.miter_limit = Some(miter_limit);
     }
+
+    pub fn set_snap_mode(&mut self, snap_mode: SnapMode) {
+        self.snap_mode = Some(snap_mode);
+    }
+}
+
+/// A drop shadow, for [`fill_with_shadow`].
+///
+/// The shadow is the filled shape, moved by `offset` and blurred by `blur`,
+/// the standard deviation of a Gaussian blur, as with [`blurred_shape`].
+/// Both are in user space, so they scale with the current transform.
+///
+/// [`fill_with_shadow`]: trait.RenderContext.html#method.fill_with_shadow
+/// [`blurred_shape`]: trait.RenderContext.html#method.blurred_shape
+#[derive(Clone, Debug)]
+pub struct ShadowStyle {
+    pub offset: Vec2,
+    pub blur: f64,
+    pub color: Color,
+}
+
+impl ShadowStyle {
+    pub fn new(offset: impl Into<Vec2>, blur: f64, color: Color) -> ShadowStyle {
+        ShadowStyle {
+            offset: offset.into(),
+            blur,
+            color,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use crate::{FontWeight, ImageFormat, TextAlignment};
+
+    fn round_trip<T>(value: &T) -> T
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        serde_json::from_str(&serde_json::to_string(value).unwrap()).unwrap()
+    }
+
+    /// Round trips through a format that isn't human-readable.
+    fn binary_round_trip<T>(value: &T) -> T
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        bincode::deserialize(&bincode::serialize(value).unwrap()).unwrap()
+    }
+
+    fn stroke_style() -> StrokeStyle {
+        let mut style = StrokeStyle::new();
+        style.set_line_join(LineJoin::Bevel);
+        style.set_line_cap(LineCap::Square);
+        style.set_dash(vec![4.0, 2.0], 1.0);
+        style.set_miter_limit(4.0);
+        style.set_snap_mode(SnapMode::HalfPixel);
+        style
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let style = stroke_style();
+        assert_eq!(round_trip(&style), style);
+        assert_eq!(round_trip(&StrokeStyle::new()), StrokeStyle::new());
+
+        assert_eq!(round_trip(&FontWeight::SEMI_BOLD), FontWeight::SEMI_BOLD);
+        assert_eq!(serde_json::to_string(&FontWeight::BOLD).unwrap(), "700");
+        assert_eq!(
+            round_trip(&TextAlignment::Justified),
+            TextAlignment::Justified
+        );
+        assert_eq!(
+            round_trip(&ImageFormat::BgraPremul),
+            ImageFormat::BgraPremul
+        );
+    }
+
+    #[test]
+    fn serde_binary_round_trip() {
+        let style = stroke_style();
+        assert_eq!(binary_round_trip(&style), style);
+        assert_eq!(binary_round_trip(&StrokeStyle::new()), StrokeStyle::new());
+
+        assert_eq!(
+            binary_round_trip(&FontWeight::SEMI_BOLD),
+            FontWeight::SEMI_BOLD
+        );
+        assert_eq!(bincode::serialize(&FontWeight::BOLD).unwrap(), [0xbc, 0x02]);
+        assert_eq!(
+            binary_round_trip(&TextAlignment::Justified),
+            TextAlignment::Justified
+        );
+        assert_eq!(
+            binary_round_trip(&ImageFormat::BgraPremul),
+            ImageFormat::BgraPremul
+        );
+    }
 }
@@ -1,10 +1,13 @@
 //! Traits for fonts and text handling.
 
+use std::borrow::Cow;
 use std::ops::{Range, RangeBounds};
+use std::path::Path;
+use std::rc::Rc;
 use std::sync::Arc;
 
-use crate::kurbo::{Point, Rect, Size};
-use crate::Error;
+use crate::kurbo::{BezPath, Point, Rect, Size};
+use crate::{Error, ShadowStyle};
 
 pub trait Text: Clone {
     type TextLayoutBuilder: TextLayoutBuilder<Out = Self::TextLayout>;
@@ -29,11 +32,223 @@ pub trait Text: Clone {
     /// [`FontFamily`]: struct.FontFamily.html
     fn font_family(&mut self, family_name: &str) -> Option<FontFamily>;
 
+    /// Returns the font families available to this text system, sorted by name.
+    ///
+    /// This is intended for things like font pickers; passing the name of
+    /// any of these families to [`font_family`] will find it.
+    ///
+    /// Backends that cannot enumerate the installed fonts return the generic
+    /// families they support; the default implementation returns nothing.
+    ///
+    /// [`font_family`]: #tymethod.font_family
+    fn font_families(&mut self) -> Vec<FontFamily> {
+        Vec::new()
+    }
+
+    /// Load the font in `data`, and return the [`FontFamily`] it belongs to.
+    ///
+    /// The font is available to layouts created by this text system for the
+    /// rest of the program; the returned family can also be found by name
+    /// with [`font_family`].
+    ///
+    /// The default implementation returns [`Error::NotSupported`].
+    ///
+    /// [`FontFamily`]: struct.FontFamily.html
+    /// [`font_family`]: #tymethod.font_family
+    /// [`Error::NotSupported`]: enum.Error.html#variant.NotSupported
+    fn load_font(&mut self, _data: &[u8]) -> Result<FontFamily, Error> {
+        Err(Error::NotSupported)
+    }
+
+    /// Load the font in the file at `path`, as with [`load_font`].
+    ///
+    /// Backends may load the file without reading all of it into memory; the
+    /// default implementation reads the file and passes its contents to
+    /// [`load_font`].
+    ///
+    /// [`load_font`]: #method.load_font
+    fn load_font_from_path(&mut self, path: &Path) -> Result<FontFamily, Error> {
+        let data = std::fs::read(path).map_err(|e| Error::BackendError(Box::new(e)))?;
+        self.load_font(&data)
+    }
+
     /// Create a new layout object to display the provided `text`.
     ///
+    /// The text may be any [`TextStorage`]; the layout keeps the text itself
+    /// rather than a copy of it, so passing an `Arc<str>` or `Rc<str>` lets
+    /// many layouts share the same text. With the `send-layouts` feature,
+    /// the text must also be `Send + Sync`.
+    ///
+    /// A `&str` is also accepted, and copied into the layout; see
+    /// [`IntoLayoutStorage`].
+    ///
     /// The returned object is a [`TextLayoutBuilder`]; methods on that type
     /// can be used to customize the layout.
-    fn new_text_layout(&mut self, text: &str) -> Self::TextLayoutBuilder;
+    ///
+    /// [`TextStorage`]: trait.TextStorage.html
+    /// [`IntoLayoutStorage`]: trait.IntoLayoutStorage.html
+    fn new_text_layout(&mut self, text: impl IntoLayoutStorage) -> Self::TextLayoutBuilder;
+
+    /// Returns the [`FontMetrics`] of the regular style of `family` at `size`.
+    ///
+    /// This is useful for things like vertically centering text on the cap
+    /// height, or drawing custom decorations that match the font's own.
+    ///
+    /// Returns `None` if the font cannot be loaded, or if the backend does not
+    /// provide font metrics; the default implementation always returns `None`.
+    ///
+    /// [`FontMetrics`]: struct.FontMetrics.html
+    fn font_metrics(&mut self, _family: &FontFamily, _size: f64) -> Option<FontMetrics> {
+        None
+    }
+}
+
+/// A marker for types that can be sent between threads, if the
+/// `send-layouts` feature is enabled.
+///
+/// With the feature, this is implemented for all `Send` types, and every
+/// [`TextLayout`] must be `Send`; without it, it is implemented for all types.
+///
+/// [`TextLayout`]: trait.TextLayout.html
+#[cfg(feature = "send-layouts")]
+pub trait MaybeSend: Send {}
+
+#[cfg(feature = "send-layouts")]
+impl<T: Send + ?Sized> MaybeSend for T {}
+
+/// A marker for types that can be sent between threads, if the
+/// `send-layouts` feature is enabled.
+///
+/// With the feature, this is implemented for all `Send` types, and every
+/// [`TextLayout`] must be `Send`; without it, it is implemented for all types.
+///
+/// [`TextLayout`]: trait.TextLayout.html
+#[cfg(not(feature = "send-layouts"))]
+pub trait MaybeSend {}
+
+#[cfg(not(feature = "send-layouts"))]
+impl<T: ?Sized> MaybeSend for T {}
+
+/// A marker for types that can be shared between threads, if the
+/// `send-layouts` feature is enabled.
+///
+/// With the feature, this is implemented for all `Send + Sync` types, and
+/// the text passed to [`Text::new_text_layout`] must be both, so that
+/// layouts can hold on to it; without it, it is implemented for all types.
+///
+/// [`Text::new_text_layout`]: trait.Text.html#tymethod.new_text_layout
+#[cfg(feature = "send-layouts")]
+pub trait MaybeSendSync: Send + Sync {}
+
+#[cfg(feature = "send-layouts")]
+impl<T: Send + Sync + ?Sized> MaybeSendSync for T {}
+
+/// A marker for types that can be shared between threads, if the
+/// `send-layouts` feature is enabled.
+///
+/// With the feature, this is implemented for all `Send + Sync` types, and
+/// the text passed to [`Text::new_text_layout`] must be both, so that
+/// layouts can hold on to it; without it, it is implemented for all types.
+///
+/// [`Text::new_text_layout`]: trait.Text.html#tymethod.new_text_layout
+#[cfg(not(feature = "send-layouts"))]
+pub trait MaybeSendSync {}
+
+#[cfg(not(feature = "send-layouts"))]
+impl<T: ?Sized> MaybeSendSync for T {}
+
+/// A type that stores text, which can be used to create a [`TextLayout`].
+///
+/// This is implemented for `String`, `Rc<str>` and `Arc<str>`; layouts hold
+/// on to the storage instead of copying the text out of it.
+///
+/// With the `send-layouts` feature, layouts can only be made from storage
+/// that is also `Send + Sync`, so `Rc<str>` cannot be passed to
+/// [`Text::new_text_layout`]; see [`LayoutStorage`].
+///
+/// [`TextLayout`]: trait.TextLayout.html
+/// [`Text::new_text_layout`]: trait.Text.html#tymethod.new_text_layout
+/// [`LayoutStorage`]: trait.LayoutStorage.html
+pub trait TextStorage: 'static {
+    /// Return the underlying text as a `&str`.
+    fn as_str(&self) -> &str;
+}
+
+/// [`TextStorage`] that a [`TextLayout`] can hold on to.
+///
+/// This is implemented for all text storage that is [`MaybeSendSync`]: with
+/// the `send-layouts` feature that is `Send + Sync` storage, and without it,
+/// all storage. Backends keep the text of their layouts as a
+/// `dyn LayoutStorage`.
+///
+/// [`TextStorage`]: trait.TextStorage.html
+/// [`TextLayout`]: trait.TextLayout.html
+/// [`MaybeSendSync`]: trait.MaybeSendSync.html
+pub trait LayoutStorage: TextStorage + MaybeSendSync {}
+
+impl<T: TextStorage + MaybeSendSync + ?Sized> LayoutStorage for T {}
+
+/// Text that can be passed to [`Text::new_text_layout`].
+///
+/// This is implemented for every [`LayoutStorage`], which is handed to the
+/// layout as it is, and for `&str` and `&String`.
+///
+/// The `&str` and `&String` impls are deprecated: they copy the text into an
+/// `Arc<str>`, and are only kept so that code that passed borrowed text
+/// before layouts held on to [`TextStorage`] still compiles. Pass a `String`
+/// or `Arc<str>` instead, so the text isn't copied.
+///
+/// [`Text::new_text_layout`]: trait.Text.html#tymethod.new_text_layout
+/// [`LayoutStorage`]: trait.LayoutStorage.html
+/// [`TextStorage`]: trait.TextStorage.html
+pub trait IntoLayoutStorage {
+    /// The storage the layout keeps.
+    type Storage: LayoutStorage;
+
+    /// Convert this into the storage of a layout.
+    fn into_layout_storage(self) -> Self::Storage;
+}
+
+impl<T: LayoutStorage> IntoLayoutStorage for T {
+    type Storage = T;
+
+    fn into_layout_storage(self) -> T {
+        self
+    }
+}
+
+impl IntoLayoutStorage for &str {
+    type Storage = Arc<str>;
+
+    fn into_layout_storage(self) -> Arc<str> {
+        self.into()
+    }
+}
+
+impl IntoLayoutStorage for &String {
+    type Storage = Arc<str>;
+
+    fn into_layout_storage(self) -> Arc<str> {
+        self.as_str().into()
+    }
+}
+
+impl TextStorage for String {
+    fn as_str(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl TextStorage for Rc<str> {
+    fn as_str(&self) -> &str {
+        self
+    }
+}
+
+impl TextStorage for Arc<str> {
+    fn as_str(&self) -> &str {
+        self
+    }
 }
 
 /// A reference to a font family.
@@ -107,6 +322,65 @@ impl Default for FontFamily {
     }
 }
 
+/// A list of font families in order of preference, like the CSS
+/// [`font-family`] property.
+///
+/// Characters that are missing from the first family are drawn with the
+/// first of the fallbacks that has them; if none do, the choice is left to
+/// the platform.
+///
+/// # Examples
+///
+/// ```
+/// # use piet::*;
+/// # let mut ctx = NullRenderContext::new();
+/// # let mut text = ctx.text();
+/// let families = FontFamilyList::new(FontFamily::SERIF)
+///     .with(text.font_family("Noto Serif CJK").unwrap_or(FontFamily::SANS_SERIF));
+///
+/// let layout = text.new_text_layout("hello 世界")
+///     .default_attribute(families)
+///     .build();
+/// ```
+///
+/// [`font-family`]: https://developer.mozilla.org/en-US/docs/Web/CSS/font-family
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FontFamilyList(Vec<FontFamily>);
+
+impl FontFamilyList {
+    /// A list containing only `primary`.
+    pub fn new(primary: FontFamily) -> FontFamilyList {
+        FontFamilyList(vec![primary])
+    }
+
+    /// Returns this list with `fallback` added after the existing families.
+    pub fn with(mut self, fallback: FontFamily) -> FontFamilyList {
+        self.0.push(fallback);
+        self
+    }
+
+    /// The preferred family.
+    pub fn primary(&self) -> &FontFamily {
+        &self.0[0]
+    }
+
+    /// The families to try, in order, after the primary family.
+    pub fn fallbacks(&self) -> &[FontFamily] {
+        &self.0[1..]
+    }
+
+    /// Iterate over all the families in this list, starting with the primary.
+    pub fn iter(&self) -> impl Iterator<Item = &FontFamily> + '_ {
+        self.0.iter()
+    }
+}
+
+impl From<FontFamily> for FontFamilyList {
+    fn from(family: FontFamily) -> FontFamilyList {
+        FontFamilyList::new(family)
+    }
+}
+
 /// A font weight, represented as a value in the range 1..=1000.
 ///
 /// This is based on the [CSS `font-weight`] property. In general, you should
@@ -115,6 +389,7 @@ impl Default for FontFamily {
 ///
 /// [CSS `font-weight`]: https://developer.mozilla.org/en-US/docs/Web/CSS/font-weight
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FontWeight(u16);
 
 impl FontWeight {
@@ -155,7 +430,98 @@ impl FontWeight {
     }
 }
 
+/// A set of [OpenType font features], such as small capitals or tabular figures.
+///
+/// Each feature is identified by a four-byte tag, such as `*b"smcp"`, and has
+/// a value; for most features `1` enables the feature and `0` disables it.
+/// Other features (such as `aalt`) use the value to select an alternate glyph.
+///
+/// In general, you should start from the constants defined on this type, and
+/// add other features with [`with`].
+///
+/// # Examples
+///
+/// ```
+/// use piet::FontFeatures;
+///
+/// let features = FontFeatures::TABULAR_NUMS.with(*b"zero", 1);
+/// assert_eq!(features.get(*b"tnum"), Some(1));
+/// assert_eq!(features.get(*b"smcp"), None);
+/// ```
+///
+/// [OpenType font features]: https://docs.microsoft.com/en-us/typography/opentype/spec/featurelist
+/// [`with`]: #method.with
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FontFeatures(Cow<'static, [([u8; 4], u32)]>);
+
+impl FontFeatures {
+    /// Lowercase letters drawn as small capitals (`smcp`).
+    pub const SMALL_CAPS: FontFeatures = FontFeatures(Cow::Borrowed(&[(*b"smcp", 1)]));
+    /// Figures of equal width, for lining up columns of numbers (`tnum`).
+    pub const TABULAR_NUMS: FontFeatures = FontFeatures(Cow::Borrowed(&[(*b"tnum", 1)]));
+    /// Figures with individual widths (`pnum`).
+    pub const PROPORTIONAL_NUMS: FontFeatures = FontFeatures(Cow::Borrowed(&[(*b"pnum", 1)]));
+    /// Figures that share the height of capital letters (`lnum`).
+    pub const LINING_NUMS: FontFeatures = FontFeatures(Cow::Borrowed(&[(*b"lnum", 1)]));
+    /// Figures that vary in height, like lowercase letters (`onum`).
+    pub const OLDSTYLE_NUMS: FontFeatures = FontFeatures(Cow::Borrowed(&[(*b"onum", 1)]));
+    /// Diagonal fractions, such as `1/2` (`frac`).
+    pub const FRACTIONS: FontFeatures = FontFeatures(Cow::Borrowed(&[(*b"frac", 1)]));
+    /// Disables standard ligatures, such as `fi` (`liga`).
+    pub const NO_LIGATURES: FontFeatures = FontFeatures(Cow::Borrowed(&[(*b"liga", 0)]));
+
+    /// An empty set of features; text is drawn with the font's defaults.
+    pub const fn new() -> FontFeatures {
+        FontFeatures(Cow::Borrowed(&[]))
+    }
+
+    /// Stylistic set `number` (`ss01` through `ss20`).
+    ///
+    /// Numbers outside of the range 1..=20 are clamped.
+    pub fn stylistic_set(number: u8) -> FontFeatures {
+        let number = number.clamp(1, 20);
+        let tag = [b's', b's', b'0' + number / 10, b'0' + number % 10];
+        FontFeatures::new().with(tag, 1)
+    }
+
+    /// Returns these features with `tag` set to `value`, replacing any
+    /// existing value for that tag.
+    pub fn with(mut self, tag: [u8; 4], value: u32) -> FontFeatures {
+        let features = self.0.to_mut();
+        match features.iter_mut().find(|(t, _)| *t == tag) {
+            Some(feature) => feature.1 = value,
+            None => features.push((tag, value)),
+        }
+        self
+    }
+
+    /// Returns the union of these features and `other`; values in `other`
+    /// take precedence.
+    pub fn union(self, other: &FontFeatures) -> FontFeatures {
+        other
+            .iter()
+            .fold(self, |features, (tag, value)| features.with(tag, value))
+    }
+
+    /// The value of the feature with `tag`, if it is set.
+    pub fn get(&self, tag: [u8; 4]) -> Option<u32> {
+        self.iter().find(|(t, _)| *t == tag).map(|(_, value)| value)
+    }
+
+    /// Iterate over the `(tag, value)` pairs of these features, in the order
+    /// they were added.
+    pub fn iter(&self) -> impl Iterator<Item = ([u8; 4], u32)> + '_ {
+        self.0.iter().copied()
+    }
+
+    /// Returns `true` if no features are set.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
 /// Attributes that can be applied to text.
+#[derive(Debug, Clone)]
 pub enum TextAttribute {
     /// The font family.
     Font(FontFamily),
@@ -165,11 +531,78 @@ pub enum TextAttribute {
     Weight(FontWeight),
     /// The foreground color of the text.
     ForegroundColor(crate::Color),
-    //BackgroundColor(crate::Color),
+    /// A color painted behind the text, from the top to the bottom of each line.
+    BackgroundColor(crate::Color),
     /// Italics.
     Italic(bool),
     /// Underline.
     Underline(bool),
+    /// The color of the underline, if it should differ from the foreground color.
+    UnderlineColor(crate::Color),
+    /// The [`UnderlineStyle`](enum.UnderlineStyle.html) of the underline.
+    UnderlineStyle(UnderlineStyle),
+    /// Strikethrough.
+    Strikethrough(bool),
+    /// Extra space added after each character, in points.
+    ///
+    /// This may be negative, to tighten the spacing. The web backend can only
+    /// apply one letter spacing to the whole layout, and returns
+    /// [`Error::NotSupported`] from [`TextLayoutBuilder::build`] if ranges
+    /// of the text have different spacings.
+    ///
+    /// [`Error::NotSupported`]: enum.Error.html#variant.NotSupported
+    /// [`TextLayoutBuilder::build`]: trait.TextLayoutBuilder.html#tymethod.build
+    LetterSpacing(f64),
+    /// Extra space added after each space separator (such as `' '` or
+    /// `'\u{A0}'`), in points, in addition to any letter spacing.
+    ///
+    /// Backends that do not yet support this will return [`Error::NotSupported`]
+    /// from [`TextLayoutBuilder::build`] if it is set.
+    ///
+    /// [`Error::NotSupported`]: enum.Error.html#variant.NotSupported
+    /// [`TextLayoutBuilder::build`]: trait.TextLayoutBuilder.html#tymethod.build
+    WordSpacing(f64),
+    /// A vertical offset applied to the text, in points, as for superscripts
+    /// and subscripts. Positive values raise the text.
+    ///
+    /// This does not affect the [`LineMetric`]s of the layout.
+    ///
+    /// [`LineMetric`]: struct.LineMetric.html
+    BaselineShift(f64),
+    /// The OpenType [`FontFeatures`] used to draw the text.
+    ///
+    /// Backends that do not yet support this will return [`Error::NotSupported`]
+    /// from [`TextLayoutBuilder::build`] if any features are set.
+    ///
+    /// [`FontFeatures`]: struct.FontFeatures.html
+    /// [`Error::NotSupported`]: enum.Error.html#variant.NotSupported
+    /// [`TextLayoutBuilder::build`]: trait.TextLayoutBuilder.html#tymethod.build
+    FontFeatures(FontFeatures),
+    /// A [`FontFamilyList`], for text that may need glyphs missing from
+    /// the primary family.
+    ///
+    /// This replaces any family set with `Font`, and vice versa.
+    ///
+    /// [`FontFamilyList`]: struct.FontFamilyList.html
+    FontFamilies(FontFamilyList),
+    /// The language of the text, as a [BCP 47] language tag such as `"en-US"`
+    /// or `"zh-Hans"`.
+    ///
+    /// This affects things like the glyphs chosen for characters that are
+    /// written differently in different languages, and the fonts used for
+    /// fallback. Text with no locale uses the system locale.
+    ///
+    /// [BCP 47]: https://tools.ietf.org/html/bcp47
+    Locale(Arc<str>),
+    /// A [`ShadowStyle`] drawn beneath the glyphs of the text.
+    ///
+    /// The shadows of a layout are drawn before any of its glyphs, over its
+    /// backgrounds, and are included in its [`image_bounds`]. Decorations
+    /// such as underlines don't cast shadows.
+    ///
+    /// [`ShadowStyle`]: struct.ShadowStyle.html
+    /// [`image_bounds`]: trait.TextLayout.html#tymethod.image_bounds
+    Shadow(ShadowStyle),
 }
 
 pub trait TextLayoutBuilder: Sized {
@@ -189,6 +622,112 @@ pub trait TextLayoutBuilder: Sized {
     /// [`TextAlignment`]: enum.TextAlignment.html
     fn alignment(self, alignment: TextAlignment) -> Self;
 
+    /// Set the base [`TextDirection`] of this layout.
+    ///
+    /// This is the direction of the paragraph as a whole; it determines the
+    /// order in which runs of text with different directions are laid out,
+    /// and which edge `TextAlignment::Start` and `TextAlignment::End` refer to.
+    ///
+    /// The default is `TextDirection::Auto`.
+    ///
+    /// [`TextDirection`]: enum.TextDirection.html
+    fn text_direction(self, direction: TextDirection) -> Self;
+
+    /// Set the [`TextOrientation`] of this layout.
+    ///
+    /// In a vertical layout, the text in each line runs from top to bottom,
+    /// and successive lines are placed to the left of the previous one. The
+    /// width set with [`max_width`] is then the height of each line, and
+    /// [`TextLayout::size`] reports the width of all the lines together and
+    /// the height of the tallest.
+    ///
+    /// Points passed to and returned from the hit testing methods are in the
+    /// layout's coordinate space, as it is drawn; [`LineMetric`]s are in the
+    /// rotated space of the lines, so that `height` is the width of a line and
+    /// `y_offset` is its distance from the right edge of the layout.
+    ///
+    /// Backends that do not support vertical text will return
+    /// [`Error::NotSupported`] from [`build`]. The default is
+    /// `TextOrientation::Horizontal`.
+    ///
+    /// [`TextOrientation`]: enum.TextOrientation.html
+    /// [`max_width`]: #tymethod.max_width
+    /// [`TextLayout::size`]: trait.TextLayout.html#tymethod.size
+    /// [`LineMetric`]: struct.LineMetric.html
+    /// [`Error::NotSupported`]: enum.Error.html#variant.NotSupported
+    /// [`build`]: #tymethod.build
+    fn orientation(self, orientation: TextOrientation) -> Self;
+
+    /// Set the [`LineSpacing`] to be used for this layout.
+    ///
+    /// Backends that do not yet support this will return [`Error::NotSupported`]
+    /// from [`build`] if anything other than `LineSpacing::Default` is requested.
+    ///
+    /// [`LineSpacing`]: enum.LineSpacing.html
+    /// [`Error::NotSupported`]: enum.Error.html#variant.NotSupported
+    /// [`build`]: #tymethod.build
+    fn line_spacing(self, spacing: LineSpacing) -> Self;
+
+    /// Set the maximum number of lines in this layout.
+    ///
+    /// Text that does not fit in this many lines is truncated according to
+    /// the [`TruncationMode`] set with [`truncate`]. [`TextLayout::line_count`]
+    /// will never exceed this limit, and the `end_offset` of the last visible
+    /// line is the end of the visible text; hit testing past this point maps
+    /// to the end of the visible text.
+    ///
+    /// A value of `0` is treated as `1`.
+    ///
+    /// [`TruncationMode`]: enum.TruncationMode.html
+    /// [`truncate`]: #tymethod.truncate
+    /// [`TextLayout::line_count`]: trait.TextLayout.html#tymethod.line_count
+    fn max_lines(self, max_lines: usize) -> Self;
+
+    /// Set how text is truncated when it exceeds the limit set with [`max_lines`].
+    ///
+    /// The default is `TruncationMode::Clip`.
+    ///
+    /// [`max_lines`]: #tymethod.max_lines
+    fn truncate(self, mode: TruncationMode) -> Self;
+
+    /// Set the [`TabStops`] used to position text following a tab character.
+    ///
+    /// If this is not set, the backend's default tab behaviour is used.
+    ///
+    /// [`TabStops`]: enum.TabStops.html
+    fn tab_stops(self, stops: TabStops) -> Self;
+
+    /// Set the positions at which lines may be broken when wrapping, in
+    /// place of the backend's usual soft break opportunities.
+    ///
+    /// `breaks` is called with the text of the layout, and returns the byte
+    /// offsets at which a new line may begin; an empty list means the text
+    /// only wraps at mandatory breaks, such as newlines, which are always
+    /// taken. Offsets that are not char boundaries inside the text are ignored.
+    ///
+    /// Backends that cannot change their line breaking will return
+    /// [`Error::NotSupported`] from [`build`] if this is set.
+    ///
+    /// # Examples
+    ///
+    /// Wrapping a path only after its separators:
+    ///
+    /// ```
+    /// # use piet::*;
+    /// # let mut ctx = NullRenderContext::new();
+    /// # let mut text = ctx.text();
+    /// let layout = text.new_text_layout("/usr/local/share/fonts/truetype")
+    ///     .max_width(100.0)
+    ///     .break_opportunities(|text| {
+    ///         text.match_indices('/').map(|(idx, _)| idx + 1).collect()
+    ///     })
+    ///     .build();
+    /// ```
+    ///
+    /// [`Error::NotSupported`]: enum.Error.html#variant.NotSupported
+    /// [`build`]: #tymethod.build
+    fn break_opportunities(self, breaks: impl FnOnce(&str) -> Vec<usize>) -> Self;
+
     /// A convenience method for setting the default font family and size.
     ///
     /// # Examples
@@ -215,6 +754,17 @@ pub trait TextLayoutBuilder: Sized {
             .default_attribute(TextAttribute::Size(font_size))
     }
 
+    /// A convenience method for setting the default locale of the text, as a
+    /// [BCP 47] language tag.
+    ///
+    /// This is equivalent to setting a default [`TextAttribute::Locale`].
+    ///
+    /// [BCP 47]: https://tools.ietf.org/html/bcp47
+    /// [`TextAttribute::Locale`]: enum.TextAttribute.html#variant.Locale
+    fn locale(self, locale: &str) -> Self {
+        self.default_attribute(TextAttribute::Locale(locale.into()))
+    }
+
     /// Add a default [`TextAttribute`] for this layout.
     ///
     /// Default attributes will be used for regions of the layout that do not
@@ -283,6 +833,7 @@ pub trait TextLayoutBuilder: Sized {
 ///
 /// [`TextLayout`]: trait.TextLayout.html
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TextAlignment {
     /// Text is aligned to the left edge in left-to-right scripts, and the
     /// right edge in right-to-left scripts.
@@ -294,6 +845,149 @@ pub enum TextAlignment {
     Justified,
 }
 
+/// The base direction of the text in a [`TextLayout`].
+///
+/// [`TextLayout`]: trait.TextLayout.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextDirection {
+    /// The text is laid out left-to-right.
+    LeftToRight,
+    /// The text is laid out right-to-left.
+    RightToLeft,
+    /// The direction is that of the first character with a strong direction,
+    /// or left-to-right if there is no such character.
+    ///
+    /// This is the 'first strong' heuristic from the [Unicode Bidirectional
+    /// Algorithm](https://unicode.org/reports/tr9/#P2).
+    #[default]
+    Auto,
+}
+
+/// The orientation of the lines in a [`TextLayout`].
+///
+/// [`TextLayout`]: trait.TextLayout.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextOrientation {
+    /// Lines run left to right or right to left, and are stacked from top
+    /// to bottom.
+    Horizontal,
+    /// Lines run from top to bottom, and are stacked from right to left, as
+    /// in traditional Chinese and Japanese typesetting.
+    VerticalRightToLeft,
+}
+
+/// Which side of a text position a cursor is associated with.
+///
+/// A position at a soft line break is both the end of one line and the start
+/// of the next; the affinity determines on which of those lines the cursor
+/// is drawn. A hit on the trailing half of a grapheme is upstream, and a hit
+/// on its leading half is downstream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Affinity {
+    /// The position is associated with the text before it.
+    Upstream,
+    /// The position is associated with the text after it.
+    Downstream,
+}
+
+/// The style of the line drawn for [`TextAttribute::Underline`].
+///
+/// [`TextAttribute::Underline`]: enum.TextAttribute.html#variant.Underline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnderlineStyle {
+    /// A single continuous line.
+    #[default]
+    Solid,
+    /// A line of square dots.
+    Dotted,
+    /// A line of short dashes.
+    Dashed,
+    /// A wavy line, as used to mark spelling errors.
+    Wavy,
+}
+
+/// The spacing between lines in a [`TextLayout`].
+///
+/// When the line height is changed, the extra space (which may be negative)
+/// is divided evenly above and below the line, as with the CSS `line-height`
+/// property.
+///
+/// [`TextLayout`]: trait.TextLayout.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineSpacing {
+    /// The line height suggested by the font.
+    Default,
+    /// A multiple of the line height suggested by the font.
+    Multiple(f64),
+    /// A fixed line height, in display points.
+    Exact(f64),
+}
+
+/// How text is truncated when it exceeds the maximum number of lines in a
+/// [`TextLayout`].
+///
+/// [`TextLayout`]: trait.TextLayout.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TruncationMode {
+    /// Lines past the limit are not displayed.
+    Clip,
+    /// Lines past the limit are not displayed, and the end of the last
+    /// visible line is replaced with an ellipsis ('…').
+    EllipsisEnd,
+}
+
+/// The positions to which text following a tab character (`'\t'`) advances
+/// in a [`TextLayout`].
+///
+/// Positions are in display points, measured from the leading edge of the line.
+///
+/// [`TextLayout`]: trait.TextLayout.html
+#[derive(Debug, Clone, PartialEq)]
+pub enum TabStops {
+    /// A stop at every multiple of this interval.
+    Uniform(f64),
+    /// Stops at each of these positions, which should be in increasing order.
+    ///
+    /// Past the last position, stops continue at the interval between the
+    /// last two positions (or, if there is only one, at multiples of it.)
+    Explicit(Vec<f64>),
+}
+
+impl TabStops {
+    /// The interval between stops past the last explicit position.
+    pub fn interval(&self) -> f64 {
+        match self {
+            TabStops::Uniform(interval) => *interval,
+            TabStops::Explicit(stops) => match stops.as_slice() {
+                [] => 0.0,
+                [only] => *only,
+                [.., prev, last] => last - prev,
+            },
+        }
+    }
+
+    /// Returns the position of the first stop after `x`.
+    ///
+    /// If there is no such stop (for instance if the interval is not positive)
+    /// this returns `x`.
+    pub fn next_stop(&self, x: f64) -> f64 {
+        let mut last = 0.0;
+        if let TabStops::Explicit(stops) = self {
+            if let Some(stop) = stops.iter().find(|stop| **stop > x) {
+                return *stop;
+            }
+            last = stops.last().copied().unwrap_or(0.0);
+        }
+
+        let interval = self.interval();
+        if interval <= 0.0 || !interval.is_finite() {
+            return x;
+        }
+        let count = ((x - last) / interval).floor() + 1.0;
+        last + count.max(1.0) * interval
+    }
+}
+
 /// # Text Layout
 ///
 /// ## Line Breaks
@@ -328,10 +1022,20 @@ pub enum TextAlignment {
 /// - If the text position is not at a code point or grapheme boundary, undesirable behavior may
 /// occur.
 ///
+/// ## Threads
+///
+/// With the `send-layouts` feature, text layouts are `Send`, so they can be
+/// built on a background thread and drawn on the thread that owns the render
+/// context. This is supported by the cairo, coregraphics, direct2d and svg
+/// backends; Pango layouts can't be sent, so cairo uses its toy text with the
+/// feature, even if its `pango` feature is enabled. The web backend is
+/// single-threaded, and its layouts hold on to the canvas context, so it does
+/// not support the feature.
+///
 /// [`update_width`]: trait.TextLayout.html#tymethod.update_width
 /// [`LineMetric`]: struct.LineMetric.html
 ///
-pub trait TextLayout: Clone {
+pub trait TextLayout: Clone + MaybeSend {
     /// Measure the advance width of the text.
     #[deprecated(since = "0.2.0", note = "Use size().width insead")]
     fn width(&self) -> f64;
@@ -369,6 +1073,25 @@ pub trait TextLayout: Clone {
     /// Given a line number, return a reference to that line's underlying string.
     fn line_text(&self, line_number: usize) -> Option<&str>;
 
+    /// Given a line number, return the utf-8 range of that line in the
+    /// string returned by [`text`].
+    ///
+    /// The range includes the line's trailing whitespace, and the trailing
+    /// newline if there is one; it is the same as the [`LineMetric::range`]
+    /// of the line. Consecutive lines' ranges are contiguous.
+    ///
+    /// The default implementation finds the range of [`line_text`] within
+    /// [`text`]; backends override it to use the offsets they already store.
+    ///
+    /// [`text`]: #tymethod.text
+    /// [`line_text`]: #tymethod.line_text
+    /// [`LineMetric::range`]: struct.LineMetric.html#method.range
+    fn line_text_range(&self, line_number: usize) -> Option<Range<usize>> {
+        let line = self.line_text(line_number)?;
+        let start = line.as_ptr() as usize - self.text().as_ptr() as usize;
+        Some(start..start + line.len())
+    }
+
     /// Given a line number, return a reference to that line's metrics.
     fn line_metric(&self, line_number: usize) -> Option<LineMetric>;
 
@@ -430,49 +1153,152 @@ pub trait TextLayout: Clone {
     //layout's text?`
     fn hit_test_text_position(&self, idx: usize) -> Option<HitTestPosition>;
 
+    /// Given a text position and its [`Affinity`], returns a
+    /// [`HitTestPosition`][] describing where a cursor at that position is
+    /// drawn.
+    ///
+    /// A downstream position is the same as [`hit_test_text_position`]. At a
+    /// soft line break, an upstream position is placed at the end of the line
+    /// that wraps, instead of at the start of the next line; every backend
+    /// does this.
+    ///
+    /// Where the text direction changes within a line, an upstream position
+    /// is placed at the trailing edge of the preceding grapheme in the cairo,
+    /// direct2d and pango backends. The null, coregraphics, web and svg
+    /// backends handle only soft line breaks, using
+    /// [`util::hit_test_text_position_with_affinity`].
+    ///
+    /// [`Affinity`]: enum.Affinity.html
+    /// [`HitTestPosition`]: struct.HitTestPosition.html
+    /// [`hit_test_text_position`]: #tymethod.hit_test_text_position
+    /// [`util::hit_test_text_position_with_affinity`]: util/fn.hit_test_text_position_with_affinity.html
+    fn hit_test_text_position_with_affinity(
+        &self,
+        idx: usize,
+        affinity: Affinity,
+    ) -> Option<HitTestPosition>;
+
+    /// Returns the text position of the grapheme cluster boundary after
+    /// `text_position`, or `None` if it is at the end of the text.
+    ///
+    /// This is the position of the cursor after moving it one grapheme to
+    /// the right, in left-to-right text. The boundaries are the same as those
+    /// used by [`hit_test_point`] and [`hit_test_text_position`].
+    ///
+    /// [`hit_test_point`]: #tymethod.hit_test_point
+    /// [`hit_test_text_position`]: #tymethod.hit_test_text_position
+    fn next_grapheme_offset(&self, text_position: usize) -> Option<usize> {
+        crate::util::next_grapheme_offset(self.text(), text_position)
+    }
+
+    /// Returns the text position of the grapheme cluster boundary before
+    /// `text_position`, or `None` if it is at the start of the text.
+    fn prev_grapheme_offset(&self, text_position: usize) -> Option<usize> {
+        crate::util::prev_grapheme_offset(self.text(), text_position)
+    }
+
+    /// Returns the text position of the end of the next word after
+    /// `text_position`, or `None` if it is at the end of the text.
+    ///
+    /// Whitespace and punctuation between words are skipped; see
+    /// [`util::next_word_offset`] for the details.
+    ///
+    /// [`util::next_word_offset`]: util/fn.next_word_offset.html
+    fn next_word_offset(&self, text_position: usize) -> Option<usize> {
+        crate::util::next_word_offset(self.text(), text_position)
+    }
+
+    /// Returns the text position of the start of the previous word before
+    /// `text_position`, or `None` if it is at the start of the text.
+    fn prev_word_offset(&self, text_position: usize) -> Option<usize> {
+        crate::util::prev_word_offset(self.text(), text_position)
+    }
+
     /// Returns a vector of `Rect`s that cover the region of the text indicated
     /// by `range`.
     ///
     /// The returned rectangles are suitable for things like drawing selection
-    /// regions or highlights.
+    /// regions or highlights. Each covers the full height of its line; there is
+    /// one for each visual run of the text (such as a run of right-to-left
+    /// text) that is part of the range, ordered by line and then from left to
+    /// right. If the range includes a newline, that is represented by a thin
+    /// rect at the end of its line.
     ///
-    /// `range` will be clamped to the length of the text if necessary.
+    /// `range` will be clamped to the length of the text if necessary, and
+    /// expanded to the nearest grapheme cluster boundaries.
     ///
-    /// Note: this implementation is not currently BiDi aware; it will be updated
-    /// when BiDi support is added.
+    /// Note: the default implementation is not BiDi aware, and assumes that
+    /// each line is a single left-to-right run; backends should override it.
     fn rects_for_range(&self, range: impl RangeBounds<usize>) -> Vec<Rect> {
-        let text_len = self.text().len();
-        let mut range = crate::util::resolve_range(range, text_len);
-        range.start = range.start.min(text_len);
-        range.end = range.end.min(text_len);
-
-        let first_line = self.hit_test_text_position(range.start).unwrap().line;
-        let last_line = self.hit_test_text_position(range.end).unwrap().line;
+        let lines = (0..self.line_count())
+            .filter_map(|line| self.line_metric(line))
+            .collect::<Vec<_>>();
 
         let mut result = Vec::new();
-
-        for line in first_line..=last_line {
-            let metrics = self.line_metric(line).unwrap();
-            let y0 = metrics.y_offset;
-            let y1 = y0 + metrics.height;
-            let line_range_start = if line == first_line {
-                range.start
-            } else {
-                metrics.start_offset
+        for (line, range, newline) in crate::util::line_ranges(self.text(), &lines, range) {
+            let metrics = &lines[line];
+            let x = |pos| {
+                self.hit_test_text_position(pos)
+                    // the end of a wrapped line is also the start of the next line
+                    .filter(|hit| hit.line == line)
+                    .map(|hit| hit.point.x)
+                    .unwrap_or_else(|| self.size().width)
             };
-
-            let line_range_end = if line == last_line {
-                range.end
-            } else {
-                metrics.end_offset - metrics.trailing_whitespace
-            };
-            let start_point = self.hit_test_text_position(line_range_start).unwrap();
-            let end_point = self.hit_test_text_position(line_range_end).unwrap();
-            result.push(Rect::new(start_point.point.x, y0, end_point.point.x, y1));
+            let x1 = x(range.end);
+            if !range.is_empty() {
+                let x0 = x(range.start);
+                let y0 = metrics.y_offset;
+                result.push(Rect::new(x0, y0, x1, y0 + metrics.height));
+            }
+            if newline {
+                result.push(crate::util::newline_rect(metrics, x1));
+            }
         }
-
         result
     }
+
+    /// Returns the shaped glyphs of this layout, as a list of [`GlyphRun`]s
+    /// that are each drawn with a single font.
+    ///
+    /// This is intended for things like exporting text to other formats, or
+    /// drawing effects that need the outlines of individual glyphs. The runs
+    /// are in no particular order; glyph positions are relative to the
+    /// layout's origin.
+    ///
+    /// The runs are made the first time they are needed, and kept until the
+    /// layout changes, so this is cheap enough to call whenever the layout
+    /// is drawn.
+    ///
+    /// Backends that cannot provide this information return an empty slice;
+    /// this is the default implementation, and is currently the case for the
+    /// web backend. The null and svg backends only have glyphs for text in
+    /// fonts loaded with [`Text::load_font`].
+    ///
+    /// [`Text::load_font`]: trait.Text.html#method.load_font
+    ///
+    /// [`GlyphRun`]: struct.GlyphRun.html
+    fn glyph_runs(&self) -> &[GlyphRun] {
+        &[]
+    }
+
+    /// Returns the outlines of the glyphs in this layout, as a single path
+    /// relative to the layout's origin.
+    ///
+    /// The path can be stroked or filled like any other shape, for instance
+    /// to draw outlined text with [`RenderContext::stroke`]. Decorations such
+    /// as underlines and backgrounds are not included.
+    ///
+    /// Backends that cannot provide glyph outlines return an empty path; this
+    /// is the default implementation, and is currently the case for the web
+    /// backend. The null and svg backends only have outlines for text in
+    /// TrueType fonts loaded with [`Text::load_font`].
+    ///
+    /// [`Text::load_font`]: trait.Text.html#method.load_font
+    ///
+    /// [`RenderContext::stroke`]: trait.RenderContext.html#tymethod.stroke
+    fn outline(&self) -> BezPath {
+        BezPath::new()
+    }
 }
 
 /// Metadata about each line in a text layout.
@@ -497,6 +1323,13 @@ pub struct LineMetric {
     /// The length of the trailing whitespace at the end of this line, in utf-8 code units.
     pub trailing_whitespace: usize,
 
+    /// The width of the trailing whitespace at the end of this line, not
+    /// including any newline.
+    ///
+    /// This is the distance from the end of the line's visible text to the
+    /// position after its trailing spaces, as used to place a cursor after them.
+    pub trailing_whitespace_width: f64,
+
     /// The distance from the top of the line (`y_offset`) to the baseline.
     pub baseline: f64,
 
@@ -526,6 +1359,71 @@ impl LineMetric {
     }
 }
 
+/// Metrics of a font at a particular size, in display points.
+///
+/// Ascent, descent and the heights are distances from the baseline, and are
+/// positive. The positions of the underline and strikeout are the offsets
+/// from the baseline to the centers of their lines, with positive values
+/// below the baseline, as in layout coordinates; the strikeout position is
+/// usually negative.
+///
+/// Some backends don't have access to all of these values, and approximate
+/// them from the font's size or extents.
+///
+/// This type is returned by [`Text::font_metrics`].
+///
+/// [`Text::font_metrics`]: trait.Text.html#method.font_metrics
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FontMetrics {
+    /// The distance from the baseline to the top of the font's line box.
+    pub ascent: f64,
+    /// The distance from the baseline to the bottom of the font's line box.
+    pub descent: f64,
+    /// The height of capital letters, such as 'H', above the baseline.
+    pub cap_height: f64,
+    /// The height of lowercase letters without ascenders, such as 'x',
+    /// above the baseline.
+    pub x_height: f64,
+    /// The offset from the baseline to the center of the underline.
+    pub underline_position: f64,
+    /// The thickness of the underline.
+    pub underline_thickness: f64,
+    /// The offset from the baseline to the center of the strikeout line.
+    pub strikeout_position: f64,
+    /// The thickness of the strikeout line.
+    pub strikeout_thickness: f64,
+}
+
+/// A sequence of shaped glyphs drawn with a single font.
+///
+/// This type is returned by [`TextLayout::glyph_runs`].
+///
+/// [`TextLayout::glyph_runs`]: trait.TextLayout.html#method.glyph_runs
+#[derive(Clone, Debug, PartialEq)]
+pub struct GlyphRun {
+    /// The font family the glyphs were resolved to, after any font fallback.
+    pub font: FontFamily,
+    /// The size of the font, in display points.
+    pub font_size: f64,
+    /// The glyphs, in the order they are drawn.
+    pub glyphs: Vec<Glyph>,
+}
+
+/// A single shaped glyph in a [`GlyphRun`].
+///
+/// [`GlyphRun`]: struct.GlyphRun.html
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Glyph {
+    /// The glyph's identifier in its font.
+    pub id: u32,
+    /// The origin of the glyph on the baseline, relative to the origin of
+    /// the layout.
+    pub position: Point,
+    /// The utf-8 index, in the layout's text, of the start of the cluster
+    /// this glyph belongs to.
+    pub cluster: usize,
+}
+
 /// Result of hit testing a point in a [`TextLayout`].
 ///
 /// This type is returned by [`TextLayout::hit_test_point`].
@@ -544,6 +1442,18 @@ pub struct HitTestPoint {
     /// end of that line, and a click below the last line will resolve to a
     /// position in that line.
     pub is_inside: bool,
+    /// Whether the point was on the trailing half of the grapheme before
+    /// `idx` ([`Affinity::Upstream`]), or the leading half of the grapheme
+    /// after it.
+    ///
+    /// This distinguishes a click at the end of a line that wraps from a
+    /// click at the start of the following line, which have the same `idx`;
+    /// pass it to [`TextLayout::hit_test_text_position_with_affinity`] to
+    /// find the corresponding cursor position.
+    ///
+    /// [`Affinity::Upstream`]: enum.Affinity.html#variant.Upstream
+    /// [`TextLayout::hit_test_text_position_with_affinity`]: trait.TextLayout.html#tymethod.hit_test_text_position_with_affinity
+    pub affinity: Affinity,
 }
 
 /// Result of hit testing a text position in a [`TextLayout`].
@@ -575,8 +1485,12 @@ pub struct HitTestPosition {
 impl HitTestPoint {
     /// Only for use by backends
     #[doc(hidden)]
-    pub fn new(idx: usize, is_inside: bool) -> HitTestPoint {
-        HitTestPoint { idx, is_inside }
+    pub fn new(idx: usize, is_inside: bool, affinity: Affinity) -> HitTestPoint {
+        HitTestPoint {
+            idx,
+            is_inside,
+            affinity,
+        }
     }
 }
 
@@ -594,18 +1508,59 @@ impl From<FontFamily> for TextAttribute {
     }
 }
 
+impl From<FontFamilyList> for TextAttribute {
+    fn from(families: FontFamilyList) -> TextAttribute {
+        TextAttribute::FontFamilies(families)
+    }
+}
+
 impl From<FontWeight> for TextAttribute {
     fn from(src: FontWeight) -> TextAttribute {
         TextAttribute::Weight(src)
     }
 }
 
+impl From<FontFeatures> for TextAttribute {
+    fn from(features: FontFeatures) -> TextAttribute {
+        TextAttribute::FontFeatures(features)
+    }
+}
+
+impl From<UnderlineStyle> for TextAttribute {
+    fn from(style: UnderlineStyle) -> TextAttribute {
+        TextAttribute::UnderlineStyle(style)
+    }
+}
+
+impl From<ShadowStyle> for TextAttribute {
+    fn from(shadow: ShadowStyle) -> TextAttribute {
+        TextAttribute::Shadow(shadow)
+    }
+}
+
 impl Default for TextAlignment {
     fn default() -> Self {
         TextAlignment::Start
     }
 }
 
+impl TextDirection {
+    /// Returns `true` if `text` is laid out right-to-left in this direction.
+    pub fn is_rtl(self, text: &str) -> bool {
+        match self {
+            TextDirection::LeftToRight => false,
+            TextDirection::RightToLeft => true,
+            TextDirection::Auto => crate::util::first_strong_rtl(text),
+        }
+    }
+}
+
+impl Default for Affinity {
+    fn default() -> Self {
+        Affinity::Downstream
+    }
+}
+
 impl Default for FontWeight {
     fn default() -> Self {
         FontWeight::REGULAR
@@ -1,9 +1,23 @@
 //! Code useful for multiple backends
 
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 use std::ops::{Bound, Range, RangeBounds};
+use std::sync::Arc;
 
-use crate::kurbo::{Rect, Size};
-use crate::{Color, FontFamily, FontWeight, LineMetric, TextAttribute};
+use unic_bidi::bidi_class::{BidiClass, BidiClassCategory};
+use unicode_segmentation::{GraphemeCursor, UnicodeSegmentation};
+
+use crate::gradient::lerp_color;
+use crate::kurbo::{flatten, Affine, BezPath, PathEl, Point, Rect, Shape, Size};
+use crate::{
+    Affinity, Color, ColorInterpolation, Error, FixedGradient, FixedLinearGradient,
+    FixedRadialGradient, FontFamily, FontFeatures, FontWeight, GradientStop, GradientStops,
+    HitTestPosition, ImageFormat, LineMetric, LineSpacing, ShadowStyle, SnapMode, StrokeStyle,
+    TextAttribute, TextLayout, UnderlineStyle,
+};
 
 /// The default point sie for text in piet.
 pub const DEFAULT_FONT_SIZE: f64 = 12.0;
@@ -11,6 +25,9 @@ pub const DEFAULT_FONT_SIZE: f64 = 12.0;
 /// The default foreground text color.
 pub const DEFAULT_TEXT_COLOR: Color = Color::BLACK;
 
+/// The string drawn at the end of a line truncated with `TruncationMode::EllipsisEnd`.
+pub const ELLIPSIS: &str = "\u{2026}";
+
 /// Counts the number of utf-16 code units in the given string.
 /// from xi-editor
 pub fn count_utf16(s: &str) -> usize {
@@ -61,6 +78,221 @@ pub fn line_number_for_position(lines: &[LineMetric], position: usize) -> usize
     }
 }
 
+/// A heuristic for text direction; returns `true` if, while enumerating characters
+/// in this string, a character in the 'R' (strong right-to-left) category is
+/// encountered before any character in the 'L' (strong left-to-right) category is.
+///
+/// This is used to resolve `TextDirection::Auto`.
+///
+/// See [Unicode technical report 9](https://unicode.org/reports/tr9/#Table_Bidirectional_Character_Types).
+pub fn first_strong_rtl(text: &str) -> bool {
+    text.chars()
+        // an upper bound on how many chars we'll check
+        .take(200)
+        .map(BidiClass::of)
+        .find(|c| c.category() == BidiClassCategory::Strong)
+        .map(|c| c.is_rtl())
+        .unwrap_or(false)
+}
+
+/// A [`TextLayout::hit_test_text_position_with_affinity`] built on
+/// [`TextLayout::hit_test_text_position`], for backends that have no better
+/// information: an upstream position at a soft line break is placed at the
+/// end of the wrapped line, and any other position is left as is.
+///
+/// [`TextLayout::hit_test_text_position_with_affinity`]: ../trait.TextLayout.html#tymethod.hit_test_text_position_with_affinity
+/// [`TextLayout::hit_test_text_position`]: ../trait.TextLayout.html#tymethod.hit_test_text_position
+pub fn hit_test_text_position_with_affinity<L: TextLayout>(
+    layout: &L,
+    idx: usize,
+    affinity: Affinity,
+) -> Option<HitTestPosition> {
+    let hit = layout.hit_test_text_position(idx)?;
+    if affinity == Affinity::Downstream || hit.line == 0 {
+        return Some(hit);
+    }
+    let line = layout.line_metric(hit.line)?;
+    let prev = layout.line_metric(hit.line - 1)?;
+    let prev_text = layout.text().get(prev.range())?;
+    if idx != line.start_offset || prev_text.ends_with(&['\n', '\r'][..]) {
+        return Some(hit);
+    }
+
+    let rects = layout.rects_for_range(prev.range());
+    if rects.is_empty() {
+        return Some(hit);
+    }
+    let x = if first_strong_rtl(prev_text) {
+        rects.iter().map(|r| r.x0).fold(f64::INFINITY, f64::min)
+    } else {
+        rects.iter().map(|r| r.x1).fold(f64::NEG_INFINITY, f64::max)
+    };
+    let point = Point::new(x, prev.y_offset + prev.baseline);
+    Some(HitTestPosition::new(point, hit.line - 1))
+}
+
+/// Returns the grapheme cluster boundary after `offset` in `text`, or `None`
+/// if `offset` is at the end of the text.
+///
+/// `offset` must be a char boundary.
+pub fn next_grapheme_offset(text: &str, offset: usize) -> Option<usize> {
+    GraphemeCursor::new(offset, text.len(), true)
+        .next_boundary(text, 0)
+        .ok()
+        .flatten()
+}
+
+/// Returns the grapheme cluster boundary before `offset` in `text`, or `None`
+/// if `offset` is at the start of the text.
+///
+/// `offset` must be a char boundary.
+pub fn prev_grapheme_offset(text: &str, offset: usize) -> Option<usize> {
+    GraphemeCursor::new(offset, text.len(), true)
+        .prev_boundary(text, 0)
+        .ok()
+        .flatten()
+}
+
+/// Returns the end of the first word that ends after `offset` in `text`, or the
+/// end of the text if no word follows; returns `None` if `offset` is at the
+/// end of the text.
+///
+/// Words are found with the Unicode word boundary rules ([UAX #29]); segments
+/// that are only whitespace or punctuation are skipped, as when moving the
+/// cursor to the next word in a text editor.
+///
+/// [UAX #29]: https://www.unicode.org/reports/tr29/#Word_Boundaries
+pub fn next_word_offset(text: &str, offset: usize) -> Option<usize> {
+    if offset >= text.len() {
+        return None;
+    }
+    text.split_word_bound_indices()
+        .map(|(start, word)| (start + word.len(), word))
+        .find(|(end, word)| *end > offset && is_word(word))
+        .map(|(end, _)| end)
+        .or(Some(text.len()))
+}
+
+/// Returns the start of the last word that starts before `offset` in `text`,
+/// or the start of the text if no word precedes it; returns `None` if
+/// `offset` is at the start of the text.
+///
+/// See [`next_word_offset`] for how words are found.
+///
+/// [`next_word_offset`]: fn.next_word_offset.html
+pub fn prev_word_offset(text: &str, offset: usize) -> Option<usize> {
+    if offset == 0 {
+        return None;
+    }
+    let offset = offset.min(text.len());
+    text[..offset]
+        .split_word_bound_indices()
+        .rev()
+        .find(|(_, word)| is_word(word))
+        .map(|(start, _)| start)
+        .or(Some(0))
+}
+
+fn is_word(segment: &str) -> bool {
+    segment.chars().any(char::is_alphanumeric)
+}
+
+/// Expands a range of `text` outwards to the nearest grapheme cluster boundaries.
+pub fn snap_to_graphemes(text: &str, range: Range<usize>) -> Range<usize> {
+    let mut start = range.start.min(text.len());
+    let mut end = range.end.min(text.len()).max(start);
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+    while !text.is_char_boundary(end) {
+        end += 1;
+    }
+
+    let mut cursor = GraphemeCursor::new(start, text.len(), true);
+    if !cursor.is_boundary(text, 0).unwrap_or(true) {
+        start = cursor.prev_boundary(text, 0).ok().flatten().unwrap_or(0);
+    }
+    let mut cursor = GraphemeCursor::new(end, text.len(), true);
+    if !cursor.is_boundary(text, 0).unwrap_or(true) {
+        end = cursor
+            .next_boundary(text, 0)
+            .ok()
+            .flatten()
+            .unwrap_or(text.len());
+    }
+    start..end
+}
+
+/// Splits a range of text into its parts on each line of a layout, for finding
+/// the regions covered by the range, as in [`TextLayout::rects_for_range`].
+///
+/// The range is first expanded to grapheme cluster boundaries. Each item is
+/// the number of a line, the part of the range on that line, not including
+/// the line's newline, and whether the range includes that newline.
+///
+/// [`TextLayout::rects_for_range`]: ../trait.TextLayout.html#method.rects_for_range
+pub fn line_ranges(
+    text: &str,
+    lines: &[LineMetric],
+    range: impl RangeBounds<usize>,
+) -> Vec<(usize, Range<usize>, bool)> {
+    let range = snap_to_graphemes(text, resolve_range(range, text.len()));
+    if range.is_empty() || lines.is_empty() {
+        return Vec::new();
+    }
+
+    let first_line = line_number_for_position(lines, range.start);
+    lines
+        .iter()
+        .enumerate()
+        .skip(first_line)
+        .take_while(|(_, lm)| lm.start_offset < range.end)
+        .filter_map(|(line_number, lm)| {
+            let line_text = &text[lm.range()];
+            let newline_len = if line_text.ends_with("\r\n") {
+                2
+            } else if line_text.ends_with('\n') {
+                1
+            } else {
+                0
+            };
+            let content_end = lm.end_offset - newline_len;
+            let start = range.start.max(lm.start_offset);
+            let end = range.end.min(content_end).max(start);
+            let newline =
+                newline_len > 0 && range.start <= content_end && range.end >= lm.end_offset;
+            if start < end || newline {
+                Some((line_number, start..end, newline))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// The rect representing a selected newline, a quarter of the line's height
+/// wide, starting at `x`.
+pub fn newline_rect(line: &LineMetric, x: f64) -> Rect {
+    Rect::new(
+        x,
+        line.y_offset,
+        x + line.height / 4.0,
+        line.y_offset + line.height,
+    )
+}
+
+/// Sorts the line break offsets returned by a
+/// [`TextLayoutBuilder::break_opportunities`] callback, removing duplicates
+/// and any offsets that are not char boundaries strictly inside `text`.
+///
+/// [`TextLayoutBuilder::break_opportunities`]: ../trait.TextLayoutBuilder.html#tymethod.break_opportunities
+pub fn resolve_break_opportunities(text: &str, mut breaks: Vec<usize>) -> Vec<usize> {
+    breaks.retain(|&offset| offset > 0 && offset < text.len() && text.is_char_boundary(offset));
+    breaks.sort_unstable();
+    breaks.dedup();
+    breaks
+}
+
 /// Resolves a `RangeBounds` into a range in the range 0..len.
 pub fn resolve_range(range: impl RangeBounds<usize>, len: usize) -> Range<usize> {
     let start = match range.start_bound() {
@@ -78,9 +310,614 @@ pub fn resolve_range(range: impl RangeBounds<usize>, len: usize) -> Range<usize>
     start.min(len)..end.min(len)
 }
 
+/// Resolves an attribute with a value, such as a background color, into the
+/// sorted, non-overlapping list of ranges and the value active in each.
+///
+/// `default` is the value used for the whole text, if any, and `spans` are the
+/// range attributes, in the order they were added; later spans replace earlier ones.
+pub fn resolve_spans<T: Clone>(
+    default: Option<T>,
+    len: usize,
+    spans: impl IntoIterator<Item = (Range<usize>, T)>,
+) -> Vec<(Range<usize>, T)> {
+    let mut active = Vec::new();
+    if let Some(default) = default {
+        if len > 0 {
+            active.push((0..len, default));
+        }
+    }
+
+    for (span, value) in spans {
+        let mut next = Vec::with_capacity(active.len() + 2);
+        for (range, prev) in active.drain(..) {
+            if range.end <= span.start || range.start >= span.end {
+                next.push((range, prev));
+                continue;
+            }
+            if range.start < span.start {
+                next.push((range.start..span.start, prev.clone()));
+            }
+            if range.end > span.end {
+                next.push((span.end..range.end, prev));
+            }
+        }
+        if !span.is_empty() {
+            next.push((span, value));
+        }
+        next.sort_by_key(|(r, _)| r.start);
+        active = next;
+    }
+    active
+}
+
+/// Resolves a boolean attribute, such as underline or strikethrough, into
+/// the sorted, non-overlapping list of ranges in which it is active.
+///
+/// `default` is the value used for the whole text, and `spans` are the range
+/// attributes, in the order they were added; later spans replace earlier ones.
+pub fn resolve_flag_ranges(
+    default: bool,
+    len: usize,
+    spans: impl IntoIterator<Item = (Range<usize>, bool)>,
+) -> Vec<Range<usize>> {
+    let active = resolve_spans(Some(default), len, spans)
+        .into_iter()
+        .filter(|(_, flag)| *flag)
+        .map(|(range, _)| range);
+
+    // merge adjacent ranges
+    let mut result: Vec<Range<usize>> = Vec::new();
+    for range in active {
+        match result.last_mut() {
+            Some(last) if last.end >= range.start => last.end = last.end.max(range.end),
+            _ => result.push(range),
+        }
+    }
+    result
+}
+
+/// Resolves the underline attributes into the sorted, non-overlapping list of
+/// underlined ranges, each with its style and its color, if that differs from
+/// the foreground color.
+///
+/// `defaults` provides the values for the whole text, and the other arguments
+/// are the range attributes of each kind, in the order they were added.
+pub fn resolve_underlines(
+    defaults: &LayoutDefaults,
+    len: usize,
+    underlines: impl IntoIterator<Item = (Range<usize>, bool)>,
+    colors: impl IntoIterator<Item = (Range<usize>, Color)>,
+    styles: impl IntoIterator<Item = (Range<usize>, UnderlineStyle)>,
+) -> Vec<(Range<usize>, UnderlineStyle, Option<Color>)> {
+    let colors = resolve_spans(defaults.underline_color.clone(), len, colors);
+    let styles = resolve_spans(Some(defaults.underline_style), len, styles);
+    let mut result = Vec::new();
+    for range in resolve_flag_ranges(defaults.underline, len, underlines) {
+        let mut bounds = vec![range.start, range.end];
+        let span_bounds = colors
+            .iter()
+            .map(|(span, _)| span)
+            .chain(styles.iter().map(|(span, _)| span))
+            .flat_map(|span| vec![span.start, span.end]);
+        bounds.extend(span_bounds.filter(|pos| range.contains(pos)));
+        bounds.sort_unstable();
+        bounds.dedup();
+
+        for pair in bounds.windows(2) {
+            let style = styles
+                .iter()
+                .find(|(span, _)| span.contains(&pair[0]))
+                .map(|(_, style)| *style)
+                .unwrap_or_default();
+            let color = colors
+                .iter()
+                .find(|(span, _)| span.contains(&pair[0]))
+                .map(|(_, color)| color.clone());
+            result.push((pair[0]..pair[1], style, color));
+        }
+    }
+    result
+}
+
+/// Returns the path of an underline with the given style from `x0` to `x1`,
+/// centered vertically on `y`, along with the `StrokeStyle` it should be
+/// stroked with, at a width of `thickness`.
+///
+/// This is intended for backends that draw their own underlines. A wavy line
+/// extends `thickness` above and below `y`.
+pub fn underline_geometry(
+    style: UnderlineStyle,
+    x0: f64,
+    x1: f64,
+    y: f64,
+    thickness: f64,
+) -> (BezPath, StrokeStyle) {
+    let mut path = BezPath::new();
+    let mut stroke = StrokeStyle::new();
+    path.move_to((x0, y));
+    match style {
+        UnderlineStyle::Solid => path.line_to((x1, y)),
+        UnderlineStyle::Dotted => {
+            stroke.set_dash(vec![thickness, thickness], 0.0);
+            path.line_to((x1, y));
+        }
+        UnderlineStyle::Dashed => {
+            stroke.set_dash(vec![thickness * 3.0, thickness * 2.0], 0.0);
+            path.line_to((x1, y));
+        }
+        UnderlineStyle::Wavy => {
+            // each half-period is a quadratic curve; its control point is twice
+            // as far from the center as the curve's peak.
+            let half_period = thickness * 2.0;
+            let mut x = x0;
+            let mut up = true;
+            while x < x1 {
+                let next = (x + half_period).min(x1);
+                let ctrl_y = if up {
+                    y - thickness * 2.0
+                } else {
+                    y + thickness * 2.0
+                };
+                path.quad_to(((x + next) / 2.0, ctrl_y), (next, y));
+                x = next;
+                up = !up;
+            }
+        }
+    }
+    (path, stroke)
+}
+
+/// Returns `true` if `c` is a space separator (Unicode general category `Zs`),
+/// the characters affected by `TextAttribute::WordSpacing`.
+pub fn is_space_separator(c: char) -> bool {
+    matches!(
+        c,
+        ' ' | '\u{A0}' | '\u{1680}' | '\u{2000}'
+            ..='\u{200A}' | '\u{202F}' | '\u{205F}' | '\u{3000}'
+    )
+}
+
+/// Given the natural height and baseline of a line, returns the height and
+/// baseline that result from applying the given `LineSpacing`.
+pub fn resolve_line_spacing(spacing: LineSpacing, height: f64, baseline: f64) -> (f64, f64) {
+    let new_height = match spacing {
+        LineSpacing::Default => return (height, baseline),
+        LineSpacing::Multiple(factor) => height * factor,
+        LineSpacing::Exact(new_height) => new_height,
+    };
+    (new_height, baseline + (new_height - height) / 2.0)
+}
+
 /// Extent to which to expand the blur.
 const BLUR_EXTENT: f64 = 2.5;
 
+/// Expand the stops of a gradient that uses [`ColorInterpolation::LinearRgb`]
+/// or [`ColorInterpolation::Oklab`] into stops that can be interpolated in
+/// sRGB, which is all that backends support.
+///
+/// The stops are also [normalized] first, so backends can rely on them
+/// being sorted, and covering `0.0..=1.0`. The returned gradient always uses
+/// [`ColorInterpolation::Srgb`].
+///
+/// [normalized]: ../trait.GradientStops.html#method.normalized
+/// [`ColorInterpolation::LinearRgb`]: ../enum.ColorInterpolation.html#variant.LinearRgb
+/// [`ColorInterpolation::Oklab`]: ../enum.ColorInterpolation.html#variant.Oklab
+/// [`ColorInterpolation::Srgb`]: ../enum.ColorInterpolation.html#variant.Srgb
+pub fn resolve_color_interpolation(mut gradient: FixedGradient) -> FixedGradient {
+    let (stops, interpolation) = match &mut gradient {
+        FixedGradient::Linear(linear) => (&mut linear.stops, &mut linear.interpolation),
+        FixedGradient::Radial(radial) => (&mut radial.stops, &mut radial.interpolation),
+        FixedGradient::Sweep(sweep) => (&mut sweep.stops, &mut sweep.interpolation),
+    };
+    *stops = std::mem::take(stops).normalized();
+    let subdivisions = match *interpolation {
+        ColorInterpolation::Srgb => return gradient,
+        ColorInterpolation::LinearRgb { subdivisions } => subdivisions,
+        ColorInterpolation::Oklab { subdivisions } => subdivisions,
+    };
+    *stops = subdivide_stops(stops, subdivisions.max(1), *interpolation);
+    *interpolation = ColorInterpolation::Srgb;
+    gradient
+}
+
+fn subdivide_stops(
+    stops: &[GradientStop],
+    subdivisions: usize,
+    interpolation: ColorInterpolation,
+) -> Vec<GradientStop> {
+    let mut result = Vec::with_capacity(stops.len() * subdivisions);
+    for pair in stops.windows(2) {
+        let (lo, hi) = (&pair[0], &pair[1]);
+        result.push(lo.clone());
+        if lo.pos < hi.pos {
+            for i in 1..subdivisions {
+                let t = i as f64 / subdivisions as f64;
+                result.push(GradientStop {
+                    pos: lo.pos + (hi.pos - lo.pos) * t as f32,
+                    color: lerp_color(&lo.color, &hi.color, t, interpolation),
+                });
+            }
+        }
+    }
+    result.extend(stops.last().cloned());
+    result
+}
+
+/// A key for [`RenderContext::make_brush_cached`], from a hash of the
+/// gradient.
+///
+/// [`RenderContext::make_brush_cached`]: ../trait.RenderContext.html#method.make_brush_cached
+pub fn gradient_key(gradient: &FixedGradient) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    let mut hash_f64s = |values: &[f64]| values.iter().for_each(|v| v.to_bits().hash(&mut hasher));
+    let (stops, extend, interpolation) = match gradient {
+        FixedGradient::Linear(linear) => {
+            hash_f64s(&[
+                0.0,
+                linear.start.x,
+                linear.start.y,
+                linear.end.x,
+                linear.end.y,
+            ]);
+            (&linear.stops, Some(linear.extend), linear.interpolation)
+        }
+        FixedGradient::Radial(radial) => {
+            hash_f64s(&[
+                1.0,
+                radial.center.x,
+                radial.center.y,
+                radial.origin_offset.x,
+                radial.origin_offset.y,
+                radial.radii.x,
+                radial.radii.y,
+            ]);
+            (&radial.stops, Some(radial.extend), radial.interpolation)
+        }
+        FixedGradient::Sweep(sweep) => {
+            hash_f64s(&[2.0, sweep.center.x, sweep.center.y, sweep.start_angle]);
+            (&sweep.stops, None, sweep.interpolation)
+        }
+    };
+    for stop in stops {
+        stop.pos.to_bits().hash(&mut hasher);
+        stop.color.as_rgba_u32().hash(&mut hasher);
+    }
+    extend.map(|extend| extend as u8).hash(&mut hasher);
+    match interpolation {
+        ColorInterpolation::Srgb => (0, 0),
+        ColorInterpolation::LinearRgb { subdivisions } => (1, subdivisions),
+        ColorInterpolation::Oklab { subdivisions } => (2, subdivisions),
+    }
+    .hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A bounded cache of brushes, for backends that implement
+/// [`RenderContext::make_brush_cached`].
+///
+/// When the cache is full, the least recently used brush is dropped.
+///
+/// [`RenderContext::make_brush_cached`]: ../trait.RenderContext.html#method.make_brush_cached
+pub struct BrushCache<B> {
+    /// Each brush, and the time it was last used.
+    entries: HashMap<u64, (B, u64)>,
+    capacity: usize,
+    clock: u64,
+}
+
+impl<B: Clone> BrushCache<B> {
+    /// The number of brushes a default cache holds.
+    pub const DEFAULT_CAPACITY: usize = 256;
+
+    /// Create an empty cache, holding at most `capacity` brushes.
+    pub fn new(capacity: usize) -> BrushCache<B> {
+        BrushCache {
+            entries: HashMap::new(),
+            capacity: capacity.max(1),
+            clock: 0,
+        }
+    }
+
+    /// Get the brush for `key`, if it is cached.
+    pub fn get(&mut self, key: u64) -> Option<B> {
+        self.clock += 1;
+        let (brush, used) = self.entries.get_mut(&key)?;
+        *used = self.clock;
+        Some(brush.clone())
+    }
+
+    /// Add the brush for `key`, dropping the least recently used brush if
+    /// the cache is full.
+    pub fn insert(&mut self, key: u64, brush: B) {
+        self.clock += 1;
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            let oldest = self.entries.iter().min_by_key(|(_, (_, used))| *used);
+            if let Some(oldest) = oldest.map(|(&key, _)| key) {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(key, (brush, self.clock));
+    }
+
+    /// Get the brush for `key`, creating it with `make` if it isn't cached.
+    pub fn get_or_try_insert_with<E>(
+        &mut self,
+        key: u64,
+        make: impl FnOnce() -> Result<B, E>,
+    ) -> Result<B, E> {
+        if let Some(brush) = self.get(key) {
+            return Ok(brush);
+        }
+        let brush = make()?;
+        self.insert(key, brush.clone());
+        Ok(brush)
+    }
+
+    /// The number of cached brushes.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if no brushes are cached.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Drop all of the cached brushes.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl<B: Clone> Default for BrushCache<B> {
+    fn default() -> Self {
+        BrushCache::new(Self::DEFAULT_CAPACITY)
+    }
+}
+
+/// The opacities pushed with [`RenderContext::push_opacity`], for backends
+/// to keep.
+///
+/// [`RenderContext::push_opacity`]: ../trait.RenderContext.html#tymethod.push_opacity
+#[derive(Clone, Debug, Default)]
+pub struct OpacityStack {
+    /// The opacity after each push, including the ones before it.
+    stack: Vec<f64>,
+}
+
+impl OpacityStack {
+    /// Create an empty stack, with an opacity of 1.
+    pub fn new() -> OpacityStack {
+        OpacityStack::default()
+    }
+
+    /// Multiply the opacity by `alpha`, clamped to `0.0..=1.0`; NaN is
+    /// treated as 0.
+    pub fn push(&mut self, alpha: f64) {
+        let alpha = if alpha.is_nan() {
+            0.0
+        } else {
+            alpha.clamp(0.0, 1.0)
+        };
+        self.stack.push(self.current() * alpha);
+    }
+
+    /// Undo the last `push`.
+    pub fn pop(&mut self) -> Result<(), Error> {
+        self.stack.pop().map(drop).ok_or(Error::StackUnbalance)
+    }
+
+    /// The product of the pushed opacities.
+    pub fn current(&self) -> f64 {
+        self.stack.last().copied().unwrap_or(1.0)
+    }
+
+    /// Returns `true` if the opacity is less than 1.
+    pub fn is_translucent(&self) -> bool {
+        self.current() < 1.0
+    }
+}
+
+/// The range of whole gradient periods needed to cover `rect` with a
+/// repeating or reflecting linear gradient.
+///
+/// This is for backends that have to draw repeated gradients by hand.
+pub fn linear_gradient_periods(gradient: &FixedLinearGradient, rect: Rect) -> Range<i64> {
+    let axis = gradient.end - gradient.start;
+    let len2 = axis.hypot2();
+    if len2 == 0.0 {
+        return 0..1;
+    }
+    let (mut min, mut max) = (f64::INFINITY, f64::NEG_INFINITY);
+    for &(x, y) in &[
+        (rect.x0, rect.y0),
+        (rect.x1, rect.y0),
+        (rect.x0, rect.y1),
+        (rect.x1, rect.y1),
+    ] {
+        let t = (Point::new(x, y) - gradient.start).dot(axis) / len2;
+        min = min.min(t);
+        max = max.max(t);
+    }
+    let (first, last) = (min.floor() as i64, max.ceil() as i64);
+    first..last.max(first + 1)
+}
+
+/// The range of whole gradient periods needed to cover `rect` with a
+/// repeating or reflecting radial gradient.
+///
+/// Period `k` is the band between the ellipses at `k` and `k + 1` times the
+/// radii. If the origin lies outside the ellipse, the gradient is a cone
+/// and cannot be usefully repeated, and only the first period is returned.
+pub fn radial_gradient_periods(gradient: &FixedRadialGradient, rect: Rect) -> Range<i64> {
+    let (circle, transform) = gradient.to_circular();
+    let origin = circle.center + circle.origin_offset;
+    let shrink = circle.radii.x - circle.origin_offset.hypot();
+    if shrink <= 0.0 {
+        return 0..1;
+    }
+    let inverse = transform.inverse();
+    let mut max: f64 = 1.0;
+    for &(x, y) in &[
+        (rect.x0, rect.y0),
+        (rect.x1, rect.y0),
+        (rect.x0, rect.y1),
+        (rect.x1, rect.y1),
+    ] {
+        max = max.max((inverse * Point::new(x, y) - origin).hypot() / shrink);
+    }
+    0..(max.ceil() as i64)
+}
+
+/// Check that a pixel buffer with the given row stride holds an image of
+/// the given size, returning the length of a row without padding.
+///
+/// Returns `Error::InvalidInput` if `stride` is shorter than a row, or if
+/// `buf_len` is too short for `height` rows.
+pub fn check_image_stride(
+    width: usize,
+    height: usize,
+    stride: usize,
+    buf_len: usize,
+    format: ImageFormat,
+) -> Result<usize, Error> {
+    let row_len = width
+        .checked_mul(format.bytes_per_pixel())
+        .ok_or(Error::InvalidInput)?;
+    if stride < row_len {
+        return Err(Error::InvalidInput);
+    }
+    // the last row doesn't need to be padded
+    let needed = match height {
+        0 => 0,
+        _ => stride
+            .checked_mul(height - 1)
+            .and_then(|len| len.checked_add(row_len))
+            .ok_or(Error::InvalidInput)?,
+    };
+    if buf_len < needed {
+        return Err(Error::InvalidInput);
+    }
+    Ok(row_len)
+}
+
+/// The rows of a pixel buffer with the given stride, without padding.
+///
+/// The buffer is only copied if rows are padded.
+pub fn pack_image_rows(
+    width: usize,
+    height: usize,
+    stride: usize,
+    buf: &[u8],
+    format: ImageFormat,
+) -> Result<Cow<'_, [u8]>, Error> {
+    let row_len = check_image_stride(width, height, stride, buf.len(), format)?;
+    if stride == row_len {
+        return Ok(Cow::Borrowed(&buf[..row_len * height]));
+    }
+    let mut packed = Vec::with_capacity(row_len * height);
+    for row in buf.chunks(stride).take(height) {
+        packed.extend_from_slice(&row[..row_len]);
+    }
+    Ok(Cow::Owned(packed))
+}
+
+/// Snap a stroke to device pixels, for a [`StrokeStyle`] with a
+/// `snap_mode`.
+///
+/// `transform` maps user space to device pixels. Returns the path and width
+/// to stroke instead; if the transform isn't axis-aligned, these are the
+/// shape and width unchanged.
+///
+/// [`StrokeStyle`]: ../struct.StrokeStyle.html
+pub fn snap_stroke(
+    shape: impl Shape,
+    width: f64,
+    mode: SnapMode,
+    transform: Affine,
+) -> (BezPath, f64) {
+    let path = BezPath::from_vec(shape.to_bez_path(1e-3).collect());
+    let [a, b, c, d, _, _] = transform.as_coeffs();
+    let axis_aligned = (b == 0.0 && c == 0.0) || (a == 0.0 && d == 0.0);
+    if !axis_aligned || transform.determinant() == 0.0 {
+        return (path, width);
+    }
+    match mode {
+        SnapMode::HalfPixel => {
+            // the width of the stroke across each device axis
+            let device_x = width * (a.abs() + c.abs());
+            let device_y = width * (b.abs() + d.abs());
+            // odd widths are centered on pixel centers, even widths on edges
+            let offset = |w: f64| (w.round().max(1.0) / 2.0).fract();
+            let (dx, dy) = (offset(device_x), offset(device_y));
+            let inverse = transform.inverse();
+            let snap = |p: Point| {
+                let p = transform * p;
+                let p = Point::new((p.x - dx).round() + dx, (p.y - dy).round() + dy);
+                inverse * p
+            };
+            let path = path
+                .iter()
+                .map(|el| match el {
+                    PathEl::MoveTo(p) => PathEl::MoveTo(snap(p)),
+                    PathEl::LineTo(p) => PathEl::LineTo(snap(p)),
+                    PathEl::QuadTo(p1, p2) => PathEl::QuadTo(snap(p1), snap(p2)),
+                    PathEl::CurveTo(p1, p2, p3) => PathEl::CurveTo(snap(p1), snap(p2), snap(p3)),
+                    PathEl::ClosePath => PathEl::ClosePath,
+                })
+                .collect();
+            let width = if (device_x - device_y).abs() < 1e-9 {
+                width * device_x.round().max(1.0) / device_x
+            } else {
+                width
+            };
+            (BezPath::from_vec(path), width)
+        }
+    }
+}
+
+/// The bounds of a clip after clipping to a shape with bounds `shape_bounds`,
+/// in the user space of `transform`.
+///
+/// Clip bounds are kept in the space that `transform` maps to, so that they
+/// can be carried through later transforms; `None` means unclipped.
+pub fn intersect_clip_bounds(clip: Option<Rect>, shape_bounds: Rect, transform: Affine) -> Rect {
+    let bounds = transform.transform_rect_bbox(shape_bounds);
+    match clip {
+        Some(clip) => clip.intersect(bounds),
+        None => bounds,
+    }
+}
+
+/// Clip bounds, as tracked by [`intersect_clip_bounds`], in the user space
+/// of `transform`.
+///
+/// [`intersect_clip_bounds`]: fn.intersect_clip_bounds.html
+pub fn user_clip_bounds(clip: Option<Rect>, transform: Affine) -> Option<Rect> {
+    let clip = clip?;
+    // nothing drawn through a degenerate transform is visible
+    if transform.determinant() == 0.0 {
+        return Some(Rect::ZERO);
+    }
+    Some(transform.inverse().transform_rect_bbox(clip))
+}
+
+/// The device pixels to copy for a capture of `rect`, on a surface of
+/// `size` device pixels, where `transform` maps user space to device space.
+///
+/// The rect is rounded out to whole pixels and clipped to the surface.
+/// Returns `Error::InvalidInput` if nothing is left.
+pub fn capture_rect(rect: Rect, transform: Affine, size: Size) -> Result<Rect, Error> {
+    let rect = transform
+        .transform_rect_bbox(rect.abs())
+        .expand()
+        .intersect(size.to_rect());
+    if rect.area() == 0.0 {
+        return Err(Error::InvalidInput);
+    }
+    Ok(rect)
+}
+
 pub fn size_for_blurred_rect(rect: Rect, radius: f64) -> Size {
     let padding = BLUR_EXTENT * radius;
     let rect_padded = rect.inflate(padding, padding);
@@ -118,6 +955,274 @@ pub fn compute_blurred_rect(rect: Rect, radius: f64, stride: usize, buf: &mut [u
     rect_exp
 }
 
+pub fn size_for_blurred_shape(shape: &impl Shape, radius: f64) -> Size {
+    size_for_blurred_rect(shape.bounding_box(), radius)
+}
+
+/// Generate image for a blurred shape, writing it into the provided buffer.
+///
+/// Rects are computed exactly, as in [`compute_blurred_rect`]; for other
+/// shapes, the coverage is rasterized and then blurred with three box blurs,
+/// which is a close approximation of a Gaussian blur.
+///
+/// [`compute_blurred_rect`]: fn.compute_blurred_rect.html
+pub fn compute_blurred_shape(
+    shape: &impl Shape,
+    radius: f64,
+    stride: usize,
+    buf: &mut [u8],
+) -> Rect {
+    if let Some(rect) = shape.as_rect() {
+        return compute_blurred_rect(rect, radius, stride, buf);
+    }
+    blur_coverage(shape, shape.bounding_box(), None, radius, stride, buf)
+}
+
+/// The size of the image for [`compute_blurred_shape_in_rects`].
+///
+/// [`compute_blurred_shape_in_rects`]: fn.compute_blurred_shape_in_rects.html
+pub fn size_for_blurred_shape_in_rects(shape: &impl Shape, rects: &[Rect], radius: f64) -> Size {
+    size_for_blurred_rect(bounds_in_rects(shape, rects), radius)
+}
+
+/// Generate image for a blurred shape, as with [`compute_blurred_shape`],
+/// including only the parts of the shape inside `rects`.
+///
+/// This is used for text shadows, restricting the outlines of a layout's
+/// glyphs to the rects of the shadowed ranges.
+///
+/// [`compute_blurred_shape`]: fn.compute_blurred_shape.html
+pub fn compute_blurred_shape_in_rects(
+    shape: &impl Shape,
+    rects: &[Rect],
+    radius: f64,
+    stride: usize,
+    buf: &mut [u8],
+) -> Rect {
+    let bounds = bounds_in_rects(shape, rects);
+    blur_coverage(shape, bounds, Some(rects), radius, stride, buf)
+}
+
+/// Returns the bounds of a layout's ink, with its text shadows.
+///
+/// Each shadow is taken to cover all of the ink, offset and grown by the
+/// extent of its blur, which is a conservative bound for shadows of ranges.
+pub fn bounds_with_shadows<'a>(
+    bounds: Rect,
+    shadows: impl IntoIterator<Item = &'a ShadowStyle>,
+) -> Rect {
+    if bounds.area() == 0.0 {
+        return bounds;
+    }
+    shadows.into_iter().fold(bounds, |acc, shadow| {
+        let padding = BLUR_EXTENT * shadow.blur;
+        acc.union((bounds + shadow.offset).inflate(padding, padding))
+    })
+}
+
+/// The intersection of the bounds of a shape with the union of `rects`.
+fn bounds_in_rects(shape: &impl Shape, rects: &[Rect]) -> Rect {
+    let union = rects.iter().fold(None, |acc: Option<Rect>, rect| {
+        Some(acc.map_or(*rect, |acc| acc.union(*rect)))
+    });
+    let bounds = union.unwrap_or_default().intersect(shape.bounding_box());
+    // `intersect` gives an inverted rect if they don't overlap
+    if bounds.width() < 0.0 || bounds.height() < 0.0 {
+        Rect::ZERO
+    } else {
+        bounds
+    }
+}
+
+/// Rasterize and blur the part of a shape inside `bounds`, and inside
+/// `rects` if they are given, writing it into the provided buffer.
+fn blur_coverage(
+    shape: &impl Shape,
+    bounds: Rect,
+    rects: Option<&[Rect]>,
+    radius: f64,
+    stride: usize,
+    buf: &mut [u8],
+) -> Rect {
+    let padding = BLUR_EXTENT * radius;
+    let rect_exp = bounds.inflate(padding, padding).expand();
+    let width = rect_exp.width() as usize;
+    let height = rect_exp.height() as usize;
+    let offset = Affine::translate(-rect_exp.origin().to_vec2());
+    let path = offset * BezPath::from_vec(shape.to_bez_path(1e-3).collect());
+    let mut coverage = rasterize_coverage(path, width, height);
+    if let Some(rects) = rects {
+        // keep the pixels whose centers are inside one of the rects
+        for j in 0..height {
+            for i in 0..width {
+                let center = Point::new(rect_exp.x0 + i as f64 + 0.5, rect_exp.y0 + j as f64 + 0.5);
+                if !rects.iter().any(|rect| rect.contains(center)) {
+                    coverage[j * width + i] = 0.0;
+                }
+            }
+        }
+    }
+    // `compute_blurred_rect` integrates erf(x / radius), which is a Gaussian
+    // with a standard deviation of radius / sqrt(2).
+    let std_dev = radius * std::f64::consts::FRAC_1_SQRT_2;
+    for box_width in boxes_for_gaussian(std_dev).iter() {
+        box_blur(&mut coverage, width, height, 1, width, *box_width);
+        box_blur(&mut coverage, height, width, width, 1, *box_width);
+    }
+    for j in 0..height {
+        for i in 0..width {
+            buf[j * stride + i] = (255.0 * coverage[j * width + i]).round() as u8;
+        }
+    }
+    rect_exp
+}
+
+/// Rasterize the coverage of a path, with the nonzero fill rule, into a
+/// `width` by `height` buffer.
+///
+/// This accumulates the signed area covered by each line, in the manner of
+/// font-rs; overlapping subpaths saturate rather than being counted exactly.
+fn rasterize_coverage(path: BezPath, width: usize, height: usize) -> Vec<f64> {
+    // an extra cell absorbs area pushed past the last pixel
+    let mut acc = vec![0.0; width * height + 1];
+    let mut start = Point::ZERO;
+    let mut last = Point::ZERO;
+    flatten(path, 0.1, |el| match el {
+        PathEl::MoveTo(p) => {
+            accumulate_line(&mut acc, width, height, last, start);
+            start = p;
+            last = p;
+        }
+        PathEl::LineTo(p) => {
+            accumulate_line(&mut acc, width, height, last, p);
+            last = p;
+        }
+        PathEl::ClosePath => {
+            accumulate_line(&mut acc, width, height, last, start);
+            last = start;
+        }
+        // flattening only produces lines
+        _ => (),
+    });
+    accumulate_line(&mut acc, width, height, last, start);
+
+    let mut sum = 0.0;
+    acc.truncate(width * height);
+    for cell in acc.iter_mut() {
+        sum += *cell;
+        *cell = sum.abs().min(1.0);
+    }
+    acc
+}
+
+/// Add the signed area to the right of a line to each cell it crosses.
+fn accumulate_line(acc: &mut [f64], width: usize, height: usize, p0: Point, p1: Point) {
+    if p0.y == p1.y {
+        return;
+    }
+    let (dir, p0, p1) = if p0.y < p1.y {
+        (1.0, p0, p1)
+    } else {
+        (-1.0, p1, p0)
+    };
+    let dxdy = (p1.x - p0.x) / (p1.y - p0.y);
+    let y0 = p0.y.max(0.0);
+    let mut x = p0.x + (y0 - p0.y) * dxdy;
+    let max_x = width as f64 - 1e-9;
+    for y in (y0 as usize)..height.min(p1.y.ceil() as usize) {
+        let row = y * width;
+        let dy = ((y + 1) as f64).min(p1.y) - (y as f64).max(p0.y);
+        let x_next = x + dxdy * dy;
+        let d = dy * dir;
+        // the caller keeps lines inside the buffer; this guards rounding
+        let (x0, x1) = if x < x_next { (x, x_next) } else { (x_next, x) };
+        let (x0, x1) = (x0.max(0.0).min(max_x), x1.max(0.0).min(max_x));
+        let x0_floor = x0.floor();
+        let x0i = x0_floor as usize;
+        let x1_ceil = x1.ceil();
+        let x1i = x1_ceil as usize;
+        if x1i <= x0i + 1 {
+            let xmf = 0.5 * (x0 + x1) - x0_floor;
+            acc[row + x0i] += d - d * xmf;
+            acc[row + x0i + 1] += d * xmf;
+        } else {
+            let s = (x1 - x0).recip();
+            let x0f = x0 - x0_floor;
+            let a0 = 0.5 * s * (1.0 - x0f) * (1.0 - x0f);
+            let x1f = x1 - x1_ceil + 1.0;
+            let am = 0.5 * s * x1f * x1f;
+            acc[row + x0i] += d * a0;
+            if x1i == x0i + 2 {
+                acc[row + x0i + 1] += d * (1.0 - a0 - am);
+            } else {
+                let a1 = s * (1.5 - x0f);
+                acc[row + x0i + 1] += d * (a1 - a0);
+                for xi in x0i + 2..x1i - 1 {
+                    acc[row + xi] += d * s;
+                }
+                let a2 = a1 + (x1i - x0i - 3) as f64 * s;
+                acc[row + x1i - 1] += d * (1.0 - a2 - am);
+            }
+            acc[row + x1i] += d * am;
+        }
+        x = x_next;
+    }
+}
+
+/// The widths of three successive box blurs approximating a Gaussian blur
+/// with the given standard deviation.
+fn boxes_for_gaussian(std_dev: f64) -> [usize; 3] {
+    let n = 3.0;
+    let variance = std_dev * std_dev;
+    let ideal = (12.0 * variance / n + 1.0).sqrt();
+    // the largest odd width not above the ideal
+    let lower = (ideal.floor() as usize).saturating_sub(1) | 1;
+    let upper = lower + 2;
+    let l = lower as f64;
+    let n_lower = ((12.0 * variance - n * l * l - 4.0 * n * l - 3.0 * n) / (-4.0 * l - 4.0))
+        .round()
+        .max(0.0) as usize;
+    let mut boxes = [upper; 3];
+    for width in boxes.iter_mut().take(n_lower) {
+        *width = lower;
+    }
+    boxes
+}
+
+/// Blur `count` lines of `len` values each with a box of the given odd
+/// width, treating values outside the buffer as zero.
+///
+/// `step` is the distance between values in a line, and `line_step` the
+/// distance between lines, so that the same code blurs rows and columns.
+fn box_blur(
+    buf: &mut [f64],
+    len: usize,
+    count: usize,
+    step: usize,
+    line_step: usize,
+    box_width: usize,
+) {
+    let half = box_width / 2;
+    let scale = (box_width as f64).recip();
+    let mut line = vec![0.0; len];
+    for l in 0..count {
+        let base = l * line_step;
+        for (i, value) in line.iter_mut().enumerate() {
+            *value = buf[base + i * step];
+        }
+        let mut sum: f64 = line.iter().take(half).sum();
+        for i in 0..len {
+            if i + half < len {
+                sum += line[i + half];
+            }
+            buf[base + i * step] = sum * scale;
+            if i >= half {
+                sum -= line[i - half];
+            }
+        }
+    }
+}
+
 // See https://raphlinus.github.io/audio/2018/09/05/sigmoid.html for a little
 // explanation of this approximation to the erf function.
 fn compute_erf7(x: f64) -> f64 {
@@ -131,23 +1236,53 @@ fn compute_erf7(x: f64) -> f64 {
 #[non_exhaustive]
 pub struct LayoutDefaults {
     pub font: FontFamily,
+    /// families to try, in order, for characters missing from `font`.
+    pub font_fallbacks: Vec<FontFamily>,
     pub font_size: f64,
     pub weight: FontWeight,
     pub fg_color: Color,
     pub italic: bool,
     pub underline: bool,
+    pub underline_color: Option<Color>,
+    pub underline_style: UnderlineStyle,
+    pub strikethrough: bool,
+    pub letter_spacing: f64,
+    pub word_spacing: f64,
+    pub background_color: Option<Color>,
+    pub baseline_shift: f64,
+    pub font_features: FontFeatures,
+    /// the BCP 47 language tag of the text; `None` means the system locale.
+    pub locale: Option<Arc<str>>,
+    pub shadow: Option<ShadowStyle>,
 }
 
 impl LayoutDefaults {
     /// Set the default value for a given `TextAttribute`.
     pub fn set(&mut self, val: impl Into<TextAttribute>) {
         match val.into() {
-            TextAttribute::Font(t) => self.font = t,
+            TextAttribute::Font(t) => {
+                self.font = t;
+                self.font_fallbacks.clear();
+            }
+            TextAttribute::FontFamilies(families) => {
+                self.font = families.primary().clone();
+                self.font_fallbacks = families.fallbacks().to_vec();
+            }
             TextAttribute::Size(size) => self.font_size = size,
             TextAttribute::Weight(weight) => self.weight = weight,
             TextAttribute::Italic(flag) => self.italic = flag,
             TextAttribute::Underline(flag) => self.underline = flag,
+            TextAttribute::UnderlineColor(color) => self.underline_color = Some(color),
+            TextAttribute::UnderlineStyle(style) => self.underline_style = style,
+            TextAttribute::Strikethrough(flag) => self.strikethrough = flag,
+            TextAttribute::LetterSpacing(spacing) => self.letter_spacing = spacing,
+            TextAttribute::WordSpacing(spacing) => self.word_spacing = spacing,
             TextAttribute::ForegroundColor(color) => self.fg_color = color,
+            TextAttribute::BackgroundColor(color) => self.background_color = Some(color),
+            TextAttribute::BaselineShift(shift) => self.baseline_shift = shift,
+            TextAttribute::FontFeatures(features) => self.font_features = features,
+            TextAttribute::Locale(locale) => self.locale = Some(locale),
+            TextAttribute::Shadow(shadow) => self.shadow = Some(shadow),
         }
     }
 }
@@ -156,11 +1291,22 @@ impl Default for LayoutDefaults {
     fn default() -> Self {
         LayoutDefaults {
             font: FontFamily::default(),
+            font_fallbacks: Vec::new(),
             font_size: DEFAULT_FONT_SIZE,
             weight: FontWeight::default(),
             fg_color: DEFAULT_TEXT_COLOR,
             italic: false,
             underline: false,
+            underline_color: None,
+            underline_style: UnderlineStyle::Solid,
+            strikethrough: false,
+            letter_spacing: 0.0,
+            word_spacing: 0.0,
+            background_color: None,
+            baseline_shift: 0.0,
+            font_features: FontFeatures::new(),
+            locale: None,
+            shadow: None,
         }
     }
 }
@@ -168,6 +1314,267 @@ impl Default for LayoutDefaults {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::kurbo::{Line, Vec2};
+    use crate::GradientExtend;
+
+    #[test]
+    fn test_pack_image_rows() {
+        let fmt = ImageFormat::Grayscale;
+        let buf = [1, 2, 0, 3, 4, 0, 5, 6];
+        assert_eq!(
+            &*pack_image_rows(2, 3, 3, &buf, fmt).unwrap(),
+            &[1, 2, 3, 4, 5, 6]
+        );
+        assert!(matches!(
+            pack_image_rows(2, 3, 2, &buf, fmt).unwrap(),
+            Cow::Borrowed(_)
+        ));
+        // stride too short for a row
+        assert!(pack_image_rows(2, 3, 1, &buf, fmt).is_err());
+        // buffer too short for the last row
+        assert!(pack_image_rows(2, 3, 3, &buf[..7], fmt).is_err());
+        assert!(pack_image_rows(1, 2, 4, &buf, ImageFormat::RgbaPremul).is_ok());
+        assert!(pack_image_rows(1, 2, 4, &buf[..7], ImageFormat::RgbaPremul).is_err());
+    }
+
+    #[test]
+    fn test_opacity_stack() {
+        let mut stack = OpacityStack::new();
+        assert_eq!(stack.current(), 1.0);
+        assert!(!stack.is_translucent());
+        stack.push(0.5);
+        stack.push(2.0);
+        assert_eq!(stack.current(), 0.5);
+        stack.push(0.5);
+        assert_eq!(stack.current(), 0.25);
+        assert!(stack.is_translucent());
+        stack.pop().unwrap();
+        stack.pop().unwrap();
+        stack.pop().unwrap();
+        assert_eq!(stack.current(), 1.0);
+        assert!(matches!(stack.pop(), Err(Error::StackUnbalance)));
+        stack.push(f64::NAN);
+        assert_eq!(stack.current(), 0.0);
+    }
+
+    #[test]
+    fn test_brush_cache() {
+        let mut cache = BrushCache::new(2);
+        let mut made = 0;
+        let mut get = |cache: &mut BrushCache<u64>, key| {
+            cache
+                .get_or_try_insert_with(key, || -> Result<_, ()> {
+                    made += 1;
+                    Ok(key * 10)
+                })
+                .unwrap()
+        };
+        assert_eq!(get(&mut cache, 1), 10);
+        assert_eq!(get(&mut cache, 2), 20);
+        assert_eq!(get(&mut cache, 1), 10);
+        // evicts 2, the least recently used
+        assert_eq!(get(&mut cache, 3), 30);
+        assert_eq!(get(&mut cache, 1), 10);
+        assert_eq!(get(&mut cache, 2), 20);
+        assert_eq!(made, 4);
+        assert_eq!(cache.len(), 2);
+        assert!(cache
+            .get_or_try_insert_with(4, || Err::<u64, _>(()))
+            .is_err());
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_gradient_key() {
+        let linear = FixedLinearGradient {
+            start: Point::new(0.0, 0.0),
+            end: Point::new(10.0, 0.0),
+            stops: GradientStops::to_vec((Color::WHITE, Color::BLACK)),
+            extend: GradientExtend::Pad,
+            interpolation: ColorInterpolation::Srgb,
+        };
+        let key = gradient_key(&linear.clone().into());
+        assert_eq!(key, gradient_key(&linear.clone().into()));
+        let repeat = FixedLinearGradient {
+            extend: GradientExtend::Repeat,
+            ..linear.clone()
+        };
+        assert_ne!(key, gradient_key(&repeat.into()));
+        let reversed = FixedLinearGradient {
+            stops: GradientStops::to_vec((Color::BLACK, Color::WHITE)),
+            ..linear
+        };
+        assert_ne!(key, gradient_key(&reversed.into()));
+    }
+
+    #[test]
+    fn test_snap_stroke() {
+        let line = Line::new((10.0, 10.0), (20.0, 10.0));
+        let points = |path: &BezPath| -> Vec<Point> {
+            path.elements()
+                .iter()
+                .filter_map(|el| match el {
+                    PathEl::MoveTo(p) | PathEl::LineTo(p) => Some(*p),
+                    _ => None,
+                })
+                .collect()
+        };
+        let snap = SnapMode::HalfPixel;
+        // odd widths go to pixel centers
+        let (path, width) = snap_stroke(line, 1.0, snap, Affine::default());
+        assert_eq!(
+            points(&path),
+            [Point::new(10.5, 10.5), Point::new(20.5, 10.5)]
+        );
+        assert_eq!(width, 1.0);
+        // even widths go to pixel edges
+        let (path, _) = snap_stroke(line, 2.0, snap, Affine::translate((0.3, 0.6)));
+        assert_eq!(
+            points(&path),
+            [Point::new(9.7, 10.4), Point::new(19.7, 10.4)]
+        );
+        // at 1.25x, a line one unit wide is one pixel wide
+        let scale = Affine::scale(1.25);
+        let (path, width) = snap_stroke(line, 1.0, snap, scale);
+        assert_eq!(width, 0.8);
+        for p in points(&path) {
+            let p = scale * p;
+            assert!((p.y.fract() - 0.5).abs() < 1e-9);
+        }
+        // rotations other than by right angles aren't snapped
+        let rotate = Affine::rotate(0.1);
+        let (path, width) = snap_stroke(line, 1.0, snap, rotate);
+        assert_eq!(
+            points(&path),
+            [Point::new(10.0, 10.0), Point::new(20.0, 10.0)]
+        );
+        assert_eq!(width, 1.0);
+    }
+
+    #[test]
+    fn test_clip_bounds() {
+        let scale = Affine::scale(2.0);
+        let clip = intersect_clip_bounds(None, Rect::new(10.0, 10.0, 50.0, 50.0), scale);
+        assert_eq!(clip, Rect::new(20.0, 20.0, 100.0, 100.0));
+        // a later clip in another space
+        let transform = scale * Affine::translate((20.0, 0.0));
+        let clip = intersect_clip_bounds(Some(clip), Rect::new(0.0, 0.0, 20.0, 20.0), transform);
+        assert_eq!(clip, Rect::new(40.0, 20.0, 80.0, 40.0));
+        assert_eq!(
+            user_clip_bounds(Some(clip), transform),
+            Some(Rect::new(0.0, 10.0, 20.0, 20.0))
+        );
+        assert_eq!(user_clip_bounds(None, transform), None);
+        // a rotated clip is bounded by its bounding box
+        let rotate = Affine::rotate(std::f64::consts::FRAC_PI_4);
+        let clip = intersect_clip_bounds(None, Rect::new(0.0, 0.0, 10.0, 10.0), rotate);
+        let bounds = user_clip_bounds(Some(clip), Affine::default()).unwrap();
+        assert!((bounds.width() - 200f64.sqrt()).abs() < 1e-9);
+        assert_eq!(
+            user_clip_bounds(Some(clip), Affine::scale(0.0)),
+            Some(Rect::ZERO)
+        );
+    }
+
+    #[test]
+    fn test_capture_rect() {
+        let size = Size::new(100.0, 50.0);
+        let rect = Rect::new(10.5, 10.5, 20.2, 20.0);
+        let scale = Affine::scale(2.0);
+        assert_eq!(
+            capture_rect(rect, Affine::default(), size).unwrap(),
+            Rect::new(10.0, 10.0, 21.0, 20.0)
+        );
+        assert_eq!(
+            capture_rect(rect, scale, size).unwrap(),
+            Rect::new(21.0, 21.0, 41.0, 40.0)
+        );
+        // clipped to the surface
+        let rect = Rect::new(90.0, -10.0, 120.0, 10.0);
+        assert_eq!(
+            capture_rect(rect, Affine::default(), size).unwrap(),
+            Rect::new(90.0, 0.0, 100.0, 10.0)
+        );
+        assert!(capture_rect(rect, Affine::translate((20.0, 0.0)), size).is_err());
+        assert!(capture_rect(Rect::ZERO, Affine::default(), size).is_err());
+    }
+
+    #[test]
+    fn test_blurred_shape() {
+        // as a path, a rect takes the approximate path
+        let rect = Rect::new(10.0, 10.0, 40.0, 30.0);
+        let path = BezPath::from_vec(rect.to_bez_path(1e-3).collect());
+        assert!(path.as_rect().is_none());
+        let radius = 3.0;
+        let size = size_for_blurred_shape(&path, radius);
+        assert_eq!(size, size_for_blurred_rect(rect, radius));
+        let len = (size.width * size.height) as usize;
+        let (mut exact, mut approx) = (vec![0; len], vec![0; len]);
+        let stride = size.width as usize;
+        let approx_rect = compute_blurred_shape(&path, radius, stride, &mut approx);
+        assert_eq!(approx_rect.size(), size);
+        // the exact blur samples at pixel corners rather than centers
+        let shifted = rect - Vec2::new(0.5, 0.5);
+        let exact_rect = compute_blurred_rect(shifted, radius, stride, &mut exact);
+        assert_eq!(exact_rect.origin(), approx_rect.origin());
+        for (i, (a, b)) in exact.iter().zip(approx.iter()).enumerate() {
+            let diff = (*a as i32 - *b as i32).abs();
+            assert!(diff <= 6, "pixel {}: {} vs {}", i, a, b);
+        }
+
+        // with no blur, the result is the coverage of the shape
+        let circle = crate::kurbo::Circle::new((5.0, 5.0), 4.0);
+        let mut buf = vec![0; 64];
+        assert_eq!(size_for_blurred_shape(&circle, 0.0), Size::new(8.0, 8.0));
+        let rect = compute_blurred_shape(&circle, 0.0, 8, &mut buf);
+        assert_eq!(rect, Rect::new(1.0, 1.0, 9.0, 9.0));
+        // the center, the edge of the circle, and a corner of its bounds
+        assert_eq!(buf[4 * 8 + 4], 255);
+        assert!(buf[4 * 8] > 0 && buf[4 * 8] < 255, "{}", buf[4 * 8]);
+        assert_eq!(buf[0], 0);
+    }
+
+    #[test]
+    fn test_gradient_periods() {
+        let linear = FixedLinearGradient {
+            start: Point::new(10.0, 0.0),
+            end: Point::new(20.0, 0.0),
+            stops: Vec::new(),
+            extend: GradientExtend::Repeat,
+            interpolation: ColorInterpolation::Srgb,
+        };
+        let rect = Rect::new(0.0, 0.0, 45.0, 10.0);
+        assert_eq!(linear_gradient_periods(&linear, rect), -1..4);
+
+        let radial = FixedRadialGradient {
+            center: Point::new(0.0, 0.0),
+            origin_offset: Vec2::ZERO,
+            radii: Vec2::new(10.0, 10.0),
+            stops: Vec::new(),
+            extend: GradientExtend::Repeat,
+            interpolation: ColorInterpolation::Srgb,
+        };
+        let rect = Rect::new(-30.0, -5.0, 25.0, 5.0);
+        assert_eq!(radial_gradient_periods(&radial, rect), 0..4);
+
+        let cone = FixedRadialGradient {
+            origin_offset: Vec2::new(20.0, 0.0),
+            ..radial
+        };
+        assert_eq!(radial_gradient_periods(&cone, rect), 0..1);
+
+        let ellipse = FixedRadialGradient {
+            radii: Vec2::new(10.0, 2.0),
+            ..cone
+        };
+        assert_eq!(radial_gradient_periods(&ellipse, rect), 0..1);
+        let ellipse = FixedRadialGradient {
+            origin_offset: Vec2::ZERO,
+            ..ellipse
+        };
+        assert_eq!(radial_gradient_periods(&ellipse, rect), 0..4);
+    }
 
     #[test]
     fn test_count_until_utf16() {
@@ -187,4 +1594,195 @@ mod tests {
 
         assert_eq!(count_until_utf16("", 0), None);
     }
+
+    #[test]
+    fn test_resolve_break_opportunities() {
+        let text = "a/é/b";
+        assert_eq!(
+            resolve_break_opportunities(text, vec![5, 2, 0, 3, 2, 6, 7]),
+            vec![2, 5]
+        );
+        assert!(resolve_break_opportunities("", vec![0]).is_empty());
+    }
+
+    #[test]
+    fn test_first_strong_rtl() {
+        assert!(!first_strong_rtl(""));
+        assert!(!first_strong_rtl("123 !?"));
+        assert!(!first_strong_rtl("piet \u{5e9}\u{5dc}\u{5d5}\u{5dd}"));
+        // digits and spaces are not strong
+        assert!(first_strong_rtl("123 \u{5e9}\u{5dc}\u{5d5}\u{5dd}"));
+        assert!(first_strong_rtl("\u{645}\u{631}\u{62d}\u{628}\u{627} piet"));
+    }
+
+    #[test]
+    fn test_resolve_line_spacing() {
+        assert_eq!(
+            resolve_line_spacing(LineSpacing::Default, 10.0, 8.0),
+            (10.0, 8.0)
+        );
+        assert_eq!(
+            resolve_line_spacing(LineSpacing::Multiple(1.5), 10.0, 8.0),
+            (15.0, 10.5)
+        );
+        assert_eq!(
+            resolve_line_spacing(LineSpacing::Exact(6.0), 10.0, 8.0),
+            (6.0, 6.0)
+        );
+    }
+
+    #[test]
+    fn test_resolve_spans() {
+        assert_eq!(
+            resolve_spans(None, 10, vec![(2..4, 'a')]),
+            vec![(2..4, 'a')]
+        );
+        assert_eq!(
+            resolve_spans(Some('x'), 10, vec![(2..6, 'a'), (4..8, 'b')]),
+            vec![(0..2, 'x'), (2..4, 'a'), (4..8, 'b'), (8..10, 'x')]
+        );
+        assert_eq!(
+            resolve_spans(None, 10, vec![(0..10, 'a'), (3..5, 'b')]),
+            vec![(0..3, 'a'), (3..5, 'b'), (5..10, 'a')]
+        );
+    }
+
+    #[test]
+    fn test_resolve_underlines() {
+        let defaults = LayoutDefaults::default();
+        let underlines = resolve_underlines(
+            &defaults,
+            10,
+            vec![(2..8, true)],
+            vec![(0..4, Color::WHITE)],
+            vec![(6..10, UnderlineStyle::Wavy)],
+        );
+        let ranges: Vec<_> = underlines
+            .iter()
+            .map(|(range, style, color)| (range.clone(), *style, color.is_some()))
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                (2..4, UnderlineStyle::Solid, true),
+                (4..6, UnderlineStyle::Solid, false),
+                (6..8, UnderlineStyle::Wavy, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_flag_ranges() {
+        assert_eq!(resolve_flag_ranges(false, 10, None), vec![]);
+        assert_eq!(resolve_flag_ranges(true, 10, None), vec![0..10]);
+        assert_eq!(
+            resolve_flag_ranges(true, 10, vec![(2..4, false), (6..8, false)]),
+            vec![0..2, 4..6, 8..10]
+        );
+        assert_eq!(
+            resolve_flag_ranges(false, 10, vec![(0..4, true), (2..6, true), (3..5, false)]),
+            vec![0..3, 5..6]
+        );
+        assert_eq!(
+            resolve_flag_ranges(false, 10, vec![(0..4, true), (4..6, true)]),
+            vec![0..6]
+        );
+    }
+
+    #[test]
+    fn test_line_ranges() {
+        let text = "one\ntwo e\u{301}\nthree";
+        let line = |start_offset, end_offset, y_offset| LineMetric {
+            start_offset,
+            end_offset,
+            y_offset,
+            height: 10.0,
+            ..Default::default()
+        };
+        let lines = [line(0, 4, 0.0), line(4, 12, 10.0), line(12, 17, 20.0)];
+
+        assert_eq!(
+            line_ranges(text, &lines, 1..6),
+            vec![(0, 1..3, true), (1, 4..6, false)]
+        );
+        // the newline alone, and a range ending at the start of a line
+        assert_eq!(line_ranges(text, &lines, 3..4), vec![(0, 3..3, true)]);
+        assert_eq!(line_ranges(text, &lines, 0..3), vec![(0, 0..3, false)]);
+        // ranges are expanded to grapheme boundaries
+        assert_eq!(line_ranges(text, &lines, 9..10), vec![(1, 8..11, false)]);
+        assert_eq!(line_ranges(text, &lines, 13..), vec![(2, 13..17, false)]);
+        assert!(line_ranges(text, &lines, 5..5).is_empty());
+
+        assert_eq!(
+            newline_rect(&lines[1], 30.0),
+            Rect::new(30.0, 10.0, 32.5, 20.0)
+        );
+    }
+
+    #[test]
+    fn test_grapheme_offsets() {
+        let cases: &[(&str, &[usize])] = &[
+            // combining marks
+            ("e\u{301}a\u{308}\u{323}", &[0, 3, 8]),
+            // regional indicator flags
+            ("\u{1f1e8}\u{1f1e6}\u{1f1ef}\u{1f1f5}", &[0, 8, 16]),
+            // an emoji zwj sequence (family), and a skin tone modifier
+            (
+                "a\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}\u{1f44d}\u{1f3fd}",
+                &[0, 1, 19, 27],
+            ),
+            // Hangul syllables composed of conjoining jamo
+            ("\u{1100}\u{1161}\u{11a8}\u{1100}\u{1161}", &[0, 9, 15]),
+            // CRLF is a single grapheme
+            ("a\r\nb", &[0, 1, 3, 4]),
+            ("", &[0]),
+        ];
+
+        for (text, boundaries) in cases {
+            for pair in boundaries.windows(2) {
+                assert_eq!(
+                    next_grapheme_offset(text, pair[0]),
+                    Some(pair[1]),
+                    "{:?}",
+                    text
+                );
+                assert_eq!(
+                    prev_grapheme_offset(text, pair[1]),
+                    Some(pair[0]),
+                    "{:?}",
+                    text
+                );
+            }
+            assert_eq!(next_grapheme_offset(text, text.len()), None);
+            assert_eq!(prev_grapheme_offset(text, 0), None);
+        }
+    }
+
+    #[test]
+    fn test_word_offsets() {
+        let text = "piet  text, is\r\nthe bes\u{301}t!";
+        assert_eq!(next_word_offset(text, 0), Some(4));
+        assert_eq!(next_word_offset(text, 2), Some(4));
+        // whitespace and punctuation are skipped
+        assert_eq!(next_word_offset(text, 4), Some(10));
+        assert_eq!(next_word_offset(text, 10), Some(14));
+        assert_eq!(next_word_offset(text, 14), Some(19));
+        // a combining mark is part of its word
+        assert_eq!(next_word_offset(text, 19), Some(26));
+        // no more words; move to the end
+        assert_eq!(next_word_offset(text, 26), Some(27));
+        assert_eq!(next_word_offset(text, 27), None);
+
+        assert_eq!(prev_word_offset(text, 27), Some(20));
+        assert_eq!(prev_word_offset(text, 22), Some(20));
+        assert_eq!(prev_word_offset(text, 20), Some(16));
+        assert_eq!(prev_word_offset(text, 16), Some(12));
+        assert_eq!(prev_word_offset(text, 12), Some(6));
+        assert_eq!(prev_word_offset(text, 6), Some(0));
+        assert_eq!(prev_word_offset(text, 0), None);
+
+        assert_eq!(next_word_offset("  ", 0), Some(2));
+        assert_eq!(prev_word_offset("  ", 2), Some(0));
+        assert_eq!(next_word_offset("", 0), None);
+    }
 }